@@ -7,7 +7,7 @@ use tracing_subscriber::filter::LevelFilter;
 
 use pea2pea::{
     protocols::{Handshaking, Reading, Writing},
-    Connection, ConnectionSide, Node, NodeConfig, Pea2Pea,
+    Connection, ConnectionContext, ConnectionSide, Node, NodeConfig, Pea2Pea,
 };
 
 use std::{io, net::SocketAddr, time::Duration};
@@ -66,7 +66,7 @@ impl Reading for JoJoNode {
 
     fn read_message(
         &self,
-        _source: SocketAddr,
+        _ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         let battle_cry = BattleCry::from(buffer[0]);
@@ -91,7 +91,12 @@ impl Reading for JoJoNode {
 }
 
 impl Writing for JoJoNode {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[0] = payload[0];
         let battle_cry = BattleCry::from(buffer[0]);
 
@@ -133,7 +138,7 @@ async fn main() {
 
     jotaro
         .node()
-        .connect(dio.node().listening_addr())
+        .connect(dio.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -142,7 +147,7 @@ async fn main() {
     jotaro
         .node()
         .send_direct_message(
-            dio.node().listening_addr(),
+            dio.node().listening_addr().unwrap(),
             Bytes::copy_from_slice(&[BattleCry::Ora as u8]),
         )
         .await
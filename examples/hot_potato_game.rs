@@ -14,7 +14,7 @@ use tracing_subscriber::filter::LevelFilter;
 use pea2pea::{
     connect_nodes,
     protocols::{Handshaking, Reading, Writing},
-    Connection, ConnectionSide, Node, NodeConfig, Pea2Pea, Topology,
+    Connection, ConnectionContext, ConnectionSide, Node, NodeConfig, Pea2Pea, Topology,
 };
 
 use std::{
@@ -33,6 +33,7 @@ static RNG: Lazy<Mutex<SmallRng>> = Lazy::new(|| Mutex::new(SmallRng::from_entro
 
 type PlayerName = String;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct PlayerInfo {
     name: PlayerName,
@@ -142,7 +143,7 @@ impl Reading for Player {
 
     fn read_message(
         &self,
-        _source: SocketAddr,
+        _ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         // expecting inbound messages to be prefixed with their length encoded as a LE u16
@@ -201,9 +202,14 @@ impl Reading for Player {
 }
 
 impl Writing for Player {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
-        buffer[2..][..payload.len()].copy_from_slice(&payload);
+        buffer[2..][..payload.len()].copy_from_slice(payload);
         Ok(2 + payload.len())
     }
 }
@@ -1,7 +1,7 @@
 mod common;
 
 use bytes::Bytes;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::Mutex;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     time::sleep,
@@ -11,12 +11,10 @@ use tracing_subscriber::filter::LevelFilter;
 
 use pea2pea::{
     protocols::{Handshaking, Reading, Writing},
-    Connection, ConnectionSide, Node, NodeConfig, Pea2Pea,
+    Connection, ConnectionContext, ConnectionSide, Node, NodeConfig, Pea2Pea,
 };
 
-use std::{
-    collections::HashMap, convert::TryInto, io, net::SocketAddr, str, sync::Arc, time::Duration,
-};
+use std::{convert::TryInto, io, str, time::Duration};
 
 // maximum noise message size, as specified by its protocol
 const NOISE_BUF_LEN: usize = 65535;
@@ -29,7 +27,6 @@ struct NoiseState {
 #[derive(Clone)]
 struct SecureNode {
     node: Node,
-    noise_states: Arc<RwLock<HashMap<SocketAddr, Arc<Mutex<NoiseState>>>>>,
 }
 
 impl Pea2Pea for SecureNode {
@@ -79,10 +76,7 @@ impl SecureNode {
         };
         let node = Node::new(Some(config)).await?;
 
-        Ok(Self {
-            node,
-            noise_states: Default::default(),
-        })
+        Ok(Self { node })
     }
 }
 
@@ -158,11 +152,9 @@ impl Handshaking for SecureNode {
 
         debug!(parent: conn.node.span(), "XX handshake complete");
 
+        // the noise state is handed to the Reading and Writing protocols via the connection's context
         let noise_state = NoiseState { state, buffer };
-
-        self.noise_states
-            .write()
-            .insert(conn.addr, Arc::new(Mutex::new(noise_state)));
+        conn.set_handshake_data(Mutex::new(noise_state));
 
         Ok(conn)
     }
@@ -174,13 +166,13 @@ impl Reading for SecureNode {
 
     fn read_message(
         &self,
-        source: SocketAddr,
+        ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         let bytes = read_message(buffer)?;
 
         if let Some(bytes) = bytes {
-            let noise = Arc::clone(self.noise_states.read().get(&source).unwrap());
+            let noise = ctx.handshake_data::<Mutex<NoiseState>>().unwrap();
             let NoiseState { state, buffer } = &mut *noise.lock();
 
             let len = state.read_message(bytes, buffer).ok().unwrap();
@@ -193,8 +185,12 @@ impl Reading for SecureNode {
         }
     }
 
-    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
-        info!(parent: self.node().span(), "decrypted a message from {}: \"{}\"", source, message);
+    async fn process_message_with_context(
+        &self,
+        ctx: &ConnectionContext,
+        message: Self::Message,
+    ) -> io::Result<()> {
+        info!(parent: self.node().span(), "decrypted a message from {}: \"{}\"", ctx.addr(), message);
 
         Ok(())
     }
@@ -203,21 +199,21 @@ impl Reading for SecureNode {
 impl Writing for SecureNode {
     fn write_message(
         &self,
-        target: SocketAddr,
+        ctx: &ConnectionContext,
         payload: &[u8],
         conn_buffer: &mut [u8],
     ) -> io::Result<usize> {
         let to_encrypt = str::from_utf8(payload).unwrap();
-        info!(parent: self.node.span(), "sending an encrypted message to {}: \"{}\"", target, to_encrypt);
+        info!(parent: self.node.span(), "sending an encrypted message to {}: \"{}\"", ctx.addr(), to_encrypt);
 
-        let noise = Arc::clone(&self.noise_states.read().get(&target).unwrap());
+        let noise = ctx.handshake_data::<Mutex<NoiseState>>().unwrap();
 
         let NoiseState { state, buffer } = &mut *noise.lock();
         let len = state.write_message(payload, buffer).unwrap();
         let encrypted_message = &buffer[..len];
 
         conn_buffer[..2].copy_from_slice(&(len as u16).to_be_bytes());
-        conn_buffer[2..][..len].copy_from_slice(&encrypted_message);
+        conn_buffer[2..][..len].copy_from_slice(encrypted_message);
 
         Ok(2 + len)
     }
@@ -239,7 +235,7 @@ async fn main() {
     // connect the initiator to the responder
     initiator
         .node()
-        .connect(responder.node().listening_addr())
+        .connect(responder.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -250,7 +246,7 @@ async fn main() {
     let msg = b"why hello there, fellow noise protocol user; I'm the initiator";
     initiator
         .node()
-        .send_direct_message(responder.node().listening_addr(), msg[..].into())
+        .send_direct_message(responder.node().listening_addr().unwrap(), msg[..].into())
         .await
         .unwrap();
 
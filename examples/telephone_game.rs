@@ -8,7 +8,7 @@ use tracing_subscriber::filter::LevelFilter;
 use pea2pea::{
     connect_nodes,
     protocols::{Reading, Writing},
-    Node, NodeConfig, Pea2Pea, Topology,
+    ConnectionContext, Node, NodeConfig, Pea2Pea, Topology,
 };
 
 use std::{convert::TryInto, io, net::SocketAddr, time::Duration};
@@ -28,7 +28,11 @@ const NUM_PLAYERS: usize = 100;
 impl Reading for Player {
     type Message = String;
 
-    fn read_message(&self, _src: SocketAddr, buffer: &[u8]) -> io::Result<Option<(String, usize)>> {
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(String, usize)>> {
         if buffer.len() >= 2 {
             let payload_len = u16::from_le_bytes(buffer[..2].try_into().unwrap()) as usize;
             if payload_len == 0 {
@@ -75,9 +79,14 @@ impl Reading for Player {
 }
 
 impl Writing for Player {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
-        buffer[2..][..payload.len()].copy_from_slice(&payload);
+        buffer[2..][..payload.len()].copy_from_slice(payload);
         Ok(2 + payload.len())
     }
 }
@@ -109,7 +118,7 @@ async fn main() {
     players[0]
         .node()
         .send_direct_message(
-            players[1].node().listening_addr(),
+            players[1].node().listening_addr().unwrap(),
             message.as_bytes().into(),
         )
         .await
@@ -0,0 +1,108 @@
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{
+    collections::VecDeque,
+    mem,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The maximum number of ack latency samples retained per peer.
+const MAX_LATENCY_SAMPLES: usize = 256;
+
+/// Keeps track of application-level message acknowledgements used by the `Acknowledging` protocol: the acks
+/// pending to be sent to peers (in batches) and the latencies of the acks received from them.
+#[derive(Default)]
+pub struct Acks(Mutex<FxHashMap<SocketAddr, PeerAcks>>);
+
+/// The ack-related state associated with a single peer.
+#[derive(Default)]
+struct PeerAcks {
+    /// The IDs of received messages that are yet to be acknowledged.
+    pending: Vec<u64>,
+    /// The IDs of sent messages that are yet to be acknowledged, with the timestamps of their sending.
+    unacked: FxHashMap<u64, Instant>,
+    /// The most recent ack latencies.
+    latencies: VecDeque<Duration>,
+}
+
+impl Acks {
+    /// Registers a message with the given ID sent to the given address as awaiting an ack.
+    pub fn register_sent(&self, to: SocketAddr, id: u64) {
+        self.0
+            .lock()
+            .entry(to)
+            .or_default()
+            .unacked
+            .insert(id, Instant::now());
+    }
+
+    /// Queues an ack for a message with the given ID received from the given address; it will be sent in the
+    /// next batch.
+    pub fn queue_ack(&self, to: SocketAddr, id: u64) {
+        self.0.lock().entry(to).or_default().pending.push(id);
+    }
+
+    /// Registers a batch of acks received from the given address; returns the number of acks that matched
+    /// messages awaiting them.
+    pub fn register_acked(&self, from: SocketAddr, ids: &[u64]) -> usize {
+        let mut acks = self.0.lock();
+        let peer = acks.entry(from).or_default();
+        let mut matched = 0;
+
+        for id in ids {
+            if let Some(sent) = peer.unacked.remove(id) {
+                if peer.latencies.len() == MAX_LATENCY_SAMPLES {
+                    peer.latencies.pop_front();
+                }
+                peer.latencies.push_back(sent.elapsed());
+                matched += 1;
+            }
+        }
+
+        matched
+    }
+
+    /// Returns the number of sent messages to the given address that are still awaiting an ack.
+    pub fn num_unacked(&self, addr: SocketAddr) -> usize {
+        self.0
+            .lock()
+            .get(&addr)
+            .map(|peer| peer.unacked.len())
+            .unwrap_or(0)
+    }
+
+    /// Returns the given percentile (`0.0..=1.0`) of the recent ack latencies of the given address.
+    pub fn latency_percentile(&self, addr: SocketAddr, percentile: f64) -> Option<Duration> {
+        let acks = self.0.lock();
+        let mut latencies = acks
+            .get(&addr)?
+            .latencies
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+
+        let idx = ((latencies.len() - 1) as f64 * percentile.clamp(0.0, 1.0)).round() as usize;
+        Some(latencies[idx])
+    }
+
+    /// Removes all the ack-related state associated with the given address.
+    pub fn remove(&self, addr: SocketAddr) {
+        self.0.lock().remove(&addr);
+    }
+
+    /// Takes all the pending acks, grouped by the addresses they are to be sent to.
+    pub(crate) fn take_pending(&self) -> Vec<(SocketAddr, Vec<u64>)> {
+        self.0
+            .lock()
+            .iter_mut()
+            .filter(|(_, peer)| !peer.pending.is_empty())
+            .map(|(addr, peer)| (*addr, mem::take(&mut peer.pending)))
+            .collect()
+    }
+}
@@ -0,0 +1,126 @@
+use parking_lot::Mutex;
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+/// The number of buckets making up the window the rates are measured over.
+const NUM_BUCKETS: usize = 10;
+/// The length of a single bucket; together, the buckets span 5 seconds.
+const BUCKET_LEN: Duration = Duration::from_millis(500);
+
+/// The rates of data transfer measured over the last few seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BandwidthRates {
+    /// The number of bytes sent per second.
+    pub sent_per_sec: f64,
+    /// The number of bytes received per second.
+    pub received_per_sec: f64,
+}
+
+/// The number of bits of a bucket holding (the lower bits of) its index; the remaining ones hold its byte count.
+const IDX_BITS: u32 = 24;
+/// The number of bits of a bucket holding its byte count.
+const BYTES_BITS: u32 = u64::BITS - IDX_BITS;
+/// The mask of a bucket's byte count.
+const BYTES_MASK: u64 = (1 << BYTES_BITS) - 1;
+/// The mask of the lower bits of a bucket index.
+const IDX_MASK: u64 = (1 << IDX_BITS) - 1;
+
+/// Measures the rate of data transfer over a sliding window; it's lock-free, so it can be updated on every
+/// message without contention.
+pub(crate) struct RateMeter {
+    /// The time the measurements started at.
+    start: Instant,
+    /// The lower bits of the indices of the buckets (counted from `start`), packed together with the numbers of
+    /// bytes registered within them; the indices wrap around every ~97 days.
+    buckets: [AtomicU64; NUM_BUCKETS],
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            buckets: Default::default(),
+        }
+    }
+}
+
+impl RateMeter {
+    /// Returns the (masked) index of the current bucket.
+    fn current_bucket(&self) -> u64 {
+        (self.start.elapsed().as_millis() / BUCKET_LEN.as_millis()) as u64 & IDX_MASK
+    }
+
+    /// Registers a transfer of the given number of bytes.
+    pub(crate) fn register(&self, bytes: usize) {
+        let idx = self.current_bucket();
+        let bucket = &self.buckets[idx as usize % NUM_BUCKETS];
+        let _ = bucket.fetch_update(Relaxed, Relaxed, |packed| {
+            let count = if packed >> BYTES_BITS == idx {
+                packed & BYTES_MASK
+            } else {
+                0
+            };
+            Some(idx << BYTES_BITS | (count + bytes as u64).min(BYTES_MASK))
+        });
+    }
+
+    /// Returns the number of bytes transferred per second within the window.
+    pub(crate) fn rate(&self) -> f64 {
+        let idx = self.current_bucket();
+        let bytes = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Relaxed))
+            .filter(|packed| idx.wrapping_sub(packed >> BYTES_BITS) & IDX_MASK < NUM_BUCKETS as u64)
+            .map(|packed| packed & BYTES_MASK)
+            .sum::<u64>();
+        let window = self.start.elapsed().min(BUCKET_LEN * NUM_BUCKETS as u32);
+
+        if window.is_zero() {
+            0.0
+        } else {
+            bytes as f64 / window.as_secs_f64()
+        }
+    }
+}
+
+/// A token bucket pacing the writes to a configured rate, allowing bursts of up to a second's worth of bytes.
+pub(crate) struct Throttle {
+    /// The number of bytes allowed per second.
+    rate: f64,
+    /// The number of available bytes (negative if in debt), along with the time it was last updated at.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec as f64;
+
+        Self {
+            rate,
+            state: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Registers a write of the given number of bytes; returns the time to wait before the next write in order to
+    /// stay within the rate.
+    pub(crate) fn consume(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock();
+        let (ref mut available, ref mut updated) = *state;
+
+        let now = Instant::now();
+        *available =
+            (*available + now.duration_since(*updated).as_secs_f64() * self.rate).min(self.rate);
+        *updated = now;
+        *available -= bytes as f64;
+
+        if *available < 0.0 {
+            Duration::from_secs_f64(-*available / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
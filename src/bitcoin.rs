@@ -0,0 +1,543 @@
+//! Compatibility with Bitcoin-derived networks; available with the `bitcoin` feature.
+//!
+//! Every message is wrapped in an envelope consisting of a 24-byte header and the payload:
+//!
+//! ```text
+//! [magic: 4B][command: 12B, NUL-padded ASCII][payload length: 4B, LE][checksum: 4B][payload]
+//! ```
+//!
+//! where the checksum is the beginning of the double SHA-256 of the payload. `BitcoinNode` implements the `Reading`
+//! and `Writing` protocols using this envelope, and the `Handshaking` one as the `version`/`verack` exchange.
+
+use crate::{
+    protocols::{Handshaking, Reading, Writing},
+    tracing_targets::HANDSHAKE,
+    Connection, ConnectionContext, Node, Pea2Pea,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+use tracing::*;
+
+use std::{
+    convert::TryInto,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The size of the envelope's header.
+pub const HEADER_LEN: usize = 24;
+/// The size of the command field of the header.
+pub const COMMAND_LEN: usize = 12;
+/// The maximum size of a payload, as accepted by Bitcoin Core.
+pub const MAX_PAYLOAD_LEN: usize = 32 * 1024 * 1024;
+/// The maximum size of a payload received during the handshake; it fits a `version` message with the longest user
+/// agent accepted by Bitcoin Core, so that unauthenticated peers can't cause large allocations.
+pub const MAX_HANDSHAKE_PAYLOAD_LEN: usize = 1024;
+
+/// The configuration of a `BitcoinNode`.
+#[derive(Debug, Clone)]
+pub struct BitcoinConfig {
+    /// The bytes every message starts with; they identify the network.
+    pub magic: [u8; 4],
+    /// The protocol version advertised in the `version` message.
+    pub protocol_version: i32,
+    /// The services advertised in the `version` message, as a bitset.
+    pub services: u64,
+    /// The user agent advertised in the `version` message.
+    pub user_agent: String,
+    /// The height of the best block advertised in the `version` message.
+    pub start_height: i32,
+    /// Indicates whether the peers should relay transactions to the node.
+    pub relay: bool,
+}
+
+impl BitcoinConfig {
+    /// The configuration for the Bitcoin mainnet.
+    pub fn mainnet() -> Self {
+        Self {
+            magic: [0xf9, 0xbe, 0xb4, 0xd9],
+            ..Default::default()
+        }
+    }
+
+    /// The configuration for the Bitcoin testnet (version 3).
+    pub fn testnet() -> Self {
+        Self {
+            magic: [0x0b, 0x11, 0x09, 0x07],
+            ..Default::default()
+        }
+    }
+
+    /// The configuration for the Bitcoin regtest network.
+    pub fn regtest() -> Self {
+        Self {
+            magic: [0xfa, 0xbf, 0xb5, 0xda],
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for BitcoinConfig {
+    fn default() -> Self {
+        Self {
+            magic: [0xf9, 0xbe, 0xb4, 0xd9],
+            protocol_version: 70016,
+            services: 0,
+            user_agent: concat!("/pea2pea:", env!("CARGO_PKG_VERSION"), "/").into(),
+            start_height: 0,
+            relay: false,
+        }
+    }
+}
+
+/// A message exchanged with a Bitcoin-derived network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitcoinMessage {
+    /// The command identifying the type of the message, e.g. `ping`.
+    pub command: String,
+    /// The payload of the message.
+    pub payload: Bytes,
+}
+
+/// The details the peer advertised in its `version` message; they are attached to its connection, and are
+/// available via `BitcoinNode::peer_version`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerVersion {
+    /// The protocol version of the peer.
+    pub version: i32,
+    /// The services provided by the peer, as a bitset.
+    pub services: u64,
+    /// The user agent of the peer.
+    pub user_agent: String,
+    /// The height of the peer's best block.
+    pub start_height: i32,
+    /// Indicates whether the peer wants transactions to be relayed to it.
+    pub relay: bool,
+}
+
+/// A node speaking the Bitcoin P2P protocol; the messages it receives (other than `ping`s, which are answered
+/// automatically) are passed to the receiver returned by `BitcoinNode::new`.
+#[derive(Clone)]
+pub struct BitcoinNode {
+    node: Node,
+    config: Arc<BitcoinConfig>,
+    inbound_sender: mpsc::Sender<(SocketAddr, BitcoinMessage)>,
+}
+
+impl Pea2Pea for BitcoinNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+impl BitcoinNode {
+    /// Creates a `BitcoinNode` using the given `Node`; returns it along with the receiver of the inbound messages,
+    /// whose queue depth is `NodeConfig.incoming_queue_depth`. The `Handshaking`, `Reading` and `Writing` protocols
+    /// need to be enabled afterwards.
+    pub fn new(
+        node: Node,
+        config: BitcoinConfig,
+    ) -> (Self, mpsc::Receiver<(SocketAddr, BitcoinMessage)>) {
+        let (inbound_sender, inbound_receiver) = mpsc::channel(node.config().incoming_queue_depth);
+        let bitcoin_node = Self {
+            node,
+            config: Arc::new(config),
+            inbound_sender,
+        };
+
+        (bitcoin_node, inbound_receiver)
+    }
+
+    /// Returns the node's configuration.
+    pub fn config(&self) -> &BitcoinConfig {
+        &self.config
+    }
+
+    /// Sends a message with the given command and payload to the specified peer.
+    pub async fn send(&self, addr: SocketAddr, command: &str, payload: &[u8]) -> io::Result<()> {
+        // the command is passed to the Writing protocol in front of the payload
+        let mut message = Vec::with_capacity(COMMAND_LEN + payload.len());
+        message.extend_from_slice(&encode_command(command)?);
+        message.extend_from_slice(payload);
+
+        self.node.send_direct_message(addr, message.into()).await
+    }
+
+    /// Returns the details advertised in the `version` message of the given connected peer.
+    pub fn peer_version(&self, addr: SocketAddr) -> Option<PeerVersion> {
+        self.node.connection_ext(addr)
+    }
+
+    /// Creates the payload of the node's `version` message for the given peer.
+    fn version_payload(&self, peer_addr: SocketAddr, nonce: u64) -> Vec<u8> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let own_addr = self.node.listening_addr();
+
+        let mut payload = Vec::with_capacity(86 + self.config.user_agent.len());
+        payload.extend_from_slice(&self.config.protocol_version.to_le_bytes());
+        payload.extend_from_slice(&self.config.services.to_le_bytes());
+        payload.extend_from_slice(&timestamp.to_le_bytes());
+        encode_net_addr(&mut payload, 0, Some(peer_addr));
+        encode_net_addr(&mut payload, self.config.services, own_addr);
+        payload.extend_from_slice(&nonce.to_le_bytes());
+        encode_var_int(&mut payload, self.config.user_agent.len() as u64);
+        payload.extend_from_slice(self.config.user_agent.as_bytes());
+        payload.extend_from_slice(&self.config.start_height.to_le_bytes());
+        payload.push(self.config.relay as u8);
+
+        payload
+    }
+
+    /// Writes a message with the given command and payload directly to the connection's stream.
+    async fn write_envelope(
+        &self,
+        conn: &mut Connection,
+        command: &str,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut envelope = vec![0u8; HEADER_LEN + payload.len()];
+        encode_envelope(
+            &self.config.magic,
+            encode_command(command)?,
+            payload,
+            &mut envelope,
+        )?;
+
+        conn.writer().write_all(&envelope).await
+    }
+}
+
+#[async_trait]
+impl Reading for BitcoinNode {
+    type Message = BitcoinMessage;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        if buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let (command, payload_len, checksum) = decode_header(&self.config.magic, buffer)?;
+        if buffer.len() < HEADER_LEN + payload_len {
+            return Ok(None);
+        }
+
+        let payload = &buffer[HEADER_LEN..][..payload_len];
+        verify_checksum(payload, checksum)?;
+
+        let message = BitcoinMessage {
+            command,
+            payload: Bytes::copy_from_slice(payload),
+        };
+
+        Ok(Some((message, HEADER_LEN + payload_len)))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        if message.command == "ping" {
+            return self.send(source, "pong", &message.payload).await;
+        }
+
+        // the receiver may no longer be interested
+        let _ = self.inbound_sender.send((source, message)).await;
+
+        Ok(())
+    }
+}
+
+impl Writing for BitcoinNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        // the payload is preceded by the command; see `BitcoinNode::send`
+        if payload.len() < COMMAND_LEN {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let (command, payload) = payload.split_at(COMMAND_LEN);
+
+        // safe; the length was checked above
+        encode_envelope(
+            &self.config.magic,
+            command.try_into().unwrap(),
+            payload,
+            buffer,
+        )
+    }
+}
+
+#[async_trait]
+impl Handshaking for BitcoinNode {
+    async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+        let own_nonce = OsRng.next_u64();
+        let version_payload = self.version_payload(conn.addr, own_nonce);
+        self.write_envelope(&mut conn, "version", &version_payload)
+            .await?;
+
+        let mut peer_version = None;
+        let mut got_verack = false;
+        while peer_version.is_none() || !got_verack {
+            let message =
+                read_envelope(&self.config.magic, MAX_HANDSHAKE_PAYLOAD_LEN, conn.reader()).await?;
+
+            match message.command.as_str() {
+                "version" if peer_version.is_none() => {
+                    let (version, nonce) = decode_version(&message.payload)?;
+                    if nonce == own_nonce {
+                        error!(target: HANDSHAKE, parent: conn.span(), "{} is the node itself", conn.addr);
+                        return Err(io::ErrorKind::AlreadyExists.into());
+                    }
+                    debug!(target: HANDSHAKE, parent: conn.span(), "{} advertised {:?}", conn.addr, version);
+
+                    self.write_envelope(&mut conn, "verack", &[]).await?;
+                    peer_version = Some(version);
+                }
+                "verack" => got_verack = true,
+                // other messages (e.g. feature negotiation ones) can be sent before the verack
+                command => {
+                    trace!(target: HANDSHAKE, parent: conn.span(), "ignoring a \"{}\" message from {}", command, conn.addr);
+                }
+            }
+        }
+
+        // safe; the loop only ends once the version was received
+        conn.insert_ext(peer_version.unwrap());
+
+        Ok(conn)
+    }
+}
+
+/// Calculates the checksum of the given payload: the first 4 bytes of its double SHA-256.
+pub fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+
+    // safe; the hash is 32B long
+    hash[..4].try_into().unwrap()
+}
+
+/// Encodes the given command as the NUL-padded command field of the header.
+fn encode_command(command: &str) -> io::Result<[u8; COMMAND_LEN]> {
+    if command.len() > COMMAND_LEN || !command.is_ascii() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the command must be ASCII and at most 12 bytes long",
+        ));
+    }
+
+    let mut field = [0u8; COMMAND_LEN];
+    field[..command.len()].copy_from_slice(command.as_bytes());
+
+    Ok(field)
+}
+
+/// Writes the envelope containing the given command and payload to the given buffer; returns its size.
+fn encode_envelope(
+    magic: &[u8; 4],
+    command: [u8; COMMAND_LEN],
+    payload: &[u8],
+    buffer: &mut [u8],
+) -> io::Result<usize> {
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    let len = HEADER_LEN + payload.len();
+    if buffer.len() < len {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+
+    buffer[..4].copy_from_slice(magic);
+    buffer[4..16].copy_from_slice(&command);
+    buffer[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    buffer[20..24].copy_from_slice(&checksum(payload));
+    buffer[HEADER_LEN..len].copy_from_slice(payload);
+
+    Ok(len)
+}
+
+/// Decodes the given header; returns the command, the size of the payload and its checksum.
+fn decode_header(magic: &[u8; 4], header: &[u8]) -> io::Result<(String, usize, [u8; 4])> {
+    if header[..4] != magic[..] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid magic"));
+    }
+
+    let command = &header[4..16];
+    let command_len = command
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(COMMAND_LEN);
+    // the command must be followed by NUL padding only
+    if command[command_len..].iter().any(|&byte| byte != 0) || !command.is_ascii() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid command",
+        ));
+    }
+    // safe; the command is ASCII
+    let command = std::str::from_utf8(&command[..command_len])
+        .unwrap()
+        .to_owned();
+
+    // safe; the slices have the exact lengths required by the conversions
+    let payload_len = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+    if payload_len > MAX_PAYLOAD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the payload is too large",
+        ));
+    }
+    let checksum = header[20..24].try_into().unwrap();
+
+    Ok((command, payload_len, checksum))
+}
+
+/// Checks whether the given payload matches the given checksum.
+fn verify_checksum(payload: &[u8], expected: [u8; 4]) -> io::Result<()> {
+    if checksum(payload) == expected {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checksum mismatch",
+        ))
+    }
+}
+
+/// Reads a single message with a payload of up to the given size directly from the given reader.
+async fn read_envelope<R: AsyncRead + Unpin>(
+    magic: &[u8; 4],
+    max_payload_len: usize,
+    reader: &mut R,
+) -> io::Result<BitcoinMessage> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+    let (command, payload_len, checksum) = decode_header(magic, &header)?;
+    if payload_len > max_payload_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the payload is too large",
+        ));
+    }
+
+    // the buffer only grows as the payload arrives, instead of being allocated upfront based on the header
+    let mut payload = Vec::new();
+    reader
+        .take(payload_len as u64)
+        .read_to_end(&mut payload)
+        .await?;
+    if payload.len() != payload_len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    verify_checksum(&payload, checksum)?;
+
+    Ok(BitcoinMessage {
+        command,
+        payload: payload.into(),
+    })
+}
+
+/// Encodes a network address (without a timestamp), as used in the `version` message.
+fn encode_net_addr(buffer: &mut Vec<u8>, services: u64, addr: Option<SocketAddr>) {
+    let (ip, port) = match addr {
+        Some(addr) => {
+            let ip = match addr.ip() {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            (ip.octets(), addr.port())
+        }
+        None => ([0u8; 16], 0),
+    };
+
+    buffer.extend_from_slice(&services.to_le_bytes());
+    buffer.extend_from_slice(&ip);
+    buffer.extend_from_slice(&port.to_be_bytes());
+}
+
+/// Encodes the given number as a variable-length integer (`CompactSize`).
+fn encode_var_int(buffer: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => buffer.push(value as u8),
+        0xfd..=0xffff => {
+            buffer.push(0xfd);
+            buffer.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buffer.push(0xfe);
+            buffer.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            buffer.push(0xff);
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Decodes a variable-length integer (`CompactSize`); returns it along with the number of bytes it occupied.
+fn decode_var_int(buffer: &[u8]) -> Option<(u64, usize)> {
+    let len = match *buffer.first()? {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        value => return Some((value as u64, 1)),
+    };
+
+    let mut bytes = [0u8; 8];
+    bytes[..len].copy_from_slice(buffer.get(1..1 + len)?);
+
+    Some((u64::from_le_bytes(bytes), 1 + len))
+}
+
+/// Decodes the payload of a `version` message; returns the advertised details and the nonce.
+fn decode_version(payload: &[u8]) -> io::Result<(PeerVersion, u64)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid version message");
+
+    // the version, services, timestamp, both network addresses and the nonce
+    if payload.len() < 80 {
+        return Err(invalid());
+    }
+    // safe; the slices have the exact lengths required by the conversions
+    let version = i32::from_le_bytes(payload[..4].try_into().unwrap());
+    let services = u64::from_le_bytes(payload[4..12].try_into().unwrap());
+    let nonce = u64::from_le_bytes(payload[72..80].try_into().unwrap());
+
+    let (user_agent_len, offset) = decode_var_int(&payload[80..]).ok_or_else(invalid)?;
+    let user_agent_start = 80 + offset;
+    let user_agent = payload
+        .get(user_agent_start..user_agent_start.saturating_add(user_agent_len as usize))
+        .ok_or_else(invalid)?;
+    let user_agent = String::from_utf8_lossy(user_agent).into_owned();
+    let rest = &payload[user_agent_start + user_agent_len as usize..];
+    let start_height = rest
+        .get(..4)
+        .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(invalid)?;
+    // the relay flag is optional, and defaults to true
+    let relay = rest.get(4).map(|&relay| relay != 0).unwrap_or(true);
+
+    let peer_version = PeerVersion {
+        version,
+        services,
+        user_agent,
+        start_height,
+        relay,
+    };
+
+    Ok((peer_version, nonce))
+}
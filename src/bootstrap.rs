@@ -0,0 +1,120 @@
+//! Bootstrapping from remote seed lists; available with the `bootstrap` feature.
+//!
+//! A seed list is a text document whose first line is the hex-encoded Ed25519 signature of the rest of the
+//! document, which consists of socket addresses separated by newlines or commas, e.g.
+//!
+//! ```text
+//! 6c3f...0a9b
+//! 203.0.113.1:4000
+//! 203.0.113.2:4000, 198.51.100.7:4001
+//! ```
+
+use crate::{identity::verify_signature, Node};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+/// The size of a signature.
+const SIGNATURE_LEN: usize = 64;
+
+/// A remote list of peers to bootstrap from.
+#[derive(Debug, Clone)]
+pub struct SeedList {
+    /// The HTTP(S) URL the seed list is fetched from.
+    pub url: String,
+    /// The Ed25519 public key the seed list must be signed with.
+    pub public_key: [u8; 32],
+}
+
+impl SeedList {
+    /// Fetches the seed list and returns the addresses it contains, as long as its signature is valid.
+    pub async fn fetch(&self) -> io::Result<Vec<SocketAddr>> {
+        self.fetch_with(&reqwest::Client::new()).await
+    }
+
+    /// Fetches the seed list like `SeedList::fetch`, resolving its host via `Node::resolve`, so that the node's
+    /// `Resolver` and DNS cache apply.
+    pub(crate) async fn fetch_via(&self, node: &Node) -> io::Result<Vec<SocketAddr>> {
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(NodeResolver(node.clone())))
+            .build()
+            .map_err(io::Error::other)?;
+
+        self.fetch_with(&client).await
+    }
+
+    /// Fetches the seed list using the given client.
+    async fn fetch_with(&self, client: &reqwest::Client) -> io::Result<Vec<SocketAddr>> {
+        let response = client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e))?;
+        let document = response
+            .bytes()
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::UnexpectedEof, e))?;
+
+        parse_seed_list(&document, &self.public_key)
+    }
+}
+
+/// Resolves the host names of the seed lists via `Node::resolve`.
+struct NodeResolver(Node);
+
+impl Resolve for NodeResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let node = self.0.clone();
+        Box::pin(async move {
+            // the port is overridden by the one of the URL
+            let addrs = node.resolve(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Verifies the signature of the given seed list document and returns the addresses it contains.
+pub fn parse_seed_list(document: &[u8], public_key: &[u8; 32]) -> io::Result<Vec<SocketAddr>> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+    let newline = document
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| invalid("the seed list is missing its signature"))?;
+    let (signature, payload) = (&document[..newline], &document[newline + 1..]);
+    let signature = decode_signature(signature)
+        .ok_or_else(|| invalid("the seed list signature is malformed"))?;
+
+    if !verify_signature(public_key, payload, &signature) {
+        return Err(invalid("the seed list signature is invalid"));
+    }
+
+    std::str::from_utf8(payload)
+        .map_err(|_| invalid("the seed list is not valid UTF-8"))?
+        .split(&['\n', ','][..])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse()
+                .map_err(|_| invalid("the seed list contains an invalid address"))
+        })
+        .collect()
+}
+
+/// Decodes a hex-encoded signature, ignoring any surrounding whitespace.
+fn decode_signature(hex: &[u8]) -> Option<[u8; SIGNATURE_LEN]> {
+    let hex = std::str::from_utf8(hex).ok()?.trim();
+    if hex.len() != SIGNATURE_LEN * 2 {
+        return None;
+    }
+
+    let mut signature = [0u8; SIGNATURE_LEN];
+    for (byte, chunk) in signature.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(chunk).ok()?, 16).ok()?;
+    }
+
+    Some(signature)
+}
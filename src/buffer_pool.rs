@@ -0,0 +1,104 @@
+use parking_lot::Mutex;
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+/// The size of the smallest size class.
+const MIN_CLASS_SIZE: usize = 256;
+/// The number of size classes; each class is twice the size of the previous one, so the largest pooled buffers
+/// are 16MiB in size. Larger buffers are not pooled.
+const NUM_CLASSES: usize = 17;
+
+/// A pool of buffers divided into power-of-two size classes; it is used for the per-connection read and write
+/// buffers, so that they don't need to be allocated whenever a connection is established.
+pub(crate) struct BufferPool {
+    /// The buffers available in each size class.
+    classes: Vec<Mutex<Vec<Box<[u8]>>>>,
+    /// The maximum number of buffers retained in each size class.
+    max_retained: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool retaining up to `max_retained` buffers in each size class.
+    pub(crate) fn new(max_retained: usize) -> Arc<Self> {
+        Arc::new(Self {
+            classes: (0..NUM_CLASSES).map(|_| Default::default()).collect(),
+            max_retained,
+        })
+    }
+
+    /// Returns a zeroed buffer of the given size, reusing a pooled one if possible.
+    pub(crate) fn get(self: &Arc<Self>, size: usize) -> PooledBuffer {
+        let buffer = match class_idx(size) {
+            Some(idx) => self.classes[idx]
+                .lock()
+                .pop()
+                .unwrap_or_else(|| vec![0; MIN_CLASS_SIZE << idx].into_boxed_slice()),
+            None => vec![0; size].into_boxed_slice(),
+        };
+
+        PooledBuffer {
+            buffer: Some(buffer),
+            len: size,
+            pool: Arc::clone(self),
+        }
+    }
+
+    /// Returns the given buffer to its size class, unless the class is already full.
+    fn put(&self, mut buffer: Box<[u8]>) {
+        if let Some(idx) = class_idx(buffer.len()) {
+            let mut class = self.classes[idx].lock();
+            if class.len() < self.max_retained {
+                // the buffers could contain sensitive data, and a fresh buffer is expected to be zeroed
+                buffer.fill(0);
+                class.push(buffer);
+            }
+        }
+    }
+}
+
+/// Returns the index of the smallest size class able to hold a buffer of the given size.
+fn class_idx(size: usize) -> Option<usize> {
+    let class_size = size.max(MIN_CLASS_SIZE).checked_next_power_of_two()?;
+    let idx = (class_size / MIN_CLASS_SIZE).trailing_zeros() as usize;
+
+    if idx < NUM_CLASSES {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+/// A buffer obtained from a `BufferPool`; it is returned to the pool when dropped.
+pub(crate) struct PooledBuffer {
+    buffer: Option<Box<[u8]>>,
+    /// The requested size of the buffer; the underlying buffer can be larger.
+    len: usize,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // safe; the buffer is only taken when dropped
+        &self.buffer.as_ref().unwrap()[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // safe; the buffer is only taken when dropped
+        &mut self.buffer.as_mut().unwrap()[..self.len]
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.put(buffer);
+        }
+    }
+}
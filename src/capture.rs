@@ -0,0 +1,274 @@
+use crate::{diagnostics::FrameDirection, Node};
+
+use bytes::Bytes;
+
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A hook receiving the raw frames exchanged with the peers (see `Node::{set_inbound_tap, set_outbound_tap}`),
+/// along with the addresses of the peers and the times the frames were read or written at.
+pub type FrameTap = Arc<dyn Fn(SocketAddr, SystemTime, &[u8]) + Send + Sync>;
+
+/// The magic bytes starting every capture file.
+const CAPTURE_MAGIC: &[u8; 6] = b"P2PCAP";
+/// The version of the capture file format.
+const CAPTURE_VERSION: u16 = 1;
+
+/// A frame recorded in a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// The address of the peer.
+    pub addr: SocketAddr,
+    /// The direction of the frame.
+    pub direction: FrameDirection,
+    /// The time the frame was read or written at.
+    pub timestamp: SystemTime,
+    /// The contents of the frame.
+    pub data: Bytes,
+}
+
+/// The number of records that can be queued for the capture's writing thread; the ones exceeding it are dropped.
+const CAPTURE_QUEUE_DEPTH: usize = 1024;
+/// The maximum number of bytes preallocated for a single frame read from a capture file.
+const MAX_FRAME_PREALLOCATION: usize = 64 * 1024;
+
+/// The commands processed by the writing thread of a `CaptureWriter`.
+enum CaptureCommand {
+    /// Append the given encoded record to the file.
+    Record(Vec<u8>),
+    /// Flush the buffered records, and report the outcome (along with any earlier write error).
+    Flush(mpsc::SyncSender<io::Result<()>>),
+}
+
+/// Writes the frames exchanged with the peers to a PCAP-like file, which can be read back with `read_capture`.
+///
+/// The file starts with the `P2PCAP` magic bytes and a 2B format version, followed by the records; each of them
+/// consists of the timestamp (8B, microseconds since the Unix epoch), the direction (1B: 0 for inbound, 1 for
+/// outbound), the IP version (1B: 4 or 6) and the IP of the peer (4B or 16B), the port of the peer (2B), the length
+/// of the frame (4B) and the frame itself; all the integers are little-endian.
+///
+/// The records are queued for a dedicated thread that writes them to the file, so recording a frame doesn't block;
+/// if the thread can't keep up, the records exceeding its queue are dropped (see `CaptureWriter::num_dropped`).
+pub struct CaptureWriter {
+    sender: Option<mpsc::SyncSender<CaptureCommand>>,
+    thread: Option<thread::JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+impl CaptureWriter {
+    /// Creates a capture file at the given path, truncating it if it already exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Arc<Self>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(CAPTURE_MAGIC)?;
+        file.write_all(&CAPTURE_VERSION.to_le_bytes())?;
+
+        let (sender, receiver) = mpsc::sync_channel(CAPTURE_QUEUE_DEPTH);
+        let thread = thread::Builder::new()
+            .name("pea2pea-capture".into())
+            .spawn(move || write_records(file, receiver))?;
+
+        Ok(Arc::new(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            dropped: Default::default(),
+        }))
+    }
+
+    /// Registers the capture as both the inbound and the outbound tap of the given node; since each tap can only
+    /// be set once, it can't be combined with other taps.
+    pub fn attach(self: &Arc<Self>, node: &Node) {
+        let capture = Arc::clone(self);
+        node.set_inbound_tap(move |addr, timestamp, frame| {
+            let _ = capture.record(addr, FrameDirection::Inbound, timestamp, frame);
+        });
+        let capture = Arc::clone(self);
+        node.set_outbound_tap(move |addr, timestamp, frame| {
+            let _ = capture.record(addr, FrameDirection::Outbound, timestamp, frame);
+        });
+    }
+
+    /// Queues the given frame to be appended to the capture; returns an `io::ErrorKind::WouldBlock` error if the
+    /// queue is full, in which case the frame is dropped.
+    pub fn record(
+        &self,
+        addr: SocketAddr,
+        direction: FrameDirection,
+        timestamp: SystemTime,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let micros = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let frame_len = u32::try_from(frame.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "the frame is too large"))?;
+
+        let mut record = Vec::with_capacity(32 + frame.len());
+        record.extend_from_slice(&micros.to_le_bytes());
+        record.push(match direction {
+            FrameDirection::Inbound => 0,
+            FrameDirection::Outbound => 1,
+        });
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                record.push(4);
+                record.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                record.push(6);
+                record.extend_from_slice(&ip.octets());
+            }
+        }
+        record.extend_from_slice(&addr.port().to_le_bytes());
+        record.extend_from_slice(&frame_len.to_le_bytes());
+        record.extend_from_slice(frame);
+
+        match self.sender().try_send(CaptureCommand::Record(record)) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Relaxed);
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Waits until the records queued so far are written, and flushes them to the file; returns the first error
+    /// encountered while writing, if there was one.
+    pub fn flush(&self) -> io::Result<()> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.sender()
+            .send(CaptureCommand::Flush(sender))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+        receiver
+            .recv()
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?
+    }
+
+    /// Returns the number of records that were dropped due to the writing thread not keeping up.
+    pub fn num_dropped(&self) -> u64 {
+        self.dropped.load(Relaxed)
+    }
+
+    fn sender(&self) -> &mpsc::SyncSender<CaptureCommand> {
+        // it's only taken in the Drop impl
+        self.sender.as_ref().unwrap()
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        // closing the queue makes the thread flush the remaining records and exit
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes the queued records to the capture file until its `CaptureWriter` is dropped.
+fn write_records(mut file: BufWriter<File>, receiver: mpsc::Receiver<CaptureCommand>) {
+    let mut error = None;
+    for command in receiver {
+        match command {
+            CaptureCommand::Record(record) => {
+                if error.is_none() {
+                    error = file.write_all(&record).err();
+                }
+            }
+            CaptureCommand::Flush(reply) => {
+                let result = match error.take() {
+                    Some(e) => Err(e),
+                    None => file.flush(),
+                };
+                let _ = reply.send(result);
+            }
+        }
+    }
+    let _ = file.flush();
+}
+
+/// Reads all the frames from a capture file created with `CaptureWriter`.
+pub fn read_capture<P: AsRef<Path>>(path: P) -> io::Result<Vec<CapturedFrame>> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+    if &header[..6] != CAPTURE_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a capture file",
+        ));
+    }
+    if u16::from_le_bytes([header[6], header[7]]) != CAPTURE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported capture version",
+        ));
+    }
+
+    let mut frames = Vec::new();
+    loop {
+        let mut timestamp = [0u8; 8];
+        match file.read_exact(&mut timestamp) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let timestamp = UNIX_EPOCH + Duration::from_micros(u64::from_le_bytes(timestamp));
+
+        let mut kinds = [0u8; 2];
+        file.read_exact(&mut kinds)?;
+        let direction = match kinds[0] {
+            0 => FrameDirection::Inbound,
+            1 => FrameDirection::Outbound,
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+        let ip = match kinds[1] {
+            4 => {
+                let mut octets = [0u8; 4];
+                file.read_exact(&mut octets)?;
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let mut octets = [0u8; 16];
+                file.read_exact(&mut octets)?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+
+        let mut port = [0u8; 2];
+        file.read_exact(&mut port)?;
+        let mut frame_len = [0u8; 4];
+        file.read_exact(&mut frame_len)?;
+        // the length is untrusted, so the buffer only grows as the frame is actually read
+        let frame_len = u32::from_le_bytes(frame_len) as usize;
+        let mut data = Vec::with_capacity(frame_len.min(MAX_FRAME_PREALLOCATION));
+        (&mut file).take(frame_len as u64).read_to_end(&mut data)?;
+        if data.len() != frame_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        frames.push(CapturedFrame {
+            addr: SocketAddr::new(ip, u16::from_le_bytes(port)),
+            direction,
+            timestamp,
+            data: data.into(),
+        });
+    }
+
+    Ok(frames)
+}
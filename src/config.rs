@@ -1,6 +1,21 @@
+#[cfg(feature = "bootstrap")]
+use crate::bootstrap::SeedList;
+#[cfg(feature = "compression")]
+use crate::compression::CompressionConfig;
+#[cfg(feature = "identity")]
+use crate::identity::NodeIdentity;
+use crate::protocols::Rejection;
+
+use bytes::Bytes;
+use tokio::runtime::Handle;
+use tracing::Dispatch;
+
 use std::{
+    collections::HashMap,
     io::{self, ErrorKind::*},
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::RangeInclusive,
+    time::Duration,
 };
 
 /// The node's configuration.
@@ -10,26 +25,149 @@ pub struct NodeConfig {
     ///
     /// note: if set to `None`, the `Node` will automatically be assigned a sequential, zero-based numeric identifier.
     pub name: Option<String>,
+    /// The `tracing` dispatcher that the node's spans and the events emitted by its tasks are sent to instead of the
+    /// default one; e.g. `Dispatch::new(NoSubscriber::default())` silences the node, which can be useful in tests
+    /// involving many nodes.
+    ///
+    /// note: the events emitted directly by the node's methods (e.g. `Node::disconnect`) are still sent to the
+    /// dispatcher of the caller.
+    pub tracing_dispatch: Option<Dispatch>,
+    /// The runtime that all the node's tasks are spawned on, and that its listener and outbound connections are
+    /// registered with, instead of the runtime that `Node::new` is called from; useful in applications running
+    /// multiple runtimes, or ones that need to control the order in which they shut down.
+    ///
+    /// note: the streams passed to `Node::accept_stream` remain registered with the runtime they were created in.
+    pub runtime: Option<Handle>,
     /// The IP address the node's connection listener should bind to.
     pub listener_ip: IpAddr,
     /// The desired listening port of the node.
     pub desired_listening_port: Option<u16>,
-    /// Allow listening on a different port if `desired_listening_port` is unavailable.
+    /// The ports to try in order if `desired_listening_port` isn't provided or is unavailable, before falling back
+    /// to a random port (if allowed); useful in firewalled deployments that only open a window of ports.
+    pub listening_port_range: Option<RangeInclusive<u16>>,
+    /// Allow listening on a random port if `desired_listening_port` and the ones in `listening_port_range` are
+    /// unavailable.
     pub allow_random_port: bool,
+    /// Start accepting inbound connections as soon as the node is created; otherwise the listening address is only
+    /// procured, and `Node::start_listening` needs to be called in order to accept connections.
+    pub listen_on_start: bool,
+    /// Never bind a listener, making the node outbound-only; useful for clients behind a NAT.
+    ///
+    /// note: when enabled, `Node::listening_addr` returns `None` and the other listener-related settings are ignored.
+    pub no_listener: bool,
+    /// Set `TCP_NODELAY` on all the connections, disabling Nagle's algorithm; it lowers the latency of small messages.
+    pub tcp_nodelay: bool,
+    /// If specified, enables `SO_KEEPALIVE` on all the connections, with TCP keep-alive probes sent after the given
+    /// idle time and then repeatedly at the same interval.
+    pub tcp_keepalive_interval_ms: Option<u64>,
+    /// If set, the `Writing` protocol sends a heartbeat message via the connections that have been idle for a while,
+    /// and the `Reading` protocol silently consumes the ones it receives (see `Reading::is_heartbeat`); unlike TCP
+    /// keep-alive, this keeps NAT mappings and application-aware middleboxes from dropping idle connections.
+    pub heartbeat: Option<Heartbeat>,
+    /// If specified, overrides the size of the OS send buffer (`SO_SNDBUF`) of all the connections.
+    pub tcp_send_buffer_size: Option<usize>,
+    /// If specified, overrides the size of the OS receive buffer (`SO_RCVBUF`) of all the connections.
+    pub tcp_recv_buffer_size: Option<usize>,
+    /// If specified, sets `SO_LINGER` on all the connections, i.e. the time a closed connection waits for unsent
+    /// data to be transmitted.
+    pub tcp_linger_ms: Option<u64>,
     /// The depth of the queues passing connections to protocol handlers.
     pub protocol_handler_queue_depth: usize,
-    /// The size of a per-connection buffer for reading inbound messages.
+    /// The size of a per-connection buffer for reading inbound messages; if `read_buffer_growth` is set, it's the
+    /// maximum size the buffers can grow to. It is also the maximum size of an inbound message.
     pub conn_read_buffer_size: usize,
+    /// If set, the per-connection read buffers start small and grow on demand, up to `conn_read_buffer_size`,
+    /// which reduces the memory use of nodes with many connections that only occasionally receive large messages.
+    pub read_buffer_growth: Option<ReadBufferGrowth>,
     /// The size of a per-connection buffer for writing outbound messages.
     pub conn_write_buffer_size: usize,
     /// The depth of per-connection queues used to process inbound messages.
     pub conn_inbound_queue_depth: usize,
+    /// Process inbound messages directly in the connection's reading task instead of queueing them for a dedicated
+    /// processing task; this lowers latency, but no reads are performed while a message is being processed.
+    ///
+    /// note: when enabled, `conn_inbound_queue_depth` is not applicable.
+    pub direct_message_processing: bool,
+    /// The way inbound messages are processed once they are queued for processing.
+    ///
+    /// note: not applicable when `direct_message_processing` is enabled, as the messages are then always processed
+    /// sequentially.
+    pub message_processing_mode: ProcessingMode,
+    /// If set, the processing of an inbound message (see `Reading::process_message`) is cancelled if it takes
+    /// longer than this, so that a stuck handler can't wedge the connection; the timeout is signaled by
+    /// `NodeEvent::ProcessingTimedOut`.
+    pub message_processing_timeout_ms: Option<u64>,
+    /// Disconnect from a peer once processing its message times out (see `message_processing_timeout_ms`).
+    pub disconnect_on_processing_timeout: bool,
+    /// The action taken once the queue passing inbound messages from a connection to its processing task is
+    /// closed, i.e. the task is no longer running; it is also signaled by `NodeEvent::InboundQueueClosed`.
+    ///
+    /// note: not applicable when `direct_message_processing` is enabled, and a panic in a connection's own
+    /// processing task drops the connection instead.
+    pub closed_inbound_queue_policy: ClosedInboundQueuePolicy,
+    /// The action taken when a connection's inbound message queue (see `conn_inbound_queue_depth`) is full.
+    ///
+    /// note: not applicable when `direct_message_processing` is enabled.
+    pub inbound_queue_overflow_policy: InboundQueueOverflowPolicy,
+    /// The time after which a connection whose writer hasn't flushed any bytes despite having queued outbound
+    /// messages is considered stalled; if set, the `Writing` protocol periodically checks the connections for such
+    /// stalls, which timeouts on individual writes can miss (e.g. a peer or a middlebox that stops acknowledging).
+    pub writer_stall_timeout_ms: Option<u64>,
+    /// The action taken once a connection's writer is considered stalled (see `writer_stall_timeout_ms`).
+    pub writer_stall_action: WriterStallAction,
+    /// If set, the `Writing` protocol periodically checks the connections for peers that can't keep up with the
+    /// messages sent to them, i.e. whose outbound queues remain above a high-water mark for too long.
+    pub slow_peer_detection: Option<SlowPeerDetection>,
+    /// The maximum number of bytes per second written to a single connection by the `Writing` protocol, which
+    /// paces the writes accordingly; bursts of up to a second's worth of bytes are allowed.
+    pub max_conn_upload_rate: Option<u64>,
+    /// The maximum number of bytes per second written to all the connections by the `Writing` protocol, which
+    /// paces the writes accordingly; bursts of up to a second's worth of bytes are allowed.
+    pub max_upload_rate: Option<u64>,
+    /// Determines how the internal errors that are normally only logged (e.g. listener errors, oversized inbound
+    /// messages or message processing panics) are reported; meant for tests, so that they can fail loudly instead
+    /// of passing while the node degrades.
+    #[cfg(feature = "test-utils")]
+    pub fail_fast: FailFastMode,
+    /// The number of tasks processing the messages assigned to ordering groups (see `Reading::ordering_group`); the
+    /// groups are distributed among them, so it is the maximum number of groups processed in parallel.
+    pub num_ordering_lanes: usize,
     /// The depth of per-connection queues used to send outbound messages.
     pub conn_outbound_queue_depth: usize,
+    /// The depth of the queues passing the messages published to a topic to its local subscribers.
+    pub topic_queue_depth: usize,
+    /// The depth of the queues passing the messages received on a channel to its local reader; once a channel's
+    /// queue is full, its further messages are dropped, so that it doesn't hold up the other channels. It's also
+    /// the depth of the per-channel queues of the messages sent via `Node::send_on_channel`.
+    pub channel_queue_depth: usize,
+    /// The depth of the queues passing inbound messages to the streams returned by `Node::{incoming, incoming_from}`.
+    pub incoming_queue_depth: usize,
+    /// The depth of the queue of events emitted by the node for every subscriber; if a subscriber falls behind,
+    /// it misses the oldest events.
+    pub event_queue_depth: usize,
     /// The delay on the next read attempt from a connection that can't be read from.
     pub invalid_read_delay_secs: u64,
     /// The list of IO errors considered fatal and causing the connection to be dropped.
     pub fatal_io_errors: Vec<io::ErrorKind>,
+    /// The IP address families the node is allowed to connect with, both when dialing and accepting connections;
+    /// it also determines which addresses obtained via `Node::bootstrap` are retained, and which ones are dialed
+    /// first by `Node::connect_many`.
+    pub addr_family_policy: AddrFamilyPolicy,
+    /// If specified, the number of connections (both inbound and outbound) with a single IP and a single subnet
+    /// is limited, making it harder to eclipse the node.
+    pub subnet_limits: Option<SubnetLimits>,
+    /// The maximum numbers of connections in the given groups (see `Node::add_to_group`), enforced both when a
+    /// connection is established and when a connected peer joins a group; the groups not listed here are unlimited.
+    pub group_limits: HashMap<String, usize>,
+    /// If specified, samples of the frames exchanged with the peers are recorded and made available via
+    /// `Node::diagnostics_dump`; useful for debugging codec mismatches.
+    pub frame_sampling: Option<FrameSamplingConfig>,
+    /// The length of the window within which the requests served to each peer (see `Node::register_served_request`)
+    /// are accounted for.
+    pub serving_window_secs: u64,
+    /// If specified, `Node::can_serve` throttles the peers that consume a disproportionate share of the requests
+    /// served within the current window.
+    pub serving_fairness: Option<ServingFairness>,
     /// The maximum number of active connections the node can maintain.
     ///
     /// note: this number can very briefly be breached by 1 in case of inbound connection attempts. It can never be
@@ -37,20 +175,549 @@ pub struct NodeConfig {
     pub max_connections: u16,
     /// The maximum time allowed for a connection to perform a handshake before it is rejected.
     pub max_handshake_time_ms: u64,
+    /// The maximum number of handshakes that can be performed at the same time; pending ones are queued.
+    pub max_concurrent_handshakes: u16,
+    /// The maximum number of inbound connections that can be accepted, but not fully established (e.g. still
+    /// negotiating or handshaking) at the same time; further ones are closed as soon as they're accepted, so that
+    /// a flood of connection attempts can't exhaust the node's resources before any handshake completes.
+    pub max_pending_inbound: u16,
+    /// The maximum number of connections the listener accepts within a single tick of `accept_tick_ms`; once it's
+    /// reached, the remaining connection attempts wait in the backlog until the next tick.
+    pub max_accepts_per_tick: u16,
+    /// The duration of a tick of the listener; see `max_accepts_per_tick`.
+    pub accept_tick_ms: u64,
+    /// The protocol version advertised to peers during version negotiation.
+    pub protocol_version: u32,
+    /// The capabilities advertised to peers during version negotiation, as a bitset.
+    pub capabilities: u64,
+    /// The range of protocol versions supported by the node; a peer is accepted during version negotiation if its
+    /// version falls within this range, or if the node's `protocol_version` falls within the peer's range.
+    ///
+    /// note: if set to `None`, there is no version negotiation; otherwise it is performed upon establishing every
+    /// connection, before the `Handshaking` protocol (if enabled), and is subject to `max_handshake_time_ms`.
+    pub supported_version_range: Option<RangeInclusive<u32>>,
+    /// Upon establishing every connection (after version negotiation, if applicable), report the IP observed for
+    /// the peer and receive the one it observes for the node; these reports are tallied as votes determining
+    /// `Node::external_addr`. It needs to be enabled on both sides of a connection.
+    pub exchange_observed_addrs: bool,
+    /// The minimum number of votes required for an IP reported by the peers to become the node's external one.
+    pub min_external_addr_votes: usize,
+    /// The node's cryptographic identity; if provided, the nodes exchange and verify their public keys upon
+    /// establishing every connection (after version negotiation, if applicable), and connected peers become
+    /// addressable by their `PeerId`s.
+    #[cfg(feature = "identity")]
+    pub identity: Option<NodeIdentity>,
+    /// The action taken when a peer initiates a connection using an identity that is already connected via a
+    /// different address, e.g. in order to hold many connection slots via multiple IPs.
+    #[cfg(feature = "identity")]
+    pub duplicate_identity_policy: DuplicateIdentityPolicy,
+    /// The compression settings; if provided, the nodes exchange the IDs of their pre-shared dictionaries upon
+    /// establishing every connection, and the ones known to both sides are used by `Node::{compress, decompress}`.
+    #[cfg(feature = "compression")]
+    pub compression: Option<CompressionConfig>,
+    /// The initial interval after which an idle connection is sent a keep-alive message by the `KeepAlive` protocol.
+    pub keepalive_interval_ms: u64,
+    /// The lower bound of the keep-alive interval learned for a peer.
+    pub min_keepalive_interval_ms: u64,
+    /// The upper bound of the keep-alive interval learned for a peer.
+    pub max_keepalive_interval_ms: u64,
+    /// The interval at which the `PeerExchange` protocol shares the addresses of known peers with the connected ones.
+    pub pex_interval_ms: u64,
+    /// The maximum number of addresses shared in a single peer-exchange message; messages containing more
+    /// addresses are rejected by `Node::handle_pex_message`.
+    pub pex_max_addrs: usize,
+    /// The maximum time since a known peer was last seen or active for its address to be shared by the
+    /// `PeerExchange` protocol.
+    pub pex_max_addr_age_secs: u64,
+    /// The maximum number of addresses learned via peer exchange that haven't been dialed yet; further ones are
+    /// ignored by `Node::handle_pex_message`.
+    pub pex_max_unverified_addrs: usize,
+    /// The maximum number of addresses learned via peer exchange from a single IP that haven't been dialed yet.
+    pub pex_max_unverified_addrs_per_source: usize,
+    /// The interval at which the acks queued by the `Acknowledging` protocol are sent to peers in batches.
+    pub ack_flush_interval_ms: u64,
+    /// The number of the most recent sequenced messages retained per peer for retransmission; see
+    /// `Node::send_sequenced`.
+    pub retransmit_buffer_len: usize,
+    /// The maximum time allowed for an outbound TCP connection to be established before the attempt is aborted.
+    pub dial_timeout: Duration,
+    /// If specified, the outbound connection attempts that fail transiently (e.g. due to a handshake timeout or a
+    /// busy peer) are retried; it can be overridden for specific attempts via `ConnectOptions`.
+    pub connect_retry_policy: Option<RetryPolicy>,
+    /// If specified, the node watches for the symptoms of a likely network partition or a loss of local
+    /// connectivity, and signals them with `NetworkEvent::PartitionSuspected`.
+    pub partition_detection: Option<PartitionDetection>,
+    /// The maximum number of connection attempts performed at the same time by `Node::connect_many`.
+    pub max_concurrent_dials: u16,
+    /// The number of failures that an hour of staleness of an address is worth when prioritizing dials; the higher
+    /// it is, the more strongly recently seen addresses are preferred over stale ones.
+    pub dial_freshness_weight: f64,
+    /// The time for which the names resolved via `Node::resolve` are cached if the resolver doesn't provide their
+    /// TTL; the TTLs provided by the resolver are capped at `dns_max_ttl_secs`.
+    pub dns_default_ttl_secs: u64,
+    /// The maximum time for which a resolved name is cached; 0 disables the caching.
+    pub dns_max_ttl_secs: u64,
+    /// The maximum number of names cached by `Node::resolve`; once it's reached, the expired names are dropped,
+    /// followed by the ones closest to expiring. 0 disables the caching.
+    pub dns_cache_capacity: usize,
+    /// If specified, `Node::connect_dns` races the connection attempts to the resolved addresses (Happy Eyeballs):
+    /// the addresses are ordered so that the IPv6 and IPv4 ones alternate, and the next attempt is started as
+    /// soon as the previous ones fail or after this delay, whichever comes first; otherwise the addresses are
+    /// attempted one by one.
+    pub happy_eyeballs_delay_ms: Option<u64>,
+    /// The maximum number of message hashes remembered by `Node::relay` in order to suppress echoes and repeated
+    /// relays; the least recently seen ones are evicted first.
+    pub seen_cache_capacity: usize,
+    /// The time for which `Node::relay` remembers a message.
+    pub seen_cache_ttl_secs: u64,
+    /// The peers dialed (with retries, as per `NodeConfig.connect_retry_policy` or the default `RetryPolicy`) once
+    /// bootstrapping starts, and re-dialed whenever the node loses all of its connections; the completion of every
+    /// such round is signaled with `NetworkEvent::BootstrapCompleted`.
+    pub bootstrap_peers: Vec<SocketAddr>,
+    /// The host names (along with the ports) dialed via `Node::connect_dns` along with `bootstrap_peers`; they are
+    /// resolved anew in every round (subject to the caching of `Node::resolve`), so that they keep working when
+    /// their addresses rotate.
+    pub bootstrap_hosts: Vec<(String, u16)>,
+    /// Start bootstrapping from `bootstrap_peers` and `bootstrap_hosts` as soon as the node is created, i.e. before
+    /// any protocols (e.g. `Handshaking`) can be enabled, so it's only suitable for nodes that don't use any;
+    /// otherwise (the default), `Node::start_bootstrapping` needs to be called once the protocols are enabled.
+    pub bootstrap_on_start: bool,
+    /// The delay before the bootstrap peers are re-dialed after the node loses all of its connections.
+    pub bootstrap_redial_delay_ms: u64,
+    /// The remote seed lists used by `Node::bootstrap` to discover peers; their addresses are also dialed along with
+    /// `bootstrap_peers`.
+    #[cfg(feature = "bootstrap")]
+    pub seed_lists: Vec<SeedList>,
+    /// Map the listening port on the local gateway via NAT-PMP (or UPnP, if the gateway doesn't support it) once the
+    /// node starts, renewing the lease until it shuts down; the resulting address is available via
+    /// `Node::external_addr`.
+    #[cfg(feature = "nat")]
+    pub nat_port_mapping: bool,
+    /// The address of the gateway's NAT-PMP server; if not specified, the default gateway is used (Linux only). UPnP
+    /// gateways are discovered via SSDP.
+    #[cfg(feature = "nat")]
+    pub nat_gateway: Option<SocketAddr>,
+    /// The requested lifetime of the port mapping, in seconds; must be nonzero.
+    #[cfg(feature = "nat")]
+    pub nat_lease_secs: u32,
+}
+
+/// Specifies how queued inbound messages are processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingMode {
+    /// The messages are processed strictly in order, one at a time.
+    Sequential,
+    /// Up to `max_in_flight` messages are processed at the same time; their processing can conclude in any order.
+    Concurrent {
+        /// The maximum number of messages processed at the same time.
+        max_in_flight: usize,
+    },
+    /// The messages from all the connections are processed by a single task that draws them round-robin across the
+    /// connections, so that the processing capacity is shared fairly and a chatty peer can't monopolize it; up to
+    /// `max_in_flight` messages (from any connections) are processed at the same time, so the messages from a
+    /// single connection are only guaranteed to be processed in order if it's 1.
+    Fair {
+        /// The maximum number of messages processed at the same time.
+        max_in_flight: usize,
+    },
+}
+
+/// Specifies how the internal errors that are normally only logged are reported; available with the `test-utils`
+/// feature.
+#[cfg(feature = "test-utils")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailFastMode {
+    /// The errors are only logged.
+    Disabled,
+    /// The errors are also recorded; they are available via `Node::failures`, and `Node::assert_healthy` panics if
+    /// there are any.
+    Record,
+    /// The errors are also recorded, and they cause a panic right away.
+    Panic,
+}
+
+/// Specifies the action taken when a peer initiates a connection using an identity that is already connected via a
+/// different address; available with the `identity` feature.
+#[cfg(feature = "identity")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateIdentityPolicy {
+    /// Reject the new connection.
+    RejectNew,
+    /// Accept the new connection, and disconnect from the old address.
+    ReplaceOld,
+    /// Accept the new connection, and keep the old one too.
+    AllowBoth,
+}
+
+/// Specifies the action taken once the queue passing inbound messages from a connection to its processing task is
+/// closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClosedInboundQueuePolicy {
+    /// Stop reading from the connection, but keep it alive.
+    PauseReading,
+    /// Disconnect from the peer.
+    Disconnect,
+    /// Disconnect from all the peers.
+    DisconnectAll,
+    /// Shut the node down.
+    ShutDown,
+    /// Keep reading from the connection, but silently drop the messages; they are counted in `NodeStats::dropped`.
+    DropMessages,
+}
+
+/// Specifies the action taken once the queue passing inbound messages from a connection to its processing task is
+/// full; the messages dropped as a result are counted in `NodeStats::overflowed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InboundQueueOverflowPolicy {
+    /// Stop reading from the connection until there's room in the queue.
+    Block,
+    /// Drop the newly read message.
+    DropNewest,
+    /// Drop the oldest queued message in order to make room for the newly read one.
+    DropOldest,
+    /// Disconnect from the peer.
+    DisconnectPeer,
+}
+
+/// Specifies the action taken once a connection's writer is considered stalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriterStallAction {
+    /// Only log the stall.
+    Log,
+    /// Log the stall and emit a `NodeEvent::WriterStalled`.
+    EmitEvent,
+    /// Log the stall, emit a `NodeEvent::WriterStalled` and disconnect from the peer.
+    Disconnect,
+}
+
+/// Specifies how the peers that can't keep up with the outbound messages are detected; see
+/// `NodeConfig.slow_peer_detection`. Such peers are signaled with `NodeEvent::SlowPeer`.
+#[derive(Debug, Clone)]
+pub struct SlowPeerDetection {
+    /// The number of queued outbound messages above which a peer is considered to be falling behind.
+    pub high_water_mark: usize,
+    /// The time a peer's outbound queue needs to remain above the high-water mark for it to be considered slow.
+    pub max_duration_ms: u64,
+    /// Disconnect from the slow peers.
+    pub disconnect: bool,
+}
+
+impl Default for SlowPeerDetection {
+    fn default() -> Self {
+        Self {
+            high_water_mark: 12,
+            max_duration_ms: 5_000,
+            disconnect: false,
+        }
+    }
+}
+
+/// Specifies the application-level heartbeats sent via idle connections; see `NodeConfig.heartbeat`.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    /// The time a connection needs to remain idle (i.e. without any writes) for a heartbeat to be sent.
+    pub interval_ms: u64,
+    /// The payload of the heartbeat message; it is framed with `Writing::write_message` like any other payload, and
+    /// the receiving side needs to recognize it via `Reading::is_heartbeat`.
+    pub payload: Bytes,
+}
+
+/// Specifies how the frames exchanged with the peers are sampled; see `NodeConfig.frame_sampling`.
+#[derive(Debug, Clone)]
+pub struct FrameSamplingConfig {
+    /// The number of leading bytes of a frame recorded in its sample.
+    pub prefix_len: usize,
+    /// Every `sample_interval`-th frame (regardless of its direction and peer) is sampled.
+    pub sample_interval: u32,
+    /// The maximum number of frames sampled per second.
+    pub max_samples_per_sec: u32,
+    /// The number of the most recent samples retained.
+    pub max_retained_samples: usize,
+    /// Only record the sizes of the sampled frames, and not their leading bytes, which could contain sensitive data.
+    pub redact: bool,
+}
+
+impl Default for FrameSamplingConfig {
+    fn default() -> Self {
+        Self {
+            prefix_len: 16,
+            sample_interval: 100,
+            max_samples_per_sec: 10,
+            max_retained_samples: 256,
+            redact: true,
+        }
+    }
+}
+
+/// Specifies when the peers are throttled for consuming too many serving resources; see
+/// `NodeConfig.serving_fairness`.
+#[derive(Debug, Clone)]
+pub struct ServingFairness {
+    /// The maximum share (between 0 and 1) of all the requests served within the window that a single peer can
+    /// consume before it's throttled; it only applies once other peers were served within the window too.
+    pub max_share: f64,
+    /// The number of requests that a peer is always served within the window, regardless of its share.
+    pub min_requests: u64,
+}
+
+impl Default for ServingFairness {
+    fn default() -> Self {
+        Self {
+            max_share: 0.25,
+            min_requests: 100,
+        }
+    }
+}
+
+/// Specifies how failed outbound connection attempts are retried; see `NodeConfig.connect_retry_policy`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of retries of a connection attempt.
+    pub max_retries: u32,
+    /// The delay before the first retry; it doubles with every subsequent one.
+    pub initial_backoff_ms: u64,
+    /// The maximum delay between retries.
+    pub max_backoff_ms: u64,
+    /// The errors considered transient, i.e. the ones the attempts are retried after; a `Rejection` received from
+    /// the peer never is.
+    pub transient_errors: Vec<io::ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 5_000,
+            transient_errors: vec![
+                TimedOut,
+                ConnectionRefused,
+                ConnectionReset,
+                ConnectionAborted,
+                UnexpectedEof,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns a policy that doesn't retry any attempts.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Checks whether the given error is considered transient.
+    pub(crate) fn is_transient(&self, error: &io::Error) -> bool {
+        // a rejection received during the handshake is deliberate
+        Rejection::from_error(error).is_none() && self.transient_errors.contains(&error.kind())
+    }
+
+    /// Returns the delay before the retry with the given (zero-based) index.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1u64.checked_shl(retry).unwrap_or(u64::MAX));
+
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// Specifies the symptoms of a likely network partition; see `NodeConfig.partition_detection`.
+#[derive(Debug, Clone)]
+pub struct PartitionDetection {
+    /// The window within which the lost peers are counted.
+    pub window_secs: u64,
+    /// The fraction (between 0 and 1) of the peers that need to be lost within the window.
+    pub min_lost_fraction: f64,
+    /// The minimum number of peers connected at the beginning of the window, so that losing a few peers of a
+    /// sparsely connected node isn't considered a partition.
+    pub min_peers: usize,
+    /// The number of consecutive failed outbound connection attempts.
+    pub max_dial_failures: usize,
+}
+
+impl Default for PartitionDetection {
+    fn default() -> Self {
+        Self {
+            window_secs: 10,
+            min_lost_fraction: 0.5,
+            min_peers: 4,
+            max_dial_failures: 8,
+        }
+    }
+}
+
+/// Specifies the IP address families the node is allowed to connect with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrFamilyPolicy {
+    /// Both IPv4 and IPv6 addresses are allowed, with no preference.
+    Any,
+    /// Both IPv4 and IPv6 addresses are allowed, but the IPv4 ones are dialed first.
+    PreferIpv4,
+    /// Both IPv4 and IPv6 addresses are allowed, but the IPv6 ones are dialed first.
+    PreferIpv6,
+    /// Only IPv4 addresses are allowed.
+    Ipv4Only,
+    /// Only IPv6 addresses are allowed.
+    Ipv6Only,
+}
+
+impl AddrFamilyPolicy {
+    /// Checks whether the given address is allowed; IPv4-mapped IPv6 addresses are considered to be IPv4 ones.
+    pub fn allows(&self, addr: SocketAddr) -> bool {
+        match self {
+            Self::Ipv4Only => is_ipv4(addr),
+            Self::Ipv6Only => !is_ipv4(addr),
+            _ => true,
+        }
+    }
+
+    /// Returns the dialing priority of the given address; the lower it is, the sooner the address is dialed.
+    pub(crate) fn priority(&self, addr: SocketAddr) -> u8 {
+        match self {
+            Self::PreferIpv4 => !is_ipv4(addr) as u8,
+            Self::PreferIpv6 => is_ipv4(addr) as u8,
+            _ => 0,
+        }
+    }
+}
+
+/// The strategy of sizing the per-connection read buffers; see `NodeConfig.read_buffer_growth`. A buffer starts
+/// at `initial_size` and doubles whenever a message doesn't fit in it, up to `NodeConfig.conn_read_buffer_size`;
+/// once `shrink_after_reads` consecutive reads fit within `initial_size`, it's shrunk back.
+#[derive(Debug, Clone)]
+pub struct ReadBufferGrowth {
+    /// The initial size of a read buffer.
+    pub initial_size: usize,
+    /// The number of consecutive reads fitting within `initial_size` after which a grown buffer is shrunk back.
+    pub shrink_after_reads: usize,
+}
+
+impl Default for ReadBufferGrowth {
+    fn default() -> Self {
+        Self {
+            initial_size: 4 * 1024,
+            shrink_after_reads: 64,
+        }
+    }
+}
+
+/// Limits the number of connections per IP and per subnet, i.e. a /24 prefix for IPv4 (and IPv4-mapped IPv6)
+/// addresses, and a /64 prefix for IPv6 ones; see `NodeConfig.subnet_limits`.
+#[derive(Debug, Clone)]
+pub struct SubnetLimits {
+    /// The maximum number of connections with a single IP.
+    pub max_per_ip: usize,
+    /// The maximum number of connections with a single subnet.
+    pub max_per_subnet: usize,
+}
+
+impl Default for SubnetLimits {
+    fn default() -> Self {
+        Self {
+            max_per_ip: 1,
+            max_per_subnet: 2,
+        }
+    }
+}
+
+impl SubnetLimits {
+    /// Checks whether a connection with the given address is allowed alongside the existing ones.
+    pub(crate) fn allows<I: IntoIterator<Item = SocketAddr>>(
+        &self,
+        addr: SocketAddr,
+        existing: I,
+    ) -> bool {
+        let (ip, prefix) = (canonical_ip(addr.ip()), subnet(addr.ip()));
+        let (mut num_same_ip, mut num_same_subnet) = (0, 0);
+        for existing in existing {
+            if canonical_ip(existing.ip()) == ip {
+                num_same_ip += 1;
+            }
+            if subnet(existing.ip()) == prefix {
+                num_same_subnet += 1;
+            }
+        }
+
+        num_same_ip < self.max_per_ip && num_same_subnet < self.max_per_subnet
+    }
+}
+
+/// Converts an IPv4-mapped IPv6 address to the IPv4 one, and returns the other ones unchanged.
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+/// Returns the subnet of the given IP: its /24 prefix for IPv4 (and IPv4-mapped IPv6) addresses, and its /64
+/// prefix for IPv6 ones.
+fn subnet(ip: IpAddr) -> IpAddr {
+    match canonical_ip(ip) {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4([a, b, c, 0].into())
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            IpAddr::V6([a, b, c, d, 0, 0, 0, 0].into())
+        }
+    }
+}
+
+/// Checks whether the given address is an IPv4 (or an IPv4-mapped IPv6) one.
+fn is_ipv4(addr: SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().is_some(),
+    }
 }
 
 impl Default for NodeConfig {
     fn default() -> Self {
         Self {
             name: None,
+            tracing_dispatch: None,
+            runtime: None,
             listener_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             desired_listening_port: None,
+            listening_port_range: None,
             allow_random_port: true,
+            listen_on_start: true,
+            no_listener: false,
+            tcp_nodelay: false,
+            tcp_keepalive_interval_ms: None,
+            tcp_send_buffer_size: None,
+            tcp_recv_buffer_size: None,
+            tcp_linger_ms: None,
             protocol_handler_queue_depth: 16,
             conn_read_buffer_size: 64 * 1024,
             conn_write_buffer_size: 64 * 1024,
             conn_inbound_queue_depth: 64,
+            direct_message_processing: false,
+            message_processing_mode: ProcessingMode::Sequential,
+            message_processing_timeout_ms: None,
+            disconnect_on_processing_timeout: false,
+            closed_inbound_queue_policy: ClosedInboundQueuePolicy::Disconnect,
+            inbound_queue_overflow_policy: InboundQueueOverflowPolicy::Block,
+            writer_stall_timeout_ms: None,
+            max_conn_upload_rate: None,
+            max_upload_rate: None,
+            writer_stall_action: WriterStallAction::Log,
+            slow_peer_detection: None,
+            heartbeat: None,
+            #[cfg(feature = "test-utils")]
+            fail_fast: FailFastMode::Disabled,
+            num_ordering_lanes: 8,
             conn_outbound_queue_depth: 16,
+            topic_queue_depth: 64,
+            channel_queue_depth: 64,
+            incoming_queue_depth: 64,
+            event_queue_depth: 64,
             invalid_read_delay_secs: 10,
             fatal_io_errors: vec![
                 ConnectionReset,
@@ -59,8 +726,364 @@ impl Default for NodeConfig {
                 InvalidData,
                 UnexpectedEof,
             ],
+            addr_family_policy: AddrFamilyPolicy::Any,
+            subnet_limits: None,
+            group_limits: Default::default(),
+            read_buffer_growth: None,
+            frame_sampling: None,
+            serving_window_secs: 60,
+            serving_fairness: None,
             max_connections: 100,
             max_handshake_time_ms: 3_000,
+            max_concurrent_handshakes: 16,
+            max_pending_inbound: 64,
+            max_accepts_per_tick: 64,
+            accept_tick_ms: 10,
+            protocol_version: 0,
+            capabilities: 0,
+            supported_version_range: None,
+            exchange_observed_addrs: false,
+            min_external_addr_votes: 3,
+            #[cfg(feature = "identity")]
+            identity: None,
+            #[cfg(feature = "identity")]
+            duplicate_identity_policy: DuplicateIdentityPolicy::AllowBoth,
+            #[cfg(feature = "compression")]
+            compression: None,
+            keepalive_interval_ms: 30_000,
+            min_keepalive_interval_ms: 5_000,
+            max_keepalive_interval_ms: 300_000,
+            pex_interval_ms: 60_000,
+            pex_max_addrs: 32,
+            pex_max_addr_age_secs: 3_600,
+            pex_max_unverified_addrs: 1024,
+            pex_max_unverified_addrs_per_source: 64,
+            ack_flush_interval_ms: 100,
+            retransmit_buffer_len: 256,
+            dial_timeout: Duration::from_secs(5),
+            connect_retry_policy: None,
+            partition_detection: None,
+            max_concurrent_dials: 16,
+            dial_freshness_weight: 1.0,
+            dns_default_ttl_secs: 60,
+            dns_max_ttl_secs: 3600,
+            dns_cache_capacity: 1024,
+            happy_eyeballs_delay_ms: None,
+            seen_cache_capacity: 4096,
+            seen_cache_ttl_secs: 120,
+            bootstrap_peers: Vec::new(),
+            bootstrap_hosts: Vec::new(),
+            bootstrap_on_start: false,
+            bootstrap_redial_delay_ms: 1_000,
+            #[cfg(feature = "bootstrap")]
+            seed_lists: Vec::new(),
+            #[cfg(feature = "nat")]
+            nat_port_mapping: false,
+            #[cfg(feature = "nat")]
+            nat_gateway: None,
+            #[cfg(feature = "nat")]
+            nat_lease_secs: 3_600,
+        }
+    }
+}
+
+/// Ensures that the given condition holds, returning an `InvalidInput` error with the given description otherwise.
+fn ensure(condition: bool, description: &str) -> io::Result<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(io::Error::new(InvalidInput, description))
+    }
+}
+
+impl NodeConfig {
+    /// Returns a builder of a `NodeConfig`, starting from the default one.
+    pub fn builder() -> NodeConfigBuilder {
+        NodeConfigBuilder(Default::default())
+    }
+
+    /// Checks that the combination of the configured values is valid; it is also done by `Node::new`.
+    pub fn validate(&self) -> io::Result<()> {
+        ensure(
+            self.no_listener
+                || self.desired_listening_port.is_some()
+                || self.listening_port_range.is_some()
+                || self.allow_random_port,
+            "either a desired listening port or port range must be provided or a random one must be allowed",
+        )?;
+        if let Some(ref ports) = self.listening_port_range {
+            ensure(!ports.is_empty(), "the listening port range can't be empty")?;
         }
+        ensure(
+            self.conn_read_buffer_size != 0 && self.conn_write_buffer_size != 0,
+            "the connection buffers can't be empty",
+        )?;
+        ensure(
+            self.conn_read_buffer_size <= u32::MAX as usize,
+            "the read buffer can't be larger than the maximum frame size",
+        )?;
+        ensure(
+            self.protocol_handler_queue_depth != 0
+                && self.conn_inbound_queue_depth != 0
+                && self.conn_outbound_queue_depth != 0
+                && self.topic_queue_depth != 0
+                && self.channel_queue_depth != 0
+                && self.incoming_queue_depth != 0,
+            "the queue depths must be nonzero",
+        )?;
+        ensure(
+            self.max_concurrent_handshakes != 0 && self.max_concurrent_dials != 0,
+            "the numbers of concurrent handshakes and dials must be nonzero",
+        )?;
+        ensure(
+            self.max_pending_inbound != 0 && self.max_accepts_per_tick != 0 && self.accept_tick_ms != 0,
+            "the pending inbound connection limit, the accept limit and the accept tick must be nonzero",
+        )?;
+        ensure(
+            self.min_keepalive_interval_ms <= self.keepalive_interval_ms
+                && self.keepalive_interval_ms <= self.max_keepalive_interval_ms,
+            "the keep-alive interval must be within its bounds",
+        )?;
+        ensure(
+            self.pex_interval_ms != 0 && self.pex_max_addrs != 0,
+            "the peer-exchange interval and the number of shared addresses must be nonzero",
+        )?;
+        if let Some(ref versions) = self.supported_version_range {
+            ensure(
+                versions.contains(&self.protocol_version),
+                "the protocol version must be among the supported ones",
+            )?;
+        }
+        ensure(
+            self.dial_freshness_weight.is_finite() && self.dial_freshness_weight >= 0.0,
+            "the dial freshness weight must be a non-negative number",
+        )?;
+        if let Some(ref fairness) = self.serving_fairness {
+            ensure(
+                fairness.max_share > 0.0 && fairness.max_share <= 1.0,
+                "the maximum serving share must be within (0, 1]",
+            )?;
+        }
+        if let Some(ref detection) = self.partition_detection {
+            ensure(
+                detection.min_lost_fraction > 0.0 && detection.min_lost_fraction <= 1.0,
+                "the fraction of lost peers indicating a partition must be within (0, 1]",
+            )?;
+            ensure(
+                detection.window_secs != 0 && detection.max_dial_failures != 0,
+                "the partition detection window and dial failure threshold must be nonzero",
+            )?;
+        }
+        ensure(
+            self.message_processing_timeout_ms != Some(0),
+            "the message processing timeout must be nonzero",
+        )?;
+        ensure(
+            self.max_conn_upload_rate != Some(0) && self.max_upload_rate != Some(0),
+            "the upload rate limits must be nonzero",
+        )?;
+        ensure(
+            self.seen_cache_capacity != 0,
+            "the seen message cache capacity must be nonzero",
+        )?;
+        ensure(
+            self.retransmit_buffer_len != 0,
+            "the retransmit buffer length must be nonzero",
+        )?;
+        if let Some(ref heartbeat) = self.heartbeat {
+            ensure(
+                heartbeat.interval_ms != 0,
+                "the heartbeat interval must be nonzero",
+            )?;
+        }
+        if let Some(ref detection) = self.slow_peer_detection {
+            ensure(
+                detection.high_water_mark != 0
+                    && detection.high_water_mark < self.conn_outbound_queue_depth,
+                "the outbound queue high-water mark must be nonzero and lower than the queue depth",
+            )?;
+            ensure(
+                detection.max_duration_ms != 0,
+                "the time a peer can spend above the high-water mark must be nonzero",
+            )?;
+        }
+        if let Some(ref growth) = self.read_buffer_growth {
+            ensure(
+                growth.initial_size != 0 && growth.initial_size <= self.conn_read_buffer_size,
+                "the initial read buffer size must be nonzero and can't exceed the maximum one",
+            )?;
+            ensure(
+                growth.shrink_after_reads != 0,
+                "the number of reads before shrinking a read buffer must be nonzero",
+            )?;
+        }
+        if let Some(ref limits) = self.subnet_limits {
+            ensure(
+                limits.max_per_ip != 0 && limits.max_per_ip <= limits.max_per_subnet,
+                "the per-IP connection limit must be nonzero and can't exceed the per-subnet one",
+            )?;
+        }
+        ensure(
+            self.happy_eyeballs_delay_ms != Some(0),
+            "the Happy Eyeballs delay must be nonzero",
+        )?;
+        ensure(
+            self.group_limits.values().all(|&limit| limit != 0),
+            "the group connection limits must be nonzero",
+        )?;
+        if let Some(ref sampling) = self.frame_sampling {
+            ensure(
+                sampling.sample_interval != 0,
+                "the frame sampling interval must be nonzero",
+            )?;
+        }
+        #[cfg(feature = "nat")]
+        ensure(
+            self.nat_lease_secs != 0,
+            "the lifetime of the port mapping must be nonzero",
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Sets the given fields of the `NodeConfig` held by the builder; the optional fields are set to `Some`.
+macro_rules! setters {
+    ($($(#[$attr:meta])* $field:ident: $ty:ty),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            #[doc = concat!("Sets `NodeConfig.", stringify!($field), "`.")]
+            pub fn $field<T: Into<$ty>>(mut self, value: T) -> Self {
+                self.0.$field = value.into();
+                self
+            }
+        )*
+    };
+    (optional $($(#[$attr:meta])* $field:ident: $ty:ty),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            #[doc = concat!("Sets `NodeConfig.", stringify!($field), "`.")]
+            pub fn $field<T: Into<$ty>>(mut self, value: T) -> Self {
+                self.0.$field = Some(value.into());
+                self
+            }
+        )*
+    };
+}
+
+/// A builder of a `NodeConfig`, whose `build` validates the resulting configuration; it can be obtained via
+/// `NodeConfig::builder`.
+#[derive(Debug, Clone)]
+pub struct NodeConfigBuilder(NodeConfig);
+
+impl NodeConfigBuilder {
+    setters!(optional
+        name: String,
+        tracing_dispatch: Dispatch,
+        runtime: Handle,
+        desired_listening_port: u16,
+        listening_port_range: RangeInclusive<u16>,
+        tcp_keepalive_interval_ms: u64,
+        tcp_send_buffer_size: usize,
+        tcp_recv_buffer_size: usize,
+        tcp_linger_ms: u64,
+        writer_stall_timeout_ms: u64,
+        message_processing_timeout_ms: u64,
+        max_conn_upload_rate: u64,
+        max_upload_rate: u64,
+        happy_eyeballs_delay_ms: u64,
+        connect_retry_policy: RetryPolicy,
+        partition_detection: PartitionDetection,
+        frame_sampling: FrameSamplingConfig,
+        serving_fairness: ServingFairness,
+        slow_peer_detection: SlowPeerDetection,
+        heartbeat: Heartbeat,
+        subnet_limits: SubnetLimits,
+        read_buffer_growth: ReadBufferGrowth,
+        supported_version_range: RangeInclusive<u32>,
+        #[cfg(feature = "identity")]
+        identity: NodeIdentity,
+        #[cfg(feature = "compression")]
+        compression: CompressionConfig,
+        #[cfg(feature = "nat")]
+        nat_gateway: SocketAddr,
+    );
+
+    setters!(
+        listener_ip: IpAddr,
+        allow_random_port: bool,
+        listen_on_start: bool,
+        no_listener: bool,
+        tcp_nodelay: bool,
+        protocol_handler_queue_depth: usize,
+        conn_read_buffer_size: usize,
+        conn_write_buffer_size: usize,
+        conn_inbound_queue_depth: usize,
+        direct_message_processing: bool,
+        message_processing_mode: ProcessingMode,
+        disconnect_on_processing_timeout: bool,
+        closed_inbound_queue_policy: ClosedInboundQueuePolicy,
+        inbound_queue_overflow_policy: InboundQueueOverflowPolicy,
+        writer_stall_action: WriterStallAction,
+        #[cfg(feature = "test-utils")]
+        fail_fast: FailFastMode,
+        num_ordering_lanes: usize,
+        conn_outbound_queue_depth: usize,
+        topic_queue_depth: usize,
+        channel_queue_depth: usize,
+        incoming_queue_depth: usize,
+        event_queue_depth: usize,
+        invalid_read_delay_secs: u64,
+        fatal_io_errors: Vec<io::ErrorKind>,
+        addr_family_policy: AddrFamilyPolicy,
+        group_limits: HashMap<String, usize>,
+        serving_window_secs: u64,
+        max_connections: u16,
+        max_handshake_time_ms: u64,
+        max_concurrent_handshakes: u16,
+        max_pending_inbound: u16,
+        max_accepts_per_tick: u16,
+        accept_tick_ms: u64,
+        protocol_version: u32,
+        capabilities: u64,
+        exchange_observed_addrs: bool,
+        min_external_addr_votes: usize,
+        #[cfg(feature = "identity")]
+        duplicate_identity_policy: DuplicateIdentityPolicy,
+        keepalive_interval_ms: u64,
+        min_keepalive_interval_ms: u64,
+        max_keepalive_interval_ms: u64,
+        pex_interval_ms: u64,
+        pex_max_addrs: usize,
+        pex_max_addr_age_secs: u64,
+        pex_max_unverified_addrs: usize,
+        pex_max_unverified_addrs_per_source: usize,
+        ack_flush_interval_ms: u64,
+        retransmit_buffer_len: usize,
+        dial_timeout: Duration,
+        max_concurrent_dials: u16,
+        dial_freshness_weight: f64,
+        dns_default_ttl_secs: u64,
+        dns_max_ttl_secs: u64,
+        dns_cache_capacity: usize,
+        seen_cache_capacity: usize,
+        seen_cache_ttl_secs: u64,
+        bootstrap_peers: Vec<SocketAddr>,
+        bootstrap_hosts: Vec<(String, u16)>,
+        bootstrap_on_start: bool,
+        bootstrap_redial_delay_ms: u64,
+        #[cfg(feature = "bootstrap")]
+        seed_lists: Vec<SeedList>,
+        #[cfg(feature = "nat")]
+        nat_port_mapping: bool,
+        #[cfg(feature = "nat")]
+        nat_lease_secs: u32,
+    );
+
+    /// Validates and returns the configuration.
+    pub fn build(self) -> io::Result<NodeConfig> {
+        self.0.validate()?;
+
+        Ok(self.0)
     }
 }
@@ -0,0 +1,161 @@
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The number of the most recent samples of each timing retained for a single peer.
+const MAX_PEER_SAMPLES: usize = 64;
+/// The number of the most recent samples of each timing retained for all the peers.
+const MAX_SAMPLES: usize = 1024;
+/// The number of peers whose timings are retained; once it's reached, the least recently connected one is forgotten.
+const MAX_PEERS: usize = 1024;
+
+/// The timing breakdown of establishing a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionTimings {
+    /// The time it took to establish the TCP connection; only known if the node was the initiator.
+    pub connect: Option<Duration>,
+    /// The time it took to perform the version negotiation and the handshake, and to enable the protocols.
+    pub handshake: Duration,
+    /// The time between the connection being fully established and the first message being received from it.
+    pub first_message: Option<Duration>,
+}
+
+/// The percentiles of a set of timing samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingPercentiles {
+    /// The number of samples the percentiles were calculated from.
+    pub samples: usize,
+    /// The median.
+    pub p50: Duration,
+    /// The 90th percentile.
+    pub p90: Duration,
+    /// The 99th percentile.
+    pub p99: Duration,
+    /// The maximum.
+    pub max: Duration,
+}
+
+impl TimingPercentiles {
+    /// Calculates the percentiles of the given samples; returns `None` if there are none.
+    fn new(samples: &VecDeque<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        // the nearest-rank method
+        let percentile = |p: usize| sorted[(p * sorted.len()).div_ceil(100).max(1) - 1];
+
+        Some(Self {
+            samples: sorted.len(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// The percentiles of the recent connection establishment timings; see `ConnectionTimings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionTimingStats {
+    /// The percentiles of the TCP connection times.
+    pub connect: Option<TimingPercentiles>,
+    /// The percentiles of the handshake durations.
+    pub handshake: Option<TimingPercentiles>,
+    /// The percentiles of the times to the first message.
+    pub first_message: Option<TimingPercentiles>,
+}
+
+/// The recent samples of the connection establishment timings.
+#[derive(Default)]
+struct TimingSamples {
+    connect: VecDeque<Duration>,
+    handshake: VecDeque<Duration>,
+    first_message: VecDeque<Duration>,
+    last_established: Option<Instant>,
+}
+
+impl TimingSamples {
+    fn stats(&self) -> ConnectionTimingStats {
+        ConnectionTimingStats {
+            connect: TimingPercentiles::new(&self.connect),
+            handshake: TimingPercentiles::new(&self.handshake),
+            first_message: TimingPercentiles::new(&self.first_message),
+        }
+    }
+}
+
+/// Appends the given sample, discarding the oldest one if the limit is reached.
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration, limit: usize) {
+    if samples.len() >= limit {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+/// Collects the connection establishment timings, both for all the peers and for each of them.
+#[derive(Default)]
+pub(crate) struct ConnectionMetrics {
+    all: Mutex<TimingSamples>,
+    peers: Mutex<FxHashMap<SocketAddr, TimingSamples>>,
+}
+
+impl ConnectionMetrics {
+    /// Registers the timings of a newly established connection.
+    pub(crate) fn register_established(&self, addr: SocketAddr, timings: &ConnectionTimings) {
+        let mut all = self.all.lock();
+        let mut peers = self.peers.lock();
+        if peers.len() >= MAX_PEERS && !peers.contains_key(&addr) {
+            let least_recent = peers
+                .iter()
+                .min_by_key(|(_, samples)| samples.last_established)
+                .map(|(addr, _)| *addr);
+            if let Some(least_recent) = least_recent {
+                peers.remove(&least_recent);
+            }
+        }
+        let peer = peers.entry(addr).or_default();
+        peer.last_established = Some(Instant::now());
+
+        for (samples, limit) in [(&mut *all, MAX_SAMPLES), (peer, MAX_PEER_SAMPLES)] {
+            if let Some(connect) = timings.connect {
+                push_sample(&mut samples.connect, connect, limit);
+            }
+            push_sample(&mut samples.handshake, timings.handshake, limit);
+        }
+    }
+
+    /// Registers the time to the first message received from a connection.
+    pub(crate) fn register_first_message(&self, addr: SocketAddr, first_message: Duration) {
+        push_sample(
+            &mut self.all.lock().first_message,
+            first_message,
+            MAX_SAMPLES,
+        );
+        if let Some(peer) = self.peers.lock().get_mut(&addr) {
+            push_sample(&mut peer.first_message, first_message, MAX_PEER_SAMPLES);
+        }
+    }
+
+    /// Returns the percentiles of the timings of all the peers.
+    pub(crate) fn stats(&self) -> ConnectionTimingStats {
+        self.all.lock().stats()
+    }
+
+    /// Returns the percentiles of the timings of the given peer.
+    pub(crate) fn peer_stats(&self, addr: SocketAddr) -> Option<ConnectionTimingStats> {
+        self.peers.lock().get(&addr).map(|samples| samples.stats())
+    }
+
+    /// Forgets the timings of the given peer; the samples of all the peers are retained.
+    pub(crate) fn remove_peer(&self, addr: SocketAddr) {
+        self.peers.lock().remove(&addr);
+    }
+}
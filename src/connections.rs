@@ -1,27 +1,49 @@
 //! Objects associated with connection handling.
 
-use crate::Node;
+#[cfg(feature = "identity")]
+use crate::identity::PeerId;
+use crate::{
+    node::create_conn_span,
+    protocols::{OutboundMessage, Rejection, StreamTransform},
+    tracing_targets::NODE,
+    BandwidthRates, ConnectionCounts, ConnectionTimings, Node, NodeStats, PeerCapabilities,
+    PeerStats, QueueUtilization, RetryPolicy,
+};
 
-use bytes::Bytes;
+use futures_core::Stream;
 use fxhash::FxHashMap;
-use parking_lot::RwLock;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
 use tokio::{
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream,
     },
-    sync::mpsc::Sender,
+    sync::{
+        mpsc::{Receiver, Sender},
+        watch,
+    },
     task::JoinHandle,
 };
 use tracing::*;
 
-use std::{io, net::SocketAddr, ops::Not};
+use std::{
+    any::{Any, TypeId},
+    io,
+    net::SocketAddr,
+    ops::Not,
+    panic,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 #[derive(Default)]
 pub(crate) struct Connections(RwLock<FxHashMap<SocketAddr, Connection>>);
 
 impl Connections {
-    pub(crate) fn sender(&self, addr: SocketAddr) -> io::Result<Sender<Bytes>> {
+    pub(crate) fn sender(&self, addr: SocketAddr) -> io::Result<Sender<OutboundMessage>> {
         if let Some(conn) = self.0.read().get(&addr) {
             conn.sender()
         } else {
@@ -29,22 +51,318 @@ impl Connections {
         }
     }
 
-    pub(crate) fn add(&self, conn: Connection) {
+    /// Registers a fully established connection; returns its timings.
+    pub(crate) fn add(&self, mut conn: Connection) -> ConnectionTimings {
+        // until now, the connection's establishment time was the time its stream was obtained
+        conn.timings.handshake = conn.established.elapsed();
+        conn.established = Instant::now();
+        let timings = conn.timings;
         self.0.write().insert(conn.addr, conn);
+
+        timings
+    }
+
+    pub(crate) fn register_sent_message(&self, addr: SocketAddr, len: usize) {
+        if let Some(conn) = self.0.read().get(&addr) {
+            conn.stats.register_sent_message(len);
+        }
+    }
+
+    /// Registers a received message; returns the time to the first message if it's the first one.
+    pub(crate) fn register_received_message(
+        &self,
+        addr: SocketAddr,
+        len: usize,
+    ) -> Option<Duration> {
+        let conns = self.0.read();
+        let conn = conns.get(&addr)?;
+        conn.stats.register_received_message(len);
+
+        let mut first_message = None;
+        conn.first_message.get_or_init(|| {
+            let elapsed = conn.established.elapsed();
+            first_message = Some(elapsed);
+            elapsed
+        });
+
+        first_message
+    }
+
+    /// Registers a dropped inbound message; returns `true` if it's the first one dropped for the connection.
+    pub(crate) fn register_dropped_message(&self, addr: SocketAddr) -> bool {
+        if let Some(conn) = self.0.read().get(&addr) {
+            let is_first = conn.stats.dropped() == 0;
+            conn.stats.register_dropped_message();
+            is_first
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn register_overflowed_message(&self, addr: SocketAddr) {
+        if let Some(conn) = self.0.read().get(&addr) {
+            conn.stats.register_overflowed_message();
+        }
+    }
+
+    pub(crate) fn handle(&self, addr: SocketAddr) -> Option<ConnectionHandle> {
+        self.0.read().get(&addr).map(|conn| conn.handle())
+    }
+
+    pub(crate) fn id(&self, addr: SocketAddr) -> Option<usize> {
+        self.0.read().get(&addr).map(|conn| conn.id)
+    }
+
+    pub(crate) fn info(&self, addr: SocketAddr) -> Option<ConnectionInfo> {
+        self.0.read().get(&addr).map(|conn| {
+            let (msgs_sent, bytes_sent) = conn.stats.sent();
+            let (msgs_received, bytes_received) = conn.stats.received();
+            // the capacity of the queue is only reduced by the messages it contains
+            let (outbound_queue_len, outbound_queue_capacity) = conn
+                .outbound_message_sender
+                .as_ref()
+                .map(|sender| {
+                    (
+                        sender.max_capacity() - sender.capacity(),
+                        sender.max_capacity(),
+                    )
+                })
+                .unwrap_or((0, conn.node.config().conn_outbound_queue_depth));
+
+            ConnectionInfo {
+                id: conn.id,
+                addr: conn.addr,
+                side: conn.side,
+                established: conn.established,
+                peer_capabilities: conn.peer_capabilities,
+                timings: ConnectionTimings {
+                    first_message: conn.first_message.get().copied(),
+                    ..conn.timings
+                },
+                msgs_sent,
+                msgs_received,
+                bytes_sent,
+                bytes_received,
+                msgs_overflowed: conn.stats.overflowed(),
+                rates: conn.stats.rates(),
+                mode: conn.mode(),
+                outbound_queue_len,
+                outbound_queue_capacity,
+                tags: Vec::new(),
+            }
+        })
+    }
+
+    pub(crate) fn senders(&self) -> io::Result<Vec<(SocketAddr, Sender<OutboundMessage>)>> {
+        self.0
+            .read()
+            .values()
+            .filter(|conn| !conn.closing && conn.mode().can_write())
+            .map(|conn| conn.sender().map(|sender| (conn.addr, sender)))
+            .collect()
+    }
+
+    /// Returns the state of the writer of every connection with the `Writing` protocol enabled.
+    pub(crate) fn writer_states(&self) -> Vec<WriterState> {
+        self.0
+            .read()
+            .values()
+            .filter_map(|conn| {
+                let sender = conn.outbound_message_sender.as_ref()?;
+
+                Some(WriterState {
+                    addr: conn.addr,
+                    queued: sender.max_capacity() - sender.capacity(),
+                    bytes_sent: conn.stats.sent().1,
+                    write_started: *conn.write_started.lock(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the numbers of the established connections by their direction and state, along with the utilization
+    /// of their outbound queues.
+    pub(crate) fn status(&self) -> (ConnectionCounts, QueueUtilization) {
+        let conns = self.0.read();
+        let mut counts = ConnectionCounts {
+            established: conns.len(),
+            ..Default::default()
+        };
+        let mut queues = QueueUtilization::default();
+
+        for conn in conns.values() {
+            if conn.side == ConnectionSide::Initiator {
+                counts.inbound += 1;
+            } else {
+                counts.outbound += 1;
+            }
+            if conn.closing {
+                counts.closing += 1;
+            }
+            if let Some(sender) = &conn.outbound_message_sender {
+                let queue_depth = sender.max_capacity();
+                let queued = queue_depth - sender.capacity();
+                queues.outbound_queued += queued;
+                queues.outbound_capacity += queue_depth;
+                queues.max_outbound_occupancy = queues
+                    .max_outbound_occupancy
+                    .max(queued as f64 / queue_depth.max(1) as f64);
+            }
+        }
+
+        (counts, queues)
+    }
+
+    /// Sets the mode of the connection with the given address; returns the previous one.
+    pub(crate) fn set_mode(
+        &self,
+        addr: SocketAddr,
+        mode: ConnectionMode,
+    ) -> io::Result<ConnectionMode> {
+        if let Some(conn) = self.0.read().get(&addr) {
+            Ok(conn.mode.send_replace(mode))
+        } else {
+            Err(io::ErrorKind::NotConnected.into())
+        }
     }
 
-    pub(crate) fn senders(&self) -> io::Result<Vec<Sender<Bytes>>> {
-        self.0.read().values().map(|conn| conn.sender()).collect()
+    /// Updates the mode of the connection with the given address using the provided function; returns the previous
+    /// mode.
+    pub(crate) fn update_mode<F: FnOnce(ConnectionMode) -> ConnectionMode>(
+        &self,
+        addr: SocketAddr,
+        update: F,
+    ) -> io::Result<ConnectionMode> {
+        if let Some(conn) = self.0.read().get(&addr) {
+            let mut prev = None;
+            conn.mode.send_if_modified(|mode| {
+                prev = Some(*mode);
+                *mode = update(*mode);
+                prev != Some(*mode)
+            });
+
+            Ok(prev.unwrap()) // safe; set above
+        } else {
+            Err(io::ErrorKind::NotConnected.into())
+        }
     }
 
     pub(crate) fn is_connected(&self, addr: SocketAddr) -> bool {
         self.0.read().contains_key(&addr)
     }
 
+    /// Marks the connection as closing; returns its ID.
+    pub(crate) fn set_closing(&self, addr: SocketAddr) -> io::Result<usize> {
+        if let Some(conn) = self.0.write().get_mut(&addr) {
+            if conn.closing {
+                Err(io::ErrorKind::ConnectionAborted.into())
+            } else {
+                conn.closing = true;
+                Ok(conn.id)
+            }
+        } else {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    /// Stops reading from the connection's stream, if the `Reading` protocol is enabled.
+    pub(crate) fn stop_reading(&self, addr: SocketAddr) {
+        if let Some(task) = self
+            .0
+            .read()
+            .get(&addr)
+            .and_then(|conn| conn.reader_task.as_ref())
+        {
+            task.abort();
+        }
+    }
+
+    pub(crate) fn is_closing(&self, addr: SocketAddr) -> bool {
+        self.0
+            .read()
+            .get(&addr)
+            .map(|conn| conn.closing)
+            .unwrap_or(false)
+    }
+
     pub(crate) fn remove(&self, addr: SocketAddr) -> bool {
         self.0.write().remove(&addr).is_some()
     }
 
+    pub(crate) fn remove_many(&self, addrs: &[SocketAddr]) -> Vec<Connection> {
+        let mut conns = self.0.write();
+        addrs.iter().filter_map(|addr| conns.remove(addr)).collect()
+    }
+
+    pub(crate) fn side(&self, addr: SocketAddr) -> Option<ConnectionSide> {
+        self.0.read().get(&addr).map(|conn| conn.side)
+    }
+
+    pub(crate) fn peer_capabilities(&self, addr: SocketAddr) -> Option<PeerCapabilities> {
+        self.0
+            .read()
+            .get(&addr)
+            .and_then(|conn| conn.peer_capabilities)
+    }
+
+    #[cfg(feature = "identity")]
+    pub(crate) fn peer_id(&self, addr: SocketAddr) -> Option<PeerId> {
+        self.0.read().get(&addr).and_then(|conn| conn.peer_id)
+    }
+
+    pub(crate) fn set_peer_capabilities(
+        &self,
+        addr: SocketAddr,
+        peer_caps: PeerCapabilities,
+    ) -> io::Result<()> {
+        if let Some(conn) = self.0.write().get_mut(&addr) {
+            conn.peer_capabilities = Some(peer_caps);
+            Ok(())
+        } else {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    pub(crate) fn ext<T: Clone + Send + Sync + 'static>(&self, addr: SocketAddr) -> Option<T> {
+        self.0
+            .read()
+            .get(&addr)
+            .and_then(|conn| conn.ext::<T>().cloned())
+    }
+
+    pub(crate) fn context(&self, addr: SocketAddr) -> Option<ConnectionContext> {
+        self.0.read().get(&addr).map(|conn| conn.context())
+    }
+
+    pub(crate) fn insert_ext<T: Send + Sync + 'static>(
+        &self,
+        addr: SocketAddr,
+        value: T,
+    ) -> io::Result<Option<T>> {
+        if let Some(conn) = self.0.write().get_mut(&addr) {
+            Ok(conn.insert_ext(value))
+        } else {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    #[cfg(feature = "identity")]
+    pub(crate) fn peer_public_key(&self, addr: SocketAddr) -> Option<[u8; 32]> {
+        self.0
+            .read()
+            .get(&addr)
+            .and_then(|conn| conn.peer_public_key)
+    }
+
+    #[cfg(feature = "identity")]
+    pub(crate) fn peer_addr(&self, peer_id: &PeerId) -> Option<SocketAddr> {
+        self.0
+            .read()
+            .values()
+            .find(|conn| conn.peer_id.as_ref() == Some(peer_id))
+            .map(|conn| conn.addr)
+    }
+
     pub(crate) fn num_connected(&self) -> usize {
         self.0.read().len()
     }
@@ -54,8 +372,94 @@ impl Connections {
     }
 }
 
+/// A snapshot of the state of a connection, e.g. for status RPCs and dashboards.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// The connection's unique (within the node) ID.
+    pub id: usize,
+    /// The address of the connection.
+    pub addr: SocketAddr,
+    /// The connection's side in relation to the node.
+    pub side: ConnectionSide,
+    /// The time the connection was established at.
+    pub established: Instant,
+    /// The peer's protocol version and capabilities, if version negotiation is enabled.
+    pub peer_capabilities: Option<PeerCapabilities>,
+    /// The timing breakdown of establishing the connection.
+    pub timings: ConnectionTimings,
+    /// The number of messages sent via the connection.
+    pub msgs_sent: u64,
+    /// The number of messages received via the connection.
+    pub msgs_received: u64,
+    /// The number of bytes sent via the connection.
+    pub bytes_sent: u64,
+    /// The number of bytes received via the connection.
+    pub bytes_received: u64,
+    /// The number of inbound messages dropped due to the connection's inbound queue being full.
+    pub msgs_overflowed: u64,
+    /// The rates of sending and receiving via the connection over the last 5 seconds.
+    pub rates: BandwidthRates,
+    /// The directions in which messages are currently exchanged via the connection.
+    pub mode: ConnectionMode,
+    /// The number of messages queued for the `Writing` protocol.
+    pub outbound_queue_len: usize,
+    /// The maximum number of messages that can be queued for the `Writing` protocol, i.e.
+    /// `NodeConfig.conn_outbound_queue_depth`.
+    pub outbound_queue_capacity: usize,
+    /// The application-defined tags attached to the peer via `Node::tag_peer`.
+    pub tags: Vec<String>,
+}
+
+impl ConnectionInfo {
+    /// Checks whether the connection was initiated by the peer.
+    pub fn is_inbound(&self) -> bool {
+        self.side == ConnectionSide::Initiator
+    }
+
+    /// Returns the fraction of the outbound queue that is occupied; a persistently high value indicates that the
+    /// peer can't keep up (see `NodeConfig.slow_peer_detection`).
+    pub fn outbound_queue_occupancy(&self) -> f64 {
+        self.outbound_queue_len as f64 / self.outbound_queue_capacity.max(1) as f64
+    }
+}
+
+/// The directions in which messages are exchanged via a connection; see `Node::set_connection_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionMode {
+    /// Messages are both read and written.
+    ReadWrite,
+    /// Messages are only read; no new outbound messages can be queued, and the already queued ones are held back.
+    ReadOnly,
+    /// Messages are only written; the `Reading` protocol stops reading from the connection.
+    WriteOnly,
+    /// Messages are neither read nor written, e.g. once reading from a `ReadOnly` connection is paused.
+    Suspended,
+}
+
+impl ConnectionMode {
+    /// Checks whether messages can be read in this mode.
+    pub fn can_read(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadOnly)
+    }
+
+    /// Checks whether messages can be written in this mode.
+    pub fn can_write(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::WriteOnly)
+    }
+
+    /// Returns the mode with reading paused or resumed, leaving writing unaffected.
+    pub(crate) fn with_reading(self, can_read: bool) -> Self {
+        match (can_read, self.can_write()) {
+            (true, true) => Self::ReadWrite,
+            (true, false) => Self::ReadOnly,
+            (false, true) => Self::WriteOnly,
+            (false, false) => Self::Suspended,
+        }
+    }
+}
+
 /// Indicates who was the initiator and who was the responder when the connection was established.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ConnectionSide {
     /// The side that initiated the connection.
     Initiator,
@@ -74,11 +478,27 @@ impl Not for ConnectionSide {
     }
 }
 
+/// The state of a connection's writer, as observed by the writer watchdog.
+pub(crate) struct WriterState {
+    /// The address of the connection.
+    pub(crate) addr: SocketAddr,
+    /// The number of queued outbound messages.
+    pub(crate) queued: usize,
+    /// The number of bytes sent via the connection.
+    pub(crate) bytes_sent: u64,
+    /// The time the write in progress started at, if there is one.
+    pub(crate) write_started: Option<Instant>,
+}
+
 /// Keeps track of tasks that have been spawned for the purposes of a connection; it
 /// also contains a sender that communicates with the `Writing` protocol handler.
 pub struct Connection {
     /// A reference to the owning node.
     pub node: Node,
+    /// The connection's ID, unique within the owning node.
+    pub id: usize,
+    /// The connection's tracing span.
+    span: Span,
     /// The address of the connection.
     pub addr: SocketAddr,
     /// Kept only until the protocols are enabled (`Reading` should `take()` it).
@@ -87,15 +507,53 @@ pub struct Connection {
     pub writer: Option<OwnedWriteHalf>,
     /// Handles to tasks spawned by the connection.
     pub tasks: Vec<JoinHandle<()>>,
+    /// The task reading from the stream, if the `Reading` protocol is enabled.
+    pub(crate) reader_task: Option<JoinHandle<()>>,
     /// Used to queue writes to the stream.
-    pub outbound_message_sender: Option<Sender<Bytes>>,
+    pub outbound_message_sender: Option<Sender<OutboundMessage>>,
+    /// Used by the `Reading` protocol to queue replies; it's shared with its tasks before `Writing` sets it.
+    pub(crate) reply_sender: Arc<OnceCell<Sender<OutboundMessage>>>,
     /// The connection's side in relation to the node.
     pub side: ConnectionSide,
+    /// The peer's protocol version and capabilities, if version negotiation is enabled.
+    pub peer_capabilities: Option<PeerCapabilities>,
+    /// The peer's verified identity, if the node has an identity.
+    #[cfg(feature = "identity")]
+    pub peer_id: Option<PeerId>,
+    /// The peer's verified public key, if the node has an identity.
+    #[cfg(feature = "identity")]
+    pub peer_public_key: Option<[u8; 32]>,
+    /// Applied to all the bytes read from the stream once the `Reading` protocol takes over (e.g. decryption).
+    pub inbound_transform: Option<Box<dyn StreamTransform>>,
+    /// Applied to all the bytes written to the stream once the `Writing` protocol takes over (e.g. encryption).
+    pub outbound_transform: Option<Box<dyn StreamTransform>>,
+    /// Arbitrary per-connection state, keyed by its type.
+    extensions: FxHashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    /// The value produced by the handshake; see `Connection::set_handshake_data`.
+    handshake_data: Option<Arc<dyn Any + Send + Sync>>,
+    /// The time the connection was established at.
+    established: Instant,
+    /// The statistics of the messages exchanged via the connection.
+    stats: NodeStats,
+    /// The time the write in progress started at, if there is one; set by the `Writing` protocol, so that a single
+    /// stuck write can be detected.
+    pub(crate) write_started: Arc<Mutex<Option<Instant>>>,
+    /// The timing breakdown of establishing the connection.
+    pub(crate) timings: ConnectionTimings,
+    /// The time between the connection being fully established and the first message being received from it.
+    first_message: OnceCell<Duration>,
+    /// Indicates that the connection is being closed via `Node::{disconnect_after, disconnect_gracefully}`.
+    closing: bool,
+    /// The directions in which the protocols currently exchange messages via the connection.
+    mode: watch::Sender<ConnectionMode>,
+    /// Held by the connection and its tasks; see `ConnectionHandle`.
+    task_guard: TaskGuard,
 }
 
 impl Connection {
     /// Creates a `Connection` with placeholders for protocol-related objects.
     pub(crate) fn new(
+        id: usize,
         addr: SocketAddr,
         stream: TcpStream,
         side: ConnectionSide,
@@ -105,12 +563,112 @@ impl Connection {
 
         Self {
             node: node.clone(),
+            id,
+            span: create_conn_span(node, id, addr),
             addr,
             reader: Some(reader),
             writer: Some(writer),
             side,
             tasks: Default::default(),
+            reader_task: None,
             outbound_message_sender: Default::default(),
+            reply_sender: Default::default(),
+            peer_capabilities: None,
+            #[cfg(feature = "identity")]
+            peer_id: None,
+            #[cfg(feature = "identity")]
+            peer_public_key: None,
+            inbound_transform: None,
+            outbound_transform: None,
+            extensions: Default::default(),
+            handshake_data: None,
+            established: Instant::now(),
+            stats: Default::default(),
+            write_started: Default::default(),
+            timings: Default::default(),
+            first_message: Default::default(),
+            closing: false,
+            mode: watch::channel(ConnectionMode::ReadWrite).0,
+            task_guard: TaskGuard(Arc::new(watch::channel(()).0)),
+        }
+    }
+
+    /// Returns the tracing `Span` associated with the connection; it is a child of the node's span.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Returns the connection's current mode.
+    pub fn mode(&self) -> ConnectionMode {
+        *self.mode.borrow()
+    }
+
+    /// Returns a guard that should be held by every task performing I/O or processing for the connection, so that
+    /// `ConnectionHandle::stopped` can tell when all of them are gone.
+    pub(crate) fn task_guard(&self) -> TaskGuard {
+        self.task_guard.clone()
+    }
+
+    /// Returns a handle to the connection.
+    pub fn handle(&self) -> ConnectionHandle {
+        ConnectionHandle {
+            node: self.node.clone(),
+            addr: self.addr,
+            id: self.id,
+            tasks: Arc::downgrade(&self.task_guard.0),
+            tasks_stopped: self.task_guard.0.subscribe(),
+        }
+    }
+
+    /// Subscribes to the changes of the connection's mode; used by the protocols to respect it dynamically.
+    pub(crate) fn subscribe_mode(&self) -> watch::Receiver<ConnectionMode> {
+        self.mode.subscribe()
+    }
+
+    /// Attaches a value of the given type to the connection, e.g. state established during the handshake; returns
+    /// the previously attached value of that type, if there was one.
+    pub fn insert_ext<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|prev| prev.downcast().ok())
+            .map(|prev| *prev)
+    }
+
+    /// Returns a reference to the value of the given type attached to the connection.
+    pub fn ext<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns a mutable reference to the value of the given type attached to the connection.
+    pub fn ext_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.extensions
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Detaches the value of the given type from the connection and returns it.
+    pub fn remove_ext<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.extensions
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Stores the value produced by the handshake (e.g. the negotiated parameters or a cipher state) on the
+    /// connection; it is handed to the `Reading` and `Writing` protocols via the `ConnectionContext` passed to their
+    /// `read_message`, `write_message` and `process_message_with_context` methods. It replaces the previously stored
+    /// value, if there was one.
+    pub fn set_handshake_data<T: Send + Sync + 'static>(&mut self, data: T) {
+        self.handshake_data = Some(Arc::new(data));
+    }
+
+    /// Returns the context of the connection, as seen by the `Reading` and `Writing` protocols.
+    pub fn context(&self) -> ConnectionContext {
+        ConnectionContext {
+            addr: self.addr,
+            handshake_data: self.handshake_data.clone(),
         }
     }
 
@@ -129,21 +687,303 @@ impl Connection {
     }
 
     /// Returns a `Sender` for outbound messages, as long as `Writing` is enabled.
-    fn sender(&self) -> io::Result<Sender<Bytes>> {
+    fn sender(&self) -> io::Result<Sender<OutboundMessage>> {
+        if !self.mode().can_write() {
+            debug!(target: NODE, parent: self.span(), "can't send messages: the connection is read-only");
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
         if let Some(ref sender) = self.outbound_message_sender {
             Ok(sender.clone())
         } else {
-            error!(parent: self.node.span(), "can't send messages: the Writing protocol is disabled");
+            error!(target: NODE, parent: self.span(), "can't send messages: the Writing protocol is disabled");
             Err(io::ErrorKind::Other.into())
         }
     }
 }
 
+/// A filter consulted before dialing or accepting a connection (see `Node::{set_dial_filter, set_accept_filter}`);
+/// it receives the address and its `KnownPeers` stats (if it's a known one; see `KnownPeers::inbound_stats` for how
+/// the inbound ones are matched), and returns `false` to veto the connection.
+pub type ConnectionFilter = Arc<dyn Fn(SocketAddr, Option<&PeerStats>) -> bool + Send + Sync>;
+
+/// Held by a connection and the tasks spawned for it; once all the guards are dropped, the connection's I/O and
+/// message processing have fully stopped.
+#[derive(Clone)]
+pub(crate) struct TaskGuard(Arc<watch::Sender<()>>);
+
+/// The context of a connection passed to the `Reading` and `Writing` protocols; it provides access to the value
+/// produced by the handshake (see `Connection::set_handshake_data`), which makes it unnecessary to look up the
+/// per-connection state by the peer's address.
+#[derive(Clone)]
+pub struct ConnectionContext {
+    addr: SocketAddr,
+    handshake_data: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl ConnectionContext {
+    /// Creates the context of a connection without any handshake data, e.g. for transports that don't perform the
+    /// `Handshaking` protocol, or in order to call `Reading::read_message` or `Writing::write_message` directly.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            handshake_data: None,
+        }
+    }
+
+    /// Creates the context of a connection with the given handshake data, e.g. for transports whose own handshake
+    /// produces it.
+    #[cfg(feature = "quic")]
+    pub(crate) fn with_handshake_data(
+        addr: SocketAddr,
+        handshake_data: Arc<dyn Any + Send + Sync>,
+    ) -> Self {
+        Self {
+            addr,
+            handshake_data: Some(handshake_data),
+        }
+    }
+
+    /// Returns the address of the connection.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a reference to the value produced by the handshake, provided it's of the given type.
+    pub fn handshake_data<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.handshake_data
+            .as_deref()
+            .and_then(|data| data.downcast_ref())
+    }
+}
+
+/// A handle to an established connection, obtained via `Node::connection_handle`; it can be used to close the
+/// connection, and to await the moment all of its tasks (reading, processing and writing) have stopped, e.g. in
+/// order to safely discard the state associated with the peer. It keeps the owning node alive.
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    node: Node,
+    addr: SocketAddr,
+    id: usize,
+    tasks: Weak<watch::Sender<()>>,
+    tasks_stopped: watch::Receiver<()>,
+}
+
+impl ConnectionHandle {
+    /// Returns the address of the connection.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the ID of the connection.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Disconnects from the peer, unless the connection is already closed; returns `false` if it was. A newer
+    /// connection with the same address is not affected.
+    pub fn close(&self) -> bool {
+        self.node.connection_info(self.addr).map(|info| info.id) == Some(self.id)
+            && self.node.disconnect(self.addr)
+    }
+
+    /// Checks whether all the connection's tasks have stopped; it can only be the case once it's closed.
+    pub fn is_stopped(&self) -> bool {
+        self.tasks.strong_count() == 0
+    }
+
+    /// Waits until all the connection's tasks have stopped; it doesn't close the connection on its own.
+    pub async fn stopped(&self) {
+        let mut tasks_stopped = self.tasks_stopped.clone();
+        // an error means that all the guards were dropped
+        while tasks_stopped.changed().await.is_ok() {}
+    }
+
+    /// Closes the connection and waits until all of its tasks have stopped.
+    pub async fn join(self) {
+        self.close();
+        self.stopped().await;
+    }
+}
+
+/// The options of a connection attempt started with `Node::connect_with_options`; the unspecified ones are taken
+/// from the `NodeConfig`.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Overrides `NodeConfig.dial_timeout`.
+    pub dial_timeout: Option<Duration>,
+    /// Overrides `NodeConfig.connect_retry_policy`; `RetryPolicy::none()` disables the retries.
+    pub retry_policy: Option<RetryPolicy>,
+}
+
+/// A handle to a connection attempt started with `Node::dial`; it can be used to await the outcome of the
+/// attempt or to cancel it.
+pub struct DialHandle {
+    addr: SocketAddr,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl DialHandle {
+    pub(crate) fn new(addr: SocketAddr, task: JoinHandle<io::Result<()>>) -> Self {
+        Self { addr, task }
+    }
+
+    /// Returns the address that is being connected to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Cancels the connection attempt, unless it is already concluded.
+    pub fn cancel(&self) {
+        self.task.abort();
+    }
+
+    /// Checks whether the connection attempt is concluded.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the connection attempt to conclude; a cancelled attempt results in `io::ErrorKind::Interrupted`.
+    pub async fn outcome(self) -> io::Result<()> {
+        match self.task.await {
+            Ok(ret) => ret,
+            Err(e) if e.is_cancelled() => Err(io::ErrorKind::Interrupted.into()),
+            Err(e) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// The category of a failed connection attempt, as reported by `DialOutcome::failure`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialFailure {
+    /// The peer refused the connection.
+    Refused,
+    /// The peer rejected the connection during the handshake (see `Handshaking::reject`).
+    Rejected,
+    /// The connection or its handshake timed out.
+    TimedOut,
+    /// The attempt wasn't made, e.g. due to the connection limit or the address family policy.
+    NotAllowed,
+    /// The node is already connected (or connecting) to the address.
+    AlreadyConnected,
+    /// The connection was closed or became invalid during its setup, e.g. because the handshake failed.
+    Handshake,
+    /// The attempt was cancelled.
+    Cancelled,
+    /// Any other failure.
+    Other,
+}
+
+impl DialFailure {
+    /// Categorizes the error of a failed connection attempt.
+    pub fn categorize(error: &io::Error) -> Self {
+        use io::ErrorKind::*;
+
+        if Rejection::from_error(error).is_some() {
+            return Self::Rejected;
+        }
+
+        match error.kind() {
+            ConnectionRefused => Self::Refused,
+            TimedOut => Self::TimedOut,
+            PermissionDenied | AddrInUse | AddrNotAvailable => Self::NotAllowed,
+            AlreadyExists => Self::AlreadyConnected,
+            ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe | UnexpectedEof
+            | InvalidData | InvalidInput | Unsupported => Self::Handshake,
+            Interrupted => Self::Cancelled,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// The outcome of one of the connection attempts started with `Node::dial_many`.
+#[derive(Debug)]
+pub struct DialOutcome {
+    /// The address that was connected to.
+    pub addr: SocketAddr,
+    /// The information about the established connection, or the error that caused the attempt to fail.
+    pub result: io::Result<ConnectionInfo>,
+}
+
+impl DialOutcome {
+    /// Returns the category of the failure, if the attempt failed.
+    pub fn failure(&self) -> Option<DialFailure> {
+        self.result.as_ref().err().map(DialFailure::categorize)
+    }
+}
+
+/// A stream of the outcomes of the connection attempts started with `Node::dial_many`, in the order in which they
+/// conclude. Dropping it cancels the attempts that are still pending.
+pub struct DialProgress {
+    receiver: Receiver<DialOutcome>,
+    task: JoinHandle<()>,
+    total: usize,
+    completed: usize,
+}
+
+impl DialProgress {
+    pub(crate) fn new(receiver: Receiver<DialOutcome>, task: JoinHandle<()>, total: usize) -> Self {
+        Self {
+            receiver,
+            task,
+            total,
+            completed: 0,
+        }
+    }
+
+    /// Receives the outcome of the next concluded attempt; returns `None` once all of them are concluded.
+    pub async fn recv(&mut self) -> Option<DialOutcome> {
+        let outcome = self.receiver.recv().await;
+        if outcome.is_some() {
+            self.completed += 1;
+        }
+
+        outcome
+    }
+
+    /// Returns the total number of connection attempts.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Returns the number of connection attempts whose outcomes were already received.
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+}
+
+impl Stream for DialProgress {
+    type Item = DialOutcome;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.receiver.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.completed += 1;
+        }
+
+        poll
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.total - self.completed;
+        (remaining, Some(remaining))
+    }
+}
+
+impl Drop for DialProgress {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
 impl Drop for Connection {
     fn drop(&mut self) {
-        debug!(parent: self.node.span(), "disconnecting from {}", self.addr);
+        debug!(target: NODE, parent: self.span(), "disconnecting from {}", self.addr);
 
         // shut the associated tasks down
+        if let Some(task) = &self.reader_task {
+            task.abort();
+        }
         for task in self.tasks.iter().rev() {
             task.abort();
         }
@@ -151,8 +991,10 @@ impl Drop for Connection {
         // if the (owning) node was not the initiator of the connection, it doesn't know the listening address
         // of the associated peer, so the related stats are unreliable; the next connection initiated by the
         // peer could be bound to an entirely different port number
+        // the stats are only kept if the address was registered as an alias of the peer's listening one
         if matches!(self.side, ConnectionSide::Initiator) {
             self.node.known_peers().remove(self.addr);
         }
+        self.node.known_peers().remove_alias(self.addr);
     }
 }
@@ -0,0 +1,123 @@
+use crate::config::FrameSamplingConfig;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// A snapshot of the node's diagnostic data, returned by `Node::diagnostics_dump`.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsDump {
+    /// The most recent frame samples, starting with the oldest one; empty unless `NodeConfig.frame_sampling` is
+    /// specified.
+    pub frame_samples: Vec<FrameSample>,
+    /// The known peers that have any application-defined tags attached (via `Node::tag_peer`), along with them.
+    pub peer_tags: Vec<(SocketAddr, Vec<String>)>,
+}
+
+/// The direction of a sampled frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDirection {
+    /// A frame read from the peer.
+    Inbound,
+    /// A frame written to the peer.
+    Outbound,
+}
+
+/// A sample of a frame exchanged with a peer.
+#[derive(Debug, Clone)]
+pub struct FrameSample {
+    /// The address of the peer.
+    pub addr: SocketAddr,
+    /// The direction of the frame.
+    pub direction: FrameDirection,
+    /// The time the frame was sampled at.
+    pub timestamp: Instant,
+    /// The full size of the frame.
+    pub len: usize,
+    /// The leading bytes of the frame, or `None` if `FrameSamplingConfig.redact` is enabled.
+    pub prefix: Option<Bytes>,
+}
+
+/// Records samples of the frames exchanged with the peers.
+pub(crate) struct FrameSampler {
+    config: FrameSamplingConfig,
+    state: Mutex<SamplerState>,
+}
+
+/// The mutable state of the `FrameSampler`.
+struct SamplerState {
+    /// The number of frames seen since the last sample.
+    skipped: u32,
+    /// The beginning of the current rate-limiting window.
+    window_start: Instant,
+    /// The number of samples recorded in the current rate-limiting window.
+    window_samples: u32,
+    /// The most recent samples.
+    samples: VecDeque<FrameSample>,
+}
+
+impl FrameSampler {
+    /// Creates a sampler with the given configuration.
+    pub(crate) fn new(config: FrameSamplingConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(SamplerState {
+                skipped: 0,
+                window_start: Instant::now(),
+                window_samples: 0,
+                samples: Default::default(),
+            }),
+        }
+    }
+
+    /// Considers the given frame for sampling.
+    pub(crate) fn sample(&self, addr: SocketAddr, direction: FrameDirection, frame: &[u8]) {
+        let mut state = self.state.lock();
+
+        // only every `sample_interval`-th frame is a candidate
+        state.skipped += 1;
+        if state.skipped < self.config.sample_interval {
+            return;
+        }
+        state.skipped = 0;
+
+        // don't exceed the sampling rate limit
+        let now = Instant::now();
+        if now.duration_since(state.window_start) >= Duration::from_secs(1) {
+            state.window_start = now;
+            state.window_samples = 0;
+        }
+        if state.window_samples >= self.config.max_samples_per_sec {
+            return;
+        }
+        state.window_samples += 1;
+
+        let prefix = if self.config.redact {
+            None
+        } else {
+            let prefix_len = frame.len().min(self.config.prefix_len);
+            Some(Bytes::copy_from_slice(&frame[..prefix_len]))
+        };
+
+        if state.samples.len() >= self.config.max_retained_samples {
+            state.samples.pop_front();
+        }
+        state.samples.push_back(FrameSample {
+            addr,
+            direction,
+            timestamp: now,
+            len: frame.len(),
+            prefix,
+        });
+    }
+
+    /// Returns the retained samples, starting with the oldest one.
+    pub(crate) fn samples(&self) -> Vec<FrameSample> {
+        self.state.lock().samples.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,130 @@
+use async_trait::async_trait;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{
+    io,
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+/// The answer to a DNS query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsAnswer {
+    /// The IPs the name resolves to.
+    pub ips: Vec<IpAddr>,
+    /// The time the answer can be cached for; if unknown, `NodeConfig.dns_default_ttl_secs` applies.
+    pub ttl: Option<Duration>,
+}
+
+/// An asynchronous DNS resolver, used by `Node::resolve`; it can be registered with the `Node` via
+/// `Node::set_resolver`, and defaults to `SystemResolver`.
+#[async_trait]
+pub trait Resolver: Send + Sync + 'static {
+    /// Resolves the given host name.
+    async fn resolve(&self, host: &str) -> io::Result<DnsAnswer>;
+}
+
+/// A resolver using the system's facilities via `tokio::net::lookup_host`, which performs the blocking lookups
+/// on a dedicated thread pool; it doesn't provide the TTLs of the records.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> io::Result<DnsAnswer> {
+        let mut ips = Vec::new();
+        for addr in tokio::net::lookup_host((host, 0)).await? {
+            if !ips.contains(&addr.ip()) {
+                ips.push(addr.ip());
+            }
+        }
+
+        Ok(DnsAnswer { ips, ttl: None })
+    }
+}
+
+/// The statistics of the node's DNS cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DnsCacheStats {
+    /// The number of resolutions answered from the cache.
+    pub hits: u64,
+    /// The number of resolutions that required a query.
+    pub misses: u64,
+    /// The number of cached names.
+    pub entries: usize,
+}
+
+/// Caches the resolved names until their TTLs expire.
+#[derive(Default)]
+pub(crate) struct DnsCache {
+    entries: Mutex<FxHashMap<String, (Vec<IpAddr>, Instant)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DnsCache {
+    /// Returns the cached IPs of the given name, as long as they haven't expired.
+    pub(crate) fn get(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let mut entries = self.entries.lock();
+        let cached = match entries.get(host) {
+            Some((ips, expiry)) if *expiry > Instant::now() => Some(ips.clone()),
+            Some(_) => {
+                entries.remove(host);
+                None
+            }
+            None => None,
+        };
+
+        if cached.is_some() {
+            self.hits.fetch_add(1, Relaxed);
+        } else {
+            self.misses.fetch_add(1, Relaxed);
+        }
+
+        cached
+    }
+
+    /// Caches the IPs of the given name for the specified time, making room for it if the cache already holds
+    /// `capacity` names.
+    pub(crate) fn insert(&self, host: &str, ips: Vec<IpAddr>, ttl: Duration, capacity: usize) {
+        if ttl.is_zero() || capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock();
+        if entries.len() >= capacity && !entries.contains_key(host) {
+            let now = Instant::now();
+            entries.retain(|_, (_, expiry)| *expiry > now);
+        }
+        while entries.len() >= capacity && !entries.contains_key(host) {
+            // safe; the cache isn't empty
+            let closest_to_expiry = entries
+                .iter()
+                .min_by_key(|(_, (_, expiry))| *expiry)
+                .map(|(host, _)| host.clone())
+                .unwrap();
+            entries.remove(&closest_to_expiry);
+        }
+        entries.insert(host.to_owned(), (ips, Instant::now() + ttl));
+    }
+
+    /// Removes all the cached names; returns their number.
+    pub(crate) fn flush(&self) -> usize {
+        let mut entries = self.entries.lock();
+        let num_entries = entries.len();
+        entries.clear();
+
+        num_entries
+    }
+
+    /// Returns the statistics of the cache.
+    pub(crate) fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            hits: self.hits.load(Relaxed),
+            misses: self.misses.load(Relaxed),
+            entries: self.entries.lock().len(),
+        }
+    }
+}
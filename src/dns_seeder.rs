@@ -0,0 +1,217 @@
+//! A minimal authoritative DNS responder serving the addresses of a node's verified peers as A/AAAA records;
+//! available with the `dns-seeder` feature.
+
+use crate::{tracing_targets::DISCOVERY, Node};
+
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tracing::*;
+
+use std::{convert::TryInto, net::IpAddr};
+
+/// The maximum size of a DNS message sent over UDP.
+const MAX_UDP_MSG_LEN: usize = 512;
+/// The size of a DNS message header.
+const HEADER_LEN: usize = 12;
+
+/// The record type of an IPv4 address.
+const TYPE_A: u16 = 1;
+/// The record type of an IPv6 address.
+const TYPE_AAAA: u16 = 28;
+/// The query type requesting all the records.
+const TYPE_ANY: u16 = 255;
+/// The Internet class.
+const CLASS_IN: u16 = 1;
+
+/// The response code indicating success.
+const RCODE_NO_ERROR: u8 = 0;
+/// The response code indicating a malformed query.
+const RCODE_FORMAT_ERROR: u8 = 1;
+/// The response code indicating an unsupported kind of query.
+const RCODE_NOT_IMPLEMENTED: u8 = 4;
+/// The response code indicating that the name is not served.
+const RCODE_REFUSED: u8 = 5;
+
+/// A DNS seeder answering A and AAAA queries for a single domain with the IPs of the node's verified peers, i.e.
+/// ones the node has successfully connected to; the freshest peers are served first.
+#[derive(Debug, Clone)]
+pub struct DnsSeeder {
+    /// The domain the seeder is authoritative for, e.g. `seed.example.com`.
+    pub domain: String,
+    /// The TTL of the served records, in seconds.
+    pub ttl: u32,
+    /// The maximum number of records in a single response.
+    pub max_records: usize,
+    /// If specified, only the peers listening on the given port are served, as DNS records don't contain ports.
+    pub port: Option<u16>,
+}
+
+impl DnsSeeder {
+    /// Spawns a task answering the DNS queries received via the given socket.
+    pub fn spawn(self, node: Node, socket: UdpSocket) -> JoinHandle<()> {
+        node.clone().spawn_task(format_args!("dns-seeder"), async move {
+            trace!(target: DISCOVERY, parent: node.span(), "spawned the DNS seeder task");
+            let mut query = [0u8; MAX_UDP_MSG_LEN];
+
+            loop {
+                let (len, source) = match socket.recv_from(&mut query).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!(target: DISCOVERY, parent: node.span(), "couldn't receive a DNS query: {}", e);
+                        continue;
+                    }
+                };
+
+                if let Some(response) = self.respond(&node, &query[..len]) {
+                    if let Err(e) = socket.send_to(&response, source).await {
+                        warn!(target: DISCOVERY, parent: node.span(), "couldn't answer a DNS query from {}: {}", source, e);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Returns the IPs of the node's verified peers, the freshest ones first.
+    fn seed_ips(&self, node: &Node) -> Vec<IpAddr> {
+        let known_peers = node.known_peers().read();
+        let mut peers = known_peers
+            .iter()
+            .filter(|(addr, stats)| {
+                stats.times_connected > 0
+                    && !addr.ip().is_unspecified()
+                    && self.port.map(|port| addr.port() == port).unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.freshness()));
+
+        let mut ips = Vec::with_capacity(peers.len());
+        for (addr, _) in peers {
+            if !ips.contains(&addr.ip()) {
+                ips.push(addr.ip());
+            }
+        }
+
+        ips
+    }
+
+    /// Prepares the response to the given query; malformed queries and responses are ignored.
+    fn respond(&self, node: &Node, query: &[u8]) -> Option<Vec<u8>> {
+        if query.len() < HEADER_LEN || query[2] & 0x80 != 0 {
+            return None;
+        }
+
+        let opcode = (query[2] >> 3) & 0x0f;
+        let num_questions = u16::from_be_bytes([query[4], query[5]]);
+
+        let mut response = Vec::with_capacity(MAX_UDP_MSG_LEN);
+        response.extend_from_slice(&query[..2]);
+        // QR and AA are set, RD is copied from the query
+        response.extend_from_slice(&[0x84 | (query[2] & 0x01), 0]);
+        response.extend_from_slice(&[0; 8]);
+
+        if opcode != 0 {
+            return Some(finish_response(response, RCODE_NOT_IMPLEMENTED, 0, 0));
+        }
+        let question = match parse_question(&query[HEADER_LEN..]) {
+            Some(question) if num_questions == 1 => question,
+            _ => return Some(finish_response(response, RCODE_FORMAT_ERROR, 0, 0)),
+        };
+        response.extend_from_slice(&query[HEADER_LEN..][..question.len]);
+
+        if !question
+            .name
+            .eq_ignore_ascii_case(self.domain.trim_end_matches('.'))
+        {
+            return Some(finish_response(response, RCODE_REFUSED, 1, 0));
+        }
+        if question.class != CLASS_IN {
+            return Some(finish_response(response, RCODE_NOT_IMPLEMENTED, 1, 0));
+        }
+
+        let mut num_answers = 0;
+        for ip in self.seed_ips(node) {
+            let (record_type, rdata) = match ip {
+                IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+                IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+            };
+            if question.record_type != record_type && question.record_type != TYPE_ANY {
+                continue;
+            }
+            // a pointer to the name in the question, the type, the class, the TTL, and the data
+            let record_len = 2 + 2 + 2 + 4 + 2 + rdata.len();
+            if num_answers == self.max_records || response.len() + record_len > MAX_UDP_MSG_LEN {
+                break;
+            }
+
+            response.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+            response.extend_from_slice(&record_type.to_be_bytes());
+            response.extend_from_slice(&CLASS_IN.to_be_bytes());
+            response.extend_from_slice(&self.ttl.to_be_bytes());
+            response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            response.extend_from_slice(&rdata);
+            num_answers += 1;
+        }
+        debug!(target: DISCOVERY, parent: node.span(), "serving {} DNS records", num_answers);
+
+        Some(finish_response(
+            response,
+            RCODE_NO_ERROR,
+            1,
+            num_answers as u16,
+        ))
+    }
+}
+
+/// A question from a DNS query.
+struct Question {
+    /// The queried name, without the trailing dot.
+    name: String,
+    /// The queried record type.
+    record_type: u16,
+    /// The queried class.
+    class: u16,
+    /// The size of the question.
+    len: usize,
+}
+
+/// Parses the first question of a DNS query; compressed names are not supported, as they're not used in queries.
+fn parse_question(bytes: &[u8]) -> Option<Question> {
+    let mut labels = Vec::new();
+    let mut idx = 0;
+
+    loop {
+        let label_len = *bytes.get(idx)? as usize;
+        idx += 1;
+        if label_len == 0 {
+            break;
+        }
+        if label_len > 63 {
+            return None;
+        }
+        labels.push(std::str::from_utf8(bytes.get(idx..idx + label_len)?).ok()?);
+        idx += label_len;
+    }
+
+    let record_type = u16::from_be_bytes(bytes.get(idx..idx + 2)?.try_into().ok()?);
+    let class = u16::from_be_bytes(bytes.get(idx + 2..idx + 4)?.try_into().ok()?);
+
+    Some(Question {
+        name: labels.join("."),
+        record_type,
+        class,
+        len: idx + 4,
+    })
+}
+
+/// Sets the response code and the record counts in the header of the given response.
+fn finish_response(
+    mut response: Vec<u8>,
+    rcode: u8,
+    num_questions: u16,
+    num_answers: u16,
+) -> Vec<u8> {
+    response[3] = rcode;
+    response[4..6].copy_from_slice(&num_questions.to_be_bytes());
+    response[6..8].copy_from_slice(&num_answers.to_be_bytes());
+
+    response
+}
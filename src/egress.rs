@@ -0,0 +1,15 @@
+use std::net::SocketAddr;
+
+/// A policy consulted before any outbound message is queued for a peer; it allows all the egress filtering rules
+/// (e.g. compliance-related ones or per-peer protocol restrictions) to be specified in one place. It can be
+/// registered with the `Node` via `Node::set_egress_policy`.
+pub trait EgressPolicy: Send + Sync {
+    /// Returns the class of the given outbound message (e.g. its type); all the messages belong to the same
+    /// class by default.
+    fn message_class(&self, _message: &[u8]) -> u8 {
+        0
+    }
+
+    /// Decides whether a message of the given class and length can be sent to the specified address.
+    fn allow_send(&self, addr: SocketAddr, class: u8, len: usize) -> bool;
+}
@@ -0,0 +1,88 @@
+use crate::PartitionSignal;
+
+use std::{collections::BTreeMap, net::SocketAddr};
+
+/// An event emitted by the node; they can be received via `Node::subscribe`. The events concerning a single
+/// connection contain its address followed by its ID (see `ConnectionInfo::id`), so that they can be correlated
+/// with the events in its tracing span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NodeEvent {
+    /// The queue passing inbound messages from the given connection to its processing task was closed, i.e. the
+    /// task is no longer running (e.g. `Reading::process_message` panicked); `NodeConfig::closed_inbound_queue_policy`
+    /// determines what happens next.
+    InboundQueueClosed(SocketAddr, usize),
+    /// The given connection has completed its handshake and is now active.
+    HandshakeCompleted(SocketAddr, usize),
+    /// The given connection couldn't be established, e.g. because its handshake failed.
+    HandshakeFailed(SocketAddr, usize),
+    /// The writer of the given connection hasn't flushed any bytes for longer than
+    /// `NodeConfig.writer_stall_timeout_ms`, despite having queued outbound messages.
+    WriterStalled(SocketAddr, usize),
+    /// The outbound queue of the given connection has remained above the high-water mark for longer than allowed by
+    /// `NodeConfig.slow_peer_detection`, i.e. the peer can't keep up.
+    SlowPeer(SocketAddr, usize),
+    /// Processing a message from the given connection took longer than `NodeConfig.message_processing_timeout_ms`,
+    /// and was cancelled.
+    ProcessingTimedOut(SocketAddr, usize),
+}
+
+impl NodeEvent {
+    /// Returns the address of the connection the event concerns.
+    pub fn addr(&self) -> SocketAddr {
+        self.connection().0
+    }
+
+    /// Returns the ID of the connection the event concerns.
+    pub fn conn_id(&self) -> usize {
+        self.connection().1
+    }
+
+    /// Returns the address and the ID of the connection the event concerns.
+    fn connection(&self) -> (SocketAddr, usize) {
+        match self {
+            Self::InboundQueueClosed(addr, conn_id)
+            | Self::HandshakeCompleted(addr, conn_id)
+            | Self::HandshakeFailed(addr, conn_id)
+            | Self::WriterStalled(addr, conn_id)
+            | Self::SlowPeer(addr, conn_id)
+            | Self::ProcessingTimedOut(addr, conn_id) => (*addr, *conn_id),
+        }
+    }
+}
+
+/// An event concerning the node's connectivity as a whole rather than a single connection; they can be received
+/// via `Node::subscribe_network_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NetworkEvent {
+    /// A network partition or a loss of local connectivity is suspected, based on the given signal; it isn't
+    /// signaled again until a connection is established.
+    PartitionSuspected(PartitionSignal),
+    /// A round of dialing `NodeConfig.bootstrap_peers` has concluded, with the given number of them connected.
+    BootstrapCompleted(usize),
+}
+
+/// The purpose of a connection (e.g. "block-sync" or "light-client"), as declared by the application; it can be
+/// attached to a connection via `Connection::insert_ext` or `Node::insert_connection_ext`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionIntent(pub String);
+
+/// Metadata obtained by the application during a connection's handshake (e.g. the peer's client version); it can
+/// be attached to a connection via `Connection::insert_ext` or `Node::insert_connection_ext`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakeMetadata(pub BTreeMap<String, String>);
+
+/// A `NodeEvent` along with the application-attached metadata of the connection it concerns, so that it can be
+/// logged without further lookups; they can be received via `Node::subscribe_annotated_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedEvent {
+    /// The event.
+    pub event: NodeEvent,
+    /// The tags of the peer (see `Node::tag_peer`).
+    pub tags: Vec<String>,
+    /// The intent attached to the connection, if any.
+    pub intent: Option<String>,
+    /// The handshake metadata attached to the connection.
+    pub handshake_metadata: BTreeMap<String, String>,
+}
@@ -0,0 +1,40 @@
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{collections::VecDeque, net::IpAddr};
+
+/// The maximum number of votes retained; the oldest ones are discarded first.
+const MAX_VOTES: usize = 64;
+
+/// Tallies the IPs that the node's peers report to observe for it.
+#[derive(Default)]
+pub(crate) struct AddrVotes(Mutex<VecDeque<(IpAddr, IpAddr)>>);
+
+impl AddrVotes {
+    /// Registers the IP observed by the given voter; each voter IP has a single vote, so that a single host can't
+    /// sway the result by connecting multiple times.
+    pub(crate) fn register(&self, voter: IpAddr, observed: IpAddr) {
+        let mut votes = self.0.lock();
+
+        votes.retain(|(ip, _)| *ip != voter);
+        if votes.len() == MAX_VOTES {
+            votes.pop_front();
+        }
+        votes.push_back((voter, observed));
+    }
+
+    /// Returns the IP that received at least `min_votes` votes and the majority of all the votes, if there is one.
+    pub(crate) fn winner(&self, min_votes: usize) -> Option<IpAddr> {
+        let votes = self.0.lock();
+
+        let mut tally = FxHashMap::<IpAddr, usize>::default();
+        for (_, observed) in votes.iter() {
+            *tally.entry(*observed).or_default() += 1;
+        }
+
+        tally
+            .into_iter()
+            .find(|(_, count)| *count >= min_votes.max(1) && *count * 2 > votes.len())
+            .map(|(ip, _)| ip)
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{ConnectionSide, Pea2Pea};
+
+use std::{fmt::Write, net::SocketAddr, time::Duration};
+
+/// A node in a `ConnectionGraph`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphNode {
+    /// The name of the node; peers from outside of the queried set are named after their address.
+    pub name: String,
+    /// The node's listening address, if known.
+    pub addr: Option<SocketAddr>,
+}
+
+/// A directed edge in a `ConnectionGraph`, leading from the initiator of a connection to its responder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEdge {
+    /// The index of the initiator in `ConnectionGraph.nodes`.
+    pub initiator: usize,
+    /// The index of the responder in `ConnectionGraph.nodes`.
+    pub responder: usize,
+    /// The median ack latency observed by the initiator, if the `Acknowledging` protocol is enabled.
+    pub latency: Option<Duration>,
+    /// The number of messages sent by the initiator.
+    pub msgs_sent: usize,
+    /// The number of messages received by the initiator.
+    pub msgs_received: usize,
+}
+
+/// A snapshot of the connections between a set of nodes, which can be exported in the DOT, GraphML or JSON format.
+///
+/// note: the connections are recorded from the point of view of their initiators, as a responder doesn't know the
+/// listening address of its peer; connections initiated by nodes from outside of the queried set are not included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionGraph {
+    /// The nodes of the graph; the queried ones come first, in their original order.
+    pub nodes: Vec<GraphNode>,
+    /// The edges of the graph.
+    pub edges: Vec<GraphEdge>,
+}
+
+impl ConnectionGraph {
+    /// Captures the current connections of the provided nodes.
+    pub fn capture<T: Pea2Pea>(nodes: &[T]) -> Self {
+        let mut graph = ConnectionGraph {
+            nodes: nodes
+                .iter()
+                .map(|node| GraphNode {
+                    name: node.node().name().to_owned(),
+                    addr: node.node().listening_addr(),
+                })
+                .collect(),
+            edges: Vec::new(),
+        };
+
+        for (initiator, node) in nodes.iter().enumerate() {
+            let node = node.node();
+
+            let mut addrs = node.connected_addrs();
+            addrs.sort_unstable();
+
+            for addr in addrs {
+                // only consider the connections initiated by the node
+                if node.connection_side(addr) != Some(ConnectionSide::Responder) {
+                    continue;
+                }
+
+                let responder = graph.node_idx(addr);
+                let (msgs_sent, msgs_received) = node
+                    .known_peers()
+                    .read()
+                    .get(&addr)
+                    .map(|stats| (stats.msgs_sent, stats.msgs_received))
+                    .unwrap_or_default();
+
+                graph.edges.push(GraphEdge {
+                    initiator,
+                    responder,
+                    latency: node.acks().latency_percentile(addr, 0.5),
+                    msgs_sent,
+                    msgs_received,
+                });
+            }
+        }
+
+        graph
+    }
+
+    /// Returns the index of the node with the given listening address, adding it if it's not there yet.
+    fn node_idx(&mut self, addr: SocketAddr) -> usize {
+        if let Some(idx) = self.nodes.iter().position(|node| node.addr == Some(addr)) {
+            idx
+        } else {
+            self.nodes.push(GraphNode {
+                name: addr.to_string(),
+                addr: Some(addr),
+            });
+            self.nodes.len() - 1
+        }
+    }
+
+    /// Exports the graph in the DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph pea2pea {\n");
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let _ = writeln!(dot, "    n{} [label=\"{}\"];", idx, escape_dot(&node.name));
+        }
+        for edge in &self.edges {
+            let _ = write!(dot, "    n{} -> n{} [", edge.initiator, edge.responder);
+            if let Some(latency) = edge.latency {
+                let _ = write!(
+                    dot,
+                    "label=\"{}ms\", latency_ms={}, ",
+                    latency.as_millis(),
+                    latency.as_millis()
+                );
+            }
+            let _ = writeln!(
+                dot,
+                "msgs_sent={}, msgs_received={}];",
+                edge.msgs_sent, edge.msgs_received
+            );
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+
+    /// Exports the graph in the GraphML format.
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n",
+            "  <key id=\"addr\" for=\"node\" attr.name=\"addr\" attr.type=\"string\"/>\n",
+            "  <key id=\"latency_ms\" for=\"edge\" attr.name=\"latency_ms\" attr.type=\"long\"/>\n",
+            "  <key id=\"msgs_sent\" for=\"edge\" attr.name=\"msgs_sent\" attr.type=\"long\"/>\n",
+            "  <key id=\"msgs_received\" for=\"edge\" attr.name=\"msgs_received\" attr.type=\"long\"/>\n",
+            "  <graph id=\"pea2pea\" edgedefault=\"directed\">\n",
+        ));
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let _ = writeln!(xml, "    <node id=\"n{}\">", idx);
+            let _ = writeln!(
+                xml,
+                "      <data key=\"name\">{}</data>",
+                escape_xml(&node.name)
+            );
+            if let Some(addr) = node.addr {
+                let _ = writeln!(xml, "      <data key=\"addr\">{}</data>", addr);
+            }
+            xml.push_str("    </node>\n");
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                xml,
+                "    <edge source=\"n{}\" target=\"n{}\">",
+                edge.initiator, edge.responder
+            );
+            if let Some(latency) = edge.latency {
+                let _ = writeln!(
+                    xml,
+                    "      <data key=\"latency_ms\">{}</data>",
+                    latency.as_millis()
+                );
+            }
+            let _ = writeln!(
+                xml,
+                "      <data key=\"msgs_sent\">{}</data>",
+                edge.msgs_sent
+            );
+            let _ = writeln!(
+                xml,
+                "      <data key=\"msgs_received\">{}</data>",
+                edge.msgs_received
+            );
+            xml.push_str("    </edge>\n");
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+
+        xml
+    }
+
+    /// Exports the graph in the JSON format; the edges refer to the nodes by their names.
+    pub fn to_json(&self) -> String {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let addr = node
+                    .addr
+                    .map(|addr| format!("\"{}\"", addr))
+                    .unwrap_or_else(|| "null".into());
+                format!(
+                    "{{\"name\":\"{}\",\"addr\":{}}}",
+                    escape_json(&node.name),
+                    addr
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let edges = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let latency = edge
+                    .latency
+                    .map(|latency| latency.as_millis().to_string())
+                    .unwrap_or_else(|| "null".into());
+                format!(
+                    "{{\"initiator\":\"{}\",\"responder\":\"{}\",\"latency_ms\":{},\"msgs_sent\":{},\"msgs_received\":{}}}",
+                    escape_json(&self.nodes[edge.initiator].name),
+                    escape_json(&self.nodes[edge.responder].name),
+                    latency,
+                    edge.msgs_sent,
+                    edge.msgs_received
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!(
+            "{{\"nodes\":[{}],\"edges\":[{}]}}",
+            nodes.join(","),
+            edges.join(",")
+        )
+    }
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
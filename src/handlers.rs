@@ -0,0 +1,61 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use std::{io, net::SocketAddr, ops::RangeInclusive, sync::Arc};
+
+/// A handler of the inbound messages of specific types, registered via `Node::{register_handler,
+/// register_handler_range}`; it allows the processing of the messages to be split across modules, with
+/// `Node::dispatch_message` passing each message to the handler of its type.
+#[async_trait]
+pub trait MessageHandler: Send + Sync + 'static {
+    /// Handles an inbound message of one of the types the handler was registered for; the message still contains
+    /// its type byte.
+    async fn handle(&self, source: SocketAddr, message: Bytes) -> io::Result<()>;
+}
+
+/// A message handler, along with the range of message types it handles.
+type RegisteredHandler = (RangeInclusive<u8>, Arc<dyn MessageHandler>);
+
+/// The message handlers registered with the node.
+#[derive(Default)]
+pub(crate) struct Handlers(RwLock<Vec<RegisteredHandler>>);
+
+impl Handlers {
+    /// Registers a handler of the given range of message types; returns `false` if any of them already has one.
+    pub(crate) fn register(
+        &self,
+        types: RangeInclusive<u8>,
+        handler: Arc<dyn MessageHandler>,
+    ) -> bool {
+        let mut handlers = self.0.write();
+        let overlaps = handlers.iter().any(|(registered, _)| {
+            registered.start() <= types.end() && types.start() <= registered.end()
+        });
+        if overlaps || types.is_empty() {
+            return false;
+        }
+        handlers.push((types, handler));
+
+        true
+    }
+
+    /// Unregisters the handler of the given message type, along with the rest of the types it handled; returns
+    /// `false` if the type didn't have one.
+    pub(crate) fn unregister(&self, msg_type: u8) -> bool {
+        let mut handlers = self.0.write();
+        let num_handlers = handlers.len();
+        handlers.retain(|(types, _)| !types.contains(&msg_type));
+
+        handlers.len() != num_handlers
+    }
+
+    /// Returns the handler of the given message type.
+    pub(crate) fn get(&self, msg_type: u8) -> Option<Arc<dyn MessageHandler>> {
+        self.0
+            .read()
+            .iter()
+            .find(|(types, _)| types.contains(&msg_type))
+            .map(|(_, handler)| Arc::clone(handler))
+    }
+}
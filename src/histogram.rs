@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The number of buckets in a `Histogram`.
+const NUM_BUCKETS: usize = 32;
+
+/// A lock-free histogram of values, with buckets whose (inclusive) upper bounds are consecutive powers of two; the
+/// last bucket also holds all the values that exceed its bound.
+#[derive(Default)]
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    /// Records the given value.
+    pub(crate) fn record(&self, value: u64) {
+        self.buckets[bucket_idx(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the recorded values.
+    pub(crate) fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(idx, count)| (1 << idx, count.load(Ordering::Relaxed)))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns the index of the bucket the given value belongs to.
+fn bucket_idx(value: u64) -> usize {
+    if value <= 1 {
+        0
+    } else {
+        ((u64::BITS - (value - 1).leading_zeros()) as usize).min(NUM_BUCKETS - 1)
+    }
+}
+
+/// A snapshot of a histogram, e.g. of the sizes of the messages (see `NodeStats::inbound_message_sizes`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// The (inclusive) upper bounds of the buckets, which are consecutive powers of two, along with the numbers of
+    /// values within them; the last bucket also holds all the values that exceed its bound.
+    pub buckets: Vec<(u64, u64)>,
+    /// The number of recorded values.
+    pub count: u64,
+    /// The sum of the recorded values.
+    pub sum: u64,
+    /// The largest recorded value.
+    pub max: u64,
+}
+
+impl HistogramSnapshot {
+    /// Returns the mean of the recorded values, if there are any.
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum as f64 / self.count as f64)
+        }
+    }
+
+    /// Returns an upper bound of the given percentile (within `0.0..=100.0`) of the recorded values, i.e. the bound of
+    /// the bucket containing it, capped at the largest value; returns `None` if there are no values.
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        // the nearest-rank method
+        let rank = ((p.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for &(bound, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return Some(bound.min(self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+}
@@ -0,0 +1,236 @@
+//! Cryptographic node identities; available with the `identity` feature.
+
+use crate::{
+    tracing_targets::{HANDSHAKE, READING},
+    Connection, Node,
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::*;
+
+use std::{convert::TryInto, fmt, io, net::SocketAddr};
+
+/// The size of a public key.
+const PUBLIC_KEY_LEN: usize = 32;
+/// The size of a challenge nonce.
+const NONCE_LEN: usize = 32;
+/// The size of a signature.
+const SIGNATURE_LEN: usize = 64;
+/// The domain separator for the signatures proving the possession of a key.
+const CHALLENGE_CONTEXT: &[u8] = b"pea2pea-identity";
+
+/// The identifier of a peer: the SHA-256 hash of its public key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(pub [u8; 32]);
+
+impl PeerId {
+    /// Derives the `PeerId` from the given public key.
+    pub fn from_public_key(public_key: &[u8]) -> Self {
+        Self(Sha256::digest(public_key).into())
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PeerId({})", self)
+    }
+}
+
+/// A node's cryptographic identity, based on an Ed25519 keypair.
+#[derive(Clone)]
+pub struct NodeIdentity {
+    signing_key: SigningKey,
+    peer_id: PeerId,
+}
+
+impl NodeIdentity {
+    /// Generates a new random identity.
+    pub fn generate() -> Self {
+        Self::from_secret_key(SigningKey::generate(&mut OsRng).to_bytes())
+    }
+
+    /// Restores an identity from the given secret key.
+    pub fn from_secret_key(secret_key: [u8; 32]) -> Self {
+        let signing_key = SigningKey::from_bytes(&secret_key);
+        let peer_id = PeerId::from_public_key(signing_key.verifying_key().as_bytes());
+
+        Self {
+            signing_key,
+            peer_id,
+        }
+    }
+
+    /// Returns the secret key, e.g. in order to persist the identity.
+    pub fn secret_key(&self) -> [u8; 32] {
+        self.signing_key.to_bytes()
+    }
+
+    /// Returns the public key.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    /// Returns the `PeerId` associated with the identity.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Signs the given message.
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.signing_key.sign(message).to_bytes()
+    }
+}
+
+impl fmt::Debug for NodeIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // don't expose the secret key
+        f.debug_struct("NodeIdentity")
+            .field("peer_id", &self.peer_id)
+            .finish()
+    }
+}
+
+/// A signature scheme used to sign outbound messages and to verify the signatures of inbound ones; it can be
+/// registered with the `Node` via `Node::set_signature_scheme`, and it relies on the peers' identities verified
+/// while connecting, so the node must have a `NodeIdentity`.
+pub trait SignatureScheme: Send + Sync {
+    /// The size of a single signature; every message is suffixed with one.
+    fn signature_len(&self) -> usize;
+
+    /// Signs the given outbound message; the returned signature must be `signature_len` bytes long.
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+
+    /// Checks whether the signature of an inbound message is valid for the given public key of its sender.
+    fn verify(&self, public_key: &[u8; 32], message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// The Ed25519 signature scheme, using the keys of the node's own identity.
+impl SignatureScheme for NodeIdentity {
+    fn signature_len(&self) -> usize {
+        SIGNATURE_LEN
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        NodeIdentity::sign(self, message).to_vec()
+    }
+
+    fn verify(&self, public_key: &[u8; 32], message: &[u8], signature: &[u8]) -> bool {
+        signature
+            .try_into()
+            .map(|signature| verify_signature(public_key, message, signature))
+            .unwrap_or(false)
+    }
+}
+
+/// Verifies the given signature of a message using the provided public key.
+pub fn verify_signature(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    if let Ok(verifying_key) = VerifyingKey::from_bytes(public_key) {
+        verifying_key
+            .verify(message, &Signature::from_bytes(signature))
+            .is_ok()
+    } else {
+        false
+    }
+}
+
+/// Exchanges the public keys with the peer and makes both sides prove the possession of the associated secret
+/// keys by signing a random challenge; returns the peer's public key.
+pub(crate) async fn exchange_identities(
+    conn: &mut Connection,
+    identity: &NodeIdentity,
+) -> io::Result<[u8; PUBLIC_KEY_LEN]> {
+    let mut own_nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut own_nonce);
+
+    let mut own_msg = [0u8; PUBLIC_KEY_LEN + NONCE_LEN];
+    own_msg[..PUBLIC_KEY_LEN].copy_from_slice(&identity.public_key());
+    own_msg[PUBLIC_KEY_LEN..].copy_from_slice(&own_nonce);
+    conn.writer().write_all(&own_msg).await?;
+
+    let mut peer_msg = [0u8; PUBLIC_KEY_LEN + NONCE_LEN];
+    conn.reader().read_exact(&mut peer_msg).await?;
+    let mut peer_public_key = [0u8; PUBLIC_KEY_LEN];
+    peer_public_key.copy_from_slice(&peer_msg[..PUBLIC_KEY_LEN]);
+
+    // sign the peer's challenge
+    let signature = identity.sign(&[CHALLENGE_CONTEXT, &peer_msg[PUBLIC_KEY_LEN..]].concat());
+    conn.writer().write_all(&signature).await?;
+
+    // verify the peer's response to own challenge
+    let mut peer_signature = [0u8; SIGNATURE_LEN];
+    conn.reader().read_exact(&mut peer_signature).await?;
+    let challenge = [CHALLENGE_CONTEXT, &own_nonce].concat();
+
+    if verify_signature(&peer_public_key, &challenge, &peer_signature) {
+        let peer_id = PeerId::from_public_key(&peer_public_key);
+        debug!(target: HANDSHAKE, parent: conn.span(), "{} is {}", conn.addr, peer_id);
+        Ok(peer_public_key)
+    } else {
+        error!(target: HANDSHAKE, parent: conn.span(), "{} failed to prove its identity", conn.addr);
+        Err(io::ErrorKind::InvalidData.into())
+    }
+}
+
+/// Suffixes the message of the given length contained in the buffer with its signature, as long as message
+/// signing is enabled; returns the length of the signed message.
+pub(crate) fn sign_message(node: &Node, buffer: &mut [u8], len: usize) -> io::Result<usize> {
+    if let Some(scheme) = node.signature_scheme() {
+        let signature = scheme.sign(&buffer[..len]);
+        let signed_len = len + signature.len();
+
+        buffer
+            .get_mut(len..signed_len)
+            .ok_or(io::ErrorKind::InvalidInput)?
+            .copy_from_slice(&signature);
+
+        Ok(signed_len)
+    } else {
+        Ok(len)
+    }
+}
+
+/// Verifies the signature suffixing the message of the given length at the beginning of the buffer, as long as
+/// message signing is enabled; returns the length of the signed message, or `None` if the signature is incomplete.
+/// A peer that sends an invalid signature is disconnected from.
+pub(crate) fn verify_message(
+    node: &Node,
+    source: SocketAddr,
+    buffer: &[u8],
+    len: usize,
+) -> io::Result<Option<usize>> {
+    let scheme = if let Some(scheme) = node.signature_scheme() {
+        scheme
+    } else {
+        return Ok(Some(len));
+    };
+
+    let signed_len = len + scheme.signature_len();
+    if buffer.len() < signed_len {
+        return Ok(None);
+    }
+
+    let is_valid = node
+        .connected_peer_public_key(source)
+        .map(|public_key| scheme.verify(&public_key, &buffer[..len], &buffer[len..signed_len]))
+        .unwrap_or(false);
+
+    if is_valid {
+        Ok(Some(signed_len))
+    } else {
+        error!(target: READING, "a message from {} has an invalid signature", source);
+        node.disconnect(source);
+        Err(io::ErrorKind::InvalidData.into())
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{protocols::Reading, ConnectionContext, Node, Pea2Pea};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_core::Stream;
+use parking_lot::RwLock;
+use tokio::sync::mpsc;
+
+use std::{
+    convert::TryInto,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The size of the length prefix of the messages read by the built-in reader.
+const LEN_PREFIX_SIZE: usize = 4;
+
+/// A stream of inbound messages (along with the addresses of their senders), returned by `Node::incoming` and
+/// `Node::incoming_from`; the messages are expected to be prefixed with the length of their payload, encoded as a
+/// little-endian `u32`, and the stream only yields the payloads. It ends once the node shuts down or, if it is
+/// limited to a single peer, once that peer disconnects.
+pub struct Incoming {
+    receiver: mpsc::Receiver<(SocketAddr, Bytes)>,
+}
+
+impl Incoming {
+    /// Creates a stream of the messages passed to the given receiver.
+    pub(crate) fn new(receiver: mpsc::Receiver<(SocketAddr, Bytes)>) -> Self {
+        Self { receiver }
+    }
+
+    /// Receives the next inbound message; returns `None` once the stream has ended.
+    pub async fn recv(&mut self) -> Option<(SocketAddr, Bytes)> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for Incoming {
+    type Item = (SocketAddr, Bytes);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Passes an inbound message to a single `Incoming` stream.
+type IncomingSender = mpsc::Sender<(SocketAddr, Bytes)>;
+
+/// The `Incoming` streams created for the node, optionally limited to a single peer.
+#[derive(Default)]
+pub(crate) struct IncomingStreams {
+    senders: RwLock<Vec<(Option<SocketAddr>, IncomingSender)>>,
+}
+
+impl IncomingStreams {
+    /// Registers a new stream of the messages from the given peer (or all of them).
+    pub(crate) fn register(&self, source: Option<SocketAddr>, sender: IncomingSender) {
+        self.senders.write().push((source, sender));
+    }
+
+    /// Passes the given message to all the interested streams, waiting for room in their queues; the streams that
+    /// were dropped are removed.
+    pub(crate) async fn dispatch(&self, source: SocketAddr, message: Bytes) {
+        let senders = {
+            let mut senders = self.senders.write();
+            senders.retain(|(_, sender)| !sender.is_closed());
+            senders
+                .iter()
+                .filter(|(addr, _)| addr.map(|addr| addr == source).unwrap_or(true))
+                .map(|(_, sender)| sender.clone())
+                .collect::<Vec<_>>()
+        };
+
+        for sender in senders {
+            let _ = sender.send((source, message.clone())).await;
+        }
+    }
+
+    /// Ends the streams limited to the given peer.
+    pub(crate) fn remove_peer(&self, addr: SocketAddr) {
+        self.senders
+            .write()
+            .retain(|(source, _)| *source != Some(addr));
+    }
+
+    /// Ends all the streams.
+    pub(crate) fn clear(&self) {
+        self.senders.write().clear();
+    }
+}
+
+/// The built-in reader feeding the `Incoming` streams; it reads messages prefixed with their length, encoded as
+/// a little-endian `u32`.
+#[derive(Clone)]
+pub(crate) struct IncomingReader(pub(crate) Node);
+
+impl Pea2Pea for IncomingReader {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl Reading for IncomingReader {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        if buffer.len() < LEN_PREFIX_SIZE {
+            return Ok(None);
+        }
+
+        // safe; the length was checked above
+        let len = u32::from_le_bytes(buffer[..LEN_PREFIX_SIZE].try_into().unwrap()) as usize;
+        match buffer.get(LEN_PREFIX_SIZE..LEN_PREFIX_SIZE + len) {
+            Some(payload) => Ok(Some((
+                Bytes::copy_from_slice(payload),
+                LEN_PREFIX_SIZE + len,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        self.0.incoming_streams().dispatch(source, message).await;
+
+        Ok(())
+    }
+}
@@ -1,11 +1,45 @@
-use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use fxhash::FxHashMap;
-use std::{net::SocketAddr, time::Instant};
+use std::{
+    any::{Any, TypeId},
+    collections::{hash_map::RandomState, BTreeSet},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// Contains statistics related to node's peers, currently connected or not.
+///
+/// A peer can be known under several addresses, e.g. its listening address and the ephemeral one its connection
+/// originates from; such addresses can be registered as aliases of the canonical one (see
+/// `KnownPeers::register_alias`), which makes all the activity associated with them count towards the canonical
+/// peer record.
 #[derive(Default)]
-pub struct KnownPeers(RwLock<FxHashMap<SocketAddr, PeerStats>>);
+pub struct KnownPeers {
+    peers: RwLock<FxHashMap<SocketAddr, PeerStats>>,
+    /// The canonical addresses of the aliased ones.
+    aliases: RwLock<FxHashMap<SocketAddr, SocketAddr>>,
+    /// The failed handshakes with the peers that connected to the node; they aren't recorded per peer, as their
+    /// addresses are ephemeral ones.
+    inbound_handshake_failures: Mutex<InboundHandshakeFailures>,
+}
+
+/// The maximum number of distinct reasons of the failed inbound handshakes that are recorded.
+const MAX_INBOUND_FAILURE_REASONS: usize = 16;
+
+/// The summary of the failed inbound handshakes; see `KnownPeers::register_inbound_handshake_failure`.
+#[derive(Default)]
+struct InboundHandshakeFailures {
+    /// The number of failed handshakes.
+    count: usize,
+    /// The numbers of failed handshakes per their reasons; once the limit of distinct reasons is reached, the new
+    /// ones are only counted towards the total.
+    reasons: FxHashMap<String, usize>,
+}
 
 impl KnownPeers {
     /// Adds an address to the list of known peers.
@@ -18,8 +52,17 @@ impl KnownPeers {
         self.write().remove(&addr)
     }
 
+    /// Registers a successful dial of the given address, which proves that it's a listening one.
+    pub fn register_dial(&self, addr: SocketAddr) {
+        let addr = self.canonical_addr(addr);
+        if let Some(ref mut stats) = self.write().get_mut(&addr) {
+            stats.last_dialed = Some(Instant::now());
+        }
+    }
+
     /// Registers a connection to the given address.
     pub fn register_connection(&self, addr: SocketAddr) {
+        let addr = self.canonical_addr(addr);
         if let Some(ref mut stats) = self.write().get_mut(&addr) {
             stats.last_connected = Some(Instant::now());
             stats.times_connected += 1;
@@ -28,35 +71,419 @@ impl KnownPeers {
 
     /// Registers a submission of a message to the given address.
     pub fn register_sent_message(&self, to: SocketAddr, len: usize) {
+        let to = self.canonical_addr(to);
         if let Some(ref mut stats) = self.write().get_mut(&to) {
             stats.msgs_sent += 1;
             stats.bytes_sent += len as u64;
+            stats.last_sent = Some(Instant::now());
         }
     }
 
     /// Registers a receipt of a message to the given address.
     pub fn register_received_message(&self, from: SocketAddr, len: usize) {
+        let from = self.canonical_addr(from);
         if let Some(ref mut stats) = self.write().get_mut(&from) {
             stats.msgs_received += 1;
             stats.bytes_received += len as u64;
+            stats.last_received = Some(Instant::now());
         }
     }
 
+    /// Registers a sighting of the given address, e.g. via peer discovery; it is added to the list of known peers
+    /// if it's not there yet.
+    pub fn register_seen(&self, addr: SocketAddr) {
+        let addr = self.canonical_addr(addr);
+        self.write().entry(addr).or_default().last_seen = Some(Instant::now());
+    }
+
+    /// Returns the penalty used to prioritize dialing the given address: the fresher the address and the fewer
+    /// failures associated with it, the lower the penalty. The `freshness_weight` is the number of failures that an
+    /// hour of staleness is worth; addresses without any recorded sighting or activity have an infinite penalty.
+    pub fn dial_penalty(&self, addr: SocketAddr, freshness_weight: f64) -> f64 {
+        let addr = self.canonical_addr(addr);
+        self.read()
+            .get(&addr)
+            .map(|stats| stats.dial_penalty(freshness_weight))
+            .unwrap_or(f64::INFINITY)
+    }
+
     /// Registers a failure associated with the given address.
     pub fn register_failure(&self, addr: SocketAddr) {
+        let addr = self.canonical_addr(addr);
         if let Some(ref mut stats) = self.write().get_mut(&addr) {
             stats.failures += 1;
         }
     }
 
+    /// Registers the outcome of a handshake with the given address, which took the given time; it is added to the
+    /// list of known peers if it's not there yet.
+    pub fn register_handshake(
+        &self,
+        addr: SocketAddr,
+        duration: Duration,
+        failure: Option<&io::Error>,
+    ) {
+        let addr = self.canonical_addr(addr);
+        let mut peers = self.write();
+        let stats = peers.entry(addr).or_default();
+
+        stats.handshake_attempts += 1;
+        stats.last_handshake_duration = Some(duration);
+        stats.last_handshake_failure = None;
+        if let Some(e) = failure {
+            stats.handshake_failures += 1;
+            stats.last_handshake_failure = Some(HandshakeFailure {
+                kind: e.kind(),
+                reason: e.to_string(),
+            });
+        }
+    }
+
+    /// Registers a failed handshake with a peer that connected to the node; unlike `KnownPeers::register_handshake`,
+    /// it doesn't create a peer record, and the failures are only included in `KnownPeers::handshake_stats`.
+    pub fn register_inbound_handshake_failure(&self, failure: &io::Error) {
+        let mut failures = self.inbound_handshake_failures.lock();
+        failures.count += 1;
+
+        let reason = failure.to_string();
+        if let Some(count) = failures.reasons.get_mut(&reason) {
+            *count += 1;
+        } else if failures.reasons.len() < MAX_INBOUND_FAILURE_REASONS {
+            failures.reasons.insert(reason, 1);
+        }
+    }
+
+    /// Checks whether the given address or the one it's an alias of is known.
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        let addr = self.canonical_addr(addr);
+        self.read().contains_key(&addr)
+    }
+
+    /// Returns the statistics of the peer known under the given address or one of its aliases.
+    pub fn stats(&self, addr: SocketAddr) -> Option<PeerStats> {
+        let addr = self.canonical_addr(addr);
+        self.read().get(&addr).cloned()
+    }
+
+    /// Returns the statistics of the peer an inbound connection from the given address most likely belongs to:
+    /// the one known under that address or one of its aliases, or otherwise the freshest peer with the same IP,
+    /// as inbound connections originate from ephemeral ports rather than the peers' listening addresses.
+    pub fn inbound_stats(&self, addr: SocketAddr) -> Option<PeerStats> {
+        let canonical = self.canonical_addr(addr);
+        let peers = self.read();
+        if let Some(stats) = peers.get(&canonical) {
+            return Some(stats.clone());
+        }
+
+        peers
+            .iter()
+            .filter(|(known_addr, _)| known_addr.ip() == addr.ip())
+            .max_by_key(|(_, stats)| stats.freshness())
+            .map(|(_, stats)| stats.clone())
+    }
+
+    /// Returns a summary of the handshakes with all the known peers, and of the failed inbound ones.
+    pub fn handshake_stats(&self) -> HandshakeStats {
+        let mut stats = HandshakeStats::default();
+        let mut total_duration = Duration::ZERO;
+        let mut num_durations = 0u32;
+        let mut reasons: FxHashMap<&str, usize> = Default::default();
+
+        let peers = self.read();
+        for peer in peers.values().filter(|peer| peer.handshake_attempts != 0) {
+            stats.attempts += peer.handshake_attempts;
+            stats.failures += peer.handshake_failures;
+            stats.peers_attempted += 1;
+            if let Some(duration) = peer.last_handshake_duration {
+                total_duration += duration;
+                num_durations += 1;
+            }
+            if let Some(ref failure) = peer.last_handshake_failure {
+                stats.peers_failing += 1;
+                *reasons.entry(&failure.reason).or_default() += 1;
+            }
+        }
+
+        let inbound_failures = self.inbound_handshake_failures.lock();
+        stats.attempts += inbound_failures.count;
+        stats.failures += inbound_failures.count;
+        for (reason, count) in &inbound_failures.reasons {
+            *reasons.entry(reason).or_default() += count;
+        }
+
+        if num_durations != 0 {
+            stats.mean_duration = Some(total_duration / num_durations);
+        }
+        stats.failure_reasons = reasons
+            .into_iter()
+            .map(|(reason, count)| (reason.to_owned(), count))
+            .collect();
+        stats
+            .failure_reasons
+            .sort_unstable_by(|(r1, c1), (r2, c2)| c2.cmp(c1).then_with(|| r1.cmp(r2)));
+
+        stats
+    }
+
+    /// Attaches an application-defined tag (e.g. "validator") to the given address; it is added to the list of
+    /// known peers if it's not there yet. Returns `false` if the address already had the tag.
+    pub fn tag(&self, addr: SocketAddr, tag: &str) -> bool {
+        let addr = self.canonical_addr(addr);
+        self.write()
+            .entry(addr)
+            .or_default()
+            .tags
+            .insert(tag.to_owned())
+    }
+
+    /// Detaches a tag from the given address; returns `false` if the address didn't have it.
+    pub fn untag(&self, addr: SocketAddr, tag: &str) -> bool {
+        let addr = self.canonical_addr(addr);
+        self.write()
+            .get_mut(&addr)
+            .map(|stats| stats.tags.remove(tag))
+            .unwrap_or(false)
+    }
+
+    /// Checks whether the given address has the specified tag.
+    pub fn has_tag(&self, addr: SocketAddr, tag: &str) -> bool {
+        let addr = self.canonical_addr(addr);
+        self.read()
+            .get(&addr)
+            .map(|stats| stats.tags.contains(tag))
+            .unwrap_or(false)
+    }
+
+    /// Returns the tags attached to the given address, in alphabetical order.
+    pub fn tags(&self, addr: SocketAddr) -> Vec<String> {
+        let addr = self.canonical_addr(addr);
+        self.read()
+            .get(&addr)
+            .map(|stats| stats.tags.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the addresses that have the specified tag.
+    pub fn tagged(&self, tag: &str) -> Vec<SocketAddr> {
+        self.read()
+            .iter()
+            .filter(|(_, stats)| stats.tags.contains(tag))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Returns the addresses that have the specified tag; an alias of `KnownPeers::tagged`, paired with
+    /// `KnownPeers::random_peer_with_tag`.
+    pub fn peers_with_tag(&self, tag: &str) -> Vec<SocketAddr> {
+        self.tagged(tag)
+    }
+
+    /// Returns a randomly chosen address with the specified tag, skipping the ones rejected by `filter` (e.g. the
+    /// already connected ones).
+    pub fn random_peer_with_tag<F: Fn(SocketAddr) -> bool>(
+        &self,
+        tag: &str,
+        filter: F,
+    ) -> Option<SocketAddr> {
+        let candidates = self
+            .tagged(tag)
+            .into_iter()
+            .filter(|addr| filter(*addr))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // a randomly seeded hasher is a sufficient source of randomness here
+        let idx = RandomState::new().build_hasher().finish() as usize % candidates.len();
+
+        Some(candidates[idx])
+    }
+
+    /// Attaches a value of the given type to the given address (e.g. its advertised services); it is added to
+    /// the list of known peers if it's not there yet. Returns `true` if it replaced a value of that type.
+    pub fn insert_metadata<T: Send + Sync + 'static>(&self, addr: SocketAddr, value: T) -> bool {
+        let addr = self.canonical_addr(addr);
+        self.write()
+            .entry(addr)
+            .or_default()
+            .metadata
+            .0
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .is_some()
+    }
+
+    /// Returns the value of the given type attached to the given address.
+    pub fn metadata<T: Clone + Send + Sync + 'static>(&self, addr: SocketAddr) -> Option<T> {
+        let addr = self.canonical_addr(addr);
+        self.read()
+            .get(&addr)
+            .and_then(|stats| stats.metadata.get::<T>().cloned())
+    }
+
+    /// Detaches the value of the given type from the given address; returns `false` if it didn't have one.
+    pub fn remove_metadata<T: Send + Sync + 'static>(&self, addr: SocketAddr) -> bool {
+        let addr = self.canonical_addr(addr);
+        self.write()
+            .get_mut(&addr)
+            .map(|stats| stats.metadata.0.remove(&TypeId::of::<T>()).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Returns the addresses that have a value of the given type attached, along with it.
+    pub fn peers_with_metadata<T: Clone + Send + Sync + 'static>(&self) -> Vec<(SocketAddr, T)> {
+        self.read()
+            .iter()
+            .filter_map(|(addr, stats)| {
+                stats
+                    .metadata
+                    .get::<T>()
+                    .map(|value| (*addr, value.clone()))
+            })
+            .collect()
+    }
+
+    /// Registers `alias` (e.g. the ephemeral address a peer's connection originates from) as an alias of the
+    /// `canonical` address (e.g. the peer's listening address, once revealed by the handshake): the statistics
+    /// recorded for the alias so far are merged into the canonical peer record, and from then on all the activity
+    /// associated with the alias counts towards it. Registering an address as its own alias is a no-op.
+    pub fn register_alias(&self, alias: SocketAddr, canonical: SocketAddr) {
+        let canonical = self.canonical_addr(canonical);
+        if alias == canonical {
+            return;
+        }
+
+        let mut aliases = self.aliases.write();
+        // the aliases of the alias are redirected too
+        for target in aliases.values_mut() {
+            if *target == alias {
+                *target = canonical;
+            }
+        }
+        aliases.insert(alias, canonical);
+
+        let mut peers = self.write();
+        let aliased = peers.remove(&alias);
+        let record = peers.entry(canonical).or_default();
+        if let Some(aliased) = aliased {
+            record.merge(aliased);
+        }
+    }
+
+    /// Removes the given alias, e.g. once the connection it belongs to is closed; returns the canonical address it
+    /// pointed to.
+    pub fn remove_alias(&self, alias: SocketAddr) -> Option<SocketAddr> {
+        self.aliases.write().remove(&alias)
+    }
+
+    /// Returns the canonical address of the given one, i.e. the address itself unless it's a registered alias.
+    pub fn canonical_addr(&self, addr: SocketAddr) -> SocketAddr {
+        self.aliases.read().get(&addr).copied().unwrap_or(addr)
+    }
+
+    /// Returns the registered aliases of the given canonical address.
+    pub fn aliases(&self, canonical: SocketAddr) -> Vec<SocketAddr> {
+        self.aliases
+            .read()
+            .iter()
+            .filter(|(_, target)| **target == canonical)
+            .map(|(alias, _)| *alias)
+            .collect()
+    }
+
+    /// Returns a report of the statistics of all the known peers, sorted by their addresses; it can be exported
+    /// e.g. for periodic telemetry dumps.
+    pub fn snapshot_stats(&self) -> PeerStatsReport {
+        let mut peers = self
+            .read()
+            .iter()
+            .map(|(addr, stats)| PeerReport::new(*addr, stats))
+            .collect::<Vec<_>>();
+        peers.sort_unstable_by_key(|peer| peer.addr);
+
+        PeerStatsReport { peers }
+    }
+
+    /// Resets the message, byte, and failure counters of all the known peers, retaining their timestamps, tags and
+    /// metadata; returns the report of their statistics from right before the reset, so that no activity is lost
+    /// between the two.
+    pub fn reset_stats(&self) -> PeerStatsReport {
+        let mut peers = self.write();
+        let mut report = Vec::with_capacity(peers.len());
+        for (addr, stats) in peers.iter_mut() {
+            report.push(PeerReport::new(*addr, stats));
+            stats.msgs_sent = 0;
+            stats.msgs_received = 0;
+            stats.bytes_sent = 0;
+            stats.bytes_received = 0;
+            stats.failures = 0;
+        }
+        report.sort_unstable_by_key(|peer| peer.addr);
+
+        PeerStatsReport { peers: report }
+    }
+
     /// Acquires a read lock over the collection of known peers.
+    ///
+    /// note: the collection is keyed by the canonical addresses, so the aliases need to be resolved via
+    /// `KnownPeers::canonical_addr` before looking the peers up in it.
     pub fn read(&self) -> RwLockReadGuard<'_, FxHashMap<SocketAddr, PeerStats>> {
-        self.0.read()
+        self.peers.read()
     }
 
-    /// Acquires a write lock over the collection of known peers.
+    /// Acquires a write lock over the collection of known peers; see `KnownPeers::read` regarding the aliases.
     pub fn write(&self) -> RwLockWriteGuard<'_, FxHashMap<SocketAddr, PeerStats>> {
-        self.0.write()
+        self.peers.write()
+    }
+}
+
+/// A report of the statistics of the known peers, returned by `KnownPeers::snapshot_stats`; it can be exported e.g.
+/// for telemetry, as it is serializable if the `serde` feature is enabled.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PeerStatsReport {
+    /// The reports of the individual peers, sorted by their addresses.
+    pub peers: Vec<PeerReport>,
+}
+
+/// The statistics of a single peer within a `PeerStatsReport`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PeerReport {
+    /// The address of the peer.
+    pub addr: SocketAddr,
+    /// The number of times a connection with the peer has been established.
+    pub times_connected: usize,
+    /// The number of messages sent to the peer.
+    pub msgs_sent: usize,
+    /// The number of messages received from the peer.
+    pub msgs_received: usize,
+    /// The number of bytes sent to the peer.
+    pub bytes_sent: u64,
+    /// The number of bytes received from the peer.
+    pub bytes_received: u64,
+    /// The number of failures related to the peer.
+    pub failures: u8,
+    /// The time since the peer was last seen or active (see `PeerStats::freshness`), if ever.
+    pub last_seen_ago: Option<Duration>,
+    /// The peer's score, i.e. its negated dial penalty (see `KnownPeers::dial_penalty`) with a freshness weight of
+    /// 1; the higher, the better.
+    pub score: f64,
+}
+
+impl PeerReport {
+    fn new(addr: SocketAddr, stats: &PeerStats) -> Self {
+        Self {
+            addr,
+            times_connected: stats.times_connected,
+            msgs_sent: stats.msgs_sent,
+            msgs_received: stats.msgs_received,
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            failures: stats.failures,
+            last_seen_ago: stats.freshness().map(|freshness| freshness.elapsed()),
+            score: -stats.dial_penalty(1.0),
+        }
     }
 }
 
@@ -69,7 +496,7 @@ pub struct PeerStats {
     pub added: Instant,
     /// The timestamp of the most recent connection with the peer.
     pub last_connected: Option<Instant>,
-    /// The timestamp of the peer's last activity.
+    /// The number of messages sent to the peer.
     pub msgs_sent: usize,
     /// The number of messages received from the peer.
     pub msgs_received: usize,
@@ -77,8 +504,145 @@ pub struct PeerStats {
     pub bytes_sent: u64,
     /// The number of bytes received from the peer.
     pub bytes_received: u64,
+    /// The timestamp of the most recent message sent to the peer.
+    pub last_sent: Option<Instant>,
+    /// The timestamp of the most recent message received from the peer.
+    pub last_received: Option<Instant>,
     /// The number of failures related to the peer.
     pub failures: u8,
+    /// The keep-alive interval learned for the peer by the `KeepAlive` protocol.
+    pub keepalive_interval: Option<Duration>,
+    /// The timestamp of the most recent sighting of the peer's address, e.g. via peer discovery.
+    pub last_seen: Option<Instant>,
+    /// The timestamp of the most recent successful connection initiated by the node; only the addresses that were
+    /// dialed successfully are known to be listening ones.
+    pub last_dialed: Option<Instant>,
+    /// The application-defined tags attached to the peer via `Node::tag_peer`.
+    pub tags: BTreeSet<String>,
+    /// The application-defined values attached to the peer via `KnownPeers::insert_metadata`.
+    pub metadata: PeerMetadata,
+    /// The number of handshakes attempted with the peer.
+    pub handshake_attempts: usize,
+    /// The number of failed handshakes with the peer.
+    pub handshake_failures: usize,
+    /// The duration of the most recent handshake with the peer, whether it succeeded or not.
+    pub last_handshake_duration: Option<Duration>,
+    /// The details of the failure of the most recent handshake with the peer, if it failed.
+    pub last_handshake_failure: Option<HandshakeFailure>,
+}
+
+/// The details of a failed handshake; see `PeerStats::last_handshake_failure`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeFailure {
+    /// The kind of the error the handshake failed with.
+    pub kind: io::ErrorKind,
+    /// The description of the error, e.g. one indicating a network magic mismatch.
+    pub reason: String,
+}
+
+/// A summary of the handshakes with the known peers, returned by `Node::handshake_stats`; it can be used to
+/// diagnose widespread incompatibilities, e.g. peers from a different network.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakeStats {
+    /// The number of attempted handshakes, including the failed inbound ones.
+    pub attempts: usize,
+    /// The number of failed handshakes, including the inbound ones.
+    pub failures: usize,
+    /// The number of peers a handshake was attempted with.
+    pub peers_attempted: usize,
+    /// The number of peers whose most recent handshake failed.
+    pub peers_failing: usize,
+    /// The mean duration of the most recent handshakes with the peers.
+    pub mean_duration: Option<Duration>,
+    /// The reasons of the most recent handshake failures, along with the numbers of peers they apply to (or, for the
+    /// failed inbound handshakes, the numbers of such handshakes); the most common reasons come first.
+    pub failure_reasons: Vec<(String, usize)>,
+}
+
+/// The application-defined values attached to a peer, keyed by their types.
+#[derive(Clone, Default)]
+pub struct PeerMetadata(FxHashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl PeerMetadata {
+    /// Returns a reference to the value of the given type.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns the number of attached values.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Checks whether there are no attached values.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for PeerMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PeerMetadata({} values)", self.len())
+    }
+}
+
+impl PeerStats {
+    /// Returns the timestamp of the peer's last activity, i.e. the last connection or message.
+    pub fn last_activity(&self) -> Option<Instant> {
+        self.last_connected
+            .into_iter()
+            .chain(self.last_sent)
+            .chain(self.last_received)
+            .max()
+    }
+
+    /// Returns the timestamp indicating how fresh the peer's address is, i.e. its last sighting or activity.
+    pub fn freshness(&self) -> Option<Instant> {
+        self.last_seen.max(self.last_activity())
+    }
+
+    /// Returns the penalty used to prioritize dialing the peer; see `KnownPeers::dial_penalty`.
+    pub fn dial_penalty(&self, freshness_weight: f64) -> f64 {
+        if let Some(freshness) = self.freshness() {
+            let staleness_hours = freshness.elapsed().as_secs_f64() / 3600.0;
+            freshness_weight * staleness_hours + self.failures as f64
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    /// Merges the statistics recorded for an alias of the peer into its own; the peer's own metadata values take
+    /// precedence over the alias' ones.
+    fn merge(&mut self, other: PeerStats) {
+        self.times_connected += other.times_connected;
+        self.added = self.added.min(other.added);
+        self.last_connected = self.last_connected.max(other.last_connected);
+        self.msgs_sent += other.msgs_sent;
+        self.msgs_received += other.msgs_received;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.last_sent = self.last_sent.max(other.last_sent);
+        self.last_received = self.last_received.max(other.last_received);
+        self.failures = self.failures.saturating_add(other.failures);
+        self.keepalive_interval = self.keepalive_interval.or(other.keepalive_interval);
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.last_dialed = self.last_dialed.max(other.last_dialed);
+        self.tags.extend(other.tags);
+        for (type_id, value) in other.metadata.0 {
+            self.metadata.0.entry(type_id).or_insert(value);
+        }
+        self.handshake_attempts += other.handshake_attempts;
+        self.handshake_failures += other.handshake_failures;
+        self.last_handshake_duration = self
+            .last_handshake_duration
+            .or(other.last_handshake_duration);
+        self.last_handshake_failure = self
+            .last_handshake_failure
+            .take()
+            .or(other.last_handshake_failure);
+    }
 }
 
 impl Default for PeerStats {
@@ -91,7 +655,18 @@ impl Default for PeerStats {
             msgs_received: 0,
             bytes_sent: 0,
             bytes_received: 0,
+            last_sent: None,
+            last_received: None,
             failures: 0,
+            keepalive_interval: None,
+            last_seen: None,
+            last_dialed: None,
+            tags: Default::default(),
+            metadata: Default::default(),
+            handshake_attempts: 0,
+            handshake_failures: 0,
+            last_handshake_duration: None,
+            last_handshake_failure: None,
         }
     }
 }
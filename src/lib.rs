@@ -7,21 +7,98 @@
 //! - benchmarking and stress-testing P2P nodes (or other network entities)
 //! - substituting other, "heavier" nodes in local network tests
 
+mod acks;
+mod bandwidth;
+mod buffer_pool;
+mod capture;
 mod config;
+mod conn_metrics;
+mod diagnostics;
+mod dns;
+mod egress;
+mod events;
+mod external_addr;
+mod graph;
+mod handlers;
+mod histogram;
+mod incoming;
 mod known_peers;
+#[cfg(feature = "nat")]
+mod nat;
+mod negotiation;
 mod node;
 mod node_stats;
+mod partition;
+mod scheduler;
+mod seen;
+mod sequences;
+mod serving;
+mod status;
+mod supervision;
 mod topology;
+mod wait;
 
+#[cfg(feature = "bitcoin")]
+pub mod bitcoin;
+#[cfg(feature = "bootstrap")]
+pub mod bootstrap;
+#[cfg(feature = "compression")]
+pub mod compression;
 pub mod connections;
+#[cfg(feature = "dns-seeder")]
+pub mod dns_seeder;
+#[cfg(feature = "identity")]
+pub mod identity;
+#[cfg(feature = "libp2p")]
+pub mod libp2p;
 pub mod protocols;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod tracing_targets;
+pub mod transfers;
+pub mod wire;
 
-pub use config::NodeConfig;
-pub use connections::{Connection, ConnectionSide};
-pub use known_peers::{KnownPeers, PeerStats};
+pub use acks::Acks;
+pub use bandwidth::BandwidthRates;
+pub use capture::{read_capture, CaptureWriter, CapturedFrame, FrameTap};
+#[cfg(feature = "identity")]
+pub use config::DuplicateIdentityPolicy;
+#[cfg(feature = "test-utils")]
+pub use config::FailFastMode;
+pub use config::{
+    AddrFamilyPolicy, ClosedInboundQueuePolicy, FrameSamplingConfig, Heartbeat,
+    InboundQueueOverflowPolicy, NodeConfig, NodeConfigBuilder, PartitionDetection, ProcessingMode,
+    ReadBufferGrowth, RetryPolicy, ServingFairness, SlowPeerDetection, SubnetLimits,
+    WriterStallAction,
+};
+pub use conn_metrics::{ConnectionTimingStats, ConnectionTimings, TimingPercentiles};
+pub use connections::{
+    ConnectOptions, Connection, ConnectionContext, ConnectionFilter, ConnectionHandle,
+    ConnectionInfo, ConnectionMode, ConnectionSide, DialFailure, DialHandle, DialOutcome,
+    DialProgress,
+};
+pub use diagnostics::{DiagnosticsDump, FrameDirection, FrameSample};
+pub use dns::{DnsAnswer, DnsCacheStats, Resolver, SystemResolver};
+pub use egress::EgressPolicy;
+pub use events::{AnnotatedEvent, ConnectionIntent, HandshakeMetadata, NetworkEvent, NodeEvent};
+pub use graph::{ConnectionGraph, GraphEdge, GraphNode};
+pub use handlers::MessageHandler;
+pub use histogram::HistogramSnapshot;
+pub use incoming::Incoming;
+pub use known_peers::{
+    HandshakeFailure, HandshakeStats, KnownPeers, PeerMetadata, PeerReport, PeerStats,
+    PeerStatsReport,
+};
+pub use negotiation::PeerCapabilities;
 pub use node::Node;
 pub use node_stats::NodeStats;
+pub use partition::PartitionSignal;
+pub use scheduler::ScheduleHandle;
+pub use seen::SeenCache;
+pub use sequences::{SeqStatus, Sequences};
+pub use status::{ConnectionCounts, NodeStatus, QueueUtilization, TrafficTotals};
 pub use topology::{connect_nodes, Topology};
+pub use wait::{wait_for, wait_for_all_events, wait_for_event, wait_for_events, EventCondition};
 
 /// A trait for objects containing a `Node`; it is required to implement protocols.
 pub trait Pea2Pea {
@@ -0,0 +1,1352 @@
+//! Interoperability with libp2p peers; available with the `libp2p` feature.
+//!
+//! `Libp2pNode` performs a minimal libp2p connection upgrade as its `Handshaking` protocol:
+//! - the `/noise` security protocol is selected via multistream-select
+//! - the Noise XX handshake authenticates both sides' Ed25519 identities
+//! - a stream multiplexer (yamux or mplex) is selected via multistream-select
+//! - the dialer opens a single stream for the configured protocol, which the listener accepts
+//!
+//! The messages exchanged on that stream are prefixed with their unsigned varint-encoded length, as is customary
+//! for libp2p protocols; any other streams opened by the peer are reset. The `Reading` and `Writing` protocols handle
+//! the encryption and the multiplexing of the messages.
+//!
+//! The noise messages can be up to 65537 bytes long (including their length prefix), so `NodeConfig`'s
+//! `conn_read_buffer_size` must be at least `MIN_READ_BUFFER_SIZE`, and `conn_write_buffer_size` must be able to
+//! accommodate the largest encrypted message. The `NodeConfig.identity` must not be set, as the libp2p peers don't
+//! understand the built-in identity exchange. Outbound yamux flow control is not implemented, so the messages sent
+//! shouldn't exceed the default yamux window of 256KiB.
+
+use crate::{
+    identity::{verify_signature, NodeIdentity},
+    protocols::{Handshaking, Reading, Writing},
+    tracing_targets::HANDSHAKE,
+    Connection, ConnectionContext, ConnectionSide, Node, Pea2Pea,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+use tracing::*;
+
+use std::{convert::TryInto, fmt, io, net::SocketAddr, sync::Arc};
+
+/// The minimum `NodeConfig.conn_read_buffer_size` able to hold any noise message along with its length prefix.
+pub const MIN_READ_BUFFER_SIZE: usize = 2 + NOISE_MAX_MSG_LEN;
+
+/// The identifier of the multistream-select protocol.
+const MULTISTREAM: &str = "/multistream/1.0.0";
+/// The identifier of the noise security protocol.
+const NOISE: &str = "/noise";
+/// The noise handshake pattern and primitives used by libp2p.
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// The prefix of the message signed with the identity key in order to authenticate the noise static key.
+const NOISE_SIGNATURE_PREFIX: &[u8] = b"noise-libp2p-static-key:";
+/// The maximum size of a noise message.
+const NOISE_MAX_MSG_LEN: usize = 65535;
+/// The size of the authentication tag of an encrypted noise message.
+const NOISE_TAG_LEN: usize = 16;
+/// The maximum size of a multistream-select message.
+const MAX_MULTISTREAM_MSG_LEN: usize = 1024;
+/// The maximum number of protocols proposed by a dialer or of streams negotiated at once by a listener.
+const MAX_PROPOSALS: usize = 16;
+/// The maximum size of the data carried by a single muxer frame.
+const MAX_FRAME_DATA_LEN: usize = 1024 * 1024;
+/// The size of a yamux frame header.
+const YAMUX_HEADER_LEN: usize = 12;
+
+// the yamux frame types
+const YAMUX_DATA: u8 = 0;
+const YAMUX_WINDOW_UPDATE: u8 = 1;
+const YAMUX_PING: u8 = 2;
+const YAMUX_GO_AWAY: u8 = 3;
+
+// the yamux frame flags
+const YAMUX_SYN: u16 = 1;
+const YAMUX_ACK: u16 = 2;
+const YAMUX_FIN: u16 = 4;
+const YAMUX_RST: u16 = 8;
+
+/// A stream multiplexer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Muxer {
+    /// yamux, version 1.0.0.
+    Yamux,
+    /// mplex, version 6.7.0.
+    Mplex,
+}
+
+impl Muxer {
+    /// Returns the identifier the muxer is negotiated with.
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Self::Yamux => "/yamux/1.0.0",
+            Self::Mplex => "/mplex/6.7.0",
+        }
+    }
+}
+
+/// The configuration of a `Libp2pNode`.
+#[derive(Debug, Clone)]
+pub struct Libp2pConfig {
+    /// The identity the node authenticates itself with; it determines its `Libp2pPeerId`.
+    pub identity: NodeIdentity,
+    /// The protocol of the stream the messages are exchanged on, e.g. `/my-app/1.0.0`.
+    pub protocol: String,
+    /// The supported stream multiplexers, in the order of preference.
+    pub muxers: Vec<Muxer>,
+    /// The maximum size of a single message.
+    pub max_message_len: usize,
+}
+
+impl Default for Libp2pConfig {
+    fn default() -> Self {
+        Self {
+            identity: NodeIdentity::generate(),
+            protocol: "/pea2pea/1.0.0".into(),
+            muxers: vec![Muxer::Yamux, Muxer::Mplex],
+            max_message_len: 64 * 1024,
+        }
+    }
+}
+
+/// The identifier of a libp2p peer: the identity multihash of its protobuf-encoded public key.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Libp2pPeerId(Vec<u8>);
+
+impl Libp2pPeerId {
+    /// Derives the `Libp2pPeerId` from the given Ed25519 public key.
+    pub fn from_public_key(public_key: &[u8; 32]) -> Self {
+        let key = encode_public_key(public_key);
+
+        // the identity multihash: [code: 0x00][length][digest]
+        let mut bytes = Vec::with_capacity(2 + key.len());
+        bytes.push(0x00);
+        bytes.push(key.len() as u8);
+        bytes.extend_from_slice(&key);
+
+        Self(bytes)
+    }
+
+    /// Returns the binary representation of the `Libp2pPeerId`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The `Libp2pPeerId` is displayed in base58btc, like in libp2p.
+impl fmt::Display for Libp2pPeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode_base58(&self.0))
+    }
+}
+
+impl fmt::Debug for Libp2pPeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Libp2pPeerId({})", self)
+    }
+}
+
+/// The details of a libp2p peer established during the connection upgrade; they are attached to its connection, and
+/// are available via `Libp2pNode::peer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Libp2pPeer {
+    /// The peer's identifier.
+    pub peer_id: Libp2pPeerId,
+    /// The peer's Ed25519 public key.
+    pub public_key: [u8; 32],
+    /// The negotiated stream multiplexer.
+    pub muxer: Muxer,
+}
+
+/// A node able to exchange messages with libp2p peers; the messages it receives are passed to the receiver returned
+/// by `Libp2pNode::new`.
+#[derive(Clone)]
+pub struct Libp2pNode {
+    node: Node,
+    config: Arc<Libp2pConfig>,
+    inbound_sender: mpsc::Sender<(SocketAddr, Bytes)>,
+}
+
+impl Pea2Pea for Libp2pNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+impl Libp2pNode {
+    /// Creates a `Libp2pNode` using the given `Node`; returns it along with the receiver of the inbound messages,
+    /// whose queue depth is `NodeConfig.incoming_queue_depth`. The `Handshaking`, `Reading` and `Writing` protocols
+    /// need to be enabled afterwards. Fails if `NodeConfig.conn_read_buffer_size` is smaller than
+    /// `MIN_READ_BUFFER_SIZE`, or if `NodeConfig.identity` is set.
+    pub fn new(
+        node: Node,
+        config: Libp2pConfig,
+    ) -> io::Result<(Self, mpsc::Receiver<(SocketAddr, Bytes)>)> {
+        if node.config().conn_read_buffer_size < MIN_READ_BUFFER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the read buffer can't fit a noise message",
+            ));
+        }
+        if node.config().identity.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the built-in identity exchange is not compatible with libp2p",
+            ));
+        }
+
+        let (inbound_sender, inbound_receiver) = mpsc::channel(node.config().incoming_queue_depth);
+        let libp2p_node = Self {
+            node,
+            config: Arc::new(config),
+            inbound_sender,
+        };
+
+        Ok((libp2p_node, inbound_receiver))
+    }
+
+    /// Returns the node's configuration.
+    pub fn config(&self) -> &Libp2pConfig {
+        &self.config
+    }
+
+    /// Returns the node's own `Libp2pPeerId`.
+    pub fn peer_id(&self) -> Libp2pPeerId {
+        Libp2pPeerId::from_public_key(&self.config.identity.public_key())
+    }
+
+    /// Returns the details of the given connected peer.
+    pub fn peer(&self, addr: SocketAddr) -> Option<Libp2pPeer> {
+        self.node.connection_ext(addr)
+    }
+
+    /// Sends the given message to the specified peer via the negotiated stream.
+    pub async fn send(&self, addr: SocketAddr, message: &[u8]) -> io::Result<()> {
+        if message.len() > self.config.max_message_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the message is too large",
+            ));
+        }
+
+        let (muxer, stream) = {
+            let session = self.session(addr)?;
+            let session = session.lock();
+            (session.muxer, session.stream)
+        };
+
+        let mut data = Vec::with_capacity(10 + message.len());
+        encode_uvarint(&mut data, message.len() as u64);
+        data.extend_from_slice(message);
+
+        // the muxer frames are passed to the Writing protocol, which encrypts them
+        let mut frames = Vec::with_capacity(YAMUX_HEADER_LEN + data.len());
+        encode_data(muxer, stream, &data, &mut frames);
+
+        self.node.send_direct_message(addr, frames.into()).await
+    }
+
+    /// Returns the state of the upgraded connection with the given peer.
+    fn session(&self, addr: SocketAddr) -> io::Result<Arc<Mutex<Session>>> {
+        self.node
+            .connection_ext(addr)
+            .ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+}
+
+/// The identifier of a muxed stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct StreamId {
+    id: u64,
+    /// Indicates whether the stream was opened by the node (as opposed to the peer).
+    local: bool,
+}
+
+/// A decoded muxer frame; only the aspects relevant to the single negotiated stream are retained.
+#[derive(Debug)]
+enum Frame {
+    /// The peer opened a stream.
+    Open(StreamId),
+    /// The peer sent data on a stream.
+    Data(StreamId, Bytes),
+    /// The peer closed its side of a stream.
+    Close(StreamId),
+    /// The peer reset a stream.
+    Reset(StreamId),
+    /// The peer sent a (yamux) ping with the given value.
+    Ping(u32),
+    /// The peer is terminating the session.
+    GoAway,
+}
+
+/// The outcome of processing the decrypted bytes received from a peer.
+#[derive(Debug)]
+pub enum Libp2pEvent {
+    /// A message received via the negotiated stream.
+    Message(Bytes),
+    /// Muxer frames to be sent in response (e.g. pongs or window updates).
+    Reply(Vec<u8>),
+    /// The negotiated stream or the whole session was closed by the peer.
+    Closed,
+}
+
+/// The state of an upgraded connection.
+struct Session {
+    noise: snow::TransportState,
+    muxer: Muxer,
+    is_dialer: bool,
+    /// The negotiated stream.
+    stream: StreamId,
+    /// The decrypted bytes that don't form a complete muxer frame yet.
+    plaintext: Vec<u8>,
+    /// The data received via the negotiated stream that doesn't form a complete message yet.
+    stream_data: Vec<u8>,
+    max_message_len: usize,
+}
+
+impl Session {
+    /// Decrypts the given noise message.
+    fn decrypt(&mut self, message: &[u8]) -> io::Result<()> {
+        decrypt(&mut self.noise, message, &mut self.plaintext)
+    }
+
+    /// Processes the complete muxer frames among the decrypted bytes.
+    fn process(&mut self) -> io::Result<Vec<Libp2pEvent>> {
+        let mut events = Vec::new();
+        let mut reply = Vec::new();
+        let mut closed = false;
+
+        for frame in decode_frames(self.muxer, self.is_dialer, &mut self.plaintext)? {
+            match frame {
+                Frame::Data(stream, data) if stream == self.stream => {
+                    // replenish the peer's send window
+                    if self.muxer == Muxer::Yamux {
+                        encode_yamux(
+                            &mut reply,
+                            YAMUX_WINDOW_UPDATE,
+                            0,
+                            stream.id,
+                            data.len() as u32,
+                        );
+                    }
+                    self.stream_data.extend_from_slice(&data);
+                }
+                Frame::Close(stream) | Frame::Reset(stream) if stream == self.stream => {
+                    closed = true
+                }
+                Frame::GoAway => closed = true,
+                frame => control_reply(self.muxer, &frame, &mut reply),
+            }
+        }
+
+        while let Some(message) = decode_message(&mut self.stream_data, self.max_message_len)? {
+            events.push(Libp2pEvent::Message(message));
+        }
+        if !reply.is_empty() {
+            events.push(Libp2pEvent::Reply(reply));
+        }
+        if closed {
+            events.push(Libp2pEvent::Closed);
+        }
+
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl Reading for Libp2pNode {
+    type Message = Vec<Libp2pEvent>;
+
+    fn read_message(
+        &self,
+        ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let len = match noise_message_len(buffer) {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let session = self.session(ctx.addr())?;
+        let mut session = session.lock();
+        session.decrypt(&buffer[2..][..len])?;
+        let events = session.process()?;
+
+        Ok(Some((events, 2 + len)))
+    }
+
+    async fn process_message(&self, source: SocketAddr, events: Self::Message) -> io::Result<()> {
+        for event in events {
+            match event {
+                Libp2pEvent::Message(message) => {
+                    // the receiver may no longer be interested
+                    let _ = self.inbound_sender.send((source, message)).await;
+                }
+                Libp2pEvent::Reply(frames) => {
+                    self.node.send_direct_message(source, frames.into()).await?
+                }
+                Libp2pEvent::Closed => {
+                    debug!(parent: self.node.span(), "{} closed the stream", source);
+                    self.node.handle_connection_loss(source);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Writing for Libp2pNode {
+    fn write_message(
+        &self,
+        ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        // the payload consists of muxer frames; see `Libp2pNode::send`
+        let session = self.session(ctx.addr())?;
+        let mut session = session.lock();
+
+        encrypt(&mut session.noise, payload, buffer)
+    }
+}
+
+#[async_trait]
+impl Handshaking for Libp2pNode {
+    async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+        let is_dialer = conn.side == ConnectionSide::Responder;
+        let addr = conn.addr;
+        let span = conn.span().clone();
+        let mut upgrade = Upgrade {
+            conn: &mut conn,
+            is_dialer,
+            raw: Vec::new(),
+            noise: None,
+            plaintext: Vec::new(),
+        };
+
+        // secure the connection
+        upgrade.negotiate(&[NOISE]).await?;
+        let peer_public_key = upgrade.noise_handshake(&self.config.identity).await?;
+        let peer_id = Libp2pPeerId::from_public_key(&peer_public_key);
+        debug!(target: HANDSHAKE, parent: &span, "{} is {}", addr, peer_id);
+
+        // select the muxer
+        let muxers = self
+            .config
+            .muxers
+            .iter()
+            .map(|muxer| muxer.protocol())
+            .collect::<Vec<_>>();
+        let muxer = self.config.muxers[upgrade.negotiate(&muxers).await?];
+        debug!(target: HANDSHAKE, parent: &span, "using {} with {}", muxer.protocol(), addr);
+
+        // set up the stream the messages are exchanged on
+        let (stream, stream_data) = if is_dialer {
+            upgrade.open_stream(muxer, &self.config.protocol).await?
+        } else {
+            upgrade.accept_stream(muxer, &self.config.protocol).await?
+        };
+        debug!(target: HANDSHAKE, parent: &span, "negotiated {} with {}", self.config.protocol, addr);
+
+        let Upgrade {
+            noise, plaintext, ..
+        } = upgrade;
+        let mut session = Session {
+            // safe; the noise handshake was completed above
+            noise: noise.unwrap(),
+            muxer,
+            is_dialer,
+            stream,
+            plaintext,
+            stream_data,
+            max_message_len: self.config.max_message_len,
+        };
+
+        // the peer could have sent messages along with the final negotiation ones
+        for event in session.process()? {
+            match event {
+                Libp2pEvent::Message(message) => {
+                    let _ = self.inbound_sender.send((addr, message)).await;
+                }
+                Libp2pEvent::Reply(frames) => {
+                    let mut buffer = vec![0u8; encrypted_len(frames.len())];
+                    let len = encrypt(&mut session.noise, &frames, &mut buffer)?;
+                    conn.writer().write_all(&buffer[..len]).await?;
+                }
+                Libp2pEvent::Closed => return Err(io::ErrorKind::ConnectionReset.into()),
+            }
+        }
+
+        conn.insert_ext(Libp2pPeer {
+            peer_id,
+            public_key: peer_public_key,
+            muxer,
+        });
+        conn.insert_ext(Arc::new(Mutex::new(session)));
+
+        Ok(conn)
+    }
+}
+
+/// A connection being upgraded.
+struct Upgrade<'a> {
+    conn: &'a mut Connection,
+    is_dialer: bool,
+    /// The bytes read from the stream that weren't processed yet.
+    raw: Vec<u8>,
+    /// The noise transport, available once the connection is secured.
+    noise: Option<snow::TransportState>,
+    /// The decrypted bytes that weren't processed yet.
+    plaintext: Vec<u8>,
+}
+
+impl Upgrade<'_> {
+    /// Reads the given number of additional bytes from the stream; the stream isn't read from any further, so that
+    /// the bytes meant for the `Reading` protocol remain unread.
+    async fn read_raw(&mut self, len: usize) -> io::Result<()> {
+        let start = self.raw.len();
+        self.raw.resize(start + len, 0);
+        self.conn
+            .reader()
+            .read_exact(&mut self.raw[start..])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Reads a single length-prefixed noise message.
+    async fn read_noise_message(&mut self) -> io::Result<Vec<u8>> {
+        if self.raw.len() < 2 {
+            self.read_raw(2 - self.raw.len()).await?;
+        }
+        // safe; there are at least 2 bytes
+        let len = u16::from_be_bytes(self.raw[..2].try_into().unwrap()) as usize;
+        if self.raw.len() < 2 + len {
+            self.read_raw(2 + len - self.raw.len()).await?;
+        }
+
+        Ok(self.raw.drain(..2 + len).skip(2).collect())
+    }
+
+    /// Writes the given noise message along with its length prefix.
+    async fn write_noise_message(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(2 + message.len());
+        bytes.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(message);
+
+        self.conn.writer().write_all(&bytes).await
+    }
+
+    /// Returns the received bytes that weren't processed yet; they are the decrypted ones once the connection is
+    /// secured.
+    fn pending(&mut self) -> &mut Vec<u8> {
+        if self.noise.is_some() {
+            &mut self.plaintext
+        } else {
+            &mut self.raw
+        }
+    }
+
+    /// Receives more bytes; they are decrypted once the connection is secured.
+    async fn read_more(&mut self) -> io::Result<()> {
+        if self.noise.is_some() {
+            let message = self.read_noise_message().await?;
+            // safe; checked above
+            decrypt(self.noise.as_mut().unwrap(), &message, &mut self.plaintext)
+        } else {
+            // the multistream-select messages are read bytewise, so that nothing is read past them
+            self.read_raw(1).await
+        }
+    }
+
+    /// Sends the given bytes; they are encrypted once the connection is secured.
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(noise) = &mut self.noise {
+            let mut buffer = vec![0u8; encrypted_len(data.len())];
+            let len = encrypt(noise, data, &mut buffer)?;
+            self.conn.writer().write_all(&buffer[..len]).await
+        } else {
+            self.conn.writer().write_all(data).await
+        }
+    }
+
+    /// Reads a single multistream-select message.
+    async fn read_multistream(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(message) = decode_multistream(self.pending())? {
+                return Ok(message);
+            }
+            self.read_more().await?;
+        }
+    }
+
+    /// Negotiates one of the given protocols via multistream-select; as the dialer, they are proposed in the given
+    /// order, and as the listener, the first one proposed by the peer is accepted. Returns the index of the selected
+    /// protocol.
+    async fn negotiate(&mut self, protocols: &[&str]) -> io::Result<usize> {
+        let no_agreement =
+            || io::Error::new(io::ErrorKind::InvalidData, "no protocol could be agreed on");
+
+        if self.is_dialer {
+            let first = protocols.first().ok_or_else(no_agreement)?;
+            self.write(&encode_multistream(&[MULTISTREAM, first]))
+                .await?;
+            expect_multistream_header(self.read_multistream().await?)?;
+
+            for (i, protocol) in protocols.iter().enumerate().take(MAX_PROPOSALS) {
+                if i != 0 {
+                    self.write(&encode_multistream(&[protocol])).await?;
+                }
+                match self.read_multistream().await? {
+                    response if response == *protocol => return Ok(i),
+                    response if response == "na" => continue,
+                    _ => return Err(io::ErrorKind::InvalidData.into()),
+                }
+            }
+        } else {
+            expect_multistream_header(self.read_multistream().await?)?;
+            self.write(&encode_multistream(&[MULTISTREAM])).await?;
+
+            for _ in 0..MAX_PROPOSALS {
+                let proposal = self.read_multistream().await?;
+                if let Some(i) = protocols.iter().position(|protocol| *protocol == proposal) {
+                    self.write(&encode_multistream(&[&proposal])).await?;
+                    return Ok(i);
+                } else {
+                    self.write(&encode_multistream(&["na"])).await?;
+                }
+            }
+        }
+
+        Err(no_agreement())
+    }
+
+    /// Performs the Noise XX handshake, exchanging the handshake payloads authenticating the noise static keys with
+    /// the identity keys; returns the peer's public identity key.
+    async fn noise_handshake(&mut self, identity: &NodeIdentity) -> io::Result<[u8; 32]> {
+        // safe; the parameters are valid
+        let builder = snow::Builder::new(NOISE_PARAMS.parse().unwrap());
+        let keypair = builder.generate_keypair().map_err(noise_error)?;
+        let builder = builder.local_private_key(&keypair.private);
+        let own_payload = encode_handshake_payload(identity, &keypair.public);
+        let mut buffer = vec![0u8; NOISE_MAX_MSG_LEN];
+
+        let (noise, peer_payload) = if self.is_dialer {
+            let mut noise = builder.build_initiator().map_err(noise_error)?;
+
+            // -> e
+            let len = noise.write_message(&[], &mut buffer).map_err(noise_error)?;
+            self.write_noise_message(&buffer[..len]).await?;
+
+            // <- e, ee, s, es
+            let message = self.read_noise_message().await?;
+            let len = noise
+                .read_message(&message, &mut buffer)
+                .map_err(noise_error)?;
+            let peer_payload = buffer[..len].to_vec();
+
+            // -> s, se
+            let len = noise
+                .write_message(&own_payload, &mut buffer)
+                .map_err(noise_error)?;
+            self.write_noise_message(&buffer[..len]).await?;
+
+            (noise, peer_payload)
+        } else {
+            let mut noise = builder.build_responder().map_err(noise_error)?;
+
+            // <- e
+            let message = self.read_noise_message().await?;
+            noise
+                .read_message(&message, &mut buffer)
+                .map_err(noise_error)?;
+
+            // -> e, ee, s, es
+            let len = noise
+                .write_message(&own_payload, &mut buffer)
+                .map_err(noise_error)?;
+            self.write_noise_message(&buffer[..len]).await?;
+
+            // <- s, se
+            let message = self.read_noise_message().await?;
+            let len = noise
+                .read_message(&message, &mut buffer)
+                .map_err(noise_error)?;
+
+            (noise, buffer[..len].to_vec())
+        };
+
+        let peer_static_key = noise
+            .get_remote_static()
+            .ok_or(io::ErrorKind::InvalidData)?;
+        let peer_public_key = decode_handshake_payload(&peer_payload, peer_static_key)?;
+        self.noise = Some(noise.into_transport_mode().map_err(noise_error)?);
+
+        Ok(peer_public_key)
+    }
+
+    /// Reads at least one muxer frame.
+    async fn read_frames(&mut self, muxer: Muxer) -> io::Result<Vec<Frame>> {
+        loop {
+            let frames = decode_frames(muxer, self.is_dialer, &mut self.plaintext)?;
+            if !frames.is_empty() {
+                return Ok(frames);
+            }
+            self.read_more().await?;
+        }
+    }
+
+    /// Opens a stream and negotiates the given protocol on it; returns the stream along with any data received via
+    /// it after the negotiation.
+    async fn open_stream(
+        &mut self,
+        muxer: Muxer,
+        protocol: &str,
+    ) -> io::Result<(StreamId, Vec<u8>)> {
+        // yamux dialers use odd stream IDs
+        let stream = StreamId {
+            id: if muxer == Muxer::Yamux { 1 } else { 0 },
+            local: true,
+        };
+
+        let mut frames = Vec::new();
+        match muxer {
+            Muxer::Yamux => encode_yamux(&mut frames, YAMUX_WINDOW_UPDATE, YAMUX_SYN, stream.id, 0),
+            Muxer::Mplex => {
+                encode_mplex(&mut frames, stream.id, 0, stream.id.to_string().as_bytes())
+            }
+        }
+        encode_data(
+            muxer,
+            stream,
+            &encode_multistream(&[MULTISTREAM, protocol]),
+            &mut frames,
+        );
+        self.write(&frames).await?;
+
+        let mut data = Vec::new();
+        let mut got_header = false;
+        loop {
+            while let Some(message) = decode_multistream(&mut data)? {
+                if !got_header {
+                    expect_multistream_header(message)?;
+                    got_header = true;
+                } else if message == protocol {
+                    return Ok((stream, data));
+                } else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the peer doesn't support the protocol",
+                    ));
+                }
+            }
+
+            let mut reply = Vec::new();
+            for frame in self.read_frames(muxer).await? {
+                match frame {
+                    Frame::Data(s, bytes) if s == stream => data.extend_from_slice(&bytes),
+                    Frame::Close(s) | Frame::Reset(s) if s == stream => {
+                        return Err(io::ErrorKind::ConnectionReset.into())
+                    }
+                    Frame::GoAway => return Err(io::ErrorKind::ConnectionAborted.into()),
+                    frame => control_reply(muxer, &frame, &mut reply),
+                }
+            }
+            if !reply.is_empty() {
+                self.write(&reply).await?;
+            }
+        }
+    }
+
+    /// Accepts the first stream the given protocol is negotiated on; returns the stream along with any data
+    /// received via it after the negotiation.
+    async fn accept_stream(
+        &mut self,
+        muxer: Muxer,
+        protocol: &str,
+    ) -> io::Result<(StreamId, Vec<u8>)> {
+        // the streams being negotiated, along with their data and whether the multistream header was received
+        let mut candidates: FxHashMap<StreamId, (Vec<u8>, bool)> = Default::default();
+
+        loop {
+            let mut reply = Vec::new();
+            for frame in self.read_frames(muxer).await? {
+                match frame {
+                    Frame::Open(s) if candidates.len() < MAX_PROPOSALS => {
+                        if muxer == Muxer::Yamux {
+                            encode_yamux(&mut reply, YAMUX_WINDOW_UPDATE, YAMUX_ACK, s.id, 0);
+                        }
+                        candidates.insert(s, Default::default());
+                    }
+                    Frame::Data(s, bytes) if candidates.contains_key(&s) => {
+                        // safe; checked in the match guard
+                        candidates.get_mut(&s).unwrap().0.extend_from_slice(&bytes);
+                    }
+                    Frame::Close(s) | Frame::Reset(s) if candidates.contains_key(&s) => {
+                        candidates.remove(&s);
+                    }
+                    Frame::GoAway => return Err(io::ErrorKind::ConnectionAborted.into()),
+                    frame => control_reply(muxer, &frame, &mut reply),
+                }
+            }
+
+            let mut accepted = None;
+            for (&stream, (data, got_header)) in candidates.iter_mut() {
+                while let Some(message) = decode_multistream(data)? {
+                    let response = if !*got_header {
+                        expect_multistream_header(message)?;
+                        *got_header = true;
+                        MULTISTREAM
+                    } else if message == protocol {
+                        accepted = Some(stream);
+                        protocol
+                    } else {
+                        "na"
+                    };
+                    encode_data(muxer, stream, &encode_multistream(&[response]), &mut reply);
+
+                    if accepted.is_some() {
+                        break;
+                    }
+                }
+                if accepted.is_some() {
+                    break;
+                }
+            }
+
+            if let Some(stream) = accepted {
+                // safe; the stream is one of the candidates
+                let (data, _) = candidates.remove(&stream).unwrap();
+                // only a single stream is supported
+                for &other in candidates.keys() {
+                    encode_reset(muxer, other, &mut reply);
+                }
+                self.write(&reply).await?;
+
+                return Ok((stream, data));
+            }
+
+            if !reply.is_empty() {
+                self.write(&reply).await?;
+            }
+        }
+    }
+}
+
+/// Converts a noise error to an `io::Error`.
+fn noise_error(e: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Returns the size of the length-prefixed noise message at the beginning of the given buffer, as long as it's
+/// complete.
+fn noise_message_len(buffer: &[u8]) -> Option<usize> {
+    let len = u16::from_be_bytes(buffer.get(..2)?.try_into().ok()?) as usize;
+
+    if buffer.len() >= 2 + len {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Returns the size of the given data once encrypted as length-prefixed noise messages.
+fn encrypted_len(len: usize) -> usize {
+    let num_messages = len.div_ceil(NOISE_MAX_MSG_LEN - NOISE_TAG_LEN);
+
+    len + num_messages * (2 + NOISE_TAG_LEN)
+}
+
+/// Encrypts the given data as length-prefixed noise messages written to the given buffer; returns their size.
+fn encrypt(noise: &mut snow::TransportState, data: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    if buffer.len() < encrypted_len(data.len()) {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+
+    let mut written = 0;
+    for chunk in data.chunks(NOISE_MAX_MSG_LEN - NOISE_TAG_LEN) {
+        let len = noise
+            .write_message(chunk, &mut buffer[written + 2..])
+            .map_err(noise_error)?;
+        buffer[written..][..2].copy_from_slice(&(len as u16).to_be_bytes());
+        written += 2 + len;
+    }
+
+    Ok(written)
+}
+
+/// Decrypts the given noise message, appending the result to the given plaintext.
+fn decrypt(
+    noise: &mut snow::TransportState,
+    message: &[u8],
+    plaintext: &mut Vec<u8>,
+) -> io::Result<()> {
+    let start = plaintext.len();
+    plaintext.resize(start + message.len(), 0);
+    let len = noise
+        .read_message(message, &mut plaintext[start..])
+        .map_err(noise_error)?;
+    plaintext.truncate(start + len);
+
+    Ok(())
+}
+
+/// Encodes the given unsigned varint.
+fn encode_uvarint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Decodes an unsigned varint from the beginning of the given buffer; returns it along with its size, as long as
+/// it's complete.
+fn decode_uvarint(buffer: &[u8]) -> io::Result<Option<(u64, usize)>> {
+    let mut value = 0u64;
+
+    for (i, &byte) in buffer.iter().enumerate() {
+        if i == 9 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid varint"));
+        }
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Encodes the given multistream-select messages.
+fn encode_multistream(messages: &[&str]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for message in messages {
+        encode_uvarint(&mut bytes, message.len() as u64 + 1);
+        bytes.extend_from_slice(message.as_bytes());
+        bytes.push(b'\n');
+    }
+
+    bytes
+}
+
+/// Removes a single multistream-select message from the beginning of the given buffer, as long as it's complete.
+fn decode_multistream(buffer: &mut Vec<u8>) -> io::Result<Option<String>> {
+    let (len, prefix_len) = match decode_uvarint(buffer)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    let len = len as usize;
+    if len == 0 || len > MAX_MULTISTREAM_MSG_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid multistream-select message",
+        ));
+    }
+    if buffer.len() < prefix_len + len {
+        return Ok(None);
+    }
+
+    let message = buffer
+        .drain(..prefix_len + len)
+        .skip(prefix_len)
+        .collect::<Vec<_>>();
+    match message.split_last() {
+        Some((b'\n', message)) => String::from_utf8(message.to_vec())
+            .map(Some)
+            .map_err(|_| io::ErrorKind::InvalidData.into()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid multistream-select message",
+        )),
+    }
+}
+
+/// Ensures the given multistream-select message is the protocol header.
+fn expect_multistream_header(message: String) -> io::Result<()> {
+    if message == MULTISTREAM {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported multistream-select version",
+        ))
+    }
+}
+
+/// Removes a single length-prefixed message from the beginning of the given buffer, as long as it's complete.
+fn decode_message(buffer: &mut Vec<u8>, max_len: usize) -> io::Result<Option<Bytes>> {
+    let (len, prefix_len) = match decode_uvarint(buffer)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    let len = len as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the message is too large",
+        ));
+    }
+    if buffer.len() < prefix_len + len {
+        return Ok(None);
+    }
+
+    let message = buffer
+        .drain(..prefix_len + len)
+        .skip(prefix_len)
+        .collect::<Vec<_>>();
+
+    Ok(Some(message.into()))
+}
+
+/// Appends a length-delimited protobuf field with the given number and contents.
+fn encode_protobuf_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_uvarint(buffer, field << 3 | 2);
+    encode_uvarint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// A decoded protobuf field value.
+enum ProtobufValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Decodes the given protobuf message into the numbers and values of its fields.
+fn decode_protobuf(mut bytes: &[u8]) -> io::Result<Vec<(u64, ProtobufValue<'_>)>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid protobuf message");
+    let mut fields = Vec::new();
+
+    while !bytes.is_empty() {
+        let (key, len) = decode_uvarint(bytes)?.ok_or_else(invalid)?;
+        bytes = &bytes[len..];
+
+        let value = match key & 7 {
+            0 => {
+                let (value, len) = decode_uvarint(bytes)?.ok_or_else(invalid)?;
+                bytes = &bytes[len..];
+                ProtobufValue::Varint(value)
+            }
+            2 => {
+                let (value_len, len) = decode_uvarint(bytes)?.ok_or_else(invalid)?;
+                let value = bytes
+                    .get(len..)
+                    .and_then(|bytes| bytes.get(..value_len as usize))
+                    .ok_or_else(invalid)?;
+                bytes = &bytes[len + value.len()..];
+                ProtobufValue::Bytes(value)
+            }
+            _ => return Err(invalid()),
+        };
+        fields.push((key >> 3, value));
+    }
+
+    Ok(fields)
+}
+
+/// Encodes the given Ed25519 public key as a libp2p `PublicKey` protobuf message.
+fn encode_public_key(public_key: &[u8; 32]) -> Vec<u8> {
+    // the key type field; 1 stands for Ed25519
+    let mut bytes = vec![0x08, 0x01];
+    encode_protobuf_bytes(&mut bytes, 2, public_key);
+
+    bytes
+}
+
+/// Encodes the noise handshake payload: the public identity key and its signature of the noise static key.
+fn encode_handshake_payload(identity: &NodeIdentity, static_key: &[u8]) -> Vec<u8> {
+    let signature = identity.sign(&[NOISE_SIGNATURE_PREFIX, static_key].concat());
+
+    let mut payload = Vec::new();
+    encode_protobuf_bytes(&mut payload, 1, &encode_public_key(&identity.public_key()));
+    encode_protobuf_bytes(&mut payload, 2, &signature);
+
+    payload
+}
+
+/// Decodes the noise handshake payload and verifies its signature of the given noise static key; returns the
+/// public identity key.
+fn decode_handshake_payload(payload: &[u8], static_key: &[u8]) -> io::Result<[u8; 32]> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid handshake payload");
+
+    let (mut key, mut signature) = (None, None);
+    for (field, value) in decode_protobuf(payload)? {
+        match (field, value) {
+            (1, ProtobufValue::Bytes(bytes)) => key = Some(bytes),
+            (2, ProtobufValue::Bytes(bytes)) => signature = Some(bytes),
+            // e.g. the extensions
+            _ => {}
+        }
+    }
+
+    let (mut key_type, mut key_data) = (None, None);
+    for (field, value) in decode_protobuf(key.ok_or_else(invalid)?)? {
+        match (field, value) {
+            (1, ProtobufValue::Varint(value)) => key_type = Some(value),
+            (2, ProtobufValue::Bytes(bytes)) => key_data = Some(bytes),
+            _ => {}
+        }
+    }
+    if key_type != Some(1) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "only Ed25519 identities are supported",
+        ));
+    }
+
+    let public_key: [u8; 32] = key_data
+        .and_then(|data| data.try_into().ok())
+        .ok_or_else(invalid)?;
+    let signature: [u8; 64] = signature
+        .and_then(|signature| signature.try_into().ok())
+        .ok_or_else(invalid)?;
+
+    if verify_signature(
+        &public_key,
+        &[NOISE_SIGNATURE_PREFIX, static_key].concat(),
+        &signature,
+    ) {
+        Ok(public_key)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid noise static key signature",
+        ))
+    }
+}
+
+/// Encodes the given bytes in base58btc.
+fn encode_base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    // the base58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in bytes {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    // every leading zero byte is represented by a '1'
+    let num_zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+
+    let mut encoded = "1".repeat(num_zeros);
+    encoded.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|&digit| ALPHABET[digit as usize] as char),
+    );
+
+    encoded
+}
+
+/// Appends a yamux frame header.
+fn encode_yamux(buffer: &mut Vec<u8>, ty: u8, flags: u16, stream_id: u64, len: u32) {
+    buffer.push(0); // the version
+    buffer.push(ty);
+    buffer.extend_from_slice(&flags.to_be_bytes());
+    buffer.extend_from_slice(&(stream_id as u32).to_be_bytes());
+    buffer.extend_from_slice(&len.to_be_bytes());
+}
+
+/// Appends an mplex frame with the given flag.
+fn encode_mplex(buffer: &mut Vec<u8>, stream_id: u64, flag: u64, data: &[u8]) {
+    encode_uvarint(buffer, stream_id << 3 | flag);
+    encode_uvarint(buffer, data.len() as u64);
+    buffer.extend_from_slice(data);
+}
+
+/// Appends the frames carrying the given data via the given stream.
+fn encode_data(muxer: Muxer, stream: StreamId, data: &[u8], buffer: &mut Vec<u8>) {
+    for chunk in data.chunks(MAX_FRAME_DATA_LEN) {
+        match muxer {
+            Muxer::Yamux => {
+                encode_yamux(buffer, YAMUX_DATA, 0, stream.id, chunk.len() as u32);
+                buffer.extend_from_slice(chunk);
+            }
+            Muxer::Mplex => {
+                // the flags differ depending on which side opened the stream
+                let flag = if stream.local { 2 } else { 1 };
+                encode_mplex(buffer, stream.id, flag, chunk);
+            }
+        }
+    }
+}
+
+/// Appends a frame resetting the given stream.
+fn encode_reset(muxer: Muxer, stream: StreamId, buffer: &mut Vec<u8>) {
+    match muxer {
+        Muxer::Yamux => encode_yamux(buffer, YAMUX_WINDOW_UPDATE, YAMUX_RST, stream.id, 0),
+        Muxer::Mplex => {
+            let flag = if stream.local { 6 } else { 5 };
+            encode_mplex(buffer, stream.id, flag, &[]);
+        }
+    }
+}
+
+/// Appends the response to a frame unrelated to the negotiated stream: the streams opened by the peer are reset,
+/// and pings are answered.
+fn control_reply(muxer: Muxer, frame: &Frame, buffer: &mut Vec<u8>) {
+    match *frame {
+        Frame::Open(stream) => encode_reset(muxer, stream, buffer),
+        Frame::Ping(value) => encode_yamux(buffer, YAMUX_PING, YAMUX_ACK, 0, value),
+        _ => {}
+    }
+}
+
+/// Removes all the complete muxer frames from the beginning of the given buffer and decodes them.
+fn decode_frames(muxer: Muxer, is_dialer: bool, buffer: &mut Vec<u8>) -> io::Result<Vec<Frame>> {
+    let mut frames = Vec::new();
+    let mut processed = 0;
+
+    loop {
+        let remaining = &buffer[processed..];
+        let len = match muxer {
+            Muxer::Yamux => decode_yamux_frame(remaining, is_dialer, &mut frames)?,
+            Muxer::Mplex => decode_mplex_frame(remaining, &mut frames)?,
+        };
+        match len {
+            Some(len) => processed += len,
+            None => break,
+        }
+    }
+    buffer.drain(..processed);
+
+    Ok(frames)
+}
+
+/// Decodes a yamux frame from the beginning of the given buffer, as long as it's complete; returns its size.
+fn decode_yamux_frame(
+    buffer: &[u8],
+    is_dialer: bool,
+    frames: &mut Vec<Frame>,
+) -> io::Result<Option<usize>> {
+    if buffer.len() < YAMUX_HEADER_LEN {
+        return Ok(None);
+    }
+
+    // safe; the slices have the exact lengths required by the conversions
+    let (version, ty) = (buffer[0], buffer[1]);
+    let flags = u16::from_be_bytes(buffer[2..4].try_into().unwrap());
+    let stream_id = u32::from_be_bytes(buffer[4..8].try_into().unwrap());
+    let len = u32::from_be_bytes(buffer[8..12].try_into().unwrap());
+
+    if version != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported yamux version",
+        ));
+    }
+
+    // yamux dialers use odd stream IDs
+    let stream = StreamId {
+        id: stream_id as u64,
+        local: (stream_id % 2 == 1) == is_dialer,
+    };
+    let frame_len = match ty {
+        YAMUX_DATA => {
+            let data_len = len as usize;
+            if data_len > MAX_FRAME_DATA_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the frame is too large",
+                ));
+            }
+            if buffer.len() < YAMUX_HEADER_LEN + data_len {
+                return Ok(None);
+            }
+            YAMUX_HEADER_LEN + data_len
+        }
+        YAMUX_WINDOW_UPDATE | YAMUX_PING | YAMUX_GO_AWAY => YAMUX_HEADER_LEN,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid yamux frame type",
+            ))
+        }
+    };
+
+    match ty {
+        YAMUX_DATA | YAMUX_WINDOW_UPDATE => {
+            if flags & YAMUX_SYN != 0 {
+                frames.push(Frame::Open(stream));
+            }
+            if frame_len > YAMUX_HEADER_LEN {
+                let data = Bytes::copy_from_slice(&buffer[YAMUX_HEADER_LEN..frame_len]);
+                frames.push(Frame::Data(stream, data));
+            }
+            if flags & YAMUX_FIN != 0 {
+                frames.push(Frame::Close(stream));
+            }
+            if flags & YAMUX_RST != 0 {
+                frames.push(Frame::Reset(stream));
+            }
+        }
+        YAMUX_PING if flags & YAMUX_SYN != 0 => frames.push(Frame::Ping(len)),
+        YAMUX_GO_AWAY => frames.push(Frame::GoAway),
+        _ => {}
+    }
+
+    Ok(Some(frame_len))
+}
+
+/// Decodes an mplex frame from the beginning of the given buffer, as long as it's complete; returns its size.
+fn decode_mplex_frame(buffer: &[u8], frames: &mut Vec<Frame>) -> io::Result<Option<usize>> {
+    let (header, header_len) = match decode_uvarint(buffer)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let (data_len, len_len) = match decode_uvarint(&buffer[header_len..])? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    let data_len = data_len as usize;
+    if data_len > MAX_FRAME_DATA_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the frame is too large",
+        ));
+    }
+    let frame_len = header_len + len_len + data_len;
+    if buffer.len() < frame_len {
+        return Ok(None);
+    }
+
+    // the odd flags are used by the side that didn't open the stream, so they refer to the local streams
+    let id = header >> 3;
+    let stream = |local| StreamId { id, local };
+    let data = &buffer[header_len + len_len..frame_len];
+
+    let frame = match header & 7 {
+        0 => Frame::Open(stream(false)),
+        1 => Frame::Data(stream(true), Bytes::copy_from_slice(data)),
+        2 => Frame::Data(stream(false), Bytes::copy_from_slice(data)),
+        3 => Frame::Close(stream(true)),
+        4 => Frame::Close(stream(false)),
+        5 => Frame::Reset(stream(true)),
+        6 => Frame::Reset(stream(false)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid mplex flag",
+            ))
+        }
+    };
+    frames.push(frame);
+
+    Ok(Some(frame_len))
+}
@@ -0,0 +1,437 @@
+//! NAT traversal via NAT-PMP (RFC 6886) or UPnP IGD port mapping; available with the `nat` feature.
+
+use crate::{tracing_targets::NAT, Node};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    task::JoinHandle,
+    time::sleep,
+};
+use tracing::*;
+
+use std::{
+    convert::TryInto,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+/// The port NAT-PMP servers listen on.
+const NAT_PMP_PORT: u16 = 5351;
+
+/// The opcode of the external address request.
+const OP_EXTERNAL_ADDR: u8 = 0;
+/// The opcode of the TCP mapping request.
+const OP_MAP_TCP: u8 = 2;
+/// The offset added to the request opcodes in the responses.
+const RESPONSE_OFFSET: u8 = 128;
+
+/// The number of times a request is sent before giving up.
+const MAX_ATTEMPTS: u32 = 4;
+/// The initial response timeout; it is doubled with each attempt.
+const INITIAL_TIMEOUT_MS: u64 = 250;
+/// The delay after which a failed mapping attempt is retried.
+const RETRY_INTERVAL_SECS: u64 = 60;
+
+/// The SSDP multicast group UPnP devices are discovered in.
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+/// The SSDP port.
+const SSDP_PORT: u16 = 1900;
+/// The UPnP device type of the gateways.
+const IGD_DEVICE: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+/// The UPnP services able to map ports, in the order of preference.
+const WAN_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+/// The maximum size of an HTTP response from a UPnP gateway.
+const MAX_UPNP_RESPONSE_LEN: u64 = 64 * 1024;
+/// The time a UPnP gateway has to respond to an HTTP request.
+const UPNP_TIMEOUT_MS: u64 = 2_000;
+
+/// Spawns a task that maps the given port on the gateway and periodically renews the lease, keeping the node's
+/// external address up to date.
+pub(crate) fn spawn_port_mapping_task(node: &Node, internal_port: u16) -> JoinHandle<()> {
+    let node = node.clone();
+
+    node.clone().spawn_task(format_args!("nat"), async move {
+        trace!(target: NAT, parent: node.span(), "spawned the port mapping task");
+
+        loop {
+            let renewal_delay = match map_port(&node, internal_port, node.config().nat_lease_secs)
+                .await
+            {
+                Ok((external_addr, lifetime)) => {
+                    if node.mapped_addr() != Some(external_addr) {
+                        info!(target: NAT, parent: node.span(), "mapped port {} to {}", internal_port, external_addr);
+                    }
+                    node.set_mapped_addr(Some(external_addr));
+                    if lifetime == 0 {
+                        // a gateway granting no lease mustn't make the renewals spin
+                        Duration::from_secs(RETRY_INTERVAL_SECS)
+                    } else {
+                        // renew the lease halfway through its lifetime
+                        Duration::from_secs((lifetime / 2).max(1).into())
+                    }
+                }
+                Err(e) => {
+                    warn!(target: NAT, parent: node.span(), "couldn't map port {}: {}", internal_port, e);
+                    node.set_mapped_addr(None);
+                    Duration::from_secs(RETRY_INTERVAL_SECS)
+                }
+            };
+
+            sleep(renewal_delay).await;
+        }
+    })
+}
+
+/// Requests the removal of the mapping of the given port.
+pub(crate) async fn unmap_port(node: &Node, internal_port: u16) {
+    let result = match nat_pmp_map_port(node, internal_port, 0).await {
+        Ok(_) => Ok(()),
+        Err(_) => upnp_unmap_port(internal_port).await,
+    };
+
+    if let Err(e) = result {
+        debug!(target: NAT, parent: node.span(), "couldn't remove the mapping of port {}: {}", internal_port, e);
+    } else {
+        debug!(target: NAT, parent: node.span(), "removed the mapping of port {}", internal_port);
+    }
+}
+
+/// Maps the given port for the given lifetime (in seconds) via NAT-PMP or, if the gateway doesn't support it, via
+/// UPnP; returns the external address and the lifetime granted by the gateway.
+async fn map_port(node: &Node, internal_port: u16, lifetime: u32) -> io::Result<(SocketAddr, u32)> {
+    match nat_pmp_map_port(node, internal_port, lifetime).await {
+        Ok(mapping) => Ok(mapping),
+        Err(e) => {
+            debug!(target: NAT, parent: node.span(), "NAT-PMP port mapping failed ({}); trying UPnP", e);
+            upnp_map_port(internal_port, lifetime).await
+        }
+    }
+}
+
+/// Maps the given port for the given lifetime (in seconds) via NAT-PMP; returns the external address and the
+/// lifetime granted by the gateway.
+async fn nat_pmp_map_port(
+    node: &Node,
+    internal_port: u16,
+    lifetime: u32,
+) -> io::Result<(SocketAddr, u32)> {
+    let gateway = if let Some(addr) = node.config().nat_gateway {
+        addr
+    } else {
+        SocketAddr::new(default_gateway()?, NAT_PMP_PORT)
+    };
+
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    socket.connect(gateway).await?;
+
+    let response = request(&socket, &[0, OP_EXTERNAL_ADDR], 12).await?;
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let mut mapping_request = vec![0, OP_MAP_TCP, 0, 0];
+    mapping_request.extend_from_slice(&internal_port.to_be_bytes());
+    // suggest the same external port
+    mapping_request.extend_from_slice(&internal_port.to_be_bytes());
+    mapping_request.extend_from_slice(&lifetime.to_be_bytes());
+
+    let response = request(&socket, &mapping_request, 16).await?;
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let granted_lifetime = u32::from_be_bytes(response[12..16].try_into().unwrap()); // safe; checked in request
+
+    Ok((
+        SocketAddr::new(external_ip.into(), external_port),
+        granted_lifetime,
+    ))
+}
+
+/// Sends the given request to the gateway, retransmitting it if there's no timely response; returns the response.
+async fn request(socket: &UdpSocket, request: &[u8], response_len: usize) -> io::Result<Vec<u8>> {
+    let mut response = vec![0u8; response_len];
+    let mut timeout = Duration::from_millis(INITIAL_TIMEOUT_MS);
+
+    for _ in 0..MAX_ATTEMPTS {
+        socket.send(request).await?;
+
+        match tokio::time::timeout(timeout, socket.recv(&mut response)).await {
+            Ok(Ok(len)) => {
+                if len < response_len
+                    || response[0] != 0
+                    || response[1] != RESPONSE_OFFSET + request[1]
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid NAT-PMP response",
+                    ));
+                }
+
+                let result_code = u16::from_be_bytes([response[2], response[3]]);
+                if result_code != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionRefused,
+                        format!(
+                            "the NAT-PMP request failed with result code {}",
+                            result_code
+                        ),
+                    ));
+                }
+
+                return Ok(response);
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => timeout *= 2,
+        }
+    }
+
+    Err(io::ErrorKind::TimedOut.into())
+}
+
+/// The control endpoint of a UPnP gateway's port mapping service.
+struct UpnpGateway {
+    /// The address of the gateway's HTTP server.
+    addr: SocketAddr,
+    /// The path of the service's control URL.
+    control_path: String,
+    /// The type of the service.
+    service: &'static str,
+}
+
+/// Maps the given port for the given lifetime (in seconds) via UPnP; returns the external address and the lifetime.
+async fn upnp_map_port(internal_port: u16, lifetime: u32) -> io::Result<(SocketAddr, u32)> {
+    let gateway = discover_upnp_gateway().await?;
+
+    let (response, local_ip) = soap_request(&gateway, "GetExternalIPAddress", &[]).await?;
+    let external_ip = xml_value(&response, "NewExternalIPAddress")
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+        .ok_or_else(|| invalid_upnp_data("no external address in the UPnP response"))?;
+
+    // the mapping needs to point at the address the gateway sees the node at
+    let port = internal_port.to_string();
+    soap_request(
+        &gateway,
+        "AddPortMapping",
+        &[
+            ("NewRemoteHost", ""),
+            ("NewExternalPort", &port),
+            ("NewProtocol", "TCP"),
+            ("NewInternalPort", &port),
+            ("NewInternalClient", &local_ip.to_string()),
+            ("NewEnabled", "1"),
+            ("NewPortMappingDescription", "pea2pea"),
+            ("NewLeaseDuration", &lifetime.to_string()),
+        ],
+    )
+    .await?;
+
+    Ok((SocketAddr::new(external_ip, internal_port), lifetime))
+}
+
+/// Removes the mapping of the given port via UPnP.
+async fn upnp_unmap_port(internal_port: u16) -> io::Result<()> {
+    let gateway = discover_upnp_gateway().await?;
+
+    soap_request(
+        &gateway,
+        "DeletePortMapping",
+        &[
+            ("NewRemoteHost", ""),
+            ("NewExternalPort", &internal_port.to_string()),
+            ("NewProtocol", "TCP"),
+        ],
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Finds a UPnP gateway via SSDP and the control endpoint of its port mapping service.
+async fn discover_upnp_gateway() -> io::Result<UpnpGateway> {
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}:{}\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: {}\r\n\r\n",
+        SSDP_GROUP, SSDP_PORT, IGD_DEVICE
+    );
+    let mut response = [0u8; 2048];
+    let mut timeout = Duration::from_millis(INITIAL_TIMEOUT_MS);
+
+    let mut location = None;
+    for _ in 0..MAX_ATTEMPTS {
+        socket
+            .send_to(search.as_bytes(), (SSDP_GROUP, SSDP_PORT))
+            .await?;
+
+        match tokio::time::timeout(timeout, socket.recv_from(&mut response)).await {
+            Ok(Ok((len, _))) => {
+                location = header_value(&String::from_utf8_lossy(&response[..len]), "location");
+                break;
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => timeout *= 2,
+        }
+    }
+    let location = location.ok_or(io::ErrorKind::TimedOut)?;
+
+    let (addr, description_path) = parse_http_url(&location)?;
+    let (description, _) =
+        http_request(addr, &format!("GET {} HTTP/1.0", description_path), "").await?;
+
+    // the control URL follows the type of the service in its description
+    for service in WAN_SERVICES {
+        if let Some(idx) = description.find(service) {
+            let control_url = xml_value(&description[idx..], "controlURL")
+                .ok_or_else(|| invalid_upnp_data("no control URL in the UPnP description"))?
+                .trim();
+            let (addr, control_path) = if control_url.starts_with("http://") {
+                parse_http_url(control_url)?
+            } else if control_url.starts_with('/') {
+                (addr, control_url.to_owned())
+            } else {
+                (addr, format!("/{}", control_url))
+            };
+
+            return Ok(UpnpGateway {
+                addr,
+                control_path,
+                service,
+            });
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the UPnP gateway can't map ports",
+    ))
+}
+
+/// Invokes the given action of the gateway's port mapping service; returns the response body and the local IP the
+/// request was sent from.
+async fn soap_request(
+    gateway: &UpnpGateway,
+    action: &str,
+    args: &[(&str, &str)],
+) -> io::Result<(String, IpAddr)> {
+    let args = args
+        .iter()
+        .map(|(name, value)| format!("<{0}>{1}</{0}>", name, value))
+        .collect::<String>();
+    let body = format!(
+        "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{0} xmlns:u=\"{1}\">{2}</u:{0}>\
+         </s:Body></s:Envelope>",
+        action, gateway.service, args
+    );
+    let head = format!(
+        "POST {} HTTP/1.0\r\nContent-Type: text/xml; charset=\"utf-8\"\r\nSOAPAction: \"{}#{}\"",
+        gateway.control_path, gateway.service, action
+    );
+
+    http_request(gateway.addr, &head, &body).await
+}
+
+/// Sends an HTTP request with the given request line (and optional headers) and body to the given address; returns
+/// the body of a successful response and the local IP the request was sent from. HTTP/1.0 is used, so that the
+/// responses are neither chunked nor kept alive.
+async fn http_request(addr: SocketAddr, head: &str, body: &str) -> io::Result<(String, IpAddr)> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        let local_ip = stream.local_addr()?.ip();
+
+        let request = format!(
+            "{}\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}",
+            head,
+            addr,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream
+            .take(MAX_UPNP_RESPONSE_LEN)
+            .read_to_end(&mut response)
+            .await?;
+
+        Ok::<_, io::Error>((response, local_ip))
+    };
+    let (response, local_ip) =
+        tokio::time::timeout(Duration::from_millis(UPNP_TIMEOUT_MS), exchange)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (status, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid_upnp_data("invalid UPnP response"))?;
+    let status = status.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("the UPnP request failed with \"{}\"", status),
+        ));
+    }
+
+    Ok((body.to_owned(), local_ip))
+}
+
+/// Splits the given HTTP URL into the server's address and the path.
+fn parse_http_url(url: &str) -> io::Result<(SocketAddr, String)> {
+    let url = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid_upnp_data("unsupported UPnP URL"))?;
+    let (host, path) = url.split_at(url.find('/').unwrap_or(url.len()));
+
+    // the gateways advertise themselves by their IPs
+    let addr = host
+        .parse::<SocketAddr>()
+        .or_else(|_| host.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 80)))
+        .map_err(|_| invalid_upnp_data("unsupported UPnP URL"))?;
+    let path = if path.is_empty() { "/" } else { path };
+
+    Ok((addr, path.to_owned()))
+}
+
+/// Returns the value of the given header of an HTTP message.
+fn header_value(message: &str, name: &str) -> Option<String> {
+    message.lines().find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_owned())
+    })
+}
+
+/// Returns the text of the first element with the given name in the given XML document.
+fn xml_value<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let len = xml[start..].find(&format!("</{}>", name))?;
+
+    Some(&xml[start..][..len])
+}
+
+/// Creates an error indicating an invalid response from a UPnP gateway.
+fn invalid_upnp_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the IP of the default gateway; only supported on Linux.
+fn default_gateway() -> io::Result<IpAddr> {
+    let routes = std::fs::read_to_string("/proc/net/route")?;
+
+    // the columns are: interface, destination, gateway, ...; the addresses are hex numbers in native byte order
+    for route in routes.lines().skip(1) {
+        let mut columns = route.split_whitespace().skip(1);
+        if let (Some("00000000"), Some(gateway)) = (columns.next(), columns.next()) {
+            if let Ok(gateway) = u32::from_str_radix(gateway, 16) {
+                return Ok(Ipv4Addr::from(gateway.to_ne_bytes()).into());
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "couldn't determine the default gateway",
+    ))
+}
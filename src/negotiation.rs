@@ -0,0 +1,186 @@
+use crate::{tracing_targets::HANDSHAKE, Connection, Node};
+
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::*;
+
+use std::{
+    convert::TryInto,
+    io,
+    net::{IpAddr, Ipv6Addr},
+};
+
+/// The size of a version negotiation message: a `u32` version, a `u64` capability bitset, the `u32` bounds of the
+/// supported version range, a `u32` maximum frame size, a `u32` inbound queue depth and a `u16` listening port (0
+/// if the node isn't listening).
+const NEGOTIATION_MSG_LEN: usize = 30;
+
+/// The protocol version and capabilities advertised by a peer during version negotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    /// The protocol version of the peer.
+    pub version: u32,
+    /// The capabilities of the peer, as a bitset.
+    pub capabilities: u64,
+    /// The lowest protocol version supported by the peer.
+    pub min_version: u32,
+    /// The highest protocol version supported by the peer.
+    pub max_version: u32,
+    /// The maximum size of a single frame the peer accepts; larger messages are not sent to it.
+    pub max_frame_size: u32,
+    /// The depth of the peer's per-connection inbound queue; the outbound queue of the connection with it is no
+    /// deeper than that.
+    pub inbound_queue_depth: u32,
+    /// The port the peer is listening on, if it is; for inbound connections, it reveals the peer's listening
+    /// address, which its ephemeral one is registered as an alias of (see `KnownPeers::register_alias`).
+    pub listening_port: Option<u16>,
+}
+
+impl PeerCapabilities {
+    /// Checks whether the peer has all the capabilities indicated by the given bitset.
+    pub fn supports(&self, capabilities: u64) -> bool {
+        self.capabilities & capabilities == capabilities
+    }
+}
+
+/// The size of the header of a rehandshake payload: a byte indicating whether it's a reply, and a version negotiation
+/// message; it's followed by the handshake payload (see `Handshaking::rehandshake_payload`).
+const REHANDSHAKE_HEADER_LEN: usize = 1 + NEGOTIATION_MSG_LEN;
+
+/// Exchanges the protocol versions and capabilities with the peer and checks if the versions are compatible.
+pub(crate) async fn negotiate_version(conn: &mut Connection) -> io::Result<PeerCapabilities> {
+    let node = conn.node.clone();
+
+    let own_msg = encode_capabilities(&node);
+    conn.writer().write_all(&own_msg).await?;
+
+    let mut peer_msg = [0u8; NEGOTIATION_MSG_LEN];
+    conn.reader().read_exact(&mut peer_msg).await?;
+    let peer_caps = decode_capabilities(&peer_msg);
+
+    if is_compatible(&node, &peer_caps) {
+        debug!(target: HANDSHAKE, parent: conn.span(), "negotiated {:?} with {}", peer_caps, conn.addr);
+        Ok(peer_caps)
+    } else {
+        error!(
+            target: HANDSHAKE, parent: conn.span(),
+            "{} uses an unsupported protocol version ({})", conn.addr, peer_caps.version
+        );
+        Err(io::ErrorKind::InvalidData.into())
+    }
+}
+
+/// Creates the payload of a rehandshake message, containing the given handshake payload.
+pub(crate) fn encode_rehandshake(node: &Node, is_reply: bool, handshake_payload: &[u8]) -> Bytes {
+    let mut payload = Vec::with_capacity(REHANDSHAKE_HEADER_LEN + handshake_payload.len());
+    payload.push(is_reply as u8);
+    payload.extend_from_slice(&encode_capabilities(node));
+    payload.extend_from_slice(handshake_payload);
+
+    payload.into()
+}
+
+/// Decodes the payload of a rehandshake message; returns the peer's capabilities, an indication of whether it's a
+/// reply, and the handshake payload.
+pub(crate) fn decode_rehandshake(payload: Bytes) -> io::Result<(PeerCapabilities, bool, Bytes)> {
+    if payload.len() < REHANDSHAKE_HEADER_LEN || payload[0] > 1 {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    // safe; the length was checked above
+    let peer_caps = decode_capabilities(payload[1..REHANDSHAKE_HEADER_LEN].try_into().unwrap());
+
+    Ok((
+        peer_caps,
+        payload[0] == 1,
+        payload.slice(REHANDSHAKE_HEADER_LEN..),
+    ))
+}
+
+/// Checks whether the node's and the peer's protocol versions are compatible, i.e. whether the peer's version is
+/// supported by the node, or the node's version is supported by the peer.
+fn is_compatible(node: &Node, peer_caps: &PeerCapabilities) -> bool {
+    let (version, _) = node.advertised_version();
+
+    is_supported(node, peer_caps)
+        || (peer_caps.min_version..=peer_caps.max_version).contains(&version)
+}
+
+/// Checks whether the peer's protocol version is supported by the node.
+pub(crate) fn is_supported(node: &Node, peer_caps: &PeerCapabilities) -> bool {
+    node.config()
+        .supported_version_range
+        .as_ref()
+        .map(|versions| versions.contains(&peer_caps.version))
+        .unwrap_or(false)
+}
+
+/// Creates the version negotiation message advertising the node's version and capabilities.
+fn encode_capabilities(node: &Node) -> [u8; NEGOTIATION_MSG_LEN] {
+    let config = node.config();
+    let (version, capabilities) = node.advertised_version();
+
+    let mut msg = [0u8; NEGOTIATION_MSG_LEN];
+    msg[..4].copy_from_slice(&version.to_le_bytes());
+    msg[4..12].copy_from_slice(&capabilities.to_le_bytes());
+    let (min_version, max_version) = config
+        .supported_version_range
+        .as_ref()
+        .map(|versions| (*versions.start(), *versions.end()))
+        .unwrap_or((version, version));
+    msg[12..16].copy_from_slice(&min_version.to_le_bytes());
+    msg[16..20].copy_from_slice(&max_version.to_le_bytes());
+    // the frames are read into the read buffer, so it determines their maximum size
+    msg[20..24].copy_from_slice(&saturating_u32(config.conn_read_buffer_size).to_le_bytes());
+    msg[24..28].copy_from_slice(&saturating_u32(config.conn_inbound_queue_depth).to_le_bytes());
+    let listening_port = node.listening_addr().map(|addr| addr.port()).unwrap_or(0);
+    msg[28..].copy_from_slice(&listening_port.to_le_bytes());
+
+    msg
+}
+
+/// Decodes the peer's version negotiation message.
+fn decode_capabilities(msg: &[u8; NEGOTIATION_MSG_LEN]) -> PeerCapabilities {
+    // safe; the slices have the exact lengths required by the conversions
+    PeerCapabilities {
+        version: u32::from_le_bytes(msg[..4].try_into().unwrap()),
+        capabilities: u64::from_le_bytes(msg[4..12].try_into().unwrap()),
+        min_version: u32::from_le_bytes(msg[12..16].try_into().unwrap()),
+        max_version: u32::from_le_bytes(msg[16..20].try_into().unwrap()),
+        max_frame_size: u32::from_le_bytes(msg[20..24].try_into().unwrap()),
+        inbound_queue_depth: u32::from_le_bytes(msg[24..28].try_into().unwrap()),
+        listening_port: Some(u16::from_le_bytes(msg[28..].try_into().unwrap()))
+            .filter(|port| *port != 0),
+    }
+}
+
+/// Reports the IP observed for the peer and returns the one it observes for the node; the IPs are exchanged in
+/// their IPv6 form, with IPv4 ones being IPv4-mapped.
+pub(crate) async fn exchange_observed_addrs(conn: &mut Connection) -> io::Result<IpAddr> {
+    let observed_ip = match conn.addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    conn.writer().write_all(&observed_ip.octets()).await?;
+
+    let mut peer_msg = [0u8; 16];
+    conn.reader().read_exact(&mut peer_msg).await?;
+    let reported_ip = Ipv6Addr::from(peer_msg);
+    let reported_ip = reported_ip
+        .to_ipv4_mapped()
+        .map(IpAddr::V4)
+        .unwrap_or(IpAddr::V6(reported_ip));
+
+    if reported_ip.is_unspecified() {
+        error!(target: HANDSHAKE, parent: conn.span(), "{} reported an unspecified address", conn.addr);
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    debug!(target: HANDSHAKE, parent: conn.span(), "{} observes the node at {}", conn.addr, reported_ip);
+
+    Ok(reported_ip)
+}
+
+/// Converts the given value to a `u32`, saturating at `u32::MAX`.
+fn saturating_u32(value: usize) -> u32 {
+    value.try_into().unwrap_or(u32::MAX)
+}
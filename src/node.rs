@@ -1,28 +1,75 @@
+#[cfg(feature = "compression")]
+use crate::compression::{exchange_dictionary_ids, Compression, SharedDictionaries};
+#[cfg(feature = "identity")]
+use crate::identity::{exchange_identities, PeerId, SignatureScheme};
+#[cfg(feature = "nat")]
+use crate::nat::{spawn_port_mapping_task, unmap_port};
+#[cfg(feature = "identity")]
+use crate::DuplicateIdentityPolicy;
+#[cfg(feature = "test-utils")]
+use crate::FailFastMode;
 use crate::{
-    connections::{Connection, ConnectionSide, Connections},
-    protocols::{ProtocolHandler, Protocols},
-    KnownPeers, NodeConfig, NodeStats,
+    bandwidth::Throttle,
+    buffer_pool::BufferPool,
+    capture::FrameTap,
+    conn_metrics::ConnectionMetrics,
+    connections::{
+        ConnectOptions, Connection, ConnectionContext, ConnectionFilter, ConnectionHandle,
+        ConnectionInfo, ConnectionMode, ConnectionSide, Connections, DialHandle, DialOutcome,
+        DialProgress, WriterState,
+    },
+    diagnostics::{DiagnosticsDump, FrameDirection, FrameSampler},
+    dns::DnsCache,
+    external_addr::AddrVotes,
+    handlers::Handlers,
+    incoming::{Incoming, IncomingReader, IncomingStreams},
+    negotiation::{exchange_observed_addrs, is_supported, negotiate_version},
+    partition::PartitionDetector,
+    protocols::{
+        decode_channel_payload, decode_pex, decode_pubsub, encode_channel_payload, encode_pubsub,
+        Channels, MessageKind, MultiplexingHandler, OutboundMessage, ProtocolHandler, Protocols,
+        PubSubHandler, PubSubKind, Reading, RehandshakeHandler, RehandshakeStep, Topics,
+    },
+    scheduler::{ScheduleHandle, Scheduler},
+    serving::ServedRequests,
+    status::{ConnectionCounts, NodeStatus, TrafficTotals},
+    supervision::{panic_message, CatchUnwind},
+    tracing_targets::{DISCOVERY, HANDSHAKE, MULTIPLEXING, NODE, PUBSUB},
+    Acks, AnnotatedEvent, ClosedInboundQueuePolicy, ConnectionIntent, ConnectionTimingStats,
+    DnsCacheStats, EgressPolicy, HandshakeMetadata, HandshakeStats, KnownPeers, MessageHandler,
+    NetworkEvent, NodeConfig, NodeEvent, NodeStats, PartitionSignal, PeerCapabilities, PeerStats,
+    Resolver, SeenCache, Sequences, SystemResolver,
 };
+#[cfg(feature = "test-utils")]
+use futures_core::future::BoxFuture;
 
-use bytes::Bytes;
-use fxhash::FxHashSet;
+use bytes::{Bytes, BytesMut};
+use fxhash::{FxHashMap, FxHashSet};
 use once_cell::sync::OnceCell;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
+use socket2::{SockRef, TcpKeepalive};
 use tokio::{
     net::{TcpListener, TcpStream},
-    sync::oneshot,
-    task::JoinHandle,
+    runtime::Handle,
+    sync::{broadcast, mpsc, oneshot, watch, Notify, Semaphore},
+    task::{JoinHandle, JoinSet},
+    time::{self, sleep, timeout},
 };
-use tracing::*;
+use tracing::{instrument::WithSubscriber, *};
 
 use std::{
+    any::{Any, TypeId},
+    fmt,
+    future::Future,
     io,
-    net::SocketAddr,
-    ops::Deref,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::{Deref, RangeInclusive},
+    panic::{self, AssertUnwindSafe},
     sync::{
         atomic::{AtomicUsize, Ordering::*},
         Arc,
     },
+    time::{Duration, Instant, SystemTime},
 };
 
 macro_rules! enable_protocol {
@@ -46,6 +93,15 @@ macro_rules! enable_protocol {
 // A seuential numeric identifier assigned to `Node`s that were not provided with a name.
 static SEQUENTIAL_NODE_ID: AtomicUsize = AtomicUsize::new(0);
 
+/// Processes the messages injected via `Node::inject_inbound` using the node's `Reading` implementation.
+#[cfg(feature = "test-utils")]
+pub(crate) type InboundInjector =
+    Arc<dyn Fn(SocketAddr, Bytes) -> BoxFuture<'static, io::Result<()>> + Send + Sync>;
+
+/// Serializes the messages of a single type sent via `Node::send_typed_message` using a `TypedWriting`
+/// implementation.
+pub(crate) type TypedSerializer<M> = Arc<dyn Fn(&M, &mut BytesMut) -> io::Result<()> + Send + Sync>;
+
 /// The central object responsible for handling all the connections.
 #[derive(Clone)]
 pub struct Node(Arc<InnerNode>);
@@ -58,66 +114,175 @@ impl Deref for Node {
     }
 }
 
-#[doc(hide)]
+#[doc(hidden)]
 pub struct InnerNode {
     /// The tracing span.
     span: Span,
     /// The node's configuration.
     config: NodeConfig,
-    /// The node's listening address.
-    listening_addr: SocketAddr,
+    /// The node's listening address; `None` if the node is outbound-only.
+    listening_addr: Option<SocketAddr>,
     /// Contains objects used by the protocols implemented by the node.
     protocols: Protocols,
     /// A list of connections that have not been finalized yet.
     connecting: Mutex<FxHashSet<SocketAddr>>,
+    /// The inbound connections that have been accepted, but not fully established yet.
+    pending_inbound: Mutex<FxHashSet<SocketAddr>>,
     /// Contains objects related to the node's active connections.
     connections: Connections,
     /// Collects statistics related to the node's peers.
     known_peers: KnownPeers,
     /// Collects statistics related to the node itself.
     stats: NodeStats,
+    /// The time the node was created at.
+    started: Instant,
+    /// Sends the messages scheduled for the future.
+    scheduler: Arc<Scheduler>,
+    /// Provides the per-connection buffers.
+    buffer_pool: Arc<BufferPool>,
+    /// The `TypedSerializer`s registered via `TypedWriting::enable_typed_writing`, keyed by their message types;
+    /// they're removed on shutdown, as they hold references to the node.
+    typed_serializers: RwLock<FxHashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+    /// The buffer the typed messages are serialized into; its allocation is reused across messages.
+    serialization_buffer: Mutex<BytesMut>,
+    /// Keeps track of application-level acks.
+    acks: Acks,
+    /// Keeps track of the sequenced messages.
+    sequences: Sequences,
+    /// The internal errors recorded in fail-fast mode.
+    #[cfg(feature = "test-utils")]
+    failures: Mutex<Vec<String>>,
+    /// Processes the messages injected via `Node::inject_inbound`, once the `Reading` protocol is enabled; it's
+    /// removed on shutdown, as it holds a reference to the node.
+    #[cfg(feature = "test-utils")]
+    inbound_injector: Mutex<Option<InboundInjector>>,
+    /// The outbound messages recorded since `Node::record_outbound` was called, if it was.
+    #[cfg(feature = "test-utils")]
+    outbound_log: Mutex<Option<Vec<(SocketAddr, Bytes)>>>,
+    /// Collects the connection establishment timings.
+    conn_metrics: ConnectionMetrics,
+    /// Records samples of the exchanged frames, if frame sampling is enabled.
+    frame_sampler: Option<FrameSampler>,
+    /// The hook receiving the raw inbound frames.
+    inbound_tap: OnceCell<FrameTap>,
+    /// The hook receiving the raw outbound frames.
+    outbound_tap: OnceCell<FrameTap>,
+    /// The signature scheme used to sign and verify messages, if message signing is enabled.
+    #[cfg(feature = "identity")]
+    signature_scheme: OnceCell<Arc<dyn SignatureScheme>>,
+    /// The identities of the peers whose inbound connections are being set up, reserved so that concurrent
+    /// handshakes with the same identity are subject to the `NodeConfig.duplicate_identity_policy`.
+    #[cfg(feature = "identity")]
+    pending_identities: Mutex<FxHashSet<PeerId>>,
+    /// The policy consulted before outbound messages are queued.
+    egress_policy: OnceCell<Arc<dyn EgressPolicy>>,
+    /// The compression settings, with the dictionaries digested up front.
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    /// The filter consulted before dialing a connection.
+    dial_filter: OnceCell<ConnectionFilter>,
+    /// The filter consulted before accepting a connection.
+    accept_filter: OnceCell<ConnectionFilter>,
+    /// The resolver used by `Node::resolve`, if it's not the default one.
+    resolver: OnceCell<Arc<dyn Resolver>>,
+    /// Caches the names resolved via `Node::resolve`.
+    dns_cache: DnsCache,
+    /// Remembers the broadcast and relayed messages, suppressing their echoes.
+    seen_messages: SeenCache,
+    /// The addresses learned via peer exchange that haven't been dialed yet, along with the IPs of their sources.
+    unverified_addrs: Mutex<FxHashMap<SocketAddr, IpAddr>>,
+    /// Serializes the checks against `NodeConfig.group_limits` with the connections and group members they admit.
+    group_admission: Mutex<()>,
+    /// Paces the writes to all the connections, if `NodeConfig.max_upload_rate` is set.
+    upload_throttle: Option<Throttle>,
+    /// Indicates whether the node is paused.
+    paused: watch::Sender<bool>,
+    /// Broadcasts the events emitted by the node.
+    events: broadcast::Sender<NodeEvent>,
+    /// Broadcasts the events emitted by the node, along with the metadata of their connections.
+    annotated_events: broadcast::Sender<AnnotatedEvent>,
+    /// Broadcasts the events concerning the node's connectivity as a whole.
+    network_events: broadcast::Sender<NetworkEvent>,
     /// The node's listening task.
-    listening_task: OnceCell<JoinHandle<()>>,
+    listening_task: Mutex<Option<JoinHandle<()>>>,
+    /// The task dialing `NodeConfig.bootstrap_peers`.
+    bootstrap_task: Mutex<Option<JoinHandle<()>>>,
+    /// Notified when the node loses all of its connections.
+    all_disconnected: Notify,
+    /// The ID to be assigned to the next connection.
+    next_conn_id: AtomicUsize,
+    /// Tallies the IPs that the node's peers observe for it.
+    addr_votes: AddrVotes,
+    /// The requests served to the peers within the current accounting window.
+    served_requests: ServedRequests,
+    /// Watches for the symptoms of a likely network partition.
+    partition_detector: PartitionDetector,
+    /// The protocol version and capabilities advertised during version negotiation.
+    advertised_version: RwLock<(u32, u64)>,
+    /// The pending rehandshakes initiated by the node.
+    pending_rehandshakes:
+        Mutex<FxHashMap<SocketAddr, oneshot::Sender<io::Result<PeerCapabilities>>>>,
+    /// The topic subscriptions of the node and its peers.
+    topics: Topics,
+    /// The local readers of the channels used by the `Multiplexing` protocol.
+    channels: Channels,
+    /// The handlers of the inbound messages, keyed by their types.
+    handlers: Handlers,
+    /// The streams of inbound messages returned by `Node::{incoming, incoming_from}`.
+    incoming: IncomingStreams,
+    /// Indicates whether the built-in reader feeding the `Incoming` streams is enabled.
+    incoming_reader: OnceCell<()>,
+    /// The node's external address, if its listening port is mapped on the gateway.
+    #[cfg(feature = "nat")]
+    mapped_addr: RwLock<Option<SocketAddr>>,
+    /// The task responsible for the port mapping.
+    #[cfg(feature = "nat")]
+    port_mapping_task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl Node {
     /// Creates a new `Node` optionally using a given `NodeConfig`.
     pub async fn new(config: Option<NodeConfig>) -> io::Result<Self> {
         let mut config = config.unwrap_or_default();
+        config.validate()?;
 
         // if there is no pre-configured name, assign a sequential numeric identifier
         if config.name.is_none() {
             config.name = Some(SEQUENTIAL_NODE_ID.fetch_add(1, SeqCst).to_string());
         }
 
-        // create a tracing span containing the node's name
-        let span = create_span(config.name.as_deref().unwrap());
+        // create a tracing span containing the node's name; the address is recorded once it's known
+        let span = with_dispatch(&config, || create_span(config.name.as_deref().unwrap()));
 
-        // procure a listening address
-        let listener_ip = config.listener_ip;
-        let listener = if let Some(port) = config.desired_listening_port {
-            let desired_listening_addr = SocketAddr::new(listener_ip, port);
-            match TcpListener::bind(desired_listening_addr).await {
-                Ok(listener) => listener,
-                Err(e) => {
-                    if config.allow_random_port {
-                        warn!(parent: span.clone(), "trying any port, the desired one is unavailable: {}", e);
-                        let random_available_addr = SocketAddr::new(listener_ip, 0);
-                        TcpListener::bind(random_available_addr).await?
-                    } else {
-                        error!(parent: span.clone(), "the desired port is unavailable: {}", e);
-                        return Err(e);
-                    }
-                }
-            }
-        } else if config.allow_random_port {
-            let random_available_addr = SocketAddr::new(listener_ip, 0);
-            TcpListener::bind(random_available_addr).await?
+        // procure a listening address, unless the node is outbound-only
+        let listener = if config.no_listener {
+            None
         } else {
-            panic!("you must either provide a desired port or allow a random port to be chosen");
+            Some(bind_listener(&config, &span).await?)
         };
 
-        let listening_addr = listener.local_addr()?;
+        let listening_addr = listener
+            .as_ref()
+            .map(|listener| listener.local_addr())
+            .transpose()?;
+        if let Some(addr) = listening_addr {
+            span.record("addr", field::display(addr));
+        }
+
+        let advertised_version = (config.protocol_version, config.capabilities);
+        let buffer_pool = BufferPool::new(config.max_connections as usize);
+        let events = broadcast::channel(config.event_queue_depth.max(1)).0;
+        let annotated_events = broadcast::channel(config.event_queue_depth.max(1)).0;
+        let network_events = broadcast::channel(config.event_queue_depth.max(1)).0;
+        let frame_sampler = config.frame_sampling.clone().map(FrameSampler::new);
+        let upload_throttle = config.max_upload_rate.map(Throttle::new);
+        let seen_messages = SeenCache::new(
+            config.seen_cache_capacity,
+            Duration::from_secs(config.seen_cache_ttl_secs),
+        );
+        let sequences = Sequences::new(config.retransmit_buffer_len);
+        #[cfg(feature = "compression")]
+        let compression = config.compression.as_ref().map(Compression::new);
 
         let node = Node(Arc::new(InnerNode {
             span,
@@ -125,45 +290,411 @@ impl Node {
             listening_addr,
             protocols: Default::default(),
             connecting: Default::default(),
+            pending_inbound: Default::default(),
             connections: Default::default(),
             known_peers: Default::default(),
             stats: Default::default(),
+            started: Instant::now(),
+            scheduler: Default::default(),
+            buffer_pool,
+            typed_serializers: Default::default(),
+            serialization_buffer: Default::default(),
+            acks: Default::default(),
+            sequences,
+            #[cfg(feature = "test-utils")]
+            failures: Default::default(),
+            #[cfg(feature = "test-utils")]
+            inbound_injector: Default::default(),
+            #[cfg(feature = "test-utils")]
+            outbound_log: Default::default(),
+            conn_metrics: Default::default(),
+            frame_sampler,
+            inbound_tap: Default::default(),
+            outbound_tap: Default::default(),
+            #[cfg(feature = "identity")]
+            signature_scheme: Default::default(),
+            #[cfg(feature = "identity")]
+            pending_identities: Default::default(),
+            egress_policy: Default::default(),
+            #[cfg(feature = "compression")]
+            compression,
+            dial_filter: Default::default(),
+            accept_filter: Default::default(),
+            resolver: Default::default(),
+            dns_cache: Default::default(),
+            seen_messages,
+            unverified_addrs: Default::default(),
+            group_admission: Default::default(),
+            upload_throttle,
+            paused: watch::channel(false).0,
+            events,
+            annotated_events,
+            network_events,
             listening_task: Default::default(),
+            bootstrap_task: Default::default(),
+            all_disconnected: Default::default(),
+            next_conn_id: Default::default(),
+            addr_votes: Default::default(),
+            served_requests: Default::default(),
+            partition_detector: Default::default(),
+            advertised_version: RwLock::new(advertised_version),
+            pending_rehandshakes: Default::default(),
+            topics: Default::default(),
+            channels: Default::default(),
+            handlers: Default::default(),
+            incoming: Default::default(),
+            incoming_reader: Default::default(),
+            #[cfg(feature = "nat")]
+            mapped_addr: Default::default(),
+            #[cfg(feature = "nat")]
+            port_mapping_task: Default::default(),
         }));
 
-        let node_clone = node.clone();
-        let listening_task = tokio::spawn(async move {
-            trace!(parent: node_clone.span(), "spawned the listening task");
+        #[cfg(feature = "nat")]
+        if let (true, Some(addr)) = (node.config.nat_port_mapping, listening_addr) {
+            *node.port_mapping_task.lock() = Some(spawn_port_mapping_task(&node, addr.port()));
+        }
+
+        match (listener, listening_addr) {
+            (Some(listener), Some(listening_addr)) => {
+                if node.config.listen_on_start {
+                    node.spawn_listening_task(listener);
+                    debug!(target: NODE, parent: node.span(), "the node is ready; listening on {}", listening_addr);
+                } else {
+                    // the listener was only used to procure the listening address
+                    drop(listener);
+                    debug!(target: NODE, parent: node.span(), "the node is ready; it will listen on {}", listening_addr);
+                }
+            }
+            _ => {
+                debug!(target: NODE, parent: node.span(), "the node is ready; it is outbound-only");
+            }
+        }
+
+        if node.config.bootstrap_on_start {
+            node.start_bootstrapping();
+        }
+
+        Ok(node)
+    }
+
+    /// Starts dialing `NodeConfig.{bootstrap_peers, bootstrap_hosts}` (and, with the `bootstrap` feature, the
+    /// addresses from `NodeConfig.seed_lists`) in the background, re-dialing them whenever the node loses all of its
+    /// connections; it should be called once the node's protocols are enabled, unless
+    /// `NodeConfig.bootstrap_on_start` is set. Returns `false` if there is nothing to bootstrap from, or if
+    /// bootstrapping was already started.
+    pub fn start_bootstrapping(&self) -> bool {
+        let mut bootstrap_task = self.bootstrap_task.lock();
+        if !self.has_bootstrap_sources() || bootstrap_task.is_some() {
+            return false;
+        }
+
+        let node = self.clone();
+        *bootstrap_task = Some(self.spawn_task(format_args!("bootstrap"), async move {
+            trace!(target: DISCOVERY, parent: node.span(), "spawned the bootstrapping task");
+            let redial_delay = Duration::from_millis(node.config.bootstrap_redial_delay_ms);
+
             loop {
-                match listener.accept().await {
-                    Ok((stream, addr)) => {
-                        debug!(parent: node_clone.span(), "tentatively accepted a connection from {}", addr);
+                let num_connected = node.dial_bootstrap_peers().await;
+                debug!(target: DISCOVERY, parent: node.span(), "connected to {} bootstrap peer(s)", num_connected);
+                node.emit_network(NetworkEvent::BootstrapCompleted(num_connected));
 
-                        if !node_clone.can_add_connection() {
-                            debug!(parent: node_clone.span(), "rejecting the connection from {}", addr);
-                            continue;
-                        }
+                // wait until the node is left without any connections
+                while node.num_connected() != 0 {
+                    node.all_disconnected.notified().await;
+                }
+                debug!(target: DISCOVERY, parent: node.span(), "lost all the connections; re-dialing the bootstrap peers");
+                sleep(redial_delay).await;
+            }
+        }));
 
-                        if let Err(e) = node_clone
-                            .adapt_stream(stream, addr, ConnectionSide::Responder)
-                            .await
-                        {
-                            node_clone.known_peers().register_failure(addr);
-                            error!(parent: node_clone.span(), "couldn't accept a connection: {}", e);
+        true
+    }
+
+    /// Checks whether any bootstrap peers, hosts or seed lists are configured.
+    fn has_bootstrap_sources(&self) -> bool {
+        #[cfg(feature = "bootstrap")]
+        if !self.config.seed_lists.is_empty() {
+            return true;
+        }
+
+        !self.config.bootstrap_peers.is_empty() || !self.config.bootstrap_hosts.is_empty()
+    }
+
+    /// Dials the bootstrap peers and hosts (along with the addresses from the seed lists) that the node isn't
+    /// connected to concurrently, resolving the hosts and fetching the seed lists anew; returns the number of the
+    /// ones it's connected to afterwards.
+    async fn dial_bootstrap_peers(&self) -> usize {
+        let options = ConnectOptions {
+            retry_policy: Some(self.config.connect_retry_policy.clone().unwrap_or_default()),
+            ..Default::default()
+        };
+
+        let mut attempts = JoinSet::new();
+        for &addr in &self.config.bootstrap_peers {
+            if self.is_connected(addr) {
+                continue;
+            }
+            let node = self.clone();
+            let options = options.clone();
+            self.spawn_task_in_set(&mut attempts, format_args!("dial:{}", addr), async move {
+                if let Err(e) = node.connect_with_options(addr, options).await {
+                    warn!(target: DISCOVERY, parent: node.span(), "couldn't connect to bootstrap peer {}: {}", addr, e);
+                }
+            });
+        }
+        // the hosts and the seed-list addresses connected to
+        let num_others_connected = Arc::new(AtomicUsize::new(0));
+        for (name, port) in &self.config.bootstrap_hosts {
+            let node = self.clone();
+            let options = options.clone();
+            let (host, port) = (name.clone(), *port);
+            let num_others_connected = Arc::clone(&num_others_connected);
+            self.spawn_task_in_set(&mut attempts, format_args!("dial:{}", name), async move {
+                match node.connect_dns_with_options(&host, port, options).await {
+                    Ok(_) => {
+                        num_others_connected.fetch_add(1, Relaxed);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                        num_others_connected.fetch_add(1, Relaxed);
+                    }
+                    Err(e) => {
+                        warn!(target: DISCOVERY, parent: node.span(), "couldn't connect to bootstrap host {}: {}", host, e);
+                    }
+                }
+            });
+        }
+        #[cfg(feature = "bootstrap")]
+        if !self.config.seed_lists.is_empty() {
+            let node = self.clone();
+            let num_others_connected = Arc::clone(&num_others_connected);
+            self.spawn_task_in_set(&mut attempts, format_args!("dial:seeds"), async move {
+                let seeds = match node.fetch_seed_lists().await {
+                    Ok(seeds) => seeds,
+                    Err(e) => {
+                        warn!(target: DISCOVERY, parent: node.span(), "couldn't fetch any seed list: {}", e);
+                        return;
+                    }
+                };
+                for &addr in &seeds {
+                    node.known_peers().add(addr);
+                }
+                let seeds = seeds
+                    .into_iter()
+                    .filter(|addr| !node.is_connected(*addr))
+                    .collect::<Vec<_>>();
+                let num_connected = node
+                    .connect_many(&seeds)
+                    .await
+                    .into_iter()
+                    .filter(|(_, result)| result.is_ok())
+                    .count();
+                num_others_connected.fetch_add(num_connected, Relaxed);
+            });
+        }
+        while attempts.join_next().await.is_some() {}
+
+        self.config
+            .bootstrap_peers
+            .iter()
+            .filter(|addr| self.is_connected(**addr))
+            .count()
+            + num_others_connected.load(Relaxed)
+    }
+
+    /// Checks whether an inbound connection from the given address can be accepted, as per the connection limits,
+    /// the address family policy, the subnet limits, and the accept filter.
+    fn admits_inbound(&self, addr: SocketAddr) -> bool {
+        if !self.can_add_connection() {
+            debug!(target: NODE, parent: self.span(), "rejecting the connection from {}", addr);
+            return false;
+        }
+
+        if !self.config.addr_family_policy.allows(addr) {
+            debug!(target: NODE, parent: self.span(), "rejecting the connection from {}; its address family is not allowed", addr);
+            return false;
+        }
+
+        if !self.passes_filter(self.accept_filter.get(), addr, ConnectionSide::Responder) {
+            debug!(target: NODE, parent: self.span(), "rejecting the connection from {}; the accept filter vetoed it", addr);
+            return false;
+        }
+
+        true
+    }
+
+    /// Registers a pending inbound connection, as long as there are fewer than `NodeConfig.max_pending_inbound`
+    /// of them and it respects `NodeConfig.subnet_limits`; the returned guard unregisters it once the connection
+    /// is fully established or fails.
+    fn reserve_pending_inbound(&self, addr: SocketAddr) -> Option<PendingInboundGuard> {
+        // the limits are checked and the connection is registered atomically, so that parallel connections from
+        // a single subnet can't all pass them
+        let connecting = self.connecting.lock();
+        let mut pending_inbound = self.pending_inbound.lock();
+        if pending_inbound.len() >= self.config.max_pending_inbound as usize {
+            debug!(target: NODE, parent: self.span(), "rejecting the connection from {}; there are too many pending inbound connections", addr);
+            return None;
+        }
+        if !self.respects_subnet_limits(addr, &connecting, &pending_inbound) {
+            debug!(target: NODE, parent: self.span(), "rejecting the connection from {}; its subnet limits are reached", addr);
+            return None;
+        }
+        pending_inbound.insert(addr);
+
+        Some(PendingInboundGuard {
+            node: self.clone(),
+            addr,
+        })
+    }
+
+    /// Returns the number of inbound connections that have been accepted, but aren't fully established yet, e.g.
+    /// because they are still handshaking.
+    pub fn num_pending_inbound(&self) -> usize {
+        self.pending_inbound.lock().len()
+    }
+
+    /// Spawns the task accepting inbound connections using the given listener.
+    fn spawn_listening_task(&self, listener: TcpListener) {
+        let node_clone = self.clone();
+        let listening_task = self.spawn_task(format_args!("listener"), async move {
+            trace!(target: NODE, parent: node_clone.span(), "spawned the listening task");
+
+            // the backlog is drained in limited bursts, so that a flood of connection attempts can't starve the rest
+            let config = node_clone.config();
+            let tick = Duration::from_millis(config.accept_tick_ms);
+            let mut tick_start = time::Instant::now();
+            let mut accepted_in_tick = 0;
+
+            loop {
+                if accepted_in_tick >= config.max_accepts_per_tick {
+                    trace!(target: NODE, parent: node_clone.span(), "accepted {} connections in a tick; pausing", accepted_in_tick);
+                    time::sleep_until(tick_start + tick).await;
+                }
+                if tick_start.elapsed() >= tick {
+                    tick_start = time::Instant::now();
+                    accepted_in_tick = 0;
+                }
+
+                match listener.accept().await {
+                    Ok((stream, addr)) => {
+                        accepted_in_tick += 1;
+                        debug!(target: NODE, parent: node_clone.span(), "tentatively accepted a connection from {}", addr);
+
+                        // the accept filter is user code; a panic in it must not bring the listener down
+                        match panic::catch_unwind(AssertUnwindSafe(|| node_clone.admits_inbound(addr))) {
+                            Ok(true) => {}
+                            Ok(false) => continue,
+                            Err(payload) => {
+                                node_clone.handle_task_panic(addr, panic_message(&*payload));
+                                continue;
+                            }
                         }
+                        let pending = if let Some(pending) = node_clone.reserve_pending_inbound(addr) {
+                            pending
+                        } else {
+                            continue;
+                        };
+
+                        // adapt the stream in a dedicated task, so that pending handshakes don't block the listener
+                        let node_clone = node_clone.clone();
+                        node_clone.clone().spawn_supervised_task(format_args!("accept:{}", addr), addr, async move {
+                            let _pending = pending;
+                            if let Err(e) = node_clone
+                                .adapt_stream(stream, addr, ConnectionSide::Responder, None)
+                                .await
+                            {
+                                node_clone.known_peers().register_failure(addr);
+                                error!(target: NODE, parent: node_clone.span(), "couldn't accept a connection: {}", e);
+                            }
+                        });
                     }
                     Err(e) => {
-                        error!(parent: node_clone.span(), "couldn't accept a connection: {}", e);
+                        error!(target: NODE, parent: node_clone.span(), "couldn't accept a connection: {}", e);
+                        node_clone.fail_fast(format_args!("the listener failed: {}", e));
                     }
                 }
             }
         });
 
-        node.listening_task.set(listening_task).unwrap();
+        *self.listening_task.lock() = Some(listening_task);
+    }
+
+    /// Starts accepting inbound connections at the node's listening address; it is only needed if the node was
+    /// created with `NodeConfig.listen_on_start` disabled, or if listening was stopped with `Node::stop_listening`.
+    /// Outbound-only nodes (see `NodeConfig.no_listener`) can't listen.
+    pub async fn start_listening(&self) -> io::Result<()> {
+        let listening_addr = if let Some(addr) = self.listening_addr {
+            addr
+        } else {
+            error!(target: NODE, parent: self.span(), "can't listen; the node is outbound-only");
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        };
 
-        debug!(parent: node.span(), "the node is ready; listening on {}", listening_addr);
+        if self.is_listening() {
+            warn!(target: NODE, parent: self.span(), "already listening on {}", listening_addr);
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
 
-        Ok(node)
+        let listener = match on_runtime(&self.config, TcpListener::bind(listening_addr)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(target: NODE, parent: self.span(), "couldn't listen on {}: {}", listening_addr, e);
+                return Err(e);
+            }
+        };
+        self.spawn_listening_task(listener);
+        debug!(target: NODE, parent: self.span(), "listening on {}", listening_addr);
+
+        Ok(())
+    }
+
+    /// Stops accepting inbound connections, while the existing connections remain intact; returns `false` if the
+    /// node wasn't listening.
+    pub async fn stop_listening(&self) -> bool {
+        let listening_task = self.listening_task.lock().take();
+
+        if let Some(listening_task) = listening_task {
+            listening_task.abort();
+            // wait until the listener is dropped, so that the address can be reused immediately
+            let _ = listening_task.await;
+            debug!(target: NODE, parent: self.span(), "stopped listening");
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks whether the node is accepting inbound connections.
+    pub fn is_listening(&self) -> bool {
+        self.listening_task.lock().is_some()
+    }
+
+    /// Returns the node's external address, if it's known: the one mapped on the gateway (if the `nat` feature
+    /// and `NodeConfig.nat_port_mapping` are enabled), or the node's listening port at the IP that the majority of
+    /// its peers observe for it (if `NodeConfig.exchange_observed_addrs` is enabled), provided there are at least
+    /// `NodeConfig.min_external_addr_votes` votes for it.
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        #[cfg(feature = "nat")]
+        if let Some(addr) = self.mapped_addr() {
+            return Some(addr);
+        }
+
+        let ip = self
+            .addr_votes
+            .winner(self.config.min_external_addr_votes)?;
+        Some(SocketAddr::new(ip, self.listening_addr()?.port()))
+    }
+
+    /// Returns the node's external address mapped on the gateway.
+    #[cfg(feature = "nat")]
+    pub(crate) fn mapped_addr(&self) -> Option<SocketAddr> {
+        *self.mapped_addr.read()
+    }
+
+    /// Sets the node's external address mapped on the gateway.
+    #[cfg(feature = "nat")]
+    pub(crate) fn set_mapped_addr(&self, addr: Option<SocketAddr>) {
+        *self.mapped_addr.write() = addr;
     }
 
     /// Returns the name assigned to the node.
@@ -182,16 +713,149 @@ impl Node {
         &self.stats
     }
 
+    /// Subscribes to the events emitted by the node from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Subscribes to the events emitted by the node from now on, annotated with the application-attached metadata
+    /// of the connections they concern: the peer's tags, and the `ConnectionIntent` and `HandshakeMetadata`
+    /// attached to the connection.
+    pub fn subscribe_annotated_events(&self) -> broadcast::Receiver<AnnotatedEvent> {
+        self.annotated_events.subscribe()
+    }
+
+    /// Subscribes to the events concerning the node's connectivity as a whole, emitted from now on.
+    pub fn subscribe_network_events(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.network_events.subscribe()
+    }
+
+    /// Emits the given event to all the subscribers.
+    pub(crate) fn emit(&self, event: NodeEvent) {
+        // only look the metadata up if there's anyone interested in it
+        if self.annotated_events.receiver_count() != 0 {
+            let addr = event.addr();
+            let annotated = AnnotatedEvent {
+                tags: self.peer_tags(addr),
+                intent: self
+                    .connection_ext::<ConnectionIntent>(addr)
+                    .map(|intent| intent.0),
+                handshake_metadata: self
+                    .connection_ext::<HandshakeMetadata>(addr)
+                    .map(|metadata| metadata.0)
+                    .unwrap_or_default(),
+                event: event.clone(),
+            };
+            let _ = self.annotated_events.send(annotated);
+        }
+
+        // an error only means that there are no subscribers
+        let _ = self.events.send(event);
+    }
+
+    /// Emits the given network event to all its subscribers.
+    pub(crate) fn emit_network(&self, event: NetworkEvent) {
+        // an error only means that there are no subscribers
+        let _ = self.network_events.send(event);
+    }
+
+    /// Emits the event concerning the connection with the given address, created from its address and ID, as long
+    /// as it is still connected.
+    pub(crate) fn emit_for(&self, addr: SocketAddr, event: fn(SocketAddr, usize) -> NodeEvent) {
+        if let Some(conn_id) = self.connections.id(addr) {
+            self.emit(event(addr, conn_id));
+        }
+    }
+
+    /// Spawns a task whose events are emitted within the node's span and sent to the node's `tracing` dispatcher,
+    /// if there is one; with the `tokio-console` feature (and the `tokio_unstable` cfg flag), the task is also
+    /// given the provided name.
+    pub(crate) fn spawn_task<F>(&self, name: fmt::Arguments<'_>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let future = future.instrument(self.span().clone());
+
+        let runtime = self.config.runtime.as_ref();
+        if let Some(dispatch) = &self.config.tracing_dispatch {
+            spawn_named(runtime, name, future.with_subscriber(dispatch.clone()))
+        } else {
+            spawn_named(runtime, name, future)
+        }
+    }
+
+    /// Spawns a task like `Node::spawn_task`, but supervised: if it panics (e.g. in the user's protocol code), the
+    /// panic is logged along with the address of the peer the task is handling, and the connection with the peer
+    /// is dropped, so that it doesn't linger without its task while the rest of the node keeps running.
+    pub(crate) fn spawn_supervised_task<F>(
+        &self,
+        name: fmt::Arguments<'_>,
+        addr: SocketAddr,
+        future: F,
+    ) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let node = self.clone();
+        self.spawn_task(name, async move {
+            if let Err(payload) = CatchUnwind::new(future).await {
+                node.handle_task_panic(addr, panic_message(&*payload));
+            }
+        })
+    }
+
+    /// Reports a panic that occurred while handling the given peer, and drops the connection with it, if any.
+    pub(crate) fn handle_task_panic(&self, addr: SocketAddr, msg: &str) {
+        error!(target: NODE, parent: self.span(), "a task handling {} panicked: {}", addr, msg);
+        self.known_peers.register_failure(addr);
+        if self.is_connected(addr) {
+            self.disconnect(addr);
+        }
+        self.fail_fast(format_args!("a task handling {} panicked: {}", addr, msg));
+    }
+
+    /// Spawns a task like `Node::spawn_task`, but as part of the given `JoinSet`.
+    pub(crate) fn spawn_task_in_set<F>(
+        &self,
+        set: &mut JoinSet<F::Output>,
+        name: fmt::Arguments<'_>,
+        future: F,
+    ) where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let future = future.instrument(self.span().clone());
+
+        let runtime = self.config.runtime.as_ref();
+        if let Some(dispatch) = &self.config.tracing_dispatch {
+            spawn_named_in_set(set, runtime, name, future.with_subscriber(dispatch.clone()));
+        } else {
+            spawn_named_in_set(set, runtime, name, future);
+        }
+    }
+
+    /// Returns the pool of the per-connection buffers.
+    pub(crate) fn buffer_pool(&self) -> &Arc<BufferPool> {
+        &self.buffer_pool
+    }
+
     /// Returns the tracing `Span` associated with the node.
     pub fn span(&self) -> &Span {
         &self.span
     }
 
-    /// Returns the node's listening address.
-    pub fn listening_addr(&self) -> SocketAddr {
+    /// Returns the node's listening address; it is retained even if the node is not listening, and it is `None` if
+    /// the node is outbound-only (see `NodeConfig.no_listener`).
+    pub fn listening_addr(&self) -> Option<SocketAddr> {
         self.listening_addr
     }
 
+    /// Returns a new ID to be assigned to a connection.
+    pub(crate) fn next_conn_id(&self) -> usize {
+        self.next_conn_id.fetch_add(1, Relaxed)
+    }
+
     async fn enable_protocols(&self, conn: Connection) -> io::Result<Connection> {
         let conn = enable_protocol!("HandshakeProtocol", handshake_handler, self, conn);
         let conn = enable_protocol!("ReadingProtocol", reading_handler, self, conn);
@@ -200,28 +864,193 @@ impl Node {
         Ok(conn)
     }
 
-    /// Prepares the freshly acquired connection to handle the protocols the Node implements.
+    /// Performs the built-in negotiation steps enabled in the `NodeConfig`: version negotiation, the exchange of
+    /// observed addresses, the exchange of identities, and the exchange of compression dictionary IDs.
+    async fn negotiate(&self, conn: &mut Connection) -> io::Result<()> {
+        if self.config.supported_version_range.is_some() {
+            conn.peer_capabilities = Some(negotiate_version(conn).await?);
+        }
+
+        if self.config.exchange_observed_addrs {
+            let observed_ip = exchange_observed_addrs(conn).await?;
+            self.addr_votes.register(conn.addr.ip(), observed_ip);
+        }
+
+        #[cfg(feature = "identity")]
+        if let Some(ref identity) = self.config.identity {
+            let peer_public_key = exchange_identities(conn, identity).await?;
+            let peer_id = PeerId::from_public_key(&peer_public_key);
+
+            conn.peer_id = Some(peer_id);
+            conn.peer_public_key = Some(peer_public_key);
+        }
+
+        #[cfg(feature = "compression")]
+        if let Some(ref compression) = self.config.compression {
+            let shared = exchange_dictionary_ids(conn, compression).await?;
+            conn.insert_ext(shared);
+        }
+
+        Ok(())
+    }
+
+    /// Applies the `NodeConfig.duplicate_identity_policy` to a connection with the given peer ID, in case it's
+    /// already connected via a different address or another connection with it is being set up; the identity is
+    /// reserved until the returned guard is dropped, which should happen once the connection is registered.
+    #[cfg(feature = "identity")]
+    fn reserve_identity(
+        &self,
+        conn: &Connection,
+        peer_id: PeerId,
+    ) -> io::Result<Option<PendingIdentityGuard>> {
+        if self.config.duplicate_identity_policy == DuplicateIdentityPolicy::AllowBoth {
+            return Ok(None);
+        }
+
+        // the lock is held throughout, so that concurrent handshakes can't both pass the check
+        let mut pending = self.pending_identities.lock();
+        if pending.contains(&peer_id) {
+            warn!(target: HANDSHAKE, parent: conn.span(), "rejecting {}, as another connection with its identity is being set up", conn.addr);
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        if let Some(existing_addr) = self.peer_addr(&peer_id).filter(|addr| *addr != conn.addr) {
+            if self.config.duplicate_identity_policy == DuplicateIdentityPolicy::RejectNew {
+                warn!(target: HANDSHAKE, parent: conn.span(), "rejecting {}, as its identity is already connected via {}", conn.addr, existing_addr);
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            debug!(target: HANDSHAKE, parent: conn.span(), "replacing the connection with {}, as {} uses the same identity", existing_addr, conn.addr);
+            self.disconnect(existing_addr);
+        }
+        pending.insert(peer_id);
+
+        Ok(Some(PendingIdentityGuard {
+            node: self.clone(),
+            peer_id,
+        }))
+    }
+
+    /// Applies the TCP socket options specified in the `NodeConfig` to the given stream.
+    fn apply_socket_options(&self, stream: &TcpStream) -> io::Result<()> {
+        let config = &self.config;
+        let socket = SockRef::from(stream);
+
+        if config.tcp_nodelay {
+            socket.set_tcp_nodelay(true)?;
+        }
+        if let Some(interval_ms) = config.tcp_keepalive_interval_ms {
+            let interval = Duration::from_millis(interval_ms);
+            let keepalive = TcpKeepalive::new().with_time(interval);
+            #[cfg(any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "ios",
+                target_os = "linux",
+                target_os = "macos",
+                target_os = "windows",
+            ))]
+            let keepalive = keepalive.with_interval(interval);
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(size) = config.tcp_send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = config.tcp_recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(linger_ms) = config.tcp_linger_ms {
+            socket.set_linger(Some(Duration::from_millis(linger_ms)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Prepares the freshly acquired connection to handle the protocols the Node implements, and emits the outcome
+    /// of its handshake.
     async fn adapt_stream(
         &self,
         stream: TcpStream,
         peer_addr: SocketAddr,
         own_side: ConnectionSide,
+        connect_time: Option<Duration>,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let conn_id = self.next_conn_id();
+        let ret = self
+            .set_up_connection(conn_id, stream, peer_addr, own_side, connect_time)
+            .await;
+        // the addresses of the failed inbound connections are ephemeral, so they aren't worth keeping track of
+        match (own_side, &ret) {
+            (ConnectionSide::Responder, Err(e)) => {
+                self.known_peers.register_inbound_handshake_failure(e)
+            }
+            _ => {
+                self.known_peers
+                    .register_handshake(peer_addr, start.elapsed(), ret.as_ref().err())
+            }
+        }
+
+        if ret.is_ok() {
+            self.emit(NodeEvent::HandshakeCompleted(peer_addr, conn_id));
+        } else {
+            self.emit(NodeEvent::HandshakeFailed(peer_addr, conn_id));
+        }
+
+        ret
+    }
+
+    /// Performs the negotiation and the handshake with the peer, and registers the resulting connection.
+    async fn set_up_connection(
+        &self,
+        conn_id: usize,
+        stream: TcpStream,
+        peer_addr: SocketAddr,
+        own_side: ConnectionSide,
+        connect_time: Option<Duration>,
     ) -> io::Result<()> {
+        self.apply_socket_options(&stream).map_err(|e| {
+            error!(target: NODE, parent: self.span(), "couldn't configure the connection with {}: {}", peer_addr, e);
+            e
+        })?;
+
+        // the record of an inbound connection's address is removed along with the connection
         self.known_peers.add(peer_addr);
 
         // register the port seen by the peer
         if let ConnectionSide::Initiator = own_side {
             if let Ok(addr) = stream.local_addr() {
                 debug!(
-                    parent: self.span(), "establishing connection with {}; the peer is connected on port {}",
+                    target: NODE, parent: self.span(), "establishing connection with {}; the peer is connected on port {}",
                     peer_addr, addr.port()
                 );
             } else {
-                warn!(parent: self.span(), "couldn't determine the peer's port");
+                warn!(target: NODE, parent: self.span(), "couldn't determine the peer's port");
+            }
+        }
+
+        let mut connection = Connection::new(conn_id, peer_addr, stream, !own_side, self);
+        connection.timings.connect = connect_time;
+
+        // perform the built-in negotiation steps, if applicable
+        let negotiation = timeout(
+            Duration::from_millis(self.config.max_handshake_time_ms),
+            self.negotiate(&mut connection),
+        );
+        match negotiation.await {
+            Ok(ret) => ret?,
+            Err(_) => {
+                error!(target: HANDSHAKE, parent: connection.span(), "negotiation with {} timed out", peer_addr);
+                return Err(io::ErrorKind::TimedOut.into());
             }
         }
 
-        let connection = Connection::new(peer_addr, stream, !own_side, self);
+        // only the connections initiated by the peer are subject to the duplicate identity policy
+        #[cfg(feature = "identity")]
+        let _identity_guard = match connection.peer_id {
+            Some(peer_id) if connection.side == ConnectionSide::Initiator => {
+                self.reserve_identity(&connection, peer_id)?
+            }
+            _ => None,
+        };
 
         // enact the enabled protocols
         let mut connection = self.enable_protocols(connection).await?;
@@ -231,100 +1060,2232 @@ impl Node {
         connection.reader = None;
         connection.writer = None;
 
-        self.connections.add(connection);
+        // the activity of a peer connecting from an ephemeral address counts towards its listening one; the alias
+        // is only registered once the handshake succeeds, so that failed connections don't leave records behind
+        let listening_port = connection
+            .peer_capabilities
+            .and_then(|caps| caps.listening_port);
+        if let (ConnectionSide::Initiator, Some(port)) = (connection.side, listening_port) {
+            let listening_addr = SocketAddr::new(peer_addr.ip(), port);
+            self.known_peers.register_alias(peer_addr, listening_addr);
+        }
+
+        // the peer's groups are known by now, including the ones of an inbound peer's listening address
+        let timings = {
+            let _guard = self.group_admission.lock();
+            if !self.respects_group_limits(peer_addr) {
+                error!(target: NODE, parent: self.span(), "can't connect with {}; its groups are full", peer_addr);
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            self.connections.add(connection)
+        };
+        self.conn_metrics
+            .register_established(self.known_peers.canonical_addr(peer_addr), &timings);
         self.known_peers.register_connection(peer_addr);
+        self.partition_detector.register_connection();
+
+        // inform the peer about the node's topic subscriptions
+        if self.protocols.pubsub_handler.get().is_some() {
+            for topic in self.topics.local_topics() {
+                let _ = self
+                    .send_pubsub(peer_addr, PubSubKind::Subscribe, &topic, &[])
+                    .await;
+            }
+        }
 
         Ok(())
     }
 
-    /// Connects to the provided `SocketAddr`.
+    /// Accepts a connection whose stream was obtained outside of the node, e.g. by an accept loop owned by the
+    /// application or an inetd-style supervisor; the stream is subject to the same checks as the ones accepted by
+    /// the node's listener (failing with `ConnectionRefused` if they don't pass), and goes through the usual
+    /// negotiation and handshake before the protocols take over. The node acts as the responder, and the `source`
+    /// is the peer's address; it works regardless of whether the node is listening.
+    pub async fn accept_stream(&self, stream: TcpStream, source: SocketAddr) -> io::Result<()> {
+        debug!(target: NODE, parent: self.span(), "tentatively accepted an injected connection from {}", source);
+
+        if self.is_connected(source) || self.connecting.lock().contains(&source) {
+            debug!(target: NODE, parent: self.span(), "rejecting the connection from {}; it's already connected", source);
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        if !self.admits_inbound(source) {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+        let _pending = self
+            .reserve_pending_inbound(source)
+            .ok_or(io::ErrorKind::ConnectionRefused)?;
+
+        self.adapt_stream(stream, source, ConnectionSide::Responder, None)
+            .await
+            .map_err(|e| {
+                self.known_peers().register_failure(source);
+                error!(target: NODE, parent: self.span(), "couldn't accept a connection: {}", e);
+                e
+            })
+    }
+
+    /// Connects to the provided `SocketAddr`; the attempt to establish the TCP connection is subject to
+    /// `NodeConfig.dial_timeout`, and failed attempts are retried according to `NodeConfig.connect_retry_policy`.
+    /// If the peer rejects the connection during the handshake (see `Handshaking::reject`), the resulting error is
+    /// of kind `ConnectionRefused` and carries the `Rejection`, and the attempt is not retried.
     pub async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
-        if addr == self.listening_addr()
-            || addr.ip().is_loopback() && addr.port() == self.listening_addr().port()
-        {
-            error!(parent: self.span(), "can't connect to node's own listening address ({})", addr);
-            return Err(io::ErrorKind::AddrInUse.into());
+        self.connect_with_options(addr, Default::default()).await
+    }
+
+    /// Connects to the provided `SocketAddr`, aborting the attempt to establish the TCP connection if it
+    /// takes longer than `dial_timeout`; failed attempts are retried according to `NodeConfig.connect_retry_policy`.
+    pub async fn initiate_connection_with_timeout(
+        &self,
+        addr: SocketAddr,
+        dial_timeout: Duration,
+    ) -> io::Result<()> {
+        let options = ConnectOptions {
+            dial_timeout: Some(dial_timeout),
+            ..Default::default()
+        };
+
+        self.connect_with_options(addr, options).await
+    }
+
+    /// Connects to the provided `SocketAddr` using the given `ConnectOptions`, which override the related settings
+    /// of the `NodeConfig`. Attempts that fail with one of the transient errors of the retry policy (e.g. a timed
+    /// out handshake) are retried with an exponential backoff, and the error of the last one is returned.
+    pub async fn connect_with_options(
+        &self,
+        addr: SocketAddr,
+        options: ConnectOptions,
+    ) -> io::Result<()> {
+        let dial_timeout = options.dial_timeout.unwrap_or(self.config.dial_timeout);
+        let retry_policy = options
+            .retry_policy
+            .as_ref()
+            .or(self.config.connect_retry_policy.as_ref());
+
+        let mut retries = 0;
+        loop {
+            let err = match self.try_connect(addr, dial_timeout).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            let policy = match retry_policy {
+                Some(policy) if retries < policy.max_retries && policy.is_transient(&err) => policy,
+                _ => return Err(err),
+            };
+            let backoff = policy.backoff(retries);
+            retries += 1;
+            debug!(target: NODE, parent: self.span(), "retrying the connection to {} in {:?} ({}/{})", addr, backoff, retries, policy.max_retries);
+            sleep(backoff).await;
+        }
+    }
+
+    /// Performs a single attempt to connect to the provided `SocketAddr`.
+    async fn try_connect(&self, addr: SocketAddr, dial_timeout: Duration) -> io::Result<()> {
+        // postpone the attempt while the node is paused
+        self.resumed().await;
+
+        if !self.config.addr_family_policy.allows(addr) {
+            error!(target: NODE, parent: self.span(), "can't connect to {}; its address family is not allowed", addr);
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        if !self.passes_filter(self.dial_filter.get(), addr, ConnectionSide::Initiator) {
+            error!(target: NODE, parent: self.span(), "can't connect to {}; the dial filter vetoed it", addr);
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        if let Some(listening_addr) = self.listening_addr() {
+            if addr == listening_addr
+                || addr.ip().is_loopback() && addr.port() == listening_addr.port()
+            {
+                error!(target: NODE, parent: self.span(), "can't connect to node's own listening address ({})", addr);
+                return Err(io::ErrorKind::AddrInUse.into());
+            }
         }
 
         if !self.can_add_connection() {
-            error!(parent: self.span(), "refusing to connect to {}", addr);
+            error!(target: NODE, parent: self.span(), "refusing to connect to {}", addr);
             return Err(io::ErrorKind::Other.into());
         }
 
         if self.connections.is_connected(addr) {
-            warn!(parent: self.span(), "already connected to {}", addr);
+            warn!(target: NODE, parent: self.span(), "already connected to {}", addr);
             return Err(io::ErrorKind::AlreadyExists.into());
         }
 
-        if !self.connecting.lock().insert(addr) {
-            warn!(parent: self.span(), "already connecting to {}", addr);
-            return Err(io::ErrorKind::AlreadyExists.into());
+        {
+            let mut connecting = self.connecting.lock();
+            if !self.respects_subnet_limits(addr, &connecting, &self.pending_inbound.lock()) {
+                error!(target: NODE, parent: self.span(), "can't connect to {}; its subnet limits are reached", addr);
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+
+            if !connecting.insert(addr) {
+                warn!(target: NODE, parent: self.span(), "already connecting to {}", addr);
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
         }
 
-        let stream = TcpStream::connect(addr).await.map_err(|e| {
-            self.connecting.lock().remove(&addr);
-            e
-        })?;
+        // ensures that the address is no longer considered pending, even if the attempt gets cancelled
+        let _guard = ConnectingGuard { node: self, addr };
+
+        let dial_start = Instant::now();
+        let stream = match timeout(
+            dial_timeout,
+            on_runtime(&self.config, TcpStream::connect(addr)),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => {
+                self.register_dial_failure();
+                self.settle_unverified_addr(addr, false);
+                return Err(e);
+            }
+            Err(_) => {
+                error!(target: NODE, parent: self.span(), "connecting to {} timed out", addr);
+                self.register_dial_failure();
+                self.settle_unverified_addr(addr, false);
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        };
 
         let ret = self
-            .adapt_stream(stream, addr, ConnectionSide::Initiator)
+            .adapt_stream(
+                stream,
+                addr,
+                ConnectionSide::Initiator,
+                Some(dial_start.elapsed()),
+            )
             .await;
 
         if let Err(ref e) = ret {
             self.known_peers().register_failure(addr);
-            error!(parent: self.span(), "couldn't initiate a connection with {}: {}", addr, e);
+            self.register_dial_failure();
+            error!(target: NODE, parent: self.span(), "couldn't initiate a connection with {}: {}", addr, e);
+        } else {
+            self.known_peers().register_dial(addr);
         }
-
-        self.connecting.lock().remove(&addr);
+        self.settle_unverified_addr(addr, ret.is_ok());
 
         ret
     }
 
-    /// Disconnects from the provided `SocketAddr`.
-    pub fn disconnect(&self, addr: SocketAddr) -> bool {
-        let disconnected = self.connections.remove(addr);
+    /// Settles the given address after dialing it, in case it was learned via peer exchange: it's forgotten if it
+    /// couldn't be connected to, and otherwise it's considered verified, i.e. seen.
+    fn settle_unverified_addr(&self, addr: SocketAddr, connected: bool) {
+        if self.unverified_addrs.lock().remove(&addr).is_none() {
+            return;
+        }
 
-        if disconnected {
-            info!(parent: self.span(), "disconnected from {}", addr);
+        if connected {
+            self.known_peers.register_seen(addr);
         } else {
-            warn!(parent: self.span(), "wasn't connected to {}", addr);
+            debug!(target: DISCOVERY, parent: self.span(), "forgetting {}; it was learned via peer exchange, but couldn't be connected to", addr);
+            self.known_peers.remove(addr);
+        }
+    }
+
+    /// Registers a failed outbound connection attempt with the partition detector, if it's enabled.
+    fn register_dial_failure(&self) {
+        if let Some(ref config) = self.config.partition_detection {
+            if let Some(signal) = self.partition_detector.register_dial_failure(config) {
+                self.signal_partition(signal);
+            }
+        }
+    }
+
+    /// Emits `NetworkEvent::PartitionSuspected` with the given signal.
+    fn signal_partition(&self, signal: PartitionSignal) {
+        warn!(target: NODE, parent: self.span(), "a network partition is suspected: {:?}", signal);
+        self.emit_network(NetworkEvent::PartitionSuspected(signal));
+    }
+
+    /// Checks whether a network partition (or a loss of local connectivity) is currently suspected; it is only
+    /// detected if `NodeConfig.partition_detection` is specified, and no longer suspected once a connection is
+    /// established.
+    pub fn is_partition_suspected(&self) -> bool {
+        self.partition_detector.is_suspected()
+    }
+
+    /// Starts connecting to the provided `SocketAddr` in the background; the returned `DialHandle` can be used
+    /// to await the outcome of the attempt or to cancel it while it's still in progress.
+    pub fn dial(&self, addr: SocketAddr) -> DialHandle {
+        let node = self.clone();
+        let task = self.spawn_task(format_args!("dial:{}", addr), async move {
+            node.connect(addr).await
+        });
+
+        DialHandle::new(addr, task)
+    }
+
+    /// Resolves the given host name (see `Node::resolve`) and connects to one of its addresses, returning the one
+    /// it connected to; the addresses are attempted in the order of priority used by `Node::connect_many`, either
+    /// one by one, or raced as per `NodeConfig.happy_eyeballs_delay_ms`. Since the resolved addresses are only
+    /// cached for the duration of their TTLs, calling it again (e.g. in order to reconnect) picks up the addresses
+    /// that have rotated in the meantime. Fails with an `io::ErrorKind::AlreadyExists` error if the node is already
+    /// connected to any of the addresses, and with the error of the last attempt if none of them succeeds.
+    pub async fn connect_dns(&self, name: &str, port: u16) -> io::Result<SocketAddr> {
+        self.connect_dns_with_options(name, port, Default::default())
+            .await
+    }
+
+    /// Connects to one of the addresses of the given host name like `Node::connect_dns`, using the given
+    /// `ConnectOptions` for every attempt.
+    async fn connect_dns_with_options(
+        &self,
+        name: &str,
+        port: u16,
+        options: ConnectOptions,
+    ) -> io::Result<SocketAddr> {
+        let addrs = self
+            .resolve(name, port)
+            .await?
+            .into_iter()
+            .filter(|addr| self.config.addr_family_policy.allows(*addr))
+            .collect::<Vec<_>>();
+        if let Some(addr) = addrs.iter().find(|addr| self.is_connected(**addr)) {
+            warn!(target: NODE, parent: self.span(), "already connected to {} ({})", name, addr);
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+
+        let mut pending = self
+            .dial_order(&addrs)
+            .into_iter()
+            .map(|idx| addrs[idx])
+            .collect::<Vec<_>>();
+        let stagger = self
+            .config
+            .happy_eyeballs_delay_ms
+            .map(Duration::from_millis);
+        if stagger.is_some() {
+            pending = interleave_families(pending);
+        }
+        let mut pending = pending.into_iter();
+
+        let mut attempts = JoinSet::new();
+        let mut last_error = None;
+        loop {
+            if let Some(addr) = pending.next() {
+                let node = self.clone();
+                let options = options.clone();
+                self.spawn_task_in_set(&mut attempts, format_args!("dial:{}", addr), async move {
+                    (addr, node.connect_with_options(addr, options).await)
+                });
+            }
+
+            // start the next attempt once the current ones fail, or once the stagger delay passes
+            let outcome = match stagger {
+                Some(delay) if pending.len() != 0 => {
+                    match timeout(delay, attempts.join_next()).await {
+                        Ok(outcome) => outcome,
+                        Err(_) => continue,
+                    }
+                }
+                _ => attempts.join_next().await,
+            };
+
+            match outcome {
+                Some(Ok((addr, Ok(())))) => {
+                    // the attempts still in progress are redundant; the ones that have just succeeded are undone
+                    attempts.abort_all();
+                    while let Some(outcome) = attempts.join_next().await {
+                        if let Ok((other_addr, Ok(()))) = outcome {
+                            self.disconnect(other_addr);
+                        }
+                    }
+                    debug!(target: NODE, parent: self.span(), "connected to {} via {}", name, addr);
+
+                    return Ok(addr);
+                }
+                Some(Ok((addr, Err(e)))) => {
+                    debug!(target: NODE, parent: self.span(), "couldn't connect to {} via {}: {}", name, addr, e);
+                    last_error = Some(e);
+                }
+                Some(Err(e)) => panic::resume_unwind(e.into_panic()),
+                None => break,
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::ErrorKind::AddrNotAvailable.into()))
+    }
+
+    /// Connects to the provided list of addresses concurrently, performing up to `NodeConfig.max_concurrent_dials`
+    /// connection attempts at the same time; returns the results of the attempts in the order of the addresses.
+    /// The attempts are started in the order of priority determined by `NodeConfig.addr_family_policy` and
+    /// `KnownPeers::dial_penalty`.
+    pub async fn connect_many(&self, addrs: &[SocketAddr]) -> Vec<(SocketAddr, io::Result<()>)> {
+        let limiter = Arc::new(Semaphore::new(self.config.max_concurrent_dials as usize));
+        let order = self.dial_order(addrs);
+
+        let mut attempts = (0..addrs.len()).map(|_| None).collect::<Vec<_>>();
+        for idx in order {
+            // safe; the semaphore is never closed
+            let permit = limiter.clone().acquire_owned().await.unwrap();
+            let node = self.clone();
+            let addr = addrs[idx];
+
+            attempts[idx] = Some(self.spawn_task(format_args!("dial:{}", addr), async move {
+                let _permit = permit;
+                node.connect(addr).await
+            }));
+        }
+
+        let mut results = Vec::with_capacity(addrs.len());
+        for (addr, attempt) in addrs.iter().zip(attempts.into_iter().flatten()) {
+            let result = match attempt.await {
+                Ok(result) => result,
+                Err(e) => panic::resume_unwind(e.into_panic()),
+            };
+            results.push((*addr, result));
+        }
+
+        results
+    }
+
+    /// Starts connecting to the provided list of addresses in the background, performing up to `concurrency`
+    /// connection attempts at the same time (in the order of priority used by `Node::connect_many`); the returned
+    /// `DialProgress` is a stream of the outcomes of the attempts, in the order in which they conclude.
+    pub fn dial_many(&self, addrs: &[SocketAddr], concurrency: usize) -> DialProgress {
+        let mut pending = self
+            .dial_order(addrs)
+            .into_iter()
+            .map(|idx| addrs[idx])
+            .collect::<Vec<_>>()
+            .into_iter();
+        let total = addrs.len();
+        // the queue can hold all the outcomes, so the attempts are never held back by a slow consumer
+        let (sender, receiver) = mpsc::channel(total.max(1));
+
+        let node = self.clone();
+        let task = self.spawn_task(format_args!("dial-many"), async move {
+            let mut attempts = JoinSet::new();
+            loop {
+                while attempts.len() < concurrency.max(1) {
+                    let addr = match pending.next() {
+                        Some(addr) => addr,
+                        None => break,
+                    };
+                    let node_clone = node.clone();
+                    node.spawn_task_in_set(
+                        &mut attempts,
+                        format_args!("dial:{}", addr),
+                        async move {
+                            let result = node_clone.connect(addr).await.and_then(|_| {
+                                node_clone
+                                    .connection_info(addr)
+                                    .ok_or_else(|| io::ErrorKind::NotConnected.into())
+                            });
+                            DialOutcome { addr, result }
+                        },
+                    );
+                }
+
+                match attempts.join_next().await {
+                    Some(Ok(outcome)) => {
+                        let _ = sender.send(outcome).await;
+                    }
+                    Some(Err(e)) => panic::resume_unwind(e.into_panic()),
+                    None => break,
+                }
+            }
+        });
+
+        DialProgress::new(receiver, task, total)
+    }
+
+    /// Returns the indices of the given addresses in the order in which they should be dialed: the most promising
+    /// addresses of the preferred address family come first.
+    fn dial_order(&self, addrs: &[SocketAddr]) -> Vec<usize> {
+        let policy = self.config.addr_family_policy;
+        let penalties = addrs
+            .iter()
+            .map(|&addr| {
+                self.known_peers
+                    .dial_penalty(addr, self.config.dial_freshness_weight)
+            })
+            .collect::<Vec<_>>();
+        let mut order = (0..addrs.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| {
+            policy
+                .priority(addrs[a])
+                .cmp(&policy.priority(addrs[b]))
+                .then(penalties[a].total_cmp(&penalties[b]))
+        });
+
+        order
+    }
+
+    /// Disconnects from the provided `SocketAddr`.
+    pub fn disconnect(&self, addr: SocketAddr) -> bool {
+        self.remove_connection(addr, false)
+    }
+
+    /// Disconnects from the provided `SocketAddr` after the connection was lost, i.e. closed by the peer or broken
+    /// by an I/O error; unlike the local disconnects, such losses are registered with the partition detector, and
+    /// losing the final connection this way triggers re-dialing the bootstrap peers.
+    pub(crate) fn handle_connection_loss(&self, addr: SocketAddr) -> bool {
+        self.remove_connection(addr, true)
+    }
+
+    /// Removes the connection with the provided `SocketAddr` and all the state associated with it.
+    fn remove_connection(&self, addr: SocketAddr, is_loss: bool) -> bool {
+        let disconnected = self.connections.remove(addr);
+        self.remove_peer_state(addr);
+
+        if disconnected {
+            info!(target: NODE, parent: self.span(), "disconnected from {}", addr);
+
+            if is_loss {
+                if self.num_connected() == 0 {
+                    self.all_disconnected.notify_one();
+                }
+
+                if let Some(ref config) = self.config.partition_detection {
+                    if let Some(signal) = self
+                        .partition_detector
+                        .register_disconnect(config, self.num_connected())
+                    {
+                        self.signal_partition(signal);
+                    }
+                }
+            }
+        } else {
+            warn!(target: NODE, parent: self.span(), "wasn't connected to {}", addr);
         }
 
         disconnected
     }
 
-    /// Sends the provided message to the specified `SocketAddr`, as long as the `Writing` protocol is enabled.
-    pub async fn send_direct_message(&self, addr: SocketAddr, message: Bytes) -> io::Result<()> {
-        self.connections
-            .sender(addr)?
-            .send(message)
+    /// Removes the per-peer state associated with the connection with the provided `SocketAddr`.
+    fn remove_peer_state(&self, addr: SocketAddr) {
+        self.acks.remove(addr);
+        self.topics.remove_peer(addr);
+        self.incoming.remove_peer(addr);
+        self.sequences.remove(addr);
+        self.pending_rehandshakes.lock().remove(&addr);
+        // the timings of an inbound peer without a known listening address are recorded under its ephemeral one,
+        // which is forgotten along with the connection
+        if self.known_peers.stats(addr).is_none() {
+            self.conn_metrics.remove_peer(addr);
+        }
+    }
+
+    /// Disconnects from all the peers whose connections match the given predicate; returns their addresses.
+    ///
+    /// note: the connections are removed at once and the tasks of all of them are aborted without waiting for one
+    /// another, so they are torn down concurrently; these are local disconnects, so they don't count towards
+    /// partition detection or trigger re-dialing the bootstrap peers.
+    pub fn prune_connections<F: Fn(&ConnectionInfo) -> bool>(
+        &self,
+        predicate: F,
+    ) -> Vec<SocketAddr> {
+        // select all the connections first, so that the predicate sees the state from before the pruning
+        let selected = self
+            .connected_addrs()
+            .into_iter()
+            .filter(|addr| {
+                self.connection_info(*addr)
+                    .map(|info| predicate(&info))
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        // dropping the removed connections aborts their tasks
+        let pruned = self
+            .connections
+            .remove_many(&selected)
+            .into_iter()
+            .map(|conn| conn.addr)
+            .collect::<Vec<_>>();
+        for addr in &pruned {
+            self.remove_peer_state(*addr);
+            info!(target: NODE, parent: self.span(), "disconnected from {}", addr);
+        }
+
+        if !pruned.is_empty() {
+            debug!(target: NODE, parent: self.span(), "pruned {} connection(s)", pruned.len());
+        }
+
+        pruned
+    }
+
+    /// Disconnects from all the peers; returns their addresses.
+    pub fn disconnect_all(&self) -> Vec<SocketAddr> {
+        self.prune_connections(|_| true)
+    }
+
+    /// Disconnects from all the peers that initiated their connections; returns their addresses.
+    pub fn disconnect_inbound(&self) -> Vec<SocketAddr> {
+        self.prune_connections(|info| info.is_inbound())
+    }
+
+    /// Disconnects from all the peers that the node connected to; returns their addresses.
+    pub fn disconnect_outbound(&self) -> Vec<SocketAddr> {
+        self.prune_connections(|info| !info.is_inbound())
+    }
+
+    /// Disconnects from the provided `SocketAddr` gracefully, as long as the `Writing` protocol is enabled: `reason`
+    /// is sent to the peer as the final (goodbye) message, no other messages (except for keep-alive ones) are queued
+    /// for it from then on, and the connection is closed once the already queued messages and the goodbye message
+    /// are written, or after the given `delay`, whichever comes first.
+    pub async fn disconnect_after(
+        &self,
+        addr: SocketAddr,
+        delay: Duration,
+        reason: Bytes,
+    ) -> io::Result<()> {
+        let sender = self.connections.sender(addr)?;
+        let conn_id = self.connections.set_closing(addr)?;
+        debug!(target: NODE, parent: self.span(), "disconnecting from {} in up to {:?}", addr, delay);
+
+        let (delivery_sender, delivery_receiver) = oneshot::channel();
+        let mut goodbye = OutboundMessage::from(reason);
+        goodbye.delivery = Some(delivery_sender);
+
+        let node = self.clone();
+        self.spawn_task(format_args!("disconnect:{}", addr), async move {
+            // the queue is FIFO, so once the goodbye message is written, the earlier ones are written too
+            let _ = timeout(delay, async move {
+                if sender.send(goodbye).await.is_ok() {
+                    let _ = delivery_receiver.await;
+                }
+            })
+            .await;
+
+            // the peer could have disconnected (and even reconnected) in the meantime
+            if node.connection_info(addr).map(|info| info.id) == Some(conn_id) {
+                node.disconnect(addr);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Disconnects from the provided `SocketAddr` gracefully, as long as the `Writing` protocol is enabled: the reads
+    /// from the connection stop immediately, no other messages (except for keep-alive ones) are queued for it from
+    /// then on, and the connection is closed once the already queued messages (followed by the `goodbye` message,
+    /// if provided) are written. Unlike `Node::disconnect_after`, it waits for the outbound queue to drain, up to
+    /// `drain_timeout`; if it is exceeded, the connection is closed regardless and `ErrorKind::TimedOut` is returned.
+    pub async fn disconnect_gracefully(
+        &self,
+        addr: SocketAddr,
+        drain_timeout: Duration,
+        goodbye: Option<Bytes>,
+    ) -> io::Result<()> {
+        let sender = self.connections.sender(addr)?;
+        let conn_id = self.connections.set_closing(addr)?;
+        self.connections.stop_reading(addr);
+        debug!(target: NODE, parent: self.span(), "draining the connection with {}", addr);
+
+        // the queue is FIFO, so once its final message is written (or skipped, if it's a marker), the earlier ones
+        // are written too
+        let (delivery_sender, delivery_receiver) = oneshot::channel();
+        let mut last_message = if let Some(goodbye) = goodbye {
+            OutboundMessage::from(goodbye)
+        } else {
+            let mut marker = OutboundMessage::from(Bytes::new());
+            marker.kind = MessageKind::Marker;
+            marker
+        };
+        last_message.delivery = Some(delivery_sender);
+
+        let drained = timeout(drain_timeout, async move {
+            // an error here means the connection was shut down
+            if sender.send(last_message).await.is_err() {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            delivery_receiver
+                .await
+                .unwrap_or_else(|_| Err(io::ErrorKind::NotConnected.into()))
+        })
+        .await;
+
+        // the peer could have disconnected (and even reconnected) in the meantime
+        if self.connection_info(addr).map(|info| info.id) == Some(conn_id) {
+            self.disconnect(addr);
+        }
+
+        drained.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    }
+
+    /// Sends the provided message to the specified `SocketAddr`, as long as the `Writing` protocol is enabled.
+    pub async fn send_direct_message(&self, addr: SocketAddr, message: Bytes) -> io::Result<()> {
+        self.queue_message(addr, message.into()).await
+    }
+
+    /// Sends the provided small message to the specified `SocketAddr`, as long as the `Writing` protocol is enabled;
+    /// unlike `send_direct_message`, it doesn't require the message to be `Bytes`, and messages up to
+    /// `protocols::MAX_INLINE_PAYLOAD_LEN` bytes in size are queued without any heap allocation.
+    pub async fn send_small_message(&self, addr: SocketAddr, message: &[u8]) -> io::Result<()> {
+        self.queue_message(addr, message.into()).await
+    }
+
+    /// Serializes the provided message using the `TypedWriting::serialize_message` registered for its type (see
+    /// `TypedWriting::enable_typed_writing`) and sends it to the specified `SocketAddr`; the messages are serialized
+    /// into a buffer owned by the node, whose allocation is reused. Fails with an `io::ErrorKind::Unsupported` error
+    /// if the `TypedWriting` protocol isn't enabled, and with an `io::ErrorKind::InvalidInput` one if `T` isn't the
+    /// `Message` type of any of its implementations.
+    pub async fn send_typed_message<T: Send + 'static>(
+        &self,
+        addr: SocketAddr,
+        message: T,
+    ) -> io::Result<()> {
+        let payload = {
+            let serializers = self.typed_serializers.read();
+            if serializers.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the TypedWriting protocol is not enabled",
+                ));
+            }
+            let serializer = serializers
+                .get(&TypeId::of::<T>())
+                .and_then(|serializer| serializer.downcast_ref::<TypedSerializer<T>>())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "not a TypedWriting::Message type",
+                    )
+                })?;
+
+            self.serialize(|buffer| serializer(&message, buffer))?
+        };
+
+        self.send_direct_message(addr, payload).await
+    }
+
+    /// Registers the serializer of typed messages, as part of enabling the `TypedWriting` protocol.
+    pub(crate) fn register_typed_serializer<M: 'static>(&self, serializer: TypedSerializer<M>) {
+        if self
+            .typed_serializers
+            .write()
+            .insert(TypeId::of::<M>(), Box::new(serializer))
+            .is_some()
+        {
+            panic!(
+                "the TypedWriting protocol was enabled more than once for the same message type!"
+            );
+        }
+    }
+
+    /// Serializes a message using the provided function into a buffer owned by the node, whose allocation is
+    /// reused; used by `TypedWriting::send_typed` and `Node::send_typed_message`.
+    pub(crate) fn serialize<F: FnOnce(&mut BytesMut) -> io::Result<()>>(
+        &self,
+        serialize: F,
+    ) -> io::Result<Bytes> {
+        let mut buffer = self.serialization_buffer.lock();
+        if let Err(e) = serialize(&mut buffer) {
+            buffer.clear();
+            return Err(e);
+        }
+
+        Ok(buffer.split().freeze())
+    }
+
+    /// Sends a sequenced message to the specified `SocketAddr`, as long as the `Writing` protocol is enabled: the
+    /// next sequence number for the peer is passed to `encode`, which is expected to include it in the message, and
+    /// the message is retained (up to `NodeConfig.retransmit_buffer_len` per peer) so that it can be retransmitted
+    /// via `Node::retransmit`. Returns the sequence number.
+    pub async fn send_sequenced<F: FnOnce(u64) -> Bytes>(
+        &self,
+        addr: SocketAddr,
+        encode: F,
+    ) -> io::Result<u64> {
+        if !self.is_connected(addr) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let (seq, message) = self.sequences.register_sent(addr, encode);
+        self.send_direct_message(addr, message).await?;
+
+        Ok(seq)
+    }
+
+    /// Resends the sequenced messages with the given sequence numbers to the specified `SocketAddr`, e.g. once it
+    /// reports them as missing; returns the number of messages resent, which can be lower than requested if some of
+    /// them are no longer retained.
+    pub async fn retransmit(&self, addr: SocketAddr, seqs: &[u64]) -> io::Result<usize> {
+        let messages = self.sequences.retained(addr, seqs);
+        let num_messages = messages.len();
+        debug!(target: NODE, parent: self.span(), "retransmitting {} messages to {}", num_messages, addr);
+
+        for message in messages {
+            self.send_direct_message(addr, message).await?;
+        }
+
+        Ok(num_messages)
+    }
+
+    /// Sends the provided message to the specified `SocketAddr` even if the node is paused, as long as the `Writing`
+    /// protocol is enabled; it is used to send keep-alive messages.
+    pub(crate) async fn send_unpausable_message(
+        &self,
+        addr: SocketAddr,
+        message: Bytes,
+    ) -> io::Result<()> {
+        let mut message = OutboundMessage::from(message);
+        message.kind = MessageKind::Critical;
+
+        self.queue_message(addr, message).await
+    }
+
+    /// Queues the provided message for the `Writing` protocol, as long as the egress policy allows it.
+    async fn queue_message(&self, addr: SocketAddr, message: OutboundMessage) -> io::Result<()> {
+        #[cfg(feature = "test-utils")]
+        if !self.connections.is_connected(addr) && self.log_outbound(addr, &message.payload) {
+            return Ok(());
+        }
+
+        let sender = self.connections.sender(addr)?;
+        // a closing connection only accepts the critical messages
+        if message.kind != MessageKind::Critical && self.connections.is_closing(addr) {
+            return Err(io::ErrorKind::ConnectionAborted.into());
+        }
+        self.check_egress_policy(addr, &message.payload)?;
+        #[cfg(feature = "test-utils")]
+        self.log_outbound(addr, &message.payload);
+
+        sender
+            .send(message)
+            .await
+            .map_err(|_| io::ErrorKind::NotConnected.into()) // an error here means the connection was shut down
+    }
+
+    /// Sends the provided message to the specified `SocketAddr`, as long as the `Writing` protocol is enabled;
+    /// unlike `send_direct_message`, it returns a future that resolves once the message has actually been written
+    /// to the stream (or failed to), and not once it's been queued.
+    pub async fn send_direct_message_with_delivery(
+        &self,
+        addr: SocketAddr,
+        message: Bytes,
+    ) -> io::Result<impl Future<Output = io::Result<()>>> {
+        let (delivery_sender, delivery_receiver) = oneshot::channel();
+        let mut message = OutboundMessage::from(message);
+        message.delivery = Some(delivery_sender);
+
+        self.queue_message(addr, message).await?;
+
+        // if the delivery sender is dropped, the connection was shut down before the message could be written
+        Ok(async move {
+            delivery_receiver
+                .await
+                .unwrap_or_else(|_| Err(io::ErrorKind::NotConnected.into()))
+        })
+    }
+
+    /// Sends the provided message to the connected peer with the specified `PeerId`, as long as the `Writing`
+    /// protocol is enabled.
+    #[cfg(feature = "identity")]
+    pub async fn send_to_peer(&self, peer_id: &PeerId, message: Bytes) -> io::Result<()> {
+        let addr = self
+            .peer_addr(peer_id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+
+        self.send_direct_message(addr, message).await
+    }
+
+    /// Sends the provided message to the specified `SocketAddr` once the given delay passes, as long as the
+    /// `Writing` protocol is enabled; the returned handle can be used to cancel it in the meantime. Failures to send
+    /// the message are only logged.
+    pub fn send_direct_message_after(
+        &self,
+        addr: SocketAddr,
+        message: Bytes,
+        delay: Duration,
+    ) -> ScheduleHandle {
+        self.scheduler.schedule_direct(self, addr, message, delay)
+    }
+
+    /// Broadcasts a message produced by `message_fn` to all peers every `interval` (starting one interval from
+    /// now), as long as the `Writing` protocol is enabled, until it's cancelled via the returned handle or the node
+    /// is shut down; e.g. for periodic announcements. Missed ticks are skipped rather than caught up on, and so are
+    /// the ones that occur while the previous broadcast is still in progress. Returns an `InvalidInput` error if the
+    /// interval is zero.
+    pub fn send_broadcast_every<F: FnMut() -> Bytes + Send + 'static>(
+        &self,
+        message_fn: F,
+        interval: Duration,
+    ) -> io::Result<ScheduleHandle> {
+        if interval.is_zero() {
+            error!(target: NODE, parent: self.span(), "the broadcast interval must be nonzero");
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        Ok(self
+            .scheduler
+            .schedule_broadcast(self, message_fn, interval))
+    }
+
+    /// Returns the timer sending the scheduled messages.
+    pub(crate) fn scheduler(&self) -> &Arc<Scheduler> {
+        &self.scheduler
+    }
+
+    /// Broadcasts the provided message to all peers, as long as the `Writing` protocol is enabled; peers vetoed
+    /// by the egress policy are skipped.
+    pub async fn send_broadcast(&self, message: Bytes) -> io::Result<()> {
+        self.broadcast_to(message, |_| true).await
+    }
+
+    /// Broadcasts the provided message to all the peers with the given tag (see `Node::tag_peer`), as long as the
+    /// `Writing` protocol is enabled; peers vetoed by the egress policy are skipped.
+    pub async fn send_tagged_broadcast(&self, tag: &str, message: Bytes) -> io::Result<()> {
+        self.broadcast_to(message, |addr| self.known_peers.has_tag(addr, tag))
+            .await
+    }
+
+    /// Broadcasts the provided message to all the connections in the given group (see `Node::add_to_group`), as
+    /// long as the `Writing` protocol is enabled; peers vetoed by the egress policy are skipped.
+    pub async fn send_group_broadcast(&self, group: &str, message: Bytes) -> io::Result<()> {
+        self.send_tagged_broadcast(group, message).await
+    }
+
+    /// Broadcasts a message customized for every peer, as long as the `Writing` protocol is enabled: the given
+    /// closure is called with the address and the `ConnectionInfo` of every peer, and returns the message to send
+    /// to it, or `None` if the peer should be skipped; peers vetoed by the egress policy are skipped as well. Returns
+    /// the number of peers the message was queued for.
+    ///
+    /// note: unlike the other broadcasts, the customized messages are not inserted into the `Node::seen_messages`
+    /// cache, as they are specific to their recipients.
+    pub async fn send_broadcast_filtered<F>(&self, mut customize: F) -> io::Result<usize>
+    where
+        F: FnMut(SocketAddr, &ConnectionInfo) -> Option<Bytes>,
+    {
+        self.broadcast_with(|addr| {
+            // the connection could have been closed in the meantime
+            self.connection_info(addr)
+                .and_then(|info| customize(addr, &info))
+        })
+        .await
+    }
+
+    /// Relays a message received from the given peer to all the other peers, as long as the `Writing` protocol is
+    /// enabled; returns the number of peers it was relayed to, or `None` if the message was already broadcast or
+    /// relayed within `NodeConfig.seen_cache_ttl_secs`, in which case it's dropped.
+    pub async fn relay(&self, source: SocketAddr, message: Bytes) -> io::Result<Option<usize>> {
+        if !self.seen_messages.insert(self.seen_messages.hash(&message)) {
+            trace!(target: NODE, parent: self.span(), "not relaying an already seen message from {}", source);
+            return Ok(None);
+        }
+
+        let mut num_recipients = 0;
+        for (addr, message_sender) in self.connections.senders()? {
+            if addr == source || self.check_egress_policy(addr, &message).is_err() {
+                continue;
+            }
+            #[cfg(feature = "test-utils")]
+            self.log_outbound(addr, &message);
+
+            if message_sender.send(message.clone().into()).await.is_ok() {
+                num_recipients += 1;
+            }
+        }
+
+        Ok(Some(num_recipients))
+    }
+
+    /// Returns the cache of the broadcast and relayed messages, used to suppress their echoes.
+    pub fn seen_messages(&self) -> &SeenCache {
+        &self.seen_messages
+    }
+
+    /// Broadcasts the provided message to all the peers whose addresses satisfy the given filter.
+    async fn broadcast_to<F: Fn(SocketAddr) -> bool>(
+        &self,
+        message: Bytes,
+        filter: F,
+    ) -> io::Result<()> {
+        // remember the message, so that it isn't relayed if a peer echoes it back
+        self.seen_messages.insert(self.seen_messages.hash(&message));
+
+        self.broadcast_with(|addr| filter(addr).then(|| message.clone()))
+            .await
+            .map(|_| ())
+    }
+
+    /// Sends the messages returned by the given closure to the corresponding peers, skipping the ones it returns
+    /// `None` for and the ones vetoed by the egress policy; returns the number of peers a message was queued for.
+    async fn broadcast_with<F: FnMut(SocketAddr) -> Option<Bytes>>(
+        &self,
+        mut message_for: F,
+    ) -> io::Result<usize> {
+        let mut num_recipients = 0;
+        for (addr, message_sender) in self.connections.senders()? {
+            let message = match message_for(addr) {
+                Some(message) => message,
+                None => continue,
+            };
+            if self.check_egress_policy(addr, &message).is_err() {
+                continue;
+            }
+            #[cfg(feature = "test-utils")]
+            self.log_outbound(addr, &message);
+
+            // an error means the connection is shutting down, which is already reported in logs
+            if message_sender.send(message.into()).await.is_ok() {
+                num_recipients += 1;
+            }
+        }
+
+        Ok(num_recipients)
+    }
+
+    /// Consults the egress policy (if there is one) on whether the given message can be sent to the specified
+    /// address.
+    pub(crate) fn check_egress_policy(&self, addr: SocketAddr, message: &[u8]) -> io::Result<()> {
+        if let Some(policy) = self.egress_policy.get() {
+            let class = policy.message_class(message);
+
+            if !policy.allow_send(addr, class, message.len()) {
+                debug!(target: NODE, parent: self.span(), "the egress policy vetoed a message of class {} to {}", class, addr);
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether a connection with the given address respects `NodeConfig.subnet_limits`, taking the given
+    /// pending outbound and inbound connections into account.
+    fn respects_subnet_limits(
+        &self,
+        addr: SocketAddr,
+        connecting: &FxHashSet<SocketAddr>,
+        pending_inbound: &FxHashSet<SocketAddr>,
+    ) -> bool {
+        if let Some(ref limits) = self.config.subnet_limits {
+            let pending = connecting.iter().chain(pending_inbound).copied();
+            limits.allows(addr, self.connected_addrs().into_iter().chain(pending))
+        } else {
+            true
+        }
+    }
+
+    /// Checks whether a connection with the given address respects `NodeConfig.group_limits`.
+    fn respects_group_limits(&self, addr: SocketAddr) -> bool {
+        self.config.group_limits.is_empty()
+            || self
+                .known_peers
+                .tags(addr)
+                .iter()
+                .all(|group| self.has_room_in_group(group))
+    }
+
+    /// Checks whether another connection can join the given group as per `NodeConfig.group_limits`.
+    fn has_room_in_group(&self, group: &str) -> bool {
+        match self.config.group_limits.get(group) {
+            Some(&limit) => self.group_count(group) < limit,
+            None => true,
+        }
+    }
+
+    /// Checks whether the peer known under the given address (or one of its aliases) is connected.
+    fn is_connected_peer(&self, addr: SocketAddr) -> bool {
+        let canonical = self.known_peers.canonical_addr(addr);
+        self.connected_addrs()
+            .into_iter()
+            .any(|conn_addr| self.known_peers.canonical_addr(conn_addr) == canonical)
+    }
+
+    /// Checks whether the given address passes the given connection filter, if there is one; the `side` is the
+    /// one the node would be on in the connection.
+    fn passes_filter(
+        &self,
+        filter: Option<&ConnectionFilter>,
+        addr: SocketAddr,
+        side: ConnectionSide,
+    ) -> bool {
+        if let Some(filter) = filter {
+            // the stats are cloned, so that the filter can access `KnownPeers` without a deadlock
+            let stats = match side {
+                ConnectionSide::Initiator => self.known_peers.stats(addr),
+                ConnectionSide::Responder => self.known_peers.inbound_stats(addr),
+            };
+            filter(addr, stats.as_ref())
+        } else {
+            true
+        }
+    }
+
+    /// Returns a list containing addresses of active connections.
+    pub fn connected_addrs(&self) -> Vec<SocketAddr> {
+        self.connections.addrs()
+    }
+
+    /// Returns the addresses of the connections that have completed their handshake; connections only become active
+    /// once it's done, so it's equivalent to `Node::connected_addrs`.
+    pub fn handshaken_addrs(&self) -> Vec<SocketAddr> {
+        self.connections.addrs()
+    }
+
+    /// Waits until the connection with the given address completes its handshake, returning immediately if it's
+    /// already connected; returns an error if the handshake fails, or if it doesn't complete within `timeout`.
+    ///
+    /// note: the address of an inbound connection is the one the peer connects from, not its listening address.
+    pub async fn await_handshake(&self, addr: SocketAddr, timeout: Duration) -> io::Result<()> {
+        // subscribe first, so that the outcome can't be missed between the check and the wait
+        let mut events = self.subscribe();
+        if self.connections.is_connected(addr) {
+            return Ok(());
+        }
+
+        let outcome = async {
+            loop {
+                match events.recv().await {
+                    Ok(NodeEvent::HandshakeCompleted(a, _)) if a == addr => return Ok(()),
+                    Ok(NodeEvent::HandshakeFailed(a, _)) if a == addr => {
+                        return Err(io::ErrorKind::ConnectionAborted.into());
+                    }
+                    Ok(_) => {}
+                    // some events were missed; the outcome might have been one of them
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        if self.connections.is_connected(addr) {
+                            return Ok(());
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(io::ErrorKind::BrokenPipe.into());
+                    }
+                }
+            }
+        };
+
+        match tokio::time::timeout(timeout, outcome).await {
+            Ok(ret) => ret,
+            Err(_) => {
+                debug!(target: NODE, parent: self.span(), "the handshake with {} didn't complete in time", addr);
+                Err(io::ErrorKind::TimedOut.into())
+            }
+        }
+    }
+
+    /// Returns the side of the connection with the given address in relation to the node, i.e. `Initiator` if it
+    /// was initiated by the peer; returns `None` if not connected.
+    pub fn connection_side(&self, addr: SocketAddr) -> Option<ConnectionSide> {
+        self.connections.side(addr)
+    }
+
+    /// Returns a summary of the handshakes with all the known peers, including the reasons of the failed ones; the
+    /// details concerning specific peers are available via `Node::known_peers`.
+    pub fn handshake_stats(&self) -> HandshakeStats {
+        self.known_peers.handshake_stats()
+    }
+
+    /// Returns a reference to the collection of statistics of node's known peers.
+    pub fn known_peers(&self) -> &KnownPeers {
+        &self.known_peers
+    }
+
+    /// Attaches an application-defined tag (e.g. "validator") to the peer with the given address; the tags are
+    /// stored in `KnownPeers`, so they are discarded along with the peer's stats. The tags also determine the
+    /// groups of the peer's connections (see `Node::add_to_group`), so it fails with an
+    /// `io::ErrorKind::PermissionDenied` error if the peer is connected and the group of the same name is full.
+    /// Returns `false` if the peer already had the tag.
+    pub fn tag_peer(&self, addr: SocketAddr, tag: &str) -> io::Result<bool> {
+        let _guard = self.group_admission.lock();
+        if !self.known_peers.has_tag(addr, tag)
+            && self.is_connected_peer(addr)
+            && !self.has_room_in_group(tag)
+        {
+            debug!(target: NODE, parent: self.span(), "can't add {} to group \"{}\"; the group is full", addr, tag);
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the group is full",
+            ));
+        }
+
+        Ok(self.known_peers.tag(addr, tag))
+    }
+
+    /// Detaches a tag from the peer with the given address.
+    pub fn untag_peer(&self, addr: SocketAddr, tag: &str) -> bool {
+        self.known_peers.untag(addr, tag)
+    }
+
+    /// Adds the connection with the given address to the named group (e.g. "validators"), allowing it to be
+    /// addressed via `Node::send_group_broadcast`. A group consists of the connected peers with the tag of the same
+    /// name (see `Node::tag_peer`), so the membership is retained along with the peer's stats and applies to its
+    /// later connections too. Returns `false` if the connection was already in the group, and fails with an
+    /// `io::ErrorKind::PermissionDenied` error if the group is full as per `NodeConfig.group_limits`; the limits are
+    /// also enforced when connections are established.
+    pub fn add_to_group(&self, addr: SocketAddr, group: &str) -> io::Result<bool> {
+        if !self.is_connected_peer(addr) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let added = self.tag_peer(addr, group)?;
+        if added {
+            debug!(target: NODE, parent: self.span(), "added {} to group \"{}\"", addr, group);
+        }
+
+        Ok(added)
+    }
+
+    /// Removes the connection with the given address from the named group; returns `false` if it wasn't in it.
+    pub fn remove_from_group(&self, addr: SocketAddr, group: &str) -> io::Result<bool> {
+        if !self.is_connected_peer(addr) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        Ok(self.untag_peer(addr, group))
+    }
+
+    /// Returns the addresses of the connections in the named group.
+    pub fn group_members(&self, group: &str) -> Vec<SocketAddr> {
+        self.connected_addrs()
+            .into_iter()
+            .filter(|&addr| self.known_peers.has_tag(addr, group))
+            .collect()
+    }
+
+    /// Returns the number of connections in the named group.
+    pub fn group_count(&self, group: &str) -> usize {
+        self.group_members(group).len()
+    }
+
+    /// Returns the tags attached to the peer with the given address.
+    pub fn peer_tags(&self, addr: SocketAddr) -> Vec<String> {
+        self.known_peers.tags(addr)
+    }
+
+    /// Returns a reference to the collection of application-level acks used by the `Acknowledging` protocol.
+    pub fn acks(&self) -> &Acks {
+        &self.acks
+    }
+
+    /// Returns a reference to the collection of message sequences used by `Node::send_sequenced` and the `Nacking`
+    /// protocol.
+    pub fn sequences(&self) -> &Sequences {
+        &self.sequences
+    }
+
+    /// Reports an internal error that is otherwise only logged, as specified by `NodeConfig.fail_fast`.
+    #[allow(unused_variables)]
+    pub(crate) fn fail_fast(&self, failure: fmt::Arguments<'_>) {
+        #[cfg(feature = "test-utils")]
+        match self.config.fail_fast {
+            FailFastMode::Disabled => {}
+            FailFastMode::Record => self.failures.lock().push(failure.to_string()),
+            FailFastMode::Panic => {
+                self.failures.lock().push(failure.to_string());
+                panic!("{}: {}", self.name(), failure);
+            }
+        }
+    }
+
+    /// Returns the internal errors recorded in fail-fast mode; see `NodeConfig.fail_fast`.
+    #[cfg(feature = "test-utils")]
+    pub fn failures(&self) -> Vec<String> {
+        self.failures.lock().clone()
+    }
+
+    /// Panics if any internal errors were recorded in fail-fast mode; see `NodeConfig.fail_fast`.
+    #[cfg(feature = "test-utils")]
+    pub fn assert_healthy(&self) {
+        let failures = self.failures.lock();
+        assert!(
+            failures.is_empty(),
+            "{} encountered internal errors: {:?}",
+            self.name(),
+            failures
+        );
+    }
+
+    /// Processes the messages contained in the given bytes as if they were read from the specified peer, which
+    /// doesn't need to be connected; the bytes are decoded with `Reading::read_message` (skipping any signature
+    /// verification), and the messages are passed directly to `Reading::process_message`. Returns once all of
+    /// them are processed, or with the first error; requires the `Reading` protocol to be enabled.
+    #[cfg(feature = "test-utils")]
+    pub async fn inject_inbound(&self, addr: SocketAddr, bytes: Bytes) -> io::Result<()> {
+        let injector = self.inbound_injector.lock().clone().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the Reading protocol is not enabled",
+            )
+        })?;
+
+        injector(addr, bytes).await
+    }
+
+    /// Sets up the processing of injected inbound messages, as part of enabling the `Reading` protocol.
+    #[cfg(feature = "test-utils")]
+    pub(crate) fn set_inbound_injector(&self, injector: InboundInjector) {
+        if self.inbound_injector.lock().replace(injector).is_some() {
+            panic!("the inbound_injector field was set more than once!");
+        }
+    }
+
+    /// Starts recording all the outbound messages, including the broadcast and relayed ones, in a log that can be
+    /// inspected via `Node::{outbound_log, take_outbound_log}`; while it's enabled, messages sent directly to peers
+    /// that aren't connected are recorded instead of failing, so that protocols can be tested without a live peer.
+    #[cfg(feature = "test-utils")]
+    pub fn record_outbound(&self) {
+        self.outbound_log
+            .lock()
+            .get_or_insert_with(Default::default);
+    }
+
+    /// Returns the outbound messages recorded since `Node::record_outbound` was called, along with their recipients.
+    #[cfg(feature = "test-utils")]
+    pub fn outbound_log(&self) -> Vec<(SocketAddr, Bytes)> {
+        self.outbound_log.lock().clone().unwrap_or_default()
+    }
+
+    /// Returns and clears the recorded outbound messages; the recording continues.
+    #[cfg(feature = "test-utils")]
+    pub fn take_outbound_log(&self) -> Vec<(SocketAddr, Bytes)> {
+        self.outbound_log
+            .lock()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Records the given outbound message if `Node::record_outbound` was called; returns `true` if it was recorded.
+    #[cfg(feature = "test-utils")]
+    pub(crate) fn log_outbound(&self, addr: SocketAddr, message: &[u8]) -> bool {
+        if let Some(log) = self.outbound_log.lock().as_mut() {
+            log.push((addr, Bytes::copy_from_slice(message)));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks whether the provided address is connected.
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.connections.is_connected(addr)
+    }
+
+    /// Returns a handle to the connection with the given address, which can be used to await the moment all of its
+    /// tasks have stopped once it's closed.
+    pub fn connection_handle(&self, addr: SocketAddr) -> Option<ConnectionHandle> {
+        self.connections.handle(addr)
+    }
+
+    /// Returns the context of the connection with the given address, including the value produced by its handshake
+    /// (see `Connection::set_handshake_data`).
+    pub fn connection_context(&self, addr: SocketAddr) -> Option<ConnectionContext> {
+        self.connections.context(addr)
+    }
+
+    /// Returns a snapshot of the state of the connection with the given address.
+    pub fn connection_info(&self, addr: SocketAddr) -> Option<ConnectionInfo> {
+        let mut info = self.connections.info(addr)?;
+        info.tags = self.peer_tags(addr);
+
+        Some(info)
+    }
+
+    /// Sets the mode of the connection with the given address, e.g. in order to implement a one-way feed, or to
+    /// mute a misbehaving peer without disconnecting from it; the `Reading` and `Writing` protocols respect it
+    /// immediately. Returns the previous mode.
+    pub fn set_connection_mode(
+        &self,
+        addr: SocketAddr,
+        mode: ConnectionMode,
+    ) -> io::Result<ConnectionMode> {
+        let prev = self.connections.set_mode(addr, mode)?;
+        debug!(target: NODE, parent: self.span(), "the mode of the connection with {} is now {:?}", addr, mode);
+
+        Ok(prev)
+    }
+
+    /// Stops reading from the connection with the given address, e.g. so that the processing of the already
+    /// received messages can catch up; the unread bytes remain in the socket, letting TCP backpressure slow the peer
+    /// down. A read that is already in progress is completed first, and writing to the connection is unaffected.
+    /// It's a shorthand for switching the connection to the `ConnectionMode` that doesn't allow reads (i.e.
+    /// `WriteOnly`, or `Suspended` for a `ReadOnly` connection). Returns `false` if reading was already paused.
+    pub fn pause_reading(&self, addr: SocketAddr) -> io::Result<bool> {
+        let prev = self
+            .connections
+            .update_mode(addr, |mode| mode.with_reading(false))?;
+        debug!(target: NODE, parent: self.span(), "paused reading from {}", addr);
+
+        Ok(prev.can_read())
+    }
+
+    /// Resumes reading from the connection with the given address (see `Node::pause_reading`); returns `false` if
+    /// reading wasn't paused.
+    pub fn resume_reading(&self, addr: SocketAddr) -> io::Result<bool> {
+        let prev = self
+            .connections
+            .update_mode(addr, |mode| mode.with_reading(true))?;
+        debug!(target: NODE, parent: self.span(), "resumed reading from {}", addr);
+
+        Ok(!prev.can_read())
+    }
+
+    /// Registers a message sent via the connection with the given address.
+    pub(crate) fn register_conn_sent_message(&self, addr: SocketAddr, len: usize) {
+        self.connections.register_sent_message(addr, len);
+    }
+
+    /// Registers a write of the given number of bytes with the node-wide and the given per-connection throttles;
+    /// returns the time to wait before the connection's next write in order to respect both upload rate limits.
+    pub(crate) fn upload_delay(&self, len: usize, conn_throttle: Option<&Throttle>) -> Duration {
+        [self.upload_throttle.as_ref(), conn_throttle]
+            .iter()
+            .flatten()
+            .map(|throttle| throttle.consume(len))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Registers a request (e.g. for a block or a header) that the node has served to the given peer; the counts are
+    /// reset every `NodeConfig.serving_window_secs`.
+    pub fn register_served_request(&self, addr: SocketAddr) {
+        self.served_requests.register(addr, self.serving_window());
+    }
+
+    /// Returns the number of requests served to the given peer within the current window.
+    pub fn served_requests(&self, addr: SocketAddr) -> u64 {
+        self.served_requests.count(addr, self.serving_window()).0
+    }
+
+    /// Returns the numbers of requests served to the peers within the current window, starting with the peers that
+    /// were served the most.
+    pub fn serving_stats(&self) -> Vec<(SocketAddr, u64)> {
+        self.served_requests.counts(self.serving_window())
+    }
+
+    /// Checks whether the given peer can be served another request within the current window, according to
+    /// `NodeConfig.serving_fairness`; it's always the case if fairness isn't enforced, or if no other peer was
+    /// served within the window, i.e. there is no contention.
+    pub fn can_serve(&self, addr: SocketAddr) -> bool {
+        let fairness = if let Some(ref fairness) = self.config.serving_fairness {
+            fairness
+        } else {
+            return true;
+        };
+
+        let (served, total) = self.served_requests.count(addr, self.serving_window());
+        if served < fairness.min_requests
+            || served == total
+            || served as f64 <= fairness.max_share * total as f64
+        {
+            true
+        } else {
+            debug!(target: NODE, parent: self.span(), "throttling {}; it was served {} out of {} requests", addr, served, total);
+            false
+        }
+    }
+
+    /// Sorts the given requesters so that the ones that were served the fewest requests within the current window
+    /// come first; it can be used to decide which pending requests to serve first.
+    pub fn prioritize_requesters(&self, addrs: &mut [SocketAddr]) {
+        let window = self.serving_window();
+        addrs.sort_by_cached_key(|addr| self.served_requests.count(*addr, window).0);
+    }
+
+    fn serving_window(&self) -> Duration {
+        Duration::from_secs(self.config.serving_window_secs)
+    }
+
+    /// Passes the given frame exchanged with the given peer to the corresponding traffic tap, if there is one, and
+    /// records a sample of it, if frame sampling is enabled.
+    pub(crate) fn observe_frame(&self, addr: SocketAddr, direction: FrameDirection, frame: &[u8]) {
+        let tap = match direction {
+            FrameDirection::Inbound => self.inbound_tap.get(),
+            FrameDirection::Outbound => self.outbound_tap.get(),
+        };
+        if let Some(tap) = tap {
+            tap(addr, SystemTime::now(), frame);
+        }
+        if let Some(sampler) = &self.frame_sampler {
+            sampler.sample(addr, direction, frame);
+        }
+    }
+
+    /// Returns a snapshot of the node's diagnostic data.
+    pub fn diagnostics_dump(&self) -> DiagnosticsDump {
+        DiagnosticsDump {
+            frame_samples: self
+                .frame_sampler
+                .as_ref()
+                .map(|sampler| sampler.samples())
+                .unwrap_or_default(),
+            peer_tags: self
+                .known_peers
+                .read()
+                .iter()
+                .filter(|(_, stats)| !stats.tags.is_empty())
+                .map(|(addr, stats)| (*addr, stats.tags.iter().cloned().collect()))
+                .collect(),
+        }
+    }
+
+    /// Returns a summary of the node's health, e.g. for status RPCs.
+    pub fn status(&self) -> NodeStatus {
+        let (established, queues) = self.connections.status();
+        let (msgs_sent, bytes_sent) = self.stats.sent();
+        let (msgs_received, bytes_received) = self.stats.received();
+        let rates = self.stats.rates();
+
+        NodeStatus {
+            name: self.name().into(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            listening_addr: self.listening_addr(),
+            external_addr: self.external_addr(),
+            is_listening: self.is_listening(),
+            is_paused: self.is_paused(),
+            connections: ConnectionCounts {
+                connecting: self.connecting.lock().len(),
+                pending_inbound: self.num_pending_inbound(),
+                max: self.config.max_connections as usize,
+                ..established
+            },
+            known_peers: self.known_peers.read().len(),
+            queues,
+            traffic: TrafficTotals {
+                msgs_sent,
+                bytes_sent,
+                msgs_received,
+                bytes_received,
+                msgs_dropped: self.stats.dropped(),
+                msgs_overflowed: self.stats.overflowed(),
+                sent_per_sec: rates.sent_per_sec,
+                received_per_sec: rates.received_per_sec,
+            },
+        }
+    }
+
+    /// Registers a message received via the connection with the given address.
+    pub(crate) fn register_conn_received_message(&self, addr: SocketAddr, len: usize) {
+        if let Some(first_message) = self.connections.register_received_message(addr, len) {
+            self.conn_metrics
+                .register_first_message(self.known_peers.canonical_addr(addr), first_message);
+        }
+    }
+
+    /// Returns the percentiles of the recent connection establishment timings of all the peers; the timings of
+    /// specific connections are available via `Node::connection_info`.
+    pub fn connection_timing_stats(&self) -> ConnectionTimingStats {
+        self.conn_metrics.stats()
+    }
+
+    /// Returns the percentiles of the recent connection establishment timings of the given peer; the timings are
+    /// recorded under the peer's canonical address (see `KnownPeers::canonical_addr`), and they are only retained
+    /// for as long as the peer is known, up to a bounded number of the most recently connected peers.
+    pub fn peer_connection_timing_stats(&self, addr: SocketAddr) -> Option<ConnectionTimingStats> {
+        self.conn_metrics
+            .peer_stats(self.known_peers.canonical_addr(addr))
+    }
+
+    /// Registers an inbound message from the given peer that was dropped due to its processing task not running;
+    /// returns `true` if it's the first one dropped for the connection.
+    pub(crate) fn register_dropped_message(&self, addr: SocketAddr) -> bool {
+        self.stats.register_dropped_message();
+        self.connections.register_dropped_message(addr)
+    }
+
+    /// Registers an inbound message from the given peer that was dropped due to its queue being full.
+    pub(crate) fn register_overflowed_message(&self, addr: SocketAddr) {
+        self.stats.register_overflowed_message();
+        self.connections.register_overflowed_message(addr);
+    }
+
+    /// Applies the `closed_inbound_queue_policy` once the queue passing inbound messages from the given peer to
+    /// its processing task is found to be closed.
+    pub(crate) fn handle_closed_inbound_queue(&self, addr: SocketAddr) {
+        error!(target: NODE, parent: self.span(), "the inbound message queue of {} is closed", addr);
+        self.fail_fast(format_args!(
+            "the inbound message queue of {} is closed",
+            addr
+        ));
+        self.emit_for(addr, NodeEvent::InboundQueueClosed);
+
+        match self.config.closed_inbound_queue_policy {
+            ClosedInboundQueuePolicy::PauseReading => {
+                warn!(target: NODE, parent: self.span(), "no longer reading from {}", addr);
+            }
+            ClosedInboundQueuePolicy::Disconnect => {
+                self.disconnect(addr);
+            }
+            ClosedInboundQueuePolicy::DisconnectAll => {
+                for addr in self.connected_addrs() {
+                    self.disconnect(addr);
+                }
+            }
+            ClosedInboundQueuePolicy::ShutDown => self.shut_down(),
+            ClosedInboundQueuePolicy::DropMessages => {
+                warn!(target: NODE, parent: self.span(), "dropping the inbound messages from {}", addr);
+            }
+        }
+    }
+
+    /// Returns the protocol version and capabilities negotiated with the given connected peer, as long as
+    /// version negotiation is enabled.
+    pub fn peer_capabilities(&self, addr: SocketAddr) -> Option<PeerCapabilities> {
+        self.connections.peer_capabilities(addr)
+    }
+
+    /// Returns the protocol version and capabilities advertised during version negotiation.
+    pub fn advertised_version(&self) -> (u32, u64) {
+        *self.advertised_version.read()
+    }
+
+    /// Changes the protocol version and capabilities advertised during version negotiation, e.g. after a hot
+    /// upgrade; the already connected peers can be informed about it via `Node::rehandshake`.
+    pub fn set_advertised_version(&self, version: u32, capabilities: u64) {
+        *self.advertised_version.write() = (version, capabilities);
+    }
+
+    /// Re-runs version negotiation and the handshake with the given connected peer in-band, coordinated with the
+    /// peer and without reconnecting; the handshake part is performed via `Handshaking::rehandshake_payload` and
+    /// `Handshaking::process_rehandshake_payload` on both sides. Returns the peer's refreshed capabilities, which are
+    /// also stored in its `Connection`. It requires the `Rehandshaking` protocol, and is subject to
+    /// `NodeConfig.max_handshake_time_ms`.
+    pub async fn rehandshake(&self, addr: SocketAddr) -> io::Result<PeerCapabilities> {
+        if self.config.supported_version_range.is_none() {
+            error!(target: HANDSHAKE, parent: self.span(), "can't rehandshake; version negotiation is disabled");
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        if !self.is_connected(addr) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.pending_rehandshakes.lock().insert(addr, result_sender);
+
+        debug!(target: HANDSHAKE, parent: self.span(), "rehandshaking with {}", addr);
+        if let Err(e) = self
+            .pass_rehandshake_step(addr, RehandshakeStep::Initiate)
+            .await
+        {
+            self.pending_rehandshakes.lock().remove(&addr);
+            return Err(e);
+        }
+
+        let max_time = Duration::from_millis(self.config.max_handshake_time_ms);
+        match timeout(max_time, result_receiver).await {
+            Ok(Ok(result)) => result,
+            // superseded by another rehandshake with the same peer, or the peer was disconnected
+            Ok(Err(_)) => Err(io::ErrorKind::Interrupted.into()),
+            Err(_) => {
+                self.pending_rehandshakes.lock().remove(&addr);
+                error!(target: HANDSHAKE, parent: self.span(), "the rehandshake with {} timed out", addr);
+                Err(io::ErrorKind::TimedOut.into())
+            }
+        }
+    }
+
+    /// Handles the payload of a rehandshake message received from the given peer: the `Rehandshaking` protocol
+    /// refreshes the peer's capabilities, processes its handshake payload and, if the peer initiated the rehandshake,
+    /// replies with the node's own ones. Peers failing the rehandshake (e.g. advertising an unsupported protocol
+    /// version) are disconnected.
+    pub async fn handle_rehandshake(&self, source: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        self.pass_rehandshake_step(
+            source,
+            RehandshakeStep::Receive(Bytes::copy_from_slice(payload)),
+        )
+        .await
+    }
+
+    /// Refreshes the capabilities of the given peer during a rehandshake, as long as its protocol version is
+    /// supported.
+    pub(crate) fn refresh_peer_capabilities(
+        &self,
+        addr: SocketAddr,
+        peer_caps: PeerCapabilities,
+    ) -> io::Result<()> {
+        if !is_supported(self, &peer_caps) {
+            error!(
+                target: HANDSHAKE, parent: self.span(),
+                "{} now uses an unsupported protocol version ({})", addr, peer_caps.version
+            );
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        self.connections.set_peer_capabilities(addr, peer_caps)
+    }
+
+    /// Concludes the rehandshake with the given peer initiated by the node, if there is one.
+    pub(crate) fn conclude_rehandshake(
+        &self,
+        addr: SocketAddr,
+        result: io::Result<PeerCapabilities>,
+    ) {
+        if let Some(result_sender) = self.pending_rehandshakes.lock().remove(&addr) {
+            let _ = result_sender.send(result);
+        }
+    }
+
+    /// Passes a step of a rehandshake to the `Rehandshaking` protocol.
+    async fn pass_rehandshake_step(
+        &self,
+        addr: SocketAddr,
+        step: RehandshakeStep,
+    ) -> io::Result<()> {
+        let sender = if let Some((sender, _)) = self.protocols.rehandshake_handler.get() {
+            sender
+        } else {
+            error!(target: HANDSHAKE, parent: self.span(), "can't rehandshake; the Rehandshaking protocol is disabled");
+            return Err(io::ErrorKind::Other.into());
+        };
+
+        sender
+            .send((addr, step))
+            .await
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Subscribes to the given topic, as long as the `PubSub` protocol is enabled; returns a receiver of the messages
+    /// published to it by the peers, along with their addresses. The connected peers are informed about the first
+    /// subscription to the topic, and the ones connecting later - about all the node's subscriptions.
+    pub async fn subscribe_topic(
+        &self,
+        topic: &str,
+    ) -> io::Result<mpsc::Receiver<(SocketAddr, Bytes)>> {
+        // validate the topic before registering the subscription
+        encode_pubsub(PubSubKind::Subscribe, topic, &[])?;
+        self.pubsub_handler()?;
+
+        let (sender, receiver) = mpsc::channel(self.config.topic_queue_depth);
+        if self.topics.subscribe(topic, sender) {
+            debug!(target: PUBSUB, parent: self.span(), "subscribed to topic \"{}\"", topic);
+            for addr in self.connected_addrs() {
+                let _ = self
+                    .send_pubsub(addr, PubSubKind::Subscribe, topic, &[])
+                    .await;
+            }
+        }
+
+        Ok(receiver)
+    }
+
+    /// Unsubscribes from the given topic, closing all the receivers returned by `Node::subscribe_topic` for it, and
+    /// informs the connected peers about it.
+    pub async fn unsubscribe_topic(&self, topic: &str) -> io::Result<()> {
+        self.pubsub_handler()?;
+
+        if self.topics.unsubscribe(topic) {
+            debug!(target: PUBSUB, parent: self.span(), "unsubscribed from topic \"{}\"", topic);
+            for addr in self.connected_addrs() {
+                let _ = self
+                    .send_pubsub(addr, PubSubKind::Unsubscribe, topic, &[])
+                    .await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publishes the given message to the given topic, i.e. sends it to all the peers subscribed to it, as long as
+    /// the `PubSub` protocol is enabled; returns the number of peers the message was queued for.
+    pub async fn publish(&self, topic: &str, message: Bytes) -> io::Result<usize> {
+        let payload = encode_pubsub(PubSubKind::Publish, topic, &message)?;
+        let sender = self.pubsub_handler()?;
+
+        let mut num_peers = 0;
+        for addr in self.topics.peers_subscribed_to(topic) {
+            if sender.send((addr, payload.clone())).await.is_ok() {
+                num_peers += 1;
+            }
+        }
+        trace!(target: PUBSUB, parent: self.span(), "published a message to {} peer(s) subscribed to \"{}\"", num_peers, topic);
+
+        Ok(num_peers)
+    }
+
+    /// Returns the topics the given connected peer is subscribed to.
+    pub fn peer_topics(&self, addr: SocketAddr) -> Vec<String> {
+        self.topics.peer_topics(addr)
+    }
+
+    /// Returns a stream of the messages received from all the peers, along with their addresses; it is an
+    /// alternative to implementing the `Reading` protocol. The first call enables a built-in reader, which expects
+    /// every message to be prefixed with the length of its payload, encoded as a little-endian `u32` (so the
+    /// messages, including the prefix, can't exceed `NodeConfig.conn_read_buffer_size`); every stream receives all
+    /// the messages read afterwards, and a stream that isn't polled eventually halts the reading once its queue
+    /// (`NodeConfig.incoming_queue_depth`) fills up. Fails with an `io::ErrorKind::AlreadyExists` error if the
+    /// `Reading` protocol was enabled instead.
+    ///
+    /// note: it only applies to the connections established after the first call.
+    pub fn incoming(&self) -> io::Result<Incoming> {
+        self.new_incoming(None)
+    }
+
+    /// Returns a stream of the messages received from the given connected peer; it ends once the peer disconnects.
+    /// See `Node::incoming` for details.
+    pub fn incoming_from(&self, addr: SocketAddr) -> io::Result<Incoming> {
+        if !self.is_connected(addr) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        self.new_incoming(Some(addr))
+    }
+
+    /// Creates a new `Incoming` stream, enabling the built-in reader if needed.
+    fn new_incoming(&self, source: Option<SocketAddr>) -> io::Result<Incoming> {
+        self.incoming_reader.get_or_try_init(|| {
+            // the built-in reader can't replace the application's one
+            if self.reading_handler().is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the Reading protocol is already enabled",
+                ));
+            }
+            IncomingReader(self.clone()).enable_reading();
+
+            Ok(())
+        })?;
+
+        let (sender, receiver) = mpsc::channel(self.config.incoming_queue_depth);
+        self.incoming.register(source, sender);
+
+        Ok(Incoming::new(receiver))
+    }
+
+    /// Returns the streams of inbound messages returned by `Node::{incoming, incoming_from}`.
+    pub(crate) fn incoming_streams(&self) -> &IncomingStreams {
+        &self.incoming
+    }
+
+    /// Handles the payload of a pubsub message received from the given peer: registers a change in its subscriptions
+    /// or passes a published message to the local subscribers of its topic. If there are none left, the node
+    /// unsubscribes from the topic.
+    pub async fn handle_pubsub_message(
+        &self,
+        source: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let (kind, topic, message) = decode_pubsub(payload)?;
+
+        match kind {
+            PubSubKind::Subscribe | PubSubKind::Unsubscribe => {
+                let subscribed = kind == PubSubKind::Subscribe;
+                debug!(
+                    target: PUBSUB, parent: self.span(), "{} {} topic \"{}\"",
+                    source, if subscribed { "subscribed to" } else { "unsubscribed from" }, topic
+                );
+                self.topics
+                    .register_peer_subscription(source, topic, subscribed);
+            }
+            PubSubKind::Publish => {
+                let subscribers = match self.topics.subscribers(topic) {
+                    Some(subscribers) => subscribers,
+                    None => {
+                        // the peer must have missed an unsubscription
+                        debug!(target: PUBSUB, parent: self.span(), "{} published to an unknown topic \"{}\"", source, topic);
+                        return self
+                            .send_pubsub(source, PubSubKind::Unsubscribe, topic, &[])
+                            .await;
+                    }
+                };
+
+                if subscribers.is_empty() {
+                    debug!(target: PUBSUB, parent: self.span(), "no longer subscribed to topic \"{}\"", topic);
+                    for addr in self.connected_addrs() {
+                        let _ = self
+                            .send_pubsub(addr, PubSubKind::Unsubscribe, topic, &[])
+                            .await;
+                    }
+                }
+
+                let message = Bytes::copy_from_slice(message);
+                for subscriber in subscribers {
+                    // an error means that the receiver was dropped in the meantime
+                    let _ = subscriber.send((source, message.clone())).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles the payload of a peer-exchange message received from the given peer (see the `PeerExchange`
+    /// protocol): adds the addresses it contains that weren't known yet to `KnownPeers`, and returns their number.
+    /// They are unverified until they are dialed: they don't count as seen, and once they are dialed unsuccessfully,
+    /// they are forgotten; the number of such addresses is limited by `NodeConfig.{pex_max_unverified_addrs,
+    /// pex_max_unverified_addrs_per_source}`, and the ones exceeding the limits are ignored. Addresses that can't be
+    /// dialed (e.g. unspecified or multicast ones, or ones with a zero port), aren't allowed by
+    /// `NodeConfig.addr_family_policy` or belong to the node itself are skipped, and payloads containing more than
+    /// `NodeConfig.pex_max_addrs` addresses are rejected.
+    pub fn handle_pex_message(&self, source: SocketAddr, payload: &[u8]) -> io::Result<usize> {
+        let addrs = decode_pex(payload)?;
+        if addrs.len() > self.config.pex_max_addrs {
+            warn!(target: DISCOVERY, parent: self.span(), "{} shared too many addresses ({})", source, addrs.len());
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let mut unverified_addrs = self.unverified_addrs.lock();
+        let mut num_from_source = unverified_addrs
+            .values()
+            .filter(|ip| **ip == source.ip())
+            .count();
+        let mut num_new = 0;
+        for addr in addrs {
+            let is_dialable = addr.port() != 0
+                && !addr.ip().is_unspecified()
+                && !addr.ip().is_multicast()
+                && addr.ip() != IpAddr::V4(Ipv4Addr::BROADCAST);
+
+            if is_dialable
+                && Some(addr) != self.listening_addr()
+                && self.config.addr_family_policy.allows(addr)
+                && !self.known_peers().contains(addr)
+            {
+                if unverified_addrs.len() >= self.config.pex_max_unverified_addrs
+                    || num_from_source >= self.config.pex_max_unverified_addrs_per_source
+                {
+                    debug!(target: DISCOVERY, parent: self.span(), "ignoring the rest of the addresses from {}; too many are unverified", source);
+                    break;
+                }
+
+                self.known_peers().add(addr);
+                unverified_addrs.insert(addr, source.ip());
+                num_from_source += 1;
+                num_new += 1;
+            }
+        }
+        debug!(target: DISCOVERY, parent: self.span(), "learned {} new address(es) from {}", num_new, source);
+
+        Ok(num_new)
+    }
+
+    /// Sends the given message to the specified address on the given channel, as long as the `Multiplexing` protocol
+    /// is enabled.
+    pub async fn send_on_channel(
+        &self,
+        addr: SocketAddr,
+        channel: u16,
+        message: Bytes,
+    ) -> io::Result<()> {
+        if !self.is_connected(addr) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let sender = self.multiplexing_handler()?.queue(channel);
+
+        sender
+            .send((addr, encode_channel_payload(channel, &message)))
             .await
-            .map_err(|_| io::ErrorKind::NotConnected.into()) // an error here means the connection was shut down
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
     }
 
-    /// Broadcasts the provided message to all peers, as long as the `Writing` protocol is enabled.
-    pub async fn send_broadcast(&self, message: Bytes) -> io::Result<()> {
-        for message_sender in self.connections.senders()? {
-            // an error means the connection is shutting down, which is already reported in logs
-            let _ = message_sender.send(message.clone()).await;
+    /// Registers the local reader of the given channel; returns a receiver of the messages received on it, along with
+    /// the addresses of their senders. A channel can only have a single reader at a time.
+    pub fn register_channel(
+        &self,
+        channel: u16,
+    ) -> io::Result<mpsc::Receiver<(SocketAddr, Bytes)>> {
+        let (sender, receiver) = mpsc::channel(self.config.channel_queue_depth);
+        if !self.channels.register(channel, sender) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        debug!(target: MULTIPLEXING, parent: self.span(), "registered a reader of channel {}", channel);
+
+        Ok(receiver)
+    }
+
+    /// Unregisters the local reader of the given channel, closing its receiver; returns `false` if it didn't have one.
+    pub fn unregister_channel(&self, channel: u16) -> bool {
+        self.channels.unregister(channel)
+    }
+
+    /// Handles the payload of a channel message received from the given peer: passes the message to the local reader
+    /// of its channel. The messages received on channels without a reader, or whose reader's queue is full, are
+    /// dropped; they are counted in `NodeStats::dropped`.
+    pub fn handle_channel_message(&self, source: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        let (channel, message) = decode_channel_payload(payload)?;
+
+        let reader = match self.channels.reader(channel) {
+            Some(reader) => reader,
+            None => {
+                debug!(target: MULTIPLEXING, parent: self.span(), "{} sent a message on unregistered channel {}", source, channel);
+                self.stats.register_dropped_message();
+                return Ok(());
+            }
+        };
+
+        if reader
+            .try_send((source, Bytes::copy_from_slice(message)))
+            .is_err()
+        {
+            warn!(target: MULTIPLEXING, parent: self.span(), "dropping a message from {} on channel {}; its queue is full or closed", source, channel);
+            self.stats.register_dropped_message();
         }
 
         Ok(())
     }
 
-    /// Returns a list containing addresses of active connections.
-    pub fn connected_addrs(&self) -> Vec<SocketAddr> {
-        self.connections.addrs()
+    /// Registers the handler of the inbound messages of the given type, i.e. the ones starting with the given byte;
+    /// see `Node::dispatch_message`. A message type can only have a single handler.
+    pub fn register_handler(
+        &self,
+        msg_type: u8,
+        handler: Arc<dyn MessageHandler>,
+    ) -> io::Result<()> {
+        self.register_handler_range(msg_type..=msg_type, handler)
     }
 
-    /// Returns a reference to the collection of statistics of node's known peers.
-    pub fn known_peers(&self) -> &KnownPeers {
-        &self.known_peers
+    /// Registers the handler of the inbound messages of any of the given types; see `Node::register_handler`.
+    pub fn register_handler_range(
+        &self,
+        msg_types: RangeInclusive<u8>,
+        handler: Arc<dyn MessageHandler>,
+    ) -> io::Result<()> {
+        if !self.handlers.register(msg_types.clone(), handler) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        debug!(target: NODE, parent: self.span(), "registered a handler of message types {:?}", msg_types);
+
+        Ok(())
     }
 
-    /// Checks whether the provided address is connected.
-    pub fn is_connected(&self, addr: SocketAddr) -> bool {
-        self.connections.is_connected(addr)
+    /// Unregisters the handler of the given message type (along with any other types it handles); returns `false`
+    /// if the type didn't have one.
+    pub fn unregister_handler(&self, msg_type: u8) -> bool {
+        self.handlers.unregister(msg_type)
+    }
+
+    /// Passes an inbound message to the handler registered for its type, i.e. its first byte; it is meant to be
+    /// called from `Reading::process_message`, so that the processing of different types of messages can be split
+    /// across multiple `MessageHandler`s. Messages that are empty or of a type without a handler are rejected.
+    pub async fn dispatch_message(&self, source: SocketAddr, message: Bytes) -> io::Result<()> {
+        let msg_type = match message.first() {
+            Some(msg_type) => *msg_type,
+            None => return Err(io::ErrorKind::InvalidData.into()),
+        };
+
+        match self.handlers.get(msg_type) {
+            Some(handler) => handler.handle(source, message).await,
+            None => {
+                debug!(target: NODE, parent: self.span(), "{} sent a message of unhandled type {}", source, msg_type);
+                Err(io::ErrorKind::InvalidData.into())
+            }
+        }
+    }
+
+    /// Returns the handler passing channel payloads to the `Multiplexing` protocol.
+    fn multiplexing_handler(&self) -> io::Result<&MultiplexingHandler> {
+        if let Some(handler) = self.protocols.multiplexing_handler.get() {
+            Ok(handler)
+        } else {
+            error!(target: MULTIPLEXING, parent: self.span(), "the Multiplexing protocol is disabled");
+            Err(io::ErrorKind::Other.into())
+        }
+    }
+
+    /// Returns the sender passing pubsub payloads to the `PubSub` protocol.
+    fn pubsub_handler(&self) -> io::Result<&mpsc::Sender<(SocketAddr, Bytes)>> {
+        if let Some((sender, _)) = self.protocols.pubsub_handler.get() {
+            Ok(sender)
+        } else {
+            error!(target: PUBSUB, parent: self.span(), "the PubSub protocol is disabled");
+            Err(io::ErrorKind::Other.into())
+        }
+    }
+
+    /// Passes a pubsub payload to the `PubSub` protocol.
+    async fn send_pubsub(
+        &self,
+        addr: SocketAddr,
+        kind: PubSubKind,
+        topic: &str,
+        message: &[u8],
+    ) -> io::Result<()> {
+        let payload = encode_pubsub(kind, topic, message)?;
+
+        self.pubsub_handler()?
+            .send((addr, payload))
+            .await
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Returns a clone of the value of the given type attached to the connection with the given address.
+    pub fn connection_ext<T: Clone + Send + Sync + 'static>(&self, addr: SocketAddr) -> Option<T> {
+        self.connections.ext(addr)
+    }
+
+    /// Attaches a value of the given type to the connection with the given address; returns the previously attached
+    /// value of that type, if there was one.
+    pub fn insert_connection_ext<T: Send + Sync + 'static>(
+        &self,
+        addr: SocketAddr,
+        value: T,
+    ) -> io::Result<Option<T>> {
+        self.connections.insert_ext(addr, value)
+    }
+
+    /// Compresses the given message of the given class for the peer with the given address, using the class'
+    /// dictionary if the peer has it too; the result can be decompressed by the peer with `Node::decompress`.
+    #[cfg(feature = "compression")]
+    pub fn compress(&self, addr: SocketAddr, class: u8, message: &[u8]) -> io::Result<Bytes> {
+        let compression = self.compression()?;
+        let shared = self
+            .connection_ext::<SharedDictionaries>(addr)
+            .ok_or(io::ErrorKind::NotConnected)?;
+
+        compression.compress(class, message, &shared)
+    }
+
+    /// Decompresses the given payload received from the peer with the given address; payloads compressed with a
+    /// dictionary that wasn't negotiated with the peer are rejected.
+    #[cfg(feature = "compression")]
+    pub fn decompress(&self, addr: SocketAddr, payload: &[u8]) -> io::Result<Bytes> {
+        let compression = self.compression()?;
+        let shared = self
+            .connection_ext::<SharedDictionaries>(addr)
+            .ok_or(io::ErrorKind::NotConnected)?;
+
+        compression.decompress(payload, &shared)
+    }
+
+    /// Returns the IDs of the compression dictionaries negotiated with the peer with the given address.
+    #[cfg(feature = "compression")]
+    pub fn shared_dictionaries(&self, addr: SocketAddr) -> Option<Vec<u32>> {
+        self.connection_ext::<SharedDictionaries>(addr)
+            .map(|shared| shared.0)
+    }
+
+    #[cfg(feature = "compression")]
+    fn compression(&self) -> io::Result<&Compression> {
+        if let Some(ref compression) = self.compression {
+            Ok(compression)
+        } else {
+            error!(target: NODE, parent: self.span(), "compression is disabled");
+            Err(io::ErrorKind::Other.into())
+        }
+    }
+
+    /// Returns the node's own `PeerId`, as long as it has an identity.
+    #[cfg(feature = "identity")]
+    pub fn peer_id(&self) -> Option<PeerId> {
+        self.config
+            .identity
+            .as_ref()
+            .map(|identity| identity.peer_id())
+    }
+
+    /// Returns the verified `PeerId` of the peer connected at the given address.
+    #[cfg(feature = "identity")]
+    pub fn connected_peer_id(&self, addr: SocketAddr) -> Option<PeerId> {
+        self.connections.peer_id(addr)
+    }
+
+    /// Returns the verified public key of the peer connected at the given address.
+    #[cfg(feature = "identity")]
+    pub fn connected_peer_public_key(&self, addr: SocketAddr) -> Option<[u8; 32]> {
+        self.connections.peer_public_key(addr)
+    }
+
+    /// Returns the signature scheme used to sign and verify messages, if message signing is enabled.
+    #[cfg(feature = "identity")]
+    pub fn signature_scheme(&self) -> Option<&Arc<dyn SignatureScheme>> {
+        self.signature_scheme.get()
+    }
+
+    /// Returns the address of the connected peer with the specified `PeerId`.
+    #[cfg(feature = "identity")]
+    pub fn peer_addr(&self, peer_id: &PeerId) -> Option<SocketAddr> {
+        self.connections.peer_addr(peer_id)
+    }
+
+    /// Fetches all the seed lists specified in `NodeConfig.seed_lists` and merges the addresses they contain into
+    /// `KnownPeers`; returns the number of newly discovered addresses. Seed lists that can't be fetched or verified
+    /// are skipped, unless none of them are usable. The seed lists are also dialed by the bootstrapping task (see
+    /// `Node::start_bootstrapping`).
+    #[cfg(feature = "bootstrap")]
+    pub async fn bootstrap(&self) -> io::Result<usize> {
+        let mut num_new = 0;
+        for addr in self.fetch_seed_lists().await? {
+            if !self.known_peers().contains(addr) {
+                self.known_peers().add(addr);
+                num_new += 1;
+            }
+        }
+
+        Ok(num_new)
+    }
+
+    /// Fetches all the seed lists specified in `NodeConfig.seed_lists`, resolving their hosts via `Node::resolve`;
+    /// returns the usable addresses they contain. Seed lists that can't be fetched or verified are skipped, unless
+    /// none of them are usable.
+    #[cfg(feature = "bootstrap")]
+    async fn fetch_seed_lists(&self) -> io::Result<Vec<SocketAddr>> {
+        let mut last_error = None;
+        let mut num_usable = 0;
+        let mut seeds = Vec::new();
+
+        for seed_list in &self.config().seed_lists {
+            match seed_list.fetch_via(self).await {
+                Ok(addrs) => {
+                    num_usable += 1;
+                    debug!(target: DISCOVERY, parent: self.span(), "got {} addresses from {}", addrs.len(), seed_list.url);
+
+                    for addr in addrs {
+                        if Some(addr) != self.listening_addr()
+                            && self.config.addr_family_policy.allows(addr)
+                            && !seeds.contains(&addr)
+                        {
+                            seeds.push(addr);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!(target: DISCOVERY, parent: self.span(), "couldn't use the seed list from {}: {}", seed_list.url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) if num_usable == 0 => Err(e),
+            _ => Ok(seeds),
+        }
+    }
+
+    /// Resolves the given host name using the registered `Resolver` (`SystemResolver` by default), and returns its
+    /// addresses with the specified port; the results are cached according to their TTLs (see
+    /// `NodeConfig.{dns_default_ttl_secs, dns_max_ttl_secs, dns_cache_capacity}`).
+    pub async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let with_port = |ips: Vec<IpAddr>| {
+            ips.into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect::<Vec<_>>()
+        };
+
+        if let Some(ips) = self.dns_cache.get(host) {
+            return Ok(with_port(ips));
+        }
+
+        let answer = if let Some(resolver) = self.resolver.get() {
+            resolver.resolve(host).await
+        } else {
+            SystemResolver.resolve(host).await
+        }
+        .map_err(|e| {
+            error!(target: NODE, parent: self.span(), "couldn't resolve {}: {}", host, e);
+            e
+        })?;
+
+        if answer.ips.is_empty() {
+            error!(target: NODE, parent: self.span(), "{} doesn't resolve to any address", host);
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        let ttl = answer
+            .ttl
+            .unwrap_or_else(|| Duration::from_secs(self.config.dns_default_ttl_secs))
+            .min(Duration::from_secs(self.config.dns_max_ttl_secs));
+        self.dns_cache.insert(
+            host,
+            answer.ips.clone(),
+            ttl,
+            self.config.dns_cache_capacity,
+        );
+
+        Ok(with_port(answer.ips))
+    }
+
+    /// Returns the statistics of the cache of the names resolved via `Node::resolve`.
+    pub fn dns_cache_stats(&self) -> DnsCacheStats {
+        self.dns_cache.stats()
+    }
+
+    /// Removes all the names cached by `Node::resolve`, so that they are resolved anew; returns their number.
+    pub fn flush_dns_cache(&self) -> usize {
+        self.dns_cache.flush()
     }
 
     /// Returns the number of active connections.
@@ -337,7 +3298,7 @@ impl Node {
         let num_connected = self.num_connected();
         let limit = self.config.max_connections as usize;
         if num_connected >= limit || num_connected + self.connecting.lock().len() >= limit {
-            warn!(parent: self.span(), "maximum number of connections ({}) reached", limit);
+            warn!(target: NODE, parent: self.span(), "maximum number of connections ({}) reached", limit);
             false
         } else {
             true
@@ -380,18 +3341,203 @@ impl Node {
         }
     }
 
+    /// Sets up the keep-alive task, as part of enabling the `KeepAlive` protocol.
+    pub(crate) fn set_keepalive_task(&self, task: JoinHandle<()>) {
+        if self.protocols.keepalive_task.set(task).is_err() {
+            panic!("the keepalive_task field was set more than once!");
+        }
+    }
+
+    /// Sets up the task detecting stalled writers, as part of enabling the `Writing` protocol.
+    pub(crate) fn set_writer_watchdog_task(&self, task: JoinHandle<()>) {
+        if self.protocols.writer_watchdog_task.set(task).is_err() {
+            panic!("the writer_watchdog_task field was set more than once!");
+        }
+    }
+
+    /// Returns the state of the writer of every connection.
+    pub(crate) fn writer_states(&self) -> Vec<WriterState> {
+        self.connections.writer_states()
+    }
+
+    /// Sets up the rehandshaking task and the channel used to communicate with it, as part of enabling the
+    /// `Rehandshaking` protocol.
+    pub(crate) fn set_rehandshake_handler(&self, handler: RehandshakeHandler) {
+        if self.protocols.rehandshake_handler.set(handler).is_err() {
+            panic!("the rehandshake_handler field was set more than once!");
+        }
+    }
+
+    /// Sets up the pubsub task and the channel used to communicate with it, as part of enabling the `PubSub`
+    /// protocol.
+    pub fn set_pubsub_handler(&self, handler: PubSubHandler) {
+        if self.protocols.pubsub_handler.set(handler).is_err() {
+            panic!("the pubsub_handler field was set more than once!");
+        }
+    }
+
+    /// Sets up the handler of the per-channel multiplexing tasks, as part of enabling the `Multiplexing` protocol.
+    pub fn set_multiplexing_handler(&self, handler: MultiplexingHandler) {
+        if self.protocols.multiplexing_handler.set(handler).is_err() {
+            panic!("the multiplexing_handler field was set more than once!");
+        }
+    }
+
+    /// Sets up the peer-exchange task, as part of enabling the `PeerExchange` protocol.
+    pub(crate) fn set_pex_task(&self, task: JoinHandle<()>) {
+        if self.protocols.pex_task.set(task).is_err() {
+            panic!("the pex_task field was set more than once!");
+        }
+    }
+
+    /// Registers an ordering lane's task, spawned by the `Reading` protocol on first use.
+    pub(crate) fn register_ordering_lane_task(&self, task: JoinHandle<()>) {
+        self.protocols.ordering_lane_tasks.lock().push(task);
+    }
+
+    /// Sets up the ack-sending task, as part of enabling the `Acknowledging` protocol.
+    pub(crate) fn set_acking_task(&self, task: JoinHandle<()>) {
+        if self.protocols.acking_task.set(task).is_err() {
+            panic!("the acking_task field was set more than once!");
+        }
+    }
+
+    /// Sets up the egress policy consulted before any outbound message is queued; messages it vetoes are not sent,
+    /// and attempts to send them directly result in an `io::ErrorKind::PermissionDenied` error.
+    pub fn set_egress_policy(&self, policy: Arc<dyn EgressPolicy>) {
+        if self.egress_policy.set(policy).is_err() {
+            panic!("the egress_policy field was set more than once!");
+        }
+    }
+
+    /// Sets up the filter consulted before any outbound connection is attempted; the attempts it vetoes fail with
+    /// an `io::ErrorKind::PermissionDenied` error.
+    pub fn set_dial_filter<F>(&self, filter: F)
+    where
+        F: Fn(SocketAddr, Option<&PeerStats>) -> bool + Send + Sync + 'static,
+    {
+        if self.dial_filter.set(Arc::new(filter)).is_err() {
+            panic!("the dial_filter field was set more than once!");
+        }
+    }
+
+    /// Sets up the filter consulted before any inbound connection is accepted; the connections it vetoes are
+    /// dropped right away.
+    pub fn set_accept_filter<F>(&self, filter: F)
+    where
+        F: Fn(SocketAddr, Option<&PeerStats>) -> bool + Send + Sync + 'static,
+    {
+        if self.accept_filter.set(Arc::new(filter)).is_err() {
+            panic!("the accept_filter field was set more than once!");
+        }
+    }
+
+    /// Sets up the hook receiving every raw frame read from the peers, along with the peer's address and the time
+    /// it was read at; it's called from the reading tasks, so it shouldn't block. See `CaptureWriter::attach` for
+    /// a built-in way to record the traffic to a file.
+    pub fn set_inbound_tap<F>(&self, tap: F)
+    where
+        F: Fn(SocketAddr, SystemTime, &[u8]) + Send + Sync + 'static,
+    {
+        if self.inbound_tap.set(Arc::new(tap)).is_err() {
+            panic!("the inbound_tap field was set more than once!");
+        }
+    }
+
+    /// Sets up the hook receiving every raw frame written to the peers, along with the peer's address and the time
+    /// it was written at; it's called from the writing tasks, so it shouldn't block.
+    pub fn set_outbound_tap<F>(&self, tap: F)
+    where
+        F: Fn(SocketAddr, SystemTime, &[u8]) + Send + Sync + 'static,
+    {
+        if self.outbound_tap.set(Arc::new(tap)).is_err() {
+            panic!("the outbound_tap field was set more than once!");
+        }
+    }
+
+    /// Sets up the resolver used by `Node::resolve` instead of the default `SystemResolver`; it should be done
+    /// before any names are resolved.
+    pub fn set_resolver(&self, resolver: Arc<dyn Resolver>) {
+        if self.resolver.set(resolver).is_err() {
+            panic!("the resolver field was set more than once!");
+        }
+    }
+
+    /// Enables message signing: all the outbound messages get signed using the given scheme, and inbound messages
+    /// with invalid signatures cause the offending peers to be penalized and disconnected. It requires the node
+    /// to have a `NodeIdentity`, and should be done before any connections are established.
+    #[cfg(feature = "identity")]
+    pub fn set_signature_scheme(&self, scheme: Arc<dyn SignatureScheme>) {
+        assert!(
+            self.config.identity.is_some(),
+            "message signing requires the node to have an identity!"
+        );
+
+        if self.signature_scheme.set(scheme).is_err() {
+            panic!("the signature_scheme field was set more than once!");
+        }
+    }
+
+    /// Pauses the node: reading, processing and writing messages (with the exception of keep-alive messages),
+    /// dialing, and the periodic tasks of the protocols are halted until the node is resumed, while the existing
+    /// connections are kept open.
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!(target: NODE, parent: self.span(), "paused");
+        }
+    }
+
+    /// Resumes a paused node.
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!(target: NODE, parent: self.span(), "resumed");
+        }
+    }
+
+    /// Checks whether the node is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Waits until the node is not paused; it can be used to make custom tasks (e.g. periodic maintenance) honor
+    /// `Node::pause`.
+    pub async fn resumed(&self) {
+        let mut paused = self.paused.subscribe();
+
+        while *paused.borrow_and_update() {
+            // safe; the sender is owned by the node
+            paused.changed().await.unwrap();
+        }
+    }
+
     /// Gracefully shuts the node down.
     pub fn shut_down(&self) {
-        debug!(parent: self.span(), "shutting down");
+        debug!(target: NODE, parent: self.span(), "shutting down");
 
-        if let Some(handle) = self.listening_task.get() {
+        if let Some(handle) = self.listening_task.lock().take() {
+            handle.abort();
+        }
+        if let Some(handle) = self.bootstrap_task.lock().take() {
             handle.abort();
         }
 
-        for addr in self.connected_addrs() {
-            self.disconnect(addr);
+        #[cfg(feature = "nat")]
+        if let Some(handle) = self.port_mapping_task.lock().take() {
+            handle.abort();
+            self.set_mapped_addr(None);
+            // remove the mapping in the background
+            if let Some(addr) = self.listening_addr() {
+                let node = self.clone();
+                self.spawn_task(format_args!("nat-unmap"), async move {
+                    unmap_port(&node, addr.port()).await
+                });
+            }
         }
 
+        // disconnecting from all the peers mustn't be mistaken for a partition
+        self.partition_detector.suspend();
+        self.disconnect_all();
+
         if let Some(handler) = self.handshake_handler() {
             handler.task.abort();
         }
@@ -401,31 +3547,290 @@ impl Node {
         if let Some(handler) = self.writing_handler() {
             handler.task.abort();
         }
+        // the injector and the typed serializers hold references to the node
+        #[cfg(feature = "test-utils")]
+        self.inbound_injector.lock().take();
+        self.typed_serializers.write().clear();
+        if let Some(task) = self.protocols.keepalive_task.get() {
+            task.abort();
+        }
+        if let Some(task) = self.protocols.writer_watchdog_task.get() {
+            task.abort();
+        }
+        if let Some(task) = self.protocols.acking_task.get() {
+            task.abort();
+        }
+        if let Some(task) = self.protocols.pex_task.get() {
+            task.abort();
+        }
+        for task in self.protocols.ordering_lane_tasks.lock().drain(..) {
+            task.abort();
+        }
+        if let Some((_, task)) = self.protocols.rehandshake_handler.get() {
+            task.abort();
+        }
+        if let Some((_, task)) = self.protocols.pubsub_handler.get() {
+            task.abort();
+        }
+        if let Some(handler) = self.protocols.multiplexing_handler.get() {
+            handler.abort();
+        }
+
+        self.scheduler.shut_down();
+        self.incoming.clear();
+        self.channels.clear();
+    }
+}
+
+/// Unregisters a pending inbound connection once it's fully established or fails.
+struct PendingInboundGuard {
+    node: Node,
+    addr: SocketAddr,
+}
+
+impl Drop for PendingInboundGuard {
+    fn drop(&mut self) {
+        self.node.pending_inbound.lock().remove(&self.addr);
+    }
+}
+
+/// Releases the reservation of a peer's identity once its connection is registered or fails.
+#[cfg(feature = "identity")]
+struct PendingIdentityGuard {
+    node: Node,
+    peer_id: PeerId,
+}
+
+#[cfg(feature = "identity")]
+impl Drop for PendingIdentityGuard {
+    fn drop(&mut self) {
+        self.node.pending_identities.lock().remove(&self.peer_id);
+    }
+}
+
+/// Removes an address from the list of pending connections once the connection attempt is concluded.
+struct ConnectingGuard<'a> {
+    node: &'a Node,
+    addr: SocketAddr,
+}
+
+impl Drop for ConnectingGuard<'_> {
+    fn drop(&mut self) {
+        self.node.connecting.lock().remove(&self.addr);
     }
 }
 
 // FIXME: this can probably be done more elegantly
-/// Creates the node's tracing span based on its name.
+/// Creates a tracing span with the most verbose level that is enabled, so that it's not disabled by a subscriber
+/// with a less verbose filter.
+macro_rules! create_span {
+    ($($args: tt)+) => {{
+        let mut span = trace_span!($($args)+);
+        if span.is_disabled() {
+            span = debug_span!($($args)+);
+        }
+        if span.is_disabled() {
+            span = info_span!($($args)+);
+        }
+        if span.is_disabled() {
+            span = warn_span!($($args)+);
+        }
+        if span.is_disabled() {
+            span = error_span!($($args)+);
+        }
+        span
+    }};
+}
+
+/// Creates the node's tracing span based on its name; its listening address is recorded later.
 fn create_span(node_name: &str) -> Span {
-    let mut span = trace_span!("node", name = node_name);
-    if !span.is_disabled() {
-        return span;
+    create_span!("node", name = node_name, addr = field::Empty)
+}
+
+/// Creates a connection's tracing span based on its ID and address; it is a child of the node's span.
+pub(crate) fn create_conn_span(node: &Node, id: usize, addr: SocketAddr) -> Span {
+    with_dispatch(
+        node.config(),
+        || create_span!(parent: node.span(), "conn", id, %addr),
+    )
+}
+
+/// Spawns a task with the given name on the given runtime (or the current one), so that it can be identified in
+/// `tokio-console`.
+#[cfg(all(feature = "tokio-console", tokio_unstable))]
+fn spawn_named<F>(
+    runtime: Option<&Handle>,
+    name: fmt::Arguments<'_>,
+    future: F,
+) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let name = name.to_string();
+    let builder = tokio::task::Builder::new().name(&name);
+    // safe; spawning on a runtime doesn't fail
+    if let Some(runtime) = runtime {
+        builder.spawn_on(future, runtime).unwrap()
     } else {
-        span = debug_span!("node", name = node_name);
+        builder.spawn(future).unwrap()
     }
-    if !span.is_disabled() {
-        return span;
+}
+
+/// Spawns a task on the given runtime (or the current one); its name is only used with the `tokio-console` feature.
+#[cfg(not(all(feature = "tokio-console", tokio_unstable)))]
+fn spawn_named<F>(
+    runtime: Option<&Handle>,
+    _name: fmt::Arguments<'_>,
+    future: F,
+) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if let Some(runtime) = runtime {
+        runtime.spawn(future)
     } else {
-        span = info_span!("node", name = node_name);
+        tokio::spawn(future)
     }
-    if !span.is_disabled() {
-        return span;
+}
+
+/// Spawns a task with the given name on the given runtime (or the current one) as part of the given `JoinSet`.
+#[cfg(all(feature = "tokio-console", tokio_unstable))]
+fn spawn_named_in_set<F>(
+    set: &mut JoinSet<F::Output>,
+    runtime: Option<&Handle>,
+    name: fmt::Arguments<'_>,
+    future: F,
+) where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let name = name.to_string();
+    let builder = set.build_task().name(&name);
+    // safe; spawning on a runtime doesn't fail
+    if let Some(runtime) = runtime {
+        builder.spawn_on(future, runtime).unwrap();
     } else {
-        span = warn_span!("node", name = node_name);
+        builder.spawn(future).unwrap();
     }
-    if !span.is_disabled() {
-        span
+}
+
+/// Spawns a task on the given runtime (or the current one) as part of the given `JoinSet`; its name is only used
+/// with the `tokio-console` feature.
+#[cfg(not(all(feature = "tokio-console", tokio_unstable)))]
+fn spawn_named_in_set<F>(
+    set: &mut JoinSet<F::Output>,
+    runtime: Option<&Handle>,
+    _name: fmt::Arguments<'_>,
+    future: F,
+) where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if let Some(runtime) = runtime {
+        set.spawn_on(future, runtime);
+    } else {
+        set.spawn(future);
+    }
+}
+
+/// Binds the node's listener, trying `NodeConfig.desired_listening_port` first, then the ports in
+/// `NodeConfig.listening_port_range` in order, and finally a random port, if it's allowed.
+async fn bind_listener(config: &NodeConfig, span: &Span) -> io::Result<TcpListener> {
+    let listener_ip = config.listener_ip;
+    let mut last_error = None;
+
+    if let Some(port) = config.desired_listening_port {
+        let desired_listening_addr = SocketAddr::new(listener_ip, port);
+        match on_runtime(config, TcpListener::bind(desired_listening_addr)).await {
+            Ok(listener) => return Ok(listener),
+            Err(e) => {
+                warn!(target: NODE, parent: span, "the desired port is unavailable: {}", e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    if let Some(ref ports) = config.listening_port_range {
+        for port in ports.clone() {
+            match on_runtime(
+                config,
+                TcpListener::bind(SocketAddr::new(listener_ip, port)),
+            )
+            .await
+            {
+                Ok(listener) => return Ok(listener),
+                Err(e) => {
+                    debug!(target: NODE, parent: span, "port {} is unavailable: {}", port, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        warn!(target: NODE, parent: span, "none of the ports in {}..={} is available", ports.start(), ports.end());
+    }
+
+    if config.allow_random_port {
+        if last_error.is_some() {
+            warn!(target: NODE, parent: span, "trying any port");
+        }
+        let random_available_addr = SocketAddr::new(listener_ip, 0);
+        on_runtime(config, TcpListener::bind(random_available_addr)).await
+    } else {
+        // the config is validated, so if a random port isn't allowed, some other one was tried
+        let e = last_error.unwrap();
+        error!(target: NODE, parent: span, "couldn't bind to any of the allowed ports: {}", e);
+        Err(e)
+    }
+}
+
+/// Runs the given I/O future on `NodeConfig.runtime` (if specified), so that the sockets it creates are registered
+/// with that runtime; otherwise it's simply awaited.
+async fn on_runtime<T, F>(config: &NodeConfig, future: F) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let runtime = match &config.runtime {
+        Some(runtime) => runtime,
+        None => return future.await,
+    };
+
+    match runtime.spawn(future).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        // the runtime is shutting down
+        Err(_) => Err(io::ErrorKind::Interrupted.into()),
+    }
+}
+
+/// Reorders the given addresses so that their IP address families alternate, starting with the family of the
+/// first one, while retaining their order within each family.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (first, mut second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    let mut second = second.drain(..);
+
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    for addr in first {
+        interleaved.push(addr);
+        interleaved.extend(second.next());
+    }
+    interleaved.extend(second);
+
+    interleaved
+}
+
+/// Calls the given function with the node's `tracing` dispatcher (if there is one) set as the default.
+fn with_dispatch<T>(config: &NodeConfig, f: impl FnOnce() -> T) -> T {
+    if let Some(dispatch) = &config.tracing_dispatch {
+        dispatcher::with_default(dispatch, f)
     } else {
-        error_span!("node", name = node_name)
+        f()
     }
 }
@@ -1,4 +1,12 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use crate::{
+    bandwidth::{BandwidthRates, RateMeter},
+    histogram::{Histogram, HistogramSnapshot},
+};
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 /// Contains statistics related to the node.
 #[derive(Default)]
@@ -11,6 +19,20 @@ pub struct NodeStats {
     bytes_sent: AtomicU64,
     /// The number of all bytes received.
     bytes_received: AtomicU64,
+    /// The number of all inbound messages dropped due to their processing task not running.
+    msgs_dropped: AtomicU64,
+    /// The number of all inbound messages dropped due to their processing queue being full.
+    msgs_overflowed: AtomicU64,
+    /// Measures the rate of sending.
+    send_rate: RateMeter,
+    /// Measures the rate of receiving.
+    receive_rate: RateMeter,
+    /// The sizes of the sent messages.
+    outbound_sizes: Histogram,
+    /// The sizes of the received messages.
+    inbound_sizes: Histogram,
+    /// The times from the receipt of the messages to the completion of their processing, in microseconds.
+    processing_times: Histogram,
 }
 
 impl NodeStats {
@@ -18,6 +40,8 @@ impl NodeStats {
     pub fn register_sent_message(&self, size: usize) {
         self.msgs_sent.fetch_add(1, Ordering::Relaxed);
         self.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+        self.send_rate.register(size);
+        self.outbound_sizes.record(size as u64);
     }
 
     /// Registers a received message of the provided `size` in bytes.
@@ -25,6 +49,19 @@ impl NodeStats {
         self.msgs_received.fetch_add(1, Ordering::Relaxed);
         self.bytes_received
             .fetch_add(size as u64, Ordering::Relaxed);
+        self.receive_rate.register(size);
+        self.inbound_sizes.record(size as u64);
+    }
+
+    /// Registers the time from the receipt of an inbound message to the completion of its processing.
+    pub fn register_processing_time(&self, time: Duration) {
+        self.processing_times
+            .record(time.as_micros().min(u64::MAX as u128) as u64);
+    }
+
+    /// Registers an inbound message that was dropped.
+    pub fn register_dropped_message(&self) {
+        self.msgs_dropped.fetch_add(1, Ordering::Relaxed);
     }
 
     /// Returns the number of sent messages and their collective size in bytes.
@@ -42,4 +79,43 @@ impl NodeStats {
 
         (msgs, bytes)
     }
+
+    /// Registers an inbound message that was dropped due to an overflow.
+    pub fn register_overflowed_message(&self) {
+        self.msgs_overflowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the rates of sending and receiving over the last 5 seconds.
+    pub fn rates(&self) -> BandwidthRates {
+        BandwidthRates {
+            sent_per_sec: self.send_rate.rate(),
+            received_per_sec: self.receive_rate.rate(),
+        }
+    }
+
+    /// Returns the number of dropped inbound messages.
+    pub fn dropped(&self) -> u64 {
+        self.msgs_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of inbound messages dropped due to overflows.
+    pub fn overflowed(&self) -> u64 {
+        self.msgs_overflowed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the histogram of the sizes of the sent messages, in bytes.
+    pub fn outbound_message_sizes(&self) -> HistogramSnapshot {
+        self.outbound_sizes.snapshot()
+    }
+
+    /// Returns the histogram of the sizes of the received messages, in bytes.
+    pub fn inbound_message_sizes(&self) -> HistogramSnapshot {
+        self.inbound_sizes.snapshot()
+    }
+
+    /// Returns the histogram of the times from the receipt of the inbound messages to the completion of their
+    /// processing (see `Reading::process_message`), in microseconds; it includes the time spent in the queues.
+    pub fn processing_times(&self) -> HistogramSnapshot {
+        self.processing_times.snapshot()
+    }
 }
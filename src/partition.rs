@@ -0,0 +1,117 @@
+use crate::PartitionDetection;
+
+use parking_lot::Mutex;
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// The reason why a network partition (or a loss of local connectivity) is suspected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionSignal {
+    /// A large fraction of the peers was lost within a short window.
+    PeerLoss {
+        /// The number of peers lost within the window.
+        lost: usize,
+        /// The number of peers the node was connected to at the beginning of the window.
+        previously_connected: usize,
+    },
+    /// All of the recent outbound connection attempts have failed.
+    DialFailures(usize),
+}
+
+/// Detects the symptoms of likely network partitions; see `NodeConfig.partition_detection`.
+#[derive(Default)]
+pub(crate) struct PartitionDetector(Mutex<DetectorState>);
+
+#[derive(Default)]
+struct DetectorState {
+    /// The times of the recent disconnections.
+    disconnects: VecDeque<Instant>,
+    /// The number of consecutive failed outbound connection attempts.
+    dial_failures: usize,
+    /// Indicates whether a partition is currently suspected.
+    suspected: bool,
+    /// Indicates whether the detection is suspended, e.g. because the node is shutting down.
+    suspended: bool,
+}
+
+impl PartitionDetector {
+    /// Registers a disconnection, with the given number of peers remaining connected; returns a signal if it
+    /// completes the symptoms of a partition.
+    pub(crate) fn register_disconnect(
+        &self,
+        config: &PartitionDetection,
+        num_connected: usize,
+    ) -> Option<PartitionSignal> {
+        let mut state = self.0.lock();
+        if state.suspended {
+            return None;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(config.window_secs);
+        while let Some(time) = state.disconnects.front() {
+            if now.duration_since(*time) > window {
+                state.disconnects.pop_front();
+            } else {
+                break;
+            }
+        }
+        state.disconnects.push_back(now);
+
+        let lost = state.disconnects.len();
+        let previously_connected = num_connected + lost;
+        if state.suspected
+            || previously_connected < config.min_peers
+            || (lost as f64) < config.min_lost_fraction * previously_connected as f64
+        {
+            return None;
+        }
+        state.suspected = true;
+        state.disconnects.clear();
+
+        Some(PartitionSignal::PeerLoss {
+            lost,
+            previously_connected,
+        })
+    }
+
+    /// Registers an established connection, which indicates that the node isn't isolated.
+    pub(crate) fn register_connection(&self) {
+        let mut state = self.0.lock();
+        state.dial_failures = 0;
+        state.suspected = false;
+    }
+
+    /// Registers a failed outbound connection attempt; returns a signal if it completes the symptoms of a
+    /// partition.
+    pub(crate) fn register_dial_failure(
+        &self,
+        config: &PartitionDetection,
+    ) -> Option<PartitionSignal> {
+        let mut state = self.0.lock();
+        if state.suspended {
+            return None;
+        }
+
+        state.dial_failures += 1;
+        if state.suspected || state.dial_failures < config.max_dial_failures {
+            return None;
+        }
+        state.suspected = true;
+
+        Some(PartitionSignal::DialFailures(state.dial_failures))
+    }
+
+    /// Checks whether a partition is currently suspected.
+    pub(crate) fn is_suspected(&self) -> bool {
+        self.0.lock().suspected
+    }
+
+    /// Suspends the detection, so that e.g. disconnecting from all the peers during a shutdown isn't reported.
+    pub(crate) fn suspend(&self) {
+        self.0.lock().suspended = true;
+    }
+}
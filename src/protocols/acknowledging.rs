@@ -0,0 +1,53 @@
+use crate::{tracing_targets::ACKS, Pea2Pea};
+
+use bytes::Bytes;
+use tokio::time::sleep;
+use tracing::*;
+
+use std::time::Duration;
+
+/// Can be used to acknowledge the receipt of messages at the application level; instead of being sent one by one,
+/// the acks queued via `Acks::queue_ack` are sent in batches, a single one per peer every
+/// `NodeConfig.ack_flush_interval_ms`. The acks received from peers should be registered with
+/// `Acks::register_acked`, which allows `Acks::latency_percentile` to be used.
+///
+/// note: the ack batches are sent via `Node::send_direct_message`, so the `Writing` protocol must be enabled too.
+pub trait Acknowledging: Pea2Pea
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Prepares the node to send batched acks.
+    fn enable_acknowledging(&self) {
+        let flush_interval = Duration::from_millis(self.node().config().ack_flush_interval_ms);
+
+        let self_clone = self.clone();
+        let acking_task = self.node().spawn_task(format_args!("acks"), async move {
+            let node = self_clone.node();
+            trace!(target: ACKS, parent: node.span(), "spawned the Acknowledging task");
+
+            loop {
+                sleep(flush_interval).await;
+                node.resumed().await;
+
+                for (addr, ids) in node.acks().take_pending() {
+                    if !node.is_connected(addr) {
+                        // the peer is gone; its acks are no longer needed
+                        node.acks().remove(addr);
+                        continue;
+                    }
+
+                    trace!(target: ACKS, parent: node.span(), "sending {} acks to {}", ids.len(), addr);
+                    let batch = self_clone.ack_batch_message(&ids);
+                    if let Err(e) = node.send_direct_message(addr, batch).await {
+                        warn!(target: ACKS, parent: node.span(), "couldn't send acks to {}: {}", addr, e);
+                    }
+                }
+            }
+        });
+
+        self.node().set_acking_task(acking_task);
+    }
+
+    /// Serializes a batch of IDs of the messages being acknowledged into a single message.
+    fn ack_batch_message(&self, ids: &[u64]) -> Bytes;
+}
@@ -1,9 +1,30 @@
-use crate::{connections::Connection, protocols::ReturnableConnection, Pea2Pea};
+use crate::{
+    connections::Connection, protocols::ReturnableConnection, tracing_targets::HANDSHAKE, Pea2Pea,
+};
 
-use tokio::{sync::mpsc, time::timeout};
+use bytes::Bytes;
+use fxhash::FxHashMap;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, Semaphore},
+    time::timeout,
+};
 use tracing::*;
 
-use std::{io, time::Duration};
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    error::Error,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+/// The prefix distinguishing an encoded `Rejection` from the regular handshake messages.
+const REJECTION_MAGIC: [u8; 4] = [0xff, b'R', b'J', b'T'];
+/// The size of the header of an encoded `Rejection`: the magic, the code, and the length of the reason.
+const REJECTION_HEADER_LEN: usize = REJECTION_MAGIC.len() + 2 + 2;
 
 /// Can be used to specify and enable network handshakes. Upon establishing a connection, both sides will
 /// need to adhere to the specified handshake rules in order to finalize the connection and be able to send
@@ -13,46 +34,95 @@ pub trait Handshaking: Pea2Pea
 where
     Self: Clone + Send + Sync + 'static,
 {
-    /// Prepares the node to perform specified network handshakes.
+    /// Prepares the node to perform specified network handshakes; up to `NodeConfig.max_concurrent_handshakes`
+    /// handshakes are performed at once, while the pending ones are scheduled fairly across their source IPs.
     fn enable_handshaking(&self) {
         let (from_node_sender, mut from_node_receiver) = mpsc::channel::<ReturnableConnection>(
             self.node().config().protocol_handler_queue_depth,
         );
 
+        let limiter = Arc::new(Semaphore::new(
+            self.node().config().max_concurrent_handshakes as usize,
+        ));
+
         // spawn a background task dedicated to handling the handshakes
         let self_clone = self.clone();
-        let handshaking_task = tokio::spawn(async move {
-            trace!(parent: self_clone.node().span(), "spawned the Handshaking handler task");
+        let handshaking_task = self.node().spawn_task(format_args!("handshaking"), async move {
+            trace!(target: HANDSHAKE, parent: self_clone.node().span(), "spawned the Handshaking handler task");
+
+            let mut pending = PendingHandshakes::default();
 
             loop {
-                if let Some((conn, result_sender)) = from_node_receiver.recv().await {
-                    let addr = conn.addr;
-
-                    debug!(parent: conn.node.span(), "handshaking with {} as the {:?}", addr, !conn.side);
-                    let result = timeout(
-                        Duration::from_millis(conn.node.config().max_handshake_time_ms),
-                        self_clone.perform_handshake(conn),
-                    )
-                    .await;
-
-                    let ret = match result {
-                        Ok(Ok(res)) => {
-                            debug!(parent: self_clone.node().span(), "succeessfully handshaken with {}", addr);
-                            Ok(res)
-                        }
-                        Ok(Err(e)) => {
-                            error!(parent: self_clone.node().span(), "handshake with {} failed: {}", addr, e);
-                            Err(e)
-                        }
-                        Err(_) => {
-                            error!(parent: self_clone.node().span(), "handshake with {} timed out", addr);
-                            Err(io::ErrorKind::TimedOut.into())
-                        }
-                    };
+                // wait for a connection if there are no pending ones
+                if pending.is_empty() {
+                    if let Some(returnable_conn) = from_node_receiver.recv().await {
+                        pending.push(returnable_conn);
+                    } else {
+                        error!(target: HANDSHAKE, "the Handshaking protocol is down!");
+                        break;
+                    }
+                }
+
+                // keep collecting the pending connections until the limiter allows another handshake
+                tokio::select! {
+                    biased;
 
-                    // return the Connection to the Node, resuming Node::adapt_stream
-                    if result_sender.send(ret).is_err() {
-                        unreachable!(); // can't recover if this happens
+                    Ok(permit) = limiter.clone().acquire_owned() => {
+                        // safe; there is at least one pending connection at this point
+                        let (conn, result_sender) = pending.pop().unwrap();
+                        let self_clone = self_clone.clone();
+                        let addr = conn.addr;
+
+                        conn.node.clone().spawn_supervised_task(format_args!("handshake:{}", addr), addr, async move {
+                            let span = conn.span().clone();
+
+                            debug!(target: HANDSHAKE, parent: &span, "handshaking with {} as the {:?}", addr, !conn.side);
+                            let result = timeout(
+                                Duration::from_millis(conn.node.config().max_handshake_time_ms),
+                                self_clone.perform_handshake(conn),
+                            )
+                            .await;
+
+                            // free the slot for another handshake
+                            drop(permit);
+
+                            let ret = match result {
+                                Ok(Ok(res)) => {
+                                    debug!(target: HANDSHAKE, parent: &span, "succeessfully handshaken with {}", addr);
+                                    Ok(res)
+                                }
+                                Ok(Err(e)) => {
+                                    if let Some(rejection) = Rejection::from_error(&e) {
+                                        if e.kind() == io::ErrorKind::PermissionDenied {
+                                            warn!(target: HANDSHAKE, parent: &span, "rejected {}: {}", addr, rejection);
+                                        } else {
+                                            warn!(target: HANDSHAKE, parent: &span, "rejected by {}: {}", addr, rejection);
+                                        }
+                                    } else {
+                                        error!(target: HANDSHAKE, parent: &span, "handshake with {} failed: {}", addr, e);
+                                    }
+                                    Err(e)
+                                }
+                                Err(_) => {
+                                    error!(target: HANDSHAKE, parent: &span, "handshake with {} timed out", addr);
+                                    Err(io::ErrorKind::TimedOut.into())
+                                }
+                            };
+
+                            // return the Connection to the Node, resuming Node::adapt_stream
+                            if result_sender.send(ret).is_err() {
+                                // the connection attempt was cancelled in the meantime
+                                debug!(target: HANDSHAKE, parent: &span, "the connection with {} was cancelled", addr);
+                            }
+                        });
+                    }
+                    returnable_conn = from_node_receiver.recv() => {
+                        if let Some(returnable_conn) = returnable_conn {
+                            pending.push(returnable_conn);
+                        } else {
+                            error!(target: HANDSHAKE, "the Handshaking protocol is down!");
+                            break;
+                        }
                     }
                 }
             }
@@ -63,6 +133,198 @@ where
     }
 
     /// Performs the handshake; temporarily assumes control of the `Connection` and returns it if the handshake is
-    /// successful.
+    /// successful. The peer can be rejected with a reason via `Handshaking::reject`, and the rejections sent by the
+    /// peer can be detected in the received handshake messages via `Rejection::check`.
     async fn perform_handshake(&self, conn: Connection) -> io::Result<Connection>;
+
+    /// Frames the given encoded `Rejection` the way the handshake messages are framed (e.g. by prefixing it with its
+    /// length), so that the peer reads it as a whole handshake message. The protocols that frame their handshake
+    /// messages need to override it; by default, the encoded rejection is sent as is.
+    fn frame_rejection(&self, encoded: Vec<u8>) -> Vec<u8> {
+        encoded
+    }
+
+    /// Sends the given rejection to the peer (on a best-effort basis), framed via `Handshaking::frame_rejection`,
+    /// and returns an error accompanied by it, which is meant to be returned from `perform_handshake`.
+    async fn reject(&self, conn: &mut Connection, rejection: Rejection) -> io::Error {
+        let frame = self.frame_rejection(rejection.encode());
+        if let Some(ref mut writer) = conn.writer {
+            if let Err(e) = writer.write_all(&frame).await {
+                debug!(target: HANDSHAKE, parent: conn.span(), "couldn't send the rejection to {}: {}", conn.addr, e);
+            }
+        }
+
+        io::Error::new(io::ErrorKind::PermissionDenied, rejection)
+    }
+
+    /// Creates the handshake payload of an in-band rehandshake with the given connected peer (see
+    /// `Node::rehandshake`), e.g. refreshed keys; it's passed to the peer's `process_rehandshake_payload`. By
+    /// default, it's empty.
+    fn rehandshake_payload(&self, _addr: SocketAddr) -> io::Result<Bytes> {
+        Ok(Bytes::new())
+    }
+
+    /// Processes the handshake payload of an in-band rehandshake received from the given connected peer; an error
+    /// fails the rehandshake and disconnects the peer. By default, the payload is ignored.
+    async fn process_rehandshake_payload(
+        &self,
+        _source: SocketAddr,
+        _payload: Bytes,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reason for rejecting a connection during the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionCode {
+    /// The peer belongs to a different network (e.g. its network magic doesn't match).
+    WrongNetwork,
+    /// The peer's protocol version is not supported.
+    IncompatibleVersion,
+    /// The node can't accept any more peers.
+    TooManyPeers,
+    /// The peer is banned.
+    Banned,
+    /// An application-specific reason.
+    Other(u16),
+}
+
+impl RejectionCode {
+    fn to_u16(self) -> u16 {
+        match self {
+            Self::WrongNetwork => 1,
+            Self::IncompatibleVersion => 2,
+            Self::TooManyPeers => 3,
+            Self::Banned => 4,
+            Self::Other(code) => code,
+        }
+    }
+
+    fn from_u16(code: u16) -> Self {
+        match code {
+            1 => Self::WrongNetwork,
+            2 => Self::IncompatibleVersion,
+            3 => Self::TooManyPeers,
+            4 => Self::Banned,
+            code => Self::Other(code),
+        }
+    }
+}
+
+/// A rejection of a connection during the handshake; it is sent to the peer via `Handshaking::reject`, and it
+/// accompanies the resulting `io::Error` on both sides (including the one returned by `Node::connect`), where it
+/// can be obtained via `Rejection::from_error`. The error is of kind `PermissionDenied` on the rejecting side, and
+/// `ConnectionRefused` on the rejected one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    /// The reason code.
+    pub code: RejectionCode,
+    /// An optional human-readable description.
+    pub reason: String,
+}
+
+impl Rejection {
+    /// Creates a new `Rejection`.
+    pub fn new<T: Into<String>>(code: RejectionCode, reason: T) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns the rejection accompanying the given error, if there is one.
+    pub fn from_error(error: &io::Error) -> Option<&Self> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+
+    /// Returns an error accompanied by the rejection received from the peer if the given (whole, unframed) handshake
+    /// message is one.
+    pub fn check(message: &[u8]) -> io::Result<()> {
+        match Self::decode(message) {
+            Some(rejection) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, rejection)),
+            None => Ok(()),
+        }
+    }
+
+    /// Encodes the rejection for transmission.
+    pub fn encode(&self) -> Vec<u8> {
+        let reason = &self.reason.as_bytes()[..self.reason.len().min(u16::MAX as usize)];
+
+        let mut bytes = Vec::with_capacity(REJECTION_HEADER_LEN + reason.len());
+        bytes.extend_from_slice(&REJECTION_MAGIC);
+        bytes.extend_from_slice(&self.code.to_u16().to_le_bytes());
+        bytes.extend_from_slice(&(reason.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(reason);
+
+        bytes
+    }
+
+    /// Decodes a rejection; returns `None` if the given bytes don't start with one.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < REJECTION_HEADER_LEN || bytes[..REJECTION_MAGIC.len()] != REJECTION_MAGIC {
+            return None;
+        }
+
+        let header = &bytes[REJECTION_MAGIC.len()..REJECTION_HEADER_LEN];
+        let code = u16::from_le_bytes(header[..2].try_into().unwrap());
+        let len = u16::from_le_bytes(header[2..].try_into().unwrap()) as usize;
+        let reason = bytes.get(REJECTION_HEADER_LEN..REJECTION_HEADER_LEN + len)?;
+
+        Some(Self {
+            code: RejectionCode::from_u16(code),
+            reason: String::from_utf8_lossy(reason).into_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reason.is_empty() {
+            write!(f, "{:?}", self.code)
+        } else {
+            write!(f, "{:?} ({})", self.code, self.reason)
+        }
+    }
+}
+
+impl Error for Rejection {}
+
+/// Connections awaiting a handshake; instead of being processed in a FIFO manner, they are picked from their
+/// source IPs in a round-robin fashion, so that a single IP opening many connections can't delay the others.
+#[derive(Default)]
+struct PendingHandshakes {
+    /// The pending connections, grouped by their source IPs.
+    queues: FxHashMap<IpAddr, VecDeque<ReturnableConnection>>,
+    /// The order in which the source IPs are served.
+    order: VecDeque<IpAddr>,
+}
+
+impl PendingHandshakes {
+    fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    fn push(&mut self, returnable_conn: ReturnableConnection) {
+        let ip = returnable_conn.0.addr.ip();
+        let queue = self.queues.entry(ip).or_default();
+        if queue.is_empty() {
+            self.order.push_back(ip);
+        }
+        queue.push_back(returnable_conn);
+    }
+
+    fn pop(&mut self) -> Option<ReturnableConnection> {
+        let ip = self.order.pop_front()?;
+        let queue = self.queues.get_mut(&ip)?;
+        let returnable_conn = queue.pop_front();
+
+        if queue.is_empty() {
+            self.queues.remove(&ip);
+        } else {
+            self.order.push_back(ip);
+        }
+
+        returnable_conn
+    }
 }
@@ -0,0 +1,134 @@
+use crate::{tracing_targets::KEEPALIVE, Pea2Pea};
+
+use bytes::Bytes;
+use fxhash::{FxHashMap, FxHashSet};
+use tokio::time::sleep;
+use tracing::*;
+
+use std::{cmp, net::SocketAddr, time::Duration};
+
+/// Can be used to keep idle connections alive (e.g. in order to prevent NAT bindings from expiring) by sending
+/// keep-alive messages to peers that haven't exchanged any messages with the node for a while. The keep-alive
+/// interval is learned per peer: it is halved whenever a connection breaks while idle and lengthened by a quarter
+/// whenever a keep-alive message successfully kept an idle connection alive. The learned intervals are stored in
+/// the peers' `PeerStats`, so for peers the node connects to, they are retained across connections.
+///
+/// note: the keep-alive messages are sent via the `Writing` protocol, so it must be enabled too; unlike other
+/// messages, they are also sent while the node is paused.
+pub trait KeepAlive: Pea2Pea
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Prepares the node to send keep-alive messages to idle connections.
+    fn enable_keepalive(&self) {
+        let config = self.node().config();
+        let initial_interval = Duration::from_millis(config.keepalive_interval_ms);
+        let min_interval = Duration::from_millis(config.min_keepalive_interval_ms);
+        let max_interval = Duration::from_millis(config.max_keepalive_interval_ms);
+        // check the connections twice as often as the shortest possible interval, so that it can be upheld
+        let check_interval = cmp::max(min_interval / 2, Duration::from_millis(1));
+
+        let self_clone = self.clone();
+        let keepalive_task = self.node().spawn_task(format_args!("keepalive"), async move {
+            let node = self_clone.node();
+            trace!(target: KEEPALIVE, parent: node.span(), "spawned the KeepAlive task");
+
+            // the per-connection state observed during the previous check
+            let mut observed: FxHashMap<SocketAddr, ObservedConn> = Default::default();
+
+            loop {
+                sleep(check_interval).await;
+
+                let connected = node.connected_addrs().into_iter().collect::<FxHashSet<_>>();
+                let mut idle_addrs = Vec::new();
+
+                {
+                    let mut known_peers = node.known_peers().write();
+
+                    // learn from the connections that have broken since the previous check
+                    for (addr, conn) in observed
+                        .iter()
+                        .filter(|(addr, _)| !connected.contains(addr))
+                    {
+                        if let Some(stats) = known_peers.get_mut(&conn.canonical_addr) {
+                            let interval = stats.keepalive_interval.get_or_insert(initial_interval);
+                            if stats.failures > conn.failures && conn.idle >= *interval / 2 {
+                                *interval = cmp::max(*interval / 2, min_interval);
+                                debug!(
+                                    target: KEEPALIVE, parent: node.span(),
+                                    "the connection with {} broke while idle; keep-alive interval: {:?}", addr, interval
+                                );
+                            }
+                        }
+                    }
+                    observed.retain(|addr, _| connected.contains(addr));
+
+                    for addr in &connected {
+                        // the aliases are removed along with the connections, so they're resolved up front
+                        let canonical_addr = node.known_peers().canonical_addr(*addr);
+                        let stats = if let Some(stats) = known_peers.get_mut(&canonical_addr) {
+                            stats
+                        } else {
+                            continue;
+                        };
+                        let idle = stats
+                            .last_activity()
+                            .map(|t| t.elapsed())
+                            .unwrap_or_default();
+                        let conn = observed.entry(*addr).or_insert_with(|| ObservedConn {
+                            canonical_addr,
+                            failures: stats.failures,
+                            idle,
+                            keepalive_sent: false,
+                        });
+                        let interval = stats.keepalive_interval.get_or_insert(initial_interval);
+
+                        // the previous keep-alive message has kept the idle connection alive
+                        if conn.keepalive_sent && stats.failures == conn.failures {
+                            *interval = cmp::min(*interval + *interval / 4, max_interval);
+                            trace!(
+                                target: KEEPALIVE, parent: node.span(),
+                                "the connection with {} is stable; keep-alive interval: {:?}", addr, interval
+                            );
+                        }
+
+                        conn.keepalive_sent = idle >= *interval;
+                        conn.failures = stats.failures;
+                        conn.idle = idle;
+
+                        if conn.keepalive_sent {
+                            idle_addrs.push(*addr);
+                        }
+                    }
+                }
+
+                for addr in idle_addrs {
+                    trace!(target: KEEPALIVE, parent: node.span(), "sending a keep-alive message to {}", addr);
+                    if let Err(e) = node
+                        .send_unpausable_message(addr, self_clone.keepalive_message())
+                        .await
+                    {
+                        warn!(target: KEEPALIVE, parent: node.span(), "couldn't send a keep-alive message to {}: {}", addr, e);
+                    }
+                }
+            }
+        });
+
+        self.node().set_keepalive_task(keepalive_task);
+    }
+
+    /// Returns the keep-alive message to be sent to idle connections.
+    fn keepalive_message(&self) -> Bytes;
+}
+
+/// The state of a connection as observed by the `KeepAlive` protocol.
+struct ObservedConn {
+    /// The canonical address of the peer (see `KnownPeers::canonical_addr`).
+    canonical_addr: SocketAddr,
+    /// The number of failures related to the peer.
+    failures: u8,
+    /// The time elapsed since the last activity of the connection.
+    idle: Duration,
+    /// Indicates whether a keep-alive message was sent to the peer.
+    keepalive_sent: bool,
+}
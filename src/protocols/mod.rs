@@ -2,30 +2,62 @@
 //! `Node`'s lifetime and handles a specific functionality. The communication with these tasks is done via
 //! `ProtocolHandler`s.
 
-use crate::connections::Connection;
+use crate::{connections::Connection, tracing_targets::NODE};
 
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use tokio::{
     sync::{mpsc, oneshot},
     task::JoinHandle,
 };
 use tracing::*;
 
-use std::io;
+use bytes::Bytes;
 
+use std::{io, net::SocketAddr, ops::Deref};
+
+mod acknowledging;
 mod handshaking;
+mod keepalive;
+mod multiplexing;
+mod nacking;
+mod peer_exchange;
+mod pubsub;
 mod reading;
+mod rehandshaking;
+mod transform;
 mod writing;
 
-pub use handshaking::Handshaking;
-pub use reading::Reading;
-pub use writing::Writing;
+pub use acknowledging::Acknowledging;
+pub use handshaking::{Handshaking, Rejection, RejectionCode};
+pub use keepalive::KeepAlive;
+pub(crate) use multiplexing::{decode_channel_payload, encode_channel_payload, Channels};
+pub use multiplexing::{Multiplexing, MultiplexingHandler};
+pub use nacking::Nacking;
+pub(crate) use peer_exchange::decode_pex;
+pub use peer_exchange::PeerExchange;
+pub use pubsub::PubSub;
+pub(crate) use pubsub::{decode_pubsub, encode_pubsub, PubSubKind, Topics};
+pub use reading::{processing_deadline, responder, Reading, Responder};
+pub(crate) use rehandshaking::RehandshakeStep;
+pub use rehandshaking::Rehandshaking;
+pub use transform::StreamTransform;
+pub(crate) use transform::{TransformingReader, TransformingWriter};
+pub use writing::{TypedWriting, Writing};
 
 #[derive(Default)]
 pub(crate) struct Protocols {
     pub(crate) handshake_handler: OnceCell<ProtocolHandler>,
     pub(crate) reading_handler: OnceCell<ProtocolHandler>,
     pub(crate) writing_handler: OnceCell<ProtocolHandler>,
+    pub(crate) keepalive_task: OnceCell<JoinHandle<()>>,
+    pub(crate) writer_watchdog_task: OnceCell<JoinHandle<()>>,
+    pub(crate) acking_task: OnceCell<JoinHandle<()>>,
+    pub(crate) pex_task: OnceCell<JoinHandle<()>>,
+    pub(crate) ordering_lane_tasks: Mutex<Vec<JoinHandle<()>>>,
+    pub(crate) rehandshake_handler: OnceCell<RehandshakeHandler>,
+    pub(crate) pubsub_handler: OnceCell<PubSubHandler>,
+    pub(crate) multiplexing_handler: OnceCell<MultiplexingHandler>,
 }
 
 /// An object dedicated to managing a protocol; it contains a `Sender` whose other side is
@@ -39,7 +71,7 @@ impl ProtocolHandler {
     /// Sends a returnable `Connection` to a task spawned by the protocol handler.
     pub async fn send(&self, returnable_conn: ReturnableConnection) {
         if self.sender.send(returnable_conn).await.is_err() {
-            error!("A protocol handler's Receiver is closed");
+            error!(target: NODE, "A protocol handler's Receiver is closed");
         }
     }
 }
@@ -50,6 +82,99 @@ impl From<(mpsc::Sender<ReturnableConnection>, JoinHandle<()>)> for ProtocolHand
     }
 }
 
+/// The channel used to pass the steps of rehandshakes (along with the peers' addresses) to the `Rehandshaking`
+/// protocol's task, and a handle to that task.
+pub(crate) type RehandshakeHandler = (mpsc::Sender<(SocketAddr, RehandshakeStep)>, JoinHandle<()>);
+
+/// The channel used to pass pubsub payloads (along with their destinations) to the `PubSub` protocol's task, and
+/// a handle to that task.
+pub type PubSubHandler = (mpsc::Sender<(SocketAddr, Bytes)>, JoinHandle<()>);
+
 /// An object allowing a `Connection` to be "borrowed" from the owning `Node` to enable a protocol
 /// and to be sent back to it once it's done its job.
 pub type ReturnableConnection = (Connection, oneshot::Sender<io::Result<Connection>>);
+
+/// The maximum size of a `Payload` stored inline, i.e. without a heap allocation; it's small enough for an inline
+/// `Payload` not to be larger than a `Bytes` one, so that it doesn't inflate the queues of `OutboundMessage`s.
+pub const MAX_INLINE_PAYLOAD_LEN: usize = 30;
+
+/// The payload of an `OutboundMessage`; small payloads can be stored inline, which makes it possible to send them
+/// without any heap allocation.
+#[derive(Clone)]
+pub struct Payload(PayloadRepr);
+
+#[derive(Clone)]
+enum PayloadRepr {
+    Shared(Bytes),
+    Inline([u8; MAX_INLINE_PAYLOAD_LEN], u8),
+}
+
+impl Payload {
+    /// Checks whether the payload is stored inline.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, PayloadRepr::Inline(..))
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            PayloadRepr::Shared(bytes) => bytes,
+            PayloadRepr::Inline(bytes, len) => &bytes[..*len as usize],
+        }
+    }
+}
+
+impl From<Bytes> for Payload {
+    fn from(bytes: Bytes) -> Self {
+        Self(PayloadRepr::Shared(bytes))
+    }
+}
+
+impl From<&[u8]> for Payload {
+    /// Stores the given bytes inline if they fit, and copies them to the heap otherwise.
+    fn from(bytes: &[u8]) -> Self {
+        if bytes.len() <= MAX_INLINE_PAYLOAD_LEN {
+            let mut inline = [0u8; MAX_INLINE_PAYLOAD_LEN];
+            inline[..bytes.len()].copy_from_slice(bytes);
+            Self(PayloadRepr::Inline(inline, bytes.len() as u8))
+        } else {
+            Self(PayloadRepr::Shared(Bytes::copy_from_slice(bytes)))
+        }
+    }
+}
+
+/// The kind of an `OutboundMessage`, which determines how the `Writing` protocol treats it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A regular message; it's held back while the node is paused, and rejected by closing connections.
+    Regular,
+    /// A critical message (e.g. a keep-alive one); it's written even while the node is paused, and accepted by
+    /// closing connections.
+    Critical,
+    /// A message that is not written, and only marks a point in the queue: its delivery is notified once all the
+    /// messages queued before it are written.
+    Marker,
+}
+
+/// A message queued for the `Writing` protocol.
+pub struct OutboundMessage {
+    /// The payload of the message.
+    pub payload: Payload,
+    /// Used to notify about the outcome of writing the message to the stream, if requested.
+    pub delivery: Option<oneshot::Sender<io::Result<()>>>,
+    /// The kind of the message.
+    pub kind: MessageKind,
+}
+
+impl<T: Into<Payload>> From<T> for OutboundMessage {
+    fn from(payload: T) -> Self {
+        Self {
+            payload: payload.into(),
+            delivery: None,
+            kind: MessageKind::Regular,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{protocols::Reading, tracing_targets::MULTIPLEXING, ConnectionContext, Pea2Pea};
+
+use bytes::Bytes;
+use fxhash::FxHashMap;
+use parking_lot::{Mutex, RwLock};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::*;
+
+use std::{convert::TryInto, io, net::SocketAddr};
+
+/// Can be used to multiplex independent protocols (e.g. sync, gossip and control messages) over a single connection:
+/// every payload sent via `Node::send_on_channel` is tagged with its channel ID, and the ones received from peers are
+/// passed to a dedicated queue of the local reader of their channel (see `Node::register_channel` and
+/// `Multiplexing::register_channel_reader`), so that a busy channel doesn't hold up the others; likewise, the payloads
+/// sent on every channel go through a dedicated queue and task, so a channel whose messages can't be queued for the
+/// `Writing` protocol fast enough doesn't hold up the sends on the other channels. The channel payloads
+/// are carried in-band by regular messages created with `channel_message`; the payloads of such messages received
+/// from peers should be passed to `Node::handle_channel_message`.
+///
+/// note: it requires the `Writing` protocol.
+pub trait Multiplexing: Pea2Pea
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Prepares the node to send messages on channels; the queue and the task of a channel are set up once the
+    /// first message is sent on it.
+    fn enable_multiplexing(&self) {
+        let self_clone = self.clone();
+        let spawn_channel_task = move |channel: u16| {
+            let (payload_sender, mut payload_receiver) = mpsc::channel::<(SocketAddr, Bytes)>(
+                self_clone.node().config().channel_queue_depth,
+            );
+
+            let node = self_clone.node().clone();
+            let self_clone = self_clone.clone();
+            let channel_task = node.spawn_task(
+                format_args!("multiplexing:{}", channel),
+                async move {
+                    let node = self_clone.node();
+                    trace!(target: MULTIPLEXING, parent: node.span(), "spawned a task sending on channel {}", channel);
+
+                    while let Some((addr, payload)) = payload_receiver.recv().await {
+                        let message = self_clone.channel_message(payload);
+                        if let Err(e) = node.send_direct_message(addr, message).await {
+                            warn!(target: MULTIPLEXING, parent: node.span(), "couldn't send a message on channel {} to {}: {}", channel, addr, e);
+                        }
+                    }
+                },
+            );
+
+            (payload_sender, channel_task)
+        };
+
+        self.node()
+            .set_multiplexing_handler(MultiplexingHandler::new(spawn_channel_task));
+    }
+
+    /// Registers a reader of the given channel: every payload received on it is deserialized with its
+    /// `Reading::read_message` (which must consume the whole payload) and processed with its
+    /// `Reading::process_message_with_context`, in a task dedicated to the channel.
+    fn register_channel_reader<R: Reading>(&self, channel: u16, reader: R) -> io::Result<()> {
+        let mut payload_receiver = self.node().register_channel(channel)?;
+
+        let node = self.node().clone();
+        self.node().spawn_task(format_args!("channel:{}", channel), async move {
+            trace!(target: MULTIPLEXING, parent: node.span(), "spawned a task reading channel {}", channel);
+
+            while let Some((source, payload)) = payload_receiver.recv().await {
+                let ctx = node
+                    .connection_context(source)
+                    .unwrap_or_else(|| ConnectionContext::new(source));
+                let message = match reader.read_message(&ctx, &payload) {
+                    Ok(Some((message, len))) if len == payload.len() => message,
+                    Ok(_) => {
+                        error!(target: MULTIPLEXING, parent: node.span(), "a payload from {} on channel {} is not a single message", source, channel);
+                        continue;
+                    }
+                    Err(e) => {
+                        error!(target: MULTIPLEXING, parent: node.span(), "a payload from {} on channel {} is invalid: {}", source, channel, e);
+                        continue;
+                    }
+                };
+
+                if let Err(e) = reader.process_message_with_context(&ctx, message).await {
+                    debug!(target: MULTIPLEXING, parent: node.span(), "couldn't process a message from {} on channel {}: {}", source, channel, e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Wraps the given channel payload in a message that the peer can recognize as a channel message.
+    fn channel_message(&self, payload: Bytes) -> Bytes;
+}
+
+/// The queue passing the payloads sent on a channel (along with their destinations) to its dedicated task, and a
+/// handle to that task.
+type ChannelTask = (mpsc::Sender<(SocketAddr, Bytes)>, JoinHandle<()>);
+
+/// Creates the queue of the given channel, and spawns its task.
+type ChannelTaskSpawner = Box<dyn Fn(u16) -> ChannelTask + Send + Sync>;
+
+/// The queues passing channel payloads (along with their destinations) to the `Multiplexing` protocol's per-channel
+/// tasks, and the handles to those tasks.
+pub struct MultiplexingHandler {
+    spawn_channel_task: ChannelTaskSpawner,
+    channels: Mutex<FxHashMap<u16, ChannelTask>>,
+}
+
+impl MultiplexingHandler {
+    /// Creates a handler setting up the per-channel queues and tasks with the given function.
+    pub(crate) fn new<F>(spawn_channel_task: F) -> Self
+    where
+        F: Fn(u16) -> ChannelTask + Send + Sync + 'static,
+    {
+        Self {
+            spawn_channel_task: Box::new(spawn_channel_task),
+            channels: Default::default(),
+        }
+    }
+
+    /// Returns the queue of the given channel, setting it up if needed.
+    pub(crate) fn queue(&self, channel: u16) -> mpsc::Sender<(SocketAddr, Bytes)> {
+        self.channels
+            .lock()
+            .entry(channel)
+            .or_insert_with(|| (self.spawn_channel_task)(channel))
+            .0
+            .clone()
+    }
+
+    /// Aborts all the per-channel tasks.
+    pub(crate) fn abort(&self) {
+        for (_, (_, task)) in self.channels.lock().drain() {
+            task.abort();
+        }
+    }
+}
+
+/// Creates a channel payload: the channel ID, encoded as a little-endian `u16`, followed by the message.
+pub(crate) fn encode_channel_payload(channel: u16, message: &[u8]) -> Bytes {
+    let mut payload = Vec::with_capacity(2 + message.len());
+    payload.extend_from_slice(&channel.to_le_bytes());
+    payload.extend_from_slice(message);
+
+    payload.into()
+}
+
+/// Decodes a channel payload into its channel ID and the message.
+pub(crate) fn decode_channel_payload(payload: &[u8]) -> io::Result<(u16, &[u8])> {
+    if payload.len() < 2 {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    let channel = u16::from_le_bytes(payload[..2].try_into().unwrap());
+
+    Ok((channel, &payload[2..]))
+}
+
+/// The local readers of the channels.
+#[derive(Default)]
+pub(crate) struct Channels(RwLock<FxHashMap<u16, mpsc::Sender<(SocketAddr, Bytes)>>>);
+
+impl Channels {
+    /// Registers the reader of the given channel; returns `false` if the channel already has one.
+    pub(crate) fn register(&self, channel: u16, sender: mpsc::Sender<(SocketAddr, Bytes)>) -> bool {
+        let mut readers = self.0.write();
+        if readers.get(&channel).map(|reader| !reader.is_closed()) == Some(true) {
+            return false;
+        }
+        readers.insert(channel, sender);
+
+        true
+    }
+
+    /// Unregisters the reader of the given channel; returns `false` if it didn't have one.
+    pub(crate) fn unregister(&self, channel: u16) -> bool {
+        self.0.write().remove(&channel).is_some()
+    }
+
+    /// Unregisters all the readers.
+    pub(crate) fn clear(&self) {
+        self.0.write().clear();
+    }
+
+    /// Returns the sender passing the messages to the reader of the given channel.
+    pub(crate) fn reader(&self, channel: u16) -> Option<mpsc::Sender<(SocketAddr, Bytes)>> {
+        self.0.read().get(&channel).cloned()
+    }
+}
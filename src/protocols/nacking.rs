@@ -0,0 +1,36 @@
+use crate::{tracing_targets::ACKS, Pea2Pea, SeqStatus};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tracing::*;
+
+use std::{io, net::SocketAddr};
+
+/// Can be used to request the retransmission of the sequenced messages (sent via `Node::send_sequenced`) that were
+/// lost on the way: when a gap is detected in the sequence numbers received from a peer, a negative acknowledgement
+/// (NACK) listing the missing ones is sent to it, and once the peer receives it, it can resend them from its
+/// retransmit buffer via `Node::retransmit`.
+///
+/// note: the NACKs are sent via `Node::send_direct_message`, so the `Writing` protocol must be enabled too.
+#[async_trait]
+pub trait Nacking: Pea2Pea
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Registers the sequence number of a message received from the given address, sending a NACK to it if any
+    /// messages preceding it are missing; the message should only be processed if `SeqStatus::is_new`.
+    async fn register_sequenced(&self, source: SocketAddr, seq: u64) -> io::Result<SeqStatus> {
+        let status = self.node().sequences().register_received(source, seq);
+
+        if let SeqStatus::Gap(missing) = &status {
+            debug!(target: ACKS, parent: self.node().span(), "{} messages from {} are missing; sending a NACK", missing.len(), source);
+            let nack = self.nack_message(missing);
+            self.node().send_direct_message(source, nack).await?;
+        }
+
+        Ok(status)
+    }
+
+    /// Serializes a NACK listing the sequence numbers of the missing messages.
+    fn nack_message(&self, missing: &[u64]) -> Bytes;
+}
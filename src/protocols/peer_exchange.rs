@@ -0,0 +1,135 @@
+use crate::{tracing_targets::DISCOVERY, Node, Pea2Pea};
+
+use bytes::Bytes;
+use tokio::time::sleep;
+use tracing::*;
+
+use std::{
+    collections::hash_map::RandomState,
+    convert::TryInto,
+    hash::BuildHasher,
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+/// The size of an address in a peer-exchange payload: a 16B IP (IPv4 ones being IPv4-mapped) and a 2B port.
+const PEX_ADDR_LEN: usize = 18;
+
+/// Can be used to discover peers in small networks by periodically (every `NodeConfig.pex_interval_ms`) sending
+/// every connected peer a randomized subset of up to `NodeConfig.pex_max_addrs` listening addresses of the known
+/// peers that were seen or active within the last `NodeConfig.pex_max_addr_age_secs`; the addresses are carried
+/// in-band by regular messages created with `pex_message`, and the payloads of such messages received from peers
+/// should be passed to `Node::handle_pex_message`, which merges them into `KnownPeers`.
+///
+/// Only the addresses the node has successfully dialed (see `PeerStats::last_dialed`) are shared, so the ones
+/// learned from peers aren't passed on before they are verified.
+///
+/// note: it requires the `Writing` protocol.
+pub trait PeerExchange: Pea2Pea
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Prepares the node to periodically share the addresses of its known peers with the connected ones.
+    fn enable_peer_exchange(&self) {
+        let interval = Duration::from_millis(self.node().config().pex_interval_ms);
+
+        let self_clone = self.clone();
+        let pex_task = self.node().spawn_task(format_args!("peer exchange"), async move {
+            let node = self_clone.node();
+            trace!(target: DISCOVERY, parent: node.span(), "spawned the PeerExchange task");
+
+            loop {
+                sleep(interval).await;
+
+                for addr in node.connected_addrs() {
+                    let addrs = sample_known_addrs(node, addr);
+                    if addrs.is_empty() {
+                        continue;
+                    }
+
+                    trace!(target: DISCOVERY, parent: node.span(), "sharing {} address(es) with {}", addrs.len(), addr);
+                    let message = self_clone.pex_message(encode_pex(&addrs));
+                    if let Err(e) = node.send_direct_message(addr, message).await {
+                        warn!(target: DISCOVERY, parent: node.span(), "couldn't send a peer-exchange message to {}: {}", addr, e);
+                    }
+                }
+            }
+        });
+
+        self.node().set_pex_task(pex_task);
+    }
+
+    /// Wraps the given peer-exchange payload in a message that the peer can recognize as a peer-exchange message.
+    fn pex_message(&self, payload: Bytes) -> Bytes;
+}
+
+/// Returns a randomized subset of the verified listening addresses of the node's recently seen or active known
+/// peers, to be shared with the given peer.
+fn sample_known_addrs(node: &Node, recipient: SocketAddr) -> Vec<SocketAddr> {
+    let config = node.config();
+    let max_age = Duration::from_secs(config.pex_max_addr_age_secs);
+    let recipient = node.known_peers().canonical_addr(recipient);
+
+    let mut addrs = node
+        .known_peers()
+        .read()
+        .iter()
+        .filter(|(addr, stats)| {
+            **addr != recipient
+                // the other addresses might be ephemeral ones, or unverified ones learned from peers
+                && stats.last_dialed.is_some()
+                && stats
+                    .freshness()
+                    .map(|t| t.elapsed() <= max_age)
+                    .unwrap_or(false)
+        })
+        .map(|(addr, _)| *addr)
+        .collect::<Vec<_>>();
+
+    // a randomly seeded hasher is a sufficient source of randomness here
+    let state = RandomState::new();
+    addrs.sort_unstable_by_key(|addr| state.hash_one(addr));
+    addrs.truncate(config.pex_max_addrs);
+
+    addrs
+}
+
+/// Creates a peer-exchange payload: the concatenated addresses, each consisting of its IP in the IPv6 form (with
+/// IPv4 ones being IPv4-mapped) and its port, encoded as a little-endian `u16`.
+fn encode_pex(addrs: &[SocketAddr]) -> Bytes {
+    let mut payload = Vec::with_capacity(addrs.len() * PEX_ADDR_LEN);
+    for addr in addrs {
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        payload.extend_from_slice(&ip.octets());
+        payload.extend_from_slice(&addr.port().to_le_bytes());
+    }
+
+    payload.into()
+}
+
+/// Decodes a peer-exchange payload into the addresses it contains.
+pub(crate) fn decode_pex(payload: &[u8]) -> io::Result<Vec<SocketAddr>> {
+    if !payload.chunks_exact(PEX_ADDR_LEN).remainder().is_empty() {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    Ok(payload
+        .chunks_exact(PEX_ADDR_LEN)
+        .map(|chunk| {
+            // safe; the slices have the exact lengths required by the conversions
+            let octets: [u8; 16] = chunk[..16].try_into().unwrap();
+            let ip = Ipv6Addr::from(octets);
+            let ip = ip
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ip));
+            let port = u16::from_le_bytes(chunk[16..].try_into().unwrap());
+
+            SocketAddr::new(ip, port)
+        })
+        .collect())
+}
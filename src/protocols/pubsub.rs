@@ -0,0 +1,188 @@
+use crate::{tracing_targets::PUBSUB, Pea2Pea};
+
+use bytes::Bytes;
+use fxhash::{FxHashMap, FxHashSet};
+use parking_lot::RwLock;
+use tokio::sync::mpsc;
+use tracing::*;
+
+use std::{io, net::SocketAddr};
+
+/// Can be used to exchange messages grouped by topics: peers inform one another about their topic subscriptions
+/// (see `Node::subscribe_topic`) upon connecting and whenever they change, and `Node::publish` only sends a message to the
+/// peers subscribed to its topic. The pubsub payloads are carried in-band by regular messages created with
+/// `pubsub_message`; the payloads of such messages received from peers should be passed to
+/// `Node::handle_pubsub_message`.
+///
+/// note: it requires the `Writing` protocol.
+pub trait PubSub: Pea2Pea
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Prepares the node to exchange messages grouped by topics.
+    fn enable_pubsub(&self) {
+        let (payload_sender, mut payload_receiver) =
+            mpsc::channel::<(SocketAddr, Bytes)>(self.node().config().protocol_handler_queue_depth);
+
+        let self_clone = self.clone();
+        let pubsub_task = self.node().spawn_task(format_args!("pubsub"), async move {
+            let node = self_clone.node();
+            trace!(target: PUBSUB, parent: node.span(), "spawned the PubSub task");
+
+            while let Some((addr, payload)) = payload_receiver.recv().await {
+                let message = self_clone.pubsub_message(payload);
+                if let Err(e) = node.send_direct_message(addr, message).await {
+                    warn!(target: PUBSUB, parent: node.span(), "couldn't send a pubsub message to {}: {}", addr, e);
+                }
+            }
+        });
+
+        self.node()
+            .set_pubsub_handler((payload_sender, pubsub_task));
+    }
+
+    /// Wraps the given pubsub payload in a message that the peer can recognize as a pubsub message.
+    fn pubsub_message(&self, payload: Bytes) -> Bytes;
+}
+
+/// The kinds of pubsub payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PubSubKind {
+    /// The sender subscribed to the topic.
+    Subscribe = 0,
+    /// The sender unsubscribed from the topic.
+    Unsubscribe = 1,
+    /// A message published to the topic.
+    Publish = 2,
+}
+
+/// Creates a pubsub payload: its kind, the length of the topic, the topic and, for published messages, the message.
+pub(crate) fn encode_pubsub(kind: PubSubKind, topic: &str, message: &[u8]) -> io::Result<Bytes> {
+    if topic.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the topic can't be longer than 255 bytes",
+        ));
+    }
+
+    let mut payload = Vec::with_capacity(2 + topic.len() + message.len());
+    payload.push(kind as u8);
+    payload.push(topic.len() as u8);
+    payload.extend_from_slice(topic.as_bytes());
+    payload.extend_from_slice(message);
+
+    Ok(payload.into())
+}
+
+/// Decodes a pubsub payload into its kind, topic and (for published messages) the message.
+pub(crate) fn decode_pubsub(payload: &[u8]) -> io::Result<(PubSubKind, &str, &[u8])> {
+    let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+
+    let (kind, topic_len) = match payload {
+        [kind, topic_len, ..] => (*kind, *topic_len as usize),
+        _ => return Err(invalid()),
+    };
+    let kind = match kind {
+        0 => PubSubKind::Subscribe,
+        1 => PubSubKind::Unsubscribe,
+        2 => PubSubKind::Publish,
+        _ => return Err(invalid()),
+    };
+    let topic = payload.get(2..2 + topic_len).ok_or_else(invalid)?;
+    let topic = std::str::from_utf8(topic).map_err(|_| invalid())?;
+    let message = &payload[2 + topic_len..];
+
+    if kind != PubSubKind::Publish && !message.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok((kind, topic, message))
+}
+
+/// Passes the messages published to a topic to one of its local subscribers.
+type TopicSender = mpsc::Sender<(SocketAddr, Bytes)>;
+
+/// The topic subscriptions of the node and its peers.
+#[derive(Default)]
+pub(crate) struct Topics {
+    /// The local subscribers of each topic.
+    local: RwLock<FxHashMap<String, Vec<TopicSender>>>,
+    /// The topics each peer is subscribed to.
+    remote: RwLock<FxHashMap<SocketAddr, FxHashSet<String>>>,
+}
+
+impl Topics {
+    /// Registers a local subscriber of the given topic; returns `true` if it's the first one.
+    pub(crate) fn subscribe(&self, topic: &str, sender: TopicSender) -> bool {
+        let mut local = self.local.write();
+        let subscribers = local.entry(topic.to_owned()).or_default();
+        subscribers.push(sender);
+
+        subscribers.len() == 1
+    }
+
+    /// Removes all the local subscribers of the given topic; returns `true` if there were any.
+    pub(crate) fn unsubscribe(&self, topic: &str) -> bool {
+        self.local.write().remove(topic).is_some()
+    }
+
+    /// Returns the topics with local subscribers.
+    pub(crate) fn local_topics(&self) -> Vec<String> {
+        self.local.read().keys().cloned().collect()
+    }
+
+    /// Returns the local subscribers of the given topic (or `None` if there's no such subscription), after removing
+    /// the ones that are no longer interested; if none remain, the topic is removed.
+    pub(crate) fn subscribers(&self, topic: &str) -> Option<Vec<TopicSender>> {
+        let mut local = self.local.write();
+
+        let subscribers = local.get_mut(topic)?;
+        subscribers.retain(|sender| !sender.is_closed());
+        let subscribers = subscribers.clone();
+        if subscribers.is_empty() {
+            local.remove(topic);
+        }
+
+        Some(subscribers)
+    }
+
+    /// Registers a change in the given peer's subscriptions.
+    pub(crate) fn register_peer_subscription(
+        &self,
+        addr: SocketAddr,
+        topic: &str,
+        subscribed: bool,
+    ) {
+        let mut remote = self.remote.write();
+
+        if subscribed {
+            remote.entry(addr).or_default().insert(topic.to_owned());
+        } else if let Some(topics) = remote.get_mut(&addr) {
+            topics.remove(topic);
+        }
+    }
+
+    /// Returns the topics the given peer is subscribed to.
+    pub(crate) fn peer_topics(&self, addr: SocketAddr) -> Vec<String> {
+        self.remote
+            .read()
+            .get(&addr)
+            .map(|topics| topics.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the addresses of the peers subscribed to the given topic.
+    pub(crate) fn peers_subscribed_to(&self, topic: &str) -> Vec<SocketAddr> {
+        self.remote
+            .read()
+            .iter()
+            .filter(|(_, topics)| topics.contains(topic))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Forgets the subscriptions of the given peer.
+    pub(crate) fn remove_peer(&self, addr: SocketAddr) {
+        self.remote.write().remove(&addr);
+    }
+}
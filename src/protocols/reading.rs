@@ -1,14 +1,40 @@
-use crate::{protocols::ReturnableConnection, Pea2Pea};
+#[cfg(feature = "identity")]
+use crate::identity::verify_message;
+use crate::{
+    buffer_pool::PooledBuffer,
+    connections::TaskGuard,
+    protocols::{OutboundMessage, ReturnableConnection, TransformingReader},
+    supervision::panic_message,
+    tracing_targets::READING,
+    ClosedInboundQueuePolicy, Connection, ConnectionContext, FrameDirection,
+    InboundQueueOverflowPolicy, Node, NodeEvent, Pea2Pea, ProcessingMode, ReadBufferGrowth,
+};
 
 use async_trait::async_trait;
+use bytes::Bytes;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use tokio::{
     io::{AsyncRead, AsyncReadExt},
-    sync::mpsc,
-    time::sleep,
+    sync::mpsc::{
+        self,
+        error::{SendError, TrySendError},
+        OwnedPermit,
+    },
+    task::JoinSet,
+    time::{sleep, timeout},
 };
-use tracing::*;
+use tracing::{Instrument, *};
 
-use std::{io, net::SocketAddr, time::Duration};
+use std::{
+    future::{poll_fn, Future},
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::Poll,
+    time::{Duration, Instant},
+};
 
 /// Can be used to specify and enable reading, i.e. receiving inbound messages.
 /// If handshaking is enabled too, it goes into force only after the handshake has been concluded.
@@ -28,47 +54,162 @@ where
             self.node().config().protocol_handler_queue_depth,
         );
 
+        // the lanes processing the messages assigned to ordering groups are shared by all the connections
+        let ordering_lanes: OrderingLanes<Self::Message> = Default::default();
+        // in the fair processing mode, the connections' queues are drawn from by a single shared task
+        let fair_queues: FairQueues<Self::Message> = Default::default();
+
         // the main task spawning per-connection tasks reading messages from their streams
         let self_clone = self.clone();
-        let reading_task = tokio::spawn(async move {
-            trace!(parent: self_clone.node().span(), "spawned the Reading handler task");
+        let reading_task = self.node().spawn_task(format_args!("reading"), async move {
+            trace!(target: READING, parent: self_clone.node().span(), "spawned the Reading handler task");
 
             loop {
                 // these objects are sent from `Node::adapt_stream`
                 if let Some((mut conn, conn_returner)) = conn_receiver.recv().await {
                     let addr = conn.addr;
-                    let mut reader = conn.reader.take().unwrap(); // safe; it is available at this point
-                    let mut buffer = vec![0; self_clone.node().config().conn_read_buffer_size]
-                        .into_boxed_slice();
+                    let span = conn.span().clone();
+                    // safe; the reader is available at this point
+                    let mut reader = TransformingReader::new(
+                        conn.reader.take().unwrap(),
+                        conn.inbound_transform.take(),
+                    );
+                    let mut buffer = {
+                        let config = self_clone.node().config();
+                        let size = config
+                            .read_buffer_growth
+                            .as_ref()
+                            .map(|growth| growth.initial_size)
+                            .unwrap_or(config.conn_read_buffer_size);
+                        self_clone.node().buffer_pool().get(size)
+                    };
 
-                    let (inbound_message_sender, mut inbound_message_receiver) =
-                        mpsc::channel(self_clone.node().config().conn_inbound_queue_depth);
+                    // unless the messages are processed directly by the reading task, they are queued for
+                    // a dedicated processing task
+                    let inbound_message_sender = if !self_clone
+                        .node()
+                        .config()
+                        .direct_message_processing
+                    {
+                        let (inbound_message_sender, inbound_message_receiver) =
+                            mpsc::channel(self_clone.node().config().conn_inbound_queue_depth);
+                        // the reader can evict the oldest queued messages (see `InboundQueueOverflowPolicy`); the
+                        // processing task holds the only strong reference, so the queue is closed once it stops
+                        let inbound_message_receiver = Arc::new(Mutex::new(inbound_message_receiver));
+                        conn.insert_ext(InboundQueue(Arc::downgrade(&inbound_message_receiver)));
 
-                    // the task for processing parsed messages
-                    let processing_clone = self_clone.clone();
-                    let inbound_processing_task = tokio::spawn(async move {
-                        let node = processing_clone.node();
-                        trace!(parent: node.span(), "spawned a task for processing messages from {}", addr);
+                        if let ProcessingMode::Fair { .. } = self_clone.node().config().message_processing_mode {
+                            // the messages are drawn by the processing task shared by all the connections
+                            let sub_queue = SubQueue {
+                                ctx: conn.context(),
+                                responder: Responder::new(&conn),
+                                receiver: inbound_message_receiver,
+                                task_guard: conn.task_guard(),
+                                parked: None,
+                            };
+                            // the shared task can only stop if processing a message panics
+                            if spawn_fair_processor(&self_clone, &fair_queues, &ordering_lanes).send(sub_queue).is_err() {
+                                error!(target: READING, parent: &span, "the fair processing task is down");
+                                self_clone.node().fail_fast(format_args!("the fair processing task is down"));
+                            }
+                        } else {
+                            // the task for processing parsed messages
+                            let processing_clone = self_clone.clone();
+                            let processing_span = span.clone();
+                            let ordering_lanes = ordering_lanes.clone();
+                            let task_guard = conn.task_guard();
+                            let responder = Responder::new(&conn);
+                            let ctx = conn.context();
+                            // a panic in the user's processing code is handled like one in the reader
+                            let inbound_processing_task = self_clone.node().spawn_supervised_task(format_args!("processor:{}", addr), addr, RESPONDER.scope(responder.clone(), async move {
+                                let node = processing_clone.node();
+                                let span = processing_span;
+                                trace!(target: READING, parent: &span, "spawned a task for processing messages from {}", addr);
 
-                        loop {
-                            if let Some(msg) = inbound_message_receiver.recv().await {
-                                if let Err(e) = processing_clone.process_message(addr, msg).await {
-                                    error!(parent: node.span(), "can't process an inbound message: {}", e);
-                                    node.known_peers().register_failure(addr);
+                                // messages processed concurrently; they get aborted along with this task
+                                let mut in_flight = JoinSet::new();
+
+                                loop {
+                                    if let Some((received, msg)) = poll_fn(|cx| inbound_message_receiver.lock().poll_recv(cx)).await {
+                                        // halt the processing while the node is paused
+                                        node.resumed().await;
+
+                                        // messages assigned to an ordering group are processed by its dedicated lane
+                                        if let Some(group) = processing_clone.ordering_group(addr, &msg) {
+                                            let lanes = spawn_ordering_lanes(&processing_clone, &ordering_lanes);
+                                            let lane = &lanes[(group % lanes.len() as u64) as usize];
+                                            // the lanes can only stop if processing a message panics
+                                            if lane.send((responder.clone(), ctx.clone(), received, msg)).await.is_err() {
+                                                error!(target: READING, parent: &span, "the ordering lane for group {} is closed", group);
+                                                node.fail_fast(format_args!("the ordering lane for group {} is closed", group));
+                                            }
+                                            continue;
+                                        }
+
+                                        match node.config().message_processing_mode {
+                                            ProcessingMode::Sequential => {
+                                                if let Err(e) =
+                                                    process_within_deadline(&processing_clone, &ctx, received, msg).await
+                                                {
+                                                    error!(target: READING, parent: &span, "can't process an inbound message: {}", e);
+                                                    node.known_peers().register_failure(addr);
+                                                }
+                                            }
+                                            ProcessingMode::Concurrent { max_in_flight } => {
+                                                // wait until there's room for another message
+                                                while in_flight.len() >= max_in_flight.max(1) {
+                                                    if let Some(Err(e)) = in_flight.join_next().await {
+                                                        if e.is_panic() {
+                                                            node.handle_task_panic(addr, panic_message(&*e.into_panic()));
+                                                        }
+                                                    }
+                                                }
+
+                                                let processing_clone = processing_clone.clone();
+                                                let span = span.clone();
+                                                let task_guard = task_guard.clone();
+                                                let ctx = ctx.clone();
+                                                let processing = async move {
+                                                    let _task_guard = task_guard;
+                                                    if let Err(e) =
+                                                        process_within_deadline(&processing_clone, &ctx, received, msg).await
+                                                    {
+                                                        error!(target: READING, parent: &span, "can't process an inbound message: {}", e);
+                                                        processing_clone.node().known_peers().register_failure(addr);
+                                                    }
+                                                };
+                                                node.spawn_task_in_set(&mut in_flight, format_args!("process:{}", addr), RESPONDER.scope(responder.clone(), processing));
+                                            }
+                                            // the queue is registered with the shared task instead
+                                            ProcessingMode::Fair { .. } => unreachable!(),
+                                        }
+                                    } else {
+                                        node.disconnect(addr);
+                                        break;
+                                    }
                                 }
-                            } else {
-                                node.disconnect(addr);
-                                break;
-                            }
+                            }));
+                            conn.tasks.push(inbound_processing_task);
                         }
-                    });
-                    conn.tasks.push(inbound_processing_task);
+
+                        Some(inbound_message_sender)
+                    } else {
+                        None
+                    };
 
                     // the task for reading messages from a stream
+                    // note: the events emitted when reading from the stream belong to the connection's span
                     let reader_clone = self_clone.clone();
-                    let reader_task = tokio::spawn(async move {
+                    let reader_span = span.clone();
+                    let mut mode = conn.subscribe_mode();
+                    let task_guard = conn.task_guard();
+                    // the messages can also be processed directly by this task
+                    let responder = Responder::new(&conn);
+                    let ctx = conn.context();
+                    let reader_task = self_clone.node().spawn_supervised_task(format_args!("reader:{}", addr), addr, RESPONDER.scope(responder, async move {
+                        let _task_guard = task_guard;
                         let node = reader_clone.node();
-                        trace!(parent: node.span(), "spawned a task for reading messages from {}", addr);
+                        trace!(target: READING, "spawned a task for reading messages from {}", addr);
 
                         // postpone reads until the connection is fully established; if the process fails,
                         // this task gets aborted, so there is no need for a dedicated timeout
@@ -77,14 +218,31 @@ where
                         }
 
                         let mut carry = 0;
+                        let mut sizing = node.config().read_buffer_growth.clone().map(BufferSizing::new);
                         loop {
+                            // halt the reads while the node is paused or the connection's mode doesn't allow them;
+                            // in the meantime, the bytes are left in the socket, so that TCP backpressure applies
+                            // to the peer
+                            node.resumed().await;
+                            while !mode.borrow_and_update().can_read() {
+                                // the connection is gone
+                                if mode.changed().await.is_err() {
+                                    return;
+                                }
+                            }
+
+                            // resize the buffer to fit the pending message or to release unused memory
+                            if let Some(ref mut sizing) = sizing {
+                                sizing.resize(node, &mut buffer, carry, reader.last_read());
+                            }
+
                             match reader_clone
                                 .read_from_stream(
-                                    addr,
+                                    &ctx,
                                     &mut buffer,
                                     &mut reader,
                                     carry,
-                                    &inbound_message_sender,
+                                    inbound_message_sender.as_ref(),
                                 )
                                 .await
                             {
@@ -93,8 +251,16 @@ where
                                 }
                                 Err(e) => {
                                     node.known_peers().register_failure(addr);
+                                    // the processing task is no longer running
+                                    if inbound_message_sender.as_ref().map(|sender| sender.is_closed()).unwrap_or(false)
+                                        && node.config().closed_inbound_queue_policy
+                                            != ClosedInboundQueuePolicy::DropMessages
+                                    {
+                                        node.handle_closed_inbound_queue(addr);
+                                        break;
+                                    }
                                     if node.config().fatal_io_errors.contains(&e.kind()) {
-                                        node.disconnect(addr);
+                                        node.handle_connection_loss(addr);
                                         break;
                                     } else {
                                         sleep(Duration::from_secs(
@@ -105,15 +271,16 @@ where
                                 }
                             }
                         }
-                    });
-                    conn.tasks.push(reader_task);
+                    }).instrument(reader_span));
+                    conn.reader_task = Some(reader_task);
 
                     // return the Connection to the Node, resuming Node::adapt_stream
                     if conn_returner.send(Ok(conn)).is_err() {
-                        unreachable!("could't return a Connection to the Node");
+                        // the connection attempt was cancelled in the meantime
+                        debug!(target: READING, parent: &span, "the connection with {} was cancelled", addr);
                     }
                 } else {
-                    error!("the Reading protocol is down!");
+                    error!(target: READING, "the Reading protocol is down!");
                     break;
                 }
             }
@@ -122,40 +289,92 @@ where
         // register the ReadingHandler with the Node
         self.node()
             .set_reading_handler((conn_sender, reading_task).into());
+
+        // allow the tests to inject synthetic inbound messages (see `Node::inject_inbound`)
+        #[cfg(feature = "test-utils")]
+        {
+            let self_clone = self.clone();
+            self.node()
+                .set_inbound_injector(Arc::new(move |addr, bytes| {
+                    let self_clone = self_clone.clone();
+                    Box::pin(async move { process_injected(&self_clone, addr, bytes).await })
+                }));
+        }
     }
 
     /// Performs a read from the given reader. The default implementation is buffered; it sacrifices a bit of
     /// simplicity for better performance. Read messages are sent to a message processing task in order to enable
-    /// faster reads. Returns the number of pending bytes left in the buffer in case of an incomplete read; they
-    /// should be provided to the medthod on the next call as `carry`.
+    /// faster reads (along with the time of their receipt), unless `message_sender` is `None`, in which case they
+    /// are processed directly. Returns the
+    /// number of pending bytes left in the buffer in case of an incomplete read; they should be provided to the
+    /// medthod on the next call as `carry`.
     async fn read_from_stream<R: AsyncRead + Unpin + Send>(
         &self,
-        addr: SocketAddr,
+        ctx: &ConnectionContext,
         buffer: &mut [u8],
         reader: &mut R,
         carry: usize,
-        message_sender: &mpsc::Sender<Self::Message>,
+        message_sender: Option<&mpsc::Sender<(Instant, Self::Message)>>,
     ) -> io::Result<usize> {
+        let addr = ctx.addr();
+
         // perform a read from the stream, being careful not to overwrite any bytes carried over from the previous read
         match reader.read(&mut buffer[carry..]).await {
-            Ok(0) => return Ok(carry),
+            // the peer has closed the connection
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
             Ok(n) => {
-                trace!(parent: self.node().span(), "read {}B from {}", n, addr);
+                trace!(target: READING, "read {}B from {}", n, addr);
+
+                // the node could have been paused while awaiting the read
+                self.node().resumed().await;
+
                 let mut processed = 0;
                 let mut left = carry + n;
 
                 // several messages could have been read at once; process the contents of the buffer
                 loop {
                     // try to read a single message from the buffer
-                    match self.read_message(addr, &buffer[processed..processed + left]) {
+                    let read = self.read_message(ctx, &buffer[processed..processed + left]);
+
+                    // if message signing is enabled, the message must be followed by a valid signature
+                    #[cfg(feature = "identity")]
+                    let read = read.and_then(|read| match read {
+                        Some((msg, len)) => verify_message(
+                            self.node(),
+                            addr,
+                            &buffer[processed..processed + left],
+                            len,
+                        )
+                        .map(|signed_len| signed_len.map(|len| (msg, len))),
+                        None => Ok(None),
+                    });
+
+                    match read {
+                        // heartbeats only keep the connection alive; they are consumed without being processed
+                        Ok(Some((msg, len))) if self.is_heartbeat(&msg) => {
+                            processed += len;
+                            left -= len;
+                            trace!(target: READING, "received a heartbeat from {}", addr);
+
+                            if left == 0 {
+                                return Ok(0);
+                            }
+                        }
                         // a full message was read successfully
                         Ok(Some((msg, len))) => {
+                            let received = Instant::now();
+                            self.node().observe_frame(
+                                addr,
+                                FrameDirection::Inbound,
+                                &buffer[processed..processed + len],
+                            );
+
                             // advance the counters
                             processed += len;
                             left -= len;
 
                             trace!(
-                                parent: self.node().span(),
+                                target: READING,
                                 "isolated {}B as a message from {}; {}B left to process",
                                 len,
                                 addr,
@@ -165,11 +384,30 @@ where
                                 .known_peers()
                                 .register_received_message(addr, len);
                             self.node().stats().register_received_message(len);
+                            self.node().register_conn_received_message(addr, len);
 
-                            // send the message for further processing
-                            if message_sender.send(msg).await.is_err() {
-                                error!(parent: self.node().span(), "the inbound message channel is closed");
-                                return Err(io::ErrorKind::BrokenPipe.into());
+                            if let Some(message_sender) = message_sender {
+                                // send the message for further processing
+                                if !enqueue_message(self, addr, message_sender, (received, msg))
+                                    .await?
+                                {
+                                    if self.node().config().closed_inbound_queue_policy
+                                        != ClosedInboundQueuePolicy::DropMessages
+                                    {
+                                        error!(target: READING, "the inbound message channel is closed");
+                                        return Err(io::ErrorKind::BrokenPipe.into());
+                                    }
+                                    // keep reading, discarding the message
+                                    if self.node().register_dropped_message(addr) {
+                                        self.node().handle_closed_inbound_queue(addr);
+                                    }
+                                }
+                            } else if let Err(e) =
+                                process_within_deadline(self, ctx, received, msg).await
+                            {
+                                // process the message directly
+                                error!(target: READING, "can't process an inbound message: {}", e);
+                                self.node().known_peers().register_failure(addr);
                             }
 
                             // if the read is exhausted, reset the carry and return
@@ -179,14 +417,20 @@ where
                         }
                         // the message in the buffer is incomplete
                         Ok(None) => {
-                            // forbid messages that are larger than the read buffer
-                            if left >= buffer.len() {
-                                error!(parent: self.node().span(), "a message from {} is too large", addr);
+                            // forbid messages that are larger than the maximum read buffer; if the buffer can
+                            // still grow, a full buffer is carried over in its entirety
+                            if left >= buffer.len().max(self.node().config().conn_read_buffer_size)
+                            {
+                                error!(target: READING, "a message from {} is too large", addr);
+                                self.node().fail_fast(format_args!(
+                                    "a message from {} is too large",
+                                    addr
+                                ));
                                 return Err(io::ErrorKind::InvalidData.into());
                             }
 
                             trace!(
-                                parent: self.node().span(),
+                                target: READING,
                                 "a message from {} is incomplete; carrying {}B over",
                                 addr,
                                 left
@@ -199,8 +443,8 @@ where
                             return Ok(left);
                         }
                         // an erroneous message (e.g. an unexpected zero-length payload)
-                        Err(_) => {
-                            error!(parent: self.node().span(), "a message from {} is invalid", addr);
+                        Err(e) => {
+                            error!(target: READING, "a message from {} is invalid: {}", addr, e);
                             return Err(io::ErrorKind::InvalidData.into());
                         }
                     }
@@ -208,7 +452,7 @@ where
             }
             // a stream read error
             Err(e) => {
-                error!(parent: self.node().span(), "can't read from {}: {}", addr, e);
+                error!(target: READING, "can't read from {}: {}", addr, e);
                 Err(io::ErrorKind::Other.into())
             }
         }
@@ -217,17 +461,541 @@ where
     /// Reads a single message from the given buffer; `Ok(None)` indicates that the message is
     /// incomplete, i.e. further reads from the stream must be performed in order to produce the whole message.
     /// Alongside the message it returns the number of bytes the read message occupied in the buffer. An `Err`
-    /// returned here will result in the associated connection being dropped.
+    /// returned here will result in the associated connection being dropped. The provided context of the
+    /// connection carries the source address and the value produced by its handshake (see
+    /// `Connection::set_handshake_data`).
     fn read_message(
         &self,
-        source: SocketAddr,
+        ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>>;
 
+    /// Indicates whether the given message is a heartbeat (see `NodeConfig.heartbeat`); heartbeats are consumed by the
+    /// reader without being counted or processed. No messages are heartbeats by default.
+    #[allow(unused_variables)]
+    fn is_heartbeat(&self, message: &Self::Message) -> bool {
+        false
+    }
+
+    /// Assigns the given message to an ordering group: the messages within a single group are processed one at a
+    /// time, in the order they were received in (regardless of the peers they were received from), while the ones
+    /// from different groups can be processed in parallel (see `NodeConfig.num_ordering_lanes`). The messages not
+    /// assigned to any group (the default) are processed in line with `NodeConfig.message_processing_mode`.
+    ///
+    /// note: not applicable when `direct_message_processing` is enabled.
+    #[allow(unused_variables)]
+    fn ordering_group(&self, source: SocketAddr, message: &Self::Message) -> Option<u64> {
+        None
+    }
+
     /// Processes an inbound message. Can be used to update state, send replies etc.
+    ///
+    /// note: if `NodeConfig.message_processing_timeout_ms` is set, the processing is cancelled once the deadline
+    /// (available via `processing_deadline`) passes; long-running handlers can consult it in order to wind down
+    /// cooperatively.
     #[allow(unused_variables)]
     async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
         // don't do anything by default
         Ok(())
     }
+
+    /// Processes an inbound message, like `process_message`, but with access to the context of the connection,
+    /// including the value produced by its handshake (see `Connection::set_handshake_data`); by default, it calls
+    /// `process_message`.
+    async fn process_message_with_context(
+        &self,
+        ctx: &ConnectionContext,
+        message: Self::Message,
+    ) -> io::Result<()> {
+        self.process_message(ctx.addr(), message).await
+    }
+}
+
+/// A handle to the queue of a connection's inbound messages, allowing the reader to evict the oldest ones.
+struct InboundQueue<M>(Weak<Mutex<mpsc::Receiver<(Instant, M)>>>);
+
+impl<M> Clone for InboundQueue<M> {
+    fn clone(&self) -> Self {
+        Self(Weak::clone(&self.0))
+    }
+}
+
+/// Queues the given message (along with the time of its receipt) for processing, applying
+/// `NodeConfig.inbound_queue_overflow_policy` if the queue is full; returns `false` if the queue is closed.
+async fn enqueue_message<R: Reading>(
+    reader: &R,
+    addr: SocketAddr,
+    sender: &mpsc::Sender<(Instant, R::Message)>,
+    message: (Instant, R::Message),
+) -> io::Result<bool> {
+    let node = reader.node();
+    let policy = node.config().inbound_queue_overflow_policy;
+    if policy == InboundQueueOverflowPolicy::Block {
+        return Ok(sender.send(message).await.is_ok());
+    }
+
+    let message = match sender.try_send(message) {
+        Ok(()) => return Ok(true),
+        Err(TrySendError::Closed(_)) => return Ok(false),
+        Err(TrySendError::Full(message)) => message,
+    };
+
+    match policy {
+        InboundQueueOverflowPolicy::Block => unreachable!(),
+        InboundQueueOverflowPolicy::DropNewest => {
+            debug!(target: READING, "the inbound queue of {} is full; dropping the newest message", addr);
+            node.register_overflowed_message(addr);
+            node.fail_fast(format_args!("the inbound queue of {} overflowed", addr));
+            Ok(true)
+        }
+        InboundQueueOverflowPolicy::DropOldest => {
+            // the processing task might have just made room by itself
+            let queue = node
+                .connection_ext::<InboundQueue<R::Message>>(addr)
+                .and_then(|queue| queue.0.upgrade());
+            if let Some(queue) = queue {
+                if queue.lock().try_recv().is_ok() {
+                    debug!(target: READING, "the inbound queue of {} is full; dropped the oldest message", addr);
+                    node.register_overflowed_message(addr);
+                    node.fail_fast(format_args!("the inbound queue of {} overflowed", addr));
+                }
+            }
+            Ok(sender.send(message).await.is_ok())
+        }
+        InboundQueueOverflowPolicy::DisconnectPeer => {
+            warn!(target: READING, "the inbound queue of {} is full; disconnecting", addr);
+            node.register_overflowed_message(addr);
+            node.fail_fast(format_args!("the inbound queue of {} overflowed", addr));
+            node.disconnect(addr);
+            Err(io::ErrorKind::ConnectionAborted.into())
+        }
+    }
+}
+
+tokio::task_local! {
+    /// The deadline for processing the current inbound message.
+    static PROCESSING_DEADLINE: Instant;
+}
+
+/// Returns the deadline for processing the current inbound message, if called from within
+/// `Reading::process_message` while `NodeConfig.message_processing_timeout_ms` is set.
+pub fn processing_deadline() -> Option<Instant> {
+    PROCESSING_DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+tokio::task_local! {
+    /// The handle for replying to the source of the current inbound message.
+    static RESPONDER: Responder;
+}
+
+/// Returns a handle for replying to the source of the current inbound message, if called from within
+/// `Reading::process_message`; useful for request/reply protocols.
+pub fn responder() -> Option<Responder> {
+    RESPONDER.try_with(|responder| responder.clone()).ok()
+}
+
+/// A handle for replying to the source of an inbound message, obtained via `protocols::responder`; the replies are
+/// queued directly for the writer of the source connection, without looking the connection up, and only if the
+/// `Writing` protocol is enabled. The egress policy still applies to them.
+#[derive(Clone)]
+pub struct Responder {
+    node: Node,
+    addr: SocketAddr,
+    /// Set by the `Writing` protocol once it takes over the connection.
+    sender: Arc<OnceCell<mpsc::Sender<OutboundMessage>>>,
+}
+
+impl Responder {
+    fn new(conn: &Connection) -> Self {
+        Self {
+            node: conn.node.clone(),
+            addr: conn.addr,
+            sender: conn.reply_sender.clone(),
+        }
+    }
+
+    /// Returns the address of the source of the message.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Queues the given reply for the source of the message.
+    pub async fn reply(&self, payload: Bytes) -> io::Result<()> {
+        self.node.check_egress_policy(self.addr, &payload)?;
+        #[cfg(feature = "test-utils")]
+        if self.node.log_outbound(self.addr, &payload) && self.sender.get().is_none() {
+            return Ok(());
+        }
+
+        let sender = self.sender.get().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the Writing protocol is not enabled",
+            )
+        })?;
+        // an error here means the connection was shut down
+        sender
+            .send(payload.into())
+            .await
+            .map_err(|_| io::ErrorKind::NotConnected.into())
+    }
+}
+
+/// Processes the given message, subject to `NodeConfig.message_processing_timeout_ms`; once the deadline passes,
+/// the processing is cancelled, a `NodeEvent::ProcessingTimedOut` is emitted, and the peer is disconnected if
+/// `NodeConfig.disconnect_on_processing_timeout` is set. The time from the receipt of the message to the end of its
+/// processing is registered in the node's stats.
+async fn process_within_deadline<R: Reading>(
+    reader: &R,
+    ctx: &ConnectionContext,
+    received: Instant,
+    message: R::Message,
+) -> io::Result<()> {
+    let node = reader.node();
+    let addr = ctx.addr();
+    let max_time = if let Some(ms) = node.config().message_processing_timeout_ms {
+        Duration::from_millis(ms)
+    } else {
+        let result = reader.process_message_with_context(ctx, message).await;
+        node.stats().register_processing_time(received.elapsed());
+        return result;
+    };
+
+    let processing = PROCESSING_DEADLINE.scope(
+        Instant::now() + max_time,
+        reader.process_message_with_context(ctx, message),
+    );
+    let result = timeout(max_time, processing).await;
+    node.stats().register_processing_time(received.elapsed());
+    match result {
+        Ok(result) => result,
+        Err(_) => {
+            warn!(target: READING, parent: node.span(), "processing a message from {} timed out", addr);
+            node.emit_for(addr, NodeEvent::ProcessingTimedOut);
+            if node.config().disconnect_on_processing_timeout {
+                node.disconnect(addr);
+            }
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+}
+
+/// Reads the messages from the given injected bytes and processes them as if they came from the given peer.
+#[cfg(feature = "test-utils")]
+async fn process_injected<R: Reading>(
+    reader: &R,
+    addr: SocketAddr,
+    bytes: Bytes,
+) -> io::Result<()> {
+    // the replies can only be recorded (see `Node::record_outbound`)
+    let responder = Responder {
+        node: reader.node().clone(),
+        addr,
+        sender: Default::default(),
+    };
+    let ctx = reader
+        .node()
+        .connection_context(addr)
+        .unwrap_or_else(|| ConnectionContext::new(addr));
+
+    let mut processed = 0;
+    while processed < bytes.len() {
+        match reader.read_message(&ctx, &bytes[processed..])? {
+            Some((msg, len)) => {
+                processed += len;
+                RESPONDER
+                    .scope(
+                        responder.clone(),
+                        process_within_deadline(reader, &ctx, Instant::now(), msg),
+                    )
+                    .await?;
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the injected bytes end with an incomplete message",
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resizes a connection's read buffer as per `NodeConfig.read_buffer_growth`.
+struct BufferSizing {
+    growth: ReadBufferGrowth,
+    /// The number of bytes carried over before the most recent read.
+    prev_carry: usize,
+    /// The number of consecutive reads that fit within the initial buffer size.
+    small_reads: usize,
+}
+
+impl BufferSizing {
+    fn new(growth: ReadBufferGrowth) -> Self {
+        Self {
+            growth,
+            prev_carry: 0,
+            small_reads: 0,
+        }
+    }
+
+    /// Grows the buffer if it's filled with an incomplete message, or shrinks it back if it's been underused for
+    /// long enough; `carry` is the number of pending bytes, and `last_read` the number of bytes read most recently.
+    fn resize(&mut self, node: &Node, buffer: &mut PooledBuffer, carry: usize, last_read: usize) {
+        let (initial_size, max_size) = (
+            self.growth.initial_size,
+            node.config().conn_read_buffer_size,
+        );
+
+        if self.prev_carry + last_read <= initial_size {
+            self.small_reads += 1;
+        } else {
+            self.small_reads = 0;
+        }
+        self.prev_carry = carry;
+
+        let new_size = if carry == buffer.len() && buffer.len() < max_size {
+            buffer.len().saturating_mul(2).min(max_size)
+        } else if self.small_reads >= self.growth.shrink_after_reads
+            && buffer.len() > initial_size
+            && carry <= initial_size
+        {
+            initial_size
+        } else {
+            return;
+        };
+
+        trace!(target: READING, "resizing the read buffer from {}B to {}B", buffer.len(), new_size);
+        let mut resized = node.buffer_pool().get(new_size);
+        resized[..carry].copy_from_slice(&buffer[..carry]);
+        *buffer = resized;
+        self.small_reads = 0;
+    }
+}
+
+/// The queue of a single connection's inbound messages, drawn from by the task shared by all the connections in
+/// the `ProcessingMode::Fair` mode.
+struct SubQueue<M> {
+    ctx: ConnectionContext,
+    responder: Responder,
+    receiver: Arc<Mutex<mpsc::Receiver<(Instant, M)>>>,
+    task_guard: TaskGuard,
+    /// A message waiting for room in its full ordering lane; the queue isn't drawn from in the meantime.
+    parked: Option<ParkedMessage<M>>,
+}
+
+/// A message assigned to an ordering lane that was full at the time, along with the pending reservation of a slot
+/// in the lane.
+struct ParkedMessage<M> {
+    message: LaneMessage<M>,
+    permit: LaneReservation<M>,
+}
+
+/// The pending reservation of a slot in an ordering lane.
+type LaneReservation<M> =
+    Pin<Box<dyn Future<Output = Result<OwnedPermit<LaneMessage<M>>, SendError<()>>> + Send>>;
+
+/// Registers the connections' queues with the task processing their messages in the `ProcessingMode::Fair` mode;
+/// the task is spawned on first use.
+type FairQueues<M> = Arc<OnceCell<mpsc::UnboundedSender<SubQueue<M>>>>;
+
+/// Returns the channel registering the connections' queues with the fair processing task, spawning the task if it's
+/// not running yet.
+fn spawn_fair_processor<'a, R: Reading>(
+    reader: &R,
+    queues: &'a FairQueues<R::Message>,
+    lanes: &OrderingLanes<R::Message>,
+) -> &'a mpsc::UnboundedSender<SubQueue<R::Message>> {
+    queues.get_or_init(|| {
+        let (queue_sender, queue_receiver) = mpsc::unbounded_channel();
+        let reader = reader.clone();
+        let lanes = lanes.clone();
+        reader
+            .node()
+            .clone()
+            .spawn_task(format_args!("fair-processor"), async move {
+                process_fairly(reader, queue_receiver, lanes).await
+            });
+
+        queue_sender
+    })
+}
+
+/// Processes the messages from all the connections' queues, drawing them round-robin, so that a single chatty peer
+/// can't monopolize the processing; up to `ProcessingMode::Fair.max_in_flight` messages are processed at a time. A
+/// message assigned to a full ordering lane is parked with its queue until there's room in the lane, so that the
+/// other connections' messages keep being processed in the meantime.
+async fn process_fairly<R: Reading>(
+    reader: R,
+    mut new_queues: mpsc::UnboundedReceiver<SubQueue<R::Message>>,
+    lanes: OrderingLanes<R::Message>,
+) {
+    let node = reader.node();
+    trace!(target: READING, parent: node.span(), "spawned the fair processing task");
+
+    let max_in_flight = match node.config().message_processing_mode {
+        ProcessingMode::Fair { max_in_flight } => max_in_flight.max(1),
+        _ => 1,
+    };
+    let mut queues: Vec<SubQueue<R::Message>> = Vec::new();
+    // the index of the queue to be visited first when drawing the next message
+    let mut cursor = 0;
+    let mut in_flight = JoinSet::new();
+
+    loop {
+        // wait until there's room for another message
+        while in_flight.len() >= max_in_flight {
+            if let Some(Err(e)) = in_flight.join_next().await {
+                if e.is_panic() {
+                    error!(target: READING, parent: node.span(), "processing a message panicked");
+                    node.fail_fast(format_args!("processing a message panicked"));
+                }
+            }
+        }
+
+        let (idx, (received, msg)) = poll_fn(|cx| {
+            while let Poll::Ready(Some(queue)) = new_queues.poll_recv(cx) {
+                queues.push(queue);
+            }
+
+            // visit the queues starting with the one after the queue drawn from last
+            for _ in 0..queues.len() {
+                if cursor >= queues.len() {
+                    cursor = 0;
+                }
+                let queue = &mut queues[cursor];
+
+                // a queue with a parked message is only drawn from once the message is passed to its lane
+                if let Some(parked) = queue.parked.as_mut() {
+                    match parked.permit.as_mut().poll(cx) {
+                        Poll::Ready(Ok(permit)) => {
+                            let parked = queue.parked.take().unwrap(); // safe; checked above
+                            permit.send(parked.message);
+                        }
+                        Poll::Ready(Err(_)) => {
+                            queue.parked = None;
+                            error!(target: READING, parent: node.span(), "an ordering lane is closed");
+                            node.fail_fast(format_args!("an ordering lane is closed"));
+                        }
+                        Poll::Pending => {
+                            cursor += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                let polled = queue.receiver.lock().poll_recv(cx);
+                match polled {
+                    Poll::Ready(Some(msg)) => {
+                        let idx = cursor;
+                        cursor += 1;
+                        return Poll::Ready((idx, msg));
+                    }
+                    // the connection's reader is gone
+                    Poll::Ready(None) => {
+                        let queue = queues.remove(cursor);
+                        node.disconnect(queue.ctx.addr());
+                    }
+                    Poll::Pending => cursor += 1,
+                }
+            }
+
+            Poll::Pending
+        })
+        .await;
+        let queue = &mut queues[idx];
+        let (ctx, responder, task_guard) = (
+            queue.ctx.clone(),
+            queue.responder.clone(),
+            queue.task_guard.clone(),
+        );
+
+        // halt the processing while the node is paused
+        node.resumed().await;
+        let addr = ctx.addr();
+
+        // messages assigned to an ordering group are processed by its dedicated lane
+        if let Some(group) = reader.ordering_group(addr, &msg) {
+            let lanes = spawn_ordering_lanes(&reader, &lanes);
+            let lane = &lanes[(group % lanes.len() as u64) as usize];
+            match lane.try_send((responder, ctx, received, msg)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(message)) => {
+                    trace!(target: READING, parent: node.span(), "the ordering lane for group {} is full; parking a message from {}", group, addr);
+                    queue.parked = Some(ParkedMessage {
+                        message,
+                        permit: Box::pin(lane.clone().reserve_owned()),
+                    });
+                }
+                // the lanes can only stop if processing a message panics
+                Err(TrySendError::Closed(_)) => {
+                    error!(target: READING, parent: node.span(), "the ordering lane for group {} is closed", group);
+                    node.fail_fast(format_args!(
+                        "the ordering lane for group {} is closed",
+                        group
+                    ));
+                }
+            }
+            continue;
+        }
+
+        let reader = reader.clone();
+        let processing = async move {
+            let _task_guard = task_guard;
+            if let Err(e) = process_within_deadline(&reader, &ctx, received, msg).await {
+                error!(target: READING, parent: reader.node().span(), "can't process an inbound message from {}: {}", addr, e);
+                reader.node().known_peers().register_failure(addr);
+            }
+        };
+        node.spawn_task_in_set(
+            &mut in_flight,
+            format_args!("process:{}", addr),
+            RESPONDER.scope(responder, processing),
+        );
+    }
+}
+
+/// The queues of the tasks processing the messages assigned to ordering groups; they are spawned on first use.
+type OrderingLanes<M> = Arc<OnceCell<Vec<mpsc::Sender<LaneMessage<M>>>>>;
+
+/// A message queued for an ordering lane, along with the objects needed to process it and the time of its receipt.
+type LaneMessage<M> = (Responder, ConnectionContext, Instant, M);
+
+/// Returns the queues of the ordering lanes, spawning the lanes if they are not running yet.
+fn spawn_ordering_lanes<'a, R: Reading>(
+    reader: &R,
+    lanes: &'a OrderingLanes<R::Message>,
+) -> &'a [mpsc::Sender<LaneMessage<R::Message>>] {
+    lanes.get_or_init(|| {
+        let node = reader.node();
+
+        (0..node.config().num_ordering_lanes.max(1))
+            .map(|idx| {
+                let (lane_sender, mut lane_receiver) =
+                    mpsc::channel::<LaneMessage<R::Message>>(
+                        node.config().conn_inbound_queue_depth,
+                    );
+
+                // the lane stops once all the connections and the Reading protocol are shut down, or the node is
+                // shut down
+                let reader = reader.clone();
+                let lane_task = node.spawn_task(format_args!("ordering-lane:{}", idx), async move {
+                    let node = reader.node();
+                    trace!(target: READING, parent: node.span(), "spawned ordering lane {}", idx);
+
+                    while let Some((responder, ctx, received, msg)) = lane_receiver.recv().await {
+                        let addr = ctx.addr();
+                        if let Err(e) = RESPONDER.scope(responder, process_within_deadline(&reader, &ctx, received, msg)).await {
+                            error!(target: READING, parent: node.span(), "can't process an inbound message from {}: {}", addr, e);
+                            node.known_peers().register_failure(addr);
+                        }
+                    }
+                });
+                node.register_ordering_lane_task(lane_task);
+
+                lane_sender
+            })
+            .collect()
+    })
 }
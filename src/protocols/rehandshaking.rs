@@ -0,0 +1,114 @@
+use crate::{
+    negotiation::{decode_rehandshake, encode_rehandshake},
+    protocols::Handshaking,
+    tracing_targets::HANDSHAKE,
+};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tracing::*;
+
+use std::{io, net::SocketAddr};
+
+/// Can be used to re-run version negotiation and the handshake over live connections via `Node::rehandshake`,
+/// e.g. in order to refresh the peers' versions, capabilities and keys after a rolling upgrade (see
+/// `Node::set_advertised_version`) without reconnecting. The rehandshake payloads are carried in-band by regular
+/// messages created with `rehandshake_message`; the payloads of such messages received from peers should be passed
+/// to `Node::handle_rehandshake`. The `Handshaking` protocol takes part via `Handshaking::rehandshake_payload` and
+/// `Handshaking::process_rehandshake_payload`.
+///
+/// note: it requires version negotiation (`NodeConfig.supported_version_range`) and the `Writing` protocol.
+pub trait Rehandshaking: Handshaking
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Prepares the node to re-run version negotiation and the handshake over live connections.
+    fn enable_rehandshaking(&self) {
+        let (step_sender, mut step_receiver) = mpsc::channel::<(SocketAddr, RehandshakeStep)>(
+            self.node().config().protocol_handler_queue_depth,
+        );
+
+        let self_clone = self.clone();
+        let rehandshaking_task = self.node().spawn_task(format_args!("rehandshaking"), async move {
+            let node = self_clone.node();
+            trace!(target: HANDSHAKE, parent: node.span(), "spawned the Rehandshaking task");
+
+            while let Some((addr, step)) = step_receiver.recv().await {
+                let result = match step {
+                    RehandshakeStep::Initiate => send_rehandshake(&self_clone, addr, false).await,
+                    RehandshakeStep::Receive(payload) => {
+                        process_rehandshake(&self_clone, addr, payload).await
+                    }
+                };
+                if let Err(e) = result {
+                    warn!(target: HANDSHAKE, parent: node.span(), "the rehandshake with {} failed: {}", addr, e);
+                }
+            }
+        });
+
+        self.node()
+            .set_rehandshake_handler((step_sender, rehandshaking_task));
+    }
+
+    /// Wraps the given rehandshake payload in a message that the peer can recognize as a rehandshake message.
+    fn rehandshake_message(&self, payload: Bytes) -> Bytes;
+}
+
+/// A step of an in-band rehandshake, passed to the `Rehandshaking` protocol's task.
+pub(crate) enum RehandshakeStep {
+    /// Sends the node's rehandshake payload to the peer.
+    Initiate,
+    /// Processes a rehandshake payload received from the peer.
+    Receive(Bytes),
+}
+
+/// Sends the node's capabilities and handshake payload to the given peer.
+async fn send_rehandshake<R: Rehandshaking>(
+    rehandshaker: &R,
+    addr: SocketAddr,
+    is_reply: bool,
+) -> io::Result<()> {
+    let node = rehandshaker.node();
+    let handshake_payload = rehandshaker.rehandshake_payload(addr)?;
+    let payload = encode_rehandshake(node, is_reply, &handshake_payload);
+
+    node.send_direct_message(addr, rehandshaker.rehandshake_message(payload))
+        .await
+}
+
+/// Processes a rehandshake payload received from the given peer and, if the peer initiated the rehandshake, replies
+/// with the node's own one; the peer is disconnected if it fails the rehandshake.
+async fn process_rehandshake<R: Rehandshaking>(
+    rehandshaker: &R,
+    source: SocketAddr,
+    payload: Bytes,
+) -> io::Result<()> {
+    let node = rehandshaker.node();
+    let result: io::Result<_> = async {
+        let (peer_caps, is_reply, handshake_payload) = decode_rehandshake(payload)?;
+        node.refresh_peer_capabilities(source, peer_caps)?;
+        rehandshaker
+            .process_rehandshake_payload(source, handshake_payload)
+            .await?;
+
+        Ok((peer_caps, is_reply))
+    }
+    .await;
+
+    match result {
+        Ok((peer_caps, true)) => {
+            debug!(target: HANDSHAKE, parent: node.span(), "rehandshaken with {}: {:?}", source, peer_caps);
+            node.conclude_rehandshake(source, Ok(peer_caps));
+            Ok(())
+        }
+        Ok((peer_caps, false)) => {
+            debug!(target: HANDSHAKE, parent: node.span(), "rehandshaken with {}: {:?}", source, peer_caps);
+            send_rehandshake(rehandshaker, source, true).await
+        }
+        Err(e) => {
+            node.conclude_rehandshake(source, Err(e.kind().into()));
+            node.disconnect(source);
+            Err(e)
+        }
+    }
+}
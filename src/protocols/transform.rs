@@ -0,0 +1,150 @@
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A length-preserving transformation of the raw bytes of a connection's stream, e.g. a stream cipher. It can be
+/// installed as `Connection.inbound_transform` or `Connection.outbound_transform` (e.g. in
+/// `Handshaking::perform_handshake`), in which case it is automatically applied by the `Reading` or `Writing`
+/// protocol respectively to all the bytes read from or written to the stream; the bytes are provided in the
+/// order of the stream, so stateful transformations are supported.
+pub trait StreamTransform: Send + Sync {
+    /// Transforms the given bytes in place.
+    fn apply(&mut self, bytes: &mut [u8]);
+}
+
+impl<F: FnMut(&mut [u8]) + Send + Sync> StreamTransform for F {
+    fn apply(&mut self, bytes: &mut [u8]) {
+        self(bytes)
+    }
+}
+
+/// A reader applying an optional `StreamTransform` to all the bytes it reads.
+pub(crate) struct TransformingReader<R> {
+    inner: R,
+    transform: Option<Box<dyn StreamTransform>>,
+    /// The number of bytes obtained by the most recent read.
+    last_read: usize,
+}
+
+impl<R> TransformingReader<R> {
+    pub(crate) fn new(inner: R, transform: Option<Box<dyn StreamTransform>>) -> Self {
+        Self {
+            inner,
+            transform,
+            last_read: 0,
+        }
+    }
+
+    /// Returns the number of bytes obtained by the most recent read.
+    pub(crate) fn last_read(&self) -> usize {
+        self.last_read
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TransformingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let already_filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            self.last_read = buf.filled().len() - already_filled;
+            // only the freshly read bytes are transformed
+            if let Some(transform) = self.transform.as_mut() {
+                transform.apply(&mut buf.filled_mut()[already_filled..]);
+            }
+        }
+
+        result
+    }
+}
+
+/// A writer applying an optional `StreamTransform` to all the bytes it writes; the transformed bytes that could
+/// not be written immediately are written on subsequent writes or flushes.
+pub(crate) struct TransformingWriter<W> {
+    inner: W,
+    transform: Option<Box<dyn StreamTransform>>,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W> TransformingWriter<W> {
+    pub(crate) fn new(inner: W, transform: Option<Box<dyn StreamTransform>>) -> Self {
+        Self {
+            inner,
+            transform,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> TransformingWriter<W> {
+    /// Writes the pending transformed bytes to the underlying writer.
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => self.written += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.pending.clear();
+        self.written = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TransformingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.transform.is_none() {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+
+        // the previously transformed bytes must be written first
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let this = &mut *self;
+        this.pending.extend_from_slice(buf);
+        // safe; checked at the beginning
+        this.transform.as_mut().unwrap().apply(&mut this.pending);
+
+        // the bytes are accepted as soon as they're transformed; a failure to write them is reported later
+        if let Poll::Ready(Err(e)) = this.poll_write_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
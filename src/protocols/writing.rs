@@ -1,13 +1,33 @@
-use crate::{protocols::ReturnableConnection, Pea2Pea};
+#[cfg(feature = "identity")]
+use crate::identity::sign_message;
+use crate::{
+    bandwidth::Throttle,
+    connections::WriterState,
+    node::TypedSerializer,
+    protocols::{MessageKind, OutboundMessage, ReturnableConnection, TransformingWriter},
+    tracing_targets::WRITING,
+    ConnectionContext, FrameDirection, Node, NodeEvent, Pea2Pea, SlowPeerDetection,
+    WriterStallAction,
+};
 
 use async_trait::async_trait;
+use bytes::BytesMut;
+use fxhash::FxHashMap;
 use tokio::{
     io::{AsyncWrite, AsyncWriteExt},
     sync::mpsc,
+    time::{self, sleep},
 };
-use tracing::*;
+use tracing::{Instrument, *};
 
-use std::{io, net::SocketAddr};
+use std::{
+    cmp,
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// Can be used to specify and enable writing, i.e. sending outbound messages.
 /// If handshaking is enabled too, it goes into force only after the handshake has been concluded.
@@ -24,63 +44,174 @@ where
 
         // the task spawning tasks reading messages from the given stream
         let self_clone = self.clone();
-        let writing_task = tokio::spawn(async move {
-            trace!(parent: self_clone.node().span(), "spawned the Writing handler task");
+        let writing_task = self.node().spawn_task(format_args!("writing"), async move {
+            trace!(target: WRITING, parent: self_clone.node().span(), "spawned the Writing handler task");
 
             loop {
                 // these objects are sent from `Node::adapt_stream`
                 if let Some((mut conn, conn_returner)) = conn_receiver.recv().await {
                     let addr = conn.addr;
-                    let mut writer = conn.writer.take().unwrap(); // safe; it is available at this point
-                    let mut buffer = vec![0; self_clone.node().config().conn_write_buffer_size]
-                        .into_boxed_slice();
+                    let span = conn.span().clone();
+                    // safe; the writer is available at this point
+                    let mut writer = TransformingWriter::new(
+                        conn.writer.take().unwrap(),
+                        conn.outbound_transform.take(),
+                    );
+                    let mut buffer = self_clone
+                        .node()
+                        .buffer_pool()
+                        .get(self_clone.node().config().conn_write_buffer_size);
 
+                    // don't queue more messages than the peer has declared it can queue on its side
+                    let queue_depth = match conn.peer_capabilities {
+                        Some(peer_caps) => (peer_caps.inbound_queue_depth as usize)
+                            .clamp(1, self_clone.node().config().conn_outbound_queue_depth),
+                        None => self_clone.node().config().conn_outbound_queue_depth,
+                    };
                     let (outbound_message_sender, mut outbound_message_receiver) =
-                        mpsc::channel(self_clone.node().config().conn_outbound_queue_depth);
+                        mpsc::channel(queue_depth);
+                    // the replies of the Reading protocol are queued directly
+                    let _ = conn.reply_sender.set(outbound_message_sender.clone());
                     conn.outbound_message_sender = Some(outbound_message_sender);
 
-                    // the task for writing outbound messages
+                    // the task for writing outbound messages; its events belong to the connection's span
                     let writer_clone = self_clone.clone();
-                    let writer_task = tokio::spawn(async move {
+                    let mut mode = conn.subscribe_mode();
+                    let task_guard = conn.task_guard();
+                    let ctx = conn.context();
+                    let write_started = conn.write_started.clone();
+                    let writer_task = self_clone.node().spawn_supervised_task(format_args!("writer:{}", addr), addr, async move {
+                        let _task_guard = task_guard;
                         let node = writer_clone.node();
-                        trace!(parent: node.span(), "spawned a task for writing messages to {}", addr);
+                        trace!(target: WRITING, "spawned a task for writing messages to {}", addr);
+
+                        // the messages held back while the node is paused
+                        let mut held_back = VecDeque::new();
+                        // paces the writes, if the connection's upload rate is limited
+                        let throttle = node.config().max_conn_upload_rate.map(Throttle::new);
+                        // the heartbeats sent once the connection is idle, if enabled
+                        let heartbeat = node.config().heartbeat.as_ref();
+                        let mut last_write = time::Instant::now();
 
                         loop {
-                            // TODO: when try_recv is available in tokio again (https://github.com/tokio-rs/tokio/issues/3350),
-                            // use try_recv() in order to write to the stream less often
-                            if let Some(msg) = outbound_message_receiver.recv().await {
-                                match writer_clone
-                                    .write_to_stream(&msg, addr, &mut buffer, &mut writer)
-                                    .await
-                                {
-                                    Ok(len) => {
-                                        node.known_peers().register_sent_message(addr, len);
-                                        node.stats().register_sent_message(len);
-                                        trace!(parent: node.span(), "sent {}B to {}", len, addr);
-                                    }
-                                    Err(e) => {
-                                        node.known_peers().register_failure(addr);
-                                        error!(parent: node.span(), "couldn't send a message to {}: {}", addr, e);
-                                        if node.config().fatal_io_errors.contains(&e.kind()) {
+                            let can_write = mode.borrow_and_update().can_write();
+                            let msg = if !held_back.is_empty() && !node.is_paused() && can_write {
+                                held_back.pop_front().unwrap() // safe; checked above
+                            } else {
+                                // TODO: when try_recv is available in tokio again (https://github.com/tokio-rs/tokio/issues/3350),
+                                // use try_recv() in order to write to the stream less often
+                                tokio::select! {
+                                    msg = outbound_message_receiver.recv() => {
+                                        if let Some(msg) = msg {
+                                            msg
+                                        } else {
                                             node.disconnect(addr);
                                             break;
                                         }
                                     }
+                                    _ = node.resumed(), if !held_back.is_empty() && node.is_paused() => continue,
+                                    // the connection is gone if the mode can no longer change
+                                    res = mode.changed(), if !held_back.is_empty() => if res.is_ok() { continue } else { break },
+                                    _ = time::sleep_until(last_write + Duration::from_millis(heartbeat.map(|hb| hb.interval_ms).unwrap_or_default())),
+                                        if heartbeat.is_some() && can_write =>
+                                    {
+                                        // heartbeats bypass the message queue, so they don't count as messages
+                                        let payload = &heartbeat.unwrap().payload; // safe; checked above
+                                        last_write = time::Instant::now();
+                                        *write_started.lock() = Some(Instant::now());
+                                        let ret = writer_clone
+                                            .write_to_stream(payload, &ctx, &mut buffer, &mut writer)
+                                            .await;
+                                        *write_started.lock() = None;
+                                        if let Err(e) = ret {
+                                            error!(target: WRITING, "couldn't send a heartbeat to {}: {}", addr, e);
+                                            if node.config().fatal_io_errors.contains(&e.kind()) {
+                                                node.handle_connection_loss(addr);
+                                                break;
+                                            }
+                                        } else {
+                                            trace!(target: WRITING, "sent a heartbeat to {}", addr);
+                                        }
+                                        continue;
+                                    }
+                                }
+                            };
+
+                            // while the node is paused, only the messages that ignore it are written; while the
+                            // connection is read-only, none are
+                            if node.is_paused() && msg.kind != MessageKind::Critical || !mode.borrow().can_write() {
+                                // don't hold back more messages than the outbound queue could hold
+                                if held_back.len() < queue_depth {
+                                    held_back.push_back(msg);
+                                } else {
+                                    warn!(target: WRITING, "too many messages to {} are held back; dropping the newest one", addr);
+                                    if let Some(delivery) = msg.delivery {
+                                        let _ = delivery.send(Err(io::ErrorKind::Other.into()));
+                                    }
+                                }
+                                continue;
+                            }
+
+                            // markers are only used to notify that the preceding messages were written
+                            if msg.kind == MessageKind::Marker {
+                                if let Some(delivery) = msg.delivery {
+                                    let _ = delivery.send(Ok(()));
+                                }
+                                continue;
+                            }
+
+                            let OutboundMessage { payload, delivery, .. } = msg;
+                            // the writes are timed, so that the watchdog can detect a single stuck one
+                            *write_started.lock() = Some(Instant::now());
+                            let ret = writer_clone
+                                .write_to_stream(&payload, &ctx, &mut buffer, &mut writer)
+                                .await;
+                            *write_started.lock() = None;
+                            match ret {
+                                Ok(len) => {
+                                    last_write = time::Instant::now();
+                                    node.known_peers().register_sent_message(addr, len);
+                                    node.stats().register_sent_message(len);
+                                    node.register_conn_sent_message(addr, len);
+                                    trace!(target: WRITING, "sent {}B to {}", len, addr);
+
+                                    // notify the sender of the delivery, if requested; it may no longer be interested
+                                    if let Some(delivery) = delivery {
+                                        let _ = delivery.send(Ok(()));
+                                    }
+
+                                    // stay within the upload rate limits
+                                    let delay = node.upload_delay(len, throttle.as_ref());
+                                    if !delay.is_zero() {
+                                        sleep(delay).await;
+                                    }
+                                }
+                                Err(e) => {
+                                    node.known_peers().register_failure(addr);
+                                    error!(target: WRITING, "couldn't send a message to {}: {}", addr, e);
+                                    let is_fatal = node.config().fatal_io_errors.contains(&e.kind());
+
+                                    if let Some(delivery) = delivery {
+                                        let _ = delivery.send(Err(e));
+                                    }
+
+                                    if is_fatal {
+                                        node.handle_connection_loss(addr);
+                                        break;
+                                    }
                                 }
-                            } else {
-                                node.disconnect(addr);
-                                break;
                             }
                         }
-                    });
+                    }.instrument(span.clone()));
                     conn.tasks.push(writer_task);
 
                     // return the Connection to the Node, resuming Node::adapt_stream
                     if conn_returner.send(Ok(conn)).is_err() {
-                        unreachable!("could't return a Connection to the Node");
+                        // the connection attempt was cancelled in the meantime
+                        debug!(target: WRITING, parent: &span, "the connection with {} was cancelled", addr);
                     }
                 } else {
-                    error!("the Writing protocol is down!");
+                    error!(target: WRITING, "the Writing protocol is down!");
                     break;
                 }
             }
@@ -89,30 +220,246 @@ where
         // register the WritingHandler with the Node
         self.node()
             .set_writing_handler((conn_sender, writing_task).into());
+
+        let config = self.node().config();
+        if config.writer_stall_timeout_ms.is_some() || config.slow_peer_detection.is_some() {
+            let node = self.node().clone();
+            let watchdog_task = self
+                .node()
+                .spawn_task(format_args!("writer-watchdog"), async move {
+                    watch_writers(node).await
+                });
+            self.node().set_writer_watchdog_task(watchdog_task);
+        }
     }
 
     /// Writes the given message to the provided writer, using the provided intermediate buffer; returns the number of
-    /// bytes written to the writer.
+    /// bytes written to the writer. If message signing is enabled, the message is suffixed with its signature; if
+    /// version negotiation is enabled, messages exceeding the peer's maximum frame size are rejected.
     async fn write_to_stream<W: AsyncWrite + Unpin + Send>(
         &self,
         message: &[u8],
-        addr: SocketAddr,
+        ctx: &ConnectionContext,
         buffer: &mut [u8],
         writer: &mut W,
     ) -> io::Result<usize> {
-        let len = self.write_message(addr, message, buffer)?;
+        let addr = ctx.addr();
+        let len = self.write_message(ctx, message, buffer)?;
+        #[cfg(feature = "identity")]
+        let len = sign_message(self.node(), buffer, len)?;
+
+        // don't send frames the peer has declared it can't accept
+        if let Some(peer_caps) = self.node().peer_capabilities(addr) {
+            if len > peer_caps.max_frame_size as usize {
+                error!(
+                    target: WRITING,
+                    "a {}B message exceeds the maximum frame size of {} ({}B)", len, addr, peer_caps.max_frame_size
+                );
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+        }
+
         writer.write_all(&buffer[..len]).await?;
+        // ensure that the message is fully written, even if it's been transformed
+        writer.flush().await?;
+
+        self.node()
+            .observe_frame(addr, FrameDirection::Outbound, &buffer[..len]);
 
         Ok(len)
     }
 
     /// Writes the provided payload to the given intermediate buffer; the payload can get prepended with a header
     /// indicating its length, be suffixed with a character indicating that it's complete, etc. Returns the number
-    /// of bytes written to the buffer.
+    /// of bytes written to the buffer. The provided context of the connection carries the target address and the
+    /// value produced by its handshake (see `Connection::set_handshake_data`).
     fn write_message(
         &self,
-        target: SocketAddr,
+        ctx: &ConnectionContext,
         payload: &[u8],
         buffer: &mut [u8],
     ) -> io::Result<usize>;
 }
+
+/// An extension of the `Writing` protocol allowing the node to send typed messages via `TypedWriting::send_typed`
+/// or `Node::send_typed_message`, without having to serialize them to `Bytes` at every call site; the serialized messages are then written to
+/// the stream using `Writing::write_message`, like any other payload.
+#[async_trait]
+pub trait TypedWriting: Writing {
+    /// The type of the outbound messages; it is the only type accepted by `TypedWriting::send_typed`, and the type
+    /// `Node::send_typed_message` uses this implementation for.
+    type Message: Send + 'static;
+
+    /// Prepares the node to send typed messages via `Node::send_typed_message` as well; it also enables the
+    /// `Writing` protocol, so it should be used instead of `Writing::enable_writing`. The registered serializer is
+    /// removed once the node shuts down.
+    fn enable_typed_writing(&self) {
+        let self_clone = self.clone();
+        let serializer: TypedSerializer<Self::Message> =
+            Arc::new(move |message, buffer| self_clone.serialize_message(message, buffer));
+        self.node().register_typed_serializer(serializer);
+
+        self.enable_writing();
+    }
+
+    /// Serializes the given message into the provided buffer by appending it to its contents.
+    fn serialize_message(&self, message: &Self::Message, buffer: &mut BytesMut) -> io::Result<()>;
+
+    /// Serializes the provided message using `TypedWriting::serialize_message` and sends it to the specified
+    /// `SocketAddr`, as long as the `Writing` protocol is enabled; the messages are serialized into a buffer owned
+    /// by the node, whose allocation is reused.
+    async fn send_typed(&self, addr: SocketAddr, message: Self::Message) -> io::Result<()> {
+        let payload = self
+            .node()
+            .serialize(|buffer| self.serialize_message(&message, buffer))?;
+
+        self.node().send_direct_message(addr, payload).await
+    }
+}
+
+/// The state of a connection's writer observed by the watchdog.
+struct ObservedWriter {
+    /// The number of bytes sent via the connection.
+    bytes_sent: u64,
+    /// The time since which the writer has had queued messages without making progress.
+    stalled_since: Option<Instant>,
+    /// Indicates whether the stall has already been acted upon.
+    reported: bool,
+    /// The time since which the writer's queue has been above the slow peer high-water mark.
+    congested_since: Option<Instant>,
+    /// Indicates whether the slow peer has already been acted upon.
+    reported_slow: bool,
+}
+
+/// Periodically checks the connections for writers that haven't flushed any bytes for at least
+/// `NodeConfig.writer_stall_timeout_ms` despite having queued messages or a write in progress, applying `NodeConfig.writer_stall_action`
+/// to them, and for peers whose queues remain congested as per `NodeConfig.slow_peer_detection`.
+async fn watch_writers(node: Node) {
+    trace!(target: WRITING, parent: node.span(), "spawned the writer watchdog task");
+
+    let config = node.config();
+    let stall_timeout = config.writer_stall_timeout_ms.map(Duration::from_millis);
+    let slow_peer_detection = config.slow_peer_detection.clone();
+    let check_interval = [
+        stall_timeout,
+        slow_peer_detection
+            .as_ref()
+            .map(|detection| Duration::from_millis(detection.max_duration_ms)),
+    ]
+    .iter()
+    .flatten()
+    .map(|timeout| cmp::max(*timeout / 4, Duration::from_millis(1)))
+    .min()
+    .unwrap_or(Duration::from_secs(1));
+    let mut observed: FxHashMap<SocketAddr, ObservedWriter> = Default::default();
+
+    loop {
+        sleep(check_interval).await;
+
+        let writers = node.writer_states();
+        observed.retain(|addr, _| writers.iter().any(|state| state.addr == *addr));
+
+        for WriterState {
+            addr,
+            queued,
+            bytes_sent,
+            write_started,
+        } in writers
+        {
+            let writer = observed.entry(addr).or_insert(ObservedWriter {
+                bytes_sent,
+                stalled_since: None,
+                reported: false,
+                congested_since: None,
+                reported_slow: false,
+            });
+
+            if let Some(ref detection) = slow_peer_detection {
+                if check_slow_peer(&node, addr, queued, writer, detection) {
+                    continue;
+                }
+            }
+            let stall_timeout = if let Some(timeout) = stall_timeout {
+                timeout
+            } else {
+                continue;
+            };
+
+            // a single write that takes too long means that the writer is stalled, even if its queue is empty
+            let stuck_write = write_started.filter(|started| started.elapsed() >= stall_timeout);
+
+            // otherwise, any progress or an empty queue mean that the writer isn't stalled
+            if stuck_write.is_none() && (queued == 0 || bytes_sent != writer.bytes_sent) {
+                writer.bytes_sent = bytes_sent;
+                writer.stalled_since = None;
+                writer.reported = false;
+                continue;
+            }
+
+            let stalled_since = *writer
+                .stalled_since
+                .get_or_insert_with(|| stuck_write.unwrap_or_else(Instant::now));
+            if writer.reported || stalled_since.elapsed() < stall_timeout {
+                continue;
+            }
+            writer.reported = true;
+
+            if stuck_write.is_some() {
+                warn!(
+                    target: WRITING, parent: node.span(),
+                    "the writer of {} has been stuck on a single write for {:?}", addr, stalled_since.elapsed()
+                );
+            } else {
+                warn!(
+                    target: WRITING, parent: node.span(),
+                    "the writer of {} has made no progress for {:?} despite {} queued messages", addr, stalled_since.elapsed(), queued
+                );
+            }
+
+            match node.config().writer_stall_action {
+                WriterStallAction::Log => {}
+                WriterStallAction::EmitEvent => node.emit_for(addr, NodeEvent::WriterStalled),
+                WriterStallAction::Disconnect => {
+                    node.emit_for(addr, NodeEvent::WriterStalled);
+                    node.disconnect(addr);
+                }
+            }
+        }
+    }
+}
+
+/// Checks whether the given peer's queue has remained above the high-water mark for too long, emitting a
+/// `NodeEvent::SlowPeer` if it has; returns `true` if the peer was disconnected as a result.
+fn check_slow_peer(
+    node: &Node,
+    addr: SocketAddr,
+    queued: usize,
+    writer: &mut ObservedWriter,
+    detection: &SlowPeerDetection,
+) -> bool {
+    if queued <= detection.high_water_mark {
+        writer.congested_since = None;
+        writer.reported_slow = false;
+        return false;
+    }
+
+    let congested_since = *writer.congested_since.get_or_insert_with(Instant::now);
+    if writer.reported_slow
+        || congested_since.elapsed() < Duration::from_millis(detection.max_duration_ms)
+    {
+        return false;
+    }
+    writer.reported_slow = true;
+
+    warn!(
+        target: WRITING, parent: node.span(),
+        "{} can't keep up; its outbound queue has held over {} messages for {:?}", addr, detection.high_water_mark, congested_since.elapsed()
+    );
+    node.emit_for(addr, NodeEvent::SlowPeer);
+
+    if detection.disconnect {
+        node.disconnect(addr);
+    }
+
+    detection.disconnect
+}
@@ -0,0 +1,719 @@
+//! A QUIC transport; available with the `quic` feature.
+//!
+//! `QuicTransport` maps each peer connection to a QUIC connection, and drives the `Reading` and `Writing`
+//! implementations of the wrapped object: the messages are serialized with `Writing::write_message`, deserialized
+//! with `Reading::read_message` and processed with `Reading::process_message`, just like with TCP connections.
+//!
+//! Every connection has a main (bidirectional) stream, opened by the dialer, which carries the messages sent via
+//! `QuicTransport::send`, e.g. gossip. Large messages (e.g. sync responses) can instead be sent via
+//! `QuicTransport::send_on_stream`, which uses a dedicated unidirectional stream per message; the streams are
+//! delivered independently of one another, so a large transfer doesn't hold up the traffic on the main stream. The
+//! number of unidirectional streams read concurrently per connection is limited, and so is the size of each of them.
+//!
+//! The ALPN protocol and the server name (SNI) negotiated in the TLS handshake of every connection are available as
+//! its `TlsInfo`, which can be inspected by an accept filter (see `QuicTransport::set_accept_filter`) and is also
+//! the connection's handshake data (see `ConnectionContext::handshake_data`), so that a single endpoint can serve
+//! multiple wire protocols, e.g. "gossip/1" and "sync/1", and route their messages accordingly.
+//!
+//! The wrapped object's `Node` only provides the configuration (`conn_read_buffer_size`, `conn_write_buffer_size`,
+//! `max_connections`, `dial_timeout` and `max_handshake_time_ms`), the tracing span and the message statistics;
+//! the QUIC connections don't count as the `Node`'s connections, and its `Reading` and `Writing` protocols don't
+//! need to be enabled. The transport's tasks are spawned via the `Node` (so they use its runtime and tracing
+//! settings), and are aborted by `QuicTransport::shut_down`.
+
+use crate::{
+    protocols::{Reading, Writing},
+    tracing_targets::QUIC,
+    ConnectionContext,
+};
+
+use fxhash::FxHashMap;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex as SyncMutex, RwLock};
+use quinn::{
+    crypto::rustls::{HandshakeData, QuicClientConfig, QuicServerConfig},
+    ClientConfig, Connection, Endpoint, Incoming, RecvStream, SendStream, ServerConfig, VarInt,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use tokio::{
+    sync::Mutex,
+    task::{JoinHandle, JoinSet},
+    time::timeout,
+};
+use tracing::*;
+
+use std::{convert::TryFrom, io, net::SocketAddr, sync::Arc, time::Duration};
+
+/// The server name used in the TLS handshakes.
+const SERVER_NAME: &str = "pea2pea";
+/// The default ALPN protocol identifier.
+const ALPN_PROTOCOL: &[u8] = b"pea2pea";
+/// The byte sent by the dialer in order to open the main stream.
+const MAIN_STREAM_MARKER: u8 = 0x70;
+
+/// The configuration of a `QuicTransport`.
+#[derive(Clone)]
+pub struct QuicConfig {
+    /// The configuration applied to the inbound connections.
+    pub server_config: ServerConfig,
+    /// The configuration applied to the outbound connections.
+    pub client_config: ClientConfig,
+    /// The maximum size of the data sent via a single unidirectional stream.
+    pub max_stream_len: usize,
+    /// The maximum number of unidirectional streams read concurrently per connection; the peer's further streams
+    /// wait until one of them is done.
+    pub max_concurrent_streams: usize,
+}
+
+impl QuicConfig {
+    /// Creates a configuration with a freshly generated self-signed certificate; the certificates presented by the
+    /// peers are not verified, so the peers' authentication is left to the application.
+    pub fn self_signed() -> io::Result<Self> {
+        Self::self_signed_with_protocols(&[ALPN_PROTOCOL])
+    }
+
+    /// Creates a configuration like `self_signed`, but with the given ALPN protocols, in the order of preference;
+    /// the inbound connections must offer one of them, and the outbound ones offer all of them.
+    pub fn self_signed_with_protocols(alpn_protocols: &[&[u8]]) -> io::Result<Self> {
+        let alpn_protocols = alpn_protocols
+            .iter()
+            .map(|protocol| protocol.to_vec())
+            .collect::<Vec<_>>();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+        let cert =
+            rcgen::generate_simple_self_signed(vec![SERVER_NAME.into()]).map_err(invalid_data)?;
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+        let mut server_crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(invalid_data)?
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.cert.der().clone()], key)
+            .map_err(invalid_data)?;
+        server_crypto.alpn_protocols = alpn_protocols.clone();
+        let server_config = ServerConfig::with_crypto(Arc::new(
+            QuicServerConfig::try_from(server_crypto).map_err(invalid_data)?,
+        ));
+
+        let mut client_crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(invalid_data)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+            .with_no_client_auth();
+        client_crypto.alpn_protocols = alpn_protocols;
+        let client_config = ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(client_crypto).map_err(invalid_data)?,
+        ));
+
+        Ok(Self {
+            server_config,
+            client_config,
+            max_stream_len: 16 * 1024 * 1024,
+            max_concurrent_streams: 4,
+        })
+    }
+}
+
+/// The parameters negotiated in the TLS handshake of a QUIC connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The application protocol negotiated via ALPN, if any.
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The server name indicated (SNI) by the dialer, if any.
+    pub server_name: Option<String>,
+}
+
+impl TlsInfo {
+    /// Obtains the TLS parameters of the given connection; the server name is only known to the dialing side if
+    /// it's provided.
+    fn new(conn: &Connection, dialed_name: Option<&str>) -> Self {
+        let data = conn
+            .handshake_data()
+            .and_then(|data| data.downcast::<HandshakeData>().ok());
+
+        Self {
+            alpn_protocol: data.as_ref().and_then(|data| data.protocol.clone()),
+            server_name: dialed_name
+                .map(String::from)
+                .or_else(|| data.and_then(|data| data.server_name)),
+        }
+    }
+}
+
+/// A filter consulted once the TLS handshake of an inbound connection is concluded (see
+/// `QuicTransport::set_accept_filter`); it receives the address and the `TlsInfo`, and returns `false` to veto
+/// the connection.
+pub type TlsFilter = Arc<dyn Fn(SocketAddr, &TlsInfo) -> bool + Send + Sync>;
+
+/// A QUIC transport driving the `Reading` and `Writing` implementations of the wrapped object.
+pub struct QuicTransport<T> {
+    inner: Arc<InnerQuicTransport<T>>,
+}
+
+impl<T> Clone for QuicTransport<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+struct InnerQuicTransport<T> {
+    /// The object whose `Reading` and `Writing` implementations are used.
+    protocol: T,
+    /// The QUIC endpoint used for both the inbound and outbound connections.
+    endpoint: Endpoint,
+    /// The connected peers.
+    peers: RwLock<FxHashMap<SocketAddr, QuicPeer>>,
+    /// The maximum size of the data sent via a single unidirectional stream.
+    max_stream_len: usize,
+    /// The maximum number of unidirectional streams read concurrently per connection.
+    max_concurrent_streams: usize,
+    /// The filter applied to the inbound connections.
+    accept_filter: OnceCell<TlsFilter>,
+    /// The handles to the transport's tasks, aborted on shutdown.
+    tasks: SyncMutex<Vec<JoinHandle<()>>>,
+}
+
+/// The state associated with a connected peer.
+struct QuicPeer {
+    /// The QUIC connection.
+    conn: Connection,
+    /// The connection's context; its handshake data is the `TlsInfo`.
+    ctx: ConnectionContext,
+    /// The sending side of the main stream.
+    main_stream: Arc<Mutex<SendStream>>,
+}
+
+impl<T> QuicTransport<T>
+where
+    T: Reading + Writing,
+{
+    /// Creates a QUIC endpoint bound to the given address, and starts accepting connections.
+    pub fn bind(protocol: T, addr: SocketAddr, config: QuicConfig) -> io::Result<Self> {
+        if config.max_concurrent_streams == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the number of concurrent streams must be nonzero",
+            ));
+        }
+        let mut endpoint = Endpoint::server(config.server_config, addr)?;
+        endpoint.set_default_client_config(config.client_config);
+
+        let transport = Self {
+            inner: Arc::new(InnerQuicTransport {
+                protocol,
+                endpoint,
+                peers: Default::default(),
+                max_stream_len: config.max_stream_len,
+                max_concurrent_streams: config.max_concurrent_streams,
+                accept_filter: Default::default(),
+                tasks: Default::default(),
+            }),
+        };
+
+        let transport_clone = transport.clone();
+        let task = transport.node().spawn_task(format_args!("quic-listener"), async move {
+            let mut accepts = JoinSet::new();
+            loop {
+                let incoming = tokio::select! {
+                    incoming = transport_clone.inner.endpoint.accept() => match incoming {
+                        Some(incoming) => incoming,
+                        None => break,
+                    },
+                    // reap the concluded accepts
+                    Some(_) = accepts.join_next() => continue,
+                };
+
+                let transport = transport_clone.clone();
+                let addr = incoming.remote_address();
+                transport_clone.node().spawn_task_in_set(
+                    &mut accepts,
+                    format_args!("quic-accept:{}", addr),
+                    async move {
+                        if let Err(e) = transport.accept(incoming).await {
+                            error!(target: QUIC, parent: transport.span(), "couldn't accept a connection from {}: {}", addr, e);
+                        }
+                    },
+                );
+            }
+        });
+        transport.register_task(task);
+
+        Ok(transport)
+    }
+
+    /// Registers a task, so that it's aborted on shutdown.
+    fn register_task(&self, task: JoinHandle<()>) {
+        let mut tasks = self.inner.tasks.lock();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Accepts an inbound connection, as long as the connection limit hasn't been reached and the accept filter
+    /// doesn't veto it.
+    async fn accept(&self, incoming: Incoming) -> io::Result<()> {
+        if self.num_connected() >= self.node_config().max_connections as usize {
+            incoming.refuse();
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+        let conn = incoming.await?;
+
+        let tls_info = TlsInfo::new(&conn, None);
+        if let Some(filter) = self.inner.accept_filter.get() {
+            if !filter(conn.remote_address(), &tls_info) {
+                conn.close(VarInt::from_u32(1), b"rejected");
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+        }
+
+        self.setup_connection(conn, tls_info, false).await
+    }
+
+    /// Sets a filter consulted once the TLS handshake of an inbound connection is concluded, e.g. in order to
+    /// only accept certain ALPN protocols or server names.
+    ///
+    /// note: it can only be set once.
+    pub fn set_accept_filter<F>(&self, filter: F)
+    where
+        F: Fn(SocketAddr, &TlsInfo) -> bool + Send + Sync + 'static,
+    {
+        if self.inner.accept_filter.set(Arc::new(filter)).is_err() {
+            panic!("the accept_filter field was set more than once!");
+        }
+    }
+
+    /// Returns the wrapped object.
+    pub fn protocol(&self) -> &T {
+        &self.inner.protocol
+    }
+
+    /// Returns the address the QUIC endpoint is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.endpoint.local_addr()
+    }
+
+    /// Connects to the given address.
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+        self.dial(addr, SERVER_NAME, None).await
+    }
+
+    /// Connects to the given address using the provided client configuration (e.g. one offering a specific ALPN
+    /// protocol; see `QuicConfig::self_signed_with_protocols`), indicating the given server name to the peer.
+    pub async fn connect_with(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+        client_config: ClientConfig,
+    ) -> io::Result<()> {
+        self.dial(addr, server_name, Some(client_config)).await
+    }
+
+    /// Dials the given address, using the endpoint's default client configuration if none is provided.
+    async fn dial(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+        client_config: Option<ClientConfig>,
+    ) -> io::Result<()> {
+        if self.is_connected(addr) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        if self.num_connected() >= self.node_config().max_connections as usize {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+
+        let connecting = match client_config {
+            Some(config) => self.inner.endpoint.connect_with(config, addr, server_name),
+            None => self.inner.endpoint.connect(addr, server_name),
+        }
+        .map_err(invalid_data)?;
+        let conn = timeout(self.node_config().dial_timeout, connecting)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+        let tls_info = TlsInfo::new(&conn, Some(server_name));
+        self.setup_connection(conn, tls_info, true).await
+    }
+
+    /// Closes the connection with the given address; returns `false` if it wasn't connected.
+    pub fn disconnect(&self, addr: SocketAddr) -> bool {
+        if let Some(peer) = self.inner.peers.write().remove(&addr) {
+            peer.conn.close(VarInt::from_u32(0), b"disconnected");
+            debug!(target: QUIC, parent: self.span(), "disconnected from {}", addr);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks whether the given address is connected.
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.inner.peers.read().contains_key(&addr)
+    }
+
+    /// Returns the number of connected peers.
+    pub fn num_connected(&self) -> usize {
+        self.inner.peers.read().len()
+    }
+
+    /// Returns a list containing the addresses of the connected peers.
+    pub fn connected_addrs(&self) -> Vec<SocketAddr> {
+        self.inner.peers.read().keys().copied().collect()
+    }
+
+    /// Returns the parameters negotiated in the TLS handshake with the given address, if it's connected.
+    pub fn tls_info(&self, addr: SocketAddr) -> Option<TlsInfo> {
+        self.inner
+            .peers
+            .read()
+            .get(&addr)
+            .and_then(|peer| peer.ctx.handshake_data::<TlsInfo>().cloned())
+    }
+
+    /// Sends the given message to the specified address via the connection's main stream.
+    pub async fn send(&self, addr: SocketAddr, message: &[u8]) -> io::Result<()> {
+        let (ctx, main_stream) = self
+            .inner
+            .peers
+            .read()
+            .get(&addr)
+            .map(|peer| (peer.ctx.clone(), Arc::clone(&peer.main_stream)))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+
+        let frame = self.encode(&ctx, message, self.node_config().conn_write_buffer_size)?;
+        main_stream.lock().await.write_all(&frame).await?;
+        self.protocol()
+            .node()
+            .stats()
+            .register_sent_message(frame.len());
+
+        Ok(())
+    }
+
+    /// Sends the given message to the specified address via a new unidirectional stream, so that it doesn't block
+    /// (and isn't blocked by) any other messages; meant for large messages.
+    pub async fn send_on_stream(&self, addr: SocketAddr, message: &[u8]) -> io::Result<()> {
+        let (ctx, conn) = self
+            .inner
+            .peers
+            .read()
+            .get(&addr)
+            .map(|peer| (peer.ctx.clone(), peer.conn.clone()))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+
+        // leave room for the message's framing
+        let buffer_len = message.len() + self.node_config().conn_write_buffer_size;
+        let frame = self.encode(&ctx, message, buffer_len)?;
+        if frame.len() > self.inner.max_stream_len {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let mut stream = conn.open_uni().await?;
+        stream.write_all(&frame).await?;
+        stream
+            .finish()
+            .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?;
+        self.protocol()
+            .node()
+            .stats()
+            .register_sent_message(frame.len());
+
+        Ok(())
+    }
+
+    /// Closes all the connections and the QUIC endpoint, and aborts the transport's tasks.
+    pub fn shut_down(&self) {
+        for task in self.inner.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.inner.peers.write().clear();
+        self.inner
+            .endpoint
+            .close(VarInt::from_u32(0), b"shutting down");
+    }
+
+    /// Returns the wrapped object's `Node`.
+    fn node(&self) -> &crate::Node {
+        self.protocol().node()
+    }
+
+    /// Returns the configuration of the wrapped object's `Node`.
+    fn node_config(&self) -> &crate::NodeConfig {
+        self.node().config()
+    }
+
+    /// Returns the tracing span of the wrapped object's `Node`.
+    fn span(&self) -> &Span {
+        self.node().span()
+    }
+
+    /// Serializes the given message using an intermediate buffer of the given size.
+    fn encode(
+        &self,
+        ctx: &ConnectionContext,
+        message: &[u8],
+        buffer_len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; buffer_len];
+        let len = self.protocol().write_message(ctx, message, &mut buffer)?;
+        buffer.truncate(len);
+
+        Ok(buffer)
+    }
+
+    /// Opens (or accepts) the main stream of a new connection, registers the peer and starts reading from it.
+    async fn setup_connection(
+        &self,
+        conn: Connection,
+        tls_info: TlsInfo,
+        is_dialer: bool,
+    ) -> io::Result<()> {
+        let addr = conn.remote_address();
+        // the TLS parameters are the handshake data, as QUIC connections don't perform the `Handshaking` protocol
+        let ctx = ConnectionContext::with_handshake_data(addr, Arc::new(tls_info));
+
+        let open_main_stream = async {
+            if is_dialer {
+                let (mut send, recv) = conn.open_bi().await?;
+                // the stream is only announced to the peer once something is written to it
+                send.write_all(&[MAIN_STREAM_MARKER]).await?;
+                Ok::<_, io::Error>((send, recv))
+            } else {
+                let (send, mut recv) = conn.accept_bi().await?;
+                let mut marker = [0u8; 1];
+                recv.read_exact(&mut marker)
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                if marker[0] != MAIN_STREAM_MARKER {
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+                Ok((send, recv))
+            }
+        };
+        let (send, recv) = match timeout(
+            Duration::from_millis(self.node_config().max_handshake_time_ms),
+            open_main_stream,
+        )
+        .await
+        {
+            Ok(Ok(streams)) => streams,
+            Ok(Err(e)) => {
+                conn.close(VarInt::from_u32(1), b"invalid main stream");
+                return Err(e);
+            }
+            Err(_) => {
+                conn.close(VarInt::from_u32(1), b"timed out");
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        };
+
+        {
+            let mut peers = self.inner.peers.write();
+            if peers.contains_key(&addr) {
+                conn.close(VarInt::from_u32(1), b"duplicate connection");
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            // the limit is checked again, as other connections could have been set up concurrently
+            if peers.len() >= self.node_config().max_connections as usize {
+                conn.close(VarInt::from_u32(1), b"too many connections");
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            }
+            peers.insert(
+                addr,
+                QuicPeer {
+                    conn: conn.clone(),
+                    ctx: ctx.clone(),
+                    main_stream: Arc::new(Mutex::new(send)),
+                },
+            );
+        }
+        debug!(target: QUIC, parent: self.span(), "connected to {}", addr);
+
+        // read from the main stream
+        let transport = self.clone();
+        let main_conn = conn.clone();
+        let main_ctx = ctx.clone();
+        let task = self.node().spawn_task(format_args!("quic-reader:{}", addr), async move {
+            if let Err(e) = transport.read_main_stream(&main_ctx, recv).await {
+                error!(target: QUIC, parent: transport.span(), "can't read from {}: {}", addr, e);
+                main_conn.close(VarInt::from_u32(1), b"invalid message");
+            }
+        });
+        self.register_task(task);
+
+        // accept the unidirectional streams, each in its own task; the streams are aborted with the connection
+        let transport = self.clone();
+        let task = self.node().spawn_task(format_args!("quic-streams:{}", addr), async move {
+            let mut streams = JoinSet::new();
+            loop {
+                // the peer's further streams wait until one of the ongoing ones is done
+                while streams.len() >= transport.inner.max_concurrent_streams {
+                    streams.join_next().await;
+                }
+                let stream = match conn.accept_uni().await {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+
+                let transport_clone = transport.clone();
+                let ctx = ctx.clone();
+                transport.node().spawn_task_in_set(
+                    &mut streams,
+                    format_args!("quic-stream:{}", addr),
+                    async move {
+                        if let Err(e) = transport_clone.read_uni_stream(&ctx, stream).await {
+                            error!(target: QUIC, parent: transport_clone.span(), "can't read a stream from {}: {}", addr, e);
+                        }
+                    },
+                );
+            }
+
+            // the connection was closed
+            let mut peers = transport.inner.peers.write();
+            if peers.get(&addr).map(|peer| peer.conn.stable_id()) == Some(conn.stable_id()) {
+                peers.remove(&addr);
+                debug!(target: QUIC, parent: transport.span(), "disconnected from {}", addr);
+            }
+        });
+        self.register_task(task);
+
+        Ok(())
+    }
+
+    /// Reads and processes the messages sent via the main stream until it's finished.
+    async fn read_main_stream(
+        &self,
+        ctx: &ConnectionContext,
+        mut stream: RecvStream,
+    ) -> io::Result<()> {
+        let mut buffer = vec![0u8; self.node_config().conn_read_buffer_size];
+        let mut len = 0;
+
+        loop {
+            if len == buffer.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "message too large",
+                ));
+            }
+
+            match stream.read(&mut buffer[len..]).await? {
+                Some(read) => len += read,
+                None => return Ok(()),
+            }
+
+            let processed = self.process_messages(ctx, &buffer[..len]).await?;
+            buffer.copy_within(processed..len, 0);
+            len -= processed;
+        }
+    }
+
+    /// Reads and processes the messages sent via a unidirectional stream.
+    async fn read_uni_stream(
+        &self,
+        ctx: &ConnectionContext,
+        mut stream: RecvStream,
+    ) -> io::Result<()> {
+        let data = stream
+            .read_to_end(self.inner.max_stream_len)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if self.process_messages(ctx, &data).await? != data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete message",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Processes all the complete messages contained in the given buffer; returns the number of bytes they occupied.
+    async fn process_messages(&self, ctx: &ConnectionContext, buffer: &[u8]) -> io::Result<usize> {
+        let mut processed = 0;
+
+        while processed < buffer.len() {
+            let (message, len) = match self.protocol().read_message(ctx, &buffer[processed..])? {
+                Some(message) => message,
+                None => break,
+            };
+            processed += len;
+
+            self.protocol()
+                .node()
+                .stats()
+                .register_received_message(len);
+            if let Err(e) = self
+                .protocol()
+                .process_message_with_context(ctx, message)
+                .await
+            {
+                error!(target: QUIC, parent: self.span(), "can't process a message from {}: {}", ctx.addr(), e);
+            }
+        }
+
+        Ok(processed)
+    }
+}
+
+/// A certificate verifier that accepts any (correctly signed) certificate.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Converts the given error into an `io::Error` with the `InvalidData` kind.
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
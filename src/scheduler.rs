@@ -0,0 +1,282 @@
+use crate::{tracing_targets::NODE, Node};
+
+use bytes::Bytes;
+use fxhash::{FxHashMap, FxHashSet};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tokio::{
+    sync::Notify,
+    task::{JoinHandle, JoinSet},
+    time::{self, Instant},
+};
+use tracing::*;
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    mem,
+    net::SocketAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+/// Produces the messages broadcast periodically via `Node::send_broadcast_every`.
+type MessageFn = Arc<Mutex<dyn FnMut() -> Bytes + Send>>;
+
+/// A message scheduled to be sent in the future.
+enum ScheduledSend {
+    /// A single message sent via `Node::send_direct_message_after`.
+    Direct { addr: SocketAddr, message: Bytes },
+    /// A message broadcast periodically via `Node::send_broadcast_every`.
+    Broadcast {
+        message_fn: MessageFn,
+        interval: Duration,
+    },
+}
+
+/// The node-owned timer sending the scheduled messages; all of them are driven by a single task, which is only
+/// spawned once the first message is scheduled.
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    state: Mutex<SchedulerState>,
+    /// Wakes the driver task up once an earlier deadline may have been scheduled.
+    wakeup: Notify,
+    /// The task sending the messages once their deadlines pass.
+    driver: OnceCell<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// The ID of the next scheduled message.
+    next_id: u64,
+    /// The deadlines of the scheduled messages, starting with the earliest one.
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    /// The scheduled messages, by their IDs.
+    sends: FxHashMap<u64, ScheduledSend>,
+}
+
+impl Scheduler {
+    /// Schedules a single message to be sent to the given address after the given delay.
+    pub(crate) fn schedule_direct(
+        self: &Arc<Self>,
+        node: &Node,
+        addr: SocketAddr,
+        message: Bytes,
+        delay: Duration,
+    ) -> ScheduleHandle {
+        self.schedule(node, delay, ScheduledSend::Direct { addr, message })
+    }
+
+    /// Schedules the messages produced by `message_fn` to be broadcast every `interval`.
+    pub(crate) fn schedule_broadcast<F: FnMut() -> Bytes + Send + 'static>(
+        self: &Arc<Self>,
+        node: &Node,
+        message_fn: F,
+        interval: Duration,
+    ) -> ScheduleHandle {
+        let message_fn = Arc::new(Mutex::new(message_fn));
+        self.schedule(
+            node,
+            interval,
+            ScheduledSend::Broadcast {
+                message_fn,
+                interval,
+            },
+        )
+    }
+
+    fn schedule(
+        self: &Arc<Self>,
+        node: &Node,
+        delay: Duration,
+        send: ScheduledSend,
+    ) -> ScheduleHandle {
+        let id = {
+            let mut state = self.state.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.deadlines.push(Reverse((Instant::now() + delay, id)));
+            state.sends.insert(id, send);
+            id
+        };
+
+        self.driver.get_or_init(|| {
+            let node_clone = node.clone();
+            node.spawn_task(
+                format_args!("scheduler"),
+                async move { drive(node_clone).await },
+            )
+        });
+        self.wakeup.notify_one();
+
+        ScheduleHandle {
+            id,
+            scheduler: Arc::downgrade(self),
+        }
+    }
+
+    /// Cancels the scheduled message with the given ID; returns `false` if it's no longer scheduled.
+    fn cancel(&self, id: u64) -> bool {
+        let mut state = self.state.lock();
+        if state.sends.remove(&id).is_none() {
+            return false;
+        }
+        let deadlines = mem::take(&mut state.deadlines);
+        state.deadlines = deadlines
+            .into_iter()
+            .filter(|Reverse((_, scheduled_id))| *scheduled_id != id)
+            .collect();
+        drop(state);
+
+        // the driver task may be waiting for the cancelled message's deadline
+        self.wakeup.notify_one();
+
+        true
+    }
+
+    /// Checks whether the message with the given ID is still scheduled.
+    fn is_scheduled(&self, id: u64) -> bool {
+        self.state.lock().sends.contains_key(&id)
+    }
+
+    /// Stops the driver task, along with the sends in progress, and discards all the scheduled messages.
+    pub(crate) fn shut_down(&self) {
+        if let Some(task) = self.driver.get() {
+            task.abort();
+        }
+        let mut state = self.state.lock();
+        state.deadlines.clear();
+        state.sends.clear();
+    }
+
+    /// Removes the messages whose deadlines have passed from the schedule, rescheduling the periodic ones; returns
+    /// the ones to be sent, along with their IDs.
+    fn take_due(&self) -> Vec<(u64, ScheduledSend)> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        let mut due = Vec::new();
+
+        while let Some(&Reverse((deadline, id))) = state.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            state.deadlines.pop();
+
+            match state.sends.get(&id) {
+                Some(ScheduledSend::Direct { .. }) => {
+                    // safe; checked above
+                    due.push((id, state.sends.remove(&id).unwrap()));
+                }
+                Some(ScheduledSend::Broadcast {
+                    message_fn,
+                    interval,
+                }) => {
+                    let send = ScheduledSend::Broadcast {
+                        message_fn: Arc::clone(message_fn),
+                        interval: *interval,
+                    };
+                    // skip the ticks that were missed, rather than catching up on them in a burst
+                    let mut next = deadline + *interval;
+                    if next <= now {
+                        next = now + *interval;
+                    }
+                    state.deadlines.push(Reverse((next, id)));
+                    due.push((id, send));
+                }
+                // the message was cancelled in the meantime
+                None => {}
+            }
+        }
+
+        due
+    }
+
+    /// Returns the earliest deadline, if there are any scheduled messages.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.state
+            .lock()
+            .deadlines
+            .peek()
+            .map(|Reverse((deadline, _))| *deadline)
+    }
+}
+
+/// Sends the scheduled messages once their deadlines pass. The sends are performed by tasks owned by the driver, so
+/// that they are stopped along with it; a periodic broadcast is skipped if the previous one is still in progress.
+async fn drive(node: Node) {
+    trace!(target: NODE, parent: node.span(), "spawned the scheduler task");
+    let scheduler = Arc::clone(node.scheduler());
+    // the sends in progress, which return the IDs of their messages
+    let mut sends = JoinSet::new();
+    // the IDs of the periodic broadcasts in progress
+    let mut broadcasts_in_progress = FxHashSet::default();
+
+    loop {
+        // a notification received in the meantime is retained by the `Notify`
+        let deadline = scheduler.next_deadline();
+        tokio::select! {
+            _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {}
+            _ = scheduler.wakeup.notified() => continue,
+            Some(result) = sends.join_next(), if !sends.is_empty() => {
+                if let Ok(id) = result {
+                    broadcasts_in_progress.remove(&id);
+                }
+                continue;
+            }
+        }
+
+        for (id, send) in scheduler.take_due() {
+            let node_clone = node.clone();
+            match send {
+                ScheduledSend::Direct { addr, message } => {
+                    node.spawn_task_in_set(&mut sends, format_args!("scheduled-send:{}", addr), async move {
+                        if let Err(e) = node_clone.send_direct_message(addr, message).await {
+                            warn!(target: NODE, parent: node_clone.span(), "couldn't send a scheduled message to {}: {}", addr, e);
+                        }
+                        id
+                    });
+                }
+                ScheduledSend::Broadcast { message_fn, .. } => {
+                    if !broadcasts_in_progress.insert(id) {
+                        debug!(target: NODE, parent: node.span(), "skipping a scheduled broadcast; the previous one is still in progress");
+                        continue;
+                    }
+                    let message = (*message_fn.lock())();
+                    node.spawn_task_in_set(&mut sends, format_args!("scheduled-broadcast"), async move {
+                        if let Err(e) = node_clone.send_broadcast(message).await {
+                            warn!(target: NODE, parent: node_clone.span(), "couldn't broadcast a scheduled message: {}", e);
+                        }
+                        id
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// A handle to a message scheduled via `Node::send_direct_message_after` or `Node::send_broadcast_every`; it can
+/// be used to cancel the message. Dropping the handle doesn't cancel the message.
+#[derive(Clone)]
+pub struct ScheduleHandle {
+    id: u64,
+    scheduler: Weak<Scheduler>,
+}
+
+impl ScheduleHandle {
+    /// Cancels the scheduled message; returns `false` if it's no longer scheduled, i.e. it was already sent (if
+    /// it's a single message) or cancelled, or the node was shut down.
+    pub fn cancel(&self) -> bool {
+        self.scheduler
+            .upgrade()
+            .map(|scheduler| scheduler.cancel(self.id))
+            .unwrap_or(false)
+    }
+
+    /// Checks whether the message is still scheduled.
+    pub fn is_scheduled(&self) -> bool {
+        self.scheduler
+            .upgrade()
+            .map(|scheduler| scheduler.is_scheduled(self.id))
+            .unwrap_or(false)
+    }
+}
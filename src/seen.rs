@@ -0,0 +1,126 @@
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
+
+/// A bounded cache of the hashes of recently seen messages, used to suppress echoes and repeated relays; the
+/// entries expire after the configured TTL, and the least recently seen ones are evicted once the capacity is
+/// reached. The node keeps one for `Node::relay`, but it can also be used on its own.
+pub struct SeenCache {
+    /// The randomly keyed hasher of the messages, so that their hashes can't be made to collide on purpose.
+    hasher: RandomState,
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<SeenState>,
+}
+
+#[derive(Default)]
+struct SeenState {
+    /// The time each hash was last seen at, along with the sequence number of its most recent queue entry.
+    entries: FxHashMap<u64, (Instant, u64)>,
+    /// The hashes in the order in which they were seen; entries whose sequence numbers are no longer current
+    /// are stale and skipped.
+    order: VecDeque<(u64, u64)>,
+    /// The sequence number of the next queue entry.
+    next_seq: u64,
+}
+
+impl SeenCache {
+    /// Creates a cache holding up to `capacity` hashes, each for up to `ttl`.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            hasher: RandomState::new(),
+            capacity: capacity.max(1),
+            ttl,
+            state: Default::default(),
+        }
+    }
+
+    /// Hashes the given message using the cache's own randomly keyed SipHash; the hashes are only meaningful to
+    /// the cache that produced them.
+    pub fn hash(&self, message: &[u8]) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(message);
+        hasher.finish()
+    }
+
+    /// Registers a sighting of the given hash; returns `true` if it wasn't seen within the TTL.
+    pub fn insert(&self, hash: u64) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        state.purge(now, self.ttl);
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        let is_new = state.entries.insert(hash, (now, seq)).is_none();
+        state.order.push_back((hash, seq));
+
+        while state.entries.len() > self.capacity {
+            state.pop_oldest();
+        }
+        // don't let the stale entries accumulate if the same hashes keep being seen
+        if state.order.len() > 2 * self.capacity {
+            let SeenState { entries, order, .. } = &mut *state;
+            order.retain(|(hash, seq)| entries.get(hash).map(|(_, s)| s) == Some(seq));
+        }
+
+        is_new
+    }
+
+    /// Checks whether the given hash was seen within the TTL, without registering a sighting.
+    pub fn contains(&self, hash: u64) -> bool {
+        self.state
+            .lock()
+            .entries
+            .get(&hash)
+            .map(|(seen, _)| seen.elapsed() <= self.ttl)
+            .unwrap_or(false)
+    }
+
+    /// Returns the number of cached hashes, including the ones that expired but weren't purged yet.
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    /// Checks whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all the cached hashes.
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.entries.clear();
+        state.order.clear();
+    }
+}
+
+impl SeenState {
+    /// Removes the hashes that were last seen longer than `ttl` ago.
+    fn purge(&mut self, now: Instant, ttl: Duration) {
+        while let Some(&(hash, seq)) = self.order.front() {
+            match self.entries.get(&hash) {
+                Some(&(seen, s)) if s == seq && now.duration_since(seen) <= ttl => break,
+                Some(&(_, s)) if s == seq => {
+                    self.entries.remove(&hash);
+                }
+                _ => {}
+            }
+            self.order.pop_front();
+        }
+    }
+
+    /// Removes the least recently seen hash.
+    fn pop_oldest(&mut self) {
+        while let Some((hash, seq)) = self.order.pop_front() {
+            if self.entries.get(&hash).map(|(_, s)| *s) == Some(seq) {
+                self.entries.remove(&hash);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use bytes::Bytes;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    net::SocketAddr,
+};
+
+/// The outcome of registering the sequence number of a received message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeqStatus {
+    /// The message was the next one expected.
+    Expected,
+    /// The message was one of the missing ones, e.g. a retransmission.
+    Recovered,
+    /// The message was preceded by a gap; contains the sequence numbers of the messages missing in between, which
+    /// are capped at `NodeConfig.retransmit_buffer_len`.
+    Gap(Vec<u64>),
+    /// The message had already been received, it is too old to be considered missing, or its sequence number is
+    /// invalid (`u64::MAX` is never assigned).
+    Duplicate,
+}
+
+impl SeqStatus {
+    /// Checks whether the message should be processed, i.e. whether it wasn't a duplicate.
+    pub fn is_new(&self) -> bool {
+        *self != Self::Duplicate
+    }
+}
+
+/// Keeps track of the sequence numbers of the messages sent via `Node::send_sequenced` and received from peers: it
+/// detects the gaps in the received sequences, and retains the recently sent messages so that they can be
+/// retransmitted via `Node::retransmit` once the peers report them as missing (e.g. via the `Nacking` protocol).
+pub struct Sequences {
+    peers: Mutex<FxHashMap<SocketAddr, PeerSequences>>,
+    retransmit_buffer_len: usize,
+}
+
+/// The sequence-related state associated with a single peer.
+#[derive(Default)]
+struct PeerSequences {
+    /// The sequence number of the next message sent to the peer.
+    next_outbound: u64,
+    /// The most recently sent messages, along with their sequence numbers.
+    sent: VecDeque<(u64, Bytes)>,
+    /// The sequence number of the next message expected from the peer.
+    next_inbound: u64,
+    /// The sequence numbers of the messages from the peer that are missing.
+    missing: BTreeSet<u64>,
+}
+
+impl Sequences {
+    /// Creates a collection retaining up to `retransmit_buffer_len` sent messages per peer.
+    pub(crate) fn new(retransmit_buffer_len: usize) -> Self {
+        Self {
+            peers: Default::default(),
+            retransmit_buffer_len,
+        }
+    }
+
+    /// Assigns the next sequence number to a message sent to the given address, encodes the message using it, and
+    /// retains it in case it needs to be retransmitted.
+    pub(crate) fn register_sent<F: FnOnce(u64) -> Bytes>(
+        &self,
+        to: SocketAddr,
+        encode: F,
+    ) -> (u64, Bytes) {
+        let mut peers = self.peers.lock();
+        let peer = peers.entry(to).or_default();
+
+        let seq = peer.next_outbound;
+        let message = encode(seq);
+        peer.next_outbound += 1;
+
+        if peer.sent.len() == self.retransmit_buffer_len {
+            peer.sent.pop_front();
+        }
+        peer.sent.push_back((seq, message.clone()));
+
+        (seq, message)
+    }
+
+    /// Returns the retained messages sent to the given address with the given sequence numbers; the ones that are
+    /// no longer retained are skipped.
+    pub(crate) fn retained(&self, to: SocketAddr, seqs: &[u64]) -> Vec<Bytes> {
+        let peers = self.peers.lock();
+        let sent = match peers.get(&to) {
+            Some(peer) => &peer.sent,
+            None => return Vec::new(),
+        };
+        let first = match sent.front() {
+            Some((seq, _)) => *seq,
+            None => return Vec::new(),
+        };
+
+        // the retained sequence numbers are contiguous
+        seqs.iter()
+            .filter_map(|seq| seq.checked_sub(first))
+            .filter_map(|idx| sent.get(idx as usize))
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+
+    /// Registers the sequence number of a message received from the given address.
+    pub fn register_received(&self, from: SocketAddr, seq: u64) -> SeqStatus {
+        // the next expected sequence number can't be represented past it
+        if seq == u64::MAX {
+            return SeqStatus::Duplicate;
+        }
+
+        let mut peers = self.peers.lock();
+        let peer = peers.entry(from).or_default();
+
+        if seq == peer.next_inbound {
+            peer.next_inbound += 1;
+            SeqStatus::Expected
+        } else if seq > peer.next_inbound {
+            // the messages older than the sender's retransmit buffer can't be recovered
+            let gap_start = peer
+                .next_inbound
+                .max(seq.saturating_sub(self.retransmit_buffer_len as u64));
+            let gap = (gap_start..seq).collect::<Vec<_>>();
+            peer.missing.extend(&gap);
+            while peer.missing.len() > self.retransmit_buffer_len {
+                peer.missing.pop_first();
+            }
+            peer.next_inbound = seq + 1;
+
+            SeqStatus::Gap(gap)
+        } else if peer.missing.remove(&seq) {
+            SeqStatus::Recovered
+        } else {
+            SeqStatus::Duplicate
+        }
+    }
+
+    /// Returns the sequence numbers of the messages from the given address that are still missing.
+    pub fn missing(&self, from: SocketAddr) -> Vec<u64> {
+        self.peers
+            .lock()
+            .get(&from)
+            .map(|peer| peer.missing.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes all the sequence-related state associated with the given address.
+    pub fn remove(&self, addr: SocketAddr) {
+        self.peers.lock().remove(&addr);
+    }
+}
@@ -0,0 +1,71 @@
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Counts the requests the node has served to each of its peers within the current accounting window.
+#[derive(Default)]
+pub(crate) struct ServedRequests(Mutex<ServingWindow>);
+
+#[derive(Default)]
+struct ServingWindow {
+    /// The time the current window started at.
+    start: Option<Instant>,
+    /// The number of requests served to each of the peers.
+    counts: FxHashMap<SocketAddr, u64>,
+    /// The total number of requests served.
+    total: u64,
+}
+
+impl ServingWindow {
+    /// Starts a new window if the current one is older than `window`.
+    fn refresh(&mut self, window: Duration) {
+        let now = Instant::now();
+        if self
+            .start
+            .map(|start| now.duration_since(start) >= window)
+            .unwrap_or(true)
+        {
+            self.start = Some(now);
+            self.counts.clear();
+            self.total = 0;
+        }
+    }
+}
+
+impl ServedRequests {
+    /// Registers a request served to the given peer.
+    pub(crate) fn register(&self, addr: SocketAddr, window: Duration) {
+        let mut served = self.0.lock();
+        served.refresh(window);
+
+        *served.counts.entry(addr).or_default() += 1;
+        served.total += 1;
+    }
+
+    /// Returns the number of requests served to the given peer, along with the total number of served requests.
+    pub(crate) fn count(&self, addr: SocketAddr, window: Duration) -> (u64, u64) {
+        let mut served = self.0.lock();
+        served.refresh(window);
+
+        (served.counts.get(&addr).copied().unwrap_or(0), served.total)
+    }
+
+    /// Returns the numbers of requests served to all the peers, in descending order.
+    pub(crate) fn counts(&self, window: Duration) -> Vec<(SocketAddr, u64)> {
+        let mut served = self.0.lock();
+        served.refresh(window);
+
+        let mut counts = served
+            .counts
+            .iter()
+            .map(|(addr, count)| (*addr, *count))
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+
+        counts
+    }
+}
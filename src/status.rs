@@ -0,0 +1,82 @@
+use std::net::SocketAddr;
+
+/// A one-call summary of the node's health, returned by `Node::status`; it can be embedded e.g. in status RPCs,
+/// and it is serializable if the `serde` feature is enabled.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeStatus {
+    /// The name of the node.
+    pub name: String,
+    /// The number of seconds since the node was created.
+    pub uptime_secs: u64,
+    /// The node's listening address; `None` if the node is outbound-only.
+    pub listening_addr: Option<SocketAddr>,
+    /// The node's external address, if it's known (see `Node::external_addr`).
+    pub external_addr: Option<SocketAddr>,
+    /// Indicates whether the node is accepting inbound connections.
+    pub is_listening: bool,
+    /// Indicates whether the node is paused (see `Node::pause`).
+    pub is_paused: bool,
+    /// The numbers of the node's connections.
+    pub connections: ConnectionCounts,
+    /// The number of known peers.
+    pub known_peers: usize,
+    /// The utilization of the connections' outbound queues.
+    pub queues: QueueUtilization,
+    /// The node's total traffic.
+    pub traffic: TrafficTotals,
+}
+
+/// The numbers of the node's connections by their state and direction; part of `NodeStatus`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConnectionCounts {
+    /// The number of fully established connections.
+    pub established: usize,
+    /// The number of established connections initiated by the peers.
+    pub inbound: usize,
+    /// The number of established connections initiated by the node.
+    pub outbound: usize,
+    /// The number of established connections that are being closed gracefully.
+    pub closing: usize,
+    /// The number of outbound connections that are being established.
+    pub connecting: usize,
+    /// The number of inbound connections that have been accepted, but not fully established yet.
+    pub pending_inbound: usize,
+    /// The maximum number of connections, i.e. `NodeConfig.max_connections`.
+    pub max: usize,
+}
+
+/// The utilization of the connections' outbound queues; part of `NodeStatus`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct QueueUtilization {
+    /// The number of outbound messages queued for all the connections.
+    pub outbound_queued: usize,
+    /// The number of outbound messages that can be queued for all the connections.
+    pub outbound_capacity: usize,
+    /// The highest fraction of a single connection's outbound queue that is occupied.
+    pub max_outbound_occupancy: f64,
+}
+
+/// The node's total traffic; part of `NodeStatus`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TrafficTotals {
+    /// The number of all messages sent.
+    pub msgs_sent: u64,
+    /// The number of all bytes sent.
+    pub bytes_sent: u64,
+    /// The number of all messages received.
+    pub msgs_received: u64,
+    /// The number of all bytes received.
+    pub bytes_received: u64,
+    /// The number of inbound messages dropped due to their processing task not running.
+    pub msgs_dropped: u64,
+    /// The number of inbound messages dropped due to their processing queue being full.
+    pub msgs_overflowed: u64,
+    /// The number of bytes sent per second over the last 5 seconds.
+    pub sent_per_sec: f64,
+    /// The number of bytes received per second over the last 5 seconds.
+    pub received_per_sec: f64,
+}
@@ -1,7 +1,6 @@
 use crate::Pea2Pea;
 
-use fxhash::FxHashSet;
-use std::io;
+use std::{io, net::SocketAddr};
 
 /// The way in which nodes are connected to each other; used in `connect_nodes`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,28 +26,30 @@ pub async fn connect_nodes<T: Pea2Pea>(nodes: &[T], topology: Topology) -> io::R
     match topology {
         Topology::Line | Topology::Ring => {
             for i in 0..(count - 1) {
-                let addr = nodes[i + 1].node().listening_addr();
+                let addr = listening_addr(&nodes[i + 1])?;
                 nodes[i].node().connect(addr).await?;
             }
             if topology == Topology::Ring {
-                let addr = nodes[0].node().listening_addr();
+                let addr = listening_addr(&nodes[0])?;
                 nodes[count - 1].node().connect(addr).await?;
             }
         }
         Topology::Mesh => {
-            let mut connected_pairs =
-                FxHashSet::with_capacity_and_hasher((count - 1) * 2, Default::default());
-            for i in 0..count {
-                for (j, peer) in nodes.iter().enumerate() {
-                    if i != j && connected_pairs.insert((i, j)) && connected_pairs.insert((j, i)) {
-                        let addr = peer.node().listening_addr();
-                        nodes[i].node().connect(addr).await?;
-                    }
+            // each node connects to all the nodes that come after it in the list
+            for (i, node) in nodes.iter().enumerate() {
+                let addrs = nodes
+                    .iter()
+                    .skip(i + 1)
+                    .map(listening_addr)
+                    .collect::<io::Result<Vec<_>>>()?;
+
+                for (_, result) in node.node().connect_many(&addrs).await {
+                    result?;
                 }
             }
         }
         Topology::Star => {
-            let hub_addr = nodes[0].node().listening_addr();
+            let hub_addr = listening_addr(&nodes[0])?;
             for node in nodes.iter().skip(1) {
                 node.node().connect(hub_addr).await?;
             }
@@ -57,3 +58,10 @@ pub async fn connect_nodes<T: Pea2Pea>(nodes: &[T], topology: Topology) -> io::R
 
     Ok(())
 }
+
+/// Returns the listening address of the given node; outbound-only nodes can't be connected to.
+fn listening_addr<T: Pea2Pea>(node: &T) -> io::Result<SocketAddr> {
+    node.node()
+        .listening_addr()
+        .ok_or_else(|| io::ErrorKind::AddrNotAvailable.into())
+}
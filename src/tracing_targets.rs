@@ -0,0 +1,44 @@
+//! The `tracing` targets used by the different subsystems of the `Node`; they can be used to adjust the verbosity
+//! of a single subsystem, e.g. with an `EnvFilter` directive like `pea2pea::reading=trace`.
+//!
+//! All the events are emitted within the span of the associated `Node` (available via `Node::span`), which contains
+//! its `name` and listening `addr`, and, if they relate to a specific connection, also within a child `conn` span
+//! containing the connection's `id` and `addr`; this allows the events of a single node to be selected, e.g. with
+//! an `EnvFilter` directive like `[node{name=alice}]=debug`.
+//!
+//! In tests involving many nodes, the events of each node can also be sent to a dedicated `tracing` dispatcher (or
+//! silenced altogether) via `NodeConfig::tracing_dispatch`.
+
+/// The target of events related to the node itself and its connections' lifecycle.
+pub const NODE: &str = "pea2pea::node";
+
+/// The target of events related to version negotiation and the `Handshaking` protocol.
+pub const HANDSHAKE: &str = "pea2pea::handshake";
+
+/// The target of events related to the `Reading` protocol.
+pub const READING: &str = "pea2pea::reading";
+
+/// The target of events related to the `Writing` protocol.
+pub const WRITING: &str = "pea2pea::writing";
+
+/// The target of events related to the `KeepAlive` protocol.
+pub const KEEPALIVE: &str = "pea2pea::keepalive";
+
+/// The target of events related to the `Acknowledging` protocol.
+pub const ACKS: &str = "pea2pea::acks";
+
+/// The target of events related to the `PubSub` protocol.
+pub const PUBSUB: &str = "pea2pea::pubsub";
+
+/// The target of events related to the `Multiplexing` protocol.
+pub const MULTIPLEXING: &str = "pea2pea::multiplexing";
+
+/// The target of events related to peer discovery, i.e. bootstrapping, seed lists, DNS seeding and the
+/// `PeerExchange` protocol.
+pub const DISCOVERY: &str = "pea2pea::discovery";
+
+/// The target of events related to the QUIC transport.
+pub const QUIC: &str = "pea2pea::quic";
+
+/// The target of events related to NAT traversal.
+pub const NAT: &str = "pea2pea::nat";
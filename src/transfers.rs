@@ -0,0 +1,501 @@
+//! Reassembly of large payloads (e.g. snapshots) sent in chunks, with bounded memory usage.
+//!
+//! A chunk is encoded as follows (the integers are little-endian):
+//!
+//! ```text
+//! [transfer ID: 8B][offset: 8B][total length: 8B][data]
+//! ```
+//!
+//! The chunks of a single transfer are expected in order; they can be created with `encode_chunks`, and their
+//! payloads passed to `Reassembler::handle_chunk` on the receiving side. Once a transfer would exceed its memory
+//! budget, the data received so far is spilled to the `TransferStorage` (if there is one), and so are the subsequent
+//! chunks; otherwise the transfer is aborted. The chunks of a single transfer must be handled one at a time, while
+//! different transfers can be handled concurrently; the number of transfers in progress, the size of the data
+//! spilled to storage and the time a transfer can go without a new chunk are limited as well.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+use tokio::task;
+
+use std::{
+    convert::TryInto,
+    fs,
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The size of a chunk's header.
+pub const CHUNK_HEADER_LEN: usize = 24;
+
+/// Identifies a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransferKey {
+    /// The address of the sender.
+    pub source: SocketAddr,
+    /// The ID of the transfer, unique for the sender.
+    pub id: u64,
+}
+
+/// A piece of a transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The ID of the transfer.
+    pub transfer_id: u64,
+    /// The offset of the chunk's data within the transfer.
+    pub offset: u64,
+    /// The total length of the transfer.
+    pub total_len: u64,
+    /// The chunk's data.
+    pub data: Bytes,
+}
+
+impl Chunk {
+    /// Encodes the chunk.
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = Vec::with_capacity(CHUNK_HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(&self.transfer_id.to_le_bytes());
+        bytes.extend_from_slice(&self.offset.to_le_bytes());
+        bytes.extend_from_slice(&self.total_len.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+
+        bytes.into()
+    }
+
+    /// Decodes a chunk.
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < CHUNK_HEADER_LEN {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        // safe; the slices have the exact lengths required by the conversions
+        let read_u64 = |offset: usize| u64::from_le_bytes(bytes[offset..][..8].try_into().unwrap());
+
+        Ok(Self {
+            transfer_id: read_u64(0),
+            offset: read_u64(8),
+            total_len: read_u64(16),
+            data: Bytes::copy_from_slice(&bytes[CHUNK_HEADER_LEN..]),
+        })
+    }
+}
+
+/// Splits the given payload into encoded chunks carrying up to `chunk_size` bytes of data each.
+pub fn encode_chunks(transfer_id: u64, payload: &[u8], chunk_size: usize) -> Vec<Bytes> {
+    let total_len = payload.len() as u64;
+
+    payload
+        .chunks(chunk_size.max(1))
+        .scan(0u64, |offset, data| {
+            let chunk = Chunk {
+                transfer_id,
+                offset: *offset,
+                total_len,
+                data: Bytes::copy_from_slice(data),
+            };
+            *offset += data.len() as u64;
+
+            Some(chunk.encode())
+        })
+        .collect()
+}
+
+/// A backing store the transfers exceeding their memory budget are spilled to; its methods are called from within
+/// async tasks, so any blocking I/O should be moved off the runtime's worker threads.
+#[async_trait]
+pub trait TransferStorage: Send + Sync {
+    /// Appends the given data to the stored contents of the transfer.
+    async fn append(&self, key: TransferKey, data: Bytes) -> io::Result<()>;
+
+    /// Removes the stored contents of the transfer.
+    async fn remove(&self, key: TransferKey) -> io::Result<()>;
+}
+
+/// A `TransferStorage` keeping every transfer in a file within the given directory; the file operations are performed
+/// on tokio's blocking thread pool.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    /// Creates a `FileStorage` using the given directory, creating it if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self { dir })
+    }
+
+    /// Returns the path of the file containing the given transfer.
+    pub fn path(&self, key: TransferKey) -> PathBuf {
+        let source = key
+            .source
+            .to_string()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+
+        self.dir.join(format!("{}-{}.transfer", source, key.id))
+    }
+}
+
+#[async_trait]
+impl TransferStorage for FileStorage {
+    async fn append(&self, key: TransferKey, data: Bytes) -> io::Result<()> {
+        let path = self.path(key);
+
+        task::spawn_blocking(move || {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&data)
+        })
+        .await?
+    }
+
+    async fn remove(&self, key: TransferKey) -> io::Result<()> {
+        let path = self.path(key);
+
+        match task::spawn_blocking(move || fs::remove_file(path)).await? {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The limits of a `Reassembler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyLimits {
+    /// The maximum total length of a single transfer.
+    pub max_transfer_len: u64,
+    /// The maximum number of bytes of a single transfer held in memory.
+    pub max_transfer_memory: usize,
+    /// The maximum number of bytes of all the transfers held in memory.
+    pub max_total_memory: usize,
+    /// The maximum number of transfers in progress.
+    pub max_transfers: usize,
+    /// The maximum number of bytes of all the transfers in progress spilled to storage.
+    pub max_total_stored: u64,
+    /// The time after which a transfer that hasn't received a new chunk is aborted.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ReassemblyLimits {
+    fn default() -> Self {
+        Self {
+            max_transfer_len: 1024 * 1024 * 1024,
+            max_transfer_memory: 16 * 1024 * 1024,
+            max_total_memory: 64 * 1024 * 1024,
+            max_transfers: 64,
+            max_total_stored: 4 * 1024 * 1024 * 1024,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The progress of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// The number of bytes received so far.
+    pub received: u64,
+    /// The total length of the transfer.
+    pub total_len: u64,
+    /// The number of bytes of the transfer held in memory.
+    pub memory_usage: usize,
+    /// Indicates whether the transfer was spilled to the `TransferStorage`.
+    pub spilled: bool,
+}
+
+/// The outcome of a completed transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletedTransfer {
+    /// The transfer was held in memory.
+    InMemory(Bytes),
+    /// The transfer was spilled to the `TransferStorage`, which now contains all of it; it should be removed from
+    /// the storage once it's no longer needed.
+    Stored(TransferKey),
+}
+
+/// A transfer being reassembled.
+struct Transfer {
+    total_len: u64,
+    received: u64,
+    buffer: Vec<u8>,
+    spilled: bool,
+    /// The number of bytes appended to storage.
+    stored: u64,
+    /// Indicates whether a chunk is being written to storage.
+    storing: bool,
+    last_chunk: Instant,
+}
+
+/// Reassembles chunked transfers, keeping their memory usage within the given limits; a single instance should be
+/// used for all the transfers received by a node, so that the node-wide limit applies.
+pub struct Reassembler {
+    limits: ReassemblyLimits,
+    storage: Option<Arc<dyn TransferStorage>>,
+    state: Mutex<ReassemblerState>,
+}
+
+#[derive(Default)]
+struct ReassemblerState {
+    transfers: FxHashMap<TransferKey, Transfer>,
+    memory_usage: usize,
+    stored: u64,
+}
+
+impl Reassembler {
+    /// Creates a `Reassembler` with the given limits and optional storage the transfers exceeding their memory
+    /// budget are spilled to; without storage, such transfers are aborted.
+    pub fn new(limits: ReassemblyLimits, storage: Option<Arc<dyn TransferStorage>>) -> Self {
+        Self {
+            limits,
+            storage,
+            state: Default::default(),
+        }
+    }
+
+    /// Handles the given encoded chunk received from the given peer; returns the transfer once it's complete. An
+    /// error aborts the transfer; `ErrorKind::OutOfMemory` indicates that one of the limits was exceeded and the
+    /// transfer couldn't be spilled to storage. Any idle transfers are aborted beforehand.
+    pub async fn handle_chunk(
+        &self,
+        source: SocketAddr,
+        chunk: &[u8],
+    ) -> io::Result<Option<CompletedTransfer>> {
+        let chunk = Chunk::decode(chunk)?;
+        let key = TransferKey {
+            source,
+            id: chunk.transfer_id,
+        };
+
+        self.abort_idle().await;
+
+        let ret = self.append_chunk(key, chunk).await;
+        if ret.is_err() {
+            self.abort(key).await;
+        }
+
+        ret
+    }
+
+    /// Appends the given chunk to its transfer; the state lock isn't held while the storage is written to.
+    async fn append_chunk(
+        &self,
+        key: TransferKey,
+        chunk: Chunk,
+    ) -> io::Result<Option<CompletedTransfer>> {
+        let to_store = {
+            let mut state = self.state.lock();
+            let ReassemblerState {
+                transfers,
+                memory_usage,
+                stored,
+            } = &mut *state;
+
+            if chunk.offset == 0 && !transfers.contains_key(&key) {
+                if chunk.total_len > self.limits.max_transfer_len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the transfer is too large",
+                    ));
+                }
+                if transfers.len() >= self.limits.max_transfers {
+                    return Err(io::Error::new(
+                        io::ErrorKind::OutOfMemory,
+                        "too many transfers in progress",
+                    ));
+                }
+                transfers.insert(
+                    key,
+                    Transfer {
+                        total_len: chunk.total_len,
+                        received: 0,
+                        buffer: Vec::new(),
+                        spilled: false,
+                        stored: 0,
+                        storing: false,
+                        last_chunk: Instant::now(),
+                    },
+                );
+            }
+
+            let transfer = transfers
+                .get_mut(&key)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown transfer"))?;
+            let len = chunk.data.len() as u64;
+            if transfer.storing
+                || chunk.offset != transfer.received
+                || chunk.total_len != transfer.total_len
+                || transfer.received + len > transfer.total_len
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected chunk",
+                ));
+            }
+            transfer.last_chunk = Instant::now();
+
+            let to_store = if transfer.spilled {
+                Some(chunk.data)
+            } else {
+                let fits = transfer.buffer.len() + chunk.data.len()
+                    <= self.limits.max_transfer_memory
+                    && *memory_usage + chunk.data.len() <= self.limits.max_total_memory;
+
+                if fits {
+                    transfer.buffer.extend_from_slice(&chunk.data);
+                    *memory_usage += chunk.data.len();
+                    None
+                } else if self.storage.is_some() {
+                    // move the buffered data to storage, followed by all the subsequent chunks
+                    let mut buffer = std::mem::take(&mut transfer.buffer);
+                    *memory_usage -= buffer.len();
+                    buffer.extend_from_slice(&chunk.data);
+                    transfer.spilled = true;
+                    Some(Bytes::from(buffer))
+                } else {
+                    return Err(io::ErrorKind::OutOfMemory.into());
+                }
+            };
+
+            if let Some(data) = &to_store {
+                let data_len = data.len() as u64;
+                if *stored + data_len > self.limits.max_total_stored {
+                    return Err(io::Error::new(
+                        io::ErrorKind::OutOfMemory,
+                        "the storage limit was exceeded",
+                    ));
+                }
+                *stored += data_len;
+                transfer.stored += data_len;
+                transfer.storing = true;
+            }
+            transfer.received += len;
+
+            match to_store {
+                Some(data) => data,
+                None if transfer.received < transfer.total_len => return Ok(None),
+                None => {
+                    // safe; the transfer was obtained above
+                    let transfer = transfers.remove(&key).unwrap();
+                    *memory_usage -= transfer.buffer.len();
+
+                    return Ok(Some(CompletedTransfer::InMemory(transfer.buffer.into())));
+                }
+            }
+        };
+
+        // safe; data is only stored if there is storage
+        let storage = self.storage.as_ref().unwrap();
+        storage.append(key, to_store).await?;
+
+        let aborted = {
+            let mut state = self.state.lock();
+            match state.transfers.get_mut(&key) {
+                Some(transfer) => {
+                    transfer.storing = false;
+
+                    if transfer.received < transfer.total_len {
+                        return Ok(None);
+                    }
+
+                    // safe; the transfer was obtained above
+                    let transfer = state.transfers.remove(&key).unwrap();
+                    state.stored -= transfer.stored;
+                    false
+                }
+                None => true,
+            }
+        };
+
+        if aborted {
+            // the transfer was aborted while the chunk was being stored
+            storage.remove(key).await?;
+            return Err(io::Error::other("the transfer was aborted"));
+        }
+
+        Ok(Some(CompletedTransfer::Stored(key)))
+    }
+
+    /// Returns the progress of the given transfer, if it's in progress.
+    pub fn progress(&self, key: TransferKey) -> Option<TransferProgress> {
+        self.state
+            .lock()
+            .transfers
+            .get(&key)
+            .map(|transfer| TransferProgress {
+                received: transfer.received,
+                total_len: transfer.total_len,
+                memory_usage: transfer.buffer.len(),
+                spilled: transfer.spilled,
+            })
+    }
+
+    /// Returns the number of bytes of all the transfers held in memory.
+    pub fn memory_usage(&self) -> usize {
+        self.state.lock().memory_usage
+    }
+
+    /// Returns the number of bytes of all the transfers in progress spilled to storage.
+    pub fn stored_len(&self) -> u64 {
+        self.state.lock().stored
+    }
+
+    /// Aborts the given transfer, removing its data from memory and storage.
+    pub async fn abort(&self, key: TransferKey) {
+        let transfer = {
+            let mut state = self.state.lock();
+            let transfer = state.transfers.remove(&key);
+            if let Some(transfer) = &transfer {
+                state.memory_usage -= transfer.buffer.len();
+                state.stored -= transfer.stored;
+            }
+            transfer
+        };
+
+        if let (Some(transfer), Some(storage)) = (transfer, &self.storage) {
+            if transfer.spilled {
+                let _ = storage.remove(key).await;
+            }
+        }
+    }
+
+    /// Aborts all the transfers from the given peer, e.g. once it disconnects.
+    pub async fn abort_peer(&self, source: SocketAddr) {
+        let keys = self
+            .state
+            .lock()
+            .transfers
+            .keys()
+            .filter(|key| key.source == source)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            self.abort(key).await;
+        }
+    }
+
+    /// Aborts all the transfers that haven't received a new chunk within `ReassemblyLimits::idle_timeout`; it's
+    /// called whenever a chunk is handled, but can also be called periodically.
+    pub async fn abort_idle(&self) {
+        let keys = self
+            .state
+            .lock()
+            .transfers
+            .iter()
+            .filter(|(_, transfer)| {
+                !transfer.storing && transfer.last_chunk.elapsed() >= self.limits.idle_timeout
+            })
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            self.abort(key).await;
+        }
+    }
+}
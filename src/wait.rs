@@ -0,0 +1,140 @@
+use crate::{Node, NodeEvent};
+
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{sleep, timeout},
+};
+
+use std::{future::Future, io, time::Duration};
+
+/// The interval at which `wait_for` checks its condition.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A condition on a `NodeEvent`, used with `wait_for_all_events`.
+pub type EventCondition = Box<dyn FnMut(&NodeEvent) -> bool + Send>;
+
+/// Waits until the given condition on the node is satisfied, checking it every millisecond; fails with an
+/// `io::ErrorKind::TimedOut` error if it isn't satisfied within `max_time`.
+///
+/// ```ignore
+/// pea2pea::wait_for(&node, |node| node.num_connected() == 3, Duration::from_secs(1)).await?;
+/// ```
+pub async fn wait_for<F>(node: &Node, mut condition: F, max_time: Duration) -> io::Result<()>
+where
+    F: FnMut(&Node) -> bool,
+{
+    timeout(max_time, async {
+        while !condition(node) {
+            sleep(POLL_INTERVAL).await;
+        }
+    })
+    .await
+    .map_err(|_| io::ErrorKind::TimedOut.into())
+}
+
+/// Waits for an event emitted by the node that satisfies the given condition, and returns it; fails with an
+/// `io::ErrorKind::TimedOut` error if there is no such event within `max_time`. The events are subscribed to as
+/// soon as this function is called, so it can be called before the action that triggers the awaited event.
+pub fn wait_for_event<F>(
+    node: &Node,
+    condition: F,
+    max_time: Duration,
+) -> impl Future<Output = io::Result<NodeEvent>>
+where
+    F: FnMut(&NodeEvent) -> bool,
+{
+    let events = wait_for_events(node, condition, 1, max_time);
+
+    async move {
+        // safe; exactly one event is collected on success
+        events.await.map(|mut events| events.pop().unwrap())
+    }
+}
+
+/// Waits for `count` events emitted by the node that satisfy the given condition, and returns them in the order in
+/// which they were emitted; the events are subscribed to as soon as this function is called. Fails with an
+/// `io::ErrorKind::TimedOut` error if there are fewer such events within `max_time`.
+pub fn wait_for_events<F>(
+    node: &Node,
+    mut condition: F,
+    count: usize,
+    max_time: Duration,
+) -> impl Future<Output = io::Result<Vec<NodeEvent>>>
+where
+    F: FnMut(&NodeEvent) -> bool,
+{
+    let mut matched = Vec::with_capacity(count);
+
+    watch_events(node, max_time, move |event| {
+        if matched.len() < count && condition(&event) {
+            matched.push(event);
+        }
+        if matched.len() == count {
+            Some(std::mem::take(&mut matched))
+        } else {
+            None
+        }
+    })
+}
+
+/// Waits until each of the given conditions is satisfied by an event emitted by the node, in any order, and returns
+/// the matching events in the order of the conditions; a single event only counts towards the first condition it
+/// satisfies, and the events are subscribed to as soon as this function is called. Fails with an
+/// `io::ErrorKind::TimedOut` error if any of the conditions isn't satisfied within `max_time`.
+pub fn wait_for_all_events(
+    node: &Node,
+    mut conditions: Vec<EventCondition>,
+    max_time: Duration,
+) -> impl Future<Output = io::Result<Vec<NodeEvent>>> {
+    let mut matched: Vec<Option<NodeEvent>> = vec![None; conditions.len()];
+
+    watch_events(node, max_time, move |event| {
+        if let Some(idx) =
+            (0..conditions.len()).find(|&idx| matched[idx].is_none() && conditions[idx](&event))
+        {
+            matched[idx] = Some(event);
+        }
+
+        if matched.iter().all(Option::is_some) {
+            Some(matched.drain(..).flatten().collect())
+        } else {
+            None
+        }
+    })
+}
+
+/// Subscribes to the node's events right away, and returns a future passing them to `on_event` until it produces
+/// a result or `max_time` passes.
+fn watch_events<T, F>(
+    node: &Node,
+    max_time: Duration,
+    mut on_event: F,
+) -> impl Future<Output = io::Result<T>>
+where
+    F: FnMut(NodeEvent) -> Option<T>,
+{
+    let mut events = node.subscribe();
+
+    async move {
+        let watching = async {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if let Some(result) = on_event(event) {
+                            return Ok(result);
+                        }
+                    }
+                    // the awaited events could have been among the missed ones
+                    Err(RecvError::Lagged(num_missed)) => {
+                        return Err(io::Error::other(format!("missed {} events", num_missed)));
+                    }
+                    Err(RecvError::Closed) => return Err(io::ErrorKind::NotConnected.into()),
+                }
+            }
+        };
+
+        timeout(max_time, watching)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    }
+}
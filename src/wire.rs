@@ -0,0 +1,245 @@
+//! A ready-made wire format: every frame consists of magic bytes, the length of the payload (a little-endian `u32`),
+//! the payload and an optional checksum of the payload (a little-endian `u32`), e.g.
+//!
+//! ```text
+//! [magic: 4B][len: 4B][payload: len B][checksum: 4B]
+//! ```
+//!
+//! It is meant to be used in `Reading::read_message` and `Writing::write_message` via `WireFormat::{read_frame,
+//! write_frame}`; a frame that fails validation results in a `WireError` (wrapped in an `io::Error` of the
+//! `InvalidData` kind), which causes the connection to be dropped.
+
+use std::{convert::TryInto, error::Error, fmt, io};
+
+/// The size of the magic bytes.
+const MAGIC_LEN: usize = 4;
+/// The size of the length of the payload.
+const LEN_LEN: usize = 4;
+/// The size of the checksum.
+const CHECKSUM_LEN: usize = 4;
+
+/// The algorithm used to calculate the checksums of the payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-32 (IEEE 802.3).
+    Crc32,
+    /// XXH32 with a seed of 0.
+    Xxh32,
+}
+
+impl Checksum {
+    /// Calculates the checksum of the given data.
+    pub fn compute(&self, data: &[u8]) -> u32 {
+        match self {
+            Self::Crc32 => crc32(data),
+            Self::Xxh32 => xxh32(data),
+        }
+    }
+}
+
+/// The reasons for which a frame can be rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireError {
+    /// The frame doesn't start with the expected magic bytes.
+    InvalidMagic,
+    /// The declared length of the payload exceeds `WireFormat.max_payload_len`.
+    PayloadTooLarge(usize),
+    /// The checksum of the payload doesn't match the one included in the frame.
+    ChecksumMismatch,
+    /// The buffer the frame is written to is too small.
+    BufferTooSmall,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "invalid magic bytes"),
+            Self::PayloadTooLarge(len) => write!(f, "the payload is too large ({}B)", len),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Self::BufferTooSmall => write!(f, "the buffer is too small for the frame"),
+        }
+    }
+}
+
+impl Error for WireError {}
+
+impl From<WireError> for io::Error {
+    fn from(err: WireError) -> Self {
+        let kind = if err == WireError::BufferTooSmall {
+            io::ErrorKind::InvalidInput
+        } else {
+            io::ErrorKind::InvalidData
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+/// The configuration of the wire format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WireFormat {
+    /// The bytes every frame starts with; they distinguish the network, and help detect misaligned reads.
+    pub magic: [u8; MAGIC_LEN],
+    /// The algorithm used to calculate the checksums of the payloads; if `None`, the frames contain no checksums.
+    pub checksum: Option<Checksum>,
+    /// The maximum size of a payload; frames declaring larger ones are rejected as soon as their header is read.
+    pub max_payload_len: usize,
+}
+
+impl WireFormat {
+    /// Returns the size of the frame carrying a payload of the given size.
+    pub fn frame_len(&self, payload_len: usize) -> usize {
+        let checksum_len = if self.checksum.is_some() {
+            CHECKSUM_LEN
+        } else {
+            0
+        };
+
+        MAGIC_LEN + LEN_LEN + payload_len + checksum_len
+    }
+
+    /// Reads a single frame from the given buffer; returns its payload along with the number of bytes the frame
+    /// occupied in the buffer, or `Ok(None)` if the frame is incomplete. It can be used in `Reading::read_message`.
+    pub fn read_frame<'a>(&self, buffer: &'a [u8]) -> io::Result<Option<(&'a [u8], usize)>> {
+        // validate the magic bytes as soon as they are available, even if only in part
+        let magic_len = buffer.len().min(MAGIC_LEN);
+        if buffer[..magic_len] != self.magic[..magic_len] {
+            return Err(WireError::InvalidMagic.into());
+        }
+
+        if buffer.len() < MAGIC_LEN + LEN_LEN {
+            return Ok(None);
+        }
+
+        // safe; the length was checked above
+        let payload_len =
+            u32::from_le_bytes(buffer[MAGIC_LEN..][..LEN_LEN].try_into().unwrap()) as usize;
+        if payload_len > self.max_payload_len {
+            return Err(WireError::PayloadTooLarge(payload_len).into());
+        }
+
+        let frame_len = self.frame_len(payload_len);
+        if buffer.len() < frame_len {
+            return Ok(None);
+        }
+
+        let payload = &buffer[MAGIC_LEN + LEN_LEN..][..payload_len];
+        if let Some(checksum) = self.checksum {
+            // safe; the length was checked above
+            let expected = u32::from_le_bytes(
+                buffer[frame_len - CHECKSUM_LEN..frame_len]
+                    .try_into()
+                    .unwrap(),
+            );
+            if checksum.compute(payload) != expected {
+                return Err(WireError::ChecksumMismatch.into());
+            }
+        }
+
+        Ok(Some((payload, frame_len)))
+    }
+
+    /// Writes a frame carrying the given payload to the given buffer; returns the number of bytes written. It can be
+    /// used in `Writing::write_message`.
+    pub fn write_frame(&self, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+        if payload.len() > self.max_payload_len || payload.len() > u32::MAX as usize {
+            return Err(WireError::PayloadTooLarge(payload.len()).into());
+        }
+
+        let frame_len = self.frame_len(payload.len());
+        if buffer.len() < frame_len {
+            return Err(WireError::BufferTooSmall.into());
+        }
+
+        buffer[..MAGIC_LEN].copy_from_slice(&self.magic);
+        buffer[MAGIC_LEN..][..LEN_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        buffer[MAGIC_LEN + LEN_LEN..][..payload.len()].copy_from_slice(payload);
+        if let Some(checksum) = self.checksum {
+            buffer[frame_len - CHECKSUM_LEN..frame_len]
+                .copy_from_slice(&checksum.compute(payload).to_le_bytes());
+        }
+
+        Ok(frame_len)
+    }
+}
+
+/// Calculates the CRC-32 (IEEE 802.3) of the given data.
+fn crc32(data: &[u8]) -> u32 {
+    const POLY: u32 = 0xedb8_8320;
+
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+const XXH_PRIME_1: u32 = 2_654_435_761;
+const XXH_PRIME_2: u32 = 2_246_822_519;
+const XXH_PRIME_3: u32 = 3_266_489_917;
+const XXH_PRIME_4: u32 = 668_265_263;
+const XXH_PRIME_5: u32 = 374_761_393;
+
+/// Calculates the XXH32 (with a seed of 0) of the given data.
+fn xxh32(data: &[u8]) -> u32 {
+    let read_u32 = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap()); // safe; always 4B
+    let round = |acc: u32, lane: u32| {
+        acc.wrapping_add(lane.wrapping_mul(XXH_PRIME_2))
+            .rotate_left(13)
+            .wrapping_mul(XXH_PRIME_1)
+    };
+
+    let mut stripes = data.chunks_exact(16);
+    let mut hash = if data.len() >= 16 {
+        let mut acc = [
+            XXH_PRIME_1.wrapping_add(XXH_PRIME_2),
+            XXH_PRIME_2,
+            0,
+            0u32.wrapping_sub(XXH_PRIME_1),
+        ];
+        for stripe in &mut stripes {
+            for (acc, lane) in acc.iter_mut().zip(stripe.chunks_exact(4)) {
+                *acc = round(*acc, read_u32(lane));
+            }
+        }
+
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        XXH_PRIME_5
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+
+    let mut lanes = stripes.remainder().chunks_exact(4);
+    for lane in &mut lanes {
+        hash = hash
+            .wrapping_add(read_u32(lane).wrapping_mul(XXH_PRIME_3))
+            .rotate_left(17)
+            .wrapping_mul(XXH_PRIME_4);
+    }
+    for &byte in lanes.remainder() {
+        hash = hash
+            .wrapping_add((byte as u32).wrapping_mul(XXH_PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(XXH_PRIME_1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(XXH_PRIME_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(XXH_PRIME_3);
+    hash ^= hash >> 16;
+
+    hash
+}
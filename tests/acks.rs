@@ -0,0 +1,294 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+mod common;
+use parking_lot::Mutex;
+use pea2pea::{
+    protocols::{Acknowledging, Nacking, Reading, Writing},
+    ConnectionContext, Node, NodeConfig, Pea2Pea, SeqStatus,
+};
+
+use std::{
+    convert::TryInto,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+// a message is either a payload with an ID (0) or a batch of acks (1)
+#[derive(Clone)]
+struct AckingNode(Node);
+
+impl Pea2Pea for AckingNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for AckingNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Bytes, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Bytes) -> io::Result<()> {
+        let ids = message[1..]
+            .chunks(8)
+            .map(|id| u64::from_le_bytes(id.try_into().unwrap()));
+
+        if message[0] == 0 {
+            for id in ids {
+                self.node().acks().queue_ack(source, id);
+            }
+        } else {
+            self.node()
+                .acks()
+                .register_acked(source, &ids.collect::<Vec<_>>());
+        }
+
+        Ok(())
+    }
+}
+
+impl Writing for AckingNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl Acknowledging for AckingNode {
+    fn ack_batch_message(&self, ids: &[u64]) -> Bytes {
+        let mut batch = BytesMut::with_capacity(1 + ids.len() * 8);
+        batch.put_u8(1);
+        for id in ids {
+            batch.put_u64_le(*id);
+        }
+        batch.freeze()
+    }
+}
+
+#[tokio::test]
+async fn acks_are_batched() {
+    const NUM_MESSAGES: u64 = 10;
+
+    let config = NodeConfig {
+        ack_flush_interval_ms: 50,
+        ..Default::default()
+    };
+    let nodes = common::start_nodes(2, Some(config))
+        .await
+        .into_iter()
+        .map(AckingNode)
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_reading();
+        node.enable_writing();
+        node.enable_acknowledging();
+    }
+    let (sender, receiver) = (&nodes[0], &nodes[1]);
+    let receiver_addr = receiver.node().listening_addr().unwrap();
+
+    sender.node().connect(receiver_addr).await.unwrap();
+    wait_until!(1, receiver.node().num_connected() == 1);
+
+    for id in 0..NUM_MESSAGES {
+        let mut msg = BytesMut::with_capacity(9);
+        msg.put_u8(0);
+        msg.put_u64_le(id);
+
+        sender.node().acks().register_sent(receiver_addr, id);
+        sender
+            .node()
+            .send_direct_message(receiver_addr, msg.freeze())
+            .await
+            .unwrap();
+    }
+
+    // all the messages get acknowledged with fewer ack messages
+    wait_until!(1, sender.node().acks().num_unacked(receiver_addr) == 0);
+    assert!(sender.node().stats().received().0 < NUM_MESSAGES);
+
+    let median = sender.node().acks().latency_percentile(receiver_addr, 0.5);
+    let max = sender.node().acks().latency_percentile(receiver_addr, 1.0);
+    assert!(median.is_some() && median <= max);
+
+    // the ack-related state doesn't outlive the connection
+    sender
+        .node()
+        .acks()
+        .register_sent(receiver_addr, NUM_MESSAGES);
+    assert_eq!(sender.node().acks().num_unacked(receiver_addr), 1);
+    assert!(sender.node().disconnect(receiver_addr));
+    assert_eq!(sender.node().acks().num_unacked(receiver_addr), 0);
+    assert!(sender
+        .node()
+        .acks()
+        .latency_percentile(receiver_addr, 0.5)
+        .is_none());
+}
+
+// a message is either a payload with a sequence number (0) or a NACK listing the missing ones (1)
+#[derive(Clone)]
+struct NackingNode {
+    node: Node,
+    // the sequence number of a message the writer drops the first time it is sent
+    lost_seq: u64,
+    lost: Arc<AtomicBool>,
+    received: Arc<Mutex<Vec<u64>>>,
+}
+
+impl Pea2Pea for NackingNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for NackingNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Bytes, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Bytes) -> io::Result<()> {
+        let seqs = message[1..]
+            .chunks(8)
+            .map(|seq| u64::from_le_bytes(seq.try_into().unwrap()))
+            .collect::<Vec<_>>();
+
+        if message[0] == 0 {
+            if self.register_sequenced(source, seqs[0]).await?.is_new() {
+                self.received.lock().push(seqs[0]);
+            }
+        } else {
+            self.node().retransmit(source, &seqs).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Writing for NackingNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        if payload[0] == 0
+            && payload[1..9] == self.lost_seq.to_le_bytes()
+            && !self.lost.swap(true, Ordering::Relaxed)
+        {
+            return Ok(0);
+        }
+
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl Nacking for NackingNode {
+    fn nack_message(&self, missing: &[u64]) -> Bytes {
+        let mut nack = BytesMut::with_capacity(1 + missing.len() * 8);
+        nack.put_u8(1);
+        for seq in missing {
+            nack.put_u64_le(*seq);
+        }
+        nack.freeze()
+    }
+}
+
+#[tokio::test]
+async fn nacks_trigger_retransmissions() {
+    const NUM_MESSAGES: u64 = 5;
+
+    let nodes = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(|node| NackingNode {
+            node,
+            lost_seq: 2,
+            lost: Default::default(),
+            received: Default::default(),
+        })
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_reading();
+        node.enable_writing();
+    }
+    let (sender, receiver) = (&nodes[0], &nodes[1]);
+    let receiver_addr = receiver.node().listening_addr().unwrap();
+
+    sender.node().connect(receiver_addr).await.unwrap();
+    wait_until!(1, receiver.node().num_connected() == 1);
+
+    for _ in 0..NUM_MESSAGES {
+        sender
+            .node()
+            .send_sequenced(receiver_addr, |seq| {
+                let mut msg = BytesMut::with_capacity(9);
+                msg.put_u8(0);
+                msg.put_u64_le(seq);
+                msg.freeze()
+            })
+            .await
+            .unwrap();
+    }
+
+    // the lost message is reported as missing and retransmitted
+    wait_until!(1, receiver.received.lock().len() == NUM_MESSAGES as usize);
+    assert!(sender.lost.load(Ordering::Relaxed));
+    assert_eq!(*receiver.received.lock(), [0, 1, 3, 4, 2]);
+    let sender_addr = receiver.node().connected_addrs()[0];
+    assert!(receiver.node().sequences().missing(sender_addr).is_empty());
+
+    // duplicates are detected
+    assert_eq!(
+        receiver
+            .node()
+            .sequences()
+            .register_received(sender_addr, 2),
+        SeqStatus::Duplicate
+    );
+    assert_eq!(
+        receiver
+            .node()
+            .sequences()
+            .register_received(sender_addr, 7),
+        SeqStatus::Gap(vec![5, 6])
+    );
+
+    // the largest sequence number is never assigned, and is rejected
+    assert_eq!(
+        receiver
+            .node()
+            .sequences()
+            .register_received(sender_addr, u64::MAX),
+        SeqStatus::Duplicate
+    );
+}
@@ -5,10 +5,10 @@ use rand::{distributions::Standard, rngs::SmallRng, Rng, SeedableRng};
 mod common;
 use pea2pea::{
     protocols::{Reading, Writing},
-    Node, NodeConfig, Pea2Pea,
+    ConnectionContext, Node, NodeConfig, Pea2Pea,
 };
 
-use std::{io, net::SocketAddr, time::Instant};
+use std::{io, time::Instant};
 
 static RANDOM_BYTES: Lazy<Bytes> = Lazy::new(|| {
     Bytes::from(
@@ -29,9 +29,14 @@ impl Pea2Pea for Spammer {
 }
 
 impl Writing for Spammer {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
-        buffer[4..][..payload.len()].copy_from_slice(&payload);
+        buffer[4..][..payload.len()].copy_from_slice(payload);
         Ok(4 + payload.len())
     }
 }
@@ -50,7 +55,7 @@ impl Reading for Sink {
 
     fn read_message(
         &self,
-        _source: SocketAddr,
+        _ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         let bytes = common::read_len_prefixed_message(4, buffer)?;
@@ -75,13 +80,6 @@ fn display_throughput(bytes: f64) -> String {
     }
 }
 
-#[derive(Debug)]
-struct BenchParams {
-    spammer_count: usize,
-    msg_count: usize,
-    max_msg_size: usize,
-}
-
 async fn run_bench_scenario(sender_count: usize) -> f64 {
     const NUM_MESSAGES: usize = 10_000;
     const MSG_SIZE: usize = 64 * 1024;
@@ -108,14 +106,14 @@ async fn run_bench_scenario(sender_count: usize) -> f64 {
     for spammer in &spammers {
         spammer
             .node()
-            .connect(sink.node().listening_addr())
+            .connect(sink.node().listening_addr().unwrap())
             .await
             .unwrap();
     }
 
     wait_until!(1, sink.node().num_connected() == sender_count);
 
-    let sink_addr = sink.node().listening_addr();
+    let sink_addr = sink.node().listening_addr().unwrap();
 
     let start = Instant::now();
     for spammer in spammers {
@@ -159,3 +157,52 @@ async fn bench_spam_to_one() {
     let avg_throughput = results.iter().sum::<f64>() / results.len() as f64;
     println!("\naverage: {}", display_throughput(avg_throughput));
 }
+
+async fn run_small_message_scenario(inline: bool) -> f64 {
+    const NUM_MESSAGES: usize = 100_000;
+    const MSG_SIZE: usize = 16;
+
+    let spammer = Spammer(Node::new(None).await.unwrap());
+    spammer.enable_writing();
+    let sink = Sink(Node::new(None).await.unwrap());
+    sink.enable_reading();
+
+    let sink_addr = sink.node().listening_addr().unwrap();
+    spammer.node().connect(sink_addr).await.unwrap();
+    wait_until!(1, sink.node().num_connected() == 1);
+
+    let message = &RANDOM_BYTES[..MSG_SIZE];
+    let start = Instant::now();
+    for _ in 0..NUM_MESSAGES {
+        if inline {
+            spammer
+                .node()
+                .send_small_message(sink_addr, message)
+                .await
+                .unwrap();
+        } else {
+            spammer
+                .node()
+                .send_direct_message(sink_addr, Bytes::copy_from_slice(message))
+                .await
+                .unwrap();
+        }
+    }
+
+    wait_until!(
+        10,
+        sink.node().stats().received().0 as usize == NUM_MESSAGES
+    );
+
+    NUM_MESSAGES as f64 / start.elapsed().as_secs_f64()
+}
+
+#[ignore]
+#[tokio::test(flavor = "multi_thread")]
+async fn bench_small_messages() {
+    let heap = run_small_message_scenario(false).await;
+    println!("16B messages via send_direct_message: {:.0} msg/s", heap);
+
+    let inline = run_small_message_scenario(true).await;
+    println!("16B messages via send_small_message:  {:.0} msg/s", inline);
+}
@@ -0,0 +1,144 @@
+#![cfg(feature = "bitcoin")]
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+mod common;
+use pea2pea::{
+    bitcoin::{checksum, BitcoinConfig, BitcoinMessage, BitcoinNode, MAX_HANDSHAKE_PAYLOAD_LEN},
+    protocols::{Handshaking, Reading, Writing},
+    ConnectionContext, Node, Pea2Pea,
+};
+
+use std::time::Duration;
+
+// a mainnet verack message
+const VERACK: [u8; 24] = [
+    0xf9, 0xbe, 0xb4, 0xd9, b'v', b'e', b'r', b'a', b'c', b'k', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x5d,
+    0xf6, 0xe0, 0xe2,
+];
+
+#[tokio::test]
+async fn bitcoin_envelope() {
+    assert_eq!(checksum(&[]), [0x5d, 0xf6, 0xe0, 0xe2]);
+
+    let (node, _) = BitcoinNode::new(Node::new(None).await.unwrap(), BitcoinConfig::mainnet());
+    let ctx = ConnectionContext::new("127.0.0.1:8333".parse().unwrap());
+
+    // the command is passed to the Writing protocol in front of the payload
+    let mut buffer = [0u8; 64];
+    let len = node
+        .write_message(&ctx, b"verack\0\0\0\0\0\0", &mut buffer)
+        .unwrap();
+    assert_eq!(buffer[..len], VERACK);
+
+    for i in 0..VERACK.len() {
+        assert!(node.read_message(&ctx, &VERACK[..i]).unwrap().is_none());
+    }
+    let (message, len) = node.read_message(&ctx, &VERACK).unwrap().unwrap();
+    assert_eq!(message.command, "verack");
+    assert!(message.payload.is_empty());
+    assert_eq!(len, VERACK.len());
+
+    // messages from other networks or with bad checksums are rejected
+    let (testnet_node, _) =
+        BitcoinNode::new(Node::new(None).await.unwrap(), BitcoinConfig::testnet());
+    assert!(testnet_node.read_message(&ctx, &VERACK).is_err());
+    let mut corrupted = VERACK;
+    corrupted[23] ^= 1;
+    assert!(node.read_message(&ctx, &corrupted).is_err());
+}
+
+#[tokio::test]
+async fn bitcoin_handshake_and_messaging() {
+    let config = BitcoinConfig {
+        user_agent: "/alice:0.1/".into(),
+        start_height: 100,
+        ..BitcoinConfig::regtest()
+    };
+    let (alice, mut alice_inbound) = BitcoinNode::new(Node::new(None).await.unwrap(), config);
+    let config = BitcoinConfig {
+        user_agent: "/bob:0.1/".into(),
+        relay: true,
+        ..BitcoinConfig::regtest()
+    };
+    let (bob, mut bob_inbound) = BitcoinNode::new(Node::new(None).await.unwrap(), config);
+    for node in [&alice, &bob] {
+        node.enable_handshaking();
+        node.enable_reading();
+        node.enable_writing();
+    }
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    // the details advertised during the handshake are available
+    let bobs_version = alice.peer_version(bob_addr).unwrap();
+    assert_eq!(bobs_version.version, 70016);
+    assert_eq!(bobs_version.user_agent, "/bob:0.1/");
+    assert!(bobs_version.relay);
+    let alices_version = bob.peer_version(alice_addr).unwrap();
+    assert_eq!(alices_version.user_agent, "/alice:0.1/");
+    assert_eq!(alices_version.start_height, 100);
+    assert!(!alices_version.relay);
+
+    alice.send(bob_addr, "hello", b"bob").await.unwrap();
+    let (source, message) = timeout(Duration::from_secs(1), bob_inbound.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(source, alice_addr);
+    assert_eq!(
+        message,
+        BitcoinMessage {
+            command: "hello".into(),
+            payload: b"bob"[..].into(),
+        }
+    );
+
+    // pings are answered automatically
+    alice
+        .send(bob_addr, "ping", &7u64.to_le_bytes())
+        .await
+        .unwrap();
+    let (_, message) = timeout(Duration::from_secs(1), alice_inbound.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.command, "pong");
+    assert_eq!(message.payload[..], 7u64.to_le_bytes());
+
+    // the commands can't be longer than 12 bytes
+    assert!(alice
+        .send(bob_addr, "a_very_long_command", &[])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn bitcoin_handshake_rejects_large_payloads() {
+    let (node, _) = BitcoinNode::new(Node::new(None).await.unwrap(), BitcoinConfig::mainnet());
+    node.enable_handshaking();
+    let addr = node.node().listening_addr().unwrap();
+
+    // a version message declaring a payload larger than the handshake allows
+    let mut header = VERACK;
+    header[4..16].copy_from_slice(b"version\0\0\0\0\0");
+    header[16..20].copy_from_slice(&(MAX_HANDSHAKE_PAYLOAD_LEN as u32 + 1).to_le_bytes());
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&header).await.unwrap();
+
+    // the connection is dropped without waiting for the payload
+    let mut buffer = Vec::new();
+    timeout(Duration::from_secs(1), stream.read_to_end(&mut buffer))
+        .await
+        .unwrap()
+        .ok();
+    assert_eq!(node.node().num_connected(), 0);
+}
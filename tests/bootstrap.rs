@@ -0,0 +1,138 @@
+#![cfg(feature = "bootstrap")]
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use pea2pea::{
+    bootstrap::{parse_seed_list, SeedList},
+    identity::NodeIdentity,
+    DnsAnswer, NetworkEvent, Node, NodeConfig, Resolver,
+};
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+const SEEDS: &str = "127.0.0.1:4000\n127.0.0.1:4001, 127.0.0.1:4002\n";
+
+fn sign_seed_list(identity: &NodeIdentity, payload: &str) -> String {
+    let signature = identity
+        .sign(payload.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!("{}\n{}", signature, payload)
+}
+
+// serves the given document over HTTP to a single client
+async fn serve_once(document: String) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request).await.unwrap();
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            document.len(),
+            document
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn bootstrap_from_seed_list() {
+    let publisher = NodeIdentity::generate();
+    let server_addr = serve_once(sign_seed_list(&publisher, SEEDS)).await;
+
+    let config = NodeConfig {
+        seed_lists: vec![SeedList {
+            url: format!("http://{}/seeds", server_addr),
+            public_key: publisher.public_key(),
+        }],
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+
+    assert_eq!(node.bootstrap().await.unwrap(), 3);
+    for port in 4000..=4002 {
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        assert!(node.known_peers().read().contains_key(&addr));
+    }
+}
+
+#[tokio::test]
+async fn bootstrap_dials_seed_lists() {
+    // resolves all the names to the loopback address
+    struct LoopbackResolver;
+
+    #[async_trait]
+    impl Resolver for LoopbackResolver {
+        async fn resolve(&self, _host: &str) -> io::Result<DnsAnswer> {
+            Ok(DnsAnswer {
+                ips: vec!["127.0.0.1".parse().unwrap()],
+                ttl: None,
+            })
+        }
+    }
+
+    let config = NodeConfig {
+        listener_ip: "127.0.0.1".parse().unwrap(),
+        ..Default::default()
+    };
+    let peer = Node::new(Some(config)).await.unwrap();
+    let peer_addr = peer.listening_addr().unwrap();
+
+    let publisher = NodeIdentity::generate();
+    let server_addr = serve_once(sign_seed_list(&publisher, &peer_addr.to_string())).await;
+
+    // the seed list's host is resolved via the node's resolver
+    let config = NodeConfig {
+        seed_lists: vec![SeedList {
+            url: format!("http://seeds.example.test:{}/seeds", server_addr.port()),
+            public_key: publisher.public_key(),
+        }],
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    node.set_resolver(Arc::new(LoopbackResolver));
+    let mut events = node.subscribe_network_events();
+
+    assert!(node.start_bootstrapping());
+    let num_connected = loop {
+        if let NetworkEvent::BootstrapCompleted(num_connected) = events.recv().await.unwrap() {
+            break num_connected;
+        }
+    };
+    assert_eq!(num_connected, 1);
+    assert!(node.is_connected(peer_addr));
+    assert!(node.known_peers().read().contains_key(&peer_addr));
+}
+
+#[test]
+fn seed_list_verification() {
+    let publisher = NodeIdentity::generate();
+    let document = sign_seed_list(&publisher, SEEDS);
+
+    assert_eq!(
+        parse_seed_list(document.as_bytes(), &publisher.public_key())
+            .unwrap()
+            .len(),
+        3
+    );
+
+    // a seed list signed with a different key
+    let impostor = NodeIdentity::generate();
+    assert!(parse_seed_list(document.as_bytes(), &impostor.public_key()).is_err());
+
+    // a tampered seed list
+    let tampered = document.replace("4002", "6666");
+    assert!(parse_seed_list(tampered.as_bytes(), &publisher.public_key()).is_err());
+}
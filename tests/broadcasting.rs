@@ -1,13 +1,14 @@
+use bytes::Bytes;
 use tokio::time::sleep;
 use tracing::*;
 
 mod common;
 use pea2pea::{
     protocols::{Reading, Writing},
-    Node, NodeConfig, Pea2Pea,
+    ConnectionContext, Node, NodeConfig, Pea2Pea, SeenCache,
 };
 
-use std::{io, net::SocketAddr, time::Duration};
+use std::{io, time::Duration};
 
 #[derive(Clone)]
 struct ChattyNode(Node);
@@ -19,9 +20,14 @@ impl Pea2Pea for ChattyNode {
 }
 
 impl Writing for ChattyNode {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
-        buffer[2..][..payload.len()].copy_from_slice(&payload);
+        buffer[2..][..payload.len()].copy_from_slice(payload);
         Ok(2 + payload.len())
     }
 }
@@ -75,7 +81,7 @@ async fn broadcast_example() {
     for rando in &random_nodes {
         broadcaster
             .0
-            .connect(rando.node().listening_addr())
+            .connect(rando.node().listening_addr().unwrap())
             .await
             .unwrap();
     }
@@ -87,3 +93,372 @@ async fn broadcast_example() {
             .all(|rando| rando.node().stats().received().0 != 0)
     );
 }
+
+#[tokio::test]
+async fn tagged_broadcast() {
+    let random_nodes = common::start_nodes(3, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for rando in &random_nodes {
+        rando.enable_reading();
+    }
+
+    let broadcaster = ChattyNode(Node::new(None).await.unwrap());
+    broadcaster.enable_writing();
+
+    let mut addrs = Vec::new();
+    for rando in &random_nodes {
+        let addr = rando.node().listening_addr().unwrap();
+        broadcaster.node().connect(addr).await.unwrap();
+        addrs.push(addr);
+    }
+
+    // only the first two peers are validators
+    for addr in &addrs[..2] {
+        assert!(broadcaster.node().tag_peer(*addr, "validator").unwrap());
+    }
+    assert!(!broadcaster.node().tag_peer(addrs[0], "validator").unwrap());
+    assert!(broadcaster.node().tag_peer(addrs[0], "archive").unwrap());
+    assert_eq!(
+        broadcaster.node().peer_tags(addrs[0]),
+        vec!["archive".to_owned(), "validator".to_owned()]
+    );
+    assert_eq!(
+        broadcaster.node().connection_info(addrs[1]).unwrap().tags,
+        vec!["validator".to_owned()]
+    );
+    assert_eq!(broadcaster.node().diagnostics_dump().peer_tags.len(), 2);
+
+    let message = common::prefix_with_len(2, b"validators only");
+    broadcaster
+        .node()
+        .send_tagged_broadcast("validator", message)
+        .await
+        .unwrap();
+
+    wait_until!(
+        1,
+        random_nodes[..2]
+            .iter()
+            .all(|rando| rando.node().stats().received().0 == 1)
+    );
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(random_nodes[2].node().stats().received().0, 0);
+
+    assert!(broadcaster.node().untag_peer(addrs[1], "validator"));
+    assert!(broadcaster.node().peer_tags(addrs[1]).is_empty());
+}
+
+#[tokio::test]
+async fn group_broadcast() {
+    let random_nodes = common::start_nodes(3, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for rando in &random_nodes {
+        rando.enable_reading();
+    }
+
+    let config = NodeConfig {
+        group_limits: [("validators".to_owned(), 2)].iter().cloned().collect(),
+        ..Default::default()
+    };
+    let broadcaster = ChattyNode(Node::new(Some(config)).await.unwrap());
+    broadcaster.enable_writing();
+
+    let mut addrs = Vec::new();
+    for rando in &random_nodes {
+        let addr = rando.node().listening_addr().unwrap();
+        broadcaster.node().connect(addr).await.unwrap();
+        addrs.push(addr);
+    }
+
+    // the group is limited to two connections
+    for addr in &addrs[..2] {
+        assert!(broadcaster
+            .node()
+            .add_to_group(*addr, "validators")
+            .unwrap());
+    }
+    assert!(!broadcaster
+        .node()
+        .add_to_group(addrs[0], "validators")
+        .unwrap());
+    let err = broadcaster
+        .node()
+        .add_to_group(addrs[2], "validators")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    let err = broadcaster
+        .node()
+        .tag_peer(addrs[2], "validators")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(broadcaster
+        .node()
+        .add_to_group(addrs[2], "archives")
+        .unwrap());
+    assert_eq!(broadcaster.node().group_count("validators"), 2);
+    assert_eq!(
+        broadcaster.node().connection_info(addrs[2]).unwrap().tags,
+        vec!["archives".to_owned()]
+    );
+
+    let message = common::prefix_with_len(2, b"validators only");
+    broadcaster
+        .node()
+        .send_group_broadcast("validators", message)
+        .await
+        .unwrap();
+
+    wait_until!(
+        1,
+        random_nodes[..2]
+            .iter()
+            .all(|rando| rando.node().stats().received().0 == 1)
+    );
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(random_nodes[2].node().stats().received().0, 0);
+
+    // the groups only contain the connected peers
+    assert!(broadcaster.node().disconnect(addrs[1]));
+    assert_eq!(
+        broadcaster.node().group_members("validators"),
+        vec![addrs[0]]
+    );
+    let err = broadcaster
+        .node()
+        .add_to_group(addrs[1], "validators")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+    // but the membership is retained, so the group limits also apply to new connections
+    assert!(broadcaster.node().tag_peer(addrs[2], "validators").unwrap());
+    let err = broadcaster.node().connect(addrs[1]).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(!broadcaster.node().is_connected(addrs[1]));
+
+    assert!(broadcaster
+        .node()
+        .remove_from_group(addrs[0], "validators")
+        .unwrap());
+    broadcaster.node().connect(addrs[1]).await.unwrap();
+    let mut validators = broadcaster.node().group_members("validators");
+    validators.sort_unstable();
+    let mut expected = vec![addrs[1], addrs[2]];
+    expected.sort_unstable();
+    assert_eq!(validators, expected);
+}
+
+#[tokio::test]
+async fn filtered_broadcast() {
+    let random_nodes = common::start_nodes(3, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for rando in &random_nodes {
+        rando.enable_reading();
+    }
+
+    let broadcaster = ChattyNode(Node::new(None).await.unwrap());
+    broadcaster.enable_writing();
+
+    let mut addrs = Vec::new();
+    for rando in &random_nodes {
+        let addr = rando.node().listening_addr().unwrap();
+        broadcaster.node().connect(addr).await.unwrap();
+        addrs.push(addr);
+    }
+
+    // skip the last peer, and send each of the others a payload of a different length
+    let num_recipients = broadcaster
+        .node()
+        .send_broadcast_filtered(|addr, info| {
+            assert_eq!(info.addr, addr);
+            let idx = addrs.iter().position(|a| *a == addr).unwrap();
+            (idx != 2).then(|| common::prefix_with_len(2, &vec![0; idx + 1]))
+        })
+        .await
+        .unwrap();
+    assert_eq!(num_recipients, 2);
+
+    wait_until!(
+        1,
+        random_nodes[..2]
+            .iter()
+            .all(|rando| rando.node().stats().received().0 == 1)
+    );
+    let bytes_received = random_nodes[..2]
+        .iter()
+        .map(|rando| rando.node().stats().received().1)
+        .collect::<Vec<_>>();
+    assert_eq!(bytes_received[1], bytes_received[0] + 1);
+
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(random_nodes[2].node().stats().received().0, 0);
+
+    // the per-peer messages aren't remembered as broadcast ones
+    let seen = broadcaster.node().seen_messages();
+    assert!(seen.insert(seen.hash(&common::prefix_with_len(2, &[0]))));
+}
+
+#[tokio::test]
+async fn relay_suppresses_echoes() {
+    let random_nodes = common::start_nodes(3, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for rando in &random_nodes {
+        rando.enable_reading();
+    }
+
+    let relayer = ChattyNode(Node::new(None).await.unwrap());
+    relayer.enable_writing();
+
+    let mut addrs = Vec::new();
+    for rando in &random_nodes {
+        let addr = rando.node().listening_addr().unwrap();
+        relayer.node().connect(addr).await.unwrap();
+        addrs.push(addr);
+    }
+
+    // a message is relayed to everyone but its source, and only once
+    let message = common::prefix_with_len(2, b"pass it on");
+    assert_eq!(
+        relayer
+            .node()
+            .relay(addrs[0], message.clone())
+            .await
+            .unwrap(),
+        Some(2)
+    );
+    assert_eq!(relayer.node().relay(addrs[1], message).await.unwrap(), None);
+
+    wait_until!(
+        1,
+        random_nodes[1..]
+            .iter()
+            .all(|rando| rando.node().stats().received().0 == 1)
+    );
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(random_nodes[0].node().stats().received().0, 0);
+
+    // the node's own broadcasts aren't relayed when echoed back
+    let message = common::prefix_with_len(2, b"my own words");
+    relayer
+        .node()
+        .send_broadcast(message.clone())
+        .await
+        .unwrap();
+    assert_eq!(relayer.node().relay(addrs[2], message).await.unwrap(), None);
+    assert_eq!(relayer.node().seen_messages().len(), 2);
+}
+
+#[tokio::test]
+async fn seen_cache_eviction_and_expiry() {
+    let cache = SeenCache::new(2, Duration::from_millis(100));
+
+    assert!(cache.insert(1));
+    assert!(cache.insert(2));
+    assert!(!cache.insert(1));
+
+    // 2 is the least recently seen hash, so it's evicted first
+    assert!(cache.insert(3));
+    assert!(cache.contains(1));
+    assert!(!cache.contains(2));
+    assert!(cache.contains(3));
+
+    sleep(Duration::from_millis(150)).await;
+    assert!(!cache.contains(1));
+    assert!(cache.insert(1));
+    assert_eq!(cache.len(), 1);
+}
+
+#[tokio::test]
+async fn scheduled_direct_messages() {
+    let sender = common::MessagingNode::new("sender").await;
+    sender.enable_writing();
+    let receiver = common::MessagingNode::new("receiver").await;
+    receiver.enable_reading();
+    let receiver_addr = receiver.node().listening_addr().unwrap();
+    sender.node().connect(receiver_addr).await.unwrap();
+
+    let delayed = sender.node().send_direct_message_after(
+        receiver_addr,
+        Bytes::from_static(b"later"),
+        Duration::from_millis(50),
+    );
+    let cancelled = sender.node().send_direct_message_after(
+        receiver_addr,
+        Bytes::from_static(b"never"),
+        Duration::from_millis(50),
+    );
+    assert!(delayed.is_scheduled());
+    assert!(cancelled.cancel());
+    assert!(!cancelled.is_scheduled());
+
+    // the message isn't sent right away
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(receiver.node().stats().received().0, 0);
+
+    wait_until!(1, receiver.node().stats().received().0 == 1);
+    assert!(!delayed.is_scheduled());
+    assert!(!delayed.cancel());
+
+    // the cancelled message is never sent
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(receiver.node().stats().received().0, 1);
+}
+
+#[tokio::test]
+async fn periodic_broadcasts() {
+    let broadcaster = common::MessagingNode::new("broadcaster").await;
+    broadcaster.enable_writing();
+    let receivers = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for receiver in &receivers {
+        receiver.enable_reading();
+        broadcaster
+            .node()
+            .connect(receiver.node().listening_addr().unwrap())
+            .await
+            .unwrap();
+    }
+
+    // a zero interval is rejected
+    assert!(broadcaster
+        .node()
+        .send_broadcast_every(|| Bytes::from_static(b"x"), Duration::ZERO)
+        .is_err());
+
+    // the messages are produced anew for every broadcast
+    let mut counter = 0u8;
+    let beacon = broadcaster
+        .node()
+        .send_broadcast_every(
+            move || {
+                counter += 1;
+                Bytes::from(vec![counter])
+            },
+            Duration::from_millis(20),
+        )
+        .unwrap();
+
+    for receiver in &receivers {
+        wait_until!(1, receiver.node().stats().received().0 >= 3);
+    }
+
+    assert!(beacon.cancel());
+    sleep(Duration::from_millis(50)).await;
+    let num_received = receivers[0].node().stats().received().0;
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(receivers[0].node().stats().received().0, num_received);
+}
@@ -5,7 +5,7 @@ use tracing::*;
 mod common;
 use pea2pea::{
     protocols::{Handshaking, Reading, Writing},
-    Connection, Node, NodeConfig, Pea2Pea,
+    Connection, ConnectionContext, Node, NodeConfig, Pea2Pea,
 };
 
 use std::{io, net::SocketAddr};
@@ -33,7 +33,7 @@ impl Reading for TestNode {
 
     fn read_message(
         &self,
-        _source: SocketAddr,
+        _ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         let bytes = common::read_len_prefixed_message(2, buffer)?;
@@ -63,9 +63,14 @@ impl Reading for TestNode {
 }
 
 impl Writing for TestNode {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
-        buffer[2..][..payload.len()].copy_from_slice(&payload);
+        buffer[2..][..payload.len()].copy_from_slice(payload);
         Ok(2 + payload.len())
     }
 }
@@ -83,7 +88,7 @@ async fn check_node_cleanups() {
         ..Default::default()
     };
     let drebin = TestNode(Node::new(Some(config)).await.unwrap());
-    let drebin_addr = drebin.node().listening_addr();
+    let drebin_addr = drebin.node().listening_addr().unwrap();
 
     drebin.enable_handshaking();
     drebin.enable_reading();
@@ -114,6 +119,14 @@ async fn check_node_cleanups() {
 
         wait_until!(1, hapsburgs_thug.node().stats().sent().0 == 2);
 
+        // with test-utils, the nodes with the Reading protocol also hold an injector (see `Node::inject_inbound`)
+        #[cfg(feature = "test-utils")]
+        drebin
+            .node()
+            .inject_inbound(thug_addr, common::prefix_with_len(2, b"..."))
+            .await
+            .unwrap();
+
         // the thug dies before revealing the location of Hapsburg's Plan B
         hapsburgs_thug.node().shut_down();
 
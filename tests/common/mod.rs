@@ -5,7 +5,7 @@ use tracing::*;
 
 use pea2pea::{
     protocols::{Reading, Writing},
-    Node, NodeConfig, Pea2Pea,
+    ConnectionContext, Node, NodeConfig, Pea2Pea,
 };
 
 use std::{convert::TryInto, io, net::SocketAddr};
@@ -65,6 +65,14 @@ impl Pea2Pea for MessagingNode {
     }
 }
 
+impl std::ops::Deref for MessagingNode {
+    type Target = Node;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 pub fn read_len_prefixed_message(len_size: usize, buffer: &[u8]) -> io::Result<Option<&[u8]>> {
     if buffer.len() >= len_size {
         let payload_len = match len_size {
@@ -108,8 +116,8 @@ macro_rules! impl_messaging {
         impl Reading for $target {
             type Message = Bytes;
 
-            fn read_message(&self, _source: SocketAddr, buffer: &[u8]) -> io::Result<Option<(Self::Message, usize)>> {
-                let bytes = crate::common::read_len_prefixed_message(2, buffer)?;
+            fn read_message(&self, _ctx: &ConnectionContext, buffer: &[u8]) -> io::Result<Option<(Self::Message, usize)>> {
+                let bytes = $crate::common::read_len_prefixed_message(2, buffer)?;
 
                 Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
             }
@@ -122,7 +130,7 @@ macro_rules! impl_messaging {
         }
 
         impl Writing for $target {
-            fn write_message(&self, _target: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+            fn write_message(&self, _ctx: &ConnectionContext, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
                 buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
                 buffer[2..][..payload.len()].copy_from_slice(&payload);
                 Ok(2 + payload.len())
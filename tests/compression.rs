@@ -0,0 +1,84 @@
+#![cfg(feature = "compression")]
+
+mod common;
+use pea2pea::{
+    compression::{CompressionConfig, CompressionDictionary},
+    Node, NodeConfig,
+};
+
+const TX_CLASS: u8 = 1;
+
+// produces similarly-structured "transactions", akin to the ones a dictionary would be trained on
+fn sample_tx(i: usize) -> Vec<u8> {
+    format!(
+        "{{\"version\":2,\"inputs\":[{{\"prev_out\":\"{:064x}\",\"index\":{},\"sequence\":4294967295}}],\"outputs\":[{{\"value\":{},\"script\":\"76a914{:040x}88ac\"}}],\"lock_time\":0}}",
+        i * 7919,
+        i % 4,
+        i * 1000,
+        i * 31
+    )
+    .into_bytes()
+}
+
+async fn compressing_node(dictionaries: Vec<CompressionDictionary>) -> Node {
+    let config = NodeConfig {
+        compression: Some(CompressionConfig {
+            dictionaries,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    Node::new(Some(config)).await.unwrap()
+}
+
+#[tokio::test]
+async fn dictionary_compression() {
+    let samples = (0..1000).map(sample_tx).collect::<Vec<_>>();
+    let dictionary = CompressionDictionary::train(1, TX_CLASS, &samples, 4096).unwrap();
+    assert!(CompressionDictionary::new(0, TX_CLASS, Default::default()).is_err());
+
+    let alice = compressing_node(vec![dictionary.clone()]).await;
+    let bob = compressing_node(vec![dictionary]).await;
+    let carol = compressing_node(vec![]).await;
+
+    let bob_addr = bob.listening_addr().unwrap();
+    let carol_addr = carol.listening_addr().unwrap();
+    alice.connect(bob_addr).await.unwrap();
+    alice.connect(carol_addr).await.unwrap();
+
+    wait_until!(1, bob.num_connected() == 1 && carol.num_connected() == 1);
+    let alice_addr = bob.connected_addrs()[0];
+
+    assert_eq!(alice.shared_dictionaries(bob_addr), Some(vec![1]));
+    assert_eq!(bob.shared_dictionaries(alice_addr), Some(vec![1]));
+    assert_eq!(alice.shared_dictionaries(carol_addr), Some(vec![]));
+
+    let tx = sample_tx(1234);
+
+    // bob has the dictionary, so the compressed message is much smaller
+    let with_dict = alice.compress(bob_addr, TX_CLASS, &tx).unwrap();
+    assert_eq!(
+        &bob.decompress(alice_addr, &with_dict).unwrap()[..],
+        &tx[..]
+    );
+
+    // carol doesn't, so the message is compressed without it
+    let without_dict = alice.compress(carol_addr, TX_CLASS, &tx).unwrap();
+    assert_eq!(&without_dict[..4], &0u32.to_le_bytes());
+    let alice_addr_at_carol = carol.connected_addrs()[0];
+    assert_eq!(
+        &carol
+            .decompress(alice_addr_at_carol, &without_dict)
+            .unwrap()[..],
+        &tx[..]
+    );
+    assert!(with_dict.len() < without_dict.len());
+
+    // and she rejects the payloads using it
+    assert!(carol.decompress(alice_addr_at_carol, &with_dict).is_err());
+
+    // messages of other classes are compressed without a dictionary
+    let other = alice.compress(bob_addr, TX_CLASS + 1, &tx).unwrap();
+    assert_eq!(&other[..4], &0u32.to_le_bytes());
+}
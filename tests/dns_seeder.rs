@@ -0,0 +1,100 @@
+#![cfg(feature = "dns-seeder")]
+
+use tokio::net::UdpSocket;
+
+use pea2pea::{dns_seeder::DnsSeeder, Node};
+
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, SocketAddr},
+};
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+fn build_query(id: u16, domain: &str, record_type: u16) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    // RD set, a single question
+    query.extend_from_slice(&[0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in domain.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+
+    query
+}
+
+// returns the response code and the served IPs
+async fn resolve(seeder_addr: SocketAddr, domain: &str, record_type: u16) -> (u8, Vec<IpAddr>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let query = build_query(0x1234, domain, record_type);
+    socket.send_to(&query, seeder_addr).await.unwrap();
+
+    let mut response = [0u8; 512];
+    let len = socket.recv(&mut response).await.unwrap();
+    let response = &response[..len];
+
+    assert_eq!(response[..2], [0x12, 0x34]);
+    assert_eq!(response[2] & 0x84, 0x84); // QR and AA
+    let rcode = response[3] & 0x0f;
+    let num_answers = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut ips = Vec::new();
+    let mut idx = query.len();
+    for _ in 0..num_answers {
+        // skip the name pointer, the type, the class and the TTL
+        idx += 10;
+        let rdata_len = u16::from_be_bytes([response[idx], response[idx + 1]]) as usize;
+        idx += 2;
+        let rdata = &response[idx..idx + rdata_len];
+        ips.push(match rdata_len {
+            4 => IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap()),
+            16 => IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap()),
+            _ => panic!("invalid record data"),
+        });
+        idx += rdata_len;
+    }
+
+    (rcode, ips)
+}
+
+#[tokio::test]
+async fn dns_seeder_serves_verified_peers() {
+    let node = Node::new(None).await.unwrap();
+
+    let verified = ["127.0.0.1:4000", "[::1]:4000", "127.0.0.2:5000"];
+    for addr in &verified {
+        let addr = addr.parse().unwrap();
+        node.known_peers().add(addr);
+        node.known_peers().register_connection(addr);
+    }
+    // a peer that was only seen, but never connected to
+    node.known_peers()
+        .register_seen("127.0.0.3:4000".parse().unwrap());
+
+    let seeder = DnsSeeder {
+        domain: "seed.pea2pea.test".into(),
+        ttl: 60,
+        max_records: 16,
+        port: Some(4000),
+    };
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let seeder_addr = socket.local_addr().unwrap();
+    let _seeder_task = seeder.spawn(node.clone(), socket);
+
+    let (rcode, ips) = resolve(seeder_addr, "seed.pea2pea.test", TYPE_A).await;
+    assert_eq!(rcode, 0);
+    assert_eq!(ips, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+
+    let (rcode, ips) = resolve(seeder_addr, "SEED.pea2pea.test", TYPE_AAAA).await;
+    assert_eq!(rcode, 0);
+    assert_eq!(ips, vec!["::1".parse::<IpAddr>().unwrap()]);
+
+    // the seeder is only authoritative for its own domain
+    let (rcode, ips) = resolve(seeder_addr, "example.com", TYPE_A).await;
+    assert_eq!(rcode, 5);
+    assert!(ips.is_empty());
+}
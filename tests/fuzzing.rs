@@ -3,10 +3,10 @@ use rand::{distributions::Standard, rngs::SmallRng, Rng, SeedableRng};
 mod common;
 use pea2pea::{
     protocols::{Reading, Writing},
-    Node, NodeConfig, Pea2Pea,
+    ConnectionContext, Node, NodeConfig, Pea2Pea,
 };
 
-use std::{io, net::SocketAddr};
+use std::io;
 
 #[derive(Clone)]
 struct Tester(Node);
@@ -22,7 +22,7 @@ impl Reading for Tester {
 
     fn read_message(
         &self,
-        _source: SocketAddr,
+        _ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         let bytes = common::read_len_prefixed_message(4, buffer)?;
@@ -34,12 +34,12 @@ impl Reading for Tester {
 impl Writing for Tester {
     fn write_message(
         &self,
-        _target: SocketAddr,
+        _ctx: &ConnectionContext,
         payload: &[u8],
         buffer: &mut [u8],
     ) -> io::Result<usize> {
         buffer[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
-        buffer[4..][..payload.len()].copy_from_slice(&payload);
+        buffer[4..][..payload.len()].copy_from_slice(payload);
         Ok(4 + payload.len())
     }
 }
@@ -65,7 +65,7 @@ async fn fuzzing() {
 
     sender
         .node()
-        .connect(tester.node().listening_addr())
+        .connect(tester.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -78,7 +78,10 @@ async fn fuzzing() {
         let random_payload: Vec<u8> = (&mut rng).sample_iter(Standard).take(random_len).collect();
         sender
             .node()
-            .send_direct_message(tester.node().listening_addr(), random_payload.into())
+            .send_direct_message(
+                tester.node().listening_addr().unwrap(),
+                random_payload.into(),
+            )
             .await
             .unwrap();
 
@@ -0,0 +1,94 @@
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+mod common;
+use pea2pea::{
+    protocols::{Reading, Writing},
+    ConnectionContext, MessageHandler, Node, Pea2Pea,
+};
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+#[derive(Clone)]
+struct DispatchingNode(Node);
+
+impl Pea2Pea for DispatchingNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for DispatchingNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        self.node().dispatch_message(source, message).await
+    }
+}
+
+#[derive(Default)]
+struct RecordingHandler(Mutex<Vec<Bytes>>);
+
+#[async_trait::async_trait]
+impl MessageHandler for RecordingHandler {
+    async fn handle(&self, _source: SocketAddr, message: Bytes) -> io::Result<()> {
+        self.0.lock().push(message);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn handler_registry() {
+    let sender = common::MessagingNode::new("sender").await;
+    sender.enable_writing();
+    let receiver = DispatchingNode(Node::new(None).await.unwrap());
+    receiver.enable_reading();
+
+    let pings = Arc::new(RecordingHandler::default());
+    let blocks = Arc::new(RecordingHandler::default());
+    receiver.node().register_handler(0, pings.clone()).unwrap();
+    receiver
+        .node()
+        .register_handler_range(10..=19, blocks.clone())
+        .unwrap();
+    // the message types can't be handled by multiple handlers
+    assert!(receiver
+        .node()
+        .register_handler(15, Arc::new(RecordingHandler::default()))
+        .is_err());
+
+    let receiver_addr = receiver.node().listening_addr().unwrap();
+    sender.node().connect(receiver_addr).await.unwrap();
+    wait_until!(1, receiver.node().num_connected() == 1);
+
+    for message in [&[0u8, 1][..], &[12, 2], &[19, 3], &[20, 4], &[0, 5]] {
+        sender
+            .node()
+            .send_direct_message(receiver_addr, Bytes::copy_from_slice(message))
+            .await
+            .unwrap();
+    }
+
+    wait_until!(1, pings.0.lock().len() == 2 && blocks.0.lock().len() == 2);
+    assert_eq!(pings.0.lock()[1][..], [0, 5]);
+    assert_eq!(blocks.0.lock()[..], [&[12, 2][..], &[19, 3][..]]);
+
+    // the whole range is unregistered at once
+    assert!(receiver.node().unregister_handler(19));
+    assert!(!receiver.node().unregister_handler(10));
+    receiver
+        .node()
+        .register_handler(15, Arc::new(RecordingHandler::default()))
+        .unwrap();
+}
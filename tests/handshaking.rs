@@ -1,15 +1,26 @@
 use bytes::Bytes;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
 use tracing::*;
 
 mod common;
 use pea2pea::{
-    protocols::{Handshaking, Reading, Writing},
-    Connection, ConnectionSide, Node, NodeConfig, Pea2Pea,
+    protocols::{Handshaking, Reading, Rehandshaking, Rejection, RejectionCode, Writing},
+    ConnectOptions, Connection, ConnectionContext, ConnectionIntent, ConnectionSide, DialFailure,
+    HandshakeMetadata, Node, NodeConfig, NodeEvent, Pea2Pea, RetryPolicy, SubnetLimits,
 };
 
 use parking_lot::RwLock;
-use std::{collections::HashMap, convert::TryInto, io, net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug)]
 enum HandshakeMsg {
@@ -161,7 +172,7 @@ async fn handshake_example() {
 
     initiator
         .node()
-        .connect(responder.node().listening_addr())
+        .connect(responder.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -172,6 +183,81 @@ async fn handshake_example() {
     );
 }
 
+#[tokio::test]
+async fn connection_extensions() {
+    #[derive(Clone)]
+    struct Wrap(Node);
+
+    impl Pea2Pea for Wrap {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct PeerNonce(u64);
+
+    // a handshake exchanging nonces and attaching the peer's one to the connection
+    #[async_trait::async_trait]
+    impl Handshaking for Wrap {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            let own_nonce = conn.id as u64;
+            conn.writer().write_all(&own_nonce.to_le_bytes()).await?;
+            let mut peer_nonce = [0u8; 8];
+            conn.reader().read_exact(&mut peer_nonce).await?;
+
+            conn.insert_ext(PeerNonce(u64::from_le_bytes(peer_nonce)));
+            assert!(conn.ext::<PeerNonce>().is_some());
+            assert!(conn.ext::<u8>().is_none());
+
+            Ok(conn)
+        }
+    }
+
+    let nodes = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(Wrap)
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_handshaking();
+    }
+    let (alice, bob) = (&nodes[0], &nodes[1]);
+    let bob_addr = bob.node().listening_addr().unwrap();
+
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    let alice_conn_id = alice.node().connection_info(bob_addr).unwrap().id as u64;
+    let bob_conn_id = bob.node().connection_info(alice_addr).unwrap().id as u64;
+    assert_eq!(
+        alice.node().connection_ext::<PeerNonce>(bob_addr),
+        Some(PeerNonce(bob_conn_id))
+    );
+    assert_eq!(
+        bob.node().connection_ext::<PeerNonce>(alice_addr),
+        Some(PeerNonce(alice_conn_id))
+    );
+
+    // extensions can also be attached to live connections, replacing the previous value of the same type
+    assert_eq!(
+        alice
+            .node()
+            .insert_connection_ext(bob_addr, PeerNonce(42))
+            .unwrap(),
+        Some(PeerNonce(bob_conn_id))
+    );
+    assert_eq!(
+        alice.node().connection_ext::<PeerNonce>(bob_addr),
+        Some(PeerNonce(42))
+    );
+
+    // they don't outlive the connection
+    alice.node().disconnect(bob_addr);
+    assert!(alice.node().connection_ext::<PeerNonce>(bob_addr).is_none());
+}
+
 #[tokio::test]
 async fn no_handshake_no_messaging() {
     let initiator_config = NodeConfig {
@@ -202,7 +288,7 @@ async fn no_handshake_no_messaging() {
 
     initiator
         .node()
-        .connect(responder.node().listening_addr())
+        .connect(responder.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -210,9 +296,787 @@ async fn no_handshake_no_messaging() {
 
     initiator
         .node()
-        .send_direct_message(responder.node().listening_addr(), message)
+        .send_direct_message(responder.node().listening_addr().unwrap(), message)
         .await
         .unwrap();
 
     wait_until!(1, responder.node().num_connected() == 0);
 }
+
+// the whole 127.0.0.0/8 block is only bound to the loopback interface by default on Linux
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn pending_handshakes_are_fair_across_ips() {
+    use std::net::IpAddr;
+    use tokio::{net::TcpSocket, time::sleep};
+
+    #[derive(Clone)]
+    struct Wrap(Node, Arc<RwLock<Vec<IpAddr>>>);
+
+    impl Pea2Pea for Wrap {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // a handshake that concludes once a single byte is received from the peer
+    #[async_trait::async_trait]
+    impl Handshaking for Wrap {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            conn.reader().read_exact(&mut [0u8; 1]).await?;
+            self.1.write().push(conn.addr.ip());
+
+            Ok(conn)
+        }
+    }
+
+    let config = NodeConfig {
+        max_concurrent_handshakes: 1,
+        ..Default::default()
+    };
+    let node = Wrap(Node::new(Some(config)).await.unwrap(), Default::default());
+    node.enable_handshaking();
+
+    let node_addr: SocketAddr =
+        ([127, 0, 0, 1], node.node().listening_addr().unwrap().port()).into();
+    let ip_a: IpAddr = [127, 0, 0, 1].into();
+    let ip_b: IpAddr = [127, 0, 0, 2].into();
+
+    // the first connection occupies the only handshake slot, while the others queue up behind it
+    let mut streams = Vec::new();
+    for ip in &[ip_a, ip_a, ip_a, ip_a, ip_b] {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind((*ip, 0).into()).unwrap();
+        streams.push(socket.connect(node_addr).await.unwrap());
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    for stream in streams.iter_mut().skip(1) {
+        stream.write_all(&[0]).await.unwrap();
+    }
+    streams[0].write_all(&[0]).await.unwrap();
+
+    wait_until!(1, node.1.read().len() == 5);
+    assert_eq!(*node.1.read(), vec![ip_a, ip_a, ip_b, ip_a, ip_a]);
+}
+
+#[tokio::test]
+async fn version_negotiation() {
+    let config = |version, range| NodeConfig {
+        protocol_version: version,
+        capabilities: 0b101,
+        supported_version_range: Some(range),
+        ..Default::default()
+    };
+
+    let old = Node::new(Some(config(1, 1..=1))).await.unwrap();
+    let new = Node::new(Some(config(2, 1..=2))).await.unwrap();
+    let newest = Node::new(Some(config(3, 3..=3))).await.unwrap();
+
+    // the versions are compatible
+    old.connect(new.listening_addr().unwrap()).await.unwrap();
+    wait_until!(1, new.num_connected() == 1);
+
+    let caps = old
+        .peer_capabilities(new.listening_addr().unwrap())
+        .unwrap();
+    assert_eq!(caps.version, 2);
+    assert!(caps.supports(0b100));
+    assert!(!caps.supports(0b010));
+
+    // the versions are incompatible
+    assert!(new.connect(newest.listening_addr().unwrap()).await.is_err());
+    wait_until!(1, newest.num_connected() == 0);
+    assert!(new
+        .peer_capabilities(newest.listening_addr().unwrap())
+        .is_none());
+}
+
+#[tokio::test]
+async fn negotiation_reveals_the_listening_addr() {
+    let config = NodeConfig {
+        supported_version_range: Some(0..=0),
+        ..Default::default()
+    };
+    let writer = common::MessagingNode(Node::new(Some(config.clone())).await.unwrap());
+    writer.enable_writing();
+    let reader = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+    let writer_port = writer.node().listening_addr().unwrap().port();
+
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+    let ephemeral_addr = reader.node().connected_addrs()[0];
+    let listening_addr = SocketAddr::new(ephemeral_addr.ip(), writer_port);
+
+    // the activity of the inbound connection counts towards the writer's listening address
+    let known_peers = reader.node().known_peers();
+    assert_eq!(known_peers.canonical_addr(ephemeral_addr), listening_addr);
+    writer
+        .node()
+        .send_direct_message(reader_addr, Bytes::from_static(b"herp"))
+        .await
+        .unwrap();
+    wait_until!(
+        1,
+        known_peers
+            .stats(listening_addr)
+            .map(|stats| stats.msgs_received == 1)
+            .unwrap_or(false)
+    );
+    assert!(known_peers.contains(ephemeral_addr));
+    assert!(!known_peers.read().contains_key(&ephemeral_addr));
+
+    // the alias is removed along with the connection, while the peer remains known
+    writer.node().disconnect(reader_addr);
+    wait_until!(1, reader.node().num_connected() == 0);
+    assert_eq!(known_peers.canonical_addr(ephemeral_addr), ephemeral_addr);
+    assert!(known_peers.contains(listening_addr));
+}
+
+#[tokio::test]
+async fn frame_size_negotiation() {
+    let config = |read_buffer_size| NodeConfig {
+        conn_read_buffer_size: read_buffer_size,
+        conn_inbound_queue_depth: 8,
+        supported_version_range: Some(0..=0),
+        ..Default::default()
+    };
+    let writer = common::MessagingNode(Node::new(Some(config(1024))).await.unwrap());
+    writer.enable_writing();
+    let reader = common::MessagingNode(Node::new(Some(config(32))).await.unwrap());
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+
+    let caps = writer.node().peer_capabilities(reader_addr).unwrap();
+    assert_eq!(caps.max_frame_size, 32);
+    assert_eq!(caps.inbound_queue_depth, 8);
+    // the outbound queue is no deeper than the peer's inbound one
+    let info = writer.node().connection_info(reader_addr).unwrap();
+    assert_eq!(info.outbound_queue_capacity, 8);
+
+    // a message that is too large for the peer is rejected before it's sent
+    let delivery = writer
+        .node()
+        .send_direct_message_with_delivery(reader_addr, vec![0u8; 64].into())
+        .await
+        .unwrap();
+    assert_eq!(
+        delivery.await.unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    // the connection is unaffected
+    writer
+        .node()
+        .send_direct_message(reader_addr, Bytes::from_static(b"small"))
+        .await
+        .unwrap();
+    wait_until!(1, reader.node().stats().received().0 == 1);
+    assert!(writer.node().is_connected(reader_addr));
+}
+
+#[tokio::test]
+async fn external_addr_voting() {
+    let config = |listener_ip: &str| NodeConfig {
+        listener_ip: listener_ip.parse().unwrap(),
+        exchange_observed_addrs: true,
+        min_external_addr_votes: 2,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config("127.0.0.1"))).await.unwrap();
+    let port = node.listening_addr().unwrap().port();
+
+    // the voters have distinct IPs, as each IP only has a single vote
+    let mut voters = Vec::new();
+    for ip in &["127.0.0.2", "127.0.0.3"] {
+        voters.push(Node::new(Some(config(ip))).await.unwrap());
+    }
+
+    node.connect(voters[0].listening_addr().unwrap())
+        .await
+        .unwrap();
+    assert!(node.external_addr().is_none());
+
+    node.connect(voters[1].listening_addr().unwrap())
+        .await
+        .unwrap();
+    let external_addr = node.external_addr().unwrap();
+    assert!(external_addr.ip().is_loopback());
+    assert_eq!(external_addr.port(), port);
+}
+
+// a node whose messages are tagged, so that rehandshake messages can be told apart from the regular ones; its
+// rehandshakes also exchange session keys
+#[derive(Clone)]
+struct UpgradableNode {
+    node: Node,
+    key: Arc<RwLock<u8>>,
+    peer_keys: Arc<RwLock<HashMap<SocketAddr, u8>>>,
+}
+
+impl Pea2Pea for UpgradableNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Handshaking for UpgradableNode {
+    async fn perform_handshake(&self, conn: Connection) -> io::Result<Connection> {
+        Ok(conn)
+    }
+
+    fn rehandshake_payload(&self, _addr: SocketAddr) -> io::Result<Bytes> {
+        Ok(Bytes::copy_from_slice(&[*self.key.read()]))
+    }
+
+    async fn process_rehandshake_payload(
+        &self,
+        source: SocketAddr,
+        payload: Bytes,
+    ) -> io::Result<()> {
+        if payload.len() != 1 {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        self.peer_keys.write().insert(source, payload[0]);
+
+        Ok(())
+    }
+}
+
+const REHANDSHAKE_TAG: u8 = 1;
+
+#[async_trait::async_trait]
+impl Reading for UpgradableNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        if message[0] == REHANDSHAKE_TAG {
+            self.node().handle_rehandshake(source, &message[1..]).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Writing for UpgradableNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl Rehandshaking for UpgradableNode {
+    fn rehandshake_message(&self, payload: Bytes) -> Bytes {
+        let mut message = vec![REHANDSHAKE_TAG];
+        message.extend_from_slice(&payload);
+        message.into()
+    }
+}
+
+#[tokio::test]
+async fn rehandshake() {
+    let config = NodeConfig {
+        protocol_version: 1,
+        supported_version_range: Some(1..=2),
+        ..Default::default()
+    };
+    let nodes = common::start_nodes(2, Some(config))
+        .await
+        .into_iter()
+        .map(|node| UpgradableNode {
+            node,
+            key: Arc::new(RwLock::new(1)),
+            peer_keys: Default::default(),
+        })
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_handshaking();
+        node.enable_reading();
+        node.enable_writing();
+        node.enable_rehandshaking();
+    }
+    let (alice, bob) = (&nodes[0], &nodes[1]);
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+    assert_eq!(alice.node().peer_capabilities(bob_addr).unwrap().version, 1);
+
+    // bob gets upgraded and informs alice about it, also refreshing its key
+    bob.node().set_advertised_version(2, 0b1);
+    *bob.key.write() = 2;
+    let alice_caps = bob.node().rehandshake(alice_addr).await.unwrap();
+    assert_eq!(alice_caps.version, 1);
+
+    let bob_caps = alice.node().peer_capabilities(bob_addr).unwrap();
+    assert_eq!(bob_caps.version, 2);
+    assert!(bob_caps.supports(0b1));
+
+    // the handshake was re-run on both sides
+    assert_eq!(alice.peer_keys.read().get(&bob_addr), Some(&2));
+    assert_eq!(bob.peer_keys.read().get(&alice_addr), Some(&1));
+
+    // an upgrade to an unsupported version breaks the connection
+    bob.node().set_advertised_version(3, 0);
+    assert!(bob.node().rehandshake(alice_addr).await.is_err());
+    wait_until!(1, alice.node().num_connected() == 0);
+}
+
+#[tokio::test]
+async fn awaiting_handshakes() {
+    let config = |version, range| NodeConfig {
+        protocol_version: version,
+        supported_version_range: Some(range),
+        ..Default::default()
+    };
+
+    let alice = Node::new(Some(config(1, 1..=2))).await.unwrap();
+    let bob = Node::new(Some(config(2, 1..=2))).await.unwrap();
+    let carol = Node::new(Some(config(3, 3..=3))).await.unwrap();
+    let bob_addr = bob.listening_addr().unwrap();
+    let carol_addr = carol.listening_addr().unwrap();
+
+    // the handshake succeeds
+    let _dial = alice.dial(bob_addr);
+    alice
+        .await_handshake(bob_addr, Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert_eq!(alice.handshaken_addrs(), vec![bob_addr]);
+
+    // an established connection is reported right away
+    alice
+        .await_handshake(bob_addr, Duration::from_millis(1))
+        .await
+        .unwrap();
+
+    // the handshake fails
+    let _dial = alice.dial(carol_addr);
+    let err = alice
+        .await_handshake(carol_addr, Duration::from_secs(1))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    assert_eq!(alice.handshaken_addrs(), vec![bob_addr]);
+
+    // there is no handshake to await
+    let err = carol
+        .await_handshake(bob_addr, Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn annotated_events() {
+    #[derive(Clone)]
+    struct Wrap(Node);
+
+    impl Pea2Pea for Wrap {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // a handshake that exchanges client versions
+    #[async_trait::async_trait]
+    impl Handshaking for Wrap {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            conn.writer().write_u8(7).await?;
+            let peer_version = conn.reader().read_u8().await?;
+
+            let mut metadata = HandshakeMetadata::default();
+            metadata
+                .0
+                .insert("client_version".into(), peer_version.to_string());
+            conn.insert_ext(metadata);
+            conn.insert_ext(ConnectionIntent("block-sync".into()));
+
+            Ok(conn)
+        }
+    }
+
+    let alice = Wrap(Node::new(None).await.unwrap());
+    let bob = Wrap(Node::new(None).await.unwrap());
+    alice.enable_handshaking();
+    bob.enable_handshaking();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().tag_peer(bob_addr, "archive").unwrap();
+    let mut events = alice.node().subscribe_annotated_events();
+
+    alice.node().connect(bob_addr).await.unwrap();
+
+    let annotated = tokio::time::timeout(Duration::from_secs(1), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let conn_id = alice.node().connection_info(bob_addr).unwrap().id;
+    assert_eq!(
+        annotated.event,
+        NodeEvent::HandshakeCompleted(bob_addr, conn_id)
+    );
+    assert_eq!(annotated.tags, vec!["archive".to_owned()]);
+    assert_eq!(annotated.intent.as_deref(), Some("block-sync"));
+    assert_eq!(
+        annotated.handshake_metadata.get("client_version"),
+        Some(&"7".to_owned())
+    );
+}
+
+#[tokio::test]
+async fn handshake_rejection_reaches_the_initiator() {
+    #[derive(Clone)]
+    struct MagicNode(Node, [u8; 4]);
+
+    impl Pea2Pea for MagicNode {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // the handshake messages are prefixed with their length
+    async fn read_frame(conn: &mut Connection) -> io::Result<Vec<u8>> {
+        let len = conn.reader().read_u8().await? as usize;
+        let mut frame = vec![0u8; len];
+        conn.reader().read_exact(&mut frame).await?;
+        Ok(frame)
+    }
+
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![message.len() as u8];
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    // the initiator sends its network magic, and the responder either echoes it or rejects the initiator
+    #[async_trait::async_trait]
+    impl Handshaking for MagicNode {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            match !conn.side {
+                ConnectionSide::Initiator => {
+                    conn.writer().write_all(&frame(&self.1)).await?;
+                    let message = read_frame(&mut conn).await?;
+                    Rejection::check(&message)?;
+                }
+                ConnectionSide::Responder => {
+                    if read_frame(&mut conn).await? != self.1 {
+                        let rejection = Rejection::new(RejectionCode::WrongNetwork, "bad magic");
+                        return Err(self.reject(&mut conn, rejection).await);
+                    }
+                    conn.writer().write_all(&frame(&self.1)).await?;
+                }
+            }
+
+            Ok(conn)
+        }
+
+        fn frame_rejection(&self, encoded: Vec<u8>) -> Vec<u8> {
+            frame(&encoded)
+        }
+    }
+
+    let responder = MagicNode(Node::new(None).await.unwrap(), *b"main");
+    let stranger = MagicNode(Node::new(None).await.unwrap(), *b"test");
+    let friend = MagicNode(Node::new(None).await.unwrap(), *b"main");
+    for node in &[&responder, &stranger, &friend] {
+        node.enable_handshaking();
+    }
+    let responder_addr = responder.node().listening_addr().unwrap();
+
+    let err = stranger.node().connect(responder_addr).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    assert_eq!(
+        Rejection::from_error(&err),
+        Some(&Rejection::new(RejectionCode::WrongNetwork, "bad magic"))
+    );
+    assert_eq!(DialFailure::categorize(&err), DialFailure::Rejected);
+
+    // the rejections aren't retried
+    let options = ConnectOptions {
+        retry_policy: Some(RetryPolicy {
+            transient_errors: vec![io::ErrorKind::ConnectionRefused],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let err = stranger
+        .node()
+        .connect_with_options(responder_addr, options)
+        .await
+        .unwrap_err();
+    assert!(Rejection::from_error(&err).is_some());
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    friend.node().connect(responder_addr).await.unwrap();
+    wait_until!(1, responder.node().num_connected() == 1);
+}
+
+#[tokio::test]
+async fn handshake_data_reaches_the_protocols() {
+    // the key negotiated during the handshake
+    struct XorKey(u8);
+
+    #[derive(Clone)]
+    struct XorNode(Node, u8, Arc<RwLock<Vec<Bytes>>>);
+
+    impl Pea2Pea for XorNode {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // the sides exchange their halves of the key, which is used to obfuscate the messages
+    #[async_trait::async_trait]
+    impl Handshaking for XorNode {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            conn.writer().write_all(&[self.1]).await?;
+            let peer_half = conn.reader().read_u8().await?;
+            conn.set_handshake_data(XorKey(self.1 ^ peer_half));
+
+            Ok(conn)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Reading for XorNode {
+        type Message = Bytes;
+
+        fn read_message(
+            &self,
+            ctx: &ConnectionContext,
+            buffer: &[u8],
+        ) -> io::Result<Option<(Self::Message, usize)>> {
+            let key = ctx.handshake_data::<XorKey>().unwrap().0;
+            let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+            Ok(bytes.map(|bytes| {
+                let message = bytes[2..].iter().map(|b| b ^ key).collect::<Vec<_>>();
+                (message.into(), bytes.len())
+            }))
+        }
+
+        async fn process_message_with_context(
+            &self,
+            ctx: &ConnectionContext,
+            message: Self::Message,
+        ) -> io::Result<()> {
+            assert!(ctx.handshake_data::<XorKey>().is_some());
+            self.2.write().push(message);
+
+            Ok(())
+        }
+    }
+
+    impl Writing for XorNode {
+        fn write_message(
+            &self,
+            ctx: &ConnectionContext,
+            payload: &[u8],
+            buffer: &mut [u8],
+        ) -> io::Result<usize> {
+            let key = ctx.handshake_data::<XorKey>().unwrap().0;
+            buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+            for (dst, src) in buffer[2..][..payload.len()].iter_mut().zip(payload) {
+                *dst = src ^ key;
+            }
+
+            Ok(2 + payload.len())
+        }
+    }
+
+    let alice = XorNode(Node::new(None).await.unwrap(), 0x0f, Default::default());
+    let bob = XorNode(Node::new(None).await.unwrap(), 0xf0, Default::default());
+    for node in &[&alice, &bob] {
+        node.enable_handshaking();
+        node.enable_reading();
+        node.enable_writing();
+    }
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+
+    let ctx = alice.node().connection_context(bob_addr).unwrap();
+    assert_eq!(ctx.addr(), bob_addr);
+    assert_eq!(ctx.handshake_data::<XorKey>().unwrap().0, 0xff);
+
+    let message = Bytes::from_static(b"obfuscated");
+    alice
+        .node()
+        .send_direct_message(bob_addr, message.clone())
+        .await
+        .unwrap();
+    wait_until!(1, bob.2.read().len() == 1);
+    assert_eq!(bob.2.read()[0], message);
+}
+
+#[tokio::test]
+async fn pending_inbound_connections_are_limited() {
+    #[derive(Clone)]
+    struct StallingNode(Node);
+
+    impl Pea2Pea for StallingNode {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // the handshake only completes once the initiator sends a byte
+    #[async_trait::async_trait]
+    impl Handshaking for StallingNode {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            conn.reader().read_u8().await?;
+
+            Ok(conn)
+        }
+    }
+
+    let config = NodeConfig {
+        max_pending_inbound: 1,
+        ..Default::default()
+    };
+    let node = StallingNode(Node::new(Some(config)).await.unwrap());
+    node.enable_handshaking();
+    let addr = node.node().listening_addr().unwrap();
+
+    let mut stalling = TcpStream::connect(addr).await.unwrap();
+    wait_until!(1, node.node().num_pending_inbound() == 1);
+
+    // the connections accepted while another one is pending are closed right away
+    let mut rejected = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    assert_eq!(rejected.read(&mut buf).await.unwrap(), 0);
+
+    // once the pending connection is established, there's room for another one
+    stalling.write_all(&[0]).await.unwrap();
+    wait_until!(1, node.node().num_connected() == 1);
+    assert_eq!(node.node().num_pending_inbound(), 0);
+    let mut accepted = TcpStream::connect(addr).await.unwrap();
+    accepted.write_all(&[0]).await.unwrap();
+    wait_until!(1, node.node().num_connected() == 2);
+}
+
+#[tokio::test]
+async fn pending_inbound_connections_count_towards_subnet_limits() {
+    #[derive(Clone)]
+    struct StallingNode(Node);
+
+    impl Pea2Pea for StallingNode {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // the handshake only completes once the initiator sends a byte
+    #[async_trait::async_trait]
+    impl Handshaking for StallingNode {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            conn.reader().read_u8().await?;
+
+            Ok(conn)
+        }
+    }
+
+    let config = NodeConfig {
+        subnet_limits: Some(SubnetLimits {
+            max_per_ip: 1,
+            max_per_subnet: 1,
+        }),
+        ..Default::default()
+    };
+    let node = StallingNode(Node::new(Some(config)).await.unwrap());
+    node.enable_handshaking();
+    let addr = node.node().listening_addr().unwrap();
+
+    // several parallel connections from a single IP; only one of them can be pending
+    let mut streams = Vec::new();
+    for _ in 0..3 {
+        streams.push(TcpStream::connect(addr).await.unwrap());
+    }
+    wait_until!(1, node.node().num_pending_inbound() == 1);
+
+    for stream in &mut streams {
+        let _ = stream.write_all(&[0]).await;
+    }
+    wait_until!(1, node.node().num_connected() == 1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(node.node().num_connected(), 1);
+    assert_eq!(node.node().num_pending_inbound(), 0);
+}
+
+#[tokio::test]
+async fn handshake_stats() {
+    let config = |version| NodeConfig {
+        protocol_version: version,
+        supported_version_range: Some(version..=version),
+        ..Default::default()
+    };
+
+    let alice = Node::new(Some(config(1))).await.unwrap();
+    let bob = Node::new(Some(config(1))).await.unwrap();
+    let carol = Node::new(Some(config(2))).await.unwrap();
+    let bob_addr = bob.listening_addr().unwrap();
+    let carol_addr = carol.listening_addr().unwrap();
+
+    alice.connect(bob_addr).await.unwrap();
+    for _ in 0..3 {
+        alice.connect(carol_addr).await.unwrap_err();
+    }
+
+    // the details are recorded per peer
+    let bob_stats = alice.known_peers().stats(bob_addr).unwrap();
+    assert_eq!(
+        (bob_stats.handshake_attempts, bob_stats.handshake_failures),
+        (1, 0)
+    );
+    assert!(bob_stats.last_handshake_duration.is_some());
+    assert!(bob_stats.last_handshake_failure.is_none());
+    let carol_stats = alice.known_peers().stats(carol_addr).unwrap();
+    assert_eq!(
+        (
+            carol_stats.handshake_attempts,
+            carol_stats.handshake_failures
+        ),
+        (3, 3)
+    );
+    let failure = carol_stats.last_handshake_failure.unwrap();
+    assert_eq!(failure.kind, io::ErrorKind::InvalidData);
+
+    // and summarized for the whole node
+    let stats = alice.handshake_stats();
+    assert_eq!((stats.attempts, stats.failures), (4, 3));
+    assert_eq!((stats.peers_attempted, stats.peers_failing), (2, 1));
+    assert!(stats.mean_duration.is_some());
+    assert_eq!(stats.failure_reasons, vec![(failure.reason.clone(), 1)]);
+
+    // the failed inbound handshakes are only summarized, leaving no per-peer records behind
+    wait_until!(1, carol.handshake_stats().failures == 3);
+    assert!(carol.known_peers().read().is_empty());
+    let stats = carol.handshake_stats();
+    assert_eq!((stats.attempts, stats.peers_attempted), (3, 0));
+    assert_eq!(stats.failure_reasons.len(), 1);
+    assert_eq!(stats.failure_reasons[0].1, 3);
+}
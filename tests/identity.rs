@@ -0,0 +1,259 @@
+#![cfg(feature = "identity")]
+
+use bytes::Bytes;
+
+mod common;
+use pea2pea::{
+    identity::{NodeIdentity, PeerId, SignatureScheme},
+    protocols::{Handshaking, Reading, Writing},
+    Connection, DuplicateIdentityPolicy, Node, NodeConfig, Pea2Pea,
+};
+use tokio::time::sleep;
+
+use std::{io, sync::Arc, time::Duration};
+
+// a signature scheme that produces signatures that can't be valid
+struct BogusSignatures;
+
+impl SignatureScheme for BogusSignatures {
+    fn signature_len(&self) -> usize {
+        64
+    }
+
+    fn sign(&self, _message: &[u8]) -> Vec<u8> {
+        vec![0; 64]
+    }
+
+    fn verify(&self, _public_key: &[u8; 32], _message: &[u8], _signature: &[u8]) -> bool {
+        false
+    }
+}
+
+async fn start_signing_node(scheme: Option<Arc<dyn SignatureScheme>>) -> common::MessagingNode {
+    let node = start_identified_node().await;
+    let scheme = scheme.unwrap_or_else(|| Arc::new(node.node().config().identity.clone().unwrap()));
+    node.node().set_signature_scheme(scheme);
+    node.enable_reading();
+    node.enable_writing();
+    node
+}
+
+async fn start_identified_node() -> common::MessagingNode {
+    let config = NodeConfig {
+        identity: Some(NodeIdentity::generate()),
+        max_handshake_time_ms: 100,
+        ..Default::default()
+    };
+    common::MessagingNode(Node::new(Some(config)).await.unwrap())
+}
+
+#[tokio::test]
+async fn identity_exchange() {
+    let alice = start_identified_node().await;
+    let bob = start_identified_node().await;
+    alice.enable_writing();
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    let bob_id = bob.node().peer_id().unwrap();
+    let alice_addr = bob.node().connected_addrs()[0];
+    assert_eq!(alice.node().connected_peer_id(bob_addr), Some(bob_id));
+    assert_eq!(
+        bob.node().connected_peer_id(alice_addr),
+        alice.node().peer_id()
+    );
+
+    // the peer is addressable by its PeerId
+    alice
+        .node()
+        .send_to_peer(&bob_id, Bytes::from_static(b"hi, bob"))
+        .await
+        .unwrap();
+    wait_until!(1, bob.node().stats().received().0 == 1);
+
+    let stranger = PeerId::from_public_key(&NodeIdentity::generate().public_key());
+    assert!(alice
+        .node()
+        .send_to_peer(&stranger, Bytes::from_static(b"hi?"))
+        .await
+        .is_err());
+}
+
+#[test]
+fn identity_restoration() {
+    let identity = NodeIdentity::generate();
+    let restored = NodeIdentity::from_secret_key(identity.secret_key());
+
+    assert_eq!(identity.peer_id(), restored.peer_id());
+}
+
+#[tokio::test]
+async fn duplicate_identities() {
+    for policy in [
+        DuplicateIdentityPolicy::RejectNew,
+        DuplicateIdentityPolicy::ReplaceOld,
+        DuplicateIdentityPolicy::AllowBoth,
+    ] {
+        let config = NodeConfig {
+            identity: Some(NodeIdentity::generate()),
+            duplicate_identity_policy: policy,
+            ..Default::default()
+        };
+        let bob = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+        let bob_addr = bob.node().listening_addr().unwrap();
+
+        // the same identity, used by two nodes with different addresses
+        let identity = NodeIdentity::generate();
+        let mut sybils = Vec::new();
+        for _ in 0..2 {
+            let config = NodeConfig {
+                identity: Some(identity.clone()),
+                ..Default::default()
+            };
+            sybils.push(common::MessagingNode(
+                Node::new(Some(config)).await.unwrap(),
+            ));
+        }
+
+        sybils[0].node().connect(bob_addr).await.unwrap();
+        wait_until!(1, bob.node().num_connected() == 1);
+        let first_addr = bob.node().connected_addrs()[0];
+
+        sybils[1].node().connect(bob_addr).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let addrs = bob.node().connected_addrs();
+        match policy {
+            DuplicateIdentityPolicy::RejectNew => assert_eq!(addrs, [first_addr]),
+            DuplicateIdentityPolicy::ReplaceOld => {
+                assert_eq!(addrs.len(), 1);
+                assert_ne!(addrs[0], first_addr);
+            }
+            DuplicateIdentityPolicy::AllowBoth => assert_eq!(addrs.len(), 2),
+        }
+    }
+}
+
+// a node whose handshakes take a while
+#[derive(Clone)]
+struct SlowHandshake(Node);
+
+impl Pea2Pea for SlowHandshake {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Handshaking for SlowHandshake {
+    async fn perform_handshake(&self, conn: Connection) -> io::Result<Connection> {
+        sleep(Duration::from_millis(100)).await;
+        Ok(conn)
+    }
+}
+
+#[tokio::test]
+async fn concurrent_duplicate_identities() {
+    let config = NodeConfig {
+        identity: Some(NodeIdentity::generate()),
+        duplicate_identity_policy: DuplicateIdentityPolicy::RejectNew,
+        ..Default::default()
+    };
+    let bob = SlowHandshake(Node::new(Some(config)).await.unwrap());
+    bob.enable_handshaking();
+    let bob_addr = bob.node().listening_addr().unwrap();
+
+    // the same identity, used by nodes with different addresses
+    let identity = NodeIdentity::generate();
+    let mut sybils = Vec::new();
+    for _ in 0..4 {
+        let config = NodeConfig {
+            identity: Some(identity.clone()),
+            ..Default::default()
+        };
+        sybils.push(common::MessagingNode(
+            Node::new(Some(config)).await.unwrap(),
+        ));
+    }
+
+    // the handshakes overlap, but only one of them can succeed
+    for sybil in &sybils {
+        let sybil = sybil.clone();
+        tokio::spawn(async move { sybil.node().connect(bob_addr).await });
+    }
+    wait_until!(1, bob.node().num_connected() == 1);
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(bob.node().num_connected(), 1);
+}
+
+#[tokio::test]
+async fn no_identity_no_connection() {
+    let identified = start_identified_node().await;
+    let anonymous = common::MessagingNode::new("anonymous").await;
+
+    assert!(anonymous
+        .node()
+        .connect(identified.node().listening_addr().unwrap())
+        .await
+        .is_ok());
+    wait_until!(1, identified.node().num_connected() == 0);
+}
+
+#[tokio::test]
+async fn signed_messages() {
+    let alice = start_signing_node(None).await;
+    let bob = start_signing_node(None).await;
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    for _ in 0..3 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, Bytes::from_static(b"signed, alice"))
+            .await
+            .unwrap();
+        bob.node()
+            .send_direct_message(alice_addr, Bytes::from_static(b"signed, bob"))
+            .await
+            .unwrap();
+    }
+
+    wait_until!(1, bob.node().stats().received().0 == 3);
+    wait_until!(1, alice.node().stats().received().0 == 3);
+    assert!(alice.node().is_connected(bob_addr));
+    assert!(bob.node().is_connected(alice_addr));
+}
+
+#[tokio::test]
+async fn invalid_signatures_are_penalized() {
+    let mallory = start_signing_node(Some(Arc::new(BogusSignatures))).await;
+    let bob = start_signing_node(None).await;
+
+    let mallory_addr = mallory.node().listening_addr().unwrap();
+    bob.node().connect(mallory_addr).await.unwrap();
+    wait_until!(1, mallory.node().num_connected() == 1);
+    let bob_addr = mallory.node().connected_addrs()[0];
+
+    mallory
+        .node()
+        .send_direct_message(bob_addr, Bytes::from_static(b"trust me"))
+        .await
+        .unwrap();
+
+    wait_until!(1, bob.node().num_connected() == 0);
+    assert_eq!(bob.node().stats().received().0, 0);
+    assert_eq!(
+        bob.node()
+            .known_peers()
+            .read()
+            .get(&mallory_addr)
+            .unwrap()
+            .failures,
+        1
+    );
+}
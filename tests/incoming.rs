@@ -0,0 +1,143 @@
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::time::timeout;
+
+mod common;
+use pea2pea::{
+    protocols::{Reading, Writing},
+    ConnectionContext, Node, Pea2Pea,
+};
+
+use std::{future::poll_fn, io, net::SocketAddr, pin::Pin, time::Duration};
+
+#[derive(Clone)]
+struct LenPrefixedWriter(Node);
+
+impl Pea2Pea for LenPrefixedWriter {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+impl Writing for LenPrefixedWriter {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        buffer[4..][..payload.len()].copy_from_slice(payload);
+        Ok(4 + payload.len())
+    }
+}
+
+#[derive(Clone)]
+struct Sink(Node);
+
+impl Pea2Pea for Sink {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for Sink {
+    type Message = ();
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<((), usize)>> {
+        Ok(Some(((), buffer.len())))
+    }
+
+    async fn process_message(&self, _source: SocketAddr, _message: ()) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn incoming_streams() {
+    let receiver = Node::new(None).await.unwrap();
+    let mut all = receiver.incoming().unwrap();
+
+    let senders = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(LenPrefixedWriter)
+        .collect::<Vec<_>>();
+    for sender in &senders {
+        sender.enable_writing();
+        sender
+            .node()
+            .connect(receiver.listening_addr().unwrap())
+            .await
+            .unwrap();
+    }
+    wait_until!(1, receiver.num_connected() == 2);
+
+    let addrs = receiver.connected_addrs();
+    let mut first = receiver.incoming_from(addrs[0]).unwrap();
+
+    for (i, sender) in senders.iter().enumerate() {
+        let addr = sender.node().connected_addrs()[0];
+        sender
+            .node()
+            .send_direct_message(addr, Bytes::from(vec![i as u8; 3]))
+            .await
+            .unwrap();
+    }
+
+    // the stream of all the messages receives both of them
+    let mut payloads = Vec::new();
+    for _ in 0..2 {
+        let (_, payload) = timeout(Duration::from_secs(1), all.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        payloads.push(payload);
+    }
+    payloads.sort();
+    assert_eq!(
+        payloads,
+        vec![Bytes::from(vec![0; 3]), Bytes::from(vec![1; 3])]
+    );
+
+    // the per-peer stream only receives the one from its peer, and can be polled as a Stream
+    let (source, _) = timeout(
+        Duration::from_secs(1),
+        poll_fn(|cx| Pin::new(&mut first).poll_next(cx)),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(source, addrs[0]);
+
+    // the per-peer stream ends once its peer disconnects
+    assert!(receiver.disconnect(addrs[0]));
+    assert!(first.recv().await.is_none());
+    assert!(receiver.incoming_from(addrs[0]).is_err());
+
+    // all the streams end once the node shuts down
+    receiver.shut_down();
+    assert!(all.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn incoming_streams_require_the_built_in_reader() {
+    let node = Node::new(None).await.unwrap();
+
+    // the streams can be created repeatedly
+    node.incoming().unwrap();
+    node.incoming().unwrap();
+
+    // the built-in reader can't replace an application's reader
+    let other = Sink(Node::new(None).await.unwrap());
+    other.enable_reading();
+    assert_eq!(
+        other.node().incoming().map(|_| ()).unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+}
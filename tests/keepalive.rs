@@ -0,0 +1,205 @@
+use bytes::Bytes;
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+mod common;
+use pea2pea::{
+    protocols::{KeepAlive, Reading, Writing},
+    ConnectionContext, Heartbeat, Node, NodeConfig, Pea2Pea,
+};
+
+use std::{io, net::SocketAddr, time::Duration};
+
+#[derive(Clone)]
+struct PingingNode(Node);
+
+impl Pea2Pea for PingingNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+impl Writing for PingingNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl KeepAlive for PingingNode {
+    fn keepalive_message(&self) -> Bytes {
+        Bytes::from_static(b"ping")
+    }
+}
+
+async fn start_pinging_node(interval_ms: u64) -> PingingNode {
+    let config = NodeConfig {
+        keepalive_interval_ms: interval_ms,
+        min_keepalive_interval_ms: 10,
+        max_keepalive_interval_ms: 1_000,
+        ..Default::default()
+    };
+    let node = PingingNode(Node::new(Some(config)).await.unwrap());
+    node.enable_writing();
+    node.enable_keepalive();
+
+    node
+}
+
+fn keepalive_interval(node: &PingingNode, addr: SocketAddr) -> Option<Duration> {
+    node.node()
+        .known_peers()
+        .read()
+        .get(&addr)
+        .and_then(|stats| stats.keepalive_interval)
+}
+
+#[tokio::test]
+async fn keepalive_interval_grows_on_stable_links() {
+    let pinger = start_pinging_node(40).await;
+    let listener = common::MessagingNode::new("listener").await;
+    listener.enable_reading();
+    let listener_addr = listener.node().listening_addr().unwrap();
+
+    pinger.node().connect(listener_addr).await.unwrap();
+
+    // the idle connection receives keep-alive messages and the interval gets longer
+    wait_until!(1, listener.node().stats().received().0 >= 2);
+    wait_until!(
+        1,
+        keepalive_interval(&pinger, listener_addr) > Some(Duration::from_millis(40))
+    );
+}
+
+#[tokio::test]
+async fn keepalive_interval_shrinks_on_idle_drops() {
+    let pinger = start_pinging_node(100).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+
+    pinger.node().connect(listener_addr).await.unwrap();
+
+    // the peer drops the connection while it's idle
+    drop(listener.accept().await.unwrap());
+
+    wait_until!(1, pinger.node().num_connected() == 0);
+    wait_until!(
+        1,
+        keepalive_interval(&pinger, listener_addr) < Some(Duration::from_millis(100))
+    );
+}
+
+#[tokio::test]
+async fn keepalive_ignores_pause() {
+    let pinger = start_pinging_node(40).await;
+    let listener = common::MessagingNode::new("listener").await;
+    listener.enable_reading();
+    let listener_addr = listener.node().listening_addr().unwrap();
+
+    pinger.node().connect(listener_addr).await.unwrap();
+    pinger.node().pause();
+
+    // regular messages are held back, but keep-alive messages are still sent
+    pinger
+        .node()
+        .send_direct_message(listener_addr, Bytes::from_static(b"held back"))
+        .await
+        .unwrap();
+    wait_until!(1, listener.node().stats().received().0 >= 2);
+    assert_eq!(
+        listener.node().stats().received().1,
+        listener.node().stats().received().0 * 6 // the size of a keep-alive message
+    );
+}
+
+#[derive(Clone)]
+struct HeartbeatNode(Node);
+
+impl Pea2Pea for HeartbeatNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+const HEARTBEAT: &[u8] = b"hb";
+
+#[async_trait::async_trait]
+impl Reading for HeartbeatNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    fn is_heartbeat(&self, message: &Self::Message) -> bool {
+        message == HEARTBEAT
+    }
+}
+
+impl Writing for HeartbeatNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+#[tokio::test]
+async fn heartbeats_are_invisible_to_protocols() {
+    let config = NodeConfig {
+        heartbeat: Some(Heartbeat {
+            interval_ms: 20,
+            payload: Bytes::from_static(HEARTBEAT),
+        }),
+        ..Default::default()
+    };
+    let sender = HeartbeatNode(Node::new(Some(config.clone())).await.unwrap());
+    sender.enable_writing();
+    let receiver = HeartbeatNode(Node::new(Some(config)).await.unwrap());
+    receiver.enable_reading();
+    let receiver_addr = receiver.node().listening_addr().unwrap();
+
+    // an idle connection carries heartbeats, framed like any other message
+    let raw_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    sender
+        .node()
+        .connect(raw_listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut raw_stream, _) = raw_listener.accept().await.unwrap();
+    let mut heartbeats = [0u8; 8];
+    raw_stream.read_exact(&mut heartbeats).await.unwrap();
+    let frame = common::prefix_with_len(2, HEARTBEAT);
+    assert_eq!(heartbeats, [&frame[..], &frame[..]].concat()[..]);
+
+    // the heartbeats aren't counted or processed by the receiver
+    sender.node().connect(receiver_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(receiver.node().num_connected(), 1);
+    assert_eq!(receiver.node().stats().received().0, 0);
+
+    // regular messages are unaffected
+    sender
+        .node()
+        .send_direct_message(receiver_addr, Bytes::from_static(b"hello"))
+        .await
+        .unwrap();
+    wait_until!(1, receiver.node().stats().received().0 == 1);
+    assert_eq!(sender.node().stats().sent().0, 1);
+}
@@ -0,0 +1,133 @@
+#![cfg(feature = "libp2p")]
+
+use bytes::Bytes;
+use tokio::{sync::mpsc, time::timeout};
+
+mod common;
+use pea2pea::{
+    identity::NodeIdentity,
+    libp2p::{Libp2pConfig, Libp2pNode, Libp2pPeerId, Muxer, MIN_READ_BUFFER_SIZE},
+    protocols::{Handshaking, Reading, Writing},
+    Node, NodeConfig, Pea2Pea,
+};
+
+use std::{net::SocketAddr, time::Duration};
+
+async fn libp2p_node(config: Libp2pConfig) -> (Libp2pNode, mpsc::Receiver<(SocketAddr, Bytes)>) {
+    let node_config = NodeConfig {
+        conn_read_buffer_size: MIN_READ_BUFFER_SIZE,
+        conn_write_buffer_size: MIN_READ_BUFFER_SIZE * 2,
+        ..Default::default()
+    };
+    let (node, inbound) =
+        Libp2pNode::new(Node::new(Some(node_config)).await.unwrap(), config).unwrap();
+    node.enable_handshaking();
+    node.enable_reading();
+    node.enable_writing();
+
+    (node, inbound)
+}
+
+#[test]
+fn libp2p_peer_ids() {
+    let identity = NodeIdentity::from_secret_key([7; 32]);
+    let peer_id = Libp2pPeerId::from_public_key(&identity.public_key());
+
+    // the identity multihash of the protobuf-encoded key
+    assert_eq!(
+        peer_id.as_bytes()[..6],
+        [0x00, 0x24, 0x08, 0x01, 0x12, 0x20]
+    );
+    assert_eq!(peer_id.as_bytes()[6..], identity.public_key());
+    // all the Ed25519-based peer IDs share this prefix in base58btc
+    let encoded = peer_id.to_string();
+    assert!(encoded.starts_with("12D3KooW"));
+    assert_eq!(encoded.len(), 52);
+}
+
+#[tokio::test]
+async fn libp2p_upgrade_and_messaging() {
+    for (alice_muxers, bob_muxers, expected) in [
+        (
+            vec![Muxer::Yamux, Muxer::Mplex],
+            vec![Muxer::Yamux],
+            Muxer::Yamux,
+        ),
+        (
+            vec![Muxer::Yamux, Muxer::Mplex],
+            vec![Muxer::Mplex],
+            Muxer::Mplex,
+        ),
+    ] {
+        let (alice, mut alice_inbound) = libp2p_node(Libp2pConfig {
+            muxers: alice_muxers,
+            ..Default::default()
+        })
+        .await;
+        let (bob, mut bob_inbound) = libp2p_node(Libp2pConfig {
+            muxers: bob_muxers,
+            ..Default::default()
+        })
+        .await;
+
+        let bob_addr = bob.node().listening_addr().unwrap();
+        alice.node().connect(bob_addr).await.unwrap();
+        wait_until!(1, bob.node().num_connected() == 1);
+        let alice_addr = bob.node().connected_addrs()[0];
+
+        // both sides authenticated each other and agreed on the muxer
+        let bob_as_peer = alice.peer(bob_addr).unwrap();
+        assert_eq!(bob_as_peer.peer_id, bob.peer_id());
+        assert_eq!(bob_as_peer.muxer, expected);
+        let alice_as_peer = bob.peer(alice_addr).unwrap();
+        assert_eq!(alice_as_peer.peer_id, alice.peer_id());
+        assert_eq!(alice_as_peer.muxer, expected);
+
+        // messages are exchanged in both directions, as long as they don't exceed the size limit
+        alice.send(bob_addr, b"hello").await.unwrap();
+        alice.send(bob_addr, &[0; 100_000]).await.unwrap_err();
+        bob.send(alice_addr, b"hi").await.unwrap();
+
+        let (source, message) = timeout(Duration::from_secs(1), bob_inbound.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((source, &message[..]), (alice_addr, &b"hello"[..]));
+        let (source, message) = timeout(Duration::from_secs(1), alice_inbound.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((source, &message[..]), (bob_addr, &b"hi"[..]));
+    }
+}
+
+#[tokio::test]
+async fn libp2p_large_messages() {
+    let config = Libp2pConfig {
+        max_message_len: 100_000,
+        ..Default::default()
+    };
+    let (alice, _) = libp2p_node(config.clone()).await;
+    let (bob, mut bob_inbound) = libp2p_node(Libp2pConfig {
+        identity: NodeIdentity::generate(),
+        ..config
+    })
+    .await;
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+
+    // the message doesn't fit in a single noise message
+    let large = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+    alice.send(bob_addr, &large).await.unwrap();
+    let (_, message) = timeout(Duration::from_secs(1), bob_inbound.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message[..], large[..]);
+}
+
+#[tokio::test]
+async fn libp2p_requires_large_read_buffer() {
+    assert!(Libp2pNode::new(Node::new(None).await.unwrap(), Default::default()).is_err());
+}
@@ -1,15 +1,31 @@
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use parking_lot::Mutex;
+use tokio::time::timeout;
 use tracing::*;
 
 mod common;
 use pea2pea::{
-    protocols::{Reading, Writing},
-    Node, NodeConfig, Pea2Pea,
+    protocols::{
+        processing_deadline, responder, Payload, Reading, TypedWriting, Writing,
+        MAX_INLINE_PAYLOAD_LEN,
+    },
+    read_capture, CaptureWriter, ClosedInboundQueuePolicy, ConnectionContext, ConnectionMode,
+    EgressPolicy, FrameDirection, FrameSamplingConfig, InboundQueueOverflowPolicy, Node,
+    NodeConfig, NodeEvent, Pea2Pea, ProcessingMode, ReadBufferGrowth, SlowPeerDetection,
+    WriterStallAction,
 };
 use TestMessage::*;
 
-use std::{collections::HashSet, io, net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashSet,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 enum TestMessage {
@@ -45,7 +61,7 @@ impl Reading for EchoNode {
 
     fn read_message(
         &self,
-        _source: SocketAddr,
+        _ctx: &ConnectionContext,
         buffer: &[u8],
     ) -> io::Result<Option<(Self::Message, usize)>> {
         let bytes = common::read_len_prefixed_message(2, buffer)?;
@@ -59,8 +75,11 @@ impl Reading for EchoNode {
         if self.echoed.lock().insert(message) {
             info!(parent: self.node().span(), "it was new! echoing it");
 
-            self.node()
-                .send_direct_message(source, Bytes::copy_from_slice(&[message as u8]))
+            // reply directly via the source connection
+            let responder = responder().unwrap();
+            assert_eq!(responder.addr(), source);
+            responder
+                .reply(Bytes::copy_from_slice(&[message as u8]))
                 .await
                 .unwrap();
         } else {
@@ -72,13 +91,342 @@ impl Reading for EchoNode {
 }
 
 impl Writing for EchoNode {
-    fn write_message(&self, _: SocketAddr, payload: &[u8], buffer: &mut [u8]) -> io::Result<usize> {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
         buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
-        buffer[2..][..payload.len()].copy_from_slice(&payload);
+        buffer[2..][..payload.len()].copy_from_slice(payload);
         Ok(2 + payload.len())
     }
 }
 
+impl TypedWriting for EchoNode {
+    type Message = TestMessage;
+
+    fn serialize_message(&self, message: &TestMessage, buffer: &mut BytesMut) -> io::Result<()> {
+        buffer.put_u8(*message as u8);
+        Ok(())
+    }
+}
+
+// takes a while to process messages, keeping track of their order and concurrency
+#[derive(Clone)]
+struct SlowNode {
+    node: Node,
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+    processed: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SlowNode {
+    async fn new(mode: ProcessingMode) -> Self {
+        let config = NodeConfig {
+            message_processing_mode: mode,
+            ..Default::default()
+        };
+
+        Self {
+            node: Node::new(Some(config)).await.unwrap(),
+            in_flight: Default::default(),
+            max_in_flight: Default::default(),
+            processed: Default::default(),
+        }
+    }
+}
+
+impl Pea2Pea for SlowNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for SlowNode {
+    type Message = u8;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(u8, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (bytes[2], bytes.len())))
+    }
+
+    async fn process_message(&self, _source: SocketAddr, message: u8) -> io::Result<()> {
+        let in_flight = self.in_flight.fetch_add(1, SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, SeqCst);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        self.processed.lock().push(message);
+        self.in_flight.fetch_sub(1, SeqCst);
+
+        Ok(())
+    }
+}
+
+async fn process_slowly(mode: ProcessingMode) -> SlowNode {
+    const NUM_MESSAGES: u8 = 8;
+
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+    let reader = SlowNode::new(mode).await;
+    reader.enable_reading();
+
+    let reader_addr = reader.node().listening_addr().unwrap();
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+
+    for i in 0..NUM_MESSAGES {
+        writer
+            .node()
+            .send_direct_message(reader_addr, Bytes::copy_from_slice(&[i]))
+            .await
+            .unwrap();
+    }
+
+    wait_until!(1, reader.processed.lock().len() == NUM_MESSAGES as usize);
+
+    reader
+}
+
+#[tokio::test]
+async fn sequential_message_processing() {
+    let reader = process_slowly(ProcessingMode::Sequential).await;
+
+    assert_eq!(reader.max_in_flight.load(SeqCst), 1);
+    assert_eq!(*reader.processed.lock(), (0..8).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn concurrent_message_processing() {
+    let reader = process_slowly(ProcessingMode::Concurrent { max_in_flight: 4 }).await;
+
+    assert_eq!(reader.max_in_flight.load(SeqCst), 4);
+}
+
+#[tokio::test]
+async fn fair_message_processing() {
+    let chatty = common::MessagingNode::new("chatty").await;
+    let quiet = common::MessagingNode::new("quiet").await;
+    let reader = SlowNode::new(ProcessingMode::Fair { max_in_flight: 1 }).await;
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    for writer in &[&chatty, &quiet] {
+        writer.enable_writing();
+        writer.node().connect(reader_addr).await.unwrap();
+    }
+    wait_until!(1, reader.node().num_connected() == 2);
+
+    // the chatty peer's messages are queued first
+    for i in 0..10 {
+        chatty
+            .node()
+            .send_direct_message(reader_addr, Bytes::copy_from_slice(&[i]))
+            .await
+            .unwrap();
+    }
+    wait_until!(1, reader.node().stats().received().0 == 10);
+    quiet
+        .node()
+        .send_direct_message(reader_addr, Bytes::from_static(&[100]))
+        .await
+        .unwrap();
+
+    wait_until!(1, reader.processed.lock().len() == 11);
+
+    // the quiet peer's message doesn't have to wait for all of the chatty peer's ones
+    let processed = reader.processed.lock().clone();
+    let quiet_pos = processed.iter().position(|msg| *msg == 100).unwrap();
+    assert!(quiet_pos <= 2, "{:?}", processed);
+    // the messages from a single peer are still processed in order
+    let chatty_msgs = processed
+        .into_iter()
+        .filter(|msg| *msg != 100)
+        .collect::<Vec<_>>();
+    assert_eq!(chatty_msgs, (0..10).collect::<Vec<_>>());
+    assert_eq!(reader.max_in_flight.load(SeqCst), 1);
+}
+
+#[derive(Clone)]
+struct StuckNode {
+    node: Node,
+    started: Arc<AtomicUsize>,
+    saw_deadline: Arc<AtomicUsize>,
+}
+
+impl StuckNode {
+    async fn new(config: NodeConfig) -> Self {
+        Self {
+            node: Node::new(Some(config)).await.unwrap(),
+            started: Default::default(),
+            saw_deadline: Default::default(),
+        }
+    }
+}
+
+impl Pea2Pea for StuckNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for StuckNode {
+    type Message = u8;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(u8, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (bytes[2], bytes.len())))
+    }
+
+    async fn process_message(&self, _source: SocketAddr, _message: u8) -> io::Result<()> {
+        self.started.fetch_add(1, SeqCst);
+        if processing_deadline().is_some() {
+            self.saw_deadline.fetch_add(1, SeqCst);
+        }
+
+        // e.g. awaiting a dead database
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn message_processing_timeout() {
+    let config = NodeConfig {
+        message_processing_timeout_ms: Some(100),
+        disconnect_on_processing_timeout: true,
+        ..Default::default()
+    };
+    let reader = StuckNode::new(config).await;
+    reader.enable_reading();
+    let mut events = reader.node().subscribe();
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+
+    let reader_addr = reader.node().listening_addr().unwrap();
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+    let writer_addr = reader.node().connected_addrs()[0];
+
+    writer
+        .node()
+        .send_direct_message(reader_addr, Bytes::from_static(&[0]))
+        .await
+        .unwrap();
+
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if matches!(event, NodeEvent::ProcessingTimedOut(addr, _) if addr == writer_addr) {
+            break;
+        }
+    }
+    wait_until!(1, reader.node().num_connected() == 0);
+    assert_eq!(reader.saw_deadline.load(SeqCst), 1);
+    assert!(processing_deadline().is_none());
+}
+
+async fn overflow_inbound_queue(policy: InboundQueueOverflowPolicy) -> (StuckNode, SocketAddr) {
+    let config = NodeConfig {
+        conn_inbound_queue_depth: 2,
+        inbound_queue_overflow_policy: policy,
+        #[cfg(feature = "test-utils")]
+        fail_fast: pea2pea::FailFastMode::Record,
+        ..Default::default()
+    };
+    let reader = StuckNode::new(config).await;
+    reader.enable_reading();
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+
+    let reader_addr = reader.node().listening_addr().unwrap();
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+    let writer_addr = reader.node().connected_addrs()[0];
+
+    // the first message gets stuck in processing, the next 2 fill the queue, and the last 2 overflow it
+    for i in 0..5u8 {
+        writer
+            .node()
+            .send_direct_message(reader_addr, Bytes::copy_from_slice(&[i]))
+            .await
+            .unwrap();
+        wait_until!(1, reader.node().stats().received().0 == i as u64 + 1);
+        if i == 0 {
+            wait_until!(1, reader.started.load(SeqCst) == 1);
+        }
+        if reader.node().num_connected() == 0 {
+            break;
+        }
+    }
+
+    (reader, writer_addr)
+}
+
+#[tokio::test]
+async fn inbound_queue_overflow_drop_newest() {
+    let (reader, writer_addr) =
+        overflow_inbound_queue(InboundQueueOverflowPolicy::DropNewest).await;
+
+    wait_until!(1, reader.node().stats().overflowed() == 2);
+    let info = reader.node().connection_info(writer_addr).unwrap();
+    assert_eq!(info.msgs_overflowed, 2);
+    // the overflows are internal errors reported in fail-fast mode
+    #[cfg(feature = "test-utils")]
+    assert!(reader
+        .node()
+        .failures()
+        .iter()
+        .any(|failure| failure.contains("overflowed")));
+}
+
+#[tokio::test]
+async fn inbound_queue_overflow_drop_oldest() {
+    let (reader, writer_addr) =
+        overflow_inbound_queue(InboundQueueOverflowPolicy::DropOldest).await;
+
+    wait_until!(1, reader.node().stats().overflowed() == 2);
+    let info = reader.node().connection_info(writer_addr).unwrap();
+    assert_eq!(info.msgs_overflowed, 2);
+    assert_eq!(reader.started.load(SeqCst), 1);
+    // the overflows are internal errors reported in fail-fast mode
+    #[cfg(feature = "test-utils")]
+    assert!(reader
+        .node()
+        .failures()
+        .iter()
+        .any(|failure| failure.contains("overflowed")));
+}
+
+#[tokio::test]
+async fn inbound_queue_overflow_disconnect() {
+    let (reader, _) = overflow_inbound_queue(InboundQueueOverflowPolicy::DisconnectPeer).await;
+
+    wait_until!(1, reader.node().num_connected() == 0);
+    assert_eq!(reader.node().stats().overflowed(), 1);
+    // the overflows are internal errors reported in fail-fast mode
+    #[cfg(feature = "test-utils")]
+    assert!(reader
+        .node()
+        .failures()
+        .iter()
+        .any(|failure| failure.contains("overflowed")));
+}
+
 #[tokio::test]
 async fn messaging_example() {
     tracing_subscriber::fmt::init();
@@ -98,7 +446,7 @@ async fn messaging_example() {
     picky_echo.enable_reading();
     picky_echo.enable_writing();
 
-    let picky_echo_addr = picky_echo.node().listening_addr();
+    let picky_echo_addr = picky_echo.node().listening_addr().unwrap();
 
     shouter.node().connect(picky_echo_addr).await.unwrap();
 
@@ -126,6 +474,134 @@ async fn messaging_example() {
     wait_until!(1, shouter.node().stats().received().0 == 3);
 }
 
+#[tokio::test]
+async fn direct_message_processing() {
+    let shouter = common::MessagingNode::new("shout").await;
+    shouter.enable_reading();
+    shouter.enable_writing();
+    // there is no message to reply to
+    assert!(responder().is_none());
+
+    let config = NodeConfig {
+        name: Some("direct_echo".into()),
+        direct_message_processing: true,
+        ..Default::default()
+    };
+    let echo = EchoNode {
+        node: Node::new(Some(config)).await.unwrap(),
+        echoed: Default::default(),
+    };
+    echo.enable_reading();
+    echo.enable_writing();
+
+    let echo_addr = echo.node().listening_addr().unwrap();
+    shouter.node().connect(echo_addr).await.unwrap();
+    wait_until!(1, echo.node().num_connected() == 1);
+
+    for message in &[Herp, Derp, Herp] {
+        let msg = Bytes::copy_from_slice(&[*message as u8]);
+        shouter
+            .node()
+            .send_direct_message(echo_addr, msg)
+            .await
+            .unwrap();
+    }
+
+    // the messages are processed without an intermediate queue, but the results are the same
+    wait_until!(1, shouter.node().stats().received().0 == 2);
+    assert_eq!(echo.echoed.lock().len(), 2);
+}
+
+#[tokio::test]
+async fn message_delivery_confirmation() {
+    let reader = common::MessagingNode::new("reader").await;
+    reader.enable_reading();
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+
+    let reader_addr = reader.node().listening_addr().unwrap();
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+
+    let delivery = writer
+        .node()
+        .send_direct_message_with_delivery(reader_addr, Bytes::from_static(b"important"))
+        .await
+        .unwrap();
+
+    // once the delivery is confirmed, the message has already been written to the stream
+    assert!(delivery.await.is_ok());
+    assert_eq!(writer.node().stats().sent().0, 1);
+    wait_until!(1, reader.node().stats().received().0 == 1);
+
+    // messages to unconnected addresses can't even be queued
+    writer.node().disconnect(reader_addr);
+    assert!(writer
+        .node()
+        .send_direct_message_with_delivery(reader_addr, Bytes::from_static(b"important"))
+        .await
+        .is_err());
+}
+
+// forbids sending messages other than Herp to a single address
+struct OnlyHerpTo(SocketAddr);
+
+impl EgressPolicy for OnlyHerpTo {
+    fn message_class(&self, message: &[u8]) -> u8 {
+        message[0]
+    }
+
+    fn allow_send(&self, addr: SocketAddr, class: u8, _len: usize) -> bool {
+        addr != self.0 || class == Herp as u8
+    }
+}
+
+#[tokio::test]
+async fn egress_policy() {
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+    let restricted = common::MessagingNode::new("restricted").await;
+    restricted.enable_reading();
+    let unrestricted = common::MessagingNode::new("unrestricted").await;
+    unrestricted.enable_reading();
+
+    let restricted_addr = restricted.node().listening_addr().unwrap();
+    let unrestricted_addr = unrestricted.node().listening_addr().unwrap();
+    writer
+        .node()
+        .set_egress_policy(Arc::new(OnlyHerpTo(restricted_addr)));
+    writer.node().connect(restricted_addr).await.unwrap();
+    writer.node().connect(unrestricted_addr).await.unwrap();
+
+    let herp = Bytes::copy_from_slice(&[Herp as u8]);
+    let derp = Bytes::copy_from_slice(&[Derp as u8]);
+
+    // direct messages vetoed by the policy result in an error
+    let err = writer
+        .node()
+        .send_direct_message(restricted_addr, derp.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    writer
+        .node()
+        .send_direct_message(restricted_addr, herp)
+        .await
+        .unwrap();
+    writer
+        .node()
+        .send_direct_message(unrestricted_addr, derp.clone())
+        .await
+        .unwrap();
+
+    // broadcasts skip the vetoed peers
+    writer.node().send_broadcast(derp).await.unwrap();
+
+    wait_until!(1, unrestricted.node().stats().received().0 == 2);
+    wait_until!(1, restricted.node().stats().received().0 == 1);
+    assert_eq!(writer.node().stats().sent().0, 3);
+}
+
 #[tokio::test]
 async fn drop_connection_on_invalid_message() {
     let reader = common::MessagingNode::new("reader").await;
@@ -135,7 +611,7 @@ async fn drop_connection_on_invalid_message() {
 
     writer
         .node()
-        .connect(reader.node().listening_addr())
+        .connect(reader.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -146,7 +622,7 @@ async fn drop_connection_on_invalid_message() {
 
     writer
         .node()
-        .send_direct_message(reader.node().listening_addr(), bad_message.into())
+        .send_direct_message(reader.node().listening_addr().unwrap(), bad_message.into())
         .await
         .unwrap();
 
@@ -170,7 +646,7 @@ async fn drop_connection_on_oversized_message() {
 
     writer
         .node()
-        .connect(reader.node().listening_addr())
+        .connect(reader.node().listening_addr().unwrap())
         .await
         .unwrap();
 
@@ -182,7 +658,7 @@ async fn drop_connection_on_oversized_message() {
     writer
         .node()
         .send_direct_message(
-            reader.node().listening_addr(),
+            reader.node().listening_addr().unwrap(),
             common::prefix_with_len(2, &oversized_payload),
         )
         .await
@@ -190,3 +666,1358 @@ async fn drop_connection_on_oversized_message() {
 
     wait_until!(1, reader.node().num_connected() == 0);
 }
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn fail_fast_records_internal_errors() {
+    const MSG_SIZE_LIMIT: usize = 10;
+
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+
+    let config = NodeConfig {
+        name: Some("reader".into()),
+        conn_read_buffer_size: MSG_SIZE_LIMIT,
+        fail_fast: pea2pea::FailFastMode::Record,
+        ..Default::default()
+    };
+    let reader = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    reader.enable_reading();
+    reader.node().assert_healthy();
+
+    writer
+        .node()
+        .connect(reader.node().listening_addr().unwrap())
+        .await
+        .unwrap();
+
+    wait_until!(1, reader.node().num_connected() == 1);
+
+    let oversized_payload = vec![0u8; MSG_SIZE_LIMIT];
+    writer
+        .node()
+        .send_direct_message(
+            reader.node().listening_addr().unwrap(),
+            common::prefix_with_len(2, &oversized_payload),
+        )
+        .await
+        .unwrap();
+
+    // the error that would otherwise only be logged was recorded
+    wait_until!(1, !reader.node().failures().is_empty());
+    let reader_node = reader.node().clone();
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+        move || reader_node.assert_healthy()
+    ))
+    .is_err());
+}
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn injected_inbound_messages() {
+    let echo = EchoNode {
+        node: Node::new(None).await.unwrap(),
+        echoed: Default::default(),
+    };
+    // injection requires the Reading protocol
+    let fake_peer: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    assert_eq!(
+        echo.node()
+            .inject_inbound(fake_peer, common::prefix_with_len(2, &[Herp as u8]))
+            .await
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::Unsupported
+    );
+    echo.enable_reading();
+
+    // the replies to a peer that isn't connected are only recorded
+    echo.node().record_outbound();
+    let mut bytes = Vec::new();
+    for message in &[Herp, Derp, Herp] {
+        bytes.extend_from_slice(&common::prefix_with_len(2, &[*message as u8]));
+    }
+    echo.node()
+        .inject_inbound(fake_peer, bytes.into())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        echo.node().take_outbound_log(),
+        vec![
+            (fake_peer, Bytes::from_static(&[Herp as u8])),
+            (fake_peer, Bytes::from_static(&[Derp as u8]))
+        ]
+    );
+    assert!(echo.node().outbound_log().is_empty());
+
+    // the injected bytes must contain complete messages
+    let incomplete = common::prefix_with_len(2, &[Herp as u8, Derp as u8]).slice(..3);
+    assert_eq!(
+        echo.node()
+            .inject_inbound(fake_peer, incomplete)
+            .await
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[derive(Clone)]
+struct PanickyNode(Node);
+
+impl Pea2Pea for PanickyNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for PanickyNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+        if bytes.map(|bytes| &bytes[2..]) == Some(b"boom") {
+            panic!("a buggy decoder");
+        }
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, _source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        if message == "bang" {
+            panic!("a buggy handler");
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn reader_panic_drops_the_connection() {
+    let panicky = PanickyNode(Node::new(None).await.unwrap());
+    panicky.enable_reading();
+    let panicky_addr = panicky.node().listening_addr().unwrap();
+
+    let alice = common::MessagingNode::new("alice").await;
+    alice.enable_writing();
+    let bob = common::MessagingNode::new("bob").await;
+    bob.enable_writing();
+    alice.node().connect(panicky_addr).await.unwrap();
+    wait_until!(1, panicky.node().num_connected() == 1);
+    let alice_addr = panicky.node().connected_addrs()[0];
+    bob.node().connect(panicky_addr).await.unwrap();
+    wait_until!(1, panicky.node().num_connected() == 2);
+
+    // the reader of alice's connection panics, which drops the connection
+    alice
+        .node()
+        .send_direct_message(panicky_addr, Bytes::from_static(b"boom"))
+        .await
+        .unwrap();
+    wait_until!(1, panicky.node().num_connected() == 1);
+    assert!(!panicky.node().connected_addrs().contains(&alice_addr));
+
+    // the rest of the node is unaffected
+    bob.node()
+        .send_direct_message(panicky_addr, Bytes::from_static(b"fine"))
+        .await
+        .unwrap();
+    wait_until!(1, panicky.node().stats().received().0 == 1);
+    let carol = common::MessagingNode::new("carol").await;
+    carol.node().connect(panicky_addr).await.unwrap();
+    wait_until!(1, panicky.node().num_connected() == 2);
+}
+
+#[tokio::test]
+async fn processor_panic_drops_the_connection() {
+    // the connection would otherwise linger without its processing task
+    let config = NodeConfig {
+        closed_inbound_queue_policy: ClosedInboundQueuePolicy::PauseReading,
+        #[cfg(feature = "test-utils")]
+        fail_fast: pea2pea::FailFastMode::Record,
+        ..Default::default()
+    };
+    let panicky = PanickyNode(Node::new(Some(config)).await.unwrap());
+    panicky.enable_reading();
+    let panicky_addr = panicky.node().listening_addr().unwrap();
+
+    let alice = common::MessagingNode::new("alice").await;
+    alice.enable_writing();
+    alice.node().connect(panicky_addr).await.unwrap();
+    wait_until!(1, panicky.node().num_connected() == 1);
+
+    // processing alice's message panics, which drops the connection and counts as a failure
+    alice
+        .node()
+        .send_direct_message(panicky_addr, Bytes::from_static(b"bang"))
+        .await
+        .unwrap();
+    wait_until!(1, panicky.node().num_connected() == 0);
+    #[cfg(feature = "test-utils")]
+    assert!(panicky.node().failures()[0].contains("a buggy handler"));
+
+    // the node keeps accepting connections
+    alice.node().disconnect(panicky_addr);
+    alice.node().connect(panicky_addr).await.unwrap();
+    wait_until!(1, panicky.node().num_connected() == 1);
+}
+
+#[tokio::test]
+async fn connection_handle() {
+    let alice = common::MessagingNode::new("alice").await;
+    alice.enable_reading();
+    alice.enable_writing();
+    let bob = common::MessagingNode::new("bob").await;
+    bob.enable_reading();
+    bob.enable_writing();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    let handle = alice.node().connection_handle(bob_addr).unwrap();
+    assert_eq!(handle.addr(), bob_addr);
+
+    // the tasks keep running while the connection is open
+    assert!(!handle.is_stopped());
+    assert!(timeout(Duration::from_millis(50), handle.stopped())
+        .await
+        .is_err());
+
+    // closing the connection stops all of its tasks
+    timeout(Duration::from_secs(1), handle.clone().join())
+        .await
+        .unwrap();
+    assert!(handle.is_stopped());
+    assert!(!alice.node().is_connected(bob_addr));
+
+    // a stale handle doesn't affect a newer connection
+    alice.node().connect(bob_addr).await.unwrap();
+    assert!(!handle.close());
+    assert!(alice.node().is_connected(bob_addr));
+    assert_ne!(
+        alice.node().connection_handle(bob_addr).unwrap().id(),
+        handle.id()
+    );
+}
+
+#[tokio::test]
+async fn connection_info() {
+    let alice = common::MessagingNode::new("alice").await;
+    let bob = common::MessagingNode::new("bob").await;
+    for node in &[&alice, &bob] {
+        node.enable_reading();
+        node.enable_writing();
+    }
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    let message = Bytes::from_static(b"hello");
+    for _ in 0..2 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message.clone())
+            .await
+            .unwrap();
+    }
+    wait_until!(1, bob.node().stats().received().0 == 2);
+
+    let info = alice.node().connection_info(bob_addr).unwrap();
+    assert!(!info.is_inbound());
+    assert_eq!(info.addr, bob_addr);
+    assert_eq!((info.msgs_sent, info.bytes_sent), (2, 14));
+    assert_eq!(info.msgs_received, 0);
+    assert_eq!(info.outbound_queue_len, 0);
+
+    let info = bob.node().connection_info(alice_addr).unwrap();
+    assert!(info.is_inbound());
+    assert_eq!((info.msgs_received, info.bytes_received), (2, 14));
+    assert!(info.established.elapsed() < Duration::from_secs(1));
+
+    assert!(bob.node().connection_info(bob_addr).is_none());
+}
+
+#[tokio::test]
+async fn growing_read_buffer() {
+    let config = NodeConfig {
+        read_buffer_growth: Some(ReadBufferGrowth {
+            initial_size: 256,
+            shrink_after_reads: 4,
+        }),
+        ..Default::default()
+    };
+    let alice = common::MessagingNode::new("alice").await;
+    let bob = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    alice.enable_writing();
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    // the buffer grows to fit a large message, shrinks back after a few small ones, and grows again
+    let small = Bytes::from_static(b"hello");
+    let large = Bytes::from(vec![1u8; 40_000]);
+    let mut messages = vec![large.clone()];
+    messages.extend(std::iter::repeat_n(small, 5));
+    messages.push(large);
+    for (i, message) in messages.into_iter().enumerate() {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message)
+            .await
+            .unwrap();
+        // separate the reads, so that the small messages aren't coalesced with the large ones
+        wait_until!(1, bob.node().stats().received().0 == i as u64 + 1);
+    }
+    assert_eq!(bob.node().num_connected(), 1);
+    assert_eq!(bob.node().stats().received().1, 2 * 40_002 + 5 * 7);
+}
+
+#[tokio::test]
+async fn upload_rate_limit() {
+    let config = NodeConfig {
+        max_conn_upload_rate: Some(10_000),
+        ..Default::default()
+    };
+    let alice = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    let bob = common::MessagingNode::new("bob").await;
+    alice.enable_writing();
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    // 2 of the 5kB frames fit within the burst, the remaining 3 are paced to 10kB/s
+    let start = Instant::now();
+    let message = Bytes::from(vec![0u8; 4_998]);
+    for _ in 0..5 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message.clone())
+            .await
+            .unwrap();
+    }
+    wait_until!(3, bob.node().stats().received().0 == 5);
+    assert!(start.elapsed() >= Duration::from_millis(900));
+
+    // the rates are measured both node-wide and per connection
+    assert!(alice.node().stats().rates().sent_per_sec > 0.0);
+    assert!(bob.node().stats().rates().received_per_sec > 0.0);
+    let rates = bob.node().connection_info(alice_addr).unwrap().rates;
+    assert!(rates.received_per_sec > 0.0);
+    assert_eq!(rates.sent_per_sec, 0.0);
+}
+
+#[tokio::test]
+async fn connection_modes() {
+    let alice = common::MessagingNode::new("alice").await;
+    let bob = common::MessagingNode::new("bob").await;
+    for node in &[&alice, &bob] {
+        node.enable_reading();
+        node.enable_writing();
+    }
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    // bob mutes alice, but can still send messages to her
+    assert_eq!(
+        bob.node()
+            .set_connection_mode(alice_addr, ConnectionMode::WriteOnly)
+            .unwrap(),
+        ConnectionMode::ReadWrite
+    );
+    let message = Bytes::from_static(b"hello");
+    alice
+        .node()
+        .send_direct_message(bob_addr, message.clone())
+        .await
+        .unwrap();
+    bob.node()
+        .send_direct_message(alice_addr, message.clone())
+        .await
+        .unwrap();
+    wait_until!(1, alice.node().stats().received().0 == 1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(bob.node().stats().received().0, 0);
+
+    // the muted messages are read once the mode allows it
+    bob.node()
+        .set_connection_mode(alice_addr, ConnectionMode::ReadWrite)
+        .unwrap();
+    wait_until!(1, bob.node().stats().received().0 == 1);
+
+    // a read-only connection doesn't accept outbound messages
+    alice
+        .node()
+        .set_connection_mode(bob_addr, ConnectionMode::ReadOnly)
+        .unwrap();
+    let err = alice
+        .node()
+        .send_direct_message(bob_addr, message)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(
+        alice.node().connection_info(bob_addr).unwrap().mode,
+        ConnectionMode::ReadOnly
+    );
+    assert!(alice
+        .node()
+        .set_connection_mode(alice_addr, ConnectionMode::ReadOnly)
+        .is_err());
+}
+
+#[tokio::test]
+async fn paused_reading() {
+    let alice = common::MessagingNode::new("alice").await;
+    let bob = common::MessagingNode::new("bob").await;
+    for node in &[&alice, &bob] {
+        node.enable_reading();
+        node.enable_writing();
+    }
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    assert!(bob.node().pause_reading(alice_addr).unwrap());
+    assert!(!bob.node().pause_reading(alice_addr).unwrap());
+    assert_eq!(
+        bob.node().connection_info(alice_addr).unwrap().mode,
+        ConnectionMode::WriteOnly
+    );
+
+    // a read that was already in progress can be completed, but no further reads are performed
+    let message = Bytes::from_static(b"hello");
+    for _ in 0..4 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message.clone())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(alice.node().stats().sent().0, 4);
+    assert!(bob.node().stats().received().0 <= 1);
+
+    // writing is unaffected
+    bob.node()
+        .send_direct_message(alice_addr, message)
+        .await
+        .unwrap();
+    wait_until!(1, alice.node().stats().received().0 == 1);
+
+    // the pending bytes are read once reading is resumed
+    assert!(bob.node().resume_reading(alice_addr).unwrap());
+    wait_until!(1, bob.node().stats().received().0 == 4);
+    assert!(!bob.node().resume_reading(alice_addr).unwrap());
+    assert!(bob.node().pause_reading(bob_addr).is_err());
+
+    // pausing reading from a read-only connection suspends it, and resuming restores the mode
+    bob.node()
+        .set_connection_mode(alice_addr, ConnectionMode::ReadOnly)
+        .unwrap();
+    assert!(bob.node().pause_reading(alice_addr).unwrap());
+    assert_eq!(
+        bob.node().connection_info(alice_addr).unwrap().mode,
+        ConnectionMode::Suspended
+    );
+    assert!(bob.node().resume_reading(alice_addr).unwrap());
+    assert_eq!(
+        bob.node().connection_info(alice_addr).unwrap().mode,
+        ConnectionMode::ReadOnly
+    );
+}
+
+#[tokio::test]
+async fn typed_messages() {
+    let sender = EchoNode {
+        node: Node::new(None).await.unwrap(),
+        echoed: Default::default(),
+    };
+    sender.enable_typed_writing();
+    let receiver = EchoNode {
+        node: Node::new(None).await.unwrap(),
+        echoed: Default::default(),
+    };
+    receiver.enable_reading();
+    receiver.enable_writing();
+
+    let receiver_addr = receiver.node().listening_addr().unwrap();
+    sender.node().connect(receiver_addr).await.unwrap();
+    wait_until!(1, receiver.node().num_connected() == 1);
+
+    for message in &[Herp, Derp] {
+        sender.send_typed(receiver_addr, *message).await.unwrap();
+    }
+    wait_until!(1, receiver.echoed.lock().len() == 2);
+
+    // the serializer can also be found by the node alone
+    receiver.echoed.lock().clear();
+    sender
+        .node()
+        .send_typed_message(receiver_addr, Derp)
+        .await
+        .unwrap();
+    wait_until!(1, receiver.echoed.lock().contains(&Derp));
+
+    // only the registered message types are accepted
+    let err = sender
+        .node()
+        .send_typed_message(receiver_addr, 0u8)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // the Writing protocol needs to be enabled
+    let sender_addr = receiver.node().connected_addrs()[0];
+    let unwritable = EchoNode {
+        node: Node::new(None).await.unwrap(),
+        echoed: Default::default(),
+    };
+    assert!(unwritable.send_typed(sender_addr, Herp).await.is_err());
+
+    // and so does the TypedWriting protocol, for the node to find the serializer
+    let err = unwritable
+        .node()
+        .send_typed_message(sender_addr, Herp)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}
+
+#[tokio::test]
+async fn connection_timings() {
+    let alice = common::MessagingNode::new("alice").await;
+    let bob = common::MessagingNode::new("bob").await;
+    for node in &[&alice, &bob] {
+        node.enable_reading();
+        node.enable_writing();
+    }
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    // the TCP connection time is only known to the initiator
+    let timings = alice.node().connection_info(bob_addr).unwrap().timings;
+    assert!(timings.connect.is_some());
+    assert!(timings.first_message.is_none());
+    let timings = bob.node().connection_info(alice_addr).unwrap().timings;
+    assert!(timings.connect.is_none());
+
+    alice
+        .node()
+        .send_direct_message(bob_addr, Bytes::from_static(b"hello"))
+        .await
+        .unwrap();
+    wait_until!(1, bob.node().stats().received().0 == 1);
+
+    let timings = bob.node().connection_info(alice_addr).unwrap().timings;
+    let first_message = timings.first_message.unwrap();
+
+    let stats = alice.node().connection_timing_stats();
+    assert_eq!(stats.connect.unwrap().samples, 1);
+    assert_eq!(stats.handshake.unwrap().samples, 1);
+    assert!(stats.first_message.is_none());
+
+    let stats = bob.node().peer_connection_timing_stats(alice_addr).unwrap();
+    assert!(stats.connect.is_none());
+    assert_eq!(stats.handshake.unwrap().p50, timings.handshake);
+    let first_message_stats = stats.first_message.unwrap();
+    assert_eq!(first_message_stats.samples, 1);
+    assert_eq!(first_message_stats.p99, first_message);
+    assert_eq!(bob.node().connection_timing_stats(), stats);
+    assert!(bob.node().peer_connection_timing_stats(bob_addr).is_none());
+
+    // the timings of an inbound peer are forgotten along with its ephemeral address, but not the aggregate ones
+    assert!(bob.node().disconnect(alice_addr));
+    assert!(bob
+        .node()
+        .peer_connection_timing_stats(alice_addr)
+        .is_none());
+    assert_eq!(bob.node().connection_timing_stats(), stats);
+}
+
+#[tokio::test]
+async fn small_messages() {
+    let alice = common::MessagingNode::new("alice").await;
+    let bob = common::MessagingNode::new("bob").await;
+    alice.enable_writing();
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    // inline payloads don't make the queued messages larger
+    assert!(std::mem::size_of::<Payload>() <= std::mem::size_of::<Bytes>() + 8);
+
+    // the small message is stored inline, while the large one isn't
+    assert!(Payload::from(&[0u8; MAX_INLINE_PAYLOAD_LEN][..]).is_inline());
+    assert!(!Payload::from(&[0u8; MAX_INLINE_PAYLOAD_LEN + 1][..]).is_inline());
+
+    alice
+        .node()
+        .send_small_message(bob_addr, b"tiny")
+        .await
+        .unwrap();
+    alice
+        .node()
+        .send_small_message(bob_addr, &[1u8; MAX_INLINE_PAYLOAD_LEN + 1])
+        .await
+        .unwrap();
+
+    wait_until!(1, bob.node().stats().received().0 == 2);
+    assert_eq!(
+        bob.node().stats().received().1 as usize,
+        2 + 4 + 2 + MAX_INLINE_PAYLOAD_LEN + 1
+    );
+}
+
+#[derive(Clone)]
+struct PanickingNode(Node);
+
+impl Pea2Pea for PanickingNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for PanickingNode {
+    type Message = ();
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<((), usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| ((), bytes.len())))
+    }
+
+    // the fair processing task is shared by all the connections, so it isn't dropped along with any one of them
+    fn ordering_group(&self, _source: SocketAddr, _message: &()) -> Option<u64> {
+        panic!("the message processing task died");
+    }
+
+    async fn process_message(&self, _source: SocketAddr, _message: ()) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+async fn kill_processing_task(policy: ClosedInboundQueuePolicy) -> PanickingNode {
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+
+    let config = NodeConfig {
+        name: Some("reader".into()),
+        closed_inbound_queue_policy: policy,
+        message_processing_mode: ProcessingMode::Fair { max_in_flight: 1 },
+        ..Default::default()
+    };
+    let reader = PanickingNode(Node::new(Some(config)).await.unwrap());
+    reader.enable_reading();
+    let mut events = reader.node().subscribe();
+
+    let reader_addr = reader.node().listening_addr().unwrap();
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+    let writer_addr = reader.node().connected_addrs()[0];
+
+    // the first message kills the processing task, closing the inbound queue
+    let message = Bytes::from_static(b"hello");
+    writer
+        .node()
+        .send_direct_message(reader_addr, message.clone())
+        .await
+        .unwrap();
+    wait_until!(1, reader.node().stats().received().0 == 1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    for _ in 0..2 {
+        writer
+            .node()
+            .send_direct_message(reader_addr, message.clone())
+            .await
+            .unwrap();
+    }
+    wait_until!(1, reader.node().stats().received().0 >= 2);
+
+    let completed = tokio::time::timeout(Duration::from_secs(1), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(completed, NodeEvent::HandshakeCompleted(addr, _) if addr == writer_addr));
+    // both events concern the same connection
+    let closed = tokio::time::timeout(Duration::from_secs(1), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        closed,
+        NodeEvent::InboundQueueClosed(writer_addr, completed.conn_id())
+    );
+
+    reader
+}
+
+#[tokio::test]
+async fn closed_inbound_queue_disconnect() {
+    let reader = kill_processing_task(ClosedInboundQueuePolicy::Disconnect).await;
+    wait_until!(1, reader.node().num_connected() == 0);
+}
+
+#[tokio::test]
+async fn closed_inbound_queue_drop_messages() {
+    let reader = kill_processing_task(ClosedInboundQueuePolicy::DropMessages).await;
+
+    wait_until!(1, reader.node().stats().dropped() == 2);
+    assert_eq!(reader.node().num_connected(), 1);
+}
+
+#[tokio::test]
+async fn graceful_disconnect() {
+    let alice = common::MessagingNode::new("alice").await;
+    alice.enable_writing();
+    let bob = common::MessagingNode::new("bob").await;
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    let message = Bytes::from_static(b"hello");
+    for _ in 0..3 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message.clone())
+            .await
+            .unwrap();
+    }
+    alice
+        .node()
+        .disconnect_after(
+            bob_addr,
+            Duration::from_secs(10),
+            Bytes::from_static(b"bye"),
+        )
+        .await
+        .unwrap();
+
+    // no further messages can be queued
+    let err = alice
+        .node()
+        .send_direct_message(bob_addr, message)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    let err = alice
+        .node()
+        .disconnect_after(bob_addr, Duration::from_secs(10), Bytes::new())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+
+    // the connection is closed as soon as the queue is drained, before the delay elapses
+    wait_until!(1, alice.node().num_connected() == 0);
+    wait_until!(1, bob.node().stats().received().0 == 4);
+    assert_eq!(
+        bob.node().stats().received().1,
+        3 * (2 + 5) + 2 + b"bye".len() as u64
+    );
+}
+
+#[tokio::test]
+async fn drain_before_disconnect() {
+    let alice = common::MessagingNode::new("alice").await;
+    alice.enable_reading();
+    alice.enable_writing();
+    let bob = common::MessagingNode::new("bob").await;
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    let message = Bytes::from_static(b"hello");
+    for _ in 0..3 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message.clone())
+            .await
+            .unwrap();
+    }
+
+    // the queued messages are written before the method returns and the connection is closed
+    alice
+        .node()
+        .disconnect_gracefully(bob_addr, Duration::from_secs(10), None)
+        .await
+        .unwrap();
+    assert_eq!(alice.node().num_connected(), 0);
+
+    // the marker closing the queue isn't written
+    wait_until!(1, bob.node().stats().received().0 == 3);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(bob.node().stats().received(), (3, 3 * (2 + 5)));
+
+    let err = alice
+        .node()
+        .disconnect_gracefully(bob_addr, Duration::from_secs(10), None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+}
+
+#[derive(Clone, Default)]
+struct GroupingNode {
+    node: Option<Node>,
+    in_flight: Arc<[AtomicUsize; 2]>,
+    max_in_flight_per_group: Arc<[AtomicUsize; 2]>,
+    max_in_flight: Arc<AtomicUsize>,
+    processed: Arc<Mutex<Vec<[u8; 3]>>>,
+}
+
+impl Pea2Pea for GroupingNode {
+    fn node(&self) -> &Node {
+        self.node.as_ref().unwrap()
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for GroupingNode {
+    // [ordering group, writer ID, sequence number]
+    type Message = [u8; 3];
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<([u8; 3], usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| ([bytes[2], bytes[3], bytes[4]], bytes.len())))
+    }
+
+    fn ordering_group(&self, _source: SocketAddr, message: &[u8; 3]) -> Option<u64> {
+        Some(message[0] as u64)
+    }
+
+    async fn process_message(&self, _source: SocketAddr, message: [u8; 3]) -> io::Result<()> {
+        let group = message[0] as usize;
+        let in_group = self.in_flight[group].fetch_add(1, SeqCst) + 1;
+        self.max_in_flight_per_group[group].fetch_max(in_group, SeqCst);
+        let in_flight = self.in_flight.iter().map(|n| n.load(SeqCst)).sum();
+        self.max_in_flight.fetch_max(in_flight, SeqCst);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        self.processed.lock().push(message);
+        self.in_flight[group].fetch_sub(1, SeqCst);
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn ordering_groups() {
+    const NUM_MESSAGES: u8 = 8;
+
+    let reader = GroupingNode {
+        node: Some(Node::new(None).await.unwrap()),
+        ..Default::default()
+    };
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    let mut writers = Vec::new();
+    for _ in 0..2 {
+        let writer = common::MessagingNode::new("writer").await;
+        writer.enable_writing();
+        writer.node().connect(reader_addr).await.unwrap();
+        writers.push(writer);
+    }
+    wait_until!(1, reader.node().num_connected() == 2);
+
+    for seq in 0..NUM_MESSAGES {
+        for (writer_id, writer) in writers.iter().enumerate() {
+            for group in 0..2 {
+                writer
+                    .node()
+                    .send_direct_message(
+                        reader_addr,
+                        Bytes::copy_from_slice(&[group, writer_id as u8, seq]),
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    wait_until!(
+        3,
+        reader.processed.lock().len() == 2 * 2 * NUM_MESSAGES as usize
+    );
+
+    // the messages within a group are processed one at a time, but the groups are processed in parallel
+    for max in reader.max_in_flight_per_group.iter() {
+        assert_eq!(max.load(SeqCst), 1);
+    }
+    assert_eq!(reader.max_in_flight.load(SeqCst), 2);
+
+    // the order of the messages from each of the peers is retained within the groups
+    let processed = reader.processed.lock();
+    for group in 0..2 {
+        for writer_id in 0..2 {
+            let seqs = processed
+                .iter()
+                .filter(|msg| msg[0] == group && msg[1] == writer_id)
+                .map(|msg| msg[2])
+                .collect::<Vec<_>>();
+            assert_eq!(seqs, (0..NUM_MESSAGES).collect::<Vec<_>>());
+        }
+    }
+}
+
+#[derive(Clone)]
+struct BusyLaneNode {
+    node: Node,
+    processing: Arc<AtomicUsize>,
+}
+
+impl Pea2Pea for BusyLaneNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+// decrements the counter of the messages being processed once the processing stops
+struct ProcessingGuard(Arc<AtomicUsize>);
+
+impl Drop for ProcessingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, SeqCst);
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for BusyLaneNode {
+    type Message = ();
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<((), usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| ((), bytes.len())))
+    }
+
+    fn ordering_group(&self, _source: SocketAddr, _message: &()) -> Option<u64> {
+        Some(0)
+    }
+
+    async fn process_message(&self, _source: SocketAddr, _message: ()) -> io::Result<()> {
+        self.processing.fetch_add(1, SeqCst);
+        let _guard = ProcessingGuard(self.processing.clone());
+
+        // the processing never completes on its own
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn ordering_lanes_stop_on_shutdown() {
+    let reader = BusyLaneNode {
+        node: Node::new(None).await.unwrap(),
+        processing: Default::default(),
+    };
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+    writer.node().connect(reader_addr).await.unwrap();
+    writer
+        .node()
+        .send_direct_message(reader_addr, Bytes::from_static(b"stuck"))
+        .await
+        .unwrap();
+    wait_until!(1, reader.processing.load(SeqCst) == 1);
+
+    // the lane processing the message is aborted along with the node's other tasks
+    reader.node().shut_down();
+    wait_until!(1, reader.processing.load(SeqCst) == 0);
+}
+
+#[derive(Clone)]
+struct StuckLaneNode {
+    node: Node,
+    processed: Arc<AtomicUsize>,
+}
+
+impl Pea2Pea for StuckLaneNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for StuckLaneNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Bytes, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    fn ordering_group(&self, _source: SocketAddr, message: &Bytes) -> Option<u64> {
+        (message == "stuck").then_some(0)
+    }
+
+    async fn process_message(&self, _source: SocketAddr, message: Bytes) -> io::Result<()> {
+        if message == "stuck" {
+            // the processing never completes on its own
+            std::future::pending::<()>().await;
+        }
+        self.processed.fetch_add(1, SeqCst);
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn full_ordering_lane_doesnt_stall_fair_processing() {
+    let config = NodeConfig {
+        message_processing_mode: ProcessingMode::Fair { max_in_flight: 1 },
+        num_ordering_lanes: 1,
+        conn_inbound_queue_depth: 2,
+        ..Default::default()
+    };
+    let reader = StuckLaneNode {
+        node: Node::new(Some(config)).await.unwrap(),
+        processed: Default::default(),
+    };
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    let stuck_writer = common::MessagingNode::new("stuck_writer").await;
+    let writer = common::MessagingNode::new("writer").await;
+    for node in [&stuck_writer, &writer] {
+        node.enable_writing();
+        node.node().connect(reader_addr).await.unwrap();
+    }
+
+    // the lane gets stuck processing the first message, and then fills up
+    for _ in 0..5 {
+        stuck_writer
+            .node()
+            .send_direct_message(reader_addr, Bytes::from_static(b"stuck"))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // the other connection's messages are still processed
+    for _ in 0..5 {
+        writer
+            .node()
+            .send_direct_message(reader_addr, Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+    }
+    wait_until!(1, reader.processed.load(SeqCst) == 5);
+}
+
+#[tokio::test]
+async fn frame_sampling() {
+    let sampling = FrameSamplingConfig {
+        prefix_len: 3,
+        sample_interval: 2,
+        max_samples_per_sec: 2,
+        ..Default::default()
+    };
+    let config = NodeConfig {
+        frame_sampling: Some(FrameSamplingConfig {
+            redact: false,
+            ..sampling.clone()
+        }),
+        ..Default::default()
+    };
+    let alice = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    alice.enable_writing();
+    let config = NodeConfig {
+        frame_sampling: Some(sampling),
+        ..Default::default()
+    };
+    let bob = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    bob.enable_reading();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    for _ in 0..10 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+    }
+    wait_until!(1, bob.node().stats().received().0 == 10);
+
+    // every other frame is a candidate, but the rate limit only allows 2 of them
+    let samples = alice.node().diagnostics_dump().frame_samples;
+    assert_eq!(samples.len(), 2);
+    for sample in samples {
+        assert_eq!(sample.addr, bob_addr);
+        assert_eq!(sample.direction, FrameDirection::Outbound);
+        assert_eq!(sample.len, 2 + 5);
+        assert_eq!(sample.prefix.as_deref(), Some(&[5, 0, b'h'][..]));
+    }
+
+    // the samples are redacted by default
+    let samples = bob.node().diagnostics_dump().frame_samples;
+    assert_eq!(samples.len(), 2);
+    for sample in samples {
+        assert_eq!(sample.direction, FrameDirection::Inbound);
+        assert_eq!(sample.len, 2 + 5);
+        assert!(sample.prefix.is_none());
+    }
+
+    // no samples are recorded by default
+    assert!(common::MessagingNode::new("carol")
+        .await
+        .node()
+        .diagnostics_dump()
+        .frame_samples
+        .is_empty());
+}
+
+#[tokio::test]
+async fn traffic_capture() {
+    let alice = common::MessagingNode::new("alice").await;
+    alice.enable_reading();
+    alice.enable_writing();
+    let bob = common::MessagingNode::new("bob").await;
+    bob.enable_reading();
+    bob.enable_writing();
+
+    // alice records her traffic to a file, while bob uses a custom tap
+    let path = std::env::temp_dir().join(format!("pea2pea-capture-{}", std::process::id()));
+    let capture = CaptureWriter::create(&path).unwrap();
+    capture.attach(alice.node());
+    let tapped = Arc::new(Mutex::new(Vec::new()));
+    let tapped_clone = tapped.clone();
+    bob.node().set_inbound_tap(move |addr, _, frame| {
+        tapped_clone.lock().push((addr, frame.to_vec()));
+    });
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    alice
+        .node()
+        .send_direct_message(bob_addr, Bytes::from_static(b"herp"))
+        .await
+        .unwrap();
+    bob.node()
+        .send_direct_message(alice_addr, Bytes::from_static(b"derp"))
+        .await
+        .unwrap();
+    wait_until!(1, alice.node().stats().received().0 == 1);
+
+    assert_eq!(
+        *tapped.lock(),
+        vec![(alice_addr, common::prefix_with_len(2, b"herp").to_vec())]
+    );
+
+    capture.flush().unwrap();
+    let frames = read_capture(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].direction, FrameDirection::Outbound);
+    assert_eq!(frames[0].data, common::prefix_with_len(2, b"herp"));
+    assert_eq!(frames[1].direction, FrameDirection::Inbound);
+    assert_eq!(frames[1].data, common::prefix_with_len(2, b"derp"));
+    for frame in &frames {
+        assert_eq!(frame.addr, bob_addr);
+    }
+    assert!(frames[0].timestamp <= frames[1].timestamp);
+}
+
+#[test]
+fn capture_with_a_bogus_frame_length() {
+    let path = std::env::temp_dir().join(format!("pea2pea-bogus-capture-{}", std::process::id()));
+    let mut bytes = b"P2PCAP".to_vec();
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&[0, 4, 127, 0, 0, 1]);
+    bytes.extend_from_slice(&1234u16.to_le_bytes());
+    // the frame claims to be 4GiB long, but it's only 4B long
+    bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+    bytes.extend_from_slice(b"herp");
+    std::fs::write(&path, bytes).unwrap();
+
+    let err = read_capture(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn stalled_writer_watchdog() {
+    // a peer that accepts the connection, but never reads from it
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (_stream, _) = listener.accept().await.unwrap();
+        std::future::pending::<()>().await;
+    });
+
+    let config = NodeConfig {
+        writer_stall_timeout_ms: Some(200),
+        writer_stall_action: WriterStallAction::Disconnect,
+        tcp_send_buffer_size: Some(4 * 1024),
+        ..Default::default()
+    };
+    let writer = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    writer.enable_writing();
+    let mut events = writer.node().subscribe();
+
+    writer.node().connect(peer_addr).await.unwrap();
+
+    // keep the writer busy until the socket buffers fill up and it stalls
+    let node = writer.node().clone();
+    tokio::spawn(async move {
+        let message = Bytes::from(vec![0u8; 32 * 1024]);
+        while node
+            .send_direct_message(peer_addr, message.clone())
+            .await
+            .is_ok()
+        {}
+    });
+
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if matches!(event, NodeEvent::WriterStalled(addr, _) if addr == peer_addr) {
+            break;
+        }
+    }
+    wait_until!(1, writer.node().num_connected() == 0);
+}
+
+#[tokio::test]
+async fn stalled_writer_watchdog_single_write() {
+    // a peer that accepts the connection, but never reads from it
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (_stream, _) = listener.accept().await.unwrap();
+        std::future::pending::<()>().await;
+    });
+
+    let config = NodeConfig {
+        writer_stall_timeout_ms: Some(200),
+        writer_stall_action: WriterStallAction::EmitEvent,
+        tcp_send_buffer_size: Some(4 * 1024),
+        conn_write_buffer_size: 17 * 1024 * 1024,
+        ..Default::default()
+    };
+    let writer = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    writer.enable_writing();
+    let mut events = writer.node().subscribe();
+
+    writer.node().connect(peer_addr).await.unwrap();
+
+    // a single message that can't fit in the socket buffers; the writer's queue is empty while it's being written
+    writer
+        .node()
+        .send_direct_message(peer_addr, Bytes::from(vec![0u8; 16 * 1024 * 1024]))
+        .await
+        .unwrap();
+
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if matches!(event, NodeEvent::WriterStalled(addr, _) if addr == peer_addr) {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn slow_peer_eviction() {
+    // the writer is throttled, so its queue can't be drained as fast as it's filled
+    let config = NodeConfig {
+        slow_peer_detection: Some(SlowPeerDetection {
+            high_water_mark: 4,
+            max_duration_ms: 200,
+            disconnect: true,
+        }),
+        max_conn_upload_rate: Some(10_000),
+        ..Default::default()
+    };
+    let alice = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    let bob = common::MessagingNode::new("bob").await;
+    alice.enable_writing();
+    bob.enable_reading();
+    let mut events = alice.node().subscribe();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+
+    let message = Bytes::from(vec![0u8; 4_998]);
+    for _ in 0..10 {
+        alice
+            .node()
+            .send_direct_message(bob_addr, message.clone())
+            .await
+            .unwrap();
+    }
+    let info = alice.node().connection_info(bob_addr).unwrap();
+    assert_eq!(info.outbound_queue_capacity, 16);
+    assert!(info.outbound_queue_occupancy() > 0.25);
+
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if matches!(event, NodeEvent::SlowPeer(addr, _) if addr == bob_addr) {
+            break;
+        }
+    }
+    wait_until!(1, alice.node().num_connected() == 0);
+}
@@ -0,0 +1,224 @@
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::{sync::Semaphore, time::timeout};
+
+mod common;
+use pea2pea::{
+    protocols::{Multiplexing, Reading, Writing},
+    ConnectionContext, Node, Pea2Pea,
+};
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+#[derive(Clone)]
+struct MuxNode(Node);
+
+impl Pea2Pea for MuxNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+const CHANNEL_TAG: u8 = 1;
+
+#[async_trait::async_trait]
+impl Reading for MuxNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        if message[0] == CHANNEL_TAG {
+            self.node().handle_channel_message(source, &message[1..])
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Writing for MuxNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl Multiplexing for MuxNode {
+    fn channel_message(&self, payload: Bytes) -> Bytes {
+        let mut message = vec![CHANNEL_TAG];
+        message.extend_from_slice(&payload);
+        message.into()
+    }
+}
+
+// a reader of the sync channel that only processes messages once it's allowed to
+#[derive(Clone)]
+struct SyncReader {
+    node: Node,
+    permits: Arc<Semaphore>,
+    processed: Arc<Mutex<Vec<Bytes>>>,
+}
+
+impl Pea2Pea for SyncReader {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for SyncReader {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        Ok(Some((Bytes::copy_from_slice(buffer), buffer.len())))
+    }
+
+    async fn process_message(&self, _source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        self.permits.acquire().await.unwrap().forget();
+        self.processed.lock().push(message);
+        Ok(())
+    }
+}
+
+const GOSSIP: u16 = 0;
+const SYNC: u16 = 1;
+
+#[tokio::test]
+async fn multiplexed_channels() {
+    let nodes = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(MuxNode)
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_reading();
+        node.enable_writing();
+        node.enable_multiplexing();
+    }
+    let (alice, bob) = (&nodes[0], &nodes[1]);
+
+    let mut gossip = bob.node().register_channel(GOSSIP).unwrap();
+    assert!(bob.node().register_channel(GOSSIP).is_err());
+    let sync_reader = SyncReader {
+        node: bob.node().clone(),
+        permits: Arc::new(Semaphore::new(0)),
+        processed: Default::default(),
+    };
+    bob.register_channel_reader(SYNC, sync_reader.clone())
+        .unwrap();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+    let alice_addr = bob.node().connected_addrs()[0];
+
+    // the sync channel is stalled, but it doesn't hold up the gossip
+    for i in 0..3u8 {
+        alice
+            .node()
+            .send_on_channel(bob_addr, SYNC, Bytes::from(vec![i; 1000]))
+            .await
+            .unwrap();
+    }
+    alice
+        .node()
+        .send_on_channel(bob_addr, GOSSIP, Bytes::from_static(b"gossip"))
+        .await
+        .unwrap();
+
+    let (source, message) = timeout(Duration::from_secs(1), gossip.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((source, &message[..]), (alice_addr, &b"gossip"[..]));
+    assert!(sync_reader.processed.lock().is_empty());
+
+    // once unstalled, the sync channel processes its messages in order
+    sync_reader.permits.add_permits(3);
+    wait_until!(1, sync_reader.processed.lock().len() == 3);
+    for (i, message) in sync_reader.processed.lock().iter().enumerate() {
+        assert_eq!(message[..], vec![i as u8; 1000][..]);
+    }
+
+    // messages on channels without a reader are dropped
+    assert!(bob.node().unregister_channel(GOSSIP));
+    alice
+        .node()
+        .send_on_channel(bob_addr, GOSSIP, Bytes::from_static(b"anyone?"))
+        .await
+        .unwrap();
+    wait_until!(1, bob.node().stats().dropped() == 1);
+}
+
+#[tokio::test]
+async fn multiplexed_channels_send_independently() {
+    let nodes = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(MuxNode)
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_reading();
+        node.enable_writing();
+        node.enable_multiplexing();
+    }
+    let (alice, bob) = (&nodes[0], &nodes[1]);
+    let mut gossip = bob.node().register_channel(GOSSIP).unwrap();
+
+    // a peer that never reads anything
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stalled_addr = listener.local_addr().unwrap();
+    alice.node().connect(stalled_addr).await.unwrap();
+    let (_stalled_stream, _) = listener.accept().await.unwrap();
+
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    // the sends on the sync channel get stuck once the stalled peer's buffers are full
+    let alice_clone = alice.clone();
+    tokio::spawn(async move {
+        for _ in 0..1000 {
+            let message = Bytes::from(vec![0; 60_000]);
+            if alice_clone
+                .node()
+                .send_on_channel(stalled_addr, SYNC, message)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // but the gossip channel is unaffected
+    alice
+        .node()
+        .send_on_channel(bob_addr, GOSSIP, Bytes::from_static(b"gossip"))
+        .await
+        .unwrap();
+    let (_, message) = timeout(Duration::from_secs(1), gossip.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&message[..], b"gossip");
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "nat")]
+
+use tokio::{net::UdpSocket, sync::mpsc};
+
+mod common;
+use pea2pea::{Node, NodeConfig};
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+const EXTERNAL_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+
+// a minimal NAT-PMP server that grants all the mappings for up to 2s; it reports the requested lifetimes
+async fn start_gateway() -> (SocketAddr, mpsc::UnboundedReceiver<u32>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let (lifetime_sender, lifetime_receiver) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut request = [0u8; 12];
+        loop {
+            let (len, source) = socket.recv_from(&mut request).await.unwrap();
+
+            let mut response = vec![0, 128 + request[1], 0, 0, 0, 0, 0, 1];
+            match request[1] {
+                0 if len == 2 => response.extend_from_slice(&EXTERNAL_IP.octets()),
+                2 if len == 12 => {
+                    let lifetime =
+                        u32::from_be_bytes([request[8], request[9], request[10], request[11]]);
+                    let _ = lifetime_sender.send(lifetime);
+                    // the internal port and the external one, which is offset
+                    response.extend_from_slice(&request[4..6]);
+                    let port = u16::from_be_bytes([request[4], request[5]]);
+                    response.extend_from_slice(&port.wrapping_add(1).to_be_bytes());
+                    response.extend_from_slice(&lifetime.min(2).to_be_bytes());
+                }
+                _ => continue,
+            }
+
+            socket.send_to(&response, source).await.unwrap();
+        }
+    });
+
+    (addr, lifetime_receiver)
+}
+
+#[tokio::test]
+async fn nat_port_mapping() {
+    let (gateway_addr, mut lifetimes) = start_gateway().await;
+
+    let config = NodeConfig {
+        nat_port_mapping: true,
+        nat_gateway: Some(gateway_addr),
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let port = node.listening_addr().unwrap().port();
+
+    let expected_addr = SocketAddr::new(EXTERNAL_IP.into(), port.wrapping_add(1));
+    wait_until!(1, node.external_addr() == Some(expected_addr));
+
+    // the initial mapping and its renewal
+    assert_eq!(lifetimes.recv().await, Some(3_600));
+    assert_eq!(lifetimes.recv().await, Some(3_600));
+
+    // the mapping is removed once the node shuts down
+    node.shut_down();
+    assert!(node.external_addr().is_none());
+    assert_eq!(lifetimes.recv().await, Some(0));
+}
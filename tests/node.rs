@@ -1,22 +1,38 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use socket2::SockRef;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    sync::broadcast,
+    time::{sleep, timeout},
+};
+use tracing::{
+    span::{Attributes, Id, Record},
+    subscriber::NoSubscriber,
+    Dispatch, Event, Metadata, Subscriber,
 };
 
 mod common;
 use pea2pea::{
     connect_nodes,
     protocols::{Handshaking, Reading, Writing},
-    Connection, Node, NodeConfig, Pea2Pea, Topology,
+    wait_for, wait_for_all_events, wait_for_event, wait_for_events, AddrFamilyPolicy,
+    ConnectOptions, Connection, ConnectionContext, DialFailure, DnsAnswer, DnsCacheStats,
+    EventCondition, KnownPeers, NetworkEvent, Node, NodeConfig, NodeEvent, PartitionDetection,
+    PartitionSignal, Pea2Pea, Resolver, RetryPolicy, ServingFairness, SlowPeerDetection,
+    SubnetLimits, Topology,
 };
 
 use std::{
     io,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 #[tokio::test]
@@ -24,14 +40,69 @@ async fn node_creation_any_port_works() {
     let _node = Node::new(None).await.unwrap();
 }
 
-#[should_panic]
 #[tokio::test]
-async fn node_creation_bad_params_panic() {
+async fn node_creation_bad_params_fail() {
     let config = NodeConfig {
         allow_random_port: false,
         ..Default::default()
     };
-    let _node = Node::new(Some(config)).await.unwrap();
+    let err = Node::new(Some(config)).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn node_config_builder() {
+    let config = NodeConfig::builder()
+        .name("built")
+        .max_connections(10u16)
+        .conn_read_buffer_size(1024usize)
+        .build()
+        .unwrap();
+    assert_eq!(config.name.as_deref(), Some("built"));
+    assert_eq!(config.max_connections, 10);
+
+    let node = Node::new(Some(config)).await.unwrap();
+    assert_eq!(node.name(), "built");
+
+    // invalid combinations are rejected with a description of the issue
+    let err = NodeConfig::builder()
+        .allow_random_port(false)
+        .build()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("port"));
+    assert!(NodeConfig::builder()
+        .keepalive_interval_ms(1u64)
+        .build()
+        .is_err());
+    assert!(NodeConfig::builder()
+        .conn_outbound_queue_depth(0usize)
+        .build()
+        .is_err());
+    assert!(NodeConfig::builder()
+        .max_concurrent_handshakes(0u16)
+        .build()
+        .is_err());
+    assert!(NodeConfig::builder()
+        .retransmit_buffer_len(0usize)
+        .build()
+        .is_err());
+    // a full queue can't be above the high-water mark
+    assert!(NodeConfig::builder()
+        .conn_outbound_queue_depth(16usize)
+        .slow_peer_detection(SlowPeerDetection {
+            high_water_mark: 16,
+            ..Default::default()
+        })
+        .build()
+        .is_err());
+    #[cfg(feature = "nat")]
+    assert!(NodeConfig::builder().nat_lease_secs(0u32).build().is_err());
+    assert!(NodeConfig::builder()
+        .desired_listening_port(0u16)
+        .allow_random_port(false)
+        .build()
+        .is_ok());
 }
 
 #[tokio::test]
@@ -44,20 +115,444 @@ async fn node_creation_used_port_fails() {
     assert!(Node::new(Some(config)).await.is_err());
 }
 
+#[tokio::test]
+async fn node_listening_port_range() {
+    let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = occupied.local_addr().unwrap().port();
+    let config = NodeConfig {
+        listener_ip: "127.0.0.1".parse().unwrap(),
+        listening_port_range: Some(port..=port),
+        allow_random_port: false,
+        ..Default::default()
+    };
+
+    // the only port in the range is unavailable
+    assert!(Node::new(Some(config.clone())).await.is_err());
+
+    // it's possible to fall back to a random port
+    let node = Node::new(Some(NodeConfig {
+        allow_random_port: true,
+        ..config.clone()
+    }))
+    .await
+    .unwrap();
+    assert_ne!(node.listening_addr().unwrap().port(), port);
+
+    // the port in the range is used once it's available
+    drop(occupied);
+    let node = Node::new(Some(config)).await.unwrap();
+    assert_eq!(node.listening_addr().unwrap().port(), port);
+
+    #[allow(clippy::reversed_empty_ranges)]
+    let empty_range = 2..=1;
+    assert!(NodeConfig::builder()
+        .listening_port_range(empty_range)
+        .build()
+        .is_err());
+}
+
 #[tokio::test]
 async fn node_connect_and_disconnect() {
     let nodes = common::start_inert_nodes(2, None).await;
     connect_nodes(&nodes, Topology::Line).await.unwrap();
 
-    assert!(nodes[0].disconnect(nodes[1].listening_addr()));
-    assert!(!nodes[0].is_connected(nodes[1].listening_addr()));
+    assert!(nodes[0].disconnect(nodes[1].listening_addr().unwrap()));
+    assert!(!nodes[0].is_connected(nodes[1].listening_addr().unwrap()));
     assert!(nodes[1].num_connected() == 0);
 }
 
+#[tokio::test]
+async fn node_connect_many() {
+    let config = NodeConfig {
+        max_concurrent_dials: 2,
+        ..Default::default()
+    };
+    let connector = Node::new(Some(config)).await.unwrap();
+    let connectees = common::start_inert_nodes(5, None).await;
+
+    // the last address is the connector's own, so it can't be connected to
+    let mut addrs = connectees
+        .iter()
+        .map(|node| node.listening_addr().unwrap())
+        .collect::<Vec<_>>();
+    addrs.push(connector.listening_addr().unwrap());
+
+    let results = connector.connect_many(&addrs).await;
+
+    assert_eq!(results.len(), addrs.len());
+    for ((addr, result), expected_addr) in results.iter().zip(&addrs) {
+        assert_eq!(addr, expected_addr);
+        assert_eq!(result.is_ok(), *addr != connector.listening_addr().unwrap());
+    }
+    assert_eq!(connector.num_connected(), connectees.len());
+}
+
+#[tokio::test]
+async fn node_dial_many_progress() {
+    let connector = Node::new(None).await.unwrap();
+    let connectees = common::start_inert_nodes(3, None).await;
+
+    let mut addrs = connectees
+        .iter()
+        .map(|node| node.listening_addr().unwrap())
+        .collect::<Vec<_>>();
+    // nobody is listening on the last address
+    addrs.push(
+        TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap(),
+    );
+
+    let mut progress = connector.dial_many(&addrs, 2);
+    assert_eq!(progress.total(), addrs.len());
+
+    let mut outcomes = Vec::new();
+    while let Some(outcome) = progress.recv().await {
+        outcomes.push(outcome);
+        assert_eq!(progress.completed(), outcomes.len());
+    }
+    assert_eq!(outcomes.len(), addrs.len());
+
+    for outcome in outcomes {
+        if outcome.addr == addrs[3] {
+            assert_eq!(outcome.failure(), Some(DialFailure::Refused));
+        } else {
+            assert_eq!(outcome.result.unwrap().addr, outcome.addr);
+        }
+    }
+    assert_eq!(connector.num_connected(), connectees.len());
+}
+
+#[tokio::test]
+async fn node_connect_many_prefers_fresh_addrs() {
+    let config = NodeConfig {
+        max_concurrent_dials: 1,
+        ..Default::default()
+    };
+    let connector = Node::new(Some(config)).await.unwrap();
+    let connectees = common::start_inert_nodes(3, None).await;
+    let addrs = connectees
+        .iter()
+        .map(|node| node.listening_addr().unwrap())
+        .collect::<Vec<_>>();
+
+    // the first address was never seen, and the second one was seen most recently
+    connector.known_peers().register_seen(addrs[2]);
+    sleep(Duration::from_millis(10)).await;
+    connector.known_peers().register_seen(addrs[1]);
+
+    let weight = connector.config().dial_freshness_weight;
+    assert!(
+        connector.known_peers().dial_penalty(addrs[1], weight)
+            < connector.known_peers().dial_penalty(addrs[2], weight)
+    );
+    assert!(connector
+        .known_peers()
+        .dial_penalty(addrs[0], weight)
+        .is_infinite());
+
+    let results = connector.connect_many(&addrs).await;
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+    let last_connected = |addr| {
+        connector.known_peers().read()[&addr]
+            .last_connected
+            .unwrap()
+    };
+    assert!(last_connected(addrs[1]) < last_connected(addrs[2]));
+    assert!(last_connected(addrs[2]) < last_connected(addrs[0]));
+}
+
+#[tokio::test]
+async fn node_pause_and_resume() {
+    let writer = common::MessagingNode::new("writer").await;
+    writer.enable_writing();
+    let reader = common::MessagingNode::new("reader").await;
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+
+    // a paused writer holds the messages back, but keeps the connection open
+    writer.node().pause();
+    assert!(writer.node().is_paused());
+    for _ in 0..3 {
+        writer
+            .node()
+            .send_direct_message(reader_addr, Bytes::from_static(b"later"))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(reader.node().stats().received().0, 0);
+    assert!(writer.node().is_connected(reader_addr));
+
+    // a paused node doesn't dial
+    let other = common::MessagingNode::new("other").await;
+    let other_addr = other.node().listening_addr().unwrap();
+    assert!(
+        timeout(Duration::from_millis(50), writer.node().connect(other_addr))
+            .await
+            .is_err()
+    );
+
+    writer.node().resume();
+    wait_until!(1, reader.node().stats().received().0 == 3);
+    writer.node().connect(other_addr).await.unwrap();
+
+    // a paused reader doesn't read, but the messages get read once it's resumed
+    reader.node().pause();
+    writer
+        .node()
+        .send_direct_message(reader_addr, Bytes::from_static(b"later"))
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(reader.node().stats().received().0, 3);
+
+    reader.node().resume();
+    wait_until!(1, reader.node().stats().received().0 == 4);
+}
+
+#[tokio::test]
+async fn node_pause_holds_back_a_bounded_number_of_messages() {
+    let config = NodeConfig {
+        conn_outbound_queue_depth: 2,
+        ..Default::default()
+    };
+    let writer = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    writer.enable_writing();
+    let reader = common::MessagingNode::new("reader").await;
+    reader.enable_reading();
+    let reader_addr = reader.node().listening_addr().unwrap();
+
+    writer.node().connect(reader_addr).await.unwrap();
+    wait_until!(1, reader.node().num_connected() == 1);
+
+    // the messages exceeding the held back ones and the queued ones are dropped instead of piling up
+    writer.node().pause();
+    for _ in 0..10 {
+        timeout(
+            Duration::from_secs(1),
+            writer
+                .node()
+                .send_direct_message(reader_addr, Bytes::from_static(b"later")),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    writer.node().resume();
+    wait_until!(1, reader.node().stats().received().0 >= 2);
+    sleep(Duration::from_millis(50)).await;
+    assert!(reader.node().stats().received().0 <= 4);
+}
+
+#[tokio::test]
+async fn node_start_and_stop_listening() {
+    let config = NodeConfig {
+        listen_on_start: false,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let addr = node.listening_addr().unwrap();
+    let peers = common::start_inert_nodes(2, None).await;
+
+    // the node doesn't accept connections until it starts listening
+    assert!(!node.is_listening());
+    assert!(peers[0].connect(addr).await.is_err());
+
+    node.start_listening().await.unwrap();
+    assert!(node.start_listening().await.is_err());
+    peers[0].connect(addr).await.unwrap();
+    wait_until!(1, node.num_connected() == 1);
+
+    // existing connections are unaffected by listening being stopped
+    assert!(node.stop_listening().await);
+    assert!(!node.stop_listening().await);
+    assert!(peers[1].connect(addr).await.is_err());
+    assert_eq!(node.num_connected(), 1);
+
+    // the listening address is retained
+    node.start_listening().await.unwrap();
+    assert_eq!(node.listening_addr().unwrap(), addr);
+    peers[1].connect(addr).await.unwrap();
+    wait_until!(1, node.num_connected() == 2);
+}
+
+#[tokio::test]
+async fn node_outbound_only() {
+    let config = NodeConfig {
+        no_listener: true,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let peer = Node::new(None).await.unwrap();
+
+    assert!(node.listening_addr().is_none());
+    assert!(!node.is_listening());
+    assert!(node.start_listening().await.is_err());
+
+    // outbound connections are unaffected
+    node.connect(peer.listening_addr().unwrap()).await.unwrap();
+    wait_until!(1, peer.num_connected() == 1);
+}
+
+#[tokio::test]
+async fn node_custom_runtime() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("custom-runtime")
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let config = NodeConfig {
+        runtime: Some(runtime.handle().clone()),
+        ..Default::default()
+    };
+    let alice = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    alice.enable_reading();
+    alice.enable_writing();
+    let bob = common::MessagingNode::new("bob").await;
+    bob.enable_reading();
+    bob.enable_writing();
+
+    // the inbound tap is called from the reading task
+    let threads = Arc::new(Mutex::new(Vec::new()));
+    let threads_clone = threads.clone();
+    alice.node().set_inbound_tap(move |_, _, _| {
+        let name = std::thread::current().name().map(String::from);
+        threads_clone.lock().push(name);
+    });
+
+    // both inbound and outbound connections work
+    let bob_addr = bob.node().listening_addr().unwrap();
+    alice.node().connect(bob_addr).await.unwrap();
+    let alice_addr = alice.node().listening_addr().unwrap();
+    bob.node().connect(alice_addr).await.unwrap();
+    wait_until!(1, alice.node().num_connected() == 2);
+
+    for addr in bob.node().connected_addrs() {
+        bob.node()
+            .send_direct_message(addr, common::prefix_with_len(2, b"hi"))
+            .await
+            .unwrap();
+    }
+    wait_until!(1, alice.node().stats().received().0 == 2);
+    assert_eq!(*threads.lock(), vec![Some("custom-runtime".to_owned()); 2]);
+
+    alice.node().shut_down();
+    runtime.shutdown_background();
+}
+
+#[tokio::test]
+async fn node_wait_utilities() {
+    let node = Node::new(None).await.unwrap();
+    let peers = common::start_nodes(3, None).await;
+    let peer_addrs = peers
+        .iter()
+        .map(|peer| peer.listening_addr().unwrap())
+        .collect::<Vec<_>>();
+
+    // the events are subscribed to before the connections are established
+    let completed = |event: &NodeEvent| matches!(event, NodeEvent::HandshakeCompleted(..));
+    let first = wait_for_event(&node, completed, Duration::from_secs(1));
+    let all = wait_for_events(&node, completed, 3, Duration::from_secs(1));
+    let conditions = peer_addrs
+        .iter()
+        .rev()
+        .map(|addr| {
+            let addr = *addr;
+            Box::new(move |event: &NodeEvent| event.addr() == addr) as EventCondition
+        })
+        .collect();
+    let each = wait_for_all_events(&node, conditions, Duration::from_secs(1));
+
+    for addr in &peer_addrs {
+        node.connect(*addr).await.unwrap();
+    }
+    wait_for(
+        &node,
+        |node| node.num_connected() == 3,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(first.await.unwrap().addr(), peer_addrs[0]);
+    let expected = peer_addrs.clone();
+    let all = all.await.unwrap();
+    assert_eq!(all.iter().map(|e| e.addr()).collect::<Vec<_>>(), expected);
+    // the events are returned in the order of the conditions
+    let each = each.await.unwrap();
+    assert_eq!(
+        each.iter().map(|e| e.addr()).collect::<Vec<_>>(),
+        expected.into_iter().rev().collect::<Vec<_>>()
+    );
+
+    // the unsatisfied conditions time out
+    assert_eq!(
+        wait_for(
+            &node,
+            |node| node.num_connected() == 4,
+            Duration::from_millis(10)
+        )
+        .await
+        .unwrap_err()
+        .kind(),
+        io::ErrorKind::TimedOut
+    );
+    assert_eq!(
+        wait_for_event(&node, completed, Duration::from_millis(10))
+            .await
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::TimedOut
+    );
+}
+
+#[tokio::test]
+async fn node_accept_injected_stream() {
+    let config = NodeConfig {
+        no_listener: true,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let peers = common::start_nodes(2, None).await;
+
+    // the listener is owned by the application
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+
+    let connecting = peers[0].clone();
+    let dial = tokio::spawn(async move { connecting.connect(listener_addr).await });
+    let (stream, source) = listener.accept().await.unwrap();
+    node.accept_stream(stream, source).await.unwrap();
+    dial.await.unwrap().unwrap();
+
+    assert!(node.is_connected(source));
+    assert!(node.connection_info(source).unwrap().is_inbound());
+    wait_until!(1, peers[0].num_connected() == 1);
+
+    // the injected streams are subject to the usual checks
+    node.set_accept_filter(|_, _| false);
+    let connecting = peers[1].clone();
+    tokio::spawn(async move { connecting.connect(listener_addr).await });
+    let (stream, source) = listener.accept().await.unwrap();
+    let err = node.accept_stream(stream, source).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    assert_eq!(node.num_connected(), 1);
+}
+
 #[tokio::test]
 async fn node_self_connection_fails() {
     let node = Node::new(None).await.unwrap();
-    assert!(node.connect(node.listening_addr()).await.is_err());
+    assert!(node.connect(node.listening_addr().unwrap()).await.is_err());
 }
 
 #[tokio::test]
@@ -76,7 +571,10 @@ async fn node_connector_limit_breach_fails() {
     let connector = Node::new(Some(config)).await.unwrap();
     let connectee = Node::new(None).await.unwrap();
 
-    assert!(connector.connect(connectee.listening_addr()).await.is_err());
+    assert!(connector
+        .connect(connectee.listening_addr().unwrap())
+        .await
+        .is_err());
 }
 
 #[tokio::test]
@@ -89,19 +587,51 @@ async fn node_connectee_limit_breach_fails() {
     let connector = Node::new(None).await.unwrap();
 
     // a breached connection limit doesn't close the listener, so this works
-    connector.connect(connectee.listening_addr()).await.unwrap();
+    connector
+        .connect(connectee.listening_addr().unwrap())
+        .await
+        .unwrap();
 
     // the number of connections on connectee side needs to be checked instead
     wait_until!(1, connectee.num_connected() == 0);
 }
 
+#[tokio::test]
+async fn node_addr_family_policy() {
+    let config = NodeConfig {
+        addr_family_policy: AddrFamilyPolicy::Ipv6Only,
+        ..Default::default()
+    };
+    let ipv6_only = Node::new(Some(config)).await.unwrap();
+    let node = Node::new(None).await.unwrap();
+
+    // IPv4 (and IPv4-mapped) addresses can't be dialed
+    let err = ipv6_only
+        .connect(node.listening_addr().unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    // nor are connections from them accepted
+    node.connect(ipv6_only.listening_addr().unwrap())
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(ipv6_only.num_connected(), 0);
+
+    let policy = AddrFamilyPolicy::Ipv4Only;
+    assert!(policy.allows("127.0.0.1:1".parse().unwrap()));
+    assert!(policy.allows("[::ffff:127.0.0.1]:1".parse().unwrap()));
+    assert!(!policy.allows("[::1]:1".parse().unwrap()));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn node_overlapping_duplicate_connection_attempts_fail() {
     const NUM_ATTEMPTS: usize = 5;
 
     let connector = Node::new(None).await.unwrap();
     let connectee = Node::new(None).await.unwrap();
-    let addr = connectee.listening_addr();
+    let addr = connectee.listening_addr().unwrap();
 
     let err_count = Arc::new(AtomicUsize::new(0));
     for _ in 0..NUM_ATTEMPTS {
@@ -120,7 +650,7 @@ async fn node_overlapping_duplicate_connection_attempts_fail() {
 #[tokio::test]
 async fn node_shutdown_closes_the_listener() {
     let node = Node::new(None).await.unwrap();
-    let addr = node.listening_addr();
+    let addr = node.listening_addr().unwrap();
 
     assert!(TcpListener::bind(addr).await.is_err());
     node.shut_down();
@@ -162,7 +692,7 @@ async fn node_hung_handshake_fails() {
     // the connection attempt should register just fine for the connector, as it doesn't expect a handshake
     assert!(connector
         .node()
-        .connect(connectee.node().listening_addr())
+        .connect(connectee.node().listening_addr().unwrap())
         .await
         .is_ok());
 
@@ -170,6 +700,48 @@ async fn node_hung_handshake_fails() {
     assert!(connectee.node().num_connected() == 0);
 }
 
+#[tokio::test]
+async fn node_dial_cancellation() {
+    #[derive(Clone)]
+    struct Wrap(Node);
+
+    impl Pea2Pea for Wrap {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // a handshake that never concludes, keeping the connection attempt in progress
+    #[async_trait::async_trait]
+    impl Handshaking for Wrap {
+        async fn perform_handshake(&self, _conn: Connection) -> io::Result<Connection> {
+            std::future::pending().await
+        }
+    }
+
+    let config = NodeConfig {
+        max_handshake_time_ms: 60_000,
+        ..Default::default()
+    };
+    let connector = Wrap(Node::new(Some(config)).await.unwrap());
+    let connectee = Node::new(None).await.unwrap();
+    connector.enable_handshaking();
+
+    // cancel the attempt twice in order to ensure that the first one is no longer considered pending
+    for _ in 0..2 {
+        let handle = connector.node().dial(connectee.listening_addr().unwrap());
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(!handle.is_finished());
+        handle.cancel();
+        assert_eq!(
+            handle.outcome().await.unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+    }
+
+    assert_eq!(connector.node().num_connected(), 0);
+}
+
 #[tokio::test]
 async fn node_stats_received() {
     #[derive(Clone)]
@@ -185,7 +757,11 @@ async fn node_stats_received() {
     impl Reading for Wrap {
         type Message = ();
 
-        fn read_message(&self, _src: SocketAddr, buffer: &[u8]) -> io::Result<Option<((), usize)>> {
+        fn read_message(
+            &self,
+            _ctx: &ConnectionContext,
+            buffer: &[u8],
+        ) -> io::Result<Option<((), usize)>> {
             if buffer.len() >= 2 {
                 Ok(Some(((), 2)))
             } else {
@@ -198,7 +774,7 @@ async fn node_stats_received() {
     reader.enable_reading();
 
     // no need to set up a writer node; a raw stream will suffice
-    let mut writer = TcpStream::connect(reader.node().listening_addr())
+    let mut writer = TcpStream::connect(reader.node().listening_addr().unwrap())
         .await
         .unwrap();
     let writer_addr = writer.local_addr().unwrap();
@@ -229,11 +805,11 @@ async fn node_stats_sent() {
     impl Writing for Wrap {
         fn write_message(
             &self,
-            _: SocketAddr,
+            _ctx: &ConnectionContext,
             payload: &[u8],
             buffer: &mut [u8],
         ) -> io::Result<usize> {
-            buffer[..payload.len()].copy_from_slice(&payload);
+            buffer[..payload.len()].copy_from_slice(payload);
             Ok(payload.len())
         }
     }
@@ -273,4 +849,859 @@ async fn node_stats_sent() {
             false
         }
     });
+
+    let sizes = writer.node().stats().outbound_message_sizes();
+    assert_eq!((sizes.count, sizes.sum, sizes.max), (2, 8, 4));
+    assert!(sizes.buckets.contains(&(4, 2)));
+}
+
+#[tokio::test]
+async fn node_stats_histograms() {
+    #[derive(Clone)]
+    struct Wrap(Node);
+
+    impl Pea2Pea for Wrap {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // fixed-length 2B messages that take a while to process
+    #[async_trait]
+    impl Reading for Wrap {
+        type Message = ();
+
+        fn read_message(
+            &self,
+            _ctx: &ConnectionContext,
+            buffer: &[u8],
+        ) -> io::Result<Option<((), usize)>> {
+            if buffer.len() >= 2 {
+                Ok(Some(((), 2)))
+            } else {
+                Ok(None)
+            }
+        }
+
+        async fn process_message(&self, _src: SocketAddr, _msg: ()) -> io::Result<()> {
+            sleep(Duration::from_millis(10)).await;
+            Ok(())
+        }
+    }
+
+    let reader = Wrap(Node::new(None).await.unwrap());
+    reader.enable_reading();
+
+    let mut writer = TcpStream::connect(reader.node().listening_addr().unwrap())
+        .await
+        .unwrap();
+    writer.write_all(&[0; 6]).await.unwrap();
+
+    wait_until!(1, reader.node().stats().processing_times().count == 3);
+
+    let sizes = reader.node().stats().inbound_message_sizes();
+    assert_eq!((sizes.count, sizes.sum), (3, 6));
+    assert_eq!(sizes.mean(), Some(2.0));
+    assert_eq!(sizes.percentile(99.0), Some(2));
+
+    // the messages are processed sequentially, so the later ones wait in the queue
+    let times = reader.node().stats().processing_times();
+    assert!(times.percentile(50.0).unwrap() >= 10_000);
+    assert!(times.max >= 20_000);
+}
+
+#[tokio::test]
+async fn node_socket_options() {
+    // TCP_NODELAY, SO_KEEPALIVE and SO_LINGER
+    type SocketOptions = (bool, bool, Option<Duration>);
+
+    #[derive(Clone)]
+    struct Wrap(Node, Arc<Mutex<Vec<SocketOptions>>>);
+
+    impl Pea2Pea for Wrap {
+        fn node(&self) -> &Node {
+            &self.0
+        }
+    }
+
+    // records the options of every connection's socket
+    #[async_trait::async_trait]
+    impl Handshaking for Wrap {
+        async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+            let reader = conn.reader();
+            let stream: &TcpStream = reader.as_ref();
+            let socket = SockRef::from(stream);
+            self.1
+                .lock()
+                .push((socket.tcp_nodelay()?, socket.keepalive()?, socket.linger()?));
+            Ok(conn)
+        }
+    }
+
+    let config = NodeConfig {
+        tcp_nodelay: true,
+        tcp_keepalive_interval_ms: Some(10_000),
+        tcp_linger_ms: Some(1_000),
+        ..Default::default()
+    };
+    let options = Arc::new(Mutex::new(Vec::new()));
+    let dialer = Wrap(
+        Node::new(Some(config.clone())).await.unwrap(),
+        options.clone(),
+    );
+    let listener = Wrap(Node::new(Some(config)).await.unwrap(), options.clone());
+    dialer.enable_handshaking();
+    listener.enable_handshaking();
+
+    dialer
+        .node()
+        .connect(listener.node().listening_addr().unwrap())
+        .await
+        .unwrap();
+    wait_until!(1, listener.node().num_connected() == 1);
+
+    // the options are applied to both dialed and accepted streams
+    let options = options.lock();
+    assert_eq!(options.len(), 2);
+    assert!(options
+        .iter()
+        .all(|opts| *opts == (true, true, Some(Duration::from_secs(1)))));
+}
+
+#[derive(Default)]
+struct EventCounter {
+    next_span_id: AtomicU64,
+    events: AtomicUsize,
+}
+
+impl Subscriber for EventCounter {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+        Id::from_u64(self.next_span_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[tokio::test]
+async fn node_tracing_dispatch() {
+    let dispatch = Dispatch::new(EventCounter::default());
+    let config = NodeConfig {
+        tracing_dispatch: Some(dispatch.clone()),
+        ..Default::default()
+    };
+    let traced = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    traced.enable_reading();
+
+    let config = NodeConfig {
+        tracing_dispatch: Some(Dispatch::new(NoSubscriber::default())),
+        ..Default::default()
+    };
+    let silenced = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    silenced.enable_writing();
+
+    let traced_addr = traced.node().listening_addr().unwrap();
+    silenced.node().connect(traced_addr).await.unwrap();
+    wait_until!(1, traced.node().num_connected() == 1);
+
+    silenced
+        .node()
+        .send_direct_message(traced_addr, Bytes::from_static(b"hello"))
+        .await
+        .unwrap();
+    wait_until!(1, traced.node().stats().received().0 == 1);
+
+    // the events emitted by the tasks of the traced node are sent to its own dispatcher
+    let counter = dispatch.downcast_ref::<EventCounter>().unwrap();
+    assert!(counter.events.load(Ordering::Relaxed) != 0);
+}
+
+#[tokio::test]
+async fn node_serving_fairness() {
+    let config = NodeConfig {
+        serving_window_secs: 1,
+        serving_fairness: Some(ServingFairness {
+            max_share: 0.5,
+            min_requests: 10,
+        }),
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+
+    let greedy: SocketAddr = "1.1.1.1:1000".parse().unwrap();
+    let modest: SocketAddr = "2.2.2.2:2000".parse().unwrap();
+    let newcomer: SocketAddr = "3.3.3.3:3000".parse().unwrap();
+
+    // the peers are always served a few requests, and any number of them without contention
+    for _ in 0..20 {
+        assert!(node.can_serve(greedy));
+        node.register_served_request(greedy);
+    }
+    for _ in 0..5 {
+        node.register_served_request(modest);
+    }
+
+    // but the greedy one is now consuming most of the serving resources
+    assert!(!node.can_serve(greedy));
+    assert!(node.can_serve(modest));
+    assert_eq!(node.served_requests(greedy), 20);
+    assert_eq!(node.serving_stats(), vec![(greedy, 20), (modest, 5)]);
+
+    let mut requesters = vec![greedy, modest, newcomer];
+    node.prioritize_requesters(&mut requesters);
+    assert_eq!(requesters, vec![newcomer, modest, greedy]);
+
+    // the accounting starts over in the next window
+    sleep(Duration::from_secs(1)).await;
+    assert!(node.can_serve(greedy));
+    assert_eq!(node.served_requests(greedy), 0);
+    assert!(node.serving_stats().is_empty());
+}
+
+#[tokio::test]
+async fn node_connect_retries() {
+    let retry_policy = RetryPolicy {
+        max_retries: 10,
+        initial_backoff_ms: 20,
+        max_backoff_ms: 50,
+        ..Default::default()
+    };
+    let config = NodeConfig {
+        connect_retry_policy: Some(retry_policy),
+        ..Default::default()
+    };
+    let connector = Node::new(Some(config)).await.unwrap();
+
+    // reserve a port that nobody is listening on yet
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    // without retries, the attempt fails right away
+    let options = ConnectOptions {
+        retry_policy: Some(RetryPolicy::none()),
+        ..Default::default()
+    };
+    let err = connector
+        .connect_with_options(addr, options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+    // with them, it succeeds once the peer starts listening
+    let connection = connector.dial(addr);
+    sleep(Duration::from_millis(100)).await;
+    let config = NodeConfig {
+        desired_listening_port: Some(addr.port()),
+        allow_random_port: false,
+        ..Default::default()
+    };
+    let listener = Node::new(Some(config)).await.unwrap();
+    connection.outcome().await.unwrap();
+    assert!(connector.is_connected(addr));
+    wait_until!(1, listener.num_connected() == 1);
+}
+
+#[tokio::test]
+async fn node_partition_detection() {
+    let detection = PartitionDetection {
+        min_peers: 4,
+        min_lost_fraction: 0.5,
+        max_dial_failures: 3,
+        ..Default::default()
+    };
+    let config = NodeConfig {
+        partition_detection: Some(detection),
+        ..Default::default()
+    };
+    // the connections are only found to be lost by a reader, and a node without a writer closes its side
+    let node = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    node.enable_reading();
+    node.enable_writing();
+    let mut events = node.subscribe_network_events();
+
+    let peers = common::start_nodes(4, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for peer in &peers {
+        peer.enable_reading();
+        peer.enable_writing();
+        node.connect(peer.listening_addr().unwrap()).await.unwrap();
+    }
+
+    // local disconnects aren't a symptom of a partition
+    for peer in &peers[..2] {
+        assert!(node.disconnect(peer.listening_addr().unwrap()));
+    }
+    assert!(!node.is_partition_suspected());
+    for peer in &peers[..2] {
+        node.connect(peer.listening_addr().unwrap()).await.unwrap();
+    }
+    wait_until!(1, peers.iter().all(|peer| peer.num_connected() == 1));
+
+    // losing half of the peers within the window looks like a partition
+    peers[0].disconnect_all();
+    wait_until!(1, node.num_connected() == 3);
+    assert!(!node.is_partition_suspected());
+    peers[1].disconnect_all();
+    wait_until!(1, node.is_partition_suspected());
+
+    let signal = loop {
+        if let NetworkEvent::PartitionSuspected(signal) = events.recv().await.unwrap() {
+            break signal;
+        }
+    };
+    assert_eq!(
+        signal,
+        PartitionSignal::PeerLoss {
+            lost: 2,
+            previously_connected: 4
+        }
+    );
+
+    // an established connection means that the node isn't isolated
+    node.connect(peers[0].listening_addr().unwrap())
+        .await
+        .unwrap();
+    assert!(!node.is_partition_suspected());
+
+    // all the dials failing looks like a loss of connectivity too
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    for _ in 0..3 {
+        assert!(node.connect(addr).await.is_err());
+    }
+    assert!(node.is_partition_suspected());
+
+    let signal = loop {
+        if let NetworkEvent::PartitionSuspected(signal) = events.recv().await.unwrap() {
+            break signal;
+        }
+    };
+    assert_eq!(signal, PartitionSignal::DialFailures(3));
+
+    // the shutdown isn't mistaken for a partition
+    node.connect(peers[1].listening_addr().unwrap())
+        .await
+        .unwrap();
+    node.shut_down();
+    assert!(!node.is_partition_suspected());
+}
+
+#[tokio::test]
+async fn node_dns_resolution_caching() {
+    #[derive(Default)]
+    struct CountingResolver(AtomicUsize);
+
+    #[async_trait]
+    impl Resolver for CountingResolver {
+        async fn resolve(&self, host: &str) -> io::Result<DnsAnswer> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            match host {
+                "seed.example.com" | "seed2.example.com" | "seed3.example.com" => Ok(DnsAnswer {
+                    ips: vec!["203.0.113.1".parse().unwrap()],
+                    ttl: Some(Duration::from_millis(100)),
+                }),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    let node = Node::new(None).await.unwrap();
+    let resolver = Arc::new(CountingResolver::default());
+    node.set_resolver(resolver.clone());
+
+    let expected: SocketAddr = "203.0.113.1:4000".parse().unwrap();
+    assert_eq!(
+        node.resolve("seed.example.com", 4000).await.unwrap(),
+        vec![expected]
+    );
+    assert_eq!(
+        node.resolve("seed.example.com", 4000).await.unwrap(),
+        vec![expected]
+    );
+    assert!(node.resolve("unknown.example.com", 4000).await.is_err());
+    assert_eq!(resolver.0.load(Ordering::Relaxed), 2);
+    assert_eq!(
+        node.dns_cache_stats(),
+        DnsCacheStats {
+            hits: 1,
+            misses: 2,
+            entries: 1
+        }
+    );
+
+    // the name is resolved anew once its TTL expires
+    sleep(Duration::from_millis(150)).await;
+    node.resolve("seed.example.com", 4000).await.unwrap();
+    assert_eq!(resolver.0.load(Ordering::Relaxed), 3);
+
+    // or once the cache is flushed
+    assert_eq!(node.flush_dns_cache(), 1);
+    node.resolve("seed.example.com", 4000).await.unwrap();
+    assert_eq!(resolver.0.load(Ordering::Relaxed), 4);
+
+    // the cache is bounded, making room for new names by dropping the ones closest to expiring
+    let config = NodeConfig {
+        dns_cache_capacity: 2,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let resolver = Arc::new(CountingResolver::default());
+    node.set_resolver(resolver.clone());
+
+    for host in ["seed.example.com", "seed2.example.com", "seed3.example.com"] {
+        node.resolve(host, 4000).await.unwrap();
+    }
+    assert_eq!(node.dns_cache_stats().entries, 2);
+    node.resolve("seed3.example.com", 4000).await.unwrap();
+    assert_eq!(resolver.0.load(Ordering::Relaxed), 3);
+    node.resolve("seed.example.com", 4000).await.unwrap();
+    assert_eq!(resolver.0.load(Ordering::Relaxed), 4);
+}
+
+#[tokio::test]
+async fn node_dns_dialing() {
+    // resolves names to the current list of IPs, without letting them be cached
+    #[derive(Default)]
+    struct RotatingResolver(Mutex<Vec<IpAddr>>);
+
+    #[async_trait]
+    impl Resolver for RotatingResolver {
+        async fn resolve(&self, _host: &str) -> io::Result<DnsAnswer> {
+            Ok(DnsAnswer {
+                ips: self.0.lock().clone(),
+                ttl: Some(Duration::ZERO),
+            })
+        }
+    }
+
+    let config = NodeConfig {
+        listener_ip: "127.0.0.1".parse().unwrap(),
+        ..Default::default()
+    };
+    let peer = Node::new(Some(config)).await.unwrap();
+    let peer_addr = peer.listening_addr().unwrap();
+
+    let config = NodeConfig {
+        happy_eyeballs_delay_ms: Some(50),
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let resolver = Arc::new(RotatingResolver::default());
+    node.set_resolver(resolver.clone());
+
+    // the name points to a host that isn't listening
+    *resolver.0.lock() = vec!["127.0.0.2".parse().unwrap()];
+    assert!(node
+        .connect_dns("peer.example.com", peer_addr.port())
+        .await
+        .is_err());
+
+    // once its address rotates, the name is resolved anew and the next attempt succeeds
+    *resolver.0.lock() = vec!["127.0.0.2".parse().unwrap(), peer_addr.ip()];
+    assert_eq!(
+        node.connect_dns("peer.example.com", peer_addr.port())
+            .await
+            .unwrap(),
+        peer_addr
+    );
+    wait_until!(1, peer.num_connected() == 1);
+    assert_eq!(node.connected_addrs(), vec![peer_addr]);
+
+    let err = node
+        .connect_dns("peer.example.com", peer_addr.port())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(node.num_connected(), 1);
+}
+
+#[tokio::test]
+async fn node_known_peers_address_book() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct Services(u64);
+
+    let node = Node::new(None).await.unwrap();
+    let known_peers = node.known_peers();
+
+    let validators: Vec<SocketAddr> = vec![
+        "1.1.1.1:1000".parse().unwrap(),
+        "2.2.2.2:2000".parse().unwrap(),
+    ];
+    let archival: SocketAddr = "3.3.3.3:3000".parse().unwrap();
+
+    for addr in &validators {
+        assert!(known_peers.tag(*addr, "validator"));
+    }
+    known_peers.tag(archival, "archival");
+
+    let mut tagged = known_peers.tagged("validator");
+    tagged.sort();
+    assert_eq!(tagged, validators);
+    assert!(known_peers.tagged("seed").is_empty());
+    let mut with_tag = known_peers.peers_with_tag("validator");
+    with_tag.sort();
+    assert_eq!(with_tag, validators);
+
+    let picked = known_peers
+        .random_peer_with_tag("validator", |_| true)
+        .unwrap();
+    assert!(validators.contains(&picked));
+    assert_eq!(
+        known_peers.random_peer_with_tag("validator", |addr| addr != validators[0]),
+        Some(validators[1])
+    );
+    assert_eq!(known_peers.random_peer_with_tag("seed", |_| true), None);
+
+    assert!(!known_peers.insert_metadata(archival, Services(1)));
+    assert!(known_peers.insert_metadata(archival, Services(3)));
+    assert_eq!(
+        known_peers.metadata::<Services>(archival),
+        Some(Services(3))
+    );
+    assert_eq!(known_peers.metadata::<Services>(validators[0]), None);
+    assert_eq!(
+        known_peers.peers_with_metadata::<Services>(),
+        vec![(archival, Services(3))]
+    );
+    assert!(known_peers.remove_metadata::<Services>(archival));
+    assert!(!known_peers.remove_metadata::<Services>(archival));
+}
+
+#[tokio::test]
+async fn node_known_peers_stats_report() {
+    let known_peers = KnownPeers::default();
+    let active: SocketAddr = "1.1.1.1:1000".parse().unwrap();
+    let unseen: SocketAddr = "2.2.2.2:2000".parse().unwrap();
+
+    known_peers.add(unseen);
+    known_peers.add(active);
+    known_peers.register_connection(active);
+    known_peers.register_sent_message(active, 10);
+    known_peers.register_received_message(active, 20);
+    known_peers.register_failure(active);
+
+    let report = known_peers.snapshot_stats();
+    assert_eq!(report.peers.len(), 2);
+    let peer = &report.peers[0];
+    assert_eq!(peer.addr, active);
+    assert_eq!((peer.msgs_sent, peer.bytes_sent), (1, 10));
+    assert_eq!((peer.msgs_received, peer.bytes_received), (1, 20));
+    assert_eq!(peer.failures, 1);
+    assert!(peer.last_seen_ago.is_some());
+    assert!(peer.score < -0.99 && peer.score > -1.01);
+    assert_eq!(report.peers[1].score, f64::NEG_INFINITY);
+
+    #[cfg(feature = "serde")]
+    assert!(!bincode::serialize(&report).unwrap().is_empty());
+
+    // the reset returns the final report, and keeps the timestamps
+    assert_eq!(known_peers.reset_stats().peers[0].msgs_sent, 1);
+    let peer = &known_peers.snapshot_stats().peers[0];
+    assert_eq!(
+        (peer.msgs_sent, peer.bytes_received, peer.failures),
+        (0, 0, 0)
+    );
+    assert!(peer.last_seen_ago.is_some());
+}
+
+#[tokio::test]
+async fn node_known_peers_aliases() {
+    let known_peers = KnownPeers::default();
+    let listening: SocketAddr = "1.1.1.1:1000".parse().unwrap();
+    let ephemeral: SocketAddr = "1.1.1.1:54321".parse().unwrap();
+
+    known_peers.add(listening);
+    known_peers.register_connection(listening);
+    known_peers.add(ephemeral);
+    known_peers.register_connection(ephemeral);
+    known_peers.register_received_message(ephemeral, 10);
+    known_peers.tag(ephemeral, "inbound");
+
+    // the handshake reveals the listening address
+    known_peers.register_alias(ephemeral, listening);
+    assert_eq!(known_peers.canonical_addr(ephemeral), listening);
+    assert_eq!(known_peers.aliases(listening), vec![ephemeral]);
+    assert_eq!(known_peers.read().len(), 1);
+
+    // further activity counts towards the canonical record
+    known_peers.register_sent_message(ephemeral, 20);
+    {
+        let peers = known_peers.read();
+        let stats = peers.get(&listening).unwrap();
+        assert_eq!(stats.times_connected, 2);
+        assert_eq!((stats.msgs_received, stats.bytes_received), (1, 10));
+        assert_eq!((stats.msgs_sent, stats.bytes_sent), (1, 20));
+    }
+    assert!(known_peers.has_tag(listening, "inbound"));
+
+    assert_eq!(known_peers.remove_alias(ephemeral), Some(listening));
+    assert_eq!(known_peers.canonical_addr(ephemeral), ephemeral);
+    assert!(known_peers.aliases(listening).is_empty());
+}
+
+#[tokio::test]
+async fn node_bootstrap_peers() {
+    let peers = common::start_nodes(2, None)
+        .await
+        .into_iter()
+        .map(common::MessagingNode)
+        .collect::<Vec<_>>();
+    for peer in &peers {
+        peer.enable_reading();
+        peer.enable_writing();
+    }
+    let peer_addrs = peers
+        .iter()
+        .map(|peer| peer.listening_addr().unwrap())
+        .collect::<Vec<_>>();
+
+    let config = NodeConfig {
+        bootstrap_peers: peer_addrs.clone(),
+        bootstrap_redial_delay_ms: 10,
+        ..Default::default()
+    };
+    let node = common::MessagingNode(Node::new(Some(config)).await.unwrap());
+    node.enable_reading();
+    node.enable_writing();
+    let mut events = node.subscribe_network_events();
+
+    // bootstrapping doesn't start on its own, so that the protocols can be enabled first
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(node.num_connected(), 0);
+
+    assert!(node.start_bootstrapping());
+    assert!(!node.start_bootstrapping());
+
+    async fn bootstrap_completed(events: &mut broadcast::Receiver<NetworkEvent>) -> usize {
+        loop {
+            if let NetworkEvent::BootstrapCompleted(num_connected) = events.recv().await.unwrap() {
+                break num_connected;
+            }
+        }
+    }
+    assert_eq!(bootstrap_completed(&mut events).await, 2);
+    assert_eq!(node.num_connected(), 2);
+
+    // the bootstrap peers are re-dialed once all the connections are lost
+    wait_until!(1, peers.iter().all(|peer| peer.num_connected() == 1));
+    for peer in &peers {
+        peer.disconnect_all();
+    }
+    assert_eq!(bootstrap_completed(&mut events).await, 2);
+    assert_eq!(node.num_connected(), 2);
+
+    // but not after they were disconnected locally
+    assert_eq!(node.disconnect_all().len(), 2);
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(node.num_connected(), 0);
+}
+
+#[tokio::test]
+async fn node_bootstrap_hosts() {
+    let config = NodeConfig {
+        listener_ip: "127.0.0.1".parse().unwrap(),
+        ..Default::default()
+    };
+    let peer = Node::new(Some(config)).await.unwrap();
+    let peer_addr = peer.listening_addr().unwrap();
+
+    let config = NodeConfig {
+        bootstrap_hosts: vec![("localhost".to_owned(), peer_addr.port())],
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let mut events = node.subscribe_network_events();
+
+    assert!(node.start_bootstrapping());
+    let num_connected = loop {
+        if let NetworkEvent::BootstrapCompleted(num_connected) = events.recv().await.unwrap() {
+            break num_connected;
+        }
+    };
+    assert_eq!(num_connected, 1);
+    assert!(node.is_connected(peer_addr));
+}
+
+#[tokio::test]
+async fn node_connection_filters() {
+    let nodes = common::start_nodes(3, None).await;
+    let addrs = nodes
+        .iter()
+        .map(|node| node.listening_addr().unwrap())
+        .collect::<Vec<_>>();
+
+    // only the peers tagged as trusted can be dialed
+    nodes[0].known_peers().tag(addrs[1], "trusted");
+    nodes[0].set_dial_filter(|_, stats| {
+        stats
+            .map(|stats| stats.tags.contains("trusted"))
+            .unwrap_or(false)
+    });
+    nodes[0].connect(addrs[1]).await.unwrap();
+    let err = nodes[0].connect(addrs[2]).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    // the last node doesn't accept any connections
+    nodes[2].set_accept_filter(|_, _| false);
+    nodes[1].connect(addrs[2]).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(nodes[2].num_connected(), 0);
+}
+
+#[tokio::test]
+async fn node_accept_filter_sees_the_listening_addr_stats() {
+    let nodes = common::start_nodes(2, None).await;
+    let banned_port = nodes[1].listening_addr().unwrap().port();
+    let banned_addr = SocketAddr::from(([127, 0, 0, 1], banned_port));
+
+    // the inbound connection originates from an ephemeral port, but it's matched with the known listening address
+    nodes[0].known_peers().tag(banned_addr, "banned");
+    nodes[0].set_accept_filter(move |addr, stats| {
+        assert_ne!(addr, banned_addr);
+        !stats
+            .map(|stats| stats.tags.contains("banned"))
+            .unwrap_or(false)
+    });
+    nodes[1]
+        .connect(nodes[0].listening_addr().unwrap())
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(nodes[0].num_connected(), 0);
+}
+
+#[tokio::test]
+async fn node_subnet_limits() {
+    let config = NodeConfig {
+        subnet_limits: Some(SubnetLimits {
+            max_per_ip: 1,
+            max_per_subnet: 2,
+        }),
+        ..Default::default()
+    };
+    let connector = Node::new(Some(config)).await.unwrap();
+    let ports = common::start_nodes(3, None)
+        .await
+        .iter()
+        .map(|node| node.listening_addr().unwrap().port())
+        .collect::<Vec<_>>();
+    let addr = |ip: [u8; 4], port| SocketAddr::from((ip, port));
+
+    connector
+        .connect(addr([127, 0, 0, 1], ports[0]))
+        .await
+        .unwrap();
+    // the IP is already connected to
+    let err = connector
+        .connect(addr([127, 0, 0, 1], ports[1]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    connector
+        .connect(addr([127, 0, 0, 2], ports[1]))
+        .await
+        .unwrap();
+    // the /24 subnet is full
+    let err = connector
+        .connect(addr([127, 0, 0, 3], ports[2]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    connector
+        .connect(addr([127, 0, 1, 1], ports[2]))
+        .await
+        .unwrap();
+    assert_eq!(connector.num_connected(), 3);
+}
+
+#[tokio::test]
+async fn node_bulk_disconnects() {
+    let node = Node::new(None).await.unwrap();
+    let outbound = common::start_nodes(2, None).await;
+    let inbound = common::start_nodes(2, None).await;
+
+    let mut outbound_addrs = Vec::new();
+    for peer in &outbound {
+        let addr = peer.listening_addr().unwrap();
+        node.connect(addr).await.unwrap();
+        outbound_addrs.push(addr);
+    }
+    for peer in &inbound {
+        peer.connect(node.listening_addr().unwrap()).await.unwrap();
+    }
+    wait_until!(1, node.num_connected() == 4);
+
+    let mut disconnected = node.disconnect_outbound();
+    disconnected.sort();
+    outbound_addrs.sort();
+    assert_eq!(disconnected, outbound_addrs);
+    assert_eq!(node.num_connected(), 2);
+
+    let target = node.connected_addrs()[0];
+    let pruned = node.prune_connections(|info| info.addr == target);
+    assert_eq!(pruned.len(), 1);
+    assert_eq!(node.disconnect_inbound().len(), 1);
+    assert!(node.disconnect_all().is_empty());
+
+    for peer in &outbound {
+        node.connect(peer.listening_addr().unwrap()).await.unwrap();
+    }
+    assert_eq!(node.disconnect_all().len(), 2);
+    assert_eq!(node.num_connected(), 0);
+}
+
+#[tokio::test]
+async fn node_status() {
+    let nodes = common::start_inert_nodes(3, None).await;
+    let (hub, spokes) = nodes.split_first().unwrap();
+
+    for spoke in spokes {
+        spoke.connect(hub.listening_addr().unwrap()).await.unwrap();
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    hub.connect(listener.local_addr().unwrap()).await.unwrap();
+    wait_until!(1, hub.num_connected() == 3);
+
+    let status = hub.status();
+    assert_eq!(status.name, hub.name());
+    assert_eq!(status.listening_addr, hub.listening_addr());
+    assert!(status.is_listening);
+    assert!(!status.is_paused);
+    assert_eq!(status.connections.established, 3);
+    assert_eq!(status.connections.inbound, 2);
+    assert_eq!(status.connections.outbound, 1);
+    assert_eq!(status.connections.connecting, 0);
+    assert_eq!(status.connections.pending_inbound, 0);
+    assert_eq!(
+        status.connections.max,
+        hub.config().max_connections as usize
+    );
+    assert_eq!(status.known_peers, 3);
+    // the Writing protocol isn't enabled, so there are no outbound queues
+    assert_eq!(status.queues.outbound_capacity, 0);
+    assert_eq!(status.traffic.msgs_sent, 0);
 }
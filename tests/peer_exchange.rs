@@ -0,0 +1,203 @@
+use bytes::Bytes;
+
+mod common;
+use pea2pea::{
+    protocols::{PeerExchange, Reading, Writing},
+    ConnectionContext, Node, NodeConfig, Pea2Pea,
+};
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+#[derive(Clone)]
+struct PexNode(Node);
+
+impl Pea2Pea for PexNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+const PEX_TAG: u8 = 1;
+
+#[async_trait::async_trait]
+impl Reading for PexNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        if message[0] == PEX_TAG {
+            self.node()
+                .handle_pex_message(source, &message[1..])
+                .map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Writing for PexNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl PeerExchange for PexNode {
+    fn pex_message(&self, payload: Bytes) -> Bytes {
+        let mut message = vec![PEX_TAG];
+        message.extend_from_slice(&payload);
+        message.into()
+    }
+}
+
+fn encode_addr(addr: SocketAddr) -> Vec<u8> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let mut encoded = ip.octets().to_vec();
+    encoded.extend_from_slice(&addr.port().to_le_bytes());
+    encoded
+}
+
+#[tokio::test]
+async fn peer_exchange() {
+    // the unspecified listening addresses can't be shared
+    let config = NodeConfig {
+        listener_ip: "127.0.0.1".parse().unwrap(),
+        pex_interval_ms: 10,
+        ..Default::default()
+    };
+    let nodes = common::start_nodes(3, Some(config))
+        .await
+        .into_iter()
+        .map(PexNode)
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_reading();
+        node.enable_writing();
+        node.enable_peer_exchange();
+    }
+    let (alice, bob, carol) = (&nodes[0], &nodes[1], &nodes[2]);
+    let bob_addr = bob.node().listening_addr().unwrap();
+    let carol_addr = carol.node().listening_addr().unwrap();
+
+    // bob and carol only know alice, who shares their listening addresses with them
+    alice.node().connect(bob_addr).await.unwrap();
+    alice.node().connect(carol_addr).await.unwrap();
+    wait_until!(
+        1,
+        bob.node().known_peers().read().contains_key(&carol_addr)
+            && carol.node().known_peers().read().contains_key(&bob_addr)
+    );
+
+    // the ephemeral addresses of the inbound connections aren't shared
+    assert_eq!(bob.node().known_peers().read().len(), 2);
+    bob.node().connect(carol_addr).await.unwrap();
+}
+
+#[tokio::test]
+async fn peer_exchange_validation() {
+    let config = NodeConfig {
+        pex_max_addrs: 2,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let source: SocketAddr = "1.1.1.1:1000".parse().unwrap();
+    let valid: SocketAddr = "2.2.2.2:2000".parse().unwrap();
+
+    // the addresses that can't be dialed or belong to the node are skipped
+    let mut payload = encode_addr(valid);
+    payload.extend(encode_addr("0.0.0.0:3000".parse().unwrap()));
+    assert_eq!(node.handle_pex_message(source, &payload).unwrap(), 1);
+    let payload = [
+        encode_addr("3.3.3.3:0".parse().unwrap()),
+        encode_addr(node.listening_addr().unwrap()),
+    ]
+    .concat();
+    assert_eq!(node.handle_pex_message(source, &payload).unwrap(), 0);
+    // the learned address isn't considered seen until it's dialed
+    assert!(node
+        .known_peers()
+        .read()
+        .get(&valid)
+        .unwrap()
+        .last_seen
+        .is_none());
+
+    // the already known addresses aren't counted
+    assert_eq!(
+        node.handle_pex_message(source, &encode_addr(valid))
+            .unwrap(),
+        0
+    );
+
+    // malformed and oversized payloads are rejected
+    assert!(node.handle_pex_message(source, &[0; 17]).is_err());
+    let payload = [encode_addr(valid), encode_addr(valid), encode_addr(valid)].concat();
+    assert!(node.handle_pex_message(source, &payload).is_err());
+    assert_eq!(node.known_peers().read().len(), 1);
+}
+
+#[tokio::test]
+async fn peer_exchange_limits() {
+    let config = NodeConfig {
+        pex_max_unverified_addrs: 3,
+        pex_max_unverified_addrs_per_source: 2,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    let payload = |first_port: u16| {
+        (first_port..first_port + 3)
+            .flat_map(|port| encode_addr(SocketAddr::from(([2, 2, 2, 2], port))))
+            .collect::<Vec<_>>()
+    };
+
+    // the number of unverified addresses from a single source is limited
+    let source: SocketAddr = "1.1.1.1:1000".parse().unwrap();
+    assert_eq!(node.handle_pex_message(source, &payload(1)).unwrap(), 2);
+    assert_eq!(node.handle_pex_message(source, &payload(4)).unwrap(), 0);
+
+    // the same goes for the overall number of unverified addresses
+    let source: SocketAddr = "3.3.3.3:1000".parse().unwrap();
+    assert_eq!(node.handle_pex_message(source, &payload(4)).unwrap(), 1);
+    assert_eq!(node.known_peers().read().len(), 3);
+
+    // an address that can't be connected to is forgotten, making room for another one
+    let closed_addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = NodeConfig {
+        pex_max_unverified_addrs_per_source: 1,
+        ..Default::default()
+    };
+    let node = Node::new(Some(config)).await.unwrap();
+    assert_eq!(
+        node.handle_pex_message(source, &encode_addr(closed_addr))
+            .unwrap(),
+        1
+    );
+    assert_eq!(node.handle_pex_message(source, &payload(1)).unwrap(), 0);
+    assert!(node.connect(closed_addr).await.is_err());
+    assert!(!node.known_peers().contains(closed_addr));
+    assert_eq!(node.handle_pex_message(source, &payload(1)).unwrap(), 1);
+}
@@ -0,0 +1,129 @@
+use bytes::Bytes;
+use tokio::time::timeout;
+
+mod common;
+use pea2pea::{
+    protocols::{PubSub, Reading, Writing},
+    ConnectionContext, Node, Pea2Pea,
+};
+
+use std::{io, net::SocketAddr, time::Duration};
+
+#[derive(Clone)]
+struct PubSubNode(Node);
+
+impl Pea2Pea for PubSubNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+const PUBSUB_TAG: u8 = 1;
+
+#[async_trait::async_trait]
+impl Reading for PubSubNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, source: SocketAddr, message: Self::Message) -> io::Result<()> {
+        if message[0] == PUBSUB_TAG {
+            self.node()
+                .handle_pubsub_message(source, &message[1..])
+                .await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Writing for PubSubNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+impl PubSub for PubSubNode {
+    fn pubsub_message(&self, payload: Bytes) -> Bytes {
+        let mut message = vec![PUBSUB_TAG];
+        message.extend_from_slice(&payload);
+        message.into()
+    }
+}
+
+#[tokio::test]
+async fn pubsub() {
+    let nodes = common::start_nodes(3, None)
+        .await
+        .into_iter()
+        .map(PubSubNode)
+        .collect::<Vec<_>>();
+    for node in &nodes {
+        node.enable_reading();
+        node.enable_writing();
+        node.enable_pubsub();
+    }
+    let (alice, bob, carol) = (&nodes[0], &nodes[1], &nodes[2]);
+
+    // bob subscribes before connecting, carol - after
+    let alice_addr = alice.node().listening_addr().unwrap();
+    let mut bob_blocks = bob.node().subscribe_topic("blocks").await.unwrap();
+    bob.node().connect(alice_addr).await.unwrap();
+    wait_until!(1, alice.node().num_connected() == 1);
+    let bob_addr = alice.node().connected_addrs()[0];
+
+    carol.node().connect(alice_addr).await.unwrap();
+    wait_until!(1, alice.node().num_connected() == 2);
+    let carol_addr = alice
+        .node()
+        .connected_addrs()
+        .into_iter()
+        .find(|addr| *addr != bob_addr)
+        .unwrap();
+    let mut carol_txs = carol.node().subscribe_topic("txs").await.unwrap();
+
+    wait_until!(1, alice.node().peer_topics(bob_addr) == ["blocks"]);
+    wait_until!(1, alice.node().peer_topics(carol_addr) == ["txs"]);
+
+    // the messages are only routed to the subscribers of their topic
+    let block = Bytes::from_static(b"block");
+    assert_eq!(
+        alice.node().publish("blocks", block.clone()).await.unwrap(),
+        1
+    );
+    let tx = Bytes::from_static(b"tx");
+    assert_eq!(alice.node().publish("txs", tx.clone()).await.unwrap(), 1);
+    assert_eq!(alice.node().publish("votes", tx.clone()).await.unwrap(), 0);
+
+    let recv = Duration::from_secs(1);
+    let (source, message) = timeout(recv, bob_blocks.recv()).await.unwrap().unwrap();
+    assert_eq!(source, alice_addr);
+    assert_eq!(message, block);
+    let (_, message) = timeout(recv, carol_txs.recv()).await.unwrap().unwrap();
+    assert_eq!(message, tx);
+    assert!(bob_blocks.try_recv().is_err());
+
+    // once bob unsubscribes, alice no longer sends him the blocks
+    bob.node().unsubscribe_topic("blocks").await.unwrap();
+    assert!(timeout(recv, bob_blocks.recv()).await.unwrap().is_none());
+    wait_until!(1, alice.node().peer_topics(bob_addr).is_empty());
+    assert_eq!(alice.node().publish("blocks", block).await.unwrap(), 0);
+
+    // topics are limited in length
+    assert!(bob.node().subscribe_topic(&"a".repeat(256)).await.is_err());
+}
@@ -0,0 +1,270 @@
+#![cfg(feature = "quic")]
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+mod common;
+use pea2pea::{
+    protocols::{Reading, Writing},
+    quic::{QuicConfig, QuicTransport, TlsInfo},
+    ConnectionContext, Node, NodeConfig, Pea2Pea,
+};
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+#[derive(Clone)]
+struct QuicNode {
+    node: Node,
+    received: Arc<Mutex<Vec<(SocketAddr, Bytes)>>>,
+    // the ALPN protocols the received messages were sent with
+    protocols: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Pea2Pea for QuicNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for QuicNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        let bytes = common::read_len_prefixed_message(4, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[4..]), bytes.len())))
+    }
+
+    async fn process_message_with_context(
+        &self,
+        ctx: &ConnectionContext,
+        message: Self::Message,
+    ) -> io::Result<()> {
+        if let Some(protocol) = ctx
+            .handshake_data::<TlsInfo>()
+            .and_then(|tls_info| tls_info.alpn_protocol.clone())
+        {
+            self.protocols.lock().push(protocol);
+        }
+        self.received.lock().push((ctx.addr(), message));
+
+        Ok(())
+    }
+}
+
+impl Writing for QuicNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        if buffer.len() < 4 + payload.len() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        buffer[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        buffer[4..][..payload.len()].copy_from_slice(payload);
+        Ok(4 + payload.len())
+    }
+}
+
+impl QuicNode {
+    fn received(&self) -> Vec<(SocketAddr, Bytes)> {
+        self.received.lock().clone()
+    }
+}
+
+async fn quic_node(conn_write_buffer_size: usize) -> QuicTransport<QuicNode> {
+    quic_node_with_config(conn_write_buffer_size, QuicConfig::self_signed().unwrap()).await
+}
+
+async fn quic_node_with_config(
+    conn_write_buffer_size: usize,
+    quic_config: QuicConfig,
+) -> QuicTransport<QuicNode> {
+    let config = NodeConfig {
+        no_listener: true,
+        conn_write_buffer_size,
+        ..Default::default()
+    };
+    let node = QuicNode {
+        node: Node::new(Some(config)).await.unwrap(),
+        received: Default::default(),
+        protocols: Default::default(),
+    };
+
+    QuicTransport::bind(node, "127.0.0.1:0".parse().unwrap(), quic_config).unwrap()
+}
+
+#[tokio::test]
+async fn quic_messaging() {
+    let alice = quic_node(NodeConfig::default().conn_write_buffer_size).await;
+    let bob = quic_node(NodeConfig::default().conn_write_buffer_size).await;
+    let alice_addr = alice.local_addr().unwrap();
+    let bob_addr = bob.local_addr().unwrap();
+
+    alice.connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.num_connected() == 1);
+    assert_eq!(bob.connected_addrs(), vec![alice_addr]);
+    assert!(alice.connect(bob_addr).await.is_err());
+
+    // both sides can use the main stream
+    alice.send(bob_addr, b"hello").await.unwrap();
+    alice.send(bob_addr, b"there").await.unwrap();
+    bob.send(alice_addr, b"hi").await.unwrap();
+
+    wait_until!(1, bob.protocol().received().len() == 2);
+    let received = bob.protocol().received();
+    assert_eq!(received[0], (alice_addr, Bytes::from_static(b"hello")));
+    assert_eq!(received[1], (alice_addr, Bytes::from_static(b"there")));
+    wait_until!(1, alice.protocol().received().len() == 1);
+    assert_eq!(alice.protocol().node().stats().sent().0, 2);
+
+    // a large message is sent via a dedicated stream, while the gossip continues on the main stream
+    let large = (0..4 * 1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let (large_result, small_result) = tokio::join!(
+        bob.send_on_stream(alice_addr, &large),
+        bob.send(alice_addr, b"gossip")
+    );
+    large_result.unwrap();
+    small_result.unwrap();
+
+    wait_until!(3, alice.protocol().received().len() == 3);
+    let received = alice.protocol().received();
+    assert!(received.iter().any(|(_, msg)| msg[..] == large[..]));
+    assert!(received.iter().any(|(_, msg)| &msg[..] == b"gossip"));
+
+    assert!(alice.disconnect(bob_addr));
+    wait_until!(1, bob.num_connected() == 0);
+    assert!(alice.send(bob_addr, b"bye").await.is_err());
+}
+
+#[tokio::test]
+async fn quic_oversized_message_on_main_stream() {
+    let read_buffer_size = NodeConfig::default().conn_read_buffer_size;
+    let alice = quic_node(read_buffer_size * 2).await;
+    let bob = quic_node(read_buffer_size).await;
+    let bob_addr = bob.local_addr().unwrap();
+
+    alice.connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.num_connected() == 1);
+
+    // exceeds the conn_read_buffer_size of the recipient, which only applies to the main stream
+    let oversized = vec![0u8; read_buffer_size];
+    alice.send_on_stream(bob_addr, &oversized).await.unwrap();
+    wait_until!(1, bob.protocol().received().len() == 1);
+
+    alice.send(bob_addr, &oversized).await.unwrap();
+    wait_until!(1, bob.num_connected() == 0 && alice.num_connected() == 0);
+    assert_eq!(bob.protocol().received().len(), 1);
+}
+
+#[tokio::test]
+async fn quic_alpn_and_sni() {
+    let write_buffer_size = NodeConfig::default().conn_write_buffer_size;
+    let protocols: &[&[u8]] = &[b"gossip/1", b"sync/1"];
+    let server = quic_node_with_config(
+        write_buffer_size,
+        QuicConfig::self_signed_with_protocols(protocols).unwrap(),
+    )
+    .await;
+    server.set_accept_filter(|_, tls_info| tls_info.server_name.as_deref() != Some("blocked"));
+    let server_addr = server.local_addr().unwrap();
+
+    // the client offers a single protocol and indicates a server name
+    let client = quic_node(write_buffer_size).await;
+    let client_addr = client.local_addr().unwrap();
+    let sync_config = QuicConfig::self_signed_with_protocols(&[b"sync/1"])
+        .unwrap()
+        .client_config;
+    client
+        .connect_with(server_addr, "sync.example", sync_config.clone())
+        .await
+        .unwrap();
+    wait_until!(1, server.num_connected() == 1);
+
+    let tls_info = server.tls_info(client_addr).unwrap();
+    assert_eq!(tls_info.alpn_protocol.as_deref(), Some(&b"sync/1"[..]));
+    assert_eq!(tls_info.server_name.as_deref(), Some("sync.example"));
+    assert_eq!(client.tls_info(server_addr), Some(tls_info));
+
+    // the negotiated protocol is available when processing messages
+    client.send(server_addr, b"hello").await.unwrap();
+    wait_until!(1, server.protocol().received().len() == 1);
+    assert_eq!(
+        *server.protocol().protocols.lock(),
+        vec![b"sync/1".to_vec()]
+    );
+
+    // a dialer without any of the server's protocols can't connect
+    let other = quic_node(write_buffer_size).await;
+    assert!(other.connect(server_addr).await.is_err());
+
+    // the accept filter can veto connections based on the server name
+    let _ = other
+        .connect_with(server_addr, "blocked", sync_config)
+        .await;
+    wait_until!(1, other.num_connected() == 0);
+    assert_eq!(server.num_connected(), 1);
+}
+
+#[tokio::test]
+async fn quic_limits() {
+    let quic_config = QuicConfig {
+        max_concurrent_streams: 1,
+        ..QuicConfig::self_signed().unwrap()
+    };
+    let config = NodeConfig {
+        no_listener: true,
+        max_connections: 1,
+        ..Default::default()
+    };
+    let node = QuicNode {
+        node: Node::new(Some(config)).await.unwrap(),
+        received: Default::default(),
+        protocols: Default::default(),
+    };
+    let server = QuicTransport::bind(node, "127.0.0.1:0".parse().unwrap(), quic_config).unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    // concurrent connections can't exceed the limit
+    let clients = [
+        quic_node(NodeConfig::default().conn_write_buffer_size).await,
+        quic_node(NodeConfig::default().conn_write_buffer_size).await,
+    ];
+    let _ = tokio::join!(
+        clients[0].connect(server_addr),
+        clients[1].connect(server_addr)
+    );
+    wait_until!(
+        1,
+        server.num_connected() == 1
+            && clients
+                .iter()
+                .filter(|client| client.is_connected(server_addr))
+                .count()
+                == 1
+    );
+    let client = clients
+        .iter()
+        .find(|client| client.is_connected(server_addr))
+        .unwrap();
+
+    // the streams exceeding the concurrency limit are read once the ongoing ones are done
+    let messages = (0..4u8).map(|i| vec![i; 1024]).collect::<Vec<_>>();
+    for message in &messages {
+        client.send_on_stream(server_addr, message).await.unwrap();
+    }
+    wait_until!(1, server.protocol().received().len() == 4);
+
+    // shutting down closes the connections and stops the transport's tasks
+    server.shut_down();
+    assert_eq!(server.num_connected(), 0);
+    wait_until!(1, !client.is_connected(server_addr));
+    assert_eq!(server.protocol().received().len(), 4);
+}
@@ -1,7 +1,7 @@
-#![allow(clippy::blocks_in_if_conditions)]
+#![allow(clippy::blocks_in_conditions)]
 
 mod common;
-use pea2pea::{connect_nodes, Topology};
+use pea2pea::{connect_nodes, ConnectionGraph, Topology};
 
 // the number of nodes spawned for each topology test
 const N: usize = 10;
@@ -55,3 +55,45 @@ async fn topology_star_conn_counts() {
         })
     );
 }
+
+#[tokio::test]
+async fn topology_connection_graph() {
+    let nodes = common::start_inert_nodes(4, None).await;
+    connect_nodes(&nodes, Topology::Star).await.unwrap();
+    wait_until!(1, nodes[0].num_connected() == 3);
+
+    // a peer from outside of the captured set
+    let outsider = common::start_inert_nodes(1, None).await.pop().unwrap();
+    let outsider_addr = outsider.listening_addr().unwrap();
+    nodes[1].connect(outsider_addr).await.unwrap();
+
+    let graph = ConnectionGraph::capture(&nodes);
+    assert_eq!(graph.nodes.len(), 5);
+    assert_eq!(graph.nodes[4].addr, Some(outsider_addr));
+    assert_eq!(graph.edges.len(), 4);
+    for i in 1..4 {
+        assert!(graph
+            .edges
+            .iter()
+            .any(|edge| edge.initiator == i && edge.responder == 0));
+    }
+    assert!(graph
+        .edges
+        .iter()
+        .any(|edge| edge.initiator == 1 && edge.responder == 4));
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph"));
+    assert!(dot.contains("n3 -> n0"));
+
+    let graphml = graph.to_graphml();
+    assert_eq!(graphml.matches("<edge ").count(), 4);
+
+    let json = graph.to_json();
+    let edge = format!(
+        "\"initiator\":\"{}\",\"responder\":\"{}\"",
+        nodes[2].name(),
+        nodes[0].name()
+    );
+    assert!(json.contains(&edge));
+}
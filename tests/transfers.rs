@@ -0,0 +1,229 @@
+use pea2pea::transfers::{
+    encode_chunks, CompletedTransfer, FileStorage, Reassembler, ReassemblyLimits, TransferKey,
+    TransferStorage,
+};
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+const LIMITS: ReassemblyLimits = ReassemblyLimits {
+    max_transfer_len: 1024,
+    max_transfer_memory: 64,
+    max_total_memory: 96,
+    max_transfers: 4,
+    max_total_stored: 512,
+    idle_timeout: Duration::from_secs(10),
+};
+
+fn source() -> SocketAddr {
+    "127.0.0.1:4141".parse().unwrap()
+}
+
+#[tokio::test]
+async fn transfers_in_memory() {
+    let reassembler = Reassembler::new(LIMITS, None);
+    let key = TransferKey {
+        source: source(),
+        id: 0,
+    };
+    let payload = (0..48u8).collect::<Vec<_>>();
+    let chunks = encode_chunks(key.id, &payload, 16);
+    assert_eq!(chunks.len(), 3);
+
+    for (i, chunk) in chunks[..2].iter().enumerate() {
+        assert!(reassembler
+            .handle_chunk(key.source, chunk)
+            .await
+            .unwrap()
+            .is_none());
+        let progress = reassembler.progress(key).unwrap();
+        assert_eq!(progress.received, 16 * (i as u64 + 1));
+        assert_eq!(progress.total_len, 48);
+        assert!(!progress.spilled);
+    }
+    assert_eq!(reassembler.memory_usage(), 32);
+
+    assert_eq!(
+        reassembler
+            .handle_chunk(key.source, &chunks[2])
+            .await
+            .unwrap(),
+        Some(CompletedTransfer::InMemory(payload.into()))
+    );
+    assert!(reassembler.progress(key).is_none());
+    assert_eq!(reassembler.memory_usage(), 0);
+}
+
+#[tokio::test]
+async fn transfers_memory_limits() {
+    let reassembler = Reassembler::new(LIMITS, None);
+
+    // a transfer exceeding the per-transfer memory limit is aborted
+    let chunks = encode_chunks(0, &[0; 80], 16);
+    for chunk in &chunks[..4] {
+        assert!(reassembler
+            .handle_chunk(source(), chunk)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    let err = reassembler
+        .handle_chunk(source(), &chunks[4])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+    assert_eq!(reassembler.memory_usage(), 0);
+
+    // so are the ones exceeding the node-wide limit
+    let first = encode_chunks(1, &[0; 64], 32);
+    let second = encode_chunks(2, &[0; 64], 32);
+    let third = encode_chunks(3, &[0; 64], 48);
+    assert!(reassembler
+        .handle_chunk(source(), &first[0])
+        .await
+        .unwrap()
+        .is_none());
+    assert!(reassembler
+        .handle_chunk(source(), &second[0])
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(reassembler.memory_usage(), 64);
+    let err = reassembler
+        .handle_chunk(source(), &third[0])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+
+    // completed transfers release their memory
+    assert!(reassembler
+        .handle_chunk(source(), &first[1])
+        .await
+        .unwrap()
+        .is_some());
+    assert!(reassembler
+        .handle_chunk(source(), &second[1])
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(reassembler.memory_usage(), 0);
+
+    // transfers that are too long or out of order are rejected
+    assert!(reassembler
+        .handle_chunk(source(), &encode_chunks(5, &[0; 2048], 2048)[0])
+        .await
+        .is_err());
+    assert!(reassembler
+        .handle_chunk(source(), &encode_chunks(6, &[0; 32], 16)[1])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn transfers_spill_to_storage() {
+    let dir = std::env::temp_dir().join(format!("pea2pea-transfers-{}", std::process::id()));
+    let storage = FileStorage::new(&dir).unwrap();
+    let reassembler = Reassembler::new(LIMITS, Some(Arc::new(storage.clone())));
+    let key = TransferKey {
+        source: source(),
+        id: 7,
+    };
+
+    let payload = (0..=255u8).cycle().take(200).collect::<Vec<_>>();
+    let chunks = encode_chunks(key.id, &payload, 32);
+    for chunk in &chunks[..2] {
+        assert!(reassembler
+            .handle_chunk(key.source, chunk)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    assert!(!reassembler.progress(key).unwrap().spilled);
+
+    // the memory limit is reached; the transfer is moved to storage
+    assert!(reassembler
+        .handle_chunk(key.source, &chunks[2])
+        .await
+        .unwrap()
+        .is_none());
+    let progress = reassembler.progress(key).unwrap();
+    assert!(progress.spilled);
+    assert_eq!(progress.memory_usage, 0);
+    assert_eq!(progress.received, 96);
+    assert_eq!(reassembler.memory_usage(), 0);
+
+    let mut completed = None;
+    for chunk in &chunks[3..] {
+        completed = reassembler.handle_chunk(key.source, chunk).await.unwrap();
+    }
+    assert_eq!(completed, Some(CompletedTransfer::Stored(key)));
+    assert_eq!(std::fs::read(storage.path(key)).unwrap(), payload);
+
+    storage.remove(key).await.unwrap();
+    assert!(!storage.path(key).exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn transfers_other_limits() {
+    let dir = std::env::temp_dir().join(format!("pea2pea-limits-{}", std::process::id()));
+    let storage = FileStorage::new(&dir).unwrap();
+    let limits = ReassemblyLimits {
+        idle_timeout: Duration::from_millis(100),
+        ..LIMITS
+    };
+    let reassembler = Reassembler::new(limits, Some(Arc::new(storage)));
+
+    // the number of transfers in progress is limited
+    for id in 0..4 {
+        let chunks = encode_chunks(id, &[0; 32], 16);
+        assert!(reassembler
+            .handle_chunk(source(), &chunks[0])
+            .await
+            .unwrap()
+            .is_none());
+    }
+    let err = reassembler
+        .handle_chunk(source(), &encode_chunks(4, &[0; 32], 16)[0])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+
+    // idle transfers are aborted
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    reassembler.abort_idle().await;
+    assert_eq!(reassembler.memory_usage(), 0);
+    assert!(reassembler
+        .handle_chunk(source(), &encode_chunks(0, &[0; 32], 16)[1])
+        .await
+        .is_err());
+
+    // so is the size of the data spilled to storage
+    let first = encode_chunks(5, &[0; 384], 128);
+    let second = encode_chunks(6, &[0; 384], 128);
+    for chunk in &first[..2] {
+        assert!(reassembler
+            .handle_chunk(source(), chunk)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    assert_eq!(reassembler.stored_len(), 256);
+    for chunk in &second[..2] {
+        assert!(reassembler
+            .handle_chunk(source(), chunk)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    assert_eq!(reassembler.stored_len(), 512);
+    let err = reassembler
+        .handle_chunk(source(), &first[2])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+    assert_eq!(reassembler.stored_len(), 256);
+
+    reassembler.abort_peer(source()).await;
+    assert_eq!(reassembler.stored_len(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
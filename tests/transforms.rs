@@ -0,0 +1,141 @@
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+mod common;
+use pea2pea::{
+    protocols::{Handshaking, Reading, Writing},
+    Connection, ConnectionContext, Node, Pea2Pea,
+};
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+// a toy stream cipher: XOR with a counter-based keystream
+fn keystream_cipher(key: u8) -> impl FnMut(&mut [u8]) + Send + Sync {
+    let mut counter = 0u8;
+    move |bytes: &mut [u8]| {
+        for byte in bytes {
+            *byte ^= key ^ counter;
+            counter = counter.wrapping_add(1);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CipherNode {
+    node: Node,
+    received: Arc<Mutex<Vec<Bytes>>>,
+}
+
+impl CipherNode {
+    async fn new() -> Self {
+        let node = Self {
+            node: Node::new(None).await.unwrap(),
+            received: Default::default(),
+        };
+        node.enable_handshaking();
+        node.enable_reading();
+        node.enable_writing();
+
+        node
+    }
+}
+
+impl Pea2Pea for CipherNode {
+    fn node(&self) -> &Node {
+        &self.node
+    }
+}
+
+#[async_trait::async_trait]
+impl Handshaking for CipherNode {
+    async fn perform_handshake(&self, mut conn: Connection) -> io::Result<Connection> {
+        // a real protocol would derive the keys here
+        conn.inbound_transform = Some(Box::new(keystream_cipher(0x42)));
+        conn.outbound_transform = Some(Box::new(keystream_cipher(0x42)));
+
+        Ok(conn)
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for CipherNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Bytes, usize)>> {
+        let bytes = common::read_len_prefixed_message(2, buffer)?;
+
+        Ok(bytes.map(|bytes| (Bytes::copy_from_slice(&bytes[2..]), bytes.len())))
+    }
+
+    async fn process_message(&self, _source: SocketAddr, message: Bytes) -> io::Result<()> {
+        self.received.lock().push(message);
+
+        Ok(())
+    }
+}
+
+impl Writing for CipherNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        buffer[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer[2..][..payload.len()].copy_from_slice(payload);
+        Ok(2 + payload.len())
+    }
+}
+
+#[tokio::test]
+async fn transforms_are_applied_to_streams() {
+    let alice = CipherNode::new().await;
+    let bob = CipherNode::new().await;
+    let bob_addr = bob.node().listening_addr().unwrap();
+
+    alice.node().connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+
+    let messages = ["the", "keystream", "spans", "messages"];
+    for msg in &messages {
+        alice
+            .node()
+            .send_direct_message(bob_addr, Bytes::from_static(msg.as_bytes()))
+            .await
+            .unwrap();
+    }
+
+    wait_until!(1, bob.received.lock().len() == messages.len());
+    for (received, sent) in bob.received.lock().iter().zip(&messages) {
+        assert_eq!(received, sent.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn transformed_bytes_are_on_the_wire() {
+    let alice = CipherNode::new().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listener_addr = listener.local_addr().unwrap();
+
+    alice.node().connect(listener_addr).await.unwrap();
+    let (mut stream, _) = listener.accept().await.unwrap();
+
+    alice
+        .node()
+        .send_direct_message(listener_addr, Bytes::from_static(b"secret"))
+        .await
+        .unwrap();
+
+    let mut raw = [0u8; 8];
+    stream.read_exact(&mut raw).await.unwrap();
+    assert_ne!(&raw[2..], b"secret");
+
+    keystream_cipher(0x42)(&mut raw);
+    assert_eq!(&raw[..2], &6u16.to_le_bytes());
+    assert_eq!(&raw[2..], b"secret");
+}
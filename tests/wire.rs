@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+mod common;
+use pea2pea::{
+    protocols::{Reading, Writing},
+    wire::{Checksum, WireError, WireFormat},
+    ConnectionContext, Node, Pea2Pea,
+};
+
+use std::io;
+
+const WIRE_FORMAT: WireFormat = WireFormat {
+    magic: *b"p2p!",
+    checksum: Some(Checksum::Crc32),
+    max_payload_len: 1024,
+};
+
+#[derive(Clone)]
+struct WireNode(Node);
+
+impl Pea2Pea for WireNode {
+    fn node(&self) -> &Node {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Reading for WireNode {
+    type Message = Bytes;
+
+    fn read_message(
+        &self,
+        _ctx: &ConnectionContext,
+        buffer: &[u8],
+    ) -> io::Result<Option<(Self::Message, usize)>> {
+        Ok(WIRE_FORMAT
+            .read_frame(buffer)?
+            .map(|(payload, len)| (Bytes::copy_from_slice(payload), len)))
+    }
+}
+
+impl Writing for WireNode {
+    fn write_message(
+        &self,
+        _ctx: &ConnectionContext,
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> io::Result<usize> {
+        WIRE_FORMAT.write_frame(payload, buffer)
+    }
+}
+
+fn wire_error(err: io::Error) -> WireError {
+    *err.into_inner().unwrap().downcast::<WireError>().unwrap()
+}
+
+#[test]
+fn wire_checksums() {
+    assert_eq!(Checksum::Crc32.compute(b"123456789"), 0xcbf4_3926);
+    assert_eq!(Checksum::Xxh32.compute(b""), 0x02cc_5d05);
+    assert_eq!(Checksum::Xxh32.compute(b"a"), 0x550d_7456);
+    assert_eq!(Checksum::Xxh32.compute(b"abc"), 0x32d1_53ff);
+    assert_eq!(
+        Checksum::Xxh32.compute(b"Nobody inspects the spammish repetition"),
+        0xe229_3b2f
+    );
+}
+
+#[test]
+fn wire_frames() {
+    let payload = b"hello";
+    let mut buffer = [0u8; 32];
+    let len = WIRE_FORMAT.write_frame(payload, &mut buffer).unwrap();
+    assert_eq!(len, WIRE_FORMAT.frame_len(payload.len()));
+
+    // incomplete frames are awaited
+    for i in 0..len {
+        assert!(WIRE_FORMAT.read_frame(&buffer[..i]).unwrap().is_none());
+    }
+    assert_eq!(
+        WIRE_FORMAT.read_frame(&buffer[..len]).unwrap(),
+        Some((&payload[..], len))
+    );
+
+    // corrupted frames are rejected
+    let mut corrupted = buffer;
+    corrupted[0] ^= 1;
+    assert_eq!(
+        wire_error(WIRE_FORMAT.read_frame(&corrupted[..1]).unwrap_err()),
+        WireError::InvalidMagic
+    );
+    let mut corrupted = buffer;
+    corrupted[8] ^= 1;
+    assert_eq!(
+        wire_error(WIRE_FORMAT.read_frame(&corrupted[..len]).unwrap_err()),
+        WireError::ChecksumMismatch
+    );
+    let mut corrupted = buffer;
+    corrupted[6] = 1;
+    assert_eq!(
+        wire_error(WIRE_FORMAT.read_frame(&corrupted[..8]).unwrap_err()),
+        WireError::PayloadTooLarge(65536 + 5)
+    );
+
+    assert_eq!(
+        wire_error(
+            WIRE_FORMAT
+                .write_frame(payload, &mut buffer[..8])
+                .unwrap_err()
+        ),
+        WireError::BufferTooSmall
+    );
+}
+
+#[tokio::test]
+async fn wire_corrupted_frame_disconnects() {
+    let alice = WireNode(Node::new(None).await.unwrap());
+    alice.enable_writing();
+    let bob = WireNode(Node::new(None).await.unwrap());
+    bob.enable_reading();
+    let bob_addr = bob.node().listening_addr().unwrap();
+
+    alice.node().connect(bob_addr).await.unwrap();
+    alice
+        .node()
+        .send_direct_message(bob_addr, Bytes::from_static(b"hello"))
+        .await
+        .unwrap();
+    wait_until!(1, bob.node().stats().received().0 == 1);
+
+    // a frame with a bad checksum results in a disconnect
+    let mut stream = TcpStream::connect(bob_addr).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 2);
+    let mut frame = [0u8; 32];
+    let len = WIRE_FORMAT.write_frame(b"hello", &mut frame).unwrap();
+    frame[len - 1] ^= 1;
+    stream.write_all(&frame[..len]).await.unwrap();
+    wait_until!(1, bob.node().num_connected() == 1);
+}